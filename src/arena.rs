@@ -4,6 +4,7 @@
 use std::{fmt, hash::Hash, marker::PhantomData, ops};
 
 use hashbrown::HashMap;
+use serde::{Serialize, Serializer};
 
 /// An index into an [Arena] structure
 #[derive(PartialOrd, Ord)]
@@ -28,6 +29,13 @@ impl<T> Clone for Index<T> {
 }
 impl<T> Copy for Index<T> {}
 
+//Indices are written as their raw value, ignoring the type of the indexed items
+impl<T> Serialize for Index<T> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.0.serialize(serializer)
+    }
+}
+
 impl<T> Index<T> {
     /// Create a new Index with the internal value
     const fn new(idx: usize) -> Self {
@@ -55,6 +63,13 @@ pub struct Arena<T> {
     data: Vec<T>,
 }
 
+//Arenas are written as a sequence of their items, so an item's position is its index
+impl<T: Serialize> Serialize for Arena<T> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.data.serialize(serializer)
+    }
+}
+
 /// Structure similar to the [Arena] that holds its data in a Vec<T>,
 /// but only allocates new elements when a unique one is added,
 /// so two elements that are equal share the same ID
@@ -66,6 +81,13 @@ pub struct Interner<T> {
     arena: Arena<T>,
 }
 
+//The map of interned items can be rebuilt from the arena, so only the arena is written
+impl<T: Serialize> Serialize for Interner<T> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.arena.serialize(serializer)
+    }
+}
+
 impl<T: Hash + Eq + Clone> Interner<T> {
     /// Create a new empty interner
     pub fn new() -> Self {
@@ -5,6 +5,7 @@ use std::fmt;
 use std::{cmp::Eq, hash::Hash};
 
 use bitflags::bitflags;
+use serde::Serialize;
 
 use crate::Symbol;
 
@@ -15,8 +16,14 @@ use crate::{
 
 bitflags! {
     /// Structure holding flags of a function's prototype
+    #[derive(Serialize)]
     pub struct FunFlags: u8 {
         const EXTERN = 0b00000001;
+        const EXPORT = 0b00000010;
+        /// Never warn that the function is unused
+        const USED = 0b00000100;
+        /// Never warn that the function's parameters or variables are unused
+        const ALLOW_UNUSED = 0b00001000;
     }
 }
 
@@ -170,11 +177,15 @@ pub struct FunProto {
     pub flags: FunFlags,
     /// Function's signature
     pub ty: UnresolvedFunType,
+    /// Location of every argument in the signature, including its name if it has one
+    pub arg_spans: Vec<Span>,
+    /// If every argument was declared with `mut` and may be assigned to in the function's body
+    pub arg_mutable: Vec<bool>,
 }
 
 /// A let statement that either assigns a value to an expression or
 /// creates a new variable
-#[derive(Clone, PartialEq, Eq)]
+#[derive(Clone, Debug, PartialEq, Eq)]
 pub struct Let {
     /// If this let expression was declared with the `mut` keyword
     pub mutable: bool,
@@ -187,19 +198,36 @@ pub struct Let {
 
     /// Optional value being assigned to the expression
     pub assigned: Option<Box<Expr>>,
+
+    /// Alignment in bytes requested for the variable's stack allocation
+    pub align: Option<u64>,
 }
 
 /// A match expression that matches an enum expression based on its type
-#[derive(Clone, PartialEq, Eq)]
+#[derive(Clone, Debug, PartialEq, Eq)]
 pub struct Match {
     //The expression being matched
     pub matched: Box<Expr>,
     //The possible cases being tested for
-    pub cases: Vec<(UnresolvedType, Stmt)>,
+    pub cases: Vec<MatchArm>,
+}
+
+/// A single arm of a match expression
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MatchArm {
+    /// Type or enum variant that the arm tests for, or `None` for an arm that matches any value
+    /// not matched by the other arms
+    pub case: Option<UnresolvedType>,
+    /// Name of the variable that the value carried by the matched variant is bound to
+    pub binding: Option<Symbol>,
+    /// If the binding was declared with `mut` and may be assigned to in the arm's body
+    pub mutable: bool,
+    /// Statement executed when this arm matches
+    pub body: Stmt,
 }
 
 /// A statement at the top level of a function
-#[derive(Clone, PartialEq, Eq)]
+#[derive(Clone, Debug, PartialEq, Eq)]
 pub enum StmtNode {
     /// A conditional statement with else - if chains
     If(If),
@@ -216,17 +244,20 @@ pub enum StmtNode {
     /// Return a value from the currently defined function
     Return(Box<Expr>),
 
-    /// Assignment or variable declaration
+    /// Variable declaration, or assignment to a place that is not a variable name
     Let(Let),
+    /// Assignment of a value to a previously declared variable or global
+    Assign(Box<Expr>, Box<Expr>),
 
-    /// Control flow keyword used to break from a loop
-    Break,
+    /// Control flow keyword used to break from a loop, with the value of the loop if the loop is
+    /// an expression
+    Break(Option<Box<Expr>>),
     /// Control flow keyword used to continue to the next iteration of a loop
     Continue,
 }
 
 /// An expression that appears somewhere inside an [Stmt]
-#[derive(Clone, PartialEq, Eq)]
+#[derive(Clone, Debug, PartialEq, Eq)]
 pub enum ExprNode {
     /// Variable / function access by name or path
     Access(SymbolPath),
@@ -251,6 +282,10 @@ pub enum ExprNode {
     Unary(Op, Box<Expr>),
     /// Casting an expression to a different type explicitly
     Cast(UnresolvedType, Box<Expr>),
+    /// Size in bytes of a type
+    SizeOf(UnresolvedType),
+    /// Alignment in bytes of a type
+    AlignOf(UnresolvedType),
     /// A literal (does not mean compile-time constant) value
     Literal(Literal),
     /// A block of statements, must phi a value in all paths to be a validexpression
@@ -264,7 +299,7 @@ pub enum ExprNode {
 }
 
 /// An enumeration of all parseable literals
-#[derive(Clone, PartialEq, Eq)]
+#[derive(Clone, Debug, PartialEq, Eq)]
 pub enum Literal {
     /// Number literal containing optional annotation
     Number(NumberLiteral),
@@ -282,13 +317,15 @@ pub enum Literal {
         ty: Option<UnresolvedType>,
         fields: Vec<(Symbol, Expr)>,
     },
+    /// A tuple literal with one value per element
+    Tuple(Vec<Expr>),
     /// Unit literal
     Unit,
 }
 
 /// An if expression or statement that tests the value of a boolean expression and
 /// adjusts control flow accordingly
-#[derive(Clone, PartialEq, Eq)]
+#[derive(Clone, Debug, PartialEq, Eq)]
 pub struct If {
     /// Conditional expression
     pub cond: Box<Expr>,
@@ -299,7 +336,7 @@ pub struct If {
 }
 
 /// Enum representing what can come after an if expression's body
-#[derive(Clone, PartialEq, Eq)]
+#[derive(Clone, Debug, PartialEq, Eq)]
 pub enum ElseExpr {
     ElseIf(Box<If>),
     Else(Vec<Stmt>),
@@ -307,7 +344,7 @@ pub enum ElseExpr {
 
 /// One expression in an abstract syntax tree, containing an [ExprNode] and additional location information used for
 /// error messages later in the compiler
-#[derive(Clone, PartialEq, Eq)]
+#[derive(Clone, Debug, PartialEq, Eq)]
 pub struct Expr {
     /// The AST node's data
     pub node: ExprNode,
@@ -316,7 +353,7 @@ pub struct Expr {
 }
 
 /// One statement in the abstract syntax tree, the top level syntax for a function body
-#[derive(Clone, PartialEq, Eq)]
+#[derive(Clone, Debug, PartialEq, Eq)]
 pub struct Stmt {
     /// The statement's data
     pub node: StmtNode,
@@ -345,12 +382,23 @@ pub enum DefData {
         /// The aliased type
         aliased: UnresolvedType,
     },
+    /// A declaration of a type whose layout is unknown, usable only behind a pointer unless a
+    /// type definition with the same name gives it a body
+    OpaqueDef { name: Symbol },
+    /// A named enumeration with named variants
+    EnumDef {
+        /// Name of the enum type
+        name: Symbol,
+        /// Variants in the order they were declared
+        variants: Vec<EnumVariantDef>,
+    },
     /// An imported module definition
     ImportDef { name: SymbolPath },
     /// A global value
     Global {
         name: SymbolPath,
         comptime: bool,
+        mutable: bool,
         val: Option<Expr>,
         ty: Option<UnresolvedType>,
     },
@@ -360,13 +408,29 @@ impl DefData {
     pub fn name(&self) -> Symbol {
         match self {
             Self::FunDef(FunDef { proto, .. }) | Self::FunDec(proto) => proto.name,
-            Self::AliasDef { name, .. } => *name,
+            Self::AliasDef { name, .. } | Self::OpaqueDef { name } | Self::EnumDef { name, .. } => {
+                *name
+            }
             Self::ImportDef { name } => name.last(),
             Self::Global { name, .. } => name.last(),
         }
     }
 }
 
+/// One variant of an enum definition
+#[derive(Clone)]
+pub struct EnumVariantDef {
+    /// Name of the variant
+    pub name: Symbol,
+    /// Type of the value carried by the variant, if any
+    pub payload: Option<UnresolvedType>,
+    /// Constant expression giving the variant's discriminant, variants without one take the
+    /// value after the previous variant's discriminant
+    pub discriminant: Option<Expr>,
+    /// Span of the variant's declaration
+    pub span: Span,
+}
+
 /// A structure holding both [DefData] and metadata
 /// used for error messages like location in source
 #[derive(Clone)]
@@ -376,6 +440,8 @@ pub struct Def {
     pub span: Span,
     /// File that this definition appeared in
     pub file: FileId,
+    /// If the definition is marked `pub` and can be used from outside of its module
+    pub public: bool,
 }
 
 /// Structure representing a fully parsed module with easy access
@@ -413,9 +479,9 @@ pub enum NumberLiteral {
 }
 
 /// A big integer that can hold any number literal expressed in spark source
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
 pub struct BigInt {
-    pub val: u64,
+    pub val: u128,
     pub sign: bool,
 }
 
@@ -443,12 +509,14 @@ pub enum NumberLiteralAnnotation {
     I16,
     I32,
     I64,
+    I128,
+    U128,
     Isz,
     Usz,
 }
 
 /// Type representing a function's type in spark
-#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+#[derive(Clone, Debug, PartialEq, Eq)]
 pub struct UnresolvedFunType {
     /// The return type of the function
     pub return_ty: UnresolvedType,
@@ -458,7 +526,7 @@ pub struct UnresolvedFunType {
 
 /// All types in the [AstNode] enumeration are represented by the `UnresolvedType` type, as
 /// user-defined types are resolved when lowering the AST to IR
-#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+#[derive(Clone, Debug, PartialEq, Eq)]
 pub enum UnresolvedType {
     Integer {
         /// How large in bits is the integer type
@@ -478,19 +546,25 @@ pub enum UnresolvedType {
     },
     /// Pointer to another defined type
     Pointer(Box<UnresolvedType>),
-    /// Array with one element type and constant length
+    /// Array with one element type and a length given by a constant expression
     Array {
         elements: Box<UnresolvedType>,
-        len: u64,
+        len: Box<Expr>,
     },
+    /// Pointer to a run of elements paired with its length, like `[]u8`
+    Slice(Box<UnresolvedType>),
     /// Unit type with only one value, like void in C or () in rust
     Unit,
-    /// A structure with named members
+    /// A structure with named members, each with an optional explicit alignment
     Struct {
-        fields: Vec<(UnresolvedType, Symbol)>,
+        fields: Vec<(UnresolvedType, Symbol, Option<u64>)>,
+        /// If the structure is laid out with no padding between fields
+        packed: bool,
     },
     /// A tagged union with variant types
     Enum { variants: Vec<UnresolvedType> },
+    /// An anonymous product of element types, like `(i32, *u8)`
+    Tuple(Vec<UnresolvedType>),
     /// User-defined identifier
     UserDefined {
         /// The name of the user-defined type
@@ -500,11 +574,12 @@ pub enum UnresolvedType {
 
 /// Enumeration for all possible integer bit widths in the [UnresolvedType] enum
 #[repr(u8)]
-#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize)]
 pub enum IntegerWidth {
     Eight = 8,
     Sixteen = 16,
     ThirtyTwo = 32,
     SixtyFour = 64,
+    OneTwentyEight = 128,
     PtrSize = 0,
 }
@@ -7,11 +7,16 @@ use inkwell::context::Context;
 use spark::{
     ast::ParsedModule,
     error::DiagnosticManager,
-    ir::{lower::IrLowerer, IrContext},
+    ir::{
+        layout::TargetSpec,
+        lower::{IrLowerer, LintFlags},
+        opt, IrContext,
+    },
     llvm::LLVMCodeGenerator,
     parse::{ParseError, Parser},
     util::files::{CompiledFile, FileId, Files},
-    CompileOpts, OutputFileType, OutputOptimizationLevel, Symbol,
+    AsmSyntax, CodeModel, CompileOpts, OutputFileType, OutputOptimizationLevel, RelocModel, Symbol,
+    TargetConfig,
 };
 
 /// Input source code, either a file or a directory containing source files
@@ -23,25 +28,32 @@ enum InputItem {
 }
 
 fn main() {
+    let input_arg = Arg::new("input-path")
+        .required(true)
+        .takes_value(true)
+        .help("Paths to input files or directories to compile")
+        .long_help("Full or relative paths to files or directories to compile.\nIf a directory is passed, all files ending in .sprk will be compiled.\nIf multiple paths are passed, they are all compiled into one root module")
+        .multiple_values(true)
+        .value_name("input")
+        .validator_os(|path| match Path::new(path).exists() {
+            true => Ok(()),
+            false => Err(format!(
+                "The input directory at {} does not exist",
+                path.to_string_lossy()
+            )),
+        })
+        .value_hint(ValueHint::AnyPath)
+        .help_heading("input");
+
     let app = App::new("sparkc")
         .about("Compiler for the spark programming language")
-        .arg(
-            Arg::new("input-path")
-                .required(true)
-                .takes_value(true)
-                .help("A path to an input file or directory to compile")
-                .long_help("A full or relative path to a file or directory to compile.\nIf a directory is passed, all files ending in .sprk will be compiled")
-                .multiple_values(false)
-                .value_name("input")
-                .validator_os(|path| match Path::new(path).exists() {
-                    true => Ok(()),
-                    false => Err(format!(
-                        "The input directory at {} does not exist",
-                        path.to_string_lossy()
-                    )),
-                })
-                .value_hint(ValueHint::AnyPath)
-                .help_heading("input"),
+        .subcommand_negates_reqs(true)
+        .subcommand_precedence_over_arg(true)
+        .arg(input_arg.clone())
+        .subcommand(App::new("run")
+            .about("Compile a program in memory and run its main function")
+            .long_about("Compile a program in memory and run its main function, exiting with the value it returns.\nOptions for compilation must be passed before the run subcommand")
+            .arg(input_arg)
         )
         .arg(Arg::new("opt-lvl")
             .short('O')
@@ -49,7 +61,7 @@ fn main() {
             .takes_value(true)
             .default_value("0")
             .possible_values([
-                "0", "1", "2", "size"
+                "0", "1", "2", "3", "s", "size"
             ])
             .value_name("optimization-level")
             .help("Set the optimization level of the output")
@@ -66,48 +78,201 @@ fn main() {
         .arg(Arg::new("output-type")
             .short('T')
             .long("output-type")
+            .alias("emit")
             .takes_value(true)
-            .possible_values([
-                "asm",
-                "obj",
-                "ll",
-                "ir"
-            ])
+            .validator(|ty| match ty {
+                "asm" | "obj" | "ll" | "llvm-ir" | "bc" | "ir" | "ir-json" | "cfg-dot" => Ok(()),
+                _ if ty.starts_with("cfg-dot=") => Ok(()),
+                _ => Err(format!(
+                    "Unknown output type '{}', expected one of asm, obj, ll, llvm-ir, bc, ir, ir-json, cfg-dot[=function]",
+                    ty
+                )),
+            })
             .help("Set the output type to be written to the output file")
             .help_heading("output")
-            .long_help("Explicitly set the output file type instead of guessing from the extension given to [output-file]")
+            .long_help("Explicitly set the output file type instead of guessing from the extension given to [output-file]. \
+                cfg-dot writes the control flow graph of every function, or only the named function if given as cfg-dot=function")
         )
         .arg(Arg::new("pic")
             .long("pic")
             .help("Generate position independent output")
+            .long_help("Generate position independent output, equivalent to --reloc-model pic")
             .help_heading("output")
             .takes_value(false)
         )
+        .arg(Arg::new("target")
+            .long("target")
+            .takes_value(true)
+            .value_name("triple")
+            .help("Set the target triple to generate code for, like aarch64-unknown-linux-gnu")
+            .help_heading("target")
+        )
+        .arg(Arg::new("cpu")
+            .long("cpu")
+            .takes_value(true)
+            .help("Set the target CPU to generate code for")
+            .help_heading("target")
+        )
+        .arg(Arg::new("target-features")
+            .long("target-features")
+            .takes_value(true)
+            .value_name("features")
+            .help("Enable or disable target CPU features, like +avx2,-sse4.1")
+            .help_heading("target")
+        )
+        .arg(Arg::new("reloc-model")
+            .long("reloc-model")
+            .takes_value(true)
+            .possible_values([
+                "default",
+                "static",
+                "pic",
+                "dynamic-no-pic",
+            ])
+            .default_value("default")
+            .help("Set the relocation model of generated code")
+            .help_heading("target")
+        )
+        .arg(Arg::new("code-model")
+            .long("code-model")
+            .takes_value(true)
+            .possible_values([
+                "default",
+                "small",
+                "kernel",
+                "medium",
+                "large",
+            ])
+            .default_value("default")
+            .help("Set the code model of generated code")
+            .help_heading("target")
+        )
         .arg(Arg::new("strip")
             .long("strip")
             .takes_value(false)
             .help("Strip symbols from the produced output (redundant if -Osize is passed)")
             .help_heading("output")
+        )
+        .arg(Arg::new("asm-syntax")
+            .long("asm-syntax")
+            .takes_value(true)
+            .possible_values([
+                "att",
+                "intel",
+            ])
+            .default_value("att")
+            .help("Set the syntax of x86 assembly output")
+            .help_heading("output")
+        )
+        .arg(Arg::new("backend")
+            .long("backend")
+            .takes_value(true)
+            .possible_values([
+                "llvm",
+                "cranelift",
+            ])
+            .default_value("llvm")
+            .help("Select the code generator used to produce output")
+            .long_help("Select the code generator used to produce output, the cranelift backend only produces object files and requires sparkc to be built with the backend-cranelift feature")
+            .help_heading("output")
+        )
+        .arg(Arg::new("debug")
+            .short('g')
+            .long("debug")
+            .takes_value(false)
+            .help("Generate DWARF debug information in the output")
+            .help_heading("output")
+        )
+        .arg(Arg::new("bounds-check")
+            .long("bounds-check")
+            .takes_value(false)
+            .help("Abort at runtime when an array is indexed out of bounds")
+            .help_heading("output")
+        )
+        .arg(Arg::new("overflow-check")
+            .long("overflow-check")
+            .takes_value(false)
+            .help("Abort at runtime when integer arithmetic overflows")
+            .long_help("Abort at runtime when integer addition, subtraction, or multiplication overflows instead of wrapping, and report overflow in arithmetic on constant operands as an error. Overflow in constants and array lengths is always an error")
+            .help_heading("output")
+        )
+        .arg(Arg::new("no-asserts")
+            .long("no-asserts")
+            .takes_value(false)
+            .help("Compile out all assert statements")
+            .help_heading("output")
+        )
+        .arg(Arg::new("dump-invalid-ir")
+            .long("dump-invalid-ir")
+            .takes_value(false)
+            .help("Write the generated LLVM IR to a file if it fails verification")
+            .long_help("Write the generated LLVM IR next to the output file with an .invalid.ll extension if it fails LLVM verification, for debugging the compiler")
+            .help_heading("diagnostics")
+        )
+        .arg(Arg::new("zero-init")
+            .long("zero-init")
+            .takes_value(false)
+            .help("Fill variables declared without an initializer with zeroes")
+            .long_help("Fill variables declared without an initializer with zeroes instead of reporting an error when they may be read before being assigned")
+            .help_heading("diagnostics")
+        )
+        .arg(Arg::new("strict-temporaries")
+            .long("strict-temporaries")
+            .takes_value(false)
+            .help("Report an error when taking the address of a temporary value")
+            .long_help("Report an error when taking the address of a value that is not stored in a variable, instead of storing the value in a temporary variable that lives until the end of the enclosing block")
+            .help_heading("diagnostics")
+        )
+        .arg(Arg::new("allow")
+            .short('A')
+            .long("allow")
+            .takes_value(true)
+            .multiple_occurrences(true)
+            .possible_values([
+                "shadow",
+                "ptr-cast",
+                "unused",
+                "unused-variables",
+                "unreachable",
+                "private-in-public",
+            ])
+            .value_name("lint")
+            .help("Disable a warning emitted during compilation")
+            .help_heading("diagnostics")
         );
 
     let args = app.get_matches();
+    let run = args.subcommand_matches("run");
+    //The run subcommand compiles in memory and doesn't require an output file
+    let out_file = args.value_of("output-file").unwrap_or_default();
 
     let opts = CompileOpts {
-        out_file: PathBuf::from(args.value_of("output-file").unwrap()),
+        out_file: PathBuf::from(out_file),
         out_type: match args.value_of("output-type") {
             Some(ty) => match ty {
+                "asm" => OutputFileType::Assembly,
+                "obj" => OutputFileType::Object,
+                "ll" | "llvm-ir" => OutputFileType::LLVMIR,
+                "bc" => OutputFileType::Bitcode,
+                "ir" => OutputFileType::IR,
+                "ir-json" => OutputFileType::IRJson,
+                _ if ty.starts_with("cfg-dot") => OutputFileType::CfgDot,
                 _ => unreachable!(),
             },
-            None => match Path::new(args.value_of("output-file").unwrap()).extension() {
+            None if run.is_some() => OutputFileType::Object,
+            None => match Path::new(out_file).extension() {
                 Some(ext) => match ext.to_str() {
-                    Some("obj") | Some("o") => OutputFileType::Object,
+                    Some("obj") | Some("o") | Some("wasm") => OutputFileType::Object,
                     Some("ll") => OutputFileType::LLVMIR,
+                    Some("bc") => OutputFileType::Bitcode,
                     Some("asm") | Some("s") => OutputFileType::Assembly,
                     Some("sprkir") => OutputFileType::IR,
+                    Some("json") => OutputFileType::IRJson,
+                    Some("dot") => OutputFileType::CfgDot,
                     _ => {
                         eprintln!(
                             "Output file '{}' has an unknown extension\nUse -T[type] option to explicitly set output type",
-                            args.value_of("output-file").unwrap(),
+                            out_file,
                         );
                         return;
                     }
@@ -115,7 +280,7 @@ fn main() {
                 None => {
                     eprintln!(
                         "Output file '{}' has no extension\nUse -T[type] option to explicitly set output type",
-                        args.value_of("output-file").unwrap(),
+                        out_file,
                     );
                     return;
                 }
@@ -123,92 +288,252 @@ fn main() {
         },
         opt_lvl: match args.value_of("opt-lvl").unwrap() {
             "0" => OutputOptimizationLevel::Debug,
-            "1" => OutputOptimizationLevel::Medium,
-            "2" => OutputOptimizationLevel::Release,
-            "size" => OutputOptimizationLevel::Size,
+            "1" => OutputOptimizationLevel::Basic,
+            "2" => OutputOptimizationLevel::Medium,
+            "3" => OutputOptimizationLevel::Release,
+            "s" | "size" => OutputOptimizationLevel::Size,
             _ => unreachable!(),
         },
-        pic: args.is_present("pic"),
+        target: TargetConfig {
+            triple: args.value_of("target").map(str::to_owned),
+            cpu: args.value_of("cpu").map(str::to_owned),
+            features: args.value_of("target-features").map(str::to_owned),
+            reloc: match args.value_of("reloc-model").unwrap() {
+                _ if args.is_present("pic") => RelocModel::PIC,
+                "default" => RelocModel::Default,
+                "static" => RelocModel::Static,
+                "pic" => RelocModel::PIC,
+                "dynamic-no-pic" => RelocModel::DynamicNoPIC,
+                _ => unreachable!(),
+            },
+            code_model: match args.value_of("code-model").unwrap() {
+                "default" => CodeModel::Default,
+                "small" => CodeModel::Small,
+                "kernel" => CodeModel::Kernel,
+                "medium" => CodeModel::Medium,
+                "large" => CodeModel::Large,
+                _ => unreachable!(),
+            },
+        },
         stripped: args.is_present("strip"),
+        debug: args.is_present("debug"),
+        bounds_check: args.is_present("bounds-check"),
+        overflow_check: args.is_present("overflow-check"),
+        asserts: !args.is_present("no-asserts"),
+        dump_invalid_ir: args.is_present("dump-invalid-ir"),
+        asm_syntax: match args.value_of("asm-syntax").unwrap() {
+            "att" => AsmSyntax::ATT,
+            "intel" => AsmSyntax::Intel,
+            _ => unreachable!(),
+        },
     };
 
-    let input = Path::new(args.value_of("input-path").unwrap());
     let mut files = Files::new();
-    let input = collect_files(input, &mut files);
+    let mut inputs = run
+        .unwrap_or(&args)
+        .values_of("input-path")
+        .unwrap()
+        .map(|input| collect_files(Path::new(input), &mut files))
+        .collect::<Vec<_>>();
 
-    let root_module = match input {
-        InputItem::File(f) => {
-            let src = files.get(f).text.as_str();
-            let mut parser = Parser::new(src);
-            let module = handle_parse_error(parser.parse(Symbol::from("root"), f), &files, f);
-            drop(parser);
-            module
-        }
-        InputItem::Dir(_name, items) => {
-            let main = items
-                .iter()
-                .find_map(|item| {
-                    if let InputItem::File(id) = item {
-                        if files
-                            .get(*id)
-                            .path
-                            .file_name()
-                            .map(|s| s.to_str())
-                            .flatten()
-                            == Some("main.sprk")
-                        {
-                            Some(*id)
+    //Multiple inputs are all compiled into the root module, so that they can refer to each other
+    let root_module = if inputs.len() > 1 {
+        let mut parser = Parser::new("");
+        parse_dir("root".to_owned(), inputs, &files, &mut parser)
+    } else {
+        match inputs.pop().unwrap() {
+            InputItem::File(f) => {
+                let src = files.get(f).text.as_str();
+                let mut parser = Parser::new(src);
+                let module = handle_parse_error(parser.parse(Symbol::from("root"), f), &files, f);
+                drop(parser);
+                module
+            }
+            InputItem::Dir(_name, items) => {
+                let main = items
+                    .iter()
+                    .find_map(|item| {
+                        if let InputItem::File(id) = item {
+                            if files
+                                .get(*id)
+                                .path
+                                .file_name()
+                                .map(|s| s.to_str())
+                                .flatten()
+                                == Some("main.sprk")
+                            {
+                                Some(*id)
+                            } else {
+                                None
+                            }
                         } else {
                             None
                         }
-                    } else {
-                        None
-                    }
-                })
-                .expect("main.sprk does not exist in root directory");
-            let mut root = ParsedModule::new(Symbol::from("root"));
-            let mut parser = Parser::new(files.get(main).text.as_str());
-            handle_parse_error(parser.parse_to(&mut root, main), &files, main);
+                    })
+                    .expect("main.sprk does not exist in root directory");
+                let mut root = ParsedModule::new(Symbol::from("root"));
+                let mut parser = Parser::new(files.get(main).text.as_str());
+                handle_parse_error(parser.parse_to(&mut root, main), &files, main);
 
-            for item in items {
-                match item {
-                    InputItem::File(f) if f == main => continue,
-                    InputItem::File(f) => {
-                        let src = files.get(f).text.as_str();
-                        parser.set_text(src);
-                        handle_parse_error(parser.parse_to(&mut root, f), &files, f);
-                    }
-                    InputItem::Dir(name, items) => {
-                        let child = parse_dir(name.clone(), items, &files, &mut parser);
-                        root.children.push(child);
+                for item in items {
+                    match item {
+                        InputItem::File(f) if f == main => continue,
+                        InputItem::File(f) => {
+                            let src = files.get(f).text.as_str();
+                            parser.set_text(src);
+                            handle_parse_error(parser.parse_to(&mut root, f), &files, f);
+                        }
+                        InputItem::Dir(name, items) => {
+                            let child = parse_dir(name.clone(), items, &files, &mut parser);
+                            root.children.push(child);
+                        }
                     }
                 }
+                root
             }
-            root
         }
     };
 
     let mut ctx = IrContext::new();
     let mut lowerer = IrLowerer::new(&mut ctx, root_module.name);
+    let mut lints = LintFlags::all();
+    for allowed in args.values_of("allow").into_iter().flatten() {
+        lints.remove(match allowed {
+            "shadow" => LintFlags::SHADOW,
+            "ptr-cast" => LintFlags::PTR_CAST,
+            "unused" => LintFlags::UNUSED,
+            "unused-variables" => LintFlags::UNUSED_VARIABLES,
+            "unreachable" => LintFlags::UNREACHABLE,
+            "private-in-public" => LintFlags::PRIVATE_IN_PUBLIC,
+            _ => unreachable!(),
+        });
+    }
+    lowerer.set_lints(lints);
+    lowerer.set_target(
+        opts.target
+            .triple
+            .as_deref()
+            .map_or_else(TargetSpec::host, TargetSpec::from_triple),
+    );
+    lowerer.set_zero_init(args.is_present("zero-init"));
+    lowerer.set_overflow_checks(args.is_present("overflow-check"));
+    lowerer.set_strict_temporaries(args.is_present("strict-temporaries"));
+
     let mut diags = DiagnosticManager::new(&files);
-    lowerer
-        .lower(&root_module)
+    let lowered = lowerer.lower(&root_module);
+    for warning in lowerer.take_warnings() {
+        diags.emit(warning);
+    }
+    lowered
         .map_err(|e| diags.emit(e))
         .unwrap_or_else(|()| std::process::exit(-1));
+    drop(lowerer);
+
+    //Catch bugs in the lowerer before they surface as panics during code generation
+    if cfg!(debug_assertions) {
+        let errors = ctx.validate();
+        for error in errors.iter() {
+            diags.emit(error.to_diagnostic(&ctx));
+        }
+        if !errors.is_empty() {
+            std::process::exit(-1);
+        }
+    }
+
+    //Unoptimized builds keep every variable, store, and branch where a debugger can find it
+    if opts.opt_lvl != OutputOptimizationLevel::Debug {
+        for fun in ctx.funs.indices().collect::<Vec<_>>() {
+            opt::fold_branches(&mut ctx, fun);
+            opt::remove_dead_blocks(&mut ctx, fun);
+            opt::remove_dead_stores(&mut ctx, fun);
+            opt::promote_vars(&mut ctx, fun);
+        }
+    }
+
+    if run.is_some() {
+        let llvm = Context::create();
+        let result =
+            LLVMCodeGenerator::new(&mut ctx, &llvm, &files, opts).and_then(|mut codegen| {
+                codegen.gen_module()?;
+                codegen.jit_run("main", &[])
+            });
+        match result {
+            Ok(code) => std::process::exit(code as i32),
+            Err(e) => {
+                diags.emit(e);
+                std::process::exit(-1);
+            }
+        }
+    }
 
     match opts.out_type {
         OutputFileType::IR => {
             std::fs::write(opts.out_file, ctx.to_string()).expect("Write to output file failed");
         }
+        OutputFileType::IRJson => {
+            std::fs::write(opts.out_file, ctx.to_json()).expect("Write to output file failed");
+        }
+        OutputFileType::CfgDot => {
+            let only = args
+                .value_of("output-type")
+                .and_then(|ty| ty.strip_prefix("cfg-dot="));
+            let mut dot = String::new();
+            for fun in ctx.funs.indices() {
+                if ctx.fun_body(fun).is_some()
+                    && only.map_or(true, |name| ctx[fun].name.as_str() == name)
+                {
+                    dot.push_str(&ctx.dot_of(fun));
+                }
+            }
+            if let (Some(name), true) = (only, dot.is_empty()) {
+                diags.emit(Diagnostic::error().with_message(format!(
+                    "No function named '{}' with a body was found to write the control flow graph of",
+                    name
+                )));
+                std::process::exit(-1);
+            }
+            std::fs::write(opts.out_file, dot).expect("Write to output file failed");
+        }
+        _ if args.value_of("backend") == Some("cranelift") => {
+            if let Err(e) = gen_cranelift(&ctx, &files, opts) {
+                diags.emit(e);
+                std::process::exit(-1);
+            }
+        }
         _ => {
-            drop(lowerer);
             let llvm = Context::create();
-            let codegen = LLVMCodeGenerator::new(&mut ctx, &llvm, opts);
-            codegen.gen();
+            let generated = LLVMCodeGenerator::new(&mut ctx, &llvm, &files, opts)
+                .and_then(|codegen| codegen.gen());
+            if let Err(e) = generated {
+                diags.emit(e);
+                std::process::exit(-1);
+            }
         }
     }
 }
 
+/// Generate an object file from the lowered IR with the Cranelift backend
+#[cfg(feature = "backend-cranelift")]
+fn gen_cranelift(
+    ctx: &IrContext,
+    files: &Files,
+    opts: CompileOpts,
+) -> Result<(), Diagnostic<FileId>> {
+    spark::clif::ClifCodeGenerator::new(ctx, files, opts).and_then(|codegen| codegen.gen())
+}
+
+#[cfg(not(feature = "backend-cranelift"))]
+fn gen_cranelift(
+    _ctx: &IrContext,
+    _files: &Files,
+    _opts: CompileOpts,
+) -> Result<(), Diagnostic<FileId>> {
+    Err(Diagnostic::error().with_message(
+        "sparkc was built without the cranelift backend, rebuild it with the backend-cranelift \
+        feature enabled",
+    ))
+}
+
 fn handle_parse_error<T>(res: Result<T, ParseError>, files: &Files, file: FileId) -> T {
     res.unwrap_or_else(|e| {
         let mut diags = DiagnosticManager::new(files);
@@ -0,0 +1,504 @@
+use std::cmp::Ordering;
+
+use codespan_reporting::diagnostic::{Diagnostic, Label};
+use cranelift_codegen::ir::{
+    condcodes::{FloatCC, IntCC},
+    types, InstBuilder, MemFlags, StackSlotData, StackSlotKind, Type, Value,
+};
+use cranelift_module::Module;
+
+use crate::{
+    ir::{
+        types::{IrIntegerType, IrType},
+        value::{CastKind, IrExpr, IrExprKind, IrLiteral},
+        IrContext, TypeId,
+    },
+    parse::token::Op,
+    util::{files::FileId, loc::Span},
+};
+
+use super::stmt::ClifFunctionState;
+
+impl<'a, 'ctx> ClifFunctionState<'a, 'ctx> {
+    /// Generate Cranelift instructions for a single IR expression
+    pub fn gen_expr(&mut self, expr: &IrExpr) -> Result<Value, Diagnostic<FileId>> {
+        let irctx = self.gen.irctx;
+        let ptr_ty = self.gen.module.target_config().pointer_type();
+
+        Ok(match &expr.kind {
+            IrExprKind::Var(var) => {
+                let ty = self.clif_type(expr.ty, expr.span)?;
+                let slot = self.var_slot(*var, expr.span)?;
+                self.build.ins().stack_load(ty, slot, 0)
+            }
+            IrExprKind::Param(bb, idx) => {
+                let block = self.block(*bb);
+                self.build.block_params(block)[*idx]
+            }
+            IrExprKind::Global(..) => {
+                let ty = self.clif_type(expr.ty, expr.span)?;
+                let ptr = self.gen_lval(expr)?;
+                self.build.ins().load(ty, MemFlags::new(), ptr, 0)
+            }
+            IrExprKind::Lit(lit) => match lit {
+                IrLiteral::Integer(v, _) => {
+                    let ty = self.clif_type(expr.ty, expr.span)?;
+                    self.gen_int_const(ty, v.val)
+                }
+                IrLiteral::Float(f, ty) if ty.doublewide => self.build.ins().f64const(*f),
+                IrLiteral::Float(f, _) => self.build.ins().f32const(*f as f32),
+                IrLiteral::Bool(b) => self.build.ins().iconst(types::I8, *b as i64),
+                IrLiteral::Char(c) => self.build.ins().iconst(types::I32, *c as i64),
+                IrLiteral::Unit => self.build.ins().iconst(types::I8, 0),
+                IrLiteral::String(s) => self.gen_string_lit(s)?,
+                IrLiteral::Array(_) => return Err(self.unsupported(expr.span, "Array literals")),
+                IrLiteral::Struct(_) => {
+                    return Err(self.unsupported(expr.span, "Structure literals"))
+                }
+                IrLiteral::Tuple(_) => return Err(self.unsupported(expr.span, "Tuple literals")),
+            },
+            IrExprKind::Call(fun_expr, args) => {
+                let args = args
+                    .iter()
+                    .map(|arg| self.gen_expr(arg))
+                    .collect::<Result<Vec<_>, _>>()?;
+
+                let call = match &fun_expr.kind {
+                    IrExprKind::Fun(fun) => {
+                        let callee = self.gen.module.declare_func_in_func(
+                            *self.gen.clif_funs.get_secondary(*fun),
+                            self.build.func,
+                        );
+                        self.build.ins().call(callee, &args)
+                    }
+                    _ => {
+                        let fun_ty = match &irctx[irctx.unwrap_alias(fun_expr.ty)] {
+                            IrType::Fun(fun_ty) => fun_ty,
+                            _ => {
+                                return Err(Diagnostic::error()
+                                    .with_message(format!(
+                                        "ICE: Calling a value of non-function type {}",
+                                        irctx.typename(fun_expr.ty)
+                                    ))
+                                    .with_labels(vec![Label::primary(self.file, fun_expr.span)]))
+                            }
+                        };
+                        let sig = self.gen.clif_sig(fun_ty, self.file, fun_expr.span)?;
+                        let sig = self.build.import_signature(sig);
+                        let callee = self.gen_expr(fun_expr)?;
+                        self.build.ins().call_indirect(sig, callee, &args)
+                    }
+                };
+
+                match self.build.inst_results(call).first() {
+                    Some(val) => *val,
+                    None => self.build.ins().iconst(types::I8, 0),
+                }
+            }
+            IrExprKind::Fun(fun) => {
+                let callee = self
+                    .gen
+                    .module
+                    .declare_func_in_func(*self.gen.clif_funs.get_secondary(*fun), self.build.func);
+                self.build.ins().func_addr(ptr_ty, callee)
+            }
+            IrExprKind::Member(..) => {
+                return Err(self.unsupported(expr.span, "Accessing structure fields"))
+            }
+            IrExprKind::Index(..) => return Err(self.unsupported(expr.span, "Indexing arrays")),
+            IrExprKind::Slice(..) => return Err(self.unsupported(expr.span, "Slices")),
+            IrExprKind::Variant(idx, None) => {
+                let ty = self.clif_type(expr.ty, expr.span)?;
+                let discriminant = match &irctx[irctx.unwrap_alias(expr.ty)] {
+                    IrType::Enum(e) => e.variants[*idx].discriminant,
+                    _ => unreachable!(),
+                };
+                self.gen_int_const(ty, discriminant as i128 as u128)
+            }
+            IrExprKind::Variant(_, Some(_)) => {
+                return Err(self.unsupported(expr.span, "Enum variants carrying values"))
+            }
+            IrExprKind::Cast(casted, ty, kind) => self.gen_cast(casted, *ty, *kind, expr.span)?,
+            IrExprKind::SizeOf(ty) => {
+                let size = self.clif_type(*ty, expr.span)?.bytes();
+                self.build.ins().iconst(ptr_ty, size as i64)
+            }
+            IrExprKind::AlignOf(ty) => {
+                let align = irctx
+                    .explicit_align(*ty)
+                    .unwrap_or(self.clif_type(*ty, expr.span)?.bytes());
+                self.build.ins().iconst(ptr_ty, align as i64)
+            }
+            IrExprKind::VolatileRead(ptr) => {
+                let ty = self.clif_type(expr.ty, expr.span)?;
+                let ptr = self.gen_expr(ptr)?;
+                self.build.ins().load(ty, MemFlags::new(), ptr, 0)
+            }
+            IrExprKind::Unary(op, operand) => match op {
+                Op::AND => self.gen_lval(operand)?,
+                Op::Star => {
+                    let ty = self.clif_type(expr.ty, expr.span)?;
+                    let ptr = self.gen_expr(operand)?;
+                    self.build.ins().load(ty, MemFlags::new(), ptr, 0)
+                }
+                _ => return Err(self.unsupported(expr.span, &format!("Unary operator {}", op))),
+            },
+            IrExprKind::Binary(lhs, op, rhs) => {
+                let llhs = self.gen_expr(lhs)?;
+                let lrhs = self.gen_expr(rhs)?;
+                match (
+                    self.gen.opts.overflow_check,
+                    op,
+                    &irctx[irctx.unwrap_alias(lhs.ty)],
+                    &irctx[irctx.unwrap_alias(rhs.ty)],
+                ) {
+                    (
+                        true,
+                        Op::Add | Op::Sub | Op::Star,
+                        IrType::Integer(IrIntegerType { signed, .. }),
+                        IrType::Integer(_),
+                    ) => {
+                        let ty = self.build.func.dfg.value_type(llhs);
+                        let lrhs = self.int_cast(lrhs, ty, *signed);
+                        self.gen_checked_arith(*op, *signed, llhs, lrhs, expr.span)?
+                    }
+                    _ => self.gen_bin(lhs.ty, *op, rhs.ty, llhs, lrhs, expr.span)?,
+                }
+            }
+        })
+    }
+
+    /// Generate the address of an lvalue expression, spilling other expressions to a temporary
+    /// stack slot
+    pub fn gen_lval(&mut self, expr: &IrExpr) -> Result<Value, Diagnostic<FileId>> {
+        let ptr_ty = self.gen.module.target_config().pointer_type();
+        Ok(match &expr.kind {
+            IrExprKind::Var(var) => {
+                let slot = self.var_slot(*var, expr.span)?;
+                self.build.ins().stack_addr(ptr_ty, slot, 0)
+            }
+            IrExprKind::Global(glob) => match *self.gen.clif_globs.get_secondary(*glob) {
+                Some(id) => {
+                    let glob = self.gen.module.declare_data_in_func(id, self.build.func);
+                    self.build.ins().global_value(ptr_ty, glob)
+                }
+                None => {
+                    return Err(Diagnostic::error()
+                        .with_message(format!(
+                            "ICE: Using global {} of invalid type",
+                            self.gen.irctx[*glob].name
+                        ))
+                        .with_labels(vec![Label::primary(self.file, expr.span)]))
+                }
+            },
+            IrExprKind::Unary(Op::Star, ptr) => self.gen_expr(ptr)?,
+            IrExprKind::Fun(..) => self.gen_expr(expr)?,
+            _ => {
+                let ty = self.clif_type(expr.ty, expr.span)?;
+                let slot = self.build.create_sized_stack_slot(StackSlotData::new(
+                    StackSlotKind::ExplicitSlot,
+                    ty.bytes(),
+                    ty.bytes().trailing_zeros() as u8,
+                ));
+                let val = self.gen_expr(expr)?;
+                self.build.ins().stack_store(val, slot, 0);
+                self.build.ins().stack_addr(ptr_ty, slot, 0)
+            }
+        })
+    }
+
+    /// Generate the zero value of a scalar type
+    pub fn gen_zero(&mut self, ty: TypeId, span: Span) -> Result<Value, Diagnostic<FileId>> {
+        let clif_ty = self.clif_type(ty, span)?;
+        Ok(match clif_ty {
+            types::F32 => self.build.ins().f32const(0.),
+            types::F64 => self.build.ins().f64const(0.),
+            _ => self.gen_int_const(clif_ty, 0),
+        })
+    }
+
+    /// Generate an integer constant of any width from its two's complement bits
+    fn gen_int_const(&mut self, ty: Type, val: u128) -> Value {
+        if ty == types::I128 {
+            let lo = self.build.ins().iconst(types::I64, val as u64 as i64);
+            let hi = self
+                .build
+                .ins()
+                .iconst(types::I64, (val >> 64) as u64 as i64);
+            self.build.ins().iconcat(lo, hi)
+        } else {
+            //Immediates of narrow types must have all bits above the type's width cleared
+            let mask = u64::MAX >> (64 - ty.bits());
+            self.build.ins().iconst(ty, (val as u64 & mask) as i64)
+        }
+    }
+
+    /// Convert an integer value to another integer type, extending by the given signedness
+    fn int_cast(&mut self, val: Value, to: Type, signed: bool) -> Value {
+        let from = self.build.func.dfg.value_type(val);
+        match from.bits().cmp(&to.bits()) {
+            Ordering::Equal => val,
+            Ordering::Greater => self.build.ins().ireduce(to, val),
+            Ordering::Less if signed => self.build.ins().sextend(to, val),
+            Ordering::Less => self.build.ins().uextend(to, val),
+        }
+    }
+
+    /// Generate an addition, subtraction, or multiplication of two integers of the same type
+    /// that aborts at runtime with the source location of the expression if the result
+    /// overflows
+    fn gen_checked_arith(
+        &mut self,
+        op: Op,
+        signed: bool,
+        lhs: Value,
+        rhs: Value,
+        span: Span,
+    ) -> Result<Value, Diagnostic<FileId>> {
+        let ins = self.build.ins();
+        let (val, overflowed) = match (op, signed) {
+            (Op::Add, true) => ins.sadd_overflow(lhs, rhs),
+            (Op::Add, false) => ins.uadd_overflow(lhs, rhs),
+            (Op::Sub, true) => ins.ssub_overflow(lhs, rhs),
+            (Op::Sub, false) => ins.usub_overflow(lhs, rhs),
+            (_, true) => ins.smul_overflow(lhs, rhs),
+            (_, false) => ins.umul_overflow(lhs, rhs),
+        };
+
+        let ok_block = self.build.create_block();
+        let fail_block = self.build.create_block();
+        self.build
+            .ins()
+            .brif(overflowed, fail_block, &[], ok_block, &[]);
+
+        self.build.switch_to_block(fail_block);
+        let msg = format!(
+            "{}: integer overflow in {}",
+            self.source_loc(span),
+            match op {
+                Op::Add => "addition",
+                Op::Sub => "subtraction",
+                _ => "multiplication",
+            }
+        );
+        self.gen_abort(&msg)?;
+
+        self.build.switch_to_block(ok_block);
+        Ok(val)
+    }
+
+    /// Generate Cranelift instructions for a binary expression
+    fn gen_bin(
+        &mut self,
+        lhs_ty: TypeId,
+        op: Op,
+        rhs_ty: TypeId,
+        lhs: Value,
+        rhs: Value,
+        span: Span,
+    ) -> Result<Value, Diagnostic<FileId>> {
+        let irctx = self.gen.irctx;
+        Ok(
+            match (
+                &irctx[irctx.unwrap_alias(lhs_ty)],
+                op,
+                &irctx[irctx.unwrap_alias(rhs_ty)],
+            ) {
+                (IrType::Integer(IrIntegerType { signed, .. }), _, IrType::Integer(_)) => {
+                    let signed = *signed;
+                    let ty = self.build.func.dfg.value_type(lhs);
+                    let rhs = self.int_cast(rhs, ty, signed);
+                    let ins = self.build.ins();
+                    match op {
+                        Op::Star => ins.imul(lhs, rhs),
+                        Op::Div if signed => ins.sdiv(lhs, rhs),
+                        Op::Div => ins.udiv(lhs, rhs),
+                        Op::Mod if signed => ins.srem(lhs, rhs),
+                        Op::Mod => ins.urem(lhs, rhs),
+                        Op::Add => ins.iadd(lhs, rhs),
+                        Op::Sub => ins.isub(lhs, rhs),
+                        Op::ShLeft => ins.ishl(lhs, rhs),
+                        Op::ShRight if signed => ins.sshr(lhs, rhs),
+                        Op::ShRight => ins.ushr(lhs, rhs),
+                        Op::AND => ins.band(lhs, rhs),
+                        Op::OR => ins.bor(lhs, rhs),
+                        Op::XOR => ins.bxor(lhs, rhs),
+                        Op::Greater | Op::GreaterEq | Op::Less | Op::LessEq | Op::Eq => {
+                            let cc = match (op, signed) {
+                                (Op::Greater, true) => IntCC::SignedGreaterThan,
+                                (Op::GreaterEq, true) => IntCC::SignedGreaterThanOrEqual,
+                                (Op::Less, true) => IntCC::SignedLessThan,
+                                (Op::LessEq, true) => IntCC::SignedLessThanOrEqual,
+
+                                (Op::Greater, false) => IntCC::UnsignedGreaterThan,
+                                (Op::GreaterEq, false) => IntCC::UnsignedGreaterThanOrEqual,
+                                (Op::Less, false) => IntCC::UnsignedLessThan,
+                                (Op::LessEq, false) => IntCC::UnsignedLessThanOrEqual,
+
+                                (Op::Eq, _) => IntCC::Equal,
+                                _ => unreachable!(),
+                            };
+                            ins.icmp(cc, lhs, rhs)
+                        }
+                        _ => return Err(self.unsupported_bin(lhs_ty, op, rhs_ty, span)),
+                    }
+                }
+                (IrType::Float(_), _, IrType::Float(_)) => {
+                    let ins = self.build.ins();
+                    match op {
+                        Op::Star => ins.fmul(lhs, rhs),
+                        Op::Div => ins.fdiv(lhs, rhs),
+                        Op::Add => ins.fadd(lhs, rhs),
+                        Op::Sub => ins.fsub(lhs, rhs),
+                        Op::Greater | Op::GreaterEq | Op::Less | Op::LessEq | Op::Eq => {
+                            let cc = match op {
+                                Op::Greater => FloatCC::GreaterThan,
+                                Op::GreaterEq => FloatCC::GreaterThanOrEqual,
+                                Op::Less => FloatCC::LessThan,
+                                Op::LessEq => FloatCC::LessThanOrEqual,
+                                Op::Eq => FloatCC::Equal,
+                                _ => unreachable!(),
+                            };
+                            ins.fcmp(cc, lhs, rhs)
+                        }
+                        _ => return Err(self.unsupported_bin(lhs_ty, op, rhs_ty, span)),
+                    }
+                }
+                //The difference of two pointers is the number of elements between them
+                (IrType::Ptr(pointee), Op::Sub, IrType::Ptr(_)) => {
+                    let size = self.layout(*pointee, span)?.size;
+                    let diff = self.build.ins().isub(lhs, rhs);
+                    match size {
+                        0 | 1 => diff,
+                        size => self.build.ins().sdiv_imm(diff, size as i64),
+                    }
+                }
+                //Both operands of boolean operators are always evaluated
+                (IrType::Bool, _, IrType::Bool) => {
+                    let ins = self.build.ins();
+                    match op {
+                        Op::AND | Op::LogicalAnd => ins.band(lhs, rhs),
+                        Op::OR | Op::LogicalOr => ins.bor(lhs, rhs),
+                        Op::XOR => ins.bxor(lhs, rhs),
+                        Op::Eq => ins.icmp(IntCC::Equal, lhs, rhs),
+                        _ => return Err(self.unsupported_bin(lhs_ty, op, rhs_ty, span)),
+                    }
+                }
+                //Pointers are already represented as pointer-sized integers
+                (IrType::Ptr(_), _, IrType::Ptr(_)) => {
+                    self.gen_bin(IrContext::USIZE, op, IrContext::USIZE, lhs, rhs, span)?
+                }
+                (IrType::Ptr(_), _, IrType::Integer(_)) => {
+                    self.gen_bin(IrContext::USIZE, op, rhs_ty, lhs, rhs, span)?
+                }
+                (IrType::Integer(_), _, IrType::Ptr(_)) => {
+                    self.gen_bin(lhs_ty, op, IrContext::USIZE, lhs, rhs, span)?
+                }
+                _ => return Err(self.unsupported_bin(lhs_ty, op, rhs_ty, span)),
+            },
+        )
+    }
+
+    /// Generate Cranelift instructions for a casted value
+    fn gen_cast(
+        &mut self,
+        expr: &IrExpr,
+        ty: TypeId,
+        kind: CastKind,
+        span: Span,
+    ) -> Result<Value, Diagnostic<FileId>> {
+        let irctx = self.gen.irctx;
+        if let CastKind::SumUnwrap | CastKind::SumWrap = kind {
+            return Err(self.unsupported(span, "Casting to or from sum types"));
+        }
+
+        let to_clif = self.clif_type(ty, span)?;
+        let val = self.gen_expr(expr)?;
+        let signed = |ty: TypeId| {
+            matches!(
+                &irctx[irctx.unwrap_alias(ty)],
+                IrType::Integer(IrIntegerType { signed: true, .. })
+            )
+        };
+        let ins = self.build.ins();
+        Ok(match kind {
+            CastKind::Identity | CastKind::PtrToPtr => val,
+            //Widening extends by the signedness of the value being widened
+            CastKind::IntResize => self.int_cast(val, to_clif, signed(expr.ty)),
+            //The discriminants of an enum's tag are always signed
+            CastKind::EnumToInt => self.int_cast(val, to_clif, true),
+            CastKind::IntToPtr | CastKind::PtrToInt | CastKind::BoolToInt => {
+                self.int_cast(val, to_clif, false)
+            }
+            CastKind::IntToBool => ins.icmp_imm(IntCC::NotEqual, val, 0),
+            CastKind::IntToFloat if signed(expr.ty) => ins.fcvt_from_sint(to_clif, val),
+            CastKind::IntToFloat => ins.fcvt_from_uint(to_clif, val),
+            CastKind::FloatToInt if signed(ty) => ins.fcvt_to_sint_sat(to_clif, val),
+            CastKind::FloatToInt => ins.fcvt_to_uint_sat(to_clif, val),
+            CastKind::FloatResize if to_clif == types::F64 => ins.fpromote(to_clif, val),
+            CastKind::FloatResize => ins.fdemote(to_clif, val),
+            CastKind::SumUnwrap | CastKind::SumWrap => unreachable!(),
+        })
+    }
+
+    /// Create an error for a binary operator that has no Cranelift lowering
+    fn unsupported_bin(
+        &self,
+        lhs_ty: TypeId,
+        op: Op,
+        rhs_ty: TypeId,
+        span: Span,
+    ) -> Diagnostic<FileId> {
+        let irctx = self.gen.irctx;
+        self.unsupported(
+            span,
+            &format!(
+                "Binary operator {} {} {}",
+                irctx.typename(lhs_ty),
+                op,
+                irctx.typename(rhs_ty)
+            ),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::clif::testing::run;
+
+    #[test]
+    fn int_resize_extends_by_source_sign() {
+        let status = run("
+fun widen_signed(i8 v) -> u32 { return $u32 v }
+fun widen_unsigned(u8 v) -> i32 { return $i32 v }
+fun narrow(i32 v) -> i8 { return $i8 v }
+fun narrow_unsigned(u32 v) -> u8 { return $u8 v }
+
+fun main() -> i32 {
+    if widen_signed($i8 -1) == 4294967295u32 {} else { return 1 }
+    if widen_unsigned($u8 255) == 255 {} else { return 2 }
+    if narrow(300) == 44i8 {} else { return 3 }
+    if narrow(-1) == -1i8 {} else { return 4 }
+    if narrow_unsigned(511u32) == 255u8 {} else { return 5 }
+    return 0
+}");
+        assert_eq!(status, 0);
+    }
+
+    #[test]
+    fn pointer_difference_counts_elements() {
+        let status = run("type Triple = { i32 a, i32 b, i32 c, }
+type Padded = { i64 a, u8 b, }
+
+fun main() -> i32 {
+    let [*Triple] triple = $*Triple $usize 36
+    if $i32 (triple - $*Triple $usize 0) == 3 {} else { return 1 }
+    let [*Padded] padded = $*Padded $usize 48
+    if $i32 (padded - $*Padded $usize 0) == 3 {} else { return 2 }
+    let [*i64] wide = $*i64 $usize 16
+    if $i32 (wide - $*i64 $usize 0) == 2 {} else { return 3 }
+    return 0
+}");
+        assert_eq!(status, 0);
+    }
+}
@@ -0,0 +1,391 @@
+//! Code generation through Cranelift, a backend that produces unoptimized object files much
+//! faster than LLVM and is meant for quick debug builds
+//!
+//! Only scalar values are supported: integers, floats, characters, booleans, and pointers.
+//! Structures, arrays, and sum types produce errors instead of code
+
+use codespan_reporting::diagnostic::{Diagnostic, Label};
+use cranelift_codegen::{
+    ir::{types, AbiParam, InstBuilder, Signature, Type},
+    isa,
+    settings::{self, Configurable},
+};
+use cranelift_frontend::{FunctionBuilder, FunctionBuilderContext};
+use cranelift_module::{DataDescription, DataId, FuncId, Linkage, Module, ModuleError};
+use cranelift_object::{ObjectBuilder, ObjectModule};
+
+use crate::{
+    arena::Arena,
+    ast::IntegerWidth,
+    ir::{
+        layout::{Layout, TargetSpec},
+        types::{FunType, IrType},
+        FunId, IrContext, TypeId,
+    },
+    util::{
+        files::{FileId, Files},
+        loc::Span,
+    },
+    CompileOpts, OutputFileType, OutputOptimizationLevel, RelocModel,
+};
+
+use self::stmt::ClifFunctionState;
+
+pub mod expr;
+pub mod stmt;
+
+#[cfg(test)]
+pub(crate) mod testing;
+
+/// Structure containing all state needed to generate an object file from spark IR with Cranelift
+pub struct ClifCodeGenerator<'ctx> {
+    irctx: &'ctx IrContext,
+    files: &'ctx Files,
+    opts: CompileOpts,
+    module: ObjectModule,
+    clif_funs: Arena<FuncId>,
+    /// Data objects of every global, or `None` for globals of invalid type
+    clif_globs: Arena<Option<DataId>>,
+    /// Layout properties of the target machine
+    target: TargetSpec,
+}
+
+impl<'ctx> ClifCodeGenerator<'ctx> {
+    /// Create a new [ClifCodeGenerator] for the target described in the given options, declaring
+    /// all functions and globals of the IR context
+    pub fn new(
+        irctx: &'ctx IrContext,
+        files: &'ctx Files,
+        opts: CompileOpts,
+    ) -> Result<Self, Diagnostic<FileId>> {
+        if opts.out_type != OutputFileType::Object {
+            return Err(Diagnostic::error().with_message(
+                "The cranelift backend can only produce object files, use the LLVM backend for \
+                other output formats",
+            ));
+        }
+
+        let mut flags = settings::builder();
+        let opt_level = match opts.opt_lvl {
+            OutputOptimizationLevel::Debug => "none",
+            OutputOptimizationLevel::Size => "speed_and_size",
+            _ => "speed",
+        };
+        flags.set("opt_level", opt_level).unwrap();
+        flags
+            .set(
+                "is_pic",
+                if opts.target.reloc == RelocModel::PIC {
+                    "true"
+                } else {
+                    "false"
+                },
+            )
+            .unwrap();
+
+        let isa_builder = match &opts.target.triple {
+            Some(triple) => isa::lookup_by_name(triple).map_err(|e| {
+                Diagnostic::error().with_message(format!(
+                    "Target {} is not supported by cranelift: {}",
+                    triple, e
+                ))
+            })?,
+            None => cranelift_native::builder().map_err(|e| {
+                Diagnostic::error()
+                    .with_message(format!("Failed to initialize native target: {}", e))
+            })?,
+        };
+        let isa = isa_builder
+            .finish(settings::Flags::new(flags))
+            .map_err(|e| {
+                Diagnostic::error().with_message(format!("Failed to create target ISA: {}", e))
+            })?;
+
+        let name = opts
+            .out_file
+            .file_stem()
+            .and_then(|stem| stem.to_str())
+            .unwrap_or("spark_module")
+            .to_owned();
+        let target = TargetSpec::from_triple(&isa.triple().to_string());
+        let builder = ObjectBuilder::new(isa, name, cranelift_module::default_libcall_names())
+            .map_err(|e| {
+                Diagnostic::error().with_message(format!("Failed to create object file: {}", e))
+            })?;
+
+        let mut this = Self {
+            irctx,
+            files,
+            opts,
+            module: ObjectModule::new(builder),
+            clif_funs: Arena::new(),
+            clif_globs: Arena::new(),
+            target,
+        };
+
+        for fun in irctx.funs.iter() {
+            let sig = this.clif_sig(&fun.ty, fun.file, fun.span)?;
+            let linkage = match fun.body {
+                Some(_) => Linkage::Export,
+                None => Linkage::Import,
+            };
+            let id = this
+                .module
+                .declare_function(fun.symbol.as_str(), linkage, &sig)
+                .map_err(|e| {
+                    Diagnostic::error()
+                        .with_message(format!("Failed to declare function {}: {}", fun.name, e))
+                        .with_labels(vec![Label::primary(fun.file, fun.span)])
+                })?;
+            this.clif_funs.insert(id);
+        }
+
+        for glob in irctx.globals.iter() {
+            if glob.ty == IrContext::INVALID {
+                this.clif_globs.insert(None);
+                continue;
+            }
+
+            let size = match this.clif_type(glob.ty) {
+                Some(ty) => ty.bytes() as usize,
+                None => {
+                    return Err(Diagnostic::error().with_message(format!(
+                        "Global {} of type {} is not yet supported in cranelift backend",
+                        glob.name,
+                        irctx.typename(glob.ty),
+                    )))
+                }
+            };
+
+            let glob_err = |e: ModuleError| {
+                Diagnostic::error()
+                    .with_message(format!("Failed to define global {}: {}", glob.name, e))
+            };
+            let id = this
+                .module
+                .declare_data(glob.name.as_str(), Linkage::Export, true, false)
+                .map_err(glob_err)?;
+            let mut data = DataDescription::new();
+            data.define_zeroinit(size);
+            this.module.define_data(id, &data).map_err(glob_err)?;
+            this.clif_globs.insert(Some(id));
+        }
+
+        Ok(this)
+    }
+
+    /// Generate code for all function bodies and write the finished object file to the output file
+    pub fn gen(mut self) -> Result<(), Diagnostic<FileId>> {
+        for fun in self.irctx.funs.indices() {
+            self.gen_fun(fun)?;
+        }
+
+        let out_file = self.opts.out_file.clone();
+        let object = self.module.finish().emit().map_err(|e| {
+            Diagnostic::error().with_message(format!("Failed to emit object file: {}", e))
+        })?;
+        std::fs::write(&out_file, object).map_err(|e| {
+            Diagnostic::error().with_message(format!(
+                "Failed to write output file {}: {}",
+                out_file.display(),
+                e
+            ))
+        })
+    }
+
+    /// Generate the body of a single function, doing nothing for external functions
+    fn gen_fun(&mut self, fun_id: FunId) -> Result<(), Diagnostic<FileId>> {
+        let irctx = self.irctx;
+        let fun = &irctx[fun_id];
+        let body = match &fun.body {
+            Some(body) => body,
+            None => return Ok(()),
+        };
+
+        let mut ctx = self.module.make_context();
+        ctx.func.signature = self.clif_sig(&fun.ty, fun.file, fun.span)?;
+        let mut fbctx = FunctionBuilderContext::new();
+
+        {
+            let build = FunctionBuilder::new(&mut ctx.func, &mut fbctx);
+            let mut state = ClifFunctionState::new(self, build, fun_id);
+
+            let entry = state.build.create_block();
+            state.build.append_block_params_for_function_params(entry);
+            state.build.switch_to_block(entry);
+            let params = state.build.block_params(entry).to_vec();
+            for (param, var) in params.into_iter().zip(body.args.iter()) {
+                if let Some(var) = var {
+                    let slot = state.var_slot(*var, fun.span)?;
+                    state.build.ins().stack_store(param, slot, 0);
+                }
+            }
+
+            let first = state.block(body.entry);
+            state.build.ins().jump(first, &[]);
+            state.gen_blocks(body.entry)?;
+            state.build.seal_all_blocks();
+            state.build.finalize();
+        }
+
+        let id = *self.clif_funs.get_secondary(fun_id);
+        self.module.define_function(id, &mut ctx).map_err(|e| {
+            Diagnostic::error()
+                .with_message(format!("Failed to generate function {}: {}", fun.name, e))
+                .with_labels(vec![Label::primary(fun.file, fun.span)])
+        })
+    }
+
+    /// Get the Cranelift type that values of the given type are represented as, or `None` if the
+    /// type is not a scalar
+    pub fn clif_type(&self, ty: TypeId) -> Option<Type> {
+        let ptr = self.module.target_config().pointer_type();
+        Some(match &self.irctx[ty] {
+            IrType::Integer(ity) => match ity.width {
+                IntegerWidth::Eight => types::I8,
+                IntegerWidth::Sixteen => types::I16,
+                IntegerWidth::ThirtyTwo => types::I32,
+                IntegerWidth::SixtyFour => types::I64,
+                IntegerWidth::OneTwentyEight => types::I128,
+                IntegerWidth::PtrSize => ptr,
+            },
+            IrType::Float(fty) if fty.doublewide => types::F64,
+            IrType::Float(_) => types::F32,
+            IrType::Char => types::I32,
+            IrType::Bool | IrType::Unit => types::I8,
+            IrType::Ptr(_) | IrType::Fun(_) => ptr,
+            IrType::Alias { ty, .. } => return self.clif_type(*ty),
+            //Enums without values are represented by their tag alone
+            IrType::Enum(e) if !e.has_payload() => match e.tag_type().width {
+                IntegerWidth::ThirtyTwo => types::I32,
+                _ => types::I64,
+            },
+            _ => return None,
+        })
+    }
+
+    /// Create the Cranelift signature of a function type, functions returning unit have no
+    /// return values
+    fn clif_sig(
+        &self,
+        ty: &FunType,
+        file: FileId,
+        span: Span,
+    ) -> Result<Signature, Diagnostic<FileId>> {
+        let mut sig = self.module.make_signature();
+        for (param, _) in ty.params.iter() {
+            let param = self
+                .clif_type(*param)
+                .ok_or_else(|| self.unsupported_type(*param, file, span))?;
+            sig.params.push(AbiParam::new(param));
+        }
+
+        if self.irctx.unwrap_alias(ty.return_ty) != IrContext::UNIT {
+            let ret = self
+                .clif_type(ty.return_ty)
+                .ok_or_else(|| self.unsupported_type(ty.return_ty, file, span))?;
+            sig.returns.push(AbiParam::new(ret));
+        }
+
+        Ok(sig)
+    }
+
+    /// Get the size, alignment, and field offsets of a type on the target machine
+    pub fn layout(
+        &self,
+        ty: TypeId,
+        file: FileId,
+        span: Span,
+    ) -> Result<Layout, Diagnostic<FileId>> {
+        self.irctx.layout_of(ty, &self.target).map_err(|e| {
+            Diagnostic::error()
+                .with_message(format!(
+                    "Cannot get the layout of type {}: {}",
+                    self.irctx.typename(ty),
+                    e
+                ))
+                .with_labels(vec![Label::primary(file, span)])
+        })
+    }
+
+    /// Create an error for a value of non-scalar type that the backend can't represent
+    fn unsupported_type(&self, ty: TypeId, file: FileId, span: Span) -> Diagnostic<FileId> {
+        Diagnostic::error()
+            .with_message(format!(
+                "Values of type {} are not yet supported in cranelift backend",
+                self.irctx.typename(ty)
+            ))
+            .with_labels(vec![Label::primary(file, span)])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::testing::{run, run_modules};
+
+    #[test]
+    fn globals_are_initialized_before_main() {
+        let status = run("glob mut counter = 5
+glob doubled = counter * 2
+
+fun main() -> i32 {
+    counter = counter + doubled
+    return counter
+}");
+        assert_eq!(status, 15);
+    }
+
+    #[test]
+    fn recursive_main_does_not_reinitialize_globals() {
+        let status = run("glob mut depth = 0
+
+fun main() -> i32 {
+    depth = depth + 1
+    if depth < 3 { return main() }
+    return depth
+}");
+        assert_eq!(status, 3);
+    }
+
+    #[test]
+    fn c_main_exits_with_spark_main_result() {
+        let status = run("fun exit_code() -> i32 {
+    return 42
+}
+
+fun main() -> i32 {
+    return exit_code()
+}");
+        assert_eq!(status, 42);
+    }
+
+    #[test]
+    fn unit_main_exits_with_zero() {
+        let status = run("glob mut counter = 5
+
+fun main() {
+    counter = counter + 1
+}");
+        assert_eq!(status, 0);
+    }
+
+    #[test]
+    fn same_named_private_functions_link_into_one_binary() {
+        let status = run_modules(
+            "fun main() -> i32 {
+    return a:value() + b:value()
+}",
+            &[
+                (
+                    "a",
+                    "fun init() -> i32 { return 1 }\npub fun value() -> i32 { return init() }",
+                ),
+                (
+                    "b",
+                    "fun init() -> i32 { return 20 }\npub fun value() -> i32 { return init() * 2 }",
+                ),
+            ],
+        );
+        assert_eq!(status, 41);
+    }
+}
@@ -0,0 +1,344 @@
+use codespan_reporting::diagnostic::{Diagnostic, Label};
+use cranelift_codegen::ir::{
+    types, AbiParam, Block, InstBuilder, MemFlags, StackSlot, StackSlotData, StackSlotKind,
+    TrapCode, Type, Value,
+};
+use cranelift_frontend::FunctionBuilder;
+use cranelift_module::{DataDescription, Linkage, Module, ModuleError};
+use hashbrown::{HashMap, HashSet};
+
+use crate::{
+    ir::{
+        layout::Layout, BBId, FunId, IrBranch, IrContext, IrStmt, IrStmtKind, IrTerminator, TypeId,
+        VarId,
+    },
+    util::{files::FileId, loc::Span},
+};
+
+use super::ClifCodeGenerator;
+
+/// State needed while generating the body of a single function
+pub struct ClifFunctionState<'a, 'ctx> {
+    pub(super) gen: &'a mut ClifCodeGenerator<'ctx>,
+    pub(super) build: FunctionBuilder<'a>,
+    /// Function whose body is being generated
+    fun: FunId,
+    /// Source file containing the function's definition
+    pub(super) file: FileId,
+    /// Return type of the function
+    return_ty: TypeId,
+    /// Stack slots of every variable allocated so far
+    vars: HashMap<VarId, StackSlot>,
+    /// Cranelift blocks of every basic block referenced so far
+    blocks: HashMap<BBId, Block>,
+}
+
+impl<'a, 'ctx> ClifFunctionState<'a, 'ctx> {
+    pub fn new(
+        gen: &'a mut ClifCodeGenerator<'ctx>,
+        build: FunctionBuilder<'a>,
+        fun: FunId,
+    ) -> Self {
+        let file = gen.irctx[fun].file;
+        let return_ty = gen.irctx[fun].ty.return_ty;
+        Self {
+            gen,
+            build,
+            fun,
+            file,
+            return_ty,
+            vars: HashMap::new(),
+            blocks: HashMap::new(),
+        }
+    }
+
+    /// Get the Cranelift block of a basic block, creating it if it has not been referenced yet
+    pub fn block(&mut self, bb: BBId) -> Block {
+        let Self { blocks, build, .. } = self;
+        *blocks.entry(bb).or_insert_with(|| build.create_block())
+    }
+
+    /// Get the stack slot of a variable, allocating it if this is the first use of the variable
+    pub fn var_slot(&mut self, var: VarId, span: Span) -> Result<StackSlot, Diagnostic<FileId>> {
+        if let Some(slot) = self.vars.get(&var) {
+            return Ok(*slot);
+        }
+
+        let irctx = self.gen.irctx;
+        let ty = self.clif_type(irctx[var].ty, span)?;
+        let align = irctx[var]
+            .align
+            .max(irctx.explicit_align(irctx[var].ty))
+            .unwrap_or(ty.bytes())
+            .max(1);
+        let slot = self.build.create_sized_stack_slot(StackSlotData::new(
+            StackSlotKind::ExplicitSlot,
+            ty.bytes(),
+            align.trailing_zeros() as u8,
+        ));
+        self.vars.insert(var, slot);
+        Ok(slot)
+    }
+
+    /// Generate code for every basic block reachable from the given block
+    pub fn gen_blocks(&mut self, entry: BBId) -> Result<(), Diagnostic<FileId>> {
+        let irctx = self.gen.irctx;
+        let mut visited = HashSet::new();
+        let mut worklist = vec![entry];
+
+        while let Some(bb) = worklist.pop() {
+            if !visited.insert(bb) {
+                continue;
+            }
+
+            let block = self.block(bb);
+            self.build.switch_to_block(block);
+            for stmt in irctx[bb].stmts.iter() {
+                self.gen_stmt(stmt)?;
+            }
+
+            match &irctx[bb].terminator {
+                IrTerminator::Return(v) => {
+                    let val = self.gen_expr(v)?;
+                    if irctx.unwrap_alias(self.return_ty) == IrContext::UNIT {
+                        self.build.ins().return_(&[]);
+                    } else {
+                        self.build.ins().return_(&[val]);
+                    }
+                }
+                IrTerminator::Jmp(to) => {
+                    let (to_block, args) = self.gen_branch(to)?;
+                    self.build.ins().jump(to_block, &args);
+                    worklist.push(to.bb);
+                }
+                IrTerminator::JmpIf {
+                    condition,
+                    if_true,
+                    if_false,
+                } => {
+                    let condition = self.gen_expr(condition)?;
+                    let (true_block, true_args) = self.gen_branch(if_true)?;
+                    let (false_block, false_args) = self.gen_branch(if_false)?;
+                    self.build.ins().brif(
+                        condition,
+                        true_block,
+                        &true_args,
+                        false_block,
+                        &false_args,
+                    );
+                    worklist.push(if_false.bb);
+                    worklist.push(if_true.bb);
+                }
+                IrTerminator::JmpMatch { variant, .. } => {
+                    return Err(self.unsupported(variant.span, "Matching on sum types"))
+                }
+                IrTerminator::Unreachable => {
+                    self.build.ins().trap(TrapCode::unwrap_user(1));
+                }
+                IrTerminator::Invalid => {
+                    let irfun = &irctx[self.fun];
+                    return Err(Diagnostic::error()
+                        .with_message(format!(
+                            "ICE: Basic block {} of function {} has no terminator",
+                            bb, irfun.name
+                        ))
+                        .with_labels(vec![Label::primary(
+                            self.file,
+                            irctx[bb].terminator_span.unwrap_or(irfun.span),
+                        )]));
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Get the Cranelift block that a jump targets and generate the values passed to its
+    /// parameters. Parameters are appended to a block at the first jump to it, which is always
+    /// generated before the block itself
+    fn gen_branch(&mut self, branch: &IrBranch) -> Result<(Block, Vec<Value>), Diagnostic<FileId>> {
+        let irctx = self.gen.irctx;
+        let block = self.block(branch.bb);
+        let args = branch
+            .args
+            .iter()
+            .map(|arg| self.gen_expr(arg))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        if self.build.func.dfg.block_params(block).is_empty() {
+            for (ty, arg) in irctx[branch.bb].params.iter().zip(branch.args.iter()) {
+                let ty = self.clif_type(*ty, arg.span)?;
+                self.build.append_block_param(block, ty);
+            }
+        }
+
+        Ok((block, args))
+    }
+
+    /// Translate one IR statement to Cranelift instructions
+    pub fn gen_stmt(&mut self, stmt: &IrStmt) -> Result<(), Diagnostic<FileId>> {
+        let irctx = self.gen.irctx;
+        match &stmt.kind {
+            IrStmtKind::VarLive(var) => {
+                self.var_slot(*var, stmt.span)?;
+            }
+            //Stack slots live for the whole function
+            IrStmtKind::VarDead(_) => (),
+            IrStmtKind::Zero(var) => {
+                let slot = self.var_slot(*var, stmt.span)?;
+                let zero = self.gen_zero(irctx[*var].ty, stmt.span)?;
+                self.build.ins().stack_store(zero, slot, 0);
+            }
+            IrStmtKind::Store { var, val } => {
+                let slot = self.var_slot(*var, stmt.span)?;
+                let val = self.gen_expr(val)?;
+                self.build.ins().stack_store(val, slot, 0);
+            }
+            IrStmtKind::StoreMem { addr, val } => {
+                let addr = self.gen_expr(addr)?;
+                let val = self.gen_expr(val)?;
+                self.build.ins().store(MemFlags::new(), val, addr, 0);
+            }
+            IrStmtKind::Copy { .. } => {
+                return Err(self.unsupported(stmt.span, "Copying aggregate values"))
+            }
+            IrStmtKind::VolatileWrite { ptr, val } => {
+                let ptr = self.gen_expr(ptr)?;
+                let val = self.gen_expr(val)?;
+                self.build.ins().store(MemFlags::new(), val, ptr, 0);
+            }
+            IrStmtKind::Assert { cond, msg } => {
+                if !self.gen.opts.asserts {
+                    return Ok(());
+                }
+
+                let cond = self.gen_expr(cond)?;
+                let ok_block = self.build.create_block();
+                let fail_block = self.build.create_block();
+                self.build.ins().brif(cond, ok_block, &[], fail_block, &[]);
+
+                self.build.switch_to_block(fail_block);
+                let msg = match msg {
+                    Some(msg) => {
+                        format!("{}: assertion failed: {}", self.source_loc(stmt.span), msg)
+                    }
+                    None => format!("{}: assertion failed", self.source_loc(stmt.span)),
+                };
+                self.gen_abort(&msg)?;
+
+                self.build.switch_to_block(ok_block);
+            }
+            IrStmtKind::Call { fun, args } => {
+                let callee = self
+                    .gen
+                    .module
+                    .declare_func_in_func(*self.gen.clif_funs.get_secondary(*fun), self.build.func);
+                let args = args
+                    .iter()
+                    .map(|arg| self.gen_expr(arg))
+                    .collect::<Result<Vec<_>, _>>()?;
+                self.build.ins().call(callee, &args);
+            }
+            IrStmtKind::Exec(expr) => {
+                self.gen_expr(expr)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Print a message to standard error and abort the program, leaving the builder positioned
+    /// in a block that is never reached
+    pub(super) fn gen_abort(&mut self, msg: &str) -> Result<(), Diagnostic<FileId>> {
+        let size_ty = self.gen.module.target_config().pointer_type();
+
+        let mut write_sig = self.gen.module.make_signature();
+        write_sig.params.push(AbiParam::new(types::I32));
+        write_sig.params.push(AbiParam::new(size_ty));
+        write_sig.params.push(AbiParam::new(size_ty));
+        write_sig.returns.push(AbiParam::new(size_ty));
+        let abort_sig = self.gen.module.make_signature();
+
+        let abort_err = |e: ModuleError| {
+            Diagnostic::error()
+                .with_message(format!("Failed to declare runtime abort functions: {}", e))
+        };
+        let write = self
+            .gen
+            .module
+            .declare_function("write", Linkage::Import, &write_sig)
+            .map_err(abort_err)?;
+        let abort = self
+            .gen
+            .module
+            .declare_function("abort", Linkage::Import, &abort_sig)
+            .map_err(abort_err)?;
+        let write = self.gen.module.declare_func_in_func(write, self.build.func);
+        let abort = self.gen.module.declare_func_in_func(abort, self.build.func);
+
+        let msg = format!("{}\n", msg);
+        let msg_ptr = self.gen_string_lit(&msg)?;
+        let fd = self.build.ins().iconst(types::I32, 2);
+        let len = self.build.ins().iconst(size_ty, msg.len() as i64);
+        self.build.ins().call(write, &[fd, msg_ptr, len]);
+        self.build.ins().call(abort, &[]);
+        self.build.ins().trap(TrapCode::unwrap_user(1));
+        Ok(())
+    }
+
+    /// Create an anonymous read-only data object containing a null-terminated string and get a
+    /// pointer to its first character
+    pub fn gen_string_lit(&mut self, s: &str) -> Result<Value, Diagnostic<FileId>> {
+        let mut bytes = s.as_bytes().to_vec();
+        bytes.push(0);
+
+        let mut data = DataDescription::new();
+        data.define(bytes.into_boxed_slice());
+        let data_err = |e: ModuleError| {
+            Diagnostic::error().with_message(format!("Failed to define string literal: {}", e))
+        };
+        let id = self
+            .gen
+            .module
+            .declare_anonymous_data(false, false)
+            .map_err(data_err)?;
+        self.gen.module.define_data(id, &data).map_err(data_err)?;
+
+        let ptr = self.gen.module.target_config().pointer_type();
+        let glob = self.gen.module.declare_data_in_func(id, self.build.func);
+        Ok(self.build.ins().global_value(ptr, glob))
+    }
+
+    /// Format the location of a span in the current source file for runtime error messages
+    pub(super) fn source_loc(&self, span: Span) -> String {
+        let file = self.gen.files.get(self.file);
+        let line = match file.lines.binary_search(&span.from) {
+            Ok(line) => line,
+            Err(line) => line.saturating_sub(1),
+        };
+        let col = span.from - file.lines.get(line).copied().unwrap_or(0);
+        format!("{}:{}:{}", file.path.display(), line + 1, col + 1)
+    }
+
+    /// Create an error for an IR construct that the backend can't generate code for yet
+    pub(super) fn unsupported(&self, span: Span, what: &str) -> Diagnostic<FileId> {
+        Diagnostic::error()
+            .with_message(format!(
+                "{} is not yet supported in cranelift backend",
+                what
+            ))
+            .with_labels(vec![Label::primary(self.file, span)])
+    }
+
+    /// Get the layout of a type on the target machine
+    pub(super) fn layout(&self, ty: TypeId, span: Span) -> Result<Layout, Diagnostic<FileId>> {
+        self.gen.layout(ty, self.file, span)
+    }
+
+    /// Get the Cranelift type of an IR type, producing an error for non-scalar types
+    pub(super) fn clif_type(&self, ty: TypeId, span: Span) -> Result<Type, Diagnostic<FileId>> {
+        self.gen
+            .clif_type(ty)
+            .ok_or_else(|| self.gen.unsupported_type(ty, self.file, span))
+    }
+}
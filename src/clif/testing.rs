@@ -0,0 +1,57 @@
+//! Helpers for tests that compile spark source code with Cranelift and run the result
+
+use std::{
+    path::PathBuf,
+    process::Command,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use crate::{ir::lower::testing::lower_modules, CompileOpts, OutputFileType, RelocModel};
+
+use super::ClifCodeGenerator;
+
+/// Get a path in the temporary directory that no other test in this process uses
+fn temp_path(extension: &str) -> PathBuf {
+    static COUNTER: AtomicUsize = AtomicUsize::new(0);
+    std::env::temp_dir().join(format!(
+        "spark-clif-test-{}-{}.{}",
+        std::process::id(),
+        COUNTER.fetch_add(1, Ordering::Relaxed),
+        extension
+    ))
+}
+
+/// Compile a root module and child modules given by name and source code to one object file, link
+/// it with the system C compiler, and run it, returning the exit code of the program
+pub fn run_modules(root: &str, children: &[(&str, &str)]) -> i32 {
+    let lowered = lower_modules(root, children);
+    let object = temp_path("o");
+    let exe = temp_path("out");
+    let mut opts = CompileOpts::testing(OutputFileType::Object, object.clone());
+    opts.target.reloc = RelocModel::PIC;
+
+    ClifCodeGenerator::new(&lowered.ctx, &lowered.files, opts)
+        .and_then(|gen| gen.gen())
+        .unwrap_or_else(|e| panic!("failed to generate code: {} {:?}", e.message, e.notes));
+
+    let linked = Command::new("cc")
+        .arg(&object)
+        .arg("-o")
+        .arg(&exe)
+        .status()
+        .expect("failed to run the C compiler to link the object file");
+    assert!(linked.success(), "failed to link {}", object.display());
+
+    let status = Command::new(&exe)
+        .status()
+        .unwrap_or_else(|e| panic!("failed to run {}: {}", exe.display(), e));
+    let _ = std::fs::remove_file(object);
+    let _ = std::fs::remove_file(exe);
+    status.code().expect("program was terminated by a signal")
+}
+
+/// Compile a single module to an object file, link it with the system C compiler, and run it,
+/// returning the exit code of the program
+pub fn run(src: &str) -> i32 {
+    run_modules(src, &[])
+}
@@ -0,0 +1,135 @@
+//! Call graphs recording which functions each function's body may call
+
+use hashbrown::{HashMap, HashSet};
+
+use super::{opt::block_reads, value::IrExprKind, FunId, IrContext, IrStmtKind};
+
+/// The functions that may be called by every function with a body in an [IrContext]
+///
+/// Calls through function pointers can't be resolved to a single function, so a function is
+/// considered to call every function that it takes the address of
+#[derive(Clone, Debug)]
+pub struct CallGraph {
+    /// Functions referenced by the reachable blocks of every function's body, in the order they
+    /// are first referenced
+    callees: HashMap<FunId, Vec<FunId>>,
+}
+
+impl CallGraph {
+    /// Build the call graph of every function in the context, functions without a body call no
+    /// functions
+    pub fn new(ctx: &IrContext) -> Self {
+        let mut callees = HashMap::new();
+        for fun in ctx.funs.indices() {
+            let mut called = Vec::new();
+            let mut add = |callee: FunId| {
+                if !called.contains(&callee) {
+                    called.push(callee);
+                }
+            };
+
+            for bb in ctx.blocks_of(fun) {
+                for stmt in ctx[bb].stmts.iter() {
+                    if let IrStmtKind::Call { fun, .. } = &stmt.kind {
+                        add(*fun);
+                    }
+                }
+                block_reads(&ctx[bb], &mut |expr| {
+                    expr.walk(&mut |expr| {
+                        if let IrExprKind::Fun(fun) = &expr.kind {
+                            add(*fun);
+                        }
+                    })
+                });
+            }
+
+            callees.insert(fun, called);
+        }
+
+        Self { callees }
+    }
+
+    /// Get the functions that a function may call directly or through a pointer
+    pub fn callees(&self, fun: FunId) -> &[FunId] {
+        self.callees.get(&fun).map_or(&[], Vec::as_slice)
+    }
+
+    /// Get all functions that may be called, directly or through other functions, starting from
+    /// the given functions. Every root is reachable from itself
+    pub fn reachable_from(&self, roots: impl IntoIterator<Item = FunId>) -> HashSet<FunId> {
+        let mut reachable = HashSet::new();
+        let mut stack = roots.into_iter().collect::<Vec<_>>();
+        while let Some(fun) = stack.pop() {
+            if reachable.insert(fun) {
+                stack.extend(self.callees(fun).iter().copied());
+            }
+        }
+        reachable
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::CallGraph;
+    use crate::ir::lower::testing::lower;
+
+    const THREE_FUNS: &str = "fun helper() -> i32 {
+    return 2
+}
+
+fun orphan() -> i32 {
+    return helper()
+}
+
+fun main() -> i32 {
+    return helper()
+}";
+
+    #[test]
+    fn unreachable_helper_is_not_reachable_from_main() {
+        let lowered = lower(THREE_FUNS);
+        let (main, helper, orphan) = (
+            lowered.fun("main"),
+            lowered.fun("helper"),
+            lowered.fun("orphan"),
+        );
+        let graph = CallGraph::new(&lowered.ctx);
+        //The main function also calls the function initializing globals
+        assert!(graph.callees(main).contains(&helper));
+        assert_eq!(graph.callees(orphan), &[helper]);
+        assert_eq!(graph.callees(helper), &[]);
+
+        let reachable = graph.reachable_from([main]);
+        assert!(reachable.contains(&main) && reachable.contains(&helper));
+        assert!(!reachable.contains(&orphan));
+        assert_eq!(
+            lowered.warning_messages(),
+            vec!["Function orphan is never used".to_owned()]
+        );
+    }
+
+    #[test]
+    fn used_functions_do_not_warn() {
+        let lowered = lower(&THREE_FUNS.replace("fun orphan", "fun used orphan"));
+        assert_eq!(lowered.warning_messages(), Vec::<String>::new());
+    }
+
+    #[test]
+    fn functions_called_through_pointers_are_reachable() {
+        let lowered = lower(
+            "fun helper() -> i32 {
+    return 2
+}
+
+fun main() -> i32 {
+    let f = helper
+    return f()
+}",
+        );
+        let graph = CallGraph::new(&lowered.ctx);
+        assert!(graph
+            .callees(lowered.fun("main"))
+            .contains(&lowered.fun("helper")));
+        assert_eq!(lowered.warning_messages(), Vec::<String>::new());
+    }
+}
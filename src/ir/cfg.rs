@@ -0,0 +1,205 @@
+//! Traversal of the control flow graph formed by the basic blocks of a function's body
+
+use hashbrown::{HashMap, HashSet};
+
+use super::{BBId, FunId, IrBranch, IrContext, IrTerminator};
+
+impl IrTerminator {
+    /// Get all jumps that this terminator may take with the values they pass to their targets,
+    /// with the arms of a match before its default jump
+    pub fn branches(&self) -> impl Iterator<Item = &IrBranch> + '_ {
+        let (first, arms, last) = match self {
+            Self::Jmp(to) => (Some(to), &[][..], None),
+            Self::JmpIf {
+                if_true, if_false, ..
+            } => (Some(if_true), &[][..], Some(if_false)),
+            Self::JmpMatch {
+                discriminants,
+                default_jmp,
+                ..
+            } => (None, discriminants.as_slice(), Some(default_jmp)),
+            Self::Return(_) | Self::Unreachable | Self::Invalid => (None, &[][..], None),
+        };
+
+        first
+            .into_iter()
+            .chain(arms.iter().map(|(_, branch)| branch))
+            .chain(last)
+    }
+
+    /// Get all jumps that this terminator may take mutably, in the same order as
+    /// [branches](IrTerminator::branches)
+    pub fn branches_mut(&mut self) -> impl Iterator<Item = &mut IrBranch> + '_ {
+        let (first, arms, last) = match self {
+            Self::Jmp(to) => (Some(to), &mut [][..], None),
+            Self::JmpIf {
+                if_true, if_false, ..
+            } => (Some(if_true), &mut [][..], Some(if_false)),
+            Self::JmpMatch {
+                discriminants,
+                default_jmp,
+                ..
+            } => (None, discriminants.as_mut_slice(), Some(default_jmp)),
+            Self::Return(_) | Self::Unreachable | Self::Invalid => (None, &mut [][..], None),
+        };
+
+        first
+            .into_iter()
+            .chain(arms.iter_mut().map(|(_, branch)| branch))
+            .chain(last)
+    }
+
+    /// Get all basic blocks that this terminator may jump to, with the arms of a match before its
+    /// default jump
+    pub fn successors(&self) -> impl Iterator<Item = BBId> + '_ {
+        self.branches().map(|branch| branch.bb)
+    }
+}
+
+impl IrContext {
+    /// Get all blocks of a function's body that are reachable from its entry block, in
+    /// depth-first order visiting a block's successors in the order its terminator lists them.
+    /// Returns no blocks for functions without a body
+    pub fn blocks_of(&self, fun: FunId) -> Vec<BBId> {
+        let entry = match self.fun_body(fun) {
+            Some(body) => body.entry,
+            None => return vec![],
+        };
+
+        let mut order = vec![];
+        let mut visited = HashSet::new();
+        let mut stack = vec![entry];
+        while let Some(bb) = stack.pop() {
+            if !visited.insert(bb) {
+                continue;
+            }
+            order.push(bb);
+
+            let first = stack.len();
+            stack.extend(self[bb].terminator.successors());
+            stack[first..].reverse();
+        }
+
+        order
+    }
+
+    /// Get all blocks of a function's body that are reachable from its entry block in reverse
+    /// postorder, so that every block appears before its successors except along back edges
+    pub fn reverse_postorder(&self, fun: FunId) -> Vec<BBId> {
+        let entry = match self.fun_body(fun) {
+            Some(body) => body.entry,
+            None => return vec![],
+        };
+
+        let mut postorder = vec![];
+        let mut visited = HashSet::new();
+        visited.insert(entry);
+        //Each block on the stack is paired with the index of the next successor to visit
+        let mut stack = vec![(entry, 0)];
+        while let Some((bb, next)) = stack.last_mut() {
+            let bb = *bb;
+            match self[bb].terminator.successors().nth(*next) {
+                Some(succ) => {
+                    *next += 1;
+                    if visited.insert(succ) {
+                        stack.push((succ, 0));
+                    }
+                }
+                None => {
+                    postorder.push(bb);
+                    stack.pop();
+                }
+            }
+        }
+
+        postorder.reverse();
+        postorder
+    }
+
+    /// Map every block reachable from a function's entry block to the blocks that may jump to
+    /// it, listed in the order of [blocks_of](IrContext::blocks_of)
+    pub fn predecessors(&self, fun: FunId) -> HashMap<BBId, Vec<BBId>> {
+        let blocks = self.blocks_of(fun);
+        let mut preds = blocks
+            .iter()
+            .map(|bb| (*bb, vec![]))
+            .collect::<HashMap<_, _>>();
+
+        for bb in blocks {
+            for succ in self[bb].terminator.successors() {
+                let succ_preds = preds.get_mut(&succ).unwrap();
+                //A match may jump to the same block from multiple arms
+                if succ_preds.last() != Some(&bb) {
+                    succ_preds.push(bb);
+                }
+            }
+        }
+
+        preds
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::ir::{testing::build_cfg, IrTerminator};
+
+    #[test]
+    fn successors_follow_the_terminator() {
+        let (mut ctx, _, bbs) = build_cfg(&[&[1], &[2, 3], &[3, 0, 1, 2], &[]]);
+        let succs = |ctx: &crate::ir::IrContext, idx: usize| {
+            ctx[bbs[idx]].terminator.successors().collect::<Vec<_>>()
+        };
+
+        assert_eq!(succs(&ctx, 0), [bbs[1]]);
+        assert_eq!(succs(&ctx, 1), [bbs[2], bbs[3]]);
+        //Match arms come before the default jump
+        assert_eq!(succs(&ctx, 2), [bbs[3], bbs[0], bbs[1], bbs[2]]);
+        assert_eq!(succs(&ctx, 3), []);
+
+        ctx[bbs[3]].terminator = IrTerminator::Unreachable;
+        assert_eq!(succs(&ctx, 3), []);
+        ctx[bbs[3]].terminator = IrTerminator::Invalid;
+        assert_eq!(succs(&ctx, 3), []);
+    }
+
+    #[test]
+    fn predecessors_of_reachable_blocks() {
+        //The match jumps to block 3 from two arms, block 4 is unreachable
+        let (ctx, fun, bbs) = build_cfg(&[&[1, 2], &[3], &[3, 3, 1], &[], &[3]]);
+        let preds = ctx.predecessors(fun);
+
+        assert_eq!(preds.len(), 4);
+        assert_eq!(preds[&bbs[0]], []);
+        assert_eq!(preds[&bbs[1]], [bbs[0], bbs[2]]);
+        assert_eq!(preds[&bbs[2]], [bbs[0]]);
+        assert_eq!(preds[&bbs[3]], [bbs[1], bbs[2]]);
+        assert!(!preds.contains_key(&bbs[4]));
+    }
+
+    #[test]
+    fn reverse_postorder_of_diamond() {
+        //0 branches to 1 and 2, which join at 3 before returning from 4
+        let (ctx, fun, bbs) = build_cfg(&[&[1, 2], &[3], &[3], &[4], &[]]);
+        assert_eq!(
+            ctx.reverse_postorder(fun),
+            [bbs[0], bbs[2], bbs[1], bbs[3], bbs[4]]
+        );
+        assert_eq!(ctx.blocks_of(fun), [bbs[0], bbs[1], bbs[3], bbs[4], bbs[2]]);
+    }
+
+    #[test]
+    fn reverse_postorder_places_blocks_before_successors_except_back_edges() {
+        //Loop header 1 runs body 2 or exits to 3, the body jumps back to the header
+        let (ctx, fun, bbs) = build_cfg(&[&[1], &[2, 3], &[1], &[]]);
+        let order = ctx.reverse_postorder(fun);
+        assert_eq!(order, [bbs[0], bbs[1], bbs[3], bbs[2]]);
+
+        let position = |bb| order.iter().position(|ordered| *ordered == bb).unwrap();
+        for bb in order.iter() {
+            for succ in ctx[*bb].terminator.successors() {
+                let back_edge = *bb == bbs[2] && succ == bbs[1];
+                assert_eq!(position(*bb) < position(succ), !back_edge);
+            }
+        }
+    }
+}
@@ -0,0 +1,663 @@
+//! Evaluation of IR expressions at compile time, used wherever the lowerer requires a constant
+//! and to fold constant operands of operators
+//!
+//! Integer arithmetic wraps to the width of the operand type, the same as it would at runtime.
+//! Operators on pointer-sized integers are not evaluated, as their results depend on the
+//! pointer width of the target
+
+use std::{cmp::Ordering, convert::TryFrom, fmt};
+
+use codespan_reporting::diagnostic::{Diagnostic, Label};
+use serde::Serialize;
+
+use crate::{
+    ast::{BigInt, IntegerWidth},
+    parse::token::Op,
+    util::{files::FileId, loc::Span},
+};
+
+use super::{
+    types::{IrFloatType, IrIntegerType, IrType},
+    value::{IrExpr, IrExprKind, IrLiteral},
+    IrContext, TypeId,
+};
+
+/// A value computed at compile time
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+pub enum ConstValue {
+    /// Integer of the given type, sign extended to 128 bits if the type is signed and zero
+    /// extended otherwise, so that `u128` values above `i128::MAX` are negative
+    Integer(i128, IrIntegerType),
+    /// Float of the given type, rounded to single precision if the type is `f32`
+    Float(f64, IrFloatType),
+    Bool(bool),
+    Char(char),
+}
+
+/// An error produced when an expression can't be evaluated at compile time
+#[derive(Clone, Debug)]
+pub struct ConstEvalError {
+    /// Location of the subexpression that could not be evaluated
+    pub span: Span,
+    /// What went wrong
+    pub kind: ConstEvalErrorKind,
+}
+
+/// Reasons that an expression can't be evaluated at compile time
+#[derive(Clone, Debug)]
+pub enum ConstEvalErrorKind {
+    /// The expression depends on values only known at runtime, with a description of the
+    /// offending expression like "function calls"
+    NotConst(&'static str),
+    /// An integer was divided by zero
+    DivByZero,
+    /// An operation produced a value that can't be represented and does not wrap, with a
+    /// description of the operation
+    Overflow(String),
+}
+
+impl ConstEvalError {
+    /// Check if the error was caused by a runtime value rather than an invalid operation on
+    /// constant values
+    pub fn is_not_const(&self) -> bool {
+        matches!(self.kind, ConstEvalErrorKind::NotConst(_))
+    }
+
+    /// Create an error diagnostic pointing at the subexpression that could not be evaluated
+    pub fn to_diagnostic(&self, file: FileId) -> Diagnostic<FileId> {
+        match &self.kind {
+            ConstEvalErrorKind::NotConst(what) => Diagnostic::error()
+                .with_message("Expression cannot be evaluated at compile time")
+                .with_labels(vec![Label::primary(file, self.span)
+                    .with_message("Expression depends on values only known at runtime")])
+                .with_notes(vec![format!(
+                    "{} are not allowed in constant expressions",
+                    what
+                )]),
+            ConstEvalErrorKind::DivByZero => Diagnostic::error()
+                .with_message("Division by zero in constant expression")
+                .with_labels(vec![Label::primary(file, self.span)
+                    .with_message("Constant expression evaluated here")]),
+            ConstEvalErrorKind::Overflow(msg) => Diagnostic::error()
+                .with_message(format!("Overflow in constant expression: {}", msg))
+                .with_labels(vec![Label::primary(file, self.span)
+                    .with_message("Constant expression evaluated here")]),
+        }
+    }
+}
+
+impl ConstValue {
+    /// Create a literal expression of the given type holding this value
+    pub fn to_expr(&self, span: Span, ty: TypeId) -> IrExpr {
+        let lit = match *self {
+            Self::Integer(val, ity) => IrLiteral::Integer(
+                BigInt {
+                    val: val as u128,
+                    sign: false,
+                },
+                ity,
+            ),
+            Self::Float(val, fty) => IrLiteral::Float(val, fty),
+            Self::Bool(val) => IrLiteral::Bool(val),
+            Self::Char(val) => IrLiteral::Char(val),
+        };
+
+        IrExpr {
+            span,
+            ty,
+            kind: IrExprKind::Lit(lit),
+        }
+    }
+}
+
+impl fmt::Display for ConstValue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Integer(val, IrIntegerType { signed: false, .. }) => {
+                write!(f, "{}", *val as u128)
+            }
+            Self::Integer(val, _) => write!(f, "{}", val),
+            Self::Float(val, _) => write!(f, "{:?}", val),
+            Self::Bool(val) => write!(f, "{}", val),
+            Self::Char(val) => write!(f, "{:?}", val),
+        }
+    }
+}
+
+impl IrContext {
+    /// Evaluate an expression at compile time, failing if it depends on a runtime value or
+    /// performs an invalid operation. Globals can be read if they are constants that have
+    /// already been evaluated
+    pub fn const_eval(&self, expr: &IrExpr) -> Result<ConstValue, ConstEvalError> {
+        self.eval(expr, false)
+    }
+
+    /// Evaluate an expression at compile time like [const_eval](IrContext::const_eval), but fail
+    /// instead of wrapping when integer addition, subtraction, or multiplication overflows
+    pub fn const_eval_checked(&self, expr: &IrExpr) -> Result<ConstValue, ConstEvalError> {
+        self.eval(expr, true)
+    }
+
+    /// Evaluate an expression, checking arithmetic for overflow if `checked` is set
+    fn eval(&self, expr: &IrExpr, checked: bool) -> Result<ConstValue, ConstEvalError> {
+        let err = |kind| ConstEvalError {
+            span: expr.span,
+            kind,
+        };
+
+        match &expr.kind {
+            IrExprKind::Lit(lit) => match lit {
+                IrLiteral::Integer(val, ity) => {
+                    let val = match val.sign {
+                        true => (val.val as i128).wrapping_neg(),
+                        false => val.val as i128,
+                    };
+                    Ok(ConstValue::Integer(Self::wrap_int(val, *ity), *ity))
+                }
+                IrLiteral::Float(val, fty) => {
+                    Ok(ConstValue::Float(Self::round_float(*val, *fty), *fty))
+                }
+                IrLiteral::Bool(val) => Ok(ConstValue::Bool(*val)),
+                IrLiteral::Char(val) => Ok(ConstValue::Char(*val)),
+                IrLiteral::String(_)
+                | IrLiteral::Array(_)
+                | IrLiteral::Struct(_)
+                | IrLiteral::Tuple(_)
+                | IrLiteral::Unit => Err(err(ConstEvalErrorKind::NotConst("aggregate values"))),
+            },
+            IrExprKind::Global(glob) => self[*glob].value.ok_or_else(|| {
+                err(ConstEvalErrorKind::NotConst(
+                    "reads of globals that are not previously defined constants",
+                ))
+            }),
+            IrExprKind::Unary(op, operand) => {
+                let val = self.eval(operand, checked)?;
+                self.const_unary(expr, *op, val, checked)
+            }
+            IrExprKind::Binary(lhs, op, rhs) => {
+                let l = self.eval(lhs, checked)?;
+                let r = self.eval(rhs, checked)?;
+                self.const_bin(expr, l, *op, r, rhs.span, checked)
+            }
+            IrExprKind::Cast(operand, ty, _) => {
+                let val = self.eval(operand, checked)?;
+                self.const_cast(expr, val, *ty)
+            }
+            IrExprKind::Call(..) => Err(err(ConstEvalErrorKind::NotConst("function calls"))),
+            IrExprKind::Var(_) | IrExprKind::Param(..) => {
+                Err(err(ConstEvalErrorKind::NotConst("variables")))
+            }
+            IrExprKind::VolatileRead(_) => Err(err(ConstEvalErrorKind::NotConst("volatile reads"))),
+            IrExprKind::Fun(_) => Err(err(ConstEvalErrorKind::NotConst("function addresses"))),
+            IrExprKind::SizeOf(_) | IrExprKind::AlignOf(_) => Err(err(
+                ConstEvalErrorKind::NotConst("target-dependent type sizes and alignments"),
+            )),
+            IrExprKind::Member(..)
+            | IrExprKind::Index(..)
+            | IrExprKind::Slice(..)
+            | IrExprKind::Variant(..) => Err(err(ConstEvalErrorKind::NotConst("aggregate values"))),
+        }
+    }
+
+    /// Apply a unary operator to a constant value, checking negation of signed integers for
+    /// overflow if `checked` is set
+    fn const_unary(
+        &self,
+        expr: &IrExpr,
+        op: Op,
+        val: ConstValue,
+        checked: bool,
+    ) -> Result<ConstValue, ConstEvalError> {
+        Ok(match (op, val) {
+            (_, ConstValue::Integer(_, ity)) if ity.width == IntegerWidth::PtrSize => {
+                return Err(Self::ptr_sized(expr))
+            }
+            (Op::Sub, ConstValue::Integer(val, ity)) if checked && ity.signed => {
+                match val
+                    .checked_neg()
+                    .filter(|neg| Self::wrap_int(*neg, ity) == *neg)
+                {
+                    Some(neg) => ConstValue::Integer(neg, ity),
+                    None => {
+                        return Err(ConstEvalError {
+                            span: expr.span,
+                            kind: ConstEvalErrorKind::Overflow(format!(
+                                "-({}) does not fit in a {} bit integer",
+                                val,
+                                Self::int_bits(ity)
+                            )),
+                        })
+                    }
+                }
+            }
+            (Op::Sub, ConstValue::Integer(val, ity)) => {
+                ConstValue::Integer(Self::wrap_int(val.wrapping_neg(), ity), ity)
+            }
+            (Op::NOT, ConstValue::Integer(val, ity)) => {
+                ConstValue::Integer(Self::wrap_int(!val, ity), ity)
+            }
+            (Op::Sub, ConstValue::Float(val, fty)) => ConstValue::Float(-val, fty),
+            (Op::LogicalNot, ConstValue::Bool(val)) => ConstValue::Bool(!val),
+            _ => {
+                return Err(ConstEvalError {
+                    span: expr.span,
+                    kind: ConstEvalErrorKind::NotConst("pointer operations"),
+                })
+            }
+        })
+    }
+
+    /// Apply a binary operator to constant operands, producing a value of the expression's type
+    fn const_bin(
+        &self,
+        expr: &IrExpr,
+        lhs: ConstValue,
+        op: Op,
+        rhs: ConstValue,
+        rhs_span: Span,
+        checked: bool,
+    ) -> Result<ConstValue, ConstEvalError> {
+        let err = |kind| ConstEvalError {
+            span: expr.span,
+            kind,
+        };
+
+        match (lhs, rhs) {
+            (ConstValue::Integer(_, ity), ConstValue::Integer(..))
+                if ity.width == IntegerWidth::PtrSize =>
+            {
+                Err(Self::ptr_sized(expr))
+            }
+            (ConstValue::Integer(l, ity), ConstValue::Integer(r, _)) => {
+                let bits = Self::int_bits(ity);
+                //Unsigned values are compared and divided as their zero extended bits
+                let ord = match ity.signed {
+                    true => l.cmp(&r),
+                    false => (l as u128).cmp(&(r as u128)),
+                };
+                if let Some(cmp) = Self::const_cmp(op, ord) {
+                    return Ok(ConstValue::Bool(cmp));
+                }
+
+                if checked && Self::overflows(l, op, r, ity) {
+                    return Err(err(ConstEvalErrorKind::Overflow(format!(
+                        "{} {} {} does not fit in a {} bit integer",
+                        lhs, op, rhs, bits
+                    ))));
+                }
+
+                let val = match op {
+                    Op::Add => l.wrapping_add(r),
+                    Op::Sub => l.wrapping_sub(r),
+                    Op::Star => l.wrapping_mul(r),
+                    Op::Div | Op::Mod if r == 0 => return Err(err(ConstEvalErrorKind::DivByZero)),
+                    Op::Div | Op::Mod if ity.signed && r == -1 && l == Self::int_min(ity) => {
+                        return Err(err(ConstEvalErrorKind::Overflow(format!(
+                            "{} {} -1 does not fit in a {} bit integer",
+                            l, op, bits
+                        ))))
+                    }
+                    Op::Div if ity.signed => l.wrapping_div(r),
+                    Op::Mod if ity.signed => l.wrapping_rem(r),
+                    Op::Div => ((l as u128) / (r as u128)) as i128,
+                    Op::Mod => ((l as u128) % (r as u128)) as i128,
+                    Op::ShLeft | Op::ShRight if r < 0 || r >= bits as i128 => {
+                        return Err(ConstEvalError {
+                            span: rhs_span,
+                            kind: ConstEvalErrorKind::Overflow(format!(
+                                "shift amount {} is out of range for a {} bit integer",
+                                r, bits
+                            )),
+                        })
+                    }
+                    Op::ShLeft => l.wrapping_shl(r as u32),
+                    Op::ShRight if ity.signed => l >> r,
+                    Op::ShRight => ((l as u128) >> r) as i128,
+                    Op::AND => l & r,
+                    Op::OR => l | r,
+                    Op::XOR => l ^ r,
+                    _ => return Err(err(ConstEvalErrorKind::NotConst("pointer operations"))),
+                };
+
+                Ok(ConstValue::Integer(Self::wrap_int(val, ity), ity))
+            }
+            (ConstValue::Float(l, fty), ConstValue::Float(r, _)) => {
+                //Comparisons with NaN are always false
+                let cmp = l.partial_cmp(&r).map_or(
+                    match op {
+                        Op::Eq | Op::Less | Op::LessEq | Op::Greater | Op::GreaterEq => Some(false),
+                        _ => None,
+                    },
+                    |ord| Self::const_cmp(op, ord),
+                );
+                if let Some(cmp) = cmp {
+                    return Ok(ConstValue::Bool(cmp));
+                }
+
+                let val = match op {
+                    Op::Add => l + r,
+                    Op::Sub => l - r,
+                    Op::Star => l * r,
+                    Op::Div => l / r,
+                    Op::Mod => l % r,
+                    _ => return Err(err(ConstEvalErrorKind::NotConst("pointer operations"))),
+                };
+
+                Ok(ConstValue::Float(Self::round_float(val, fty), fty))
+            }
+            (ConstValue::Bool(l), ConstValue::Bool(r)) => Ok(ConstValue::Bool(match op {
+                Op::LogicalAnd => l && r,
+                Op::LogicalOr => l || r,
+                Op::Eq => l == r,
+                Op::AND => l & r,
+                Op::OR => l | r,
+                Op::XOR => l ^ r,
+                _ => return Err(err(ConstEvalErrorKind::NotConst("pointer operations"))),
+            })),
+            _ => Err(err(ConstEvalErrorKind::NotConst("pointer operations"))),
+        }
+    }
+
+    /// Convert a constant value to another type
+    fn const_cast(
+        &self,
+        expr: &IrExpr,
+        val: ConstValue,
+        ty: TypeId,
+    ) -> Result<ConstValue, ConstEvalError> {
+        let err = |kind| ConstEvalError {
+            span: expr.span,
+            kind,
+        };
+
+        let int = match val {
+            ConstValue::Integer(val, _) => Some(val),
+            ConstValue::Char(c) => Some(c as i128),
+            _ => None,
+        };
+
+        Ok(match (val, int, &self[self.unwrap_alias(ty)]) {
+            //Pointer-sized values are wrapped to 64 bits and truncated further by the code
+            //generator if the target's pointers are narrower, the same as at runtime
+            (_, Some(val), IrType::Integer(ity)) => {
+                ConstValue::Integer(Self::wrap_int(val, *ity), *ity)
+            }
+            (ConstValue::Integer(val, from), _, IrType::Float(fty)) => {
+                let val = match from.signed {
+                    true => val as f64,
+                    false => val as u128 as f64,
+                };
+                ConstValue::Float(Self::round_float(val, *fty), *fty)
+            }
+            (_, Some(val), IrType::Char) => {
+                match u32::try_from(val).ok().and_then(char::from_u32) {
+                    Some(c) => ConstValue::Char(c),
+                    None => {
+                        return Err(err(ConstEvalErrorKind::Overflow(format!(
+                            "{} is not a valid character",
+                            val
+                        ))))
+                    }
+                }
+            }
+            (ConstValue::Float(val, _), _, IrType::Float(fty)) => {
+                ConstValue::Float(Self::round_float(val, *fty), *fty)
+            }
+            (ConstValue::Float(val, _), _, IrType::Integer(ity)) => {
+                if ity.width == IntegerWidth::PtrSize {
+                    return Err(Self::ptr_sized(expr));
+                }
+
+                //Floats are truncated towards zero, so values up to but not including the next
+                //power of two after the largest integer fit
+                let limit = 2f64.powi(Self::int_bits(*ity) as i32 - ity.signed as i32);
+                let min = match ity.signed {
+                    true => -limit,
+                    false => -1.0,
+                };
+                if val.is_nan() || val <= min - 1.0 || val >= limit {
+                    return Err(err(ConstEvalErrorKind::Overflow(format!(
+                        "{} does not fit in an integer of type {}",
+                        val,
+                        self.typename(ty)
+                    ))));
+                }
+                match ity.signed {
+                    true => ConstValue::Integer(val as i128, *ity),
+                    false => ConstValue::Integer(val as u128 as i128, *ity),
+                }
+            }
+            (ConstValue::Bool(val), _, IrType::Integer(ity)) => {
+                ConstValue::Integer(val as i128, *ity)
+            }
+            (ConstValue::Integer(val, _), _, IrType::Bool) => ConstValue::Bool(val != 0),
+            (ConstValue::Bool(val), _, IrType::Bool) => ConstValue::Bool(val),
+            _ => {
+                return Err(err(ConstEvalErrorKind::NotConst(
+                    "casts to non-number types",
+                )))
+            }
+        })
+    }
+
+    /// Get the result of a comparison operator given the ordering of its operands, or `None` if
+    /// the operator is not a comparison
+    fn const_cmp(op: Op, ord: Ordering) -> Option<bool> {
+        Some(match op {
+            Op::Eq => ord == Ordering::Equal,
+            Op::Less => ord == Ordering::Less,
+            Op::LessEq => ord != Ordering::Greater,
+            Op::Greater => ord == Ordering::Greater,
+            Op::GreaterEq => ord != Ordering::Less,
+            _ => return None,
+        })
+    }
+
+    /// Create the error for an operation on pointer-sized integers
+    fn ptr_sized(expr: &IrExpr) -> ConstEvalError {
+        ConstEvalError {
+            span: expr.span,
+            kind: ConstEvalErrorKind::NotConst(
+                "operations on pointer-sized integers, whose width depends on the target,",
+            ),
+        }
+    }
+
+    /// Round a float to the precision of its type
+    fn round_float(val: f64, fty: IrFloatType) -> f64 {
+        match fty.doublewide {
+            true => val,
+            false => val as f32 as f64,
+        }
+    }
+
+    /// Get the bit width of an integer type, treating pointer-sized integers as 64 bits wide
+    fn int_bits(ity: IrIntegerType) -> u32 {
+        match ity.width {
+            IntegerWidth::PtrSize => 64,
+            width => width as u32,
+        }
+    }
+
+    /// Truncate a value to the width of an integer type, then sign extend it if the type is
+    /// signed and zero extend it otherwise
+    fn wrap_int(val: i128, ity: IrIntegerType) -> i128 {
+        let unused = 128 - Self::int_bits(ity);
+        match ity.signed {
+            true => (val << unused) >> unused,
+            false => ((val as u128) << unused >> unused) as i128,
+        }
+    }
+
+    /// Check if adding, subtracting, or multiplying two integers of a type produces a value that
+    /// the type can't represent. Other operators never overflow
+    fn overflows(l: i128, op: Op, r: i128, ity: IrIntegerType) -> bool {
+        let exact = match (op, ity.signed) {
+            (Op::Add, true) => l.checked_add(r),
+            (Op::Sub, true) => l.checked_sub(r),
+            (Op::Star, true) => l.checked_mul(r),
+            (Op::Add, false) => (l as u128).checked_add(r as u128).map(|val| val as i128),
+            (Op::Sub, false) => (l as u128).checked_sub(r as u128).map(|val| val as i128),
+            (Op::Star, false) => (l as u128).checked_mul(r as u128).map(|val| val as i128),
+            _ => return false,
+        };
+
+        match exact {
+            Some(exact) => Self::wrap_int(exact, ity) != exact,
+            None => true,
+        }
+    }
+
+    /// Get the smallest value representable by an integer type
+    fn int_min(ity: IrIntegerType) -> i128 {
+        match ity.signed {
+            true => Self::wrap_int(1 << (Self::int_bits(ity) - 1), ity),
+            false => 0,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ConstEvalErrorKind, ConstValue};
+    use crate::{
+        ast::{BigInt, IntegerWidth},
+        ir::{
+            lower::testing::{lower, lower_err, lower_with},
+            types::IrIntegerType,
+            value::{IrExpr, IrExprKind, IrLiteral},
+            IrContext, IrStmtKind,
+        },
+        parse::token::Op,
+        util::loc::Span,
+    };
+
+    /// Lower constant declarations and get the integer value of the named constant
+    fn const_int(src: &str, name: &str) -> i128 {
+        let lowered = lower(&format!("{}\nfun main() -> i32 {{ return 0 }}", src));
+        let ctx = &lowered.ctx;
+        let glob = ctx
+            .globals
+            .indices()
+            .find(|glob| ctx[*glob].name.as_str() == name)
+            .unwrap_or_else(|| panic!("no constant named {}", name));
+        match ctx[glob].value {
+            Some(ConstValue::Integer(val, _)) => val,
+            other => panic!("constant {} has value {:?}", name, other),
+        }
+    }
+
+    #[test]
+    fn nested_arithmetic_is_evaluated() {
+        assert_eq!(const_int("const A = (2 + 3) * 4 - 10 / 2", "A"), 15);
+        assert_eq!(
+            const_int("const A = 3\nconst B = (A << 2) % 5 + -A", "B"),
+            -1
+        );
+        assert_eq!(const_int("const A = $u8 300", "A"), 44);
+    }
+
+    #[test]
+    fn constant_overflow_is_rejected() {
+        for src in [
+            "const [i8] A = 127 + 1",
+            "const A = 127i8 + 1i8",
+            "const A = -128i8 - 1i8",
+            "const A = $i64 (0u8 - 1u8)",
+            "const A = 127i8\nconst B = A * 2i8",
+        ] {
+            let err = lower_err(&format!("{}\nfun main() -> i32 {{ return 0 }}", src));
+            assert!(
+                err.message.starts_with("Overflow in constant expression"),
+                "{}: {}",
+                src,
+                err.message
+            );
+        }
+    }
+
+    #[test]
+    fn runtime_overflow_wraps_unless_checked() {
+        let src = "fun main() -> i32 {
+    let x = 127i8 + 1i8
+    return $i32 x
+}";
+        let lowered = lower(src);
+        let ctx = &lowered.ctx;
+        let stored = ctx
+            .fun_body(lowered.fun("main"))
+            .unwrap()
+            .blocks
+            .iter()
+            .flat_map(|bb| ctx[*bb].stmts.iter())
+            .find_map(|stmt| match &stmt.kind {
+                IrStmtKind::Store { var, val } if ctx[*var].name.as_str() == "x" => Some(val),
+                _ => None,
+            })
+            .unwrap();
+        assert!(
+            matches!(ctx.const_eval(stored), Ok(ConstValue::Integer(-128, _))),
+            "{}",
+            ctx
+        );
+
+        let err = lower_with(src, &[], |lowerer| lowerer.set_overflow_checks(true))
+            .err()
+            .expect("overflowing expression was accepted");
+        assert!(
+            err.message.starts_with("Overflow in constant expression"),
+            "{}",
+            err.message
+        );
+    }
+
+    #[test]
+    fn division_by_zero_is_rejected() {
+        let err = lower_err("const A = 1 / (2 - 2)\nfun main() -> i32 { return 0 }");
+        assert_eq!(err.message, "Division by zero in constant expression");
+        let err = lower_err("fun main() -> i32 { return 5 % 0 }");
+        assert_eq!(err.message, "Division by zero in constant expression");
+
+        let lit = |val: u128| IrExpr {
+            span: Span::single(0),
+            ty: IrContext::I32,
+            kind: IrExprKind::Lit(IrLiteral::Integer(
+                BigInt { val, sign: false },
+                IrIntegerType {
+                    signed: true,
+                    width: IntegerWidth::ThirtyTwo,
+                },
+            )),
+        };
+        let div = IrExpr {
+            span: Span::single(0),
+            ty: IrContext::I32,
+            kind: IrExprKind::Binary(Box::new(lit(1)), Op::Div, Box::new(lit(0))),
+        };
+        let ctx = IrContext::new();
+        assert!(matches!(
+            ctx.const_eval_checked(&div).map_err(|e| e.kind),
+            Err(ConstEvalErrorKind::DivByZero)
+        ));
+    }
+
+    #[test]
+    fn function_call_is_not_constant() {
+        let err = lower_err(
+            "fun five() -> i32 { return 5 }
+const A = 1 + five()
+fun main() -> i32 { return A }",
+        );
+        assert_eq!(
+            err.message,
+            "Expression cannot be evaluated at compile time"
+        );
+        assert_eq!(
+            err.labels
+                .iter()
+                .map(|label| label.message.as_str())
+                .collect::<Vec<_>>(),
+            vec!["Expression depends on values only known at runtime"]
+        );
+    }
+}
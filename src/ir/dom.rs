@@ -0,0 +1,315 @@
+//! Dominator trees of function control flow graphs, computed with the iterative algorithm from
+//! Cooper, Harvey, and Kennedy's "A Simple, Fast Dominance Algorithm"
+//!
+//! Blocks that are unreachable from the entry block have no dominators and are not part of the
+//! tree
+
+use hashbrown::HashMap;
+
+use super::{BBId, FunId, IrContext};
+
+/// Immediate dominators and dominance frontiers of all reachable blocks in a function's body
+#[derive(Clone, Debug)]
+pub struct DomTree {
+    /// Reachable blocks in reverse postorder, beginning with the entry block
+    rpo: Vec<BBId>,
+    /// Index of every reachable block in `rpo`
+    rpo_idx: HashMap<BBId, usize>,
+    /// Index in `rpo` of the immediate dominator of every block, the entry block is its own
+    /// immediate dominator
+    idoms: Vec<usize>,
+    /// Dominance frontier of every block, indexed the same as `rpo`
+    frontiers: Vec<Vec<BBId>>,
+}
+
+impl DomTree {
+    /// Compute the dominator tree of a function's body, producing an empty tree for functions
+    /// without a body
+    pub fn new(ctx: &IrContext, fun: FunId) -> Self {
+        let rpo = ctx.reverse_postorder(fun);
+        let rpo_idx = rpo
+            .iter()
+            .enumerate()
+            .map(|(idx, bb)| (*bb, idx))
+            .collect::<HashMap<_, _>>();
+
+        let mut preds = vec![vec![]; rpo.len()];
+        for (idx, bb) in rpo.iter().enumerate() {
+            for succ in ctx[*bb].terminator.successors() {
+                let succ_preds: &mut Vec<usize> = &mut preds[rpo_idx[&succ]];
+                if succ_preds.last() != Some(&idx) {
+                    succ_preds.push(idx);
+                }
+            }
+        }
+
+        const UNDEFINED: usize = usize::MAX;
+        let mut idoms = vec![UNDEFINED; rpo.len()];
+        if let Some(entry) = idoms.first_mut() {
+            *entry = 0;
+        }
+
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for bb in 1..rpo.len() {
+                //Every block but the entry has a predecessor earlier in reverse postorder, so at
+                //least one predecessor has been processed
+                let mut new_idom = UNDEFINED;
+                for pred in preds[bb].iter().copied() {
+                    if idoms[pred] == UNDEFINED {
+                        continue;
+                    }
+                    new_idom = match new_idom {
+                        UNDEFINED => pred,
+                        other => Self::intersect(&idoms, pred, other),
+                    };
+                }
+
+                if idoms[bb] != new_idom {
+                    idoms[bb] = new_idom;
+                    changed = true;
+                }
+            }
+        }
+
+        let mut frontiers = vec![vec![]; rpo.len()];
+        for (bb, bb_preds) in preds.iter().enumerate() {
+            if bb_preds.len() < 2 {
+                continue;
+            }
+
+            for pred in bb_preds.iter().copied() {
+                let mut runner = pred;
+                while runner != idoms[bb] {
+                    let frontier: &mut Vec<BBId> = &mut frontiers[runner];
+                    if !frontier.contains(&rpo[bb]) {
+                        frontier.push(rpo[bb]);
+                    }
+                    runner = idoms[runner];
+                }
+            }
+        }
+
+        Self {
+            rpo,
+            rpo_idx,
+            idoms,
+            frontiers,
+        }
+    }
+
+    /// Walk up the tree from two blocks until reaching their closest common dominator
+    fn intersect(idoms: &[usize], mut a: usize, mut b: usize) -> usize {
+        while a != b {
+            while a > b {
+                a = idoms[a];
+            }
+            while b > a {
+                b = idoms[b];
+            }
+        }
+        a
+    }
+
+    /// Get all blocks in the tree in reverse postorder, beginning with the entry block
+    pub fn rpo(&self) -> &[BBId] {
+        &self.rpo
+    }
+
+    /// Check if the given block is reachable from the entry block and so part of the tree
+    pub fn is_reachable(&self, bb: BBId) -> bool {
+        self.rpo_idx.contains_key(&bb)
+    }
+
+    /// Get the immediate dominator of a block, or `None` for the entry block and unreachable
+    /// blocks
+    pub fn idom(&self, bb: BBId) -> Option<BBId> {
+        match self.rpo_idx.get(&bb) {
+            Some(0) | None => None,
+            Some(idx) => Some(self.rpo[self.idoms[*idx]]),
+        }
+    }
+
+    /// Check if every path from the entry block to `b` passes through `a`. A block dominates
+    /// itself, and unreachable blocks neither dominate nor are dominated by any block
+    pub fn dominates(&self, a: BBId, b: BBId) -> bool {
+        let (a, mut b) = match (self.rpo_idx.get(&a), self.rpo_idx.get(&b)) {
+            (Some(a), Some(b)) => (*a, *b),
+            _ => return false,
+        };
+
+        //Dominators always come before the blocks they dominate in reverse postorder
+        while b > a {
+            b = self.idoms[b];
+        }
+        a == b
+    }
+
+    /// Get the dominance frontier of a block: the blocks where its dominance ends, reached from
+    /// a block that it dominates without being strictly dominated by it
+    pub fn frontier(&self, bb: BBId) -> &[BBId] {
+        match self.rpo_idx.get(&bb) {
+            Some(idx) => &self.frontiers[*idx],
+            None => &[],
+        }
+    }
+
+    /// Get the blocks immediately dominated by a block, in reverse postorder
+    pub fn children(&self, bb: BBId) -> impl Iterator<Item = BBId> + '_ {
+        let parent = self.rpo_idx.get(&bb).copied();
+        self.idoms
+            .iter()
+            .enumerate()
+            .skip(1)
+            .filter(move |(_, idom)| Some(**idom) == parent)
+            .map(move |(idx, _)| self.rpo[idx])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use hashbrown::HashSet;
+
+    use super::DomTree;
+    use crate::ir::{testing::build_cfg, BBId};
+
+    /// Graphs given as successor indices: a diamond, nested loops, a match with duplicate arms,
+    /// an unreachable cycle, and an irreducible loop entered at both 1 and 2
+    const GRAPHS: &[&[&[usize]]] = &[
+        &[&[1, 2], &[3], &[3], &[]],
+        &[&[1], &[2, 5], &[3, 4], &[2], &[1], &[]],
+        &[&[1, 2, 2, 3], &[4], &[4], &[4], &[]],
+        &[&[1], &[], &[3, 1], &[2]],
+        &[&[1, 2], &[2, 3], &[1, 3], &[]],
+        &[&[1, 4], &[2, 3], &[1], &[4, 5], &[3], &[]],
+    ];
+
+    /// Blocks reachable from the entry block without passing through `removed`
+    fn reachable_without(succs: &[&[usize]], removed: Option<usize>) -> HashSet<usize> {
+        let mut seen = HashSet::new();
+        let mut stack = vec![0];
+        while let Some(bb) = stack.pop() {
+            if Some(bb) == removed || !seen.insert(bb) {
+                continue;
+            }
+            stack.extend(succs[bb].iter().copied());
+        }
+        seen
+    }
+
+    /// Check if `a` dominates `b` by the definition: `b` is reachable, and is no longer
+    /// reachable once `a` is removed from the graph
+    fn naive_dominates(succs: &[&[usize]], a: usize, b: usize) -> bool {
+        let reachable = reachable_without(succs, None);
+        reachable.contains(&a)
+            && reachable.contains(&b)
+            && (a == b || !reachable_without(succs, Some(a)).contains(&b))
+    }
+
+    #[test]
+    fn dominance_matches_the_definition() {
+        for succs in GRAPHS {
+            let (ctx, fun, bbs) = build_cfg(succs);
+            let tree = DomTree::new(&ctx, fun);
+            for a in 0..bbs.len() {
+                for b in 0..bbs.len() {
+                    assert_eq!(
+                        tree.dominates(bbs[a], bbs[b]),
+                        naive_dominates(succs, a, b),
+                        "dominates({}, {}) in {:?}",
+                        a,
+                        b,
+                        succs,
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn immediate_dominators_are_the_closest_strict_dominators() {
+        for succs in GRAPHS {
+            let (ctx, fun, bbs) = build_cfg(succs);
+            let tree = DomTree::new(&ctx, fun);
+            let reachable = reachable_without(succs, None);
+            for b in 0..bbs.len() {
+                //Every other strict dominator of b dominates its immediate dominator
+                let strict = (0..bbs.len())
+                    .filter(|a| *a != b && naive_dominates(succs, *a, b))
+                    .collect::<Vec<_>>();
+                let expected = strict
+                    .iter()
+                    .copied()
+                    .find(|idom| strict.iter().all(|a| naive_dominates(succs, *a, *idom)))
+                    .map(|idom| bbs[idom]);
+
+                assert_eq!(tree.idom(bbs[b]), expected, "idom({}) in {:?}", b, succs);
+                assert_eq!(tree.is_reachable(bbs[b]), reachable.contains(&b));
+                for child in tree.children(bbs[b]) {
+                    assert_eq!(tree.idom(child), Some(bbs[b]));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn frontiers_match_the_definition() {
+        for succs in GRAPHS {
+            let (ctx, fun, bbs) = build_cfg(succs);
+            let tree = DomTree::new(&ctx, fun);
+            for a in 0..bbs.len() {
+                //b is in the frontier of a when a dominates a predecessor of b without strictly
+                //dominating b
+                let expected = (0..bbs.len())
+                    .filter(|b| {
+                        let dominates_pred = (0..bbs.len())
+                            .any(|pred| succs[pred].contains(b) && naive_dominates(succs, a, pred));
+                        dominates_pred && (a == *b || !naive_dominates(succs, a, *b))
+                    })
+                    .map(|b| bbs[b])
+                    .collect::<HashSet<BBId>>();
+                let frontier = tree
+                    .frontier(bbs[a])
+                    .iter()
+                    .copied()
+                    .collect::<HashSet<_>>();
+
+                assert_eq!(frontier.len(), tree.frontier(bbs[a]).len());
+                assert_eq!(frontier, expected, "frontier({}) in {:?}", a, succs);
+            }
+        }
+    }
+
+    #[test]
+    fn unreachable_blocks_are_outside_the_tree() {
+        let (ctx, fun, bbs) = build_cfg(GRAPHS[3]);
+        let tree = DomTree::new(&ctx, fun);
+
+        assert_eq!(tree.rpo(), [bbs[0], bbs[1]]);
+        for unreachable in [bbs[2], bbs[3]] {
+            assert!(!tree.is_reachable(unreachable));
+            assert_eq!(tree.idom(unreachable), None);
+            assert!(tree.frontier(unreachable).is_empty());
+            assert_eq!(tree.children(unreachable).count(), 0);
+            assert!(!tree.dominates(unreachable, unreachable));
+            assert!(!tree.dominates(bbs[0], unreachable));
+        }
+        //Block 2 jumps to 1 but is unreachable, so 1 is still dominated only through the entry
+        assert_eq!(tree.idom(bbs[1]), Some(bbs[0]));
+        assert!(tree.frontier(bbs[0]).is_empty());
+    }
+
+    #[test]
+    fn irreducible_loops_are_dominated_by_the_entry() {
+        let (ctx, fun, bbs) = build_cfg(GRAPHS[4]);
+        let tree = DomTree::new(&ctx, fun);
+
+        for bb in &bbs[1..] {
+            assert_eq!(tree.idom(*bb), Some(bbs[0]));
+        }
+        assert!(!tree.dominates(bbs[1], bbs[2]));
+        assert!(!tree.dominates(bbs[2], bbs[1]));
+        assert_eq!(tree.children(bbs[0]).count(), 3);
+    }
+}
@@ -0,0 +1,306 @@
+//! Size, alignment, and field offsets of IR types on a target machine, computed without a code
+//! generator
+//!
+//! Structures are padded like C structures, and sum types and enums with payloads are laid out
+//! like the LLVM backend lays them out: a tag followed by a payload large enough to hold any
+//! variant
+
+use std::fmt;
+
+use crate::ast::IntegerWidth;
+
+use super::{
+    types::{IrFloatType, IrIntegerType, IrStructType, IrType},
+    IrContext, TypeId,
+};
+
+/// Properties of a target machine that affect the layout of types in memory
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct TargetSpec {
+    /// Size and alignment in bytes of pointers and pointer-sized integers
+    pub ptr_bytes: u64,
+    /// Alignment in bytes of 64 bit integers and floats
+    pub i64_align: u64,
+    /// Alignment in bytes of 128 bit integers
+    pub i128_align: u64,
+}
+
+impl TargetSpec {
+    /// Get the layout properties of the target with the given target triple, assuming a 64 bit
+    /// target for unknown architectures
+    pub fn from_triple(triple: &str) -> Self {
+        let arch = triple.split('-').next().unwrap_or(triple);
+        match arch {
+            "x86" | "i386" | "i486" | "i586" | "i686" => Self {
+                ptr_bytes: 4,
+                i64_align: 4,
+                i128_align: 4,
+            },
+            "avr" | "msp430" => Self {
+                ptr_bytes: 2,
+                i64_align: 2,
+                i128_align: 2,
+            },
+            _ if arch.starts_with("arm")
+                || arch.starts_with("thumb")
+                || (arch.starts_with("mips") && !arch.starts_with("mips64"))
+                || arch == "riscv32"
+                || arch == "wasm32"
+                || arch == "powerpc" =>
+            {
+                Self {
+                    ptr_bytes: 4,
+                    i64_align: 8,
+                    i128_align: 8,
+                }
+            }
+            _ => Self {
+                ptr_bytes: 8,
+                i64_align: 8,
+                i128_align: 8,
+            },
+        }
+    }
+
+    /// Get the layout properties of the machine the compiler is running on
+    pub fn host() -> Self {
+        Self::from_triple(std::env::consts::ARCH)
+    }
+
+    /// Get the number of bits in an integer of the given width, including pointer-sized integers
+    pub const fn int_bits(&self, width: IntegerWidth) -> u32 {
+        match width {
+            IntegerWidth::PtrSize => self.ptr_bytes as u32 * 8,
+            width => width as u32,
+        }
+    }
+}
+
+/// The size, alignment, and field offsets of a type
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Layout {
+    /// Size in bytes of the type including padding needed to place it in an array
+    pub size: u64,
+    /// Alignment in bytes of the type
+    pub align: u64,
+    /// Offsets in bytes of every field of a structure or element of a tuple, or of the tag and
+    /// payload of a sum type or an enum with payloads. Empty for all other types
+    pub fields: Vec<u64>,
+}
+
+/// Reasons that a type has no layout
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LayoutError {
+    /// The type is or contains an opaque type by value, which has an unknown size
+    Opaque(TypeId),
+    /// The type is or contains the invalid type
+    Invalid,
+    /// The size of the type in bytes does not fit in 64 bits
+    TooLarge(TypeId),
+}
+
+impl Layout {
+    /// Create the layout of a type with no fields
+    const fn scalar(size: u64, align: u64) -> Self {
+        Self {
+            size,
+            align,
+            fields: Vec::new(),
+        }
+    }
+
+    /// Lay out fields in order like a C structure, placing each field at the next offset aligned
+    /// to the field's alignment or its explicit alignment, if any. Returns `None` if the size of
+    /// the structure overflows
+    fn structure<I: IntoIterator<Item = (Layout, Option<u64>)>>(
+        fields: I,
+        packed: bool,
+    ) -> Option<Self> {
+        let mut offsets = vec![];
+        let mut offset = 0;
+        let mut align = 1;
+        for (field, explicit) in fields {
+            let natural = match packed {
+                true => 1,
+                false => field.align,
+            };
+            let field_align = explicit.map_or(natural, |explicit| explicit.max(natural));
+            offset = align_to(offset, field_align)?;
+            offsets.push(offset);
+            offset = offset.checked_add(field.size)?;
+            align = align.max(field_align);
+        }
+
+        Some(Self {
+            size: align_to(offset, align)?,
+            align,
+            fields: offsets,
+        })
+    }
+
+    /// Lay out a tag followed by a payload that can hold any of the given variants, returning
+    /// `None` if the size overflows
+    fn tagged(tag: Layout, variants: impl Iterator<Item = Layout>) -> Option<Self> {
+        let payload = variants.fold(Self::scalar(0, 1), |payload, variant| {
+            Self::scalar(
+                payload.size.max(variant.size),
+                payload.align.max(variant.align),
+            )
+        });
+        let payload = Self::scalar(align_to(payload.size, payload.align)?, payload.align);
+        Self::structure([(tag, None), (payload, None)], false)
+    }
+}
+
+impl fmt::Display for LayoutError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Opaque(_) => write!(f, "opaque types have an unknown size"),
+            Self::Invalid => write!(f, "the invalid type has no layout"),
+            Self::TooLarge(_) => write!(f, "the size of the type does not fit in 64 bits"),
+        }
+    }
+}
+
+/// Round an offset up to the next multiple of an alignment, returning `None` if it overflows
+const fn align_to(offset: u64, align: u64) -> Option<u64> {
+    match offset % align {
+        0 => Some(offset),
+        rem => offset.checked_add(align - rem),
+    }
+}
+
+impl IrContext {
+    /// Get the size, alignment, and field offsets of a type on a target machine. Layouts are
+    /// remembered for every type and target, so this must only be called after all type
+    /// definitions are resolved
+    pub fn layout_of(&self, ty: TypeId, target: &TargetSpec) -> Result<Layout, LayoutError> {
+        if let Some(layout) = self.layouts.borrow().get(&(ty, *target)) {
+            return Ok(layout.clone());
+        }
+
+        let layout = self.compute_layout(ty, target)?;
+        self.layouts
+            .borrow_mut()
+            .insert((ty, *target), layout.clone());
+        Ok(layout)
+    }
+
+    /// Compute the layout of a type from the layouts of the types it contains
+    fn compute_layout(&self, ty: TypeId, target: &TargetSpec) -> Result<Layout, LayoutError> {
+        let int = |ity: &IrIntegerType| match ity.width {
+            IntegerWidth::Eight => Layout::scalar(1, 1),
+            IntegerWidth::Sixteen => Layout::scalar(2, 2),
+            IntegerWidth::ThirtyTwo => Layout::scalar(4, 4),
+            IntegerWidth::SixtyFour => Layout::scalar(8, target.i64_align),
+            IntegerWidth::OneTwentyEight => Layout::scalar(16, target.i128_align),
+            IntegerWidth::PtrSize => Layout::scalar(target.ptr_bytes, target.ptr_bytes),
+        };
+        let ptr = Layout::scalar(target.ptr_bytes, target.ptr_bytes);
+        let too_large = LayoutError::TooLarge(ty);
+
+        Ok(match &self[ty] {
+            IrType::Integer(ity) => int(ity),
+            IrType::Float(IrFloatType { doublewide: true }) => Layout::scalar(8, target.i64_align),
+            IrType::Float(IrFloatType { doublewide: false }) => Layout::scalar(4, 4),
+            IrType::Bool | IrType::Unit => Layout::scalar(1, 1),
+            IrType::Char => Layout::scalar(4, 4),
+            IrType::Ptr(_) | IrType::Fun(_) => ptr,
+            IrType::Alias { ty, .. } => self.layout_of(*ty, target)?,
+            IrType::Array(elem, len) => {
+                let elem = self.layout_of(*elem, target)?;
+                let size = elem.size.checked_mul(*len).ok_or(too_large)?;
+                Layout::scalar(size, elem.align)
+            }
+            IrType::Slice(_) => {
+                Layout::structure([(ptr.clone(), None), (ptr, None)], false).ok_or(too_large)?
+            }
+            IrType::Struct(IrStructType { fields, packed }) => Layout::structure(
+                fields
+                    .iter()
+                    .map(|field| {
+                        self.layout_of(field.ty, target)
+                            .map(|layout| (layout, field.align.map(u64::from)))
+                    })
+                    .collect::<Result<Vec<_>, _>>()?,
+                *packed,
+            )
+            .ok_or(too_large)?,
+            IrType::Tuple(elements) => Layout::structure(
+                elements
+                    .iter()
+                    .map(|element| {
+                        self.layout_of(*element, target)
+                            .map(|layout| (layout, None))
+                    })
+                    .collect::<Result<Vec<_>, _>>()?,
+                false,
+            )
+            .ok_or(too_large)?,
+            IrType::Sum(variants) => {
+                let tag = match variants.len() {
+                    0..=0x100 => Layout::scalar(1, 1),
+                    0x101..=0x10000 => Layout::scalar(2, 2),
+                    _ => Layout::scalar(4, 4),
+                };
+                let variants = variants
+                    .iter()
+                    .map(|variant| self.layout_of(*variant, target))
+                    .collect::<Result<Vec<_>, _>>()?;
+                Layout::tagged(tag, variants.into_iter()).ok_or(too_large)?
+            }
+            IrType::Enum(e) => {
+                let tag = int(&e.tag_type());
+                if !e.has_payload() {
+                    return Ok(tag);
+                }
+                let payloads = e
+                    .variants
+                    .iter()
+                    .filter_map(|variant| variant.payload)
+                    .map(|payload| self.layout_of(payload, target))
+                    .collect::<Result<Vec<_>, _>>()?;
+                Layout::tagged(tag, payloads.into_iter()).ok_or(too_large)?
+            }
+            IrType::Opaque(_) => return Err(LayoutError::Opaque(ty)),
+            IrType::Invalid => return Err(LayoutError::Invalid),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{ast::IntegerWidth, ir::IrContext};
+
+    use super::{LayoutError, TargetSpec};
+
+    #[test]
+    fn pointer_sized_integers_match_target_pointers() {
+        let width = |triple| TargetSpec::from_triple(triple).int_bits(IntegerWidth::PtrSize);
+        assert_eq!(width("x86_64-unknown-linux-gnu"), 64);
+        assert_eq!(width("i686-pc-windows-msvc"), 32);
+        assert_eq!(width("avr-unknown-unknown"), 16);
+        assert_eq!(TargetSpec::host().int_bits(IntegerWidth::Sixteen), 16);
+    }
+
+    #[test]
+    fn oversized_types_are_rejected() {
+        let mut ctx = IrContext::new();
+        let target = TargetSpec::from_triple("x86_64-unknown-linux-gnu");
+        let huge = ctx.array_of(IrContext::U32, u64::MAX / 4);
+        let nested = ctx.array_of(huge, 2);
+        let tuple = ctx.tuple_of(vec![huge, IrContext::U8]);
+        let sum = ctx.sum_of(vec![huge, IrContext::U8]);
+
+        assert_eq!(ctx.layout_of(huge, &target).unwrap().size, u64::MAX - 3);
+        assert_eq!(
+            ctx.layout_of(nested, &target),
+            Err(LayoutError::TooLarge(nested))
+        );
+        assert_eq!(
+            ctx.layout_of(tuple, &target),
+            Err(LayoutError::TooLarge(tuple))
+        );
+        assert_eq!(ctx.layout_of(sum, &target), Err(LayoutError::TooLarge(sum)));
+    }
+}
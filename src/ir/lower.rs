@@ -1,28 +1,63 @@
 //! Module containing definitions for structures containing all state needed to lower a parsed
 //! abstract syntax tree to spark IR instructions
 
+use bitflags::bitflags;
 use codespan_reporting::diagnostic::{Diagnostic, Label, LabelStyle};
-use hashbrown::HashMap;
+use hashbrown::{HashMap, HashSet};
+use std::convert::TryFrom;
 
 use crate::{
     arena::{Arena, Index},
     ast::{
-        DefData, FunDef, FunFlags, ParsedModule, PathIter, SymbolPath, UnresolvedFunType,
-        UnresolvedType,
+        BigInt, Def, DefData, EnumVariantDef, Expr, FunDef, FunFlags, IntegerWidth, ParsedModule,
+        PathIter, SymbolPath, UnresolvedFunType, UnresolvedType,
     },
     util::{files::FileId, loc::Span},
     Symbol,
 };
 
 use super::{
-    types::{FunType, IrFloatType, IrStructField, IrStructType, IrType},
+    callgraph::CallGraph,
+    consteval::ConstValue,
+    layout::TargetSpec,
+    types::{
+        FunType, IrEnumType, IrEnumVariant, IrFloatType, IrIntegerType, IrStructField,
+        IrStructType, IrType,
+    },
     value::{IrExpr, IrExprKind, IrLiteral},
     BBId, FunId, GlobalId, IrBB, IrBody, IrContext, IrFun, IrGlobal, IrStmt, IrStmtKind,
     IrTerminator, TypeId, VarId,
 };
 
 pub mod ast;
+pub mod builtin;
+pub mod init;
+pub mod mangle;
+pub mod method;
 pub mod op;
+pub mod unused;
+
+#[cfg(test)]
+pub(crate) mod testing;
+
+bitflags! {
+    /// Optional warnings that the IR lowerer will emit
+    pub struct LintFlags: u8 {
+        /// Warn when a variable declaration shadows a variable of an enclosing scope
+        const SHADOW = 0b00000001;
+        /// Warn when a pointer is cast to or from an integer type that is not pointer-sized
+        const PTR_CAST = 0b00000010;
+        /// Warn when a function is never called or referenced by the main function, an exported
+        /// function, or a global's initializer
+        const UNUSED = 0b00000100;
+        /// Warn when a variable is declared but never read
+        const UNUSED_VARIABLES = 0b00001000;
+        /// Warn when statements follow a statement that never completes, like a return
+        const UNREACHABLE = 0b00010000;
+        /// Warn when a `pub` function's signature names a type that is private to its module
+        const PRIVATE_IN_PUBLIC = 0b00100000;
+    }
+}
 
 /// Structure containing all needed state to lower parsed ASTs into spark's IR, performing type
 /// checking and resolution
@@ -41,17 +76,66 @@ pub struct IrLowerer<'ctx> {
     dtors: HashMap<TypeId, FunId>,
     /// Current basic block to generate code in
     bb: Option<BBId>,
+    /// Warnings that are enabled during lowering
+    lints: LintFlags,
+    /// All warnings produced during lowering
+    warnings: Vec<Diagnostic<FileId>>,
+    /// Variables of the function being lowered that were declared without an initializer
+    uninit_vars: HashMap<VarId, Span>,
+    /// Immutable variables of the function being lowered that were declared without an
+    /// initializer, which may be assigned once on every path
+    deferred_vars: HashMap<VarId, Span>,
+    /// Variables declared in the function being lowered and the spans of their declarations
+    declared_vars: HashMap<VarId, Span>,
+    /// External and exported functions that have been declared with a body, which can't be
+    /// defined again by another declaration of the same function
+    defined_funs: HashSet<FunId>,
+    /// Constants whose values have not been evaluated yet, and the module that each is defined in
+    consts: HashMap<GlobalId, (IntermediateModuleId, Def)>,
+    /// Every global declared as a constant, whether or not its value has been evaluated yet
+    const_globals: HashSet<GlobalId>,
+    /// Constants whose values are being evaluated, innermost last, used to find constants that
+    /// depend on their own value
+    const_stack: Vec<GlobalId>,
+    /// Blocks after if expressions whose value's type so far comes only from phi statements of
+    /// number literals with no type suffix, and the block and value of each of those phis
+    untyped_phis: HashMap<BBId, Vec<(BBId, Expr)>>,
+    /// The first break statement from each loop, keyed by the variable storing the loop's value,
+    /// and if the break has a value
+    loop_breaks: HashMap<VarId, (Span, bool)>,
+    /// If variables declared without an initializer should be filled with zeroes instead of
+    /// being checked for reads before initialization
+    zero_init: bool,
+    /// Layout properties of the target machine, which decide the range of pointer-sized integers
+    target: TargetSpec,
+    /// If constant integer arithmetic that overflows is an error instead of wrapping
+    overflow_checks: bool,
+    /// If an expression that must be evaluated at compile time is being lowered, where integer
+    /// arithmetic that overflows is always an error
+    in_const: bool,
+    /// If taking the address of a value that isn't stored in memory is an error instead of
+    /// storing the value in a temporary variable
+    strict_temporaries: bool,
+    /// Blocks that the terminator of another block jumps to, recorded when the terminator is
+    /// set so that finding unreachable blocks doesn't require searching every block of a body
+    jump_targets: HashSet<BBId>,
 }
 
 /// Represents a type of scope that we are currently in, used to represent the nested
 /// scope structure of programs with ifs, loops, etc.
 pub struct ScopePlate {
-    /// Variables defined in this scope
-    vars: HashMap<Symbol, VarId>,
+    /// Variables defined in this scope and the spans of their declarations
+    vars: HashMap<Symbol, (VarId, Span)>,
     /// Stack allocation to store the phi or return value of the block in
     return_var: Option<VarId>,
     /// Block to exit to after this one is done or a break / phi / return statement is encountered
     after_bb: BBId,
+    /// If phi statements pass their value to the single parameter of `after_bb` instead of
+    /// storing it in `return_var`
+    phi_param: bool,
+    /// First block of the loop body if this is the scope of a loop, which continue statements
+    /// jump to
+    loop_bb: Option<BBId>,
 }
 
 /// Index into the `modules` field of an [IrLowerer]
@@ -75,6 +159,9 @@ pub enum IntermediateDefId {
 pub struct IntermediateModule {
     /// Map of defined symbols to their IDs in the [IrContext]
     pub defs: HashMap<Symbol, IntermediateDefId>,
+    /// Names of definitions in this module that are not marked `pub`, which can't be used from
+    /// other modules
+    pub private: HashSet<Symbol>,
     /// Name of this module
     pub name: Symbol,
 }
@@ -91,15 +178,18 @@ impl<'ctx> IrLowerer<'ctx> {
         };
 
         let entry = IrBB {
+            params: vec![],
             stmts: vec![],
             terminator: IrTerminator::Return(IrExpr {
                 span: Span::from(0..0),
                 kind: IrExprKind::Lit(IrLiteral::Unit),
                 ty: IrContext::UNIT,
             }),
+            terminator_span: None,
         };
         let setup = IrFun {
             name: Symbol::from("__global_setup"),
+            symbol: Symbol::from("__global_setup"),
             file: unsafe { FileId::from_raw(0) },
             span: Span::from(0..0),
             ty: setup_ty.clone(),
@@ -110,6 +200,7 @@ impl<'ctx> IrLowerer<'ctx> {
 
         let tmp = IrFun {
             name: Symbol::from("__tmp"),
+            symbol: Symbol::from("__tmp"),
             file: unsafe { FileId::from_raw(0) },
             span: Span::from(0..0),
             ty: setup_ty.clone(),
@@ -119,17 +210,21 @@ impl<'ctx> IrLowerer<'ctx> {
         };
 
         let global_setup_fun = ctx.funs.insert(setup);
-        ctx.funs[global_setup_fun].body = Some(IrBody {
+        let setup_entry = ctx.bbs.insert(entry.clone());
+        ctx[global_setup_fun].body = Some(IrBody {
             parent: global_setup_fun,
-            entry: ctx.bbs.insert(entry.clone()),
+            entry: setup_entry,
             args: vec![],
+            blocks: vec![setup_entry],
         });
 
         let tmp_fun = ctx.funs.insert(tmp);
-        ctx.funs[tmp_fun].body = Some(IrBody {
+        let tmp_entry = ctx.bbs.insert(entry);
+        ctx[tmp_fun].body = Some(IrBody {
             parent: tmp_fun,
-            entry: ctx.bbs.insert(entry),
+            entry: tmp_entry,
             args: vec![],
+            blocks: vec![tmp_entry],
         });
 
         Self {
@@ -140,9 +235,60 @@ impl<'ctx> IrLowerer<'ctx> {
             scope_stack: Vec::new(),
             bb: None,
             dtors: HashMap::default(),
+            lints: LintFlags::all(),
+            warnings: Vec::new(),
+            uninit_vars: HashMap::default(),
+            deferred_vars: HashMap::default(),
+            declared_vars: HashMap::default(),
+            defined_funs: HashSet::default(),
+            consts: HashMap::default(),
+            const_globals: HashSet::default(),
+            const_stack: Vec::new(),
+            untyped_phis: HashMap::default(),
+            loop_breaks: HashMap::default(),
+            zero_init: false,
+            target: TargetSpec::host(),
+            overflow_checks: false,
+            in_const: false,
+            strict_temporaries: false,
+            jump_targets: HashSet::default(),
         }
     }
 
+    /// Set the warnings that will be emitted during lowering
+    pub fn set_lints(&mut self, lints: LintFlags) {
+        self.lints = lints;
+    }
+
+    /// Set if variables declared without an initializer are zero-initialized
+    pub fn set_zero_init(&mut self, zero_init: bool) {
+        self.zero_init = zero_init;
+    }
+
+    /// Set the target machine that the lowered IR will be compiled for
+    pub fn set_target(&mut self, target: TargetSpec) {
+        self.target = target;
+    }
+
+    /// Set if overflowing integer arithmetic folded in runtime expressions is reported as an
+    /// error, matching the runtime checks of
+    /// [overflow_check](crate::CompileOpts::overflow_check). Overflow in expressions that must
+    /// be evaluated at compile time, like constants and array lengths, is always an error
+    pub fn set_overflow_checks(&mut self, overflow_checks: bool) {
+        self.overflow_checks = overflow_checks;
+    }
+
+    /// Set if taking the address of a value that isn't stored in memory is reported as an error
+    /// instead of storing the value in a temporary variable
+    pub fn set_strict_temporaries(&mut self, strict_temporaries: bool) {
+        self.strict_temporaries = strict_temporaries;
+    }
+
+    /// Take all warnings that have been produced during lowering
+    pub fn take_warnings(&mut self) -> Vec<Diagnostic<FileId>> {
+        std::mem::take(&mut self.warnings)
+    }
+
     /// Lower a parsed module to IR
     pub fn lower(&mut self, root: &ParsedModule) -> Result<(), Diagnostic<FileId>> {
         self.populate_forward_modules_impl(self.root_module, root)?;
@@ -150,12 +296,70 @@ impl<'ctx> IrLowerer<'ctx> {
         self.populate_imported_forward(self.root_module, root)?;
         self.populate_global_forwards_impl(self.root_module, root)?;
         self.populate_defs_impl(self.root_module, root)?;
+        self.populate_imports(root)?;
+        self.check_types_impl(self.root_module, root)?;
+        self.check_entry()?;
         self.populate_global_defs_impl(self.root_module, root)?;
         self.populate_fn_bodies_impl(self.root_module, root)?;
+        self.finish_entry();
+        if self.lints.contains(LintFlags::UNUSED) {
+            self.check_unused();
+        }
 
         Ok(())
     }
 
+    /// Warn about every function defined in source that can't be reached through the call graph
+    /// from the main function, functions visible outside of the program, or global initializers
+    fn check_unused(&mut self) {
+        let graph = CallGraph::new(self.ctx);
+        let roots = self
+            .ctx
+            .funs
+            .indices()
+            .filter(|fun| {
+                self.ctx[*fun]
+                    .flags
+                    .intersects(FunFlags::EXTERN | FunFlags::EXPORT)
+            })
+            .chain(self.ctx.entry)
+            .chain(std::iter::once(self.global_setup_fun));
+        let reachable = graph.reachable_from(roots);
+
+        //Imported functions appear in multiple modules
+        let mut defined = self
+            .modules
+            .iter()
+            .flat_map(|module| module.defs.values())
+            .filter_map(|def| match def {
+                IntermediateDefId::Fun(fun, ..) => Some(*fun),
+                _ => None,
+            })
+            .filter(|fun| !reachable.contains(fun))
+            .collect::<HashSet<_>>()
+            .into_iter()
+            .collect::<Vec<_>>();
+        defined.sort_by_key(|fun| fun.val());
+
+        for fun in defined {
+            let ir_fun = &self.ctx[fun];
+            if ir_fun.body.is_none() || ir_fun.flags.contains(FunFlags::USED) {
+                continue;
+            }
+
+            self.warnings.push(
+                Diagnostic::warning()
+                    .with_message(format!("Function {} is never used", ir_fun.name))
+                    .with_labels(vec![Label::primary(ir_fun.file, ir_fun.span)
+                        .with_message("Function defined here")])
+                    .with_notes(vec![format!(
+                        "Declare the function as 'fun used {}' to allow it to be unused",
+                        ir_fun.name
+                    )]),
+            );
+        }
+    }
+
     /// Get the basic block that code is being generated in
     pub fn bb(&self) -> BBId {
         self.bb
@@ -169,6 +373,24 @@ impl<'ctx> IrLowerer<'ctx> {
             .expect("ICE: IR lowerer is not currently in a basic block")
     }
 
+    /// Create a new basic block in the body of the given function
+    pub(super) fn new_bb(&mut self, fun: FunId) -> BBId {
+        let bb = self.ctx.new_bb();
+        self.ctx
+            .fun_body_mut(fun)
+            .expect("ICE: creating a basic block in a function without a body")
+            .blocks
+            .push(bb);
+        bb
+    }
+
+    /// Set the terminator of a basic block along with the location of the code that produced it
+    pub(super) fn terminate(&mut self, bb: BBId, terminator: IrTerminator, span: Span) {
+        self.jump_targets.extend(terminator.successors());
+        self.ctx[bb].terminator = terminator;
+        self.ctx[bb].terminator_span = Some(span);
+    }
+
     fn populate_forward_modules_impl(
         &mut self,
         module: IntermediateModuleId,
@@ -192,6 +414,12 @@ impl<'ctx> IrLowerer<'ctx> {
             self.populate_forward_modules_impl(child_module, child_parsed)?;
         }
 
+        for def in parsed.defs.iter() {
+            if !def.public && !matches!(def.data, DefData::ImportDef { .. }) {
+                self.modules[module].private.insert(def.data.name());
+            }
+        }
+
         //Create forward references for imported modules
         for def in parsed.defs.iter() {
             match &def.data {
@@ -216,9 +444,30 @@ impl<'ctx> IrLowerer<'ctx> {
     ) -> Result<(), Diagnostic<FileId>> {
         for def in parsed.defs.iter() {
             match &def.data {
-                DefData::AliasDef { name, .. } => {
-                    let ty = self.ctx.types.insert_nointern(IrType::Invalid);
+                DefData::AliasDef { name, .. } | DefData::EnumDef { name, .. } => {
+                    //Give a body to a previous opaque declaration of the same type
+                    let ty = match self.modules[module].defs.get(name) {
+                        Some(IntermediateDefId::Type(ty, ..))
+                            if matches!(self.ctx[*ty], IrType::Opaque(_)) =>
+                        {
+                            let ty = *ty;
+                            self.modules[module].defs.remove(name);
+                            ty
+                        }
+                        _ => self.ctx.types.insert_nointern(IrType::Invalid),
+                    };
+
+                    let id = IntermediateDefId::Type(ty, def.file, def.span);
+                    self.ensure_no_double(module, def.file, def.span, id, *name)?;
+                    self.modules[module].defs.insert(name.clone(), id);
+                }
+                DefData::OpaqueDef { name } => {
+                    //Opaque declarations of an already declared or defined type have no effect
+                    if let Some(IntermediateDefId::Type(..)) = self.modules[module].defs.get(name) {
+                        continue;
+                    }
 
+                    let ty = self.ctx.types.insert_nointern(IrType::Opaque(*name));
                     let id = IntermediateDefId::Type(ty, def.file, def.span);
                     self.ensure_no_double(module, def.file, def.span, id, *name)?;
                     self.modules[module].defs.insert(name.clone(), id);
@@ -254,7 +503,16 @@ impl<'ctx> IrLowerer<'ctx> {
                         self.ensure_no_double(module, def.file, def.span, id, name.last())?;
                         self.modules[module].defs.insert(name.last(), id);
                     }
-                    _ => (),
+                    //Types used before imports are resolved would otherwise be reported as
+                    //missing instead of private
+                    None if !self
+                        .explain_unresolved_path(module, name, Diagnostic::error())
+                        .labels
+                        .is_empty() =>
+                    {
+                        return Err(self.unresolved_import(&[(module, def)]))
+                    }
+                    None => (),
                 },
                 _ => (),
             }
@@ -280,7 +538,7 @@ impl<'ctx> IrLowerer<'ctx> {
                 DefData::FunDef(FunDef { proto, body, .. }) => {
                     let def_id = self.modules[module].defs[&proto.name];
                     if let IntermediateDefId::Fun(fun, ..) = def_id {
-                        self.lower_body(module, def.file, fun, body)?;
+                        self.lower_body(module, def.file, fun, proto, body)?;
                     } else {
                         panic!("Internal compiler error: definition id for symbol {} should be a function, but isn't", proto.name);
                     }
@@ -307,20 +565,44 @@ impl<'ctx> IrLowerer<'ctx> {
     ) -> Result<(), Diagnostic<FileId>> {
         for def in parsed.defs.iter() {
             match &def.data {
-                DefData::Global { name, .. } => {
+                DefData::Global {
+                    name,
+                    comptime,
+                    mutable,
+                    ..
+                } => {
+                    if *comptime && *mutable {
+                        return Err(Diagnostic::error()
+                            .with_message(format!("Constant {} cannot be mutable", name))
+                            .with_labels(vec![Label::primary(def.file, def.span)
+                                .with_message("Constant defined here")])
+                            .with_notes(vec![
+                                "Constants are evaluated at compile time and can't be assigned to"
+                                    .to_owned(),
+                            ]));
+                    }
+
                     let global = IrGlobal {
                         ty: IrContext::INVALID,
                         name: name.last(),
+                        value: None,
+                        mutable: *mutable,
+                        file: def.file,
+                        span: def.span,
                     };
 
                     let global_id = self.ctx.globals.insert(global);
+                    if *comptime {
+                        self.consts.insert(global_id, (module, def.clone()));
+                        self.const_globals.insert(global_id);
+                    }
 
                     let id = IntermediateDefId::Global(global_id, def.file, def.span);
-                    self.modules[module].defs.insert(name.last(), id);
-
                     if name.len() == 1 {
                         self.ensure_no_double(module, def.file, def.span, id, name.last().clone())?;
                     }
+
+                    self.modules[module].defs.insert(name.last(), id);
                 }
                 _ => (),
             }
@@ -343,12 +625,12 @@ impl<'ctx> IrLowerer<'ctx> {
         module: IntermediateModuleId,
         parsed: &ParsedModule,
     ) -> Result<(), Diagnostic<FileId>> {
-        self.bb = Some(self.ctx[self.global_setup_fun].body.as_ref().unwrap().entry);
+        self.bb = Some(self.ctx.fun_body(self.global_setup_fun).unwrap().entry);
         for def in parsed.defs.iter() {
             match &def.data {
                 DefData::Global {
                     name,
-                    comptime: _,
+                    comptime,
                     val,
                     ty,
                     ..
@@ -363,20 +645,29 @@ impl<'ctx> IrLowerer<'ctx> {
                         unreachable!()
                     };
 
+                    //Constants are never stored to, every use is replaced with their value
+                    if *comptime {
+                        self.eval_const(glob)?;
+                        continue;
+                    }
+
                     let ty = match val {
                         Some(expr) => {
-                            self.bb =
-                                Some(self.ctx[self.global_setup_fun].body.as_ref().unwrap().entry);
+                            self.bb = Some(self.ctx.fun_body(self.global_setup_fun).unwrap().entry);
                             let expr =
                                 self.lower_expr(module, def.file, self.global_setup_fun, expr)?;
+                            let addr = self.place_addr(
+                                def.file,
+                                IrExpr {
+                                    span: Span::from(0..0),
+                                    ty: expr.ty,
+                                    kind: IrExprKind::Global(glob),
+                                },
+                            )?;
                             self.ctx[self.bb.unwrap()].stmts.push(IrStmt {
                                 span: Span::from(0..0),
-                                kind: IrStmtKind::Write {
-                                    ptr: IrExpr {
-                                        span: Span::from(0..0),
-                                        ty: expr.ty,
-                                        kind: IrExprKind::Global(glob),
-                                    },
+                                kind: IrStmtKind::StoreMem {
+                                    addr,
                                     val: expr.clone(),
                                 },
                             });
@@ -395,7 +686,7 @@ impl<'ctx> IrLowerer<'ctx> {
                         },
                     };
 
-                    self.ctx.globals[glob].ty = ty;
+                    self.ctx[glob].ty = ty;
                 }
                 _ => (),
             }
@@ -412,6 +703,205 @@ impl<'ctx> IrLowerer<'ctx> {
         Ok(())
     }
 
+    /// Evaluate the value of a constant if it hasn't been evaluated yet, first evaluating any
+    /// constants that its value depends on
+    pub(super) fn eval_const(&mut self, glob: GlobalId) -> Result<(), Diagnostic<FileId>> {
+        if let Some(pos) = self.const_stack.iter().position(|other| *other == glob) {
+            return Err(self.const_cycle(&self.const_stack[pos..]));
+        }
+
+        let (module, def) = match self.consts.remove(&glob) {
+            Some(def) => def,
+            None => return Ok(()),
+        };
+        let (name, val, ty) = match &def.data {
+            DefData::Global { name, val, ty, .. } => (name, val, ty),
+            _ => unreachable!(),
+        };
+        let val = match val {
+            Some(val) => val,
+            None => {
+                return Err(Diagnostic::error()
+                    .with_message(format!("Constant {} is not assigned a value", name))
+                    .with_labels(vec![Label::primary(def.file, def.span)]))
+            }
+        };
+
+        self.const_stack.push(glob);
+        let value = match ty {
+            Some(ty) => self
+                .resolve_type(ty, module, def.file, def.span)
+                .and_then(|ty| self.lower_const(module, def.file, val, Some(ty))),
+            None => self.lower_const(module, def.file, val, None),
+        };
+        self.const_stack.pop();
+
+        let (ty, value) = value?;
+        self.ctx[glob].ty = ty;
+        self.ctx[glob].value = Some(value);
+        Ok(())
+    }
+
+    /// Lower an expression that must be evaluated at compile time, optionally as the given type,
+    /// and get its type and value.
+    /// The expression is lowered outside of any function body that is being lowered, so that it
+    /// can't refer to local variables
+    pub(super) fn lower_const(
+        &mut self,
+        module: IntermediateModuleId,
+        file: FileId,
+        expr: &Expr,
+        ty: Option<TypeId>,
+    ) -> Result<(TypeId, ConstValue), Diagnostic<FileId>> {
+        let lowered = self.lower_const_expr(module, file, expr, ty)?;
+        let value = self
+            .ctx
+            .const_eval_checked(&lowered)
+            .map_err(|e| e.to_diagnostic(file))?;
+        Ok((lowered.ty, value))
+    }
+
+    /// Lower an expression that must be evaluated at compile time like
+    /// [lower_const](Self::lower_const), without evaluating it
+    fn lower_const_expr(
+        &mut self,
+        module: IntermediateModuleId,
+        file: FileId,
+        expr: &Expr,
+        ty: Option<TypeId>,
+    ) -> Result<IrExpr, Diagnostic<FileId>> {
+        let scopes = std::mem::take(&mut self.scope_stack);
+        let bb = self
+            .bb
+            .replace(self.ctx.fun_body(self.global_setup_fun).unwrap().entry);
+        let in_const = std::mem::replace(&mut self.in_const, true);
+        let lowered = match ty {
+            Some(ty) => self.lower_expr_as(module, file, self.global_setup_fun, expr, ty),
+            None => self.lower_expr(module, file, self.global_setup_fun, expr),
+        };
+        self.in_const = in_const;
+        self.scope_stack = scopes;
+        self.bb = bb;
+        lowered
+    }
+
+    /// Replace the sizes and alignments of types in an array length with their values on the
+    /// target machine. Array lengths are resolved for a single target, so pointer-sized integers
+    /// in them are evaluated as integers of the target's pointer width
+    fn resolve_layout_queries(
+        &self,
+        file: FileId,
+        expr: &mut IrExpr,
+    ) -> Result<(), Diagnostic<FileId>> {
+        let ptr_width = match self.target.int_bits(IntegerWidth::PtrSize) {
+            16 => IntegerWidth::Sixteen,
+            32 => IntegerWidth::ThirtyTwo,
+            _ => IntegerWidth::SixtyFour,
+        };
+        let fixed = |ty: TypeId| {
+            if ty == IrContext::USIZE {
+                IrContext::itype(false, ptr_width)
+            } else if ty == IrContext::ISIZE {
+                IrContext::itype(true, ptr_width)
+            } else {
+                ty
+            }
+        };
+
+        let query = match expr.kind {
+            IrExprKind::SizeOf(ty) => Some((ty, true)),
+            IrExprKind::AlignOf(ty) => Some((ty, false)),
+            _ => None,
+        };
+        if let Some((ty, size)) = query {
+            let layout = self.ctx.layout_of(ty, &self.target).map_err(|e| {
+                Diagnostic::error()
+                    .with_message(format!("Type {} has no size: {}", self.ctx.typename(ty), e))
+                    .with_labels(vec![
+                        Label::primary(file, expr.span).with_message("Size of type taken here")
+                    ])
+            })?;
+            let val = match size {
+                true => layout.size,
+                false => layout.align,
+            };
+            expr.ty = fixed(IrContext::USIZE);
+            expr.kind = IrExprKind::Lit(IrLiteral::Integer(
+                BigInt {
+                    val: val as u128,
+                    sign: false,
+                },
+                IrIntegerType {
+                    width: ptr_width,
+                    signed: false,
+                },
+            ));
+            return Ok(());
+        }
+
+        match &mut expr.kind {
+            IrExprKind::Lit(IrLiteral::Integer(_, ity)) if ity.width == IntegerWidth::PtrSize => {
+                ity.width = ptr_width;
+            }
+            IrExprKind::Unary(_, operand) => self.resolve_layout_queries(file, operand)?,
+            IrExprKind::Binary(lhs, _, rhs) => {
+                self.resolve_layout_queries(file, lhs)?;
+                self.resolve_layout_queries(file, rhs)?;
+            }
+            IrExprKind::Cast(operand, ty, _) => {
+                self.resolve_layout_queries(file, operand)?;
+                *ty = fixed(*ty);
+            }
+            _ => (),
+        }
+        expr.ty = fixed(expr.ty);
+        Ok(())
+    }
+
+    /// Create an error for constants whose values depend on each other, given the constants that
+    /// are being evaluated starting from the first constant in the cycle
+    fn const_cycle(&self, cycle: &[GlobalId]) -> Diagnostic<FileId> {
+        let def_of = |glob: &GlobalId| {
+            self.modules
+                .iter()
+                .find_map(|module| {
+                    module.defs.values().find_map(|def| match def {
+                        IntermediateDefId::Global(id, file, span) if id == glob => {
+                            Some((*file, *span))
+                        }
+                        _ => None,
+                    })
+                })
+                .unwrap()
+        };
+
+        let chain = cycle
+            .iter()
+            .chain(std::iter::once(&cycle[0]))
+            .map(|glob| self.ctx[*glob].name.to_string())
+            .collect::<Vec<_>>();
+        Diagnostic::error()
+            .with_message(format!(
+                "Value of constant {} depends on itself",
+                self.ctx[cycle[0]].name
+            ))
+            .with_labels(
+                cycle
+                    .iter()
+                    .enumerate()
+                    .map(|(i, glob)| {
+                        let (file, span) = def_of(glob);
+                        match i {
+                            0 => Label::primary(file, span),
+                            _ => Label::secondary(file, span),
+                        }
+                        .with_message(format!("Constant {} defined here", self.ctx[*glob].name))
+                    })
+                    .collect(),
+            )
+            .with_notes(vec![format!("Constant cycle: {}", chain.join(" -> "))])
+    }
+
     /// Populate all type definitions and function declaratations
     fn populate_defs_impl(
         &mut self,
@@ -443,39 +933,46 @@ impl<'ctx> IrLowerer<'ctx> {
                         ),
                     }
                 }
+                DefData::EnumDef { name, variants } => {
+                    let ty = match self.modules[module].defs.get(name) {
+                        Some(IntermediateDefId::Type(ty, ..)) => *ty,
+                        _ => panic!("ICE: Cannot find definition named {}", name),
+                    };
+                    let enum_ty = self.resolve_enum(module, def.file, *name, variants)?;
+                    *self.ctx.types.get_mut(ty) = IrType::Enum(enum_ty);
+                }
                 DefData::FunDec(proto) | DefData::FunDef(FunDef { proto, .. }) => {
                     let fun_ty = self.resolve_fn_type(&proto.ty, module, def.file, def.span)?;
                     let fun = IrFun {
                         file: def.file,
                         span: def.span,
                         name: proto.name.clone(),
-                        ty_id: self.ctx.types.insert(IrType::Fun(fun_ty.clone())),
+                        symbol: if proto.flags.intersects(FunFlags::EXTERN | FunFlags::EXPORT) {
+                            proto.name.clone()
+                        } else {
+                            self.mangle_fun(module, &proto.name, &fun_ty)
+                        },
+                        ty_id: self.ctx.types.insert(IrType::Fun(fun_ty.unnamed())),
                         ty: fun_ty,
                         body: None,
                         flags: proto.flags,
                     };
 
-                    if fun.flags.contains(FunFlags::EXTERN) {
-                        for other in self.ctx.funs.iter() {
-                            if other.flags.contains(FunFlags::EXTERN) && other.name == fun.name {
-                                return Err(Diagnostic::error()
-                                    .with_message(format!("Two external functions declared with the same name '{}'", fun.name))
-                                    .with_labels(vec![
-                                        Label::primary(other.file, other.span)
-                                            .with_message("First external function appears here"),
-                                        Label::secondary(fun.file, fun.span)
-                                            .with_message("Second external function appears here")
-                                    ])
-                                    .with_notes(vec!["Functions marked as external will appear in the final object file with their original name".to_owned()])
-                                );
-                            }
-                        }
+                    let has_body = matches!(def.data, DefData::FunDef(_));
+                    let fun = match self.redeclared_fun(&fun, has_body)? {
+                        Some(redeclared) => redeclared,
+                        None => self.ctx.funs.insert(fun),
+                    };
+                    if has_body {
+                        self.defined_funs.insert(fun);
                     }
 
-                    let fun = self.ctx.funs.insert(fun);
                     let id = IntermediateDefId::Fun(fun, def.file, def.span);
-                    self.modules[module].defs.insert(proto.name.clone(), id);
                     self.ensure_no_double(module, def.file, def.span, id, proto.name)?;
+                    self.modules[module].defs.insert(proto.name.clone(), id);
+                    if def.public && self.lints.contains(LintFlags::PRIVATE_IN_PUBLIC) {
+                        self.check_private_in_public(fun);
+                    }
                 }
                 _ => (),
             }
@@ -489,7 +986,35 @@ impl<'ctx> IrLowerer<'ctx> {
             self.populate_defs_impl(child_module, child_parsed)?;
         }
 
-        //Resolve all imports, including function imports
+        Ok(())
+    }
+
+    /// Resolve the imports of every module after all definitions are registered, so that modules
+    /// can import from each other regardless of the order that they are lowered in. Imports of
+    /// imported symbols are resolved by repeating until no more imports can be resolved
+    fn populate_imports(&mut self, root: &ParsedModule) -> Result<(), Diagnostic<FileId>> {
+        let mut remaining = usize::MAX;
+        loop {
+            let mut unresolved = vec![];
+            self.populate_imports_impl(self.root_module, root, &mut unresolved)?;
+            if unresolved.is_empty() {
+                return Ok(());
+            }
+            if unresolved.len() >= remaining {
+                return Err(self.unresolved_import(&unresolved));
+            }
+            remaining = unresolved.len();
+        }
+    }
+
+    /// Resolve all imports of a module and its children, including function imports, adding
+    /// every import that isn't defined yet to `unresolved` along with the module importing it
+    fn populate_imports_impl<'p>(
+        &mut self,
+        module: IntermediateModuleId,
+        parsed: &'p ParsedModule,
+        unresolved: &mut Vec<(IntermediateModuleId, &'p Def)>,
+    ) -> Result<(), Diagnostic<FileId>> {
         for def in parsed.defs.iter() {
             match &def.data {
                 DefData::ImportDef { name } => match self.resolve_path(module, name) {
@@ -497,144 +1022,826 @@ impl<'ctx> IrLowerer<'ctx> {
                         self.ensure_no_double(module, def.file, def.span, id, name.last())?;
                         self.modules[module].defs.insert(name.last(), id);
                     }
-                    None => {
-                        return Err(Diagnostic::error()
-                            .with_message(format!("Imported symbol {} not found", name))
-                            .with_labels(vec![Label::new(LabelStyle::Primary, def.file, def.span)
-                                .with_message("Import declaration here")])
-                            .with_notes(vec![format!("In module {}", self.modules[module].name)]))
-                    }
+                    None => unresolved.push((module, def)),
                 },
                 _ => (),
             }
         }
 
+        for child_parsed in parsed.children.iter() {
+            let child_module = match self.modules[module].defs.get(&child_parsed.name).unwrap() {
+                IntermediateDefId::Module(module) => *module,
+                _ => unreachable!(),
+            };
+            self.populate_imports_impl(child_module, child_parsed, unresolved)?;
+        }
+
         Ok(())
     }
 
-    /// Resolve a parsed type into a concrete type id
-    fn resolve_type(
-        &mut self,
-        ty: &UnresolvedType,
-        module: IntermediateModuleId,
-        //parsed: &ParsedModule,
-        file: FileId,
-        span: Span,
-    ) -> Result<TypeId, Diagnostic<FileId>> {
-        Ok(match ty {
-            UnresolvedType::Integer { width, signed } => IrContext::itype(*signed, *width),
-            UnresolvedType::Float { doublewide } => self.ctx.types.insert(
-                IrType::Float(IrFloatType {
-                    doublewide: *doublewide,
+    /// Create an error for imports that can never be resolved, preferring to report imports that
+    /// import each other in a cycle over imports of symbols that are not defined
+    fn unresolved_import(&self, unresolved: &[(IntermediateModuleId, &Def)]) -> Diagnostic<FileId> {
+        let import_path = |def: &Def| match &def.data {
+            DefData::ImportDef { name } => name.clone(),
+            _ => unreachable!(),
+        };
+
+        if let Some(cycle) =
+            (0..unresolved.len()).find_map(|start| self.import_cycle(unresolved, start))
+        {
+            let (_, first) = unresolved[cycle[0]];
+            let chain = cycle
+                .iter()
+                .chain(std::iter::once(&cycle[0]))
+                .map(|idx| {
+                    let (module, def) = unresolved[*idx];
+                    format!("{}:{}", self.modules[module].name, import_path(def).last())
                 })
-                .into(),
-            ),
-            UnresolvedType::Char => IrContext::CHAR,
-            UnresolvedType::Pointer(ptr) => {
-                let ty = self.resolve_type(ptr, module, file, span)?;
-                self.ctx.types.insert(IrType::Ptr(ty))
-            }
-            UnresolvedType::Array { elements, len } => {
-                let element = self.resolve_type(elements, module, file, span)?;
-                self.ctx.types.insert(IrType::Array(element, *len))
-            }
-            UnresolvedType::Unit => IrContext::UNIT,
-            UnresolvedType::Bool => IrContext::BOOL,
-            UnresolvedType::Enum { variants } => {
-                let variants = variants
-                    .iter()
-                    .map(|variant| self.resolve_type(variant, module, file, span))
-                    .collect::<Result<Vec<_>, _>>()?;
-                self.ctx.types.insert(IrType::Sum(variants).into())
-            }
-            UnresolvedType::Struct { fields } => {
-                let fields = fields
-                    .iter()
-                    .map(
-                        |(field, name)| match self.resolve_type(field, module, file, span) {
-                            Ok(field) => Ok(IrStructField {
-                                name: *name,
-                                ty: field,
-                            }),
-                            Err(e) => Err(e),
-                        },
-                    )
-                    .collect::<Result<Vec<_>, _>>()?;
-                self.ctx
-                    .types
-                    .insert(IrType::Struct(IrStructType { fields }).into())
-            }
-            UnresolvedType::UserDefined { name } => match self.resolve_path(module, name) {
-                Some(IntermediateDefId::Type(ty, ..)) => ty,
-                _ => {
-                    return Err(Diagnostic::error()
-                        .with_message(format!(
-                            "Type {} not found in module {}",
-                            name, self.modules[module].name
-                        ))
-                        .with_labels(vec![Label::new(LabelStyle::Primary, file, span)]))
-                }
-            },
-            UnresolvedType::Fun(ty) => {
-                let fn_ty = self.resolve_fn_type(ty, module, file, span)?;
-                self.ctx.types.insert(IrType::Fun(fn_ty))
-            }
-        })
+                .collect::<Vec<_>>();
+
+            return Diagnostic::error()
+                .with_message(format!(
+                    "Imported symbol {} is imported in a cycle and never defined",
+                    import_path(first).last()
+                ))
+                .with_labels(
+                    cycle
+                        .iter()
+                        .enumerate()
+                        .map(|(i, idx)| {
+                            let (module, def) = unresolved[*idx];
+                            match i {
+                                0 => Label::primary(def.file, def.span),
+                                _ => Label::secondary(def.file, def.span),
+                            }
+                            .with_message(format!(
+                                "{} imported here in module {}",
+                                import_path(def),
+                                self.modules[module].name
+                            ))
+                        })
+                        .collect(),
+                )
+                .with_notes(vec![format!("Import cycle: {}", chain.join(" -> "))]);
+        }
+
+        let (module, def) = unresolved[0];
+        let name = import_path(def);
+        let diagnostic = Diagnostic::error()
+            .with_message(format!("Imported symbol {} not found", name))
+            .with_labels(vec![Label::new(LabelStyle::Primary, def.file, def.span)
+                .with_message("Import declaration here")])
+            .with_notes(vec![format!("In module {}", self.modules[module].name)]);
+        self.explain_unresolved_path(module, &name, diagnostic)
     }
 
-    /// Resolve a function type, split into another function to be used when generating forward
-    /// references for function declarations
-    fn resolve_fn_type(
-        &mut self,
-        ty: &UnresolvedFunType,
-        module: IntermediateModuleId,
-        file: FileId,
-        span: Span,
-    ) -> Result<FunType, Diagnostic<FileId>> {
-        let return_ty = self.resolve_type(&ty.return_ty, module, file, span)?;
-        let params = ty
-            .arg_tys
-            .iter()
-            .map(
-                |(ty, name)| match self.resolve_type(ty, module, file, span) {
-                    Ok(ty) => Ok((ty, name.clone())),
-                    Err(e) => Err(e),
+    /// Follow unresolved imports starting from the one at index `start`, returning the indices of
+    /// the imports in order if they lead back to `start`
+    fn import_cycle(
+        &self,
+        unresolved: &[(IntermediateModuleId, &Def)],
+        start: usize,
+    ) -> Option<Vec<usize>> {
+        let mut chain = vec![start];
+        loop {
+            let (module, def) = unresolved[*chain.last().unwrap()];
+            let parts = match &def.data {
+                DefData::ImportDef { name } => name.iter().collect::<Vec<_>>(),
+                _ => unreachable!(),
+            };
+            let (item, prefix) = parts.split_last()?;
+            let target = match prefix.is_empty() {
+                true => module,
+                false => match self.resolve_path(module, &SymbolPath::new_parts(prefix))? {
+                    IntermediateDefId::Module(target) => target,
+                    _ => return None,
                 },
-            )
-            .collect::<Result<Vec<_>, _>>()?;
+            };
 
-        Ok(FunType { return_ty, params })
+            let next = unresolved.iter().position(|(module, def)| {
+                *module == target
+                    && matches!(&def.data, DefData::ImportDef { name } if name.last() == *item)
+            })?;
+            if next == start {
+                return Some(chain);
+            }
+            if chain.contains(&next) {
+                return None;
+            }
+            chain.push(next);
+        }
     }
 
-    /// Ensure that the given definition with name is not defined twice in the module
-    fn ensure_no_double(
-        &mut self,
-        module: IntermediateModuleId,
-        file: FileId,
-        span: Span,
-        id: IntermediateDefId,
-        def: Symbol,
-    ) -> Result<(), Diagnostic<FileId>> {
-        if def == self.modules[module].name {
+    /// Find the spark `main` function of the root module, ensuring that it has a valid signature
+    /// and that no other function will be given the symbol name of the C `main` function
+    fn check_entry(&mut self) -> Result<(), Diagnostic<FileId>> {
+        let main = match self.modules[self.root_module]
+            .defs
+            .get(&Symbol::from("main"))
+        {
+            Some(IntermediateDefId::Fun(fun, ..))
+                if !self.ctx[*fun]
+                    .flags
+                    .intersects(FunFlags::EXTERN | FunFlags::EXPORT) =>
+            {
+                *fun
+            }
+            _ => return Ok(()),
+        };
+        let fun = &self.ctx[main];
+
+        if let Some(other) = self
+            .ctx
+            .funs
+            .iter()
+            .find(|other| other.symbol.as_str() == "main")
+        {
             return Err(Diagnostic::error()
-                .with_message(format!(
-                    "Definition with name {} conflicts with module name {}",
-                    def, self.modules[module].name,
-                ))
+                .with_message("Both a spark main function and a C main function are defined")
+                .with_labels(vec![
+                    Label::primary(other.file, other.span)
+                        .with_message("C main function defined here"),
+                    Label::secondary(fun.file, fun.span)
+                        .with_message("Spark main function defined here"),
+                ])
+                .with_notes(vec![
+                    "The compiler generates a C main function that calls the spark main function"
+                        .to_owned(),
+                ]));
+        }
+
+        let return_ty = self.ctx.unwrap_alias(fun.ty.return_ty);
+        if !fun.ty.params.is_empty()
+            || (return_ty != IrContext::I32 && return_ty != IrContext::UNIT)
+        {
+            return Err(Diagnostic::error()
+                .with_message(format!(
+                    "Main function has signature {}, expected fun() -> i32 or fun()",
+                    self.ctx.typename(fun.ty_id)
+                ))
+                .with_labels(vec![
+                    Label::primary(fun.file, fun.span).with_message("Main function defined here")
+                ]));
+        }
+
+        self.ctx.entry = Some(main);
+        Ok(())
+    }
+
+    /// Generate the C `main` function of the program, which initializes globals, calls the spark
+    /// `main` function, and returns its exit code, or 0 if it returns nothing
+    fn finish_entry(&mut self) {
+        let main = match self.ctx.entry {
+            Some(main) => main,
+            None => return,
+        };
+        if self.ctx.fun_body(main).is_none() {
+            return;
+        }
+
+        let (file, span) = (self.ctx[main].file, self.ctx[main].span);
+        let setup = IrStmt {
+            span,
+            kind: IrStmtKind::Call {
+                fun: self.global_setup_fun,
+                args: vec![],
+            },
+        };
+
+        let argv = self.ctx.ptr_to(IrContext::U8);
+        let argv = self.ctx.ptr_to(argv);
+        let c_main_ty = FunType {
+            return_ty: IrContext::I32,
+            params: vec![(IrContext::I32, None), (argv, None)],
+        };
+        let c_main = self.ctx.funs.insert(IrFun {
+            name: Symbol::from("__c_main"),
+            symbol: Symbol::from("main"),
+            file,
+            span,
+            ty_id: self.ctx.types.insert(IrType::Fun(c_main_ty.clone())),
+            ty: c_main_ty,
+            body: None,
+            flags: FunFlags::empty(),
+        });
+
+        let main_ty = self.ctx[main].ty.clone();
+        let call = IrExpr {
+            span,
+            ty: main_ty.return_ty,
+            kind: IrExprKind::Call(
+                Box::new(IrExpr {
+                    span,
+                    ty: self.ctx[main].ty_id,
+                    kind: IrExprKind::Fun(main),
+                }),
+                vec![],
+            ),
+        };
+        let (stmts, exit_code) = if self.ctx.unwrap_alias(main_ty.return_ty) == IrContext::UNIT {
+            let exit_code = IrExpr {
+                span,
+                ty: IrContext::I32,
+                kind: IrExprKind::Lit(IrLiteral::Integer(
+                    BigInt {
+                        val: 0,
+                        sign: false,
+                    },
+                    IrIntegerType {
+                        width: IntegerWidth::ThirtyTwo,
+                        signed: true,
+                    },
+                )),
+            };
+            (
+                vec![
+                    setup,
+                    IrStmt {
+                        span,
+                        kind: IrStmtKind::Exec(call),
+                    },
+                ],
+                exit_code,
+            )
+        } else {
+            (vec![setup], call)
+        };
+
+        let c_main_entry = self.ctx.bbs.insert(IrBB {
+            params: vec![],
+            stmts,
+            terminator: IrTerminator::Return(exit_code),
+            terminator_span: None,
+        });
+        self.ctx[c_main].body = Some(IrBody {
+            parent: c_main,
+            entry: c_main_entry,
+            args: vec![None, None],
+            blocks: vec![c_main_entry],
+        });
+    }
+
+    /// Ensure that no type definition contains itself without indirection through a pointer,
+    /// which would give the type an infinite size, and that no type definition or function
+    /// signature uses an opaque type by value
+    fn check_types_impl(
+        &mut self,
+        module: IntermediateModuleId,
+        parsed: &ParsedModule,
+    ) -> Result<(), Diagnostic<FileId>> {
+        for def in parsed.defs.iter() {
+            if let DefData::FunDec(proto) | DefData::FunDef(FunDef { proto, .. }) = &def.data {
+                if let Some(IntermediateDefId::Fun(fun, ..)) =
+                    self.modules[module].defs.get(&proto.name)
+                {
+                    let fun_ty = self.ctx[*fun].ty.clone();
+                    for (param, _) in fun_ty.params.iter() {
+                        self.ensure_sized(*param, def.file, def.span)?;
+                    }
+                    self.ensure_sized(fun_ty.return_ty, def.file, def.span)?;
+                }
+            }
+
+            if let DefData::EnumDef { name, variants } = &def.data {
+                let ty = match self.modules[module].defs.get(name) {
+                    Some(IntermediateDefId::Type(ty, ..)) => *ty,
+                    _ => continue,
+                };
+
+                self.ensure_sized(ty, def.file, def.span)?;
+                let payloads = match &self.ctx[ty] {
+                    IrType::Enum(e) => e
+                        .variants
+                        .iter()
+                        .filter_map(|variant| variant.payload)
+                        .collect::<Vec<_>>(),
+                    _ => continue,
+                };
+                for (payload, variant) in payloads
+                    .into_iter()
+                    .zip(variants.iter().filter(|variant| variant.payload.is_some()))
+                {
+                    if self.type_contains(payload, ty, &mut HashSet::new()) {
+                        return Err(Diagnostic::error()
+                            .with_message(format!("Type {} has infinite size", name))
+                            .with_labels(vec![Label::new(
+                                LabelStyle::Primary,
+                                def.file,
+                                variant.span,
+                            )
+                            .with_message(format!(
+                                "Variant {} contains {} by value",
+                                variant.name, name
+                            ))])
+                            .with_notes(vec![format!(
+                                "Use a pointer such as *{} to refer to the type inside itself",
+                                name
+                            )]));
+                    }
+                }
+            }
+
+            if let DefData::AliasDef { name, .. } = &def.data {
+                let ty = match self.modules[module].defs.get(name) {
+                    Some(IntermediateDefId::Type(ty, ..)) => *ty,
+                    _ => continue,
+                };
+
+                let aliased = match &self.ctx[ty] {
+                    IrType::Alias { ty: aliased, .. } => *aliased,
+                    _ => continue,
+                };
+
+                if let Some(cycle) = self.alias_cycle(ty) {
+                    let chain = cycle
+                        .iter()
+                        .chain(std::iter::once(&ty))
+                        .map(|alias| self.ctx.typename(*alias).to_string())
+                        .collect::<Vec<_>>()
+                        .join(" -> ");
+                    let labels = std::iter::once(
+                        Label::primary(def.file, def.span)
+                            .with_message("Cyclic alias defined here"),
+                    )
+                    .chain(cycle.iter().skip(1).filter_map(|alias| {
+                        let (file, span) = self.type_def_loc(*alias)?;
+                        Some(Label::secondary(file, span).with_message(format!(
+                            "Alias {} defined here",
+                            self.ctx.typename(*alias)
+                        )))
+                    }))
+                    .collect();
+
+                    return Err(Diagnostic::error()
+                        .with_message(format!("Type alias {} refers to itself", name))
+                        .with_labels(labels)
+                        .with_notes(vec![format!("Aliases form the cycle {}", chain)]));
+                }
+
+                self.ensure_sized(ty, def.file, def.span)?;
+                if self.type_contains(aliased, ty, &mut HashSet::new()) {
+                    let notes = match &self.ctx[self.ctx.unwrap_alias(aliased)] {
+                        IrType::Struct(s) => s
+                            .fields
+                            .iter()
+                            .filter(|field| self.type_contains(field.ty, ty, &mut HashSet::new()))
+                            .map(|field| format!("Field {} contains {} by value", field.name, name))
+                            .collect(),
+                        _ => vec![],
+                    };
+
+                    return Err(Diagnostic::error()
+                        .with_message(format!("Type {} has infinite size", name))
+                        .with_labels(vec![Label::new(LabelStyle::Primary, def.file, def.span)
+                            .with_message("Recursive type defined here")])
+                        .with_notes(notes)
+                        .with_notes(vec![format!(
+                            "Use a pointer such as *{} to refer to the type inside itself",
+                            name
+                        )]));
+                }
+            }
+        }
+
+        for child_parsed in parsed.children.iter() {
+            if let IntermediateDefId::Module(child_module) =
+                self.modules[module].defs[&child_parsed.name]
+            {
+                self.check_types_impl(child_module, child_parsed)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Ensure that a type does not contain an opaque type by value, as opaque types have no known
+    /// size and may only be used behind a pointer
+    pub(super) fn ensure_sized(
+        &self,
+        ty: TypeId,
+        file: FileId,
+        span: Span,
+    ) -> Result<(), Diagnostic<FileId>> {
+        match self.contained_opaque(ty, &mut HashSet::new()) {
+            Some(opaque) => Err(Diagnostic::error()
+                .with_message(format!(
+                    "Opaque type {} cannot be used by value in type {}",
+                    opaque,
+                    self.ctx.typename(ty)
+                ))
+                .with_labels(vec![Label::new(LabelStyle::Primary, file, span)
+                    .with_message("Opaque type used here")])
+                .with_notes(vec![format!(
+                    "Use a pointer such as *{} or give {} a definition",
+                    opaque, opaque
+                )])),
+            None => Ok(()),
+        }
+    }
+
+    /// Get the name of an opaque type that is contained by value in the given type, if any
+    fn contained_opaque(&self, ty: TypeId, visited: &mut HashSet<TypeId>) -> Option<Symbol> {
+        if !visited.insert(ty) {
+            return None;
+        }
+
+        match &self.ctx[ty] {
+            IrType::Opaque(name) => Some(*name),
+            IrType::Alias { ty, .. } | IrType::Array(ty, _) => self.contained_opaque(*ty, visited),
+            IrType::Struct(s) => s
+                .fields
+                .iter()
+                .find_map(|field| self.contained_opaque(field.ty, visited)),
+            IrType::Sum(variants) | IrType::Tuple(variants) => variants
+                .iter()
+                .find_map(|variant| self.contained_opaque(*variant, visited)),
+            IrType::Enum(e) => e
+                .variants
+                .iter()
+                .find_map(|variant| self.contained_opaque(variant.payload?, visited)),
+            _ => None,
+        }
+    }
+
+    /// Follow a chain of type aliases starting at the given alias, returning every alias in the
+    /// chain if it leads back to the starting alias instead of ending at a concrete type
+    fn alias_cycle(&self, start: TypeId) -> Option<Vec<TypeId>> {
+        let mut chain = vec![start];
+        let mut ty = start;
+        while let IrType::Alias { ty: aliased, .. } = &self.ctx[ty] {
+            ty = *aliased;
+            if ty == start {
+                return Some(chain);
+            }
+            //A cycle that does not contain the start alias is reported for its own aliases
+            if chain.contains(&ty) {
+                return None;
+            }
+            chain.push(ty);
+        }
+
+        None
+    }
+
+    /// Get the location of the definition of the given named type
+    fn type_def_loc(&self, ty: TypeId) -> Option<(FileId, Span)> {
+        self.modules.iter().find_map(|module| {
+            module.defs.values().find_map(|def| match def {
+                IntermediateDefId::Type(id, file, span) if *id == ty => Some((*file, *span)),
+                _ => None,
+            })
+        })
+    }
+
+    /// Check if a type contains the `target` type by value, not following pointers
+    fn type_contains(&self, ty: TypeId, target: TypeId, visited: &mut HashSet<TypeId>) -> bool {
+        if ty == target {
+            return true;
+        }
+        if !visited.insert(ty) {
+            return false;
+        }
+
+        match &self.ctx[ty] {
+            IrType::Alias { ty, .. } | IrType::Array(ty, _) => {
+                self.type_contains(*ty, target, visited)
+            }
+            IrType::Struct(s) => s
+                .fields
+                .iter()
+                .any(|field| self.type_contains(field.ty, target, visited)),
+            IrType::Sum(variants) | IrType::Tuple(variants) => variants
+                .iter()
+                .any(|variant| self.type_contains(*variant, target, visited)),
+            IrType::Enum(e) => e.variants.iter().any(|variant| match variant.payload {
+                Some(payload) => self.type_contains(payload, target, visited),
+                None => false,
+            }),
+            _ => false,
+        }
+    }
+
+    /// Resolve a parsed type into a concrete type id
+    fn resolve_type(
+        &mut self,
+        ty: &UnresolvedType,
+        module: IntermediateModuleId,
+        //parsed: &ParsedModule,
+        file: FileId,
+        span: Span,
+    ) -> Result<TypeId, Diagnostic<FileId>> {
+        Ok(match ty {
+            UnresolvedType::Integer { width, signed } => IrContext::itype(*signed, *width),
+            UnresolvedType::Float { doublewide } => self.ctx.types.insert(
+                IrType::Float(IrFloatType {
+                    doublewide: *doublewide,
+                })
+                .into(),
+            ),
+            UnresolvedType::Char => IrContext::CHAR,
+            UnresolvedType::Pointer(ptr) => {
+                let ty = self.resolve_type(ptr, module, file, span)?;
+                self.ctx.ptr_to(ty)
+            }
+            UnresolvedType::Array { elements, len } => {
+                let len_span = len.span;
+                let mut len = self.lower_const_expr(module, file, len, Some(IrContext::U64))?;
+                self.resolve_layout_queries(file, &mut len)?;
+                let value = self
+                    .ctx
+                    .const_eval_checked(&len)
+                    .map_err(|e| e.to_diagnostic(file))?;
+                let len = match (len.ty, value) {
+                    (_, ConstValue::Integer(len, ity)) if ity.signed && len < 0 => {
+                        return Err(Diagnostic::error()
+                            .with_message(format!("Array length {} is negative", len))
+                            .with_labels(vec![Label::primary(file, len_span)
+                                .with_message("Array length evaluated here")]))
+                    }
+                    (_, ConstValue::Integer(len, _)) if len as u128 > u32::MAX as u128 => {
+                        return Err(Diagnostic::error()
+                            .with_message(format!(
+                                "Array length {} exceeds the maximum array length of {}",
+                                len as u128,
+                                u32::MAX
+                            ))
+                            .with_labels(vec![Label::primary(file, len_span)
+                                .with_message("Array length evaluated here")]))
+                    }
+                    (_, ConstValue::Integer(len, _)) => len as u64,
+                    (ty, _) => {
+                        return Err(Diagnostic::error()
+                            .with_message(format!(
+                                "Array length must be an integer, found a value of type {}",
+                                self.ctx.typename(ty)
+                            ))
+                            .with_labels(vec![Label::primary(file, len_span)
+                                .with_message("Array length evaluated here")]))
+                    }
+                };
+                let element = self.resolve_type(elements, module, file, span)?;
+                self.ctx.array_of(element, len)
+            }
+            UnresolvedType::Slice(elements) => {
+                let element = self.resolve_type(elements, module, file, span)?;
+                self.ctx.slice_of(element)
+            }
+            UnresolvedType::Unit => IrContext::UNIT,
+            UnresolvedType::Bool => IrContext::BOOL,
+            UnresolvedType::Enum { variants } => {
+                let variants = variants
+                    .iter()
+                    .map(|variant| self.resolve_type(variant, module, file, span))
+                    .collect::<Result<Vec<_>, _>>()?;
+                self.ctx.sum_of(variants)
+            }
+            UnresolvedType::Tuple(elements) => {
+                let elements = elements
+                    .iter()
+                    .map(|element| self.resolve_type(element, module, file, span))
+                    .collect::<Result<Vec<_>, _>>()?;
+                self.ctx.tuple_of(elements)
+            }
+            UnresolvedType::Struct { fields, packed } => {
+                let fields = fields
+                    .iter()
+                    .map(|(field, name, align)| {
+                        let align = match align {
+                            Some(align) if !align.is_power_of_two() || *align > u32::MAX as u64 => {
+                                return Err(Diagnostic::error()
+                                    .with_message(format!(
+                                        "Alignment of {} bytes for field {} is not a power of two",
+                                        align, name
+                                    ))
+                                    .with_labels(vec![Label::new(
+                                        LabelStyle::Primary,
+                                        file,
+                                        span,
+                                    )
+                                    .with_message("Structure type appears here")]))
+                            }
+                            other => other.map(|align| align as u32),
+                        };
+
+                        Ok(IrStructField {
+                            name: *name,
+                            ty: self.resolve_type(field, module, file, span)?,
+                            align,
+                        })
+                    })
+                    .collect::<Result<Vec<_>, _>>()?;
+                self.ctx.types.insert(
+                    IrType::Struct(IrStructType {
+                        fields,
+                        packed: *packed,
+                    })
+                    .into(),
+                )
+            }
+            UnresolvedType::UserDefined { name } => match self.resolve_path(module, name) {
+                Some(IntermediateDefId::Type(ty, ..)) => ty,
+                _ => {
+                    let diagnostic = Diagnostic::error()
+                        .with_message(format!(
+                            "Type {} not found in module {}",
+                            name, self.modules[module].name
+                        ))
+                        .with_labels(vec![Label::new(LabelStyle::Primary, file, span)]);
+                    return Err(self.explain_unresolved_path(module, name, diagnostic));
+                }
+            },
+            UnresolvedType::Fun(ty) => {
+                let fn_ty = self.resolve_fn_type(ty, module, file, span)?;
+                self.ctx.types.insert(IrType::Fun(fn_ty.unnamed()))
+            }
+        })
+    }
+
+    /// Resolve a function type, split into another function to be used when generating forward
+    /// references for function declarations
+    fn resolve_fn_type(
+        &mut self,
+        ty: &UnresolvedFunType,
+        module: IntermediateModuleId,
+        file: FileId,
+        span: Span,
+    ) -> Result<FunType, Diagnostic<FileId>> {
+        let return_ty = self.resolve_type(&ty.return_ty, module, file, span)?;
+        let params = ty
+            .arg_tys
+            .iter()
+            .map(
+                |(ty, name)| match self.resolve_type(ty, module, file, span) {
+                    Ok(ty) => Ok((ty, name.clone())),
+                    Err(e) => Err(e),
+                },
+            )
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(FunType { return_ty, params })
+    }
+
+    /// Resolve the variants of an enum definition, assigning every variant without an explicit
+    /// discriminant the value after the previous variant's discriminant, starting from 0
+    fn resolve_enum(
+        &mut self,
+        module: IntermediateModuleId,
+        file: FileId,
+        name: Symbol,
+        variants: &[EnumVariantDef],
+    ) -> Result<IrEnumType, Diagnostic<FileId>> {
+        let mut resolved = Vec::<IrEnumVariant>::with_capacity(variants.len());
+        let mut next = Some(0i64);
+        for (idx, variant) in variants.iter().enumerate() {
+            if let Some(other) = variants[..idx]
+                .iter()
+                .find(|other| other.name == variant.name)
+            {
+                return Err(Diagnostic::error()
+                    .with_message(format!(
+                        "Variant {} of enum {} is declared twice",
+                        variant.name, name
+                    ))
+                    .with_labels(vec![
+                        Label::primary(file, variant.span)
+                            .with_message("Second variant appears here"),
+                        Label::secondary(file, other.span)
+                            .with_message("First variant appears here"),
+                    ]));
+            }
+
+            let discriminant = match &variant.discriminant {
+                Some(explicit) => match self.lower_const(module, file, explicit, Some(IrContext::I64))? {
+                    (_, ConstValue::Integer(val, _)) => i64::try_from(val).map_err(|_| {
+                        Diagnostic::error()
+                            .with_message(format!(
+                                "Discriminant {} of variant {} does not fit in a 64 bit integer",
+                                val, variant.name
+                            ))
+                            .with_labels(vec![Label::primary(file, explicit.span)
+                                .with_message("Discriminant evaluated here")])
+                    })?,
+                    (ty, _) => {
+                        return Err(Diagnostic::error()
+                            .with_message(format!(
+                                "Discriminant of variant {} must be an integer, found a value of type {}",
+                                variant.name,
+                                self.ctx.typename(ty)
+                            ))
+                            .with_labels(vec![Label::primary(file, explicit.span)
+                                .with_message("Discriminant evaluated here")]))
+                    }
+                },
+                None => next.ok_or_else(|| {
+                    Diagnostic::error()
+                        .with_message(format!(
+                            "Implicit discriminant of variant {} overflows a 64 bit integer",
+                            variant.name
+                        ))
+                        .with_labels(vec![Label::primary(file, variant.span)])
+                        .with_notes(vec![
+                            "Give the variant an explicit discriminant with =".to_owned()
+                        ])
+                })?,
+            };
+
+            if let Some((other_idx, _)) = resolved
+                .iter()
+                .enumerate()
+                .find(|(_, other)| other.discriminant == discriminant)
+            {
+                return Err(Diagnostic::error()
+                    .with_message(format!(
+                        "Variants {} and {} of enum {} have the same discriminant {}",
+                        variants[other_idx].name, variant.name, name, discriminant
+                    ))
+                    .with_labels(vec![
+                        Label::primary(file, variant.span),
+                        Label::secondary(file, variants[other_idx].span),
+                    ]));
+            }
+
+            let payload = match &variant.payload {
+                Some(payload) => Some(self.resolve_type(payload, module, file, variant.span)?),
+                None => None,
+            };
+
+            next = discriminant.checked_add(1);
+            resolved.push(IrEnumVariant {
+                name: variant.name,
+                payload,
+                discriminant,
+            });
+        }
+
+        Ok(IrEnumType {
+            name,
+            variants: resolved,
+        })
+    }
+
+    /// Resolve a path naming a variant of an enum type, like `Color:red`, returning the enum's
+    /// type and the index of the variant
+    pub(super) fn resolve_variant(
+        &self,
+        module: IntermediateModuleId,
+        path: &SymbolPath,
+    ) -> Option<(TypeId, usize)> {
+        if path.len() < 2 {
+            return None;
+        }
+
+        let parts = path.iter().collect::<Vec<_>>();
+        let enum_path = SymbolPath::new_parts(&parts[..parts.len() - 1]);
+        match self.resolve_path(module, &enum_path)? {
+            IntermediateDefId::Type(ty, ..) => match &self.ctx[self.ctx.unwrap_alias(ty)] {
+                IrType::Enum(e) => Some((ty, e.variant_idx(&path.last())?)),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+
+    /// Ensure that the given definition with name is not defined twice in the module
+    fn ensure_no_double(
+        &mut self,
+        module: IntermediateModuleId,
+        file: FileId,
+        span: Span,
+        id: IntermediateDefId,
+        def: Symbol,
+    ) -> Result<(), Diagnostic<FileId>> {
+        if def == self.modules[module].name {
+            return Err(Diagnostic::error()
+                .with_message(format!(
+                    "Definition with name {} conflicts with module name {}",
+                    def, self.modules[module].name,
+                ))
                 .with_labels(vec![
                     Label::primary(file, span).with_message(format!("{} defined here", def,))
                 ]));
         }
-        for (name, other) in self.modules[module].defs.iter() {
-            if *other != id && *name == def {
+        if let Some(other) = self.modules[module].defs.get(&def) {
+            let same = match (*other, id) {
+                (IntermediateDefId::Type(a, ..), IntermediateDefId::Type(b, ..)) => a == b,
+                (IntermediateDefId::Fun(a, ..), IntermediateDefId::Fun(b, ..)) => a == b,
+                (IntermediateDefId::Global(a, ..), IntermediateDefId::Global(b, ..)) => a == b,
+                (IntermediateDefId::Module(a), IntermediateDefId::Module(b)) => a == b,
+                _ => false,
+            };
+            if !same {
+                //Types have no name until their definition is resolved, so use the defined name
+                let kind = match other {
+                    IntermediateDefId::Type(..) => "type",
+                    IntermediateDefId::Fun(..) => "function",
+                    IntermediateDefId::Global(..) => "global",
+                    IntermediateDefId::Module(..) => "module",
+                };
                 return Err(Diagnostic::error()
                     .with_message(format!(
-                        "{} collides with previously-defined {}",
-                        def,
-                        IntermediateDefFormatter {
-                            lower: self,
-                            id: *other
-                        },
+                        "{} collides with previously-defined {} {}",
+                        def, kind, def,
                     ))
                     .with_labels(vec![
                         Label::primary(file, span).with_message(format!("{} is defined here", def)),
@@ -646,13 +1853,7 @@ impl<'ctx> IrLowerer<'ctx> {
                             }
                             IntermediateDefId::Module(_) => Label::secondary(file, span),
                         }
-                        .with_message(format!(
-                            "Conflicts with {}",
-                            IntermediateDefFormatter {
-                                lower: self,
-                                id: *other
-                            }
-                        )),
+                        .with_message(format!("{} {} previously defined here", kind, def)),
                     ]));
             }
         }
@@ -660,6 +1861,71 @@ impl<'ctx> IrLowerer<'ctx> {
         Ok(())
     }
 
+    /// Find an external or exported function that was previously declared with the same name as
+    /// the given function. Extern functions may be declared any number of times with the same
+    /// signature, but only defined once
+    fn redeclared_fun(
+        &mut self,
+        fun: &IrFun,
+        has_body: bool,
+    ) -> Result<Option<FunId>, Diagnostic<FileId>> {
+        let unmangled = FunFlags::EXTERN | FunFlags::EXPORT;
+        if !fun.flags.intersects(unmangled) {
+            return Ok(None);
+        }
+
+        let other_id = match self.ctx.funs.indices().find(|other| {
+            self.ctx[*other].flags.intersects(unmangled) && self.ctx[*other].name == fun.name
+        }) {
+            Some(other) => other,
+            None => return Ok(None),
+        };
+        let other = &self.ctx[other_id];
+
+        //Parameter names are not part of the signature
+        let param_tys = |ty: &FunType| ty.params.iter().map(|(ty, _)| *ty).collect::<Vec<_>>();
+        if other.ty.return_ty != fun.ty.return_ty || param_tys(&other.ty) != param_tys(&fun.ty) {
+            return Err(Diagnostic::error()
+                .with_message(format!(
+                    "External function {} is redeclared with a conflicting signature",
+                    fun.name
+                ))
+                .with_labels(vec![
+                    Label::primary(fun.file, fun.span).with_message(format!(
+                        "Declared here with type {}",
+                        self.ctx.typename(fun.ty_id)
+                    )),
+                    Label::secondary(other.file, other.span).with_message(format!(
+                        "Previously declared here with type {}",
+                        self.ctx.typename(other.ty_id)
+                    )),
+                ])
+                .with_notes(vec!["Functions marked as external or exported will appear in the final object file with their original name".to_owned()]));
+        }
+
+        if has_body && self.defined_funs.contains(&other_id) {
+            return Err(Diagnostic::error()
+                .with_message(format!(
+                    "External function {} is defined more than once",
+                    fun.name
+                ))
+                .with_labels(vec![
+                    Label::primary(fun.file, fun.span).with_message("Defined again here"),
+                    Label::secondary(other.file, other.span)
+                        .with_message("Previously defined here"),
+                ])
+                .with_notes(vec!["Functions marked as external or exported will appear in the final object file with their original name".to_owned()]));
+        }
+
+        let other = &mut self.ctx[other_id];
+        other.flags |= fun.flags;
+        if has_body {
+            other.file = fun.file;
+            other.span = fun.span;
+        }
+        Ok(Some(other_id))
+    }
+
     pub fn def_name(&self, def: IntermediateDefId) -> Symbol {
         match def {
             IntermediateDefId::Type(ty, ..) => Symbol::from(&self.ctx.typename(ty).to_string()),
@@ -676,25 +1942,162 @@ impl<'ctx> IrLowerer<'ctx> {
         module: IntermediateModuleId,
         path: &SymbolPath,
     ) -> Option<IntermediateDefId> {
-        self.resolve_path_impl(module, path.iter())
+        self.resolve_path_impl(module, module, path.iter())
     }
 
-    /// Attempt to resolve the given path in this module
+    /// Attempt to resolve the given path in this module, where definitions that aren't `pub`
+    /// can only be used if `module` is the module `from` that the path appears in
     fn resolve_path_impl(
         &self,
+        from: IntermediateModuleId,
         module: IntermediateModuleId,
         mut path: PathIter,
     ) -> Option<IntermediateDefId> {
-        let next = path.next().unwrap();
-        let next = self.modules[module].defs.get(&next);
+        let name = path.next().unwrap();
+        let next = self
+            .lookup_def(module, name)
+            .filter(|_| self.is_visible(from, module, name));
         match path.len() {
-            0 => next.copied(),
+            0 => next,
             _ => match next {
-                Some(IntermediateDefId::Module(other)) => self.resolve_path_impl(*other, path),
+                Some(IntermediateDefId::Module(other)) => self.resolve_path_impl(from, other, path),
                 _ => None,
             },
         }
     }
+
+    /// Check if the definition with the given name in `module` can be used from the module `from`
+    pub(super) fn is_visible(
+        &self,
+        from: IntermediateModuleId,
+        module: IntermediateModuleId,
+        name: Symbol,
+    ) -> bool {
+        from == module || !self.modules[module].private.contains(&name)
+    }
+
+    /// Look up a name defined in or imported into a module. The name `root` refers to the root
+    /// module unless it is defined in the module, so paths can be absolute from any module
+    fn lookup_def(&self, module: IntermediateModuleId, name: Symbol) -> Option<IntermediateDefId> {
+        match self.modules[module].defs.get(&name) {
+            Some(def) => Some(*def),
+            None if name.as_str() == "root" => Some(IntermediateDefId::Module(self.root_module)),
+            None => None,
+        }
+    }
+
+    /// Add notes to an error for a path with more than one part that can't be resolved from the
+    /// given module, naming the module that was searched for the first part that isn't found, or
+    /// pointing to the definition that was found if it isn't `pub`
+    pub(super) fn explain_unresolved_path(
+        &self,
+        module: IntermediateModuleId,
+        path: &SymbolPath,
+        mut diagnostic: Diagnostic<FileId>,
+    ) -> Diagnostic<FileId> {
+        let parts = path.iter().collect::<Vec<_>>();
+        let (item, modules) = match parts.split_last() {
+            Some((item, modules)) if !modules.is_empty() => (item, modules),
+            _ => return diagnostic,
+        };
+
+        let mut searched = module;
+        for part in modules {
+            searched = match self.lookup_def(searched, *part) {
+                Some(IntermediateDefId::Module(child)) => child,
+                Some(_) => {
+                    diagnostic.notes.push(format!(
+                        "{} in module {} is not a module",
+                        part, self.modules[searched].name
+                    ));
+                    return diagnostic;
+                }
+                None => {
+                    diagnostic.notes.push(format!(
+                        "Module {} has no module named {}",
+                        self.modules[searched].name, part
+                    ));
+                    return diagnostic;
+                }
+            };
+        }
+
+        match self.lookup_def(searched, *item) {
+            Some(
+                IntermediateDefId::Type(_, file, span)
+                | IntermediateDefId::Fun(_, file, span)
+                | IntermediateDefId::Global(_, file, span),
+            ) if !self.is_visible(module, searched, *item) => {
+                diagnostic.labels.push(
+                    Label::secondary(file, span)
+                        .with_message(format!("{} is defined here without pub", item)),
+                );
+                diagnostic.notes.push(format!(
+                    "{} is private to module {}, mark its definition pub to use it from other modules",
+                    item, self.modules[searched].name
+                ));
+            }
+            Some(_) => (),
+            None => diagnostic.notes.push(format!(
+                "Module {} has no definition named {}",
+                self.modules[searched].name, item
+            )),
+        }
+
+        diagnostic
+    }
+
+    /// Warn when a `pub` function's parameter or return types name a type that is private to the
+    /// module it is defined in, even behind pointers, arrays, or slices
+    fn check_private_in_public(&mut self, fun: FunId) {
+        let ty = self.ctx[fun].ty.clone();
+        for mut ty in ty
+            .params
+            .iter()
+            .map(|(ty, _)| *ty)
+            .chain(std::iter::once(ty.return_ty))
+        {
+            while let IrType::Ptr(inner) | IrType::Array(inner, _) | IrType::Slice(inner) =
+                &self.ctx[ty]
+            {
+                ty = *inner;
+            }
+
+            let private = self.modules.iter().find_map(|module| {
+                module.defs.iter().find_map(|(name, def)| match def {
+                    IntermediateDefId::Type(id, file, span)
+                        if *id == ty && module.private.contains(name) =>
+                    {
+                        Some((*name, module.name, *file, *span))
+                    }
+                    _ => None,
+                })
+            });
+            if let Some((name, module, file, span)) = private {
+                let fun = &self.ctx[fun];
+                self.warnings.push(
+                    Diagnostic::warning()
+                        .with_message(format!(
+                            "Public function {} exposes private type {} in its signature",
+                            fun.name, name
+                        ))
+                        .with_labels(vec![
+                            Label::primary(fun.file, fun.span)
+                                .with_message("Public function defined here"),
+                            Label::secondary(file, span).with_message(format!(
+                                "{} is defined here without pub in module {}",
+                                name, module
+                            )),
+                        ])
+                        .with_notes(vec![format!(
+                            "Other modules can call {} but can't name the type {}",
+                            fun.name, name
+                        )]),
+                );
+                return;
+            }
+        }
+    }
 }
 
 impl IntermediateModule {
@@ -702,6 +2105,7 @@ impl IntermediateModule {
     pub fn new(name: Symbol) -> Self {
         Self {
             defs: HashMap::new(),
+            private: HashSet::new(),
             name,
         }
     }
@@ -722,3 +2126,733 @@ impl<'lower, 'ctx> std::fmt::Display for IntermediateDefFormatter<'lower, 'ctx>
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::testing::{
+        lower, lower_err, lower_files, lower_modules, lower_modules_err, lower_with, Lowered,
+    };
+    use crate::{
+        ir::{
+            layout::TargetSpec,
+            types::IrType,
+            value::{IrExpr, IrExprKind},
+            DiscriminantId, IrTerminator,
+        },
+        llvm::testing::run,
+        Symbol,
+    };
+
+    #[test]
+    fn gapped_enum_discriminants_are_constant_expressions() {
+        let lowered = lower(
+            "const BASE = 10
+
+enum Code { Good, Warn = BASE, Fail = BASE * 2 + 1, Later, Low = -4 }
+
+fun classify(Code c) -> i32 {
+    let v = match c {
+        Good -> phi 0,
+        Warn -> phi 1,
+        Fail -> phi 2,
+        Later -> phi 3,
+        Low -> phi 4
+    }
+    return v
+}
+
+fun main() -> i32 {
+    return classify(Code:Later)
+}",
+        );
+        let ctx = &lowered.ctx;
+        let fun = lowered.fun("classify");
+        let code = ctx.unwrap_alias(ctx[fun].ty.params[0].0);
+        let discriminants = match &ctx[code] {
+            IrType::Enum(e) => e
+                .variants
+                .iter()
+                .map(|variant| variant.discriminant)
+                .collect::<Vec<_>>(),
+            other => panic!("parameter has type {:?}", other),
+        };
+        assert_eq!(discriminants, vec![0, 10, 21, 22, -4]);
+
+        //Every variant is matched, so the default jump can never be taken
+        let body = ctx.fun_body(fun).unwrap();
+        let (arms, default) = body
+            .blocks
+            .iter()
+            .find_map(|bb| match &ctx[*bb].terminator {
+                IrTerminator::JmpMatch {
+                    discriminants,
+                    default_jmp,
+                    ..
+                } => Some((discriminants, default_jmp.bb)),
+                _ => None,
+            })
+            .unwrap();
+        let arms = arms.iter().map(|(id, _)| id.clone()).collect::<Vec<_>>();
+        assert_eq!(
+            arms,
+            (0..5).map(DiscriminantId::Variant).collect::<Vec<_>>()
+        );
+        assert!(matches!(ctx[default].terminator, IrTerminator::Unreachable));
+    }
+
+    #[test]
+    fn non_constant_enum_discriminant_is_rejected() {
+        let err = lower_err(
+            "fun seven() -> i64 { return 7i64 }
+
+enum Code { Good, Bad = seven() }
+
+fun main() -> i32 { return 0 }",
+        );
+        assert_eq!(
+            err.message,
+            "Expression cannot be evaluated at compile time"
+        );
+    }
+
+    #[test]
+    fn float_enum_discriminant_is_rejected() {
+        let err = lower_err(
+            "enum Code { Good, Bad = 1.5 }
+
+fun main() -> i32 { return 0 }",
+        );
+        assert_eq!(
+            err.message,
+            "Discriminant of variant Bad must be an integer, found a value of type f32"
+        );
+    }
+
+    #[test]
+    fn anonymous_types_in_different_files_unify() {
+        let lowered = lower_modules(
+            "type Root = { i32 x, *u8 y }
+fun take_root(Root r) -> i32 { return r.x }
+fun main() -> i32 { return 0 }",
+            &[(
+                "child",
+                "type Child = { i32 x, *u8 y }
+fun take_child(Child c) -> i32 { return c.x }",
+            )],
+        );
+        let ctx = &lowered.ctx;
+        let param = |name| ctx.unwrap_alias(ctx[lowered.fun(name)].ty.params[0].0);
+        assert_eq!(param("take_root"), param("take_child"), "{}", ctx);
+    }
+
+    #[test]
+    fn private_import_is_rejected() {
+        let err = lower_modules_err(
+            "imp child:hidden
+fun main() -> i32 { return hidden() }",
+            &[("child", "fun hidden() -> i32 { return 1 }")],
+        );
+        assert!(err
+            .labels
+            .iter()
+            .any(|label| label.message == "hidden is defined here without pub"));
+    }
+
+    #[test]
+    fn private_type_import_is_rejected() {
+        let err = lower_modules_err(
+            "imp child:T\nfun main(T t) -> i32 { return 0 }",
+            &[("child", "type T = i32")],
+        );
+        assert_eq!(err.message, "Imported symbol child:T not found");
+        assert!(err
+            .labels
+            .iter()
+            .any(|label| label.message == "T is defined here without pub"));
+    }
+
+    #[test]
+    fn pub_import_is_accepted() {
+        let lowered = lower_modules(
+            "imp child:shown
+fun main() -> i32 { return shown() }",
+            &[("child", "pub fun shown() -> i32 { return 1 }")],
+        );
+        lowered.fun("shown");
+    }
+
+    #[test]
+    fn functions_are_stored_in_function_pointers_without_parameter_names() {
+        let lowered = lower(
+            "type Handler = { fun(i32, *u8) -> i32 callback }
+fun on_event(i32 code, *u8 data) -> i32 { return code }
+fun main() -> i32 {
+    let [Handler] h = #Handler { callback = on_event }
+    let [fun(i32, *u8) -> i32] f = on_event
+    return 0
+}",
+        );
+        let ctx = &lowered.ctx;
+        let on_event = &ctx[lowered.fun("on_event")];
+        assert_eq!(
+            ctx.typename(on_event.ty_id).to_string(),
+            "fun(i32, *u8) -> i32"
+        );
+        assert_eq!(on_event.ty.params[0].1, Some(Symbol::from("code")));
+    }
+
+    #[test]
+    fn compatible_extern_redeclaration_is_accepted() {
+        let lowered = lower_modules(
+            "fun ext puts(*u8 s) -> i32
+fun ext puts(*u8 str) -> i32
+fun main() -> i32 { return puts(\"hi\") }",
+            &[],
+        );
+        let puts = lowered
+            .ctx
+            .funs
+            .iter()
+            .filter(|fun| fun.name.as_str() == "puts")
+            .count();
+        assert_eq!(puts, 1);
+    }
+
+    #[test]
+    fn conflicting_extern_redeclaration_is_rejected() {
+        let err = lower_modules_err(
+            "fun ext puts(*u8 s) -> i32
+fun ext puts(*i8 s) -> i32
+fun main() -> i32 { return 0 }",
+            &[],
+        );
+        assert_eq!(
+            err.message,
+            "External function puts is redeclared with a conflicting signature"
+        );
+
+        let err = lower_modules_err(
+            "fun ext puts(*u8 s) -> i32
+fun ext puts(*u8 s) -> i64
+fun main() -> i32 { return 0 }",
+            &[],
+        );
+        assert_eq!(
+            err.message,
+            "External function puts is redeclared with a conflicting signature"
+        );
+    }
+
+    #[test]
+    fn mutually_recursive_functions_call_each_other() {
+        let status = run("fun main() -> i32 {
+    return is_even(10) + is_odd(7) * 2
+}
+
+fun is_even(i32 n) -> i32 {
+    if n == 0 { return 1 }
+    return is_odd(n - 1)
+}
+
+fun is_odd(i32 n) -> i32 {
+    if n == 0 { return 0 }
+    return is_even(n - 1)
+}");
+        assert_eq!(status, 3);
+    }
+
+    #[test]
+    fn definitions_are_visible_before_they_appear() {
+        lower(
+            "fun main() -> i32 {
+    let p = #Pair { a = LIMIT, b = 2 }
+    return sum(p) + COUNT
+}
+
+fun sum(Pair p) -> i32 { return p.a + p.b }
+
+type Pair = { i32 a, i32 b }
+
+const LIMIT = 40
+
+glob mut COUNT = 0",
+        );
+    }
+
+    #[test]
+    fn mutually_referential_structs_resolve() {
+        let lowered = lower(
+            "type Node = { *Edge first, i32 val }
+type Edge = { *Node to, *Edge next }
+
+fun follow(*Node n) -> i32 {
+    return n.first.to.first.next.to.val
+}",
+        );
+        let ctx = &lowered.ctx;
+        let node = ctx.unwrap_alias(ctx[lowered.fun("follow")].ty.params[0].0);
+        let node = match &ctx[node] {
+            IrType::Ptr(node) => ctx.unwrap_alias(*node),
+            other => panic!("parameter has type {:?}", other),
+        };
+        let edge = match &ctx[node] {
+            IrType::Struct(node) => ctx.unwrap_alias(node.fields[0].ty),
+            other => panic!("Node has type {:?}", other),
+        };
+        let to = match &ctx[edge] {
+            IrType::Ptr(edge) => match &ctx[ctx.unwrap_alias(*edge)] {
+                IrType::Struct(edge) => ctx.unwrap_alias(edge.fields[0].ty),
+                other => panic!("Edge has type {:?}", other),
+            },
+            other => panic!("Node.first has type {:?}", other),
+        };
+        match &ctx[to] {
+            IrType::Ptr(to) => assert_eq!(ctx.unwrap_alias(*to), node),
+            other => panic!("Edge.to has type {:?}", other),
+        }
+    }
+
+    #[test]
+    fn duplicate_definitions_are_rejected() {
+        let err = lower_err(
+            "fun twice() -> i32 { return once() }
+fun once() -> i32 { return 1 }
+fun twice() -> i32 { return 2 }",
+        );
+        assert_eq!(
+            err.message,
+            "twice collides with previously-defined function twice"
+        );
+
+        let err = lower_err(
+            "fun Shape() -> i32 { return 1 }
+type Shape = { i32 sides }",
+        );
+        assert_eq!(
+            err.message,
+            "Shape collides with previously-defined type Shape"
+        );
+    }
+
+    const MATH: &str = "pub type Vec2 = { i32 x, i32 y }
+pub fun double(i32 a) -> i32 { return a * 2 }
+pub fun length(Vec2 v) -> i32 { return root:abs(v.x) + root:abs(v.y) }";
+
+    /// Get the names of the functions called in the value returned by a function
+    fn returned_callees(lowered: &Lowered, fun: &str) -> Vec<String> {
+        fn visit(lowered: &Lowered, expr: &IrExpr, callees: &mut Vec<String>) {
+            match &expr.kind {
+                IrExprKind::Binary(lhs, _, rhs) => {
+                    visit(lowered, lhs, callees);
+                    visit(lowered, rhs, callees);
+                }
+                IrExprKind::Call(called, args) => {
+                    if let IrExprKind::Fun(called) = called.kind {
+                        callees.push(lowered.ctx[called].name.to_string());
+                    }
+                    args.iter().for_each(|arg| visit(lowered, arg, callees));
+                }
+                _ => (),
+            }
+        }
+
+        let ctx = &lowered.ctx;
+        let mut callees = vec![];
+        for bb in ctx.blocks_of(lowered.fun(fun)) {
+            if let IrTerminator::Return(expr) = &ctx[bb].terminator {
+                visit(lowered, expr, &mut callees);
+            }
+        }
+        callees
+    }
+
+    #[test]
+    fn items_in_other_modules_are_resolved_by_full_path() {
+        let lowered = lower_modules(
+            "pub fun abs(i32 a) -> i32 { if a < 0 { return -a } else { return a } }
+fun main() -> i32 {
+    let [math:Vec2] v = #math:Vec2 { x = 3, y = -4 }
+    return math:length(v) + math:double(1)
+}",
+            &[("math", MATH)],
+        );
+        assert_eq!(returned_callees(&lowered, "main"), ["length", "double"]);
+        assert_eq!(returned_callees(&lowered, "length"), ["abs", "abs"]);
+    }
+
+    #[test]
+    fn items_in_other_modules_are_resolved_through_imports() {
+        let lowered = lower_modules(
+            "imp math:double
+imp math:Vec2
+pub fun abs(i32 a) -> i32 { if a < 0 { return -a } else { return a } }
+fun main() -> i32 {
+    let [Vec2] v = #Vec2 { x = 3, y = -4 }
+    return double(v.x)
+}",
+            &[("math", MATH)],
+        );
+        assert_eq!(returned_callees(&lowered, "main"), ["double"]);
+    }
+
+    #[test]
+    fn functions_defined_in_another_file_of_the_module_are_called() {
+        let lowered = lower_files(&[
+            "fun main() -> i32 { return helper(20) + 1 }",
+            "fun helper(i32 a) -> i32 { return a * 2 }",
+        ]);
+        let ctx = &lowered.ctx;
+        assert_eq!(returned_callees(&lowered, "main"), ["helper"]);
+        assert_ne!(
+            ctx[lowered.fun("main")].file,
+            ctx[lowered.fun("helper")].file
+        );
+    }
+
+    const CTX_DECL: &str = "type Ctx
+
+fun bump(*Ctx c) -> i32 {
+    return ctx_value(c) + 1
+}";
+
+    const CTX_DEF: &str = "type Ctx = { i32 value }
+
+fun ctx_value(*Ctx c) -> i32 {
+    return c.value
+}
+
+fun main() -> i32 {
+    mut [Ctx] c = #Ctx { value = 41 }
+    return bump(&c)
+}";
+
+    #[test]
+    fn opaque_types_are_defined_in_another_file() {
+        for files in [[CTX_DECL, CTX_DEF], [CTX_DEF, CTX_DECL]] {
+            let lowered = lower_files(&files);
+            let ctx = &lowered.ctx;
+            let param = ctx[lowered.fun("bump")].ty.params[0].0;
+            let pointee = match &ctx[param] {
+                IrType::Ptr(pointee) => *pointee,
+                other => panic!("expected a pointer, found {:?}", other),
+            };
+            assert!(
+                matches!(&ctx[ctx.unwrap_alias(pointee)], IrType::Struct(_)),
+                "{}",
+                ctx.typename(pointee)
+            );
+            assert_eq!(ctx[lowered.fun("ctx_value")].ty.params[0].0, param);
+        }
+    }
+
+    #[test]
+    fn opaque_types_cannot_be_used_by_value() {
+        let err = lower_err(
+            "type Ctx
+
+fun take(Ctx c) {}
+
+fun main() -> i32 { return 0 }",
+        );
+        assert_eq!(
+            err.message,
+            "Opaque type Ctx cannot be used by value in type Ctx"
+        );
+        assert_eq!(
+            err.notes,
+            vec!["Use a pointer such as *Ctx or give Ctx a definition".to_owned()]
+        );
+
+        let err = lower_err(
+            "type Ctx
+type Wrapper = { i32 tag, Ctx ctx }
+
+fun main() -> i32 { return 0 }",
+        );
+        assert_eq!(
+            err.message,
+            "Opaque type Ctx cannot be used by value in type Wrapper"
+        );
+    }
+
+    #[test]
+    fn chained_aliases_resolve_to_the_aliased_type() {
+        let lowered = lower(
+            "type Point = { i32 x, i32 y }
+type Coord = Point
+type Position = Coord
+
+fun x_of(Position p) -> i32 {
+    return p.x
+}",
+        );
+        let ctx = &lowered.ctx;
+        let param = ctx[lowered.fun("x_of")].ty.params[0].0;
+        assert_eq!(ctx.typename(param).to_string(), "Position");
+        assert!(
+            matches!(&ctx[ctx.unwrap_alias(param)], IrType::Struct(_)),
+            "{}",
+            ctx
+        );
+    }
+
+    #[test]
+    fn cyclic_aliases_are_rejected() {
+        let err = lower_err(
+            "type A = B
+type B = A
+
+fun main() -> i32 { return 0 }",
+        );
+        assert_eq!(err.message, "Type alias A refers to itself");
+        assert_eq!(
+            err.notes,
+            vec!["Aliases form the cycle A -> B -> A".to_owned()]
+        );
+        assert_eq!(
+            err.labels
+                .iter()
+                .map(|label| label.message.as_str())
+                .collect::<Vec<_>>(),
+            ["Cyclic alias defined here", "Alias B defined here"]
+        );
+    }
+
+    #[test]
+    fn unresolved_paths_name_the_module_searched() {
+        let err = lower_modules_err(
+            "fun main() -> i32 { return nope:double(1) }",
+            &[("math", MATH)],
+        );
+        assert_eq!(
+            err.message,
+            "No variable or function found for name nope:double"
+        );
+        assert_eq!(
+            err.notes,
+            vec!["Module root has no module named nope".to_owned()]
+        );
+
+        let err = lower_modules_err(
+            "fun main() -> i32 { return math:triple(1) }",
+            &[("math", MATH)],
+        );
+        assert_eq!(
+            err.notes,
+            vec!["Module math has no definition named triple".to_owned()]
+        );
+
+        let err = lower_modules_err(
+            "fun main() -> i32 {
+    let [math:double:Vec2] v = #math:Vec2 { x = 3, y = -4 }
+    return 0
+}",
+            &[("math", MATH)],
+        );
+        assert_eq!(
+            err.message,
+            "Type math:double:Vec2 not found in module root"
+        );
+        assert_eq!(
+            err.notes,
+            vec!["double in module math is not a module".to_owned()]
+        );
+    }
+
+    #[test]
+    fn two_module_import_cycle_is_rejected() {
+        let err = lower_modules_err(
+            "fun main() -> i32 { return 0 }",
+            &[("a", "imp root:b:x"), ("b", "imp root:a:x")],
+        );
+        assert_eq!(
+            err.message,
+            "Imported symbol x is imported in a cycle and never defined"
+        );
+        let labels = err
+            .labels
+            .iter()
+            .map(|label| label.message.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            labels,
+            [
+                "root:b:x imported here in module a",
+                "root:a:x imported here in module b"
+            ]
+        );
+        assert_eq!(
+            err.notes,
+            vec!["Import cycle: a:x -> b:x -> a:x".to_owned()]
+        );
+    }
+
+    #[test]
+    fn three_module_import_cycle_is_rejected() {
+        let err = lower_modules_err(
+            "fun main() -> i32 { return 0 }",
+            &[
+                ("a", "imp root:b:x"),
+                ("b", "imp root:c:x"),
+                ("c", "imp root:a:x"),
+            ],
+        );
+        assert_eq!(
+            err.message,
+            "Imported symbol x is imported in a cycle and never defined"
+        );
+        assert_eq!(err.labels.len(), 3);
+        assert_eq!(
+            err.notes,
+            vec!["Import cycle: a:x -> b:x -> c:x -> a:x".to_owned()]
+        );
+    }
+
+    #[test]
+    fn diamond_imports_are_accepted() {
+        let lowered = lower_modules(
+            "imp a:left
+imp b:right
+fun main() -> i32 { return left() + right() }",
+            &[
+                (
+                    "a",
+                    "imp root:d:base
+pub fun left() -> i32 { return base() }",
+                ),
+                (
+                    "b",
+                    "imp root:d:base
+pub fun right() -> i32 { return base() + 1 }",
+                ),
+                ("d", "pub fun base() -> i32 { return 1 }"),
+            ],
+        );
+        assert_eq!(returned_callees(&lowered, "main"), ["left", "right"]);
+        assert_eq!(returned_callees(&lowered, "left"), ["base"]);
+    }
+
+    #[test]
+    fn modules_importing_each_others_definitions_are_accepted() {
+        lower_modules(
+            "fun main() -> i32 { return 0 }",
+            &[
+                (
+                    "a",
+                    "imp root:b:two
+pub fun one() -> i32 { return 1 }
+fun sum() -> i32 { return one() + two() }",
+                ),
+                (
+                    "b",
+                    "imp root:a:one
+pub fun two() -> i32 { return one() + 1 }",
+                ),
+            ],
+        );
+    }
+
+    /// Lower a function taking an array with the given length expression after the given
+    /// definitions, returning the array's length or the error message if lowering fails
+    fn array_len(defs: &str, len: &str) -> Result<u64, String> {
+        let src = format!("{}\nfun take([{}]u8 a) -> i32 {{ return 0 }}", defs, len);
+        let lowered = lower_with(&src, &[], |_| ()).map_err(|e| e.message)?;
+        let ctx = &lowered.ctx;
+        match &ctx[ctx.unwrap_alias(ctx[lowered.fun("take")].ty.params[0].0)] {
+            IrType::Array(_, len) => Ok(*len),
+            other => panic!("parameter has type {:?}", other),
+        }
+    }
+
+    #[test]
+    fn array_lengths_are_constant_expressions() {
+        assert_eq!(array_len("", "16"), Ok(16));
+        assert_eq!(array_len("const SIZE = 4", "SIZE"), Ok(4));
+        assert_eq!(array_len("const SIZE = 4", "SIZE * 2 + 1"), Ok(9));
+        assert_eq!(
+            array_len("type Header = { i32 a, i64 b }", "sizeof(Header)"),
+            Ok(16)
+        );
+        assert_eq!(array_len("", "0"), Ok(0));
+    }
+
+    #[test]
+    fn invalid_array_lengths_are_rejected() {
+        assert_eq!(
+            array_len("const SIZE = 4", "SIZE - 5"),
+            Err("Array length -1 is negative".to_owned())
+        );
+        assert_eq!(
+            array_len("", "5000000000"),
+            Err(
+                "Array length 5000000000 exceeds the maximum array length of 4294967295".to_owned()
+            )
+        );
+        assert_eq!(
+            array_len("", "1.5"),
+            Err("Array length must be an integer, found a value of type f32".to_owned())
+        );
+        assert_eq!(
+            array_len("glob mut SIZE = 4", "SIZE"),
+            Err("Expression cannot be evaluated at compile time".to_owned())
+        );
+    }
+
+    #[test]
+    fn invalid_array_length_is_labelled_at_length_expression() {
+        let src = "const SIZE = 4\nfun take([SIZE - 5]u8 a) -> i32 { return 0 }";
+        let err = lower_err(src);
+        let span = err.labels[0].range.clone();
+        assert_eq!(&src[span], "SIZE - 5");
+        assert_eq!(err.labels[0].message, "Array length evaluated here");
+    }
+
+    #[test]
+    fn array_lengths_use_target_layouts() {
+        let src = "type Ptrs = { [sizeof(*u8) * 2 + alignof(i64)]u8 bytes }
+fun take(Ptrs p) -> i32 { return 0 }";
+        let len = |triple: &str| {
+            let lowered = lower_with(src, &[], |lowerer| {
+                lowerer.set_target(TargetSpec::from_triple(triple))
+            })
+            .unwrap();
+            let ctx = &lowered.ctx;
+            let ptrs = ctx.unwrap_alias(ctx[lowered.fun("take")].ty.params[0].0);
+            match &ctx[ptrs] {
+                IrType::Struct(s) => match &ctx[ctx.unwrap_alias(s.fields[0].ty)] {
+                    IrType::Array(_, len) => *len,
+                    other => panic!("field has type {:?}", other),
+                },
+                other => panic!("parameter has type {:?}", other),
+            }
+        };
+        assert_eq!(len("x86_64-unknown-linux-gnu"), 24);
+        assert_eq!(len("i686-unknown-linux-gnu"), 12);
+    }
+
+    #[test]
+    fn pointer_sized_array_lengths_wrap_to_target_width() {
+        let len = |triple: &str| {
+            lower_with(
+                "fun take([$usize 4294967298u64]u8 a) -> i32 { return 0 }",
+                &[],
+                |lowerer| lowerer.set_target(TargetSpec::from_triple(triple)),
+            )
+            .map(|lowered| {
+                let ctx = &lowered.ctx;
+                match &ctx[ctx[lowered.fun("take")].ty.params[0].0] {
+                    IrType::Array(_, len) => *len,
+                    other => panic!("parameter has type {:?}", other),
+                }
+            })
+            .map_err(|e| e.message)
+        };
+        assert_eq!(len("wasm32-unknown-unknown"), Ok(2));
+        assert_eq!(
+            len("x86_64-unknown-linux-gnu"),
+            Err(
+                "Array length 4294967298 exceeds the maximum array length of 4294967295".to_owned()
+            )
+        );
+    }
+}
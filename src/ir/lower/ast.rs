@@ -1,32 +1,40 @@
 use codespan_reporting::diagnostic::{Diagnostic, Label};
-use hashbrown::HashMap;
+use hashbrown::{HashMap, HashSet};
 
 use crate::{
     ast::{
-        ElseExpr, Expr, ExprNode, If, IntegerWidth, Literal, Match, NumberLiteral,
-        NumberLiteralAnnotation, Stmt, StmtNode, BigInt,
+        BigInt, ElseExpr, Expr, ExprNode, FunProto, If, IntegerWidth, Let, Literal, Match,
+        NumberLiteral, NumberLiteralAnnotation, Stmt, StmtNode, SymbolPath, UnresolvedType,
     },
     ir::{
         types::{FunType, IrFloatType, IrIntegerType, IrStructField, IrStructType, IrType},
-        value::{IrExpr, IrExprKind, IrLiteral},
-        FunId, IrBB, IrBody, IrContext, IrStmt, IrStmtKind, IrTerminator, IrVar, VarId, TypeId,
+        value::{CastKind, IrExpr, IrExprKind, IrLiteral},
+        BBId, DiscriminantId, FunId, GlobalId, IrBB, IrBody, IrBranch, IrContext, IrStmt,
+        IrStmtKind, IrTerminator, IrVar, TypeId, VarId,
     },
     parse::token::Op,
     util::{files::FileId, loc::Span},
     Symbol,
 };
 
-use super::{IntermediateDefId, IntermediateModuleId, IrLowerer, ScopePlate};
+use super::{
+    method::edit_distance, IntermediateDefId, IntermediateModuleId, IrLowerer, LintFlags,
+    ScopePlate,
+};
 
 impl<'ctx> IrLowerer<'ctx> {
-    pub(super) fn drop(
-        &mut self,
-        expr: &IrExpr,
-        ty: TypeId,
-    ) {
+    pub(super) fn drop(&mut self, expr: &IrExpr, ty: TypeId) {
         match &self.ctx[ty] {
-            IrType::Integer(_) | IrType::Float(_) | IrType::Char | 
-            IrType::Bool | IrType::Unit | IrType::Ptr(_) | IrType::Fun(_) => (),
+            IrType::Integer(_)
+            | IrType::Float(_)
+            | IrType::Char
+            | IrType::Opaque(_)
+            | IrType::Bool
+            | IrType::Unit
+            | IrType::Ptr(_)
+            | IrType::Fun(_)
+            | IrType::Enum(_)
+            | IrType::Slice(_) => (),
             IrType::Struct(s_ty) => {
                 let fields = s_ty.fields.clone();
                 for (idx, field) in fields.into_iter().enumerate() {
@@ -37,11 +45,21 @@ impl<'ctx> IrLowerer<'ctx> {
                     };
 
                     self.drop(&field, field.ty);
-                } 
-            },
-            IrType::Sum(s_ty) => {
-                
-            },
+                }
+            }
+            IrType::Tuple(elements) => {
+                let elements = elements.clone();
+                for (idx, ty) in elements.into_iter().enumerate() {
+                    let element = IrExpr {
+                        span: expr.span,
+                        ty,
+                        kind: IrExprKind::Member(Box::new(expr.clone()), idx),
+                    };
+
+                    self.drop(&element, ty);
+                }
+            }
+            IrType::Sum(s_ty) => {}
             IrType::Array(ty, len) => {
                 let ty = *ty;
                 for i in 0..*len {
@@ -49,11 +67,15 @@ impl<'ctx> IrLowerer<'ctx> {
                         span: expr.span,
                         ty: IrContext::USIZE,
                         kind: IrExprKind::Lit(IrLiteral::Integer(
-                            BigInt { val: i, sign: true },
+                            BigInt {
+                                val: i as u128,
+                                sign: true,
+                            },
                             IrIntegerType {
-                            width: IntegerWidth::PtrSize,
-                            signed: false,
-                        }))
+                                width: IntegerWidth::PtrSize,
+                                signed: false,
+                            },
+                        )),
                     };
 
                     let elem = IrExpr {
@@ -61,14 +83,14 @@ impl<'ctx> IrLowerer<'ctx> {
                         ty,
                         kind: IrExprKind::Index(Box::new(expr.clone()), Box::new(idx)),
                     };
-                    
+
                     self.drop(&elem, ty);
                 }
-            },
+            }
             IrType::Alias { name, ty } => {
                 let bb = self.bb();
                 if let Some(dtor) = self.dtors.get(&expr.ty) {
-                    let ptr = self.ctx.types.insert(IrType::Ptr(expr.ty));
+                    let ptr = self.ctx.ptr_to(expr.ty);
                     self.ctx[bb].stmts.push(IrStmt {
                         span: expr.span,
                         kind: IrStmtKind::Call {
@@ -77,51 +99,52 @@ impl<'ctx> IrLowerer<'ctx> {
                                 span: expr.span,
                                 ty: ptr,
                                 kind: IrExprKind::Unary(Op::AND, Box::new(expr.clone())),
-                            }]
-                        }
+                            }],
+                        },
                     });
                 } else {
                     self.drop(expr, *ty);
                 }
-            },
+            }
             IrType::Invalid => (),
         }
     }
 
-    pub(super) fn drop_all(
-        &mut self,
-    ) {
+    pub(super) fn drop_all(&mut self) {
         let vars = self
             .scope_stack
             .iter()
-            .map(|plate| plate
-                .vars
-                .values()
-                .copied()
-            )
+            .map(|plate| plate.vars.values().map(|(var, _)| *var))
             .flatten()
             .collect::<Vec<_>>();
         for defined in vars {
             let ty = self.ctx[defined].ty;
-            self.drop(&IrExpr {
-                span: Span::new(0, 0),
+            self.drop(
+                &IrExpr {
+                    span: Span::new(0, 0),
+                    ty,
+                    kind: IrExprKind::Var(defined),
+                },
                 ty,
-                kind: IrExprKind::Var(defined),
-            }, ty);
+            );
         }
     }
 
-    /// Lower a function's body to IR statements and basic blocks
+    /// Lower a function's body to IR statements and basic blocks, given the location of every
+    /// parameter in the function's signature
     pub(super) fn lower_body(
         &mut self,
         module: IntermediateModuleId,
         file: FileId,
         fun: FunId,
+        proto: &FunProto,
         stmts: &[Stmt],
     ) -> Result<(), Diagnostic<FileId>> {
         let entry = self.ctx.bbs.insert(IrBB {
+            params: vec![],
             stmts: vec![],
             terminator: IrTerminator::Invalid,
+            terminator_span: None,
         });
         self.bb = Some(entry);
         let return_var = match self.ctx[self.ctx[fun].ty.return_ty] {
@@ -130,6 +153,8 @@ impl<'ctx> IrLowerer<'ctx> {
                 let return_var = self.ctx.vars.insert(IrVar {
                     ty: self.ctx[fun].ty.return_ty,
                     name: Symbol::new(format!("@return_var#{}", self.ctx[fun].name)),
+                    align: None,
+                    mutable: true,
                 });
                 let span = self.ctx[fun].span;
                 self.ctx[entry].stmts.push(IrStmt {
@@ -145,18 +170,31 @@ impl<'ctx> IrLowerer<'ctx> {
             vars: HashMap::default(),
             return_var,
             after_bb: entry,
+            phi_param: false,
+            loop_bb: None,
         });
 
         let params = self.ctx[fun].ty.params.clone();
         let mut param_vars = vec![];
-        for (ty, name) in params {
+        let mut named_params = vec![];
+        for (i, (ty, name)) in params.into_iter().enumerate() {
             if let Some(name) = name {
                 let param_var = self.ctx.vars.insert(IrVar {
                     ty,
                     name: name.clone(),
+                    align: None,
+                    mutable: proto.arg_mutable.get(i).copied().unwrap_or(false),
                 });
                 param_vars.push(Some(param_var));
-                self.lowest_scope_mut().vars.insert(name.clone(), param_var);
+                let span = proto
+                    .arg_spans
+                    .get(i)
+                    .copied()
+                    .unwrap_or(self.ctx[fun].span);
+                self.lowest_scope_mut()
+                    .vars
+                    .insert(name.clone(), (param_var, span));
+                named_params.push((param_var, span));
             } else {
                 param_vars.push(None);
             }
@@ -166,39 +204,99 @@ impl<'ctx> IrLowerer<'ctx> {
             entry,
             parent: fun,
             args: param_vars,
+            blocks: vec![entry],
         });
 
-        for stmt in stmts {
-            self.lower_stmt(module, file, fun, stmt)?;
-        }
+        self.lower_stmts(module, file, fun, stmts)?;
 
         let end = self.bb();
-        match (self.ctx.unwrap_alias(self.ctx[fun].ty.return_ty), &self.ctx[end].terminator) {
+        match (
+            self.ctx.unwrap_alias(self.ctx[fun].ty.return_ty),
+            &self.ctx[end].terminator,
+        ) {
             (ty, IrTerminator::Invalid) if ty == IrContext::UNIT => {
-                self.ctx[end].terminator = IrTerminator::Return(IrExpr {
-                    span: self.ctx[fun].span,
-                    ty: IrContext::UNIT,
-                    kind: IrExprKind::Lit(IrLiteral::Unit),
-                })
-            },
-            (ty, IrTerminator::Invalid) => return Err(Diagnostic::error()
-                .with_message(format!(
-                    "Function {} must return a value of type {} but no return statement terminates the function",
-                    self.ctx[fun].name,
-                    self.ctx.typename(ty),
-                ))
-                .with_labels(vec![
-                    Label::primary(file, self.ctx[fun].span)
-                ])
-            ),
+                self.end_lifetimes(fun, 1, None);
+                let span = self.ctx[fun].span;
+                self.terminate(
+                    end,
+                    IrTerminator::Return(IrExpr {
+                        span,
+                        ty: IrContext::UNIT,
+                        kind: IrExprKind::Lit(IrLiteral::Unit),
+                    }),
+                    span,
+                )
+            }
+            (_, IrTerminator::Invalid) => {
+                let preds = self.ctx.predecessors(fun);
+                match preds.get(&end) {
+                    Some(preds) => {
+                        return Err(self.missing_return(file, fun, end, preds, stmts.last()))
+                    }
+                    //Every path through the body returns before reaching the end, like after an
+                    //if where every branch returns
+                    None => {
+                        let span = self.ctx[fun].span;
+                        self.terminate(end, IrTerminator::Unreachable, span);
+                    }
+                }
+            }
             _ => (),
         }
 
         self.scope_stack.pop();
+        self.check_init(file, fun)?;
+        self.check_unused_vars(file, fun, &named_params);
 
         Ok(())
     }
 
+    /// Create an error for a function returning a value with a reachable block at the end of its
+    /// body, labelling the jumps that reach the end
+    fn missing_return(
+        &self,
+        file: FileId,
+        fun: FunId,
+        end: BBId,
+        preds: &[BBId],
+        last: Option<&Stmt>,
+    ) -> Diagnostic<FileId> {
+        let mut labels = vec![
+            Label::primary(file, self.ctx[fun].span).with_message(format!(
+                "Function returns a value of type {}",
+                self.ctx.typename(self.ctx[fun].ty.return_ty)
+            )),
+        ];
+        for pred in preds {
+            let span = match self.ctx[*pred].terminator_span {
+                Some(span) => span,
+                None => continue,
+            };
+            let message = match &self.ctx[*pred].terminator {
+                IrTerminator::JmpIf { if_false, .. } if if_false.bb == end => {
+                    "This if has no else branch and the function does not return after it"
+                }
+                _ => "Control flow reaches the end of the function from here",
+            };
+            labels.push(Label::secondary(file, span).with_message(message));
+        }
+        //A body with no branches falls through after its last statement
+        if let (true, Some(last)) = (preds.is_empty(), last) {
+            labels.push(
+                Label::secondary(file, last.span)
+                    .with_message("Function body ends here without returning a value"),
+            );
+        }
+
+        Diagnostic::error()
+            .with_message(format!(
+                "Function {} must return a value of type {} but may reach the end of its body without returning",
+                self.ctx[fun].name,
+                self.ctx.typename(self.ctx[fun].ty.return_ty),
+            ))
+            .with_labels(labels)
+    }
+
     /// Lower a single statement to IR instructions
     pub(super) fn lower_stmt(
         &mut self,
@@ -212,16 +310,39 @@ impl<'ctx> IrLowerer<'ctx> {
                 self.lower_loop(module, file, fun, stmt.span, &block)?;
             }
             StmtNode::Return(val) => match (
-                self.lower_expr(module, file, fun, val)?,
+                self.lower_expr_as(module, file, fun, val, self.ctx[fun].ty.return_ty)?,
                 self.lowest_scope().return_var,
             ) {
                 (val, Some(_)) => {
+                    let return_ty = self.ctx[fun].ty.return_ty;
+                    let val = self.coerce(file, val, return_ty)?;
+                    if val.ty != return_ty {
+                        return Err(Diagnostic::error()
+                            .with_message(format!(
+                                "Returning a value of type {} from function {} that returns type {}",
+                                self.ctx.typename(val.ty),
+                                self.ctx[fun].name,
+                                self.ctx.typename(return_ty),
+                            ))
+                            .with_labels(vec![
+                                Label::primary(file, val.span).with_message(format!(
+                                    "Value of type {} returned here",
+                                    self.ctx.typename(val.ty)
+                                )),
+                                Label::secondary(file, self.ctx[fun].span).with_message(format!(
+                                    "Function returns a value of type {}",
+                                    self.ctx.typename(return_ty)
+                                )),
+                            ]));
+                    }
+                    self.end_lifetimes(fun, self.scope_stack.len(), Some(&val));
                     let current = self.bb();
-                    self.ctx[current].terminator = IrTerminator::Return(val);
+                    self.terminate(current, IrTerminator::Return(val), stmt.span);
                 }
                 (val, None) if self.ctx.unwrap_alias(val.ty) == IrContext::UNIT => {
+                    self.end_lifetimes(fun, self.scope_stack.len(), Some(&val));
                     let current = self.bb();
-                    self.ctx[current].terminator = IrTerminator::Return(val);
+                    self.terminate(current, IrTerminator::Return(val), stmt.span);
                 }
                 (_, None) => {
                     return Err(Diagnostic::error()
@@ -231,6 +352,23 @@ impl<'ctx> IrLowerer<'ctx> {
                         .with_labels(vec![Label::primary(file, stmt.span)]))
                 }
             },
+            StmtNode::Phi(val) if self.current_scope().phi_param => {
+                let after_bb = self.current_scope().after_bb;
+                let return_val = self.lower_phi_value(module, file, fun, after_bb, val)?;
+
+                //The value is evaluated by the jump, so variables it reads must stay live
+                self.end_lifetimes(fun, 1, Some(&return_val));
+                let current = self.bb();
+                self.terminate(
+                    current,
+                    IrTerminator::Jmp(IrBranch {
+                        bb: after_bb,
+                        args: vec![return_val],
+                    }),
+                    stmt.span,
+                );
+                *self.bb_mut() = after_bb;
+            }
             StmtNode::Phi(val) => {
                 let return_var = match self.current_scope().return_var {
                     Some(ret) => ret,
@@ -270,10 +408,57 @@ impl<'ctx> IrLowerer<'ctx> {
                         val: return_val,
                     },
                 });
-                self.ctx[current].terminator = IrTerminator::Jmp(self.current_scope().after_bb);
+                self.end_lifetimes(fun, 1, None);
+                let after_bb = self.current_scope().after_bb;
+                self.terminate(current, IrTerminator::Jmp(after_bb.into()), stmt.span);
                 *self.bb_mut() = self.current_scope().after_bb;
             }
             StmtNode::Let(let_stmt) => match let_stmt.assigned.as_ref() {
+                //Declaration of a variable with no initializer
+                None if let_stmt.ty.is_some()
+                    && matches!(let_stmt.let_expr.node, ExprNode::Access(_)) =>
+                {
+                    let name = match &let_stmt.let_expr.node {
+                        ExprNode::Access(name) => name.last(),
+                        _ => unreachable!(),
+                    };
+                    let ty = self.resolve_type(
+                        let_stmt.ty.as_ref().unwrap(),
+                        module,
+                        file,
+                        let_stmt.let_expr.span,
+                    )?;
+                    let var = self.lower_var_decl(file, let_stmt, name, ty)?;
+                    if !let_stmt.mutable {
+                        self.deferred_vars.insert(var, let_stmt.let_expr.span);
+                    }
+                    if self.zero_init {
+                        let current = self.bb();
+                        self.ctx[current].stmts.push(IrStmt {
+                            span: let_stmt.let_expr.span,
+                            kind: IrStmtKind::Zero(var),
+                        });
+                    } else {
+                        self.uninit_vars.insert(var, let_stmt.let_expr.span);
+                    }
+                }
+                //Declaration of a new variable with no type to infer
+                None if matches!(&let_stmt.let_expr.node, ExprNode::Access(_)) => {
+                    let name = match &let_stmt.let_expr.node {
+                        ExprNode::Access(name) => name.last(),
+                        _ => unreachable!(),
+                    };
+                    return Err(Diagnostic::error()
+                        .with_message(format!(
+                            "Variable {} declared without a type annotation or an initializer",
+                            name
+                        ))
+                        .with_labels(vec![Label::primary(file, let_stmt.let_expr.span)
+                            .with_message("Variable declared here")])
+                        .with_notes(vec![
+                            "The type of a variable is inferred from its initializer if it has no type annotation".to_owned(),
+                        ]));
+                }
                 None => {
                     let expr = self.lower_expr(module, file, fun, &let_stmt.let_expr)?;
                     let current = self.bb();
@@ -282,101 +467,43 @@ impl<'ctx> IrLowerer<'ctx> {
                         kind: IrStmtKind::Exec(expr),
                     })
                 }
-                Some(assigned) => {
-                    let assigned = self.lower_expr(module, file, fun, &assigned)?;
-                    let (ty, ptr) = match &let_stmt.let_expr.node {
-                        ExprNode::Access(name) => {
-                            let (ty, var) = match self.lookup_var(&name.last()) {
-                                Some(var) => (self.ctx[var].ty, var),
-                                None => {
-                                    let ty = let_stmt
-                                        .ty
-                                        .as_ref()
-                                        .map(|ty| {
-                                            self.resolve_type(
-                                                ty,
-                                                module,
-                                                file,
-                                                let_stmt.let_expr.span,
-                                            )
-                                        })
-                                        .unwrap_or(Ok(assigned.ty))?;
-
-                                    let var = IrVar {
-                                        ty,
-                                        name: name.last(),
-                                    };
-
-                                    let var_id = self.ctx.vars.insert(var);
-                                    self.current_scope_mut().vars.insert(name.last(), var_id);
-                                    let current = self.bb();
-                                    self.ctx[current].stmts.push(IrStmt {
-                                        span: let_stmt.let_expr.span,
-                                        kind: IrStmtKind::VarLive(var_id),
-                                    });
-
-                                    (ty, var_id)
-                                }
-                            };
-
-                            (
-                                ty,
-                                IrExpr {
-                                    span: let_stmt.let_expr.span,
-                                    ty,
-                                    kind: IrExprKind::Var(var),
-                                },
-                            )
-                        }
-                        _ => {
-                            let let_expr =
-                                self.lower_expr(module, file, fun, &let_stmt.let_expr)?;
-                            (assigned.ty, let_expr)
-                        }
-                    };
-
-                    if ty != assigned.ty {
-                        return Err(Diagnostic::error()
-                            .with_message(format!(
-                                "Assigning a value of type {} to a value of incompatible type {}",
-                                self.ctx.typename(assigned.ty),
-                                self.ctx.typename(ty)
-                            ))
-                            .with_labels(vec![
-                                Label::primary(file, assigned.span).with_message(format!(
-                                    "Assigned value of type {} appears here",
-                                    self.ctx.typename(assigned.ty)
-                                )),
-                                Label::secondary(file, ptr.span).with_message(format!(
-                                    "Assignee of type {} appears here",
-                                    self.ctx.typename(ty)
-                                )),
-                            ]));
+                Some(assigned) => match &let_stmt.let_expr.node {
+                    ExprNode::Literal(Literal::Tuple(names)) => {
+                        self.lower_destructure(module, file, fun, let_stmt, names, assigned)?
                     }
-                    let current = self.bb();
-                    self.ctx[current].stmts.push(IrStmt {
-                        span: (let_stmt.let_expr.span.from..assigned.span.to).into(),
-                        kind: IrStmtKind::Write { ptr, val: assigned },
-                    });
-                }
+                    _ => self.lower_assignment(module, file, fun, let_stmt, assigned, true)?,
+                },
             },
+            StmtNode::Assign(place, assigned) => {
+                let let_stmt = Let {
+                    mutable: false,
+                    ty: None,
+                    let_expr: place.clone(),
+                    assigned: None,
+                    align: None,
+                };
+                self.lower_assignment(module, file, fun, &let_stmt, assigned, false)?;
+            }
             StmtNode::Call(ident, args) => {
                 let def = self.resolve_path(module, ident);
                 match def {
                     Some(IntermediateDefId::Fun(fun_id, ..)) => {
                         let fun_ty = self.ctx[fun_id].ty.clone();
-                        let args = args
-                            .iter()
-                            .map(|arg| self.lower_expr(module, file, fun, arg))
-                            .collect::<Result<Vec<_>, _>>()?;
+                        let args = self.lower_args(module, file, fun, &fun_ty, args)?;
 
-                        self.typecheck_fun(file, stmt.span, &fun_ty, &args)?;
+                        let args = self.typecheck_fun(file, stmt.span, &fun_ty, args)?;
                         let current = self.bb();
                         self.ctx[current].stmts.push(IrStmt {
                             span: stmt.span,
                             kind: IrStmtKind::Call { fun: fun_id, args },
                         })
                     }
+                    _ if self.is_builtin(module, ident, "volatile_write") => {
+                        self.lower_volatile_write(module, file, fun, stmt.span, args)?;
+                    }
+                    _ if self.is_builtin(module, ident, "assert") => {
+                        self.lower_assert(module, file, fun, stmt.span, args)?;
+                    }
                     _ => {
                         return Err(Diagnostic::error()
                             .with_message(format!(
@@ -387,43 +514,69 @@ impl<'ctx> IrLowerer<'ctx> {
                     }
                 }
             }
-            StmtNode::If(expr) => return self.lower_if(module, file, fun, expr).map(|_| ()),
+            StmtNode::If(expr) => return self.lower_if(module, file, fun, expr, None).map(|_| ()),
             StmtNode::Block(b) => {
                 let old_bb = self.bb();
-                let new_bb = self.ctx.bb();
-                let after_bb = self.ctx.bb();
+                let new_bb = self.new_bb(fun);
+                let after_bb = self.new_bb(fun);
                 self.scope_stack.push(ScopePlate {
                     vars: HashMap::new(),
                     return_var: None,
                     after_bb,
+                    phi_param: false,
+                    loop_bb: None,
                 });
                 *self.bb_mut() = new_bb;
-                self.lower_block(module, file, fun, &b)?;
+                self.lower_block(module, file, fun, &b, stmt.span)?;
                 self.scope_stack.pop();
-                self.ctx[old_bb].terminator = IrTerminator::Jmp(new_bb);
+                self.terminate(old_bb, IrTerminator::Jmp(new_bb.into()), stmt.span);
             }
             StmtNode::Match(match_stmt) => {
-                self.lower_match(module, file, fun, match_stmt, stmt.span)?;
-            }
-            StmtNode::Break => {
-                let current = self.bb();
-                self.ctx[current].terminator = IrTerminator::Jmp(self.current_scope().after_bb);
-                *self.bb_mut() = self.current_scope().after_bb;
+                self.lower_match(module, file, fun, match_stmt, stmt.span, None)?;
             }
-            StmtNode::Continue => {
+            StmtNode::Break(..) | StmtNode::Continue => {
+                let is_break = matches!(stmt.node, StmtNode::Break(..));
+                let depth = self.loop_depth(file, stmt.span, is_break)?;
+                let loop_scope = &self.scope_stack[self.scope_stack.len() - depth];
+                let target = match is_break {
+                    true => loop_scope.after_bb,
+                    false => loop_scope.loop_bb.unwrap(),
+                };
+
+                if let (StmtNode::Break(val), Some(phi_var)) = (&stmt.node, loop_scope.return_var) {
+                    self.check_break_kind(file, phi_var, stmt.span, val.is_some())?;
+                    if let Some(val) = val {
+                        self.lower_break_value(module, file, fun, phi_var, val)?;
+                    }
+                }
+
+                //Variables of every scope inside the loop die when jumping out of the body
+                self.end_lifetimes(fun, depth, None);
                 let current = self.bb();
-                self.ctx[current].terminator = IrTerminator::Jmp(self.bb());
+                self.terminate(current, IrTerminator::Jmp(target.into()), stmt.span);
+                //Statements after the jump are unreachable
+                *self.bb_mut() = self.new_bb(fun);
             }
         }
         Ok(())
     }
 
+    /// Lower an access of a field or element of an object, dereferencing pointers to the object
+    /// as many times as needed
     pub(super) fn lower_member(
         &mut self,
         file: FileId,
-        object: IrExpr,
+        mut object: IrExpr,
         name: &Symbol,
     ) -> Result<IrExpr, Diagnostic<FileId>> {
+        while let IrType::Ptr(pointee) = &self.ctx[self.ctx.unwrap_alias(object.ty)] {
+            object = IrExpr {
+                span: object.span,
+                ty: *pointee,
+                kind: IrExprKind::Unary(Op::Star, Box::new(object)),
+            };
+        }
+
         let object_ty = self.ctx.unwrap_alias(object.ty);
         match &self.ctx[object_ty] {
             IrType::Struct(s_ty) => {
@@ -445,6 +598,64 @@ impl<'ctx> IrLowerer<'ctx> {
                     .with_labels(vec![Label::primary(file, object.span)
                         .with_message("Structure field access occurs here")]));
             }
+            IrType::Slice(element) => {
+                let (idx, ty) = match name.as_str() {
+                    "ptr" => (0, self.ctx.ptr_to(*element)),
+                    "len" => (1, IrContext::USIZE),
+                    _ => {
+                        return Err(Diagnostic::error()
+                            .with_message(format!(
+                                "Slice type {} has no field {}",
+                                self.ctx.typename(object.ty),
+                                name,
+                            ))
+                            .with_labels(vec![Label::primary(file, object.span)
+                                .with_message("Field access occurs here")])
+                            .with_notes(
+                                vec!["Slices have a ptr field and a len field".to_owned()],
+                            ))
+                    }
+                };
+                Ok(IrExpr {
+                    span: object.span,
+                    ty,
+                    kind: IrExprKind::Member(Box::new(object), idx),
+                })
+            }
+            IrType::Tuple(elements) => match name.parse::<usize>() {
+                Ok(idx) if idx < elements.len() => Ok(IrExpr {
+                    span: object.span,
+                    ty: elements[idx],
+                    kind: IrExprKind::Member(Box::new(object), idx),
+                }),
+                _ => Err(Diagnostic::error()
+                    .with_message(format!(
+                        "Tuple type {} has no element {}",
+                        self.ctx.typename(object.ty),
+                        name,
+                    ))
+                    .with_labels(vec![Label::primary(file, object.span)
+                        .with_message("Tuple element access occurs here")])
+                    .with_notes(vec![format!(
+                        "Elements of the tuple are numbered from 0 to {}",
+                        elements.len() - 1
+                    )])),
+            },
+            IrType::Enum(e) => match e.variant_idx(name) {
+                Some(idx) if e.variants[idx].payload.is_some() => Ok(IrExpr {
+                    span: object.span,
+                    ty: e.variants[idx].payload.unwrap(),
+                    kind: IrExprKind::Member(Box::new(object), idx),
+                }),
+                _ => Err(Diagnostic::error()
+                    .with_message(format!(
+                        "Enum type {} has no variant {} carrying a value",
+                        self.ctx.typename(object.ty),
+                        name,
+                    ))
+                    .with_labels(vec![Label::primary(file, object.span)
+                        .with_message("Variant value access occurs here")])),
+            },
             _ => {
                 return Err(Diagnostic::error()
                     .with_message(format!(
@@ -459,6 +670,74 @@ impl<'ctx> IrLowerer<'ctx> {
         }
     }
 
+    /// Lower the construction of an enum's variant, with the arguments passed to the variant if
+    /// it was called like a function
+    fn lower_variant(
+        &mut self,
+        module: IntermediateModuleId,
+        file: FileId,
+        fun: FunId,
+        span: Span,
+        (ty, idx): (TypeId, usize),
+        args: Option<&[Expr]>,
+    ) -> Result<IrExpr, Diagnostic<FileId>> {
+        let variant = match &self.ctx[self.ctx.unwrap_alias(ty)] {
+            IrType::Enum(e) => e.variants[idx],
+            _ => unreachable!(),
+        };
+
+        let payload = match (variant.payload, args) {
+            (None, None) => None,
+            (Some(payload_ty), Some([arg])) => {
+                let arg = self.lower_expr(module, file, fun, arg)?;
+                if arg.ty != payload_ty {
+                    return Err(Diagnostic::error()
+                        .with_message(format!(
+                            "Variant {} of enum {} carries a value of type {} but a value of type {} was given",
+                            variant.name,
+                            self.ctx.typename(ty),
+                            self.ctx.typename(payload_ty),
+                            self.ctx.typename(arg.ty),
+                        ))
+                        .with_labels(vec![
+                            Label::primary(file, arg.span).with_message("Value appears here"),
+                            Label::secondary(file, span).with_message("Variant constructed here"),
+                        ]));
+                }
+                Some(Box::new(arg))
+            }
+            (Some(payload_ty), _) => {
+                return Err(Diagnostic::error()
+                    .with_message(format!(
+                        "Variant {} of enum {} must be constructed with one value of type {}",
+                        variant.name,
+                        self.ctx.typename(ty),
+                        self.ctx.typename(payload_ty),
+                    ))
+                    .with_labels(vec![
+                        Label::primary(file, span).with_message("Variant constructed here")
+                    ]))
+            }
+            (None, Some(_)) => {
+                return Err(Diagnostic::error()
+                    .with_message(format!(
+                        "Variant {} of enum {} carries no value and cannot be called",
+                        variant.name,
+                        self.ctx.typename(ty),
+                    ))
+                    .with_labels(vec![
+                        Label::primary(file, span).with_message("Variant called here")
+                    ]))
+            }
+        };
+
+        Ok(IrExpr {
+            span,
+            ty,
+            kind: IrExprKind::Variant(idx, payload),
+        })
+    }
+
     /// Lower a single AST expression to intermediate representation
     pub(super) fn lower_expr(
         &mut self,
@@ -468,30 +747,43 @@ impl<'ctx> IrLowerer<'ctx> {
         expr: &Expr,
     ) -> Result<IrExpr, Diagnostic<FileId>> {
         Ok(match &expr.node {
+            //Local variables shadow definitions of the same name
+            ExprNode::Access(pat) if pat.len() == 1 && self.lookup_var(&pat.last()).is_some() => {
+                let var = self.lookup_var(&pat.last()).unwrap();
+                IrExpr {
+                    kind: IrExprKind::Var(var),
+                    ty: self.ctx[var].ty,
+                    span: expr.span,
+                }
+            }
             ExprNode::Access(pat) => match self.resolve_path(module, pat) {
                 Some(IntermediateDefId::Fun(fun_id, ..)) => IrExpr {
                     kind: IrExprKind::Fun(fun_id),
                     ty: self.ctx[fun_id].ty_id,
                     span: expr.span,
                 },
-                Some(IntermediateDefId::Global(g, ..)) => IrExpr {
-                    span: expr.span,
-                    ty: self.ctx[g].ty,
-                    kind: IrExprKind::Global(g),
-                },
-                _ => match self.lookup_var(&pat.last()) {
-                    Some(var) if pat.len() == 1 => IrExpr {
-                        kind: IrExprKind::Var(var),
-                        ty: self.ctx[var].ty,
-                        span: expr.span,
-                    },
-                    _ => {
-                        return Err(Diagnostic::error()
-                            .with_message(format!("No variable or function found for name {}", pat))
-                            .with_labels(vec![Label::primary(file, expr.span)
-                                .with_message("Unknown identifier appears here")]))
+                Some(IntermediateDefId::Global(g, ..)) => {
+                    self.eval_const(g)?;
+                    match self.ctx[g].value {
+                        Some(value) => value.to_expr(expr.span, self.ctx[g].ty),
+                        None => IrExpr {
+                            span: expr.span,
+                            ty: self.ctx[g].ty,
+                            kind: IrExprKind::Global(g),
+                        },
                     }
-                },
+                }
+                _ if self.resolve_variant(module, pat).is_some() => {
+                    let variant = self.resolve_variant(module, pat).unwrap();
+                    return self.lower_variant(module, file, fun, expr.span, variant, None);
+                }
+                _ => {
+                    let diagnostic = Diagnostic::error()
+                        .with_message(format!("No variable or function found for name {}", pat))
+                        .with_labels(vec![Label::primary(file, expr.span)
+                            .with_message("Unknown identifier appears here")]);
+                    return Err(self.explain_unresolved_path(module, pat, diagnostic));
+                }
             },
             ExprNode::DerefMember {
                 structure,
@@ -522,14 +814,45 @@ impl<'ctx> IrLowerer<'ctx> {
                 self.lower_member(file, object, name)?
             }
             ExprNode::Call(fun_ast, args) => {
-                let fun_ir = self.lower_expr(module, file, fun, fun_ast)?;
+                if let ExprNode::Access(path) = &fun_ast.node {
+                    if self.is_builtin(module, path, "volatile_read") {
+                        return self.lower_volatile_read(module, file, fun, expr.span, args);
+                    }
+                    if let Some(variant) = self.resolve_variant(module, path) {
+                        return self.lower_variant(
+                            module,
+                            file,
+                            fun,
+                            expr.span,
+                            variant,
+                            Some(args),
+                        );
+                    }
+                }
+
+                let (fun_ir, receiver) = match &fun_ast.node {
+                    ExprNode::Member(object, name) => {
+                        let object = self.lower_expr(module, file, fun, object)?;
+                        match self.lower_member(file, object.clone(), name) {
+                            Ok(member) => (member, None),
+                            Err(_) => {
+                                let (method, receiver) =
+                                    self.resolve_method(module, file, expr.span, object, name)?;
+                                (method, Some(receiver))
+                            }
+                        }
+                    }
+                    _ => (self.lower_expr(module, file, fun, fun_ast)?, None),
+                };
                 match self.ctx[self.ctx.unwrap_alias(fun_ir.ty)].clone() {
                     IrType::Fun(fun_ty) => {
-                        let args = args
-                            .iter()
-                            .map(|arg| self.lower_expr(module, file, fun, arg))
-                            .collect::<Result<Vec<IrExpr>, _>>()?;
-                        self.typecheck_fun(file, expr.span, &fun_ty, &args)?;
+                        let args = match receiver {
+                            Some(receiver) => {
+                                self.lower_method_args(module, file, fun, &fun_ty, receiver, args)?
+                            }
+                            None => self.lower_args(module, file, fun, &fun_ty, args)?,
+                        };
+                        let args = self.typecheck_fun(file, expr.span, &fun_ty, args)?;
 
                         IrExpr {
                             kind: IrExprKind::Call(Box::new(fun_ir), args),
@@ -548,28 +871,46 @@ impl<'ctx> IrLowerer<'ctx> {
                     }
                 }
             }
-            ExprNode::If(expr) => return self.lower_if(module, file, fun, expr),
+            ExprNode::If(expr) => return self.lower_if(module, file, fun, expr, None),
             ExprNode::Loop(stmts) => return self.lower_loop(module, file, fun, expr.span, &stmts),
             ExprNode::Match(match_expr) => {
-                return self.lower_match(module, file, fun, match_expr, expr.span)
+                return self.lower_match(module, file, fun, match_expr, expr.span, None)
             }
             ExprNode::Unary(op, expr) => return self.lower_unary(module, file, fun, *op, &expr),
             ExprNode::Bin(lhs, op, rhs) => {
                 return self.lower_bin(module, file, fun, &lhs, *op, &rhs)
             }
-            ExprNode::Cast(ty, expr) => {
+            ExprNode::Cast(ty, casted) => {
+                let ty = self.resolve_type(ty, module, file, casted.span)?;
+                return self.lower_cast(module, file, fun, casted, ty, expr.span);
+            }
+            ExprNode::SizeOf(ty) => {
+                let ty = self.resolve_type(ty, module, file, expr.span)?;
+                self.ensure_sized(ty, file, expr.span)?;
+                IrExpr {
+                    span: expr.span,
+                    ty: IrContext::USIZE,
+                    kind: IrExprKind::SizeOf(ty),
+                }
+            }
+            ExprNode::AlignOf(ty) => {
                 let ty = self.resolve_type(ty, module, file, expr.span)?;
-                return self.lower_cast(module, file, fun, expr, ty);
+                self.ensure_sized(ty, file, expr.span)?;
+                IrExpr {
+                    span: expr.span,
+                    ty: IrContext::USIZE,
+                    kind: IrExprKind::AlignOf(ty),
+                }
             }
             ExprNode::Index(obj, idx) => {
                 let obj = self.lower_expr(module, file, fun, obj)?;
                 let obj_ty = self.ctx.unwrap_alias(obj.ty);
                 let elem_ty = match self.ctx[obj_ty] {
-                    IrType::Array(elem, _) => elem,
+                    IrType::Array(elem, _) | IrType::Slice(elem) => elem,
                     _ => {
                         return Err(Diagnostic::error()
                             .with_message(format!(
-                                "Cannot index an expression of non-array type {}",
+                                "Cannot index an expression of non-array and non-slice type {}",
                                 self.ctx.typename(obj.ty),
                             ))
                             .with_labels(vec![Label::primary(file, obj.span)
@@ -605,7 +946,7 @@ impl<'ctx> IrLowerer<'ctx> {
             ExprNode::Literal(lit) => match lit {
                 Literal::String(s) => IrExpr {
                     span: expr.span,
-                    ty: self.ctx.types.insert(IrType::Ptr(IrContext::U8)),
+                    ty: self.ctx.ptr_to(IrContext::U8),
                     kind: IrExprKind::Lit(IrLiteral::String(s.clone())),
                 },
                 Literal::Bool(b) => IrExpr {
@@ -623,13 +964,27 @@ impl<'ctx> IrLowerer<'ctx> {
                     ty: IrContext::UNIT,
                     kind: IrExprKind::Lit(IrLiteral::Unit),
                 },
+                Literal::Tuple(exprs) => {
+                    let exprs = exprs
+                        .iter()
+                        .map(|expr| self.lower_expr(module, file, fun, expr))
+                        .collect::<Result<Vec<_>, _>>()?;
+
+                    IrExpr {
+                        span: expr.span,
+                        ty: self
+                            .ctx
+                            .tuple_of(exprs.iter().map(|expr| expr.ty).collect()),
+                        kind: IrExprKind::Lit(IrLiteral::Tuple(exprs)),
+                    }
+                }
                 Literal::Array(exprs) => {
                     let exprs = exprs
                         .iter()
                         .map(|expr| self.lower_expr(module, file, fun, expr))
                         .collect::<Result<Vec<_>, _>>()?;
 
-                    let ty = if exprs.len() > 1 {
+                    let ty = if !exprs.is_empty() {
                         let ty = exprs.first().unwrap().ty;
                         for (i, elem) in exprs.iter().enumerate() {
                             if elem.ty != ty {
@@ -662,7 +1017,7 @@ impl<'ctx> IrLowerer<'ctx> {
 
                     IrExpr {
                         span: expr.span,
-                        ty: self.ctx.types.insert(IrType::Array(ty, exprs.len() as u64)),
+                        ty: self.ctx.array_of(ty, exprs.len() as u64),
                         kind: IrExprKind::Lit(IrLiteral::Array(exprs)),
                     }
                 }
@@ -690,46 +1045,45 @@ impl<'ctx> IrLowerer<'ctx> {
                         None
                     };
 
-                    let fields = fields
-                        .iter()
-                        .map(|(name, field)| {
-                            if let Some(struct_ty) = &struct_ty {
-                                if struct_ty.field_ty(name).is_none() {
-                                    return Err(Diagnostic::error()
-                                        .with_message(format!(
-                                            "Structure literal assigns a value for field named {}, but structure type {} contains no such field",
-                                            name,
-                                            self.ctx.typename(ty.unwrap())
-                                        ))
-                                    )
-                                }
-                            }
-
-                            Ok((*name, self.lower_expr(module, file, fun, field)? ))
-                        })
-                        .collect::<Result<Vec<_>, _>>()?;
+                    let fields = self.lower_struct_fields(
+                        module,
+                        file,
+                        fun,
+                        expr.span,
+                        ty.zip(struct_ty.as_ref()),
+                        fields,
+                    )?;
 
-                    let lit_expr = IrExpr {
-                        span: expr.span,
-                        ty: self.ctx.types.insert(IrType::Struct(IrStructType {
+                    //Fields are checked against and ordered by the declared structure type, so
+                    //the literal has that type instead of a distinct structure type with the
+                    //same fields
+                    let lit_ty = match unwrapped {
+                        Some(unwrapped) => unwrapped,
+                        None => self.ctx.types.insert(IrType::Struct(IrStructType {
                             fields: fields
                                 .iter()
                                 .map(|(name, expr)| IrStructField {
                                     name: *name,
                                     ty: expr.ty,
+                                    align: None,
                                 })
                                 .collect(),
+                            packed: false,
                         })),
+                    };
+                    let lit_expr = IrExpr {
+                        span: expr.span,
+                        ty: lit_ty,
                         kind: IrExprKind::Lit(IrLiteral::Struct(fields)),
                     };
 
                     match ty {
-                        Some(ty) => IrExpr {
+                        Some(ty) if ty != lit_ty => IrExpr {
                             span: expr.span,
                             ty,
-                            kind: IrExprKind::Cast(Box::new(lit_expr), ty),
+                            kind: IrExprKind::Cast(Box::new(lit_expr), ty, CastKind::Identity),
                         },
-                        None => lit_expr,
+                        _ => lit_expr,
                     }
                 }
                 Literal::Number(num) => {
@@ -744,35 +1098,53 @@ impl<'ctx> IrLowerer<'ctx> {
                         NumberLiteralAnnotation::I16 => (true, IrContext::I16),
                         NumberLiteralAnnotation::I32 => (true, IrContext::I32),
                         NumberLiteralAnnotation::I64 => (true, IrContext::I64),
+                        NumberLiteralAnnotation::I128 => (true, IrContext::I128),
 
                         NumberLiteralAnnotation::U8 => (false, IrContext::U8),
                         NumberLiteralAnnotation::U16 => (false, IrContext::U16),
                         NumberLiteralAnnotation::U32 => (false, IrContext::U32),
                         NumberLiteralAnnotation::U64 => (false, IrContext::U64),
+                        NumberLiteralAnnotation::U128 => (false, IrContext::U128),
 
                         NumberLiteralAnnotation::F32 => (false, IrContext::F32),
                         NumberLiteralAnnotation::F64 => (false, IrContext::F64),
 
                         NumberLiteralAnnotation::Usz => (false, IrContext::USIZE),
-                        NumberLiteralAnnotation::Isz => (false, IrContext::ISIZE),
+                        NumberLiteralAnnotation::Isz => (true, IrContext::ISIZE),
+                    };
+
+                    //Literals are only wider than 64 bits when annotated as a 128 bit integer type
+                    let width = if ty == IrContext::I128 || ty == IrContext::U128 {
+                        IntegerWidth::OneTwentyEight
+                    } else {
+                        IntegerWidth::SixtyFour
                     };
 
                     let lit = match num {
-                        NumberLiteral::Integer(num, _) => IrExpr {
-                            span: expr.span,
-                            ty: if signed {
-                                IrContext::I64
-                            } else {
-                                IrContext::U64
-                            },
-                            kind: IrExprKind::Lit(IrLiteral::Integer(
-                                *num,
-                                IrIntegerType {
-                                    width: IntegerWidth::SixtyFour,
-                                    signed,
-                                },
-                            )),
-                        },
+                        NumberLiteral::Integer(num, _) => {
+                            let wide = width == IntegerWidth::OneTwentyEight;
+                            if !wide && num.val > u64::MAX as u128 {
+                                return Err(Diagnostic::error()
+                                    .with_message(format!(
+                                        "Integer literal {} does not fit in 64 bits",
+                                        num.val
+                                    ))
+                                    .with_labels(vec![Label::primary(file, expr.span)
+                                        .with_message("Integer literal appears here")])
+                                    .with_notes(vec![
+                                        "Annotate the literal with i128 or u128 to use a 128 bit integer".to_owned(),
+                                    ]));
+                            }
+
+                            IrExpr {
+                                span: expr.span,
+                                ty: IrContext::itype(signed, width),
+                                kind: IrExprKind::Lit(IrLiteral::Integer(
+                                    *num,
+                                    IrIntegerType { width, signed },
+                                )),
+                            }
+                        }
                         NumberLiteral::Float(num, _) => IrExpr {
                             span: expr.span,
                             ty: IrContext::F64,
@@ -783,23 +1155,27 @@ impl<'ctx> IrLowerer<'ctx> {
                         },
                     };
 
+                    //Every numeric type can be cast to every other numeric type
+                    let kind = self.ctx.cast_kind(lit.ty, ty).unwrap();
                     IrExpr {
                         span: expr.span,
                         ty,
-                        kind: IrExprKind::Cast(Box::new(lit), ty),
+                        kind: IrExprKind::Cast(Box::new(lit), ty, kind),
                     }
                 }
             },
             ExprNode::Block(b) => {
                 let old_bb = self.bb();
-                let new_bb = self.ctx.bb();
-                self.ctx[old_bb].terminator = IrTerminator::Jmp(new_bb);
+                let new_bb = self.new_bb(fun);
+                self.terminate(old_bb, IrTerminator::Jmp(new_bb.into()), expr.span);
                 *self.bb_mut() = new_bb;
 
-                let after_bb = self.ctx.bb();
+                let after_bb = self.new_bb(fun);
                 let phi_var = self.ctx.vars.insert(IrVar {
                     ty: IrContext::INVALID,
                     name: Symbol::new(format!("@phi_var#{}", new_bb)),
+                    align: None,
+                    mutable: true,
                 });
                 self.ctx[old_bb].stmts.push(IrStmt {
                     span: expr.span,
@@ -809,8 +1185,10 @@ impl<'ctx> IrLowerer<'ctx> {
                     vars: HashMap::new(),
                     return_var: Some(phi_var),
                     after_bb,
+                    phi_param: false,
+                    loop_bb: None,
                 });
-                self.lower_block(module, file, fun, &b)?;
+                self.lower_block(module, file, fun, &b, expr.span)?;
                 self.scope_stack.pop();
                 IrExpr {
                     span: expr.span,
@@ -821,170 +1199,653 @@ impl<'ctx> IrLowerer<'ctx> {
         })
     }
 
-    /// Lower an if statement to IR, including new basic blocks and jumps
-    fn lower_if(
+    /// Lower an if statement to IR, including new basic blocks and jumps. Values produced by phi
+    /// statements in the branches are passed to a parameter of the block after the if, which has
+    /// the expected type if one is given
+    pub(super) fn lower_if(
         &mut self,
         module: IntermediateModuleId,
         file: FileId,
         fun: FunId,
         expr: &If,
+        expected: Option<TypeId>,
     ) -> Result<IrExpr, Diagnostic<FileId>> {
-        let old_bb = self.bb();
         let if_cond = self.lower_expr(module, file, fun, &expr.cond)?;
+        //The condition may contain control flow, so the jump is made from the block it ends in
+        let old_bb = self.bb();
+        if self.ctx.unwrap_alias(if_cond.ty) != IrContext::BOOL {
+            return Err(Diagnostic::error()
+                .with_message(format!(
+                    "Expected bool, found {}",
+                    self.ctx.typename(if_cond.ty)
+                ))
+                .with_labels(vec![Label::primary(file, if_cond.span).with_message(
+                    format!(
+                        "Condition of type {} appears here",
+                        self.ctx.typename(if_cond.ty)
+                    ),
+                )])
+                .with_notes(vec![
+                    "The condition of an if expression must be of type bool".to_owned(),
+                ]));
+        }
 
-        let if_body_bb = self.ctx.bb();
-        let after_bb = self.ctx.bb();
-        let phi_var = self.ctx.vars.insert(IrVar {
-            ty: IrContext::INVALID,
-            name: Symbol::new(format!("@phi_var#{}", if_body_bb)),
-        });
-        let bb = self.bb();
-        self.ctx[bb].stmts.push(IrStmt {
-            span: expr.cond.span,
-            kind: IrStmtKind::VarLive(phi_var),
-        });
+        let if_body_bb = self.new_bb(fun);
+        let after_bb = self.new_bb(fun);
+        if let Some(ty) = expected {
+            self.ctx[after_bb].params.push(ty);
+        }
 
         self.scope_stack.push(ScopePlate {
             vars: HashMap::new(),
-            return_var: Some(phi_var),
+            return_var: None,
             after_bb,
+            phi_param: true,
+            loop_bb: None,
         });
         *self.bb_mut() = if_body_bb;
-        self.lower_block(module, file, fun, &expr.body)?;
+        self.lower_block(module, file, fun, &expr.body, if_cond.span)?;
         self.scope_stack.pop();
-        match &expr.else_expr {
+        let else_bb = match &expr.else_expr {
             Some(ElseExpr::ElseIf(expr)) => {
-                let else_bb = self.ctx.bb();
-                self.scope_stack.push(ScopePlate {
-                    vars: HashMap::new(),
-                    return_var: Some(phi_var),
-                    after_bb,
-                });
+                let else_bb = self.new_bb(fun);
                 *self.bb_mut() = else_bb;
-                self.lower_if(module, file, fun, &expr)?;
-                self.scope_stack.pop();
-                self.ctx[old_bb].terminator = IrTerminator::JmpIf {
-                    condition: if_cond,
-                    if_true: if_body_bb,
-                    if_false: else_bb,
+                //Number literals in the nested if take the type of an earlier branch's value
+                let expected = match self.untyped_phis.contains_key(&after_bb) {
+                    true => None,
+                    false => self.ctx[after_bb].params.first().copied(),
+                };
+                let else_val = self.lower_if(module, file, fun, &expr, expected)?;
+
+                //The nested if leaves us in its own after block, which passes on its value
+                let else_after_bb = self.bb();
+                let args = match self.ctx[else_after_bb].params.is_empty() {
+                    true => vec![],
+                    false => {
+                        self.phi_param(module, file, fun, after_bb, &else_val)?;
+                        vec![else_val]
+                    }
+                };
+                let terminator = match self.unreachable_bb(else_bb, else_after_bb) {
+                    true => IrTerminator::Unreachable,
+                    false => IrTerminator::Jmp(IrBranch { bb: after_bb, args }),
                 };
+                self.terminate(else_after_bb, terminator, if_cond.span);
+                else_bb
             }
             Some(ElseExpr::Else(body)) => {
-                let else_bb = self.ctx.bb();
+                let else_bb = self.new_bb(fun);
                 self.scope_stack.push(ScopePlate {
                     vars: HashMap::new(),
-                    return_var: Some(phi_var),
+                    return_var: None,
                     after_bb,
+                    phi_param: true,
+                    loop_bb: None,
                 });
                 *self.bb_mut() = else_bb;
-                self.lower_block(module, file, fun, &body)?;
+                self.lower_block(module, file, fun, &body, if_cond.span)?;
                 self.scope_stack.pop();
-                self.ctx[old_bb].terminator = IrTerminator::JmpIf {
-                    condition: if_cond,
-                    if_true: if_body_bb,
-                    if_false: else_bb,
-                };
+                else_bb
             }
+            None => after_bb,
+        };
+        let span = if_cond.span;
+        self.terminate(
+            old_bb,
+            IrTerminator::JmpIf {
+                condition: if_cond,
+                if_true: if_body_bb.into(),
+                if_false: else_bb.into(),
+            },
+            span,
+        );
+
+        *self.bb_mut() = after_bb;
+        self.untyped_phis.remove(&after_bb);
+
+        let ty = match self.ctx[after_bb].params.first() {
+            Some(ty) => *ty,
             None => {
-                self.ctx[old_bb].terminator = IrTerminator::JmpIf {
-                    condition: if_cond,
-                    if_true: if_body_bb,
-                    if_false: after_bb,
-                };
+                return Ok(IrExpr {
+                    span: expr.cond.span,
+                    ty: IrContext::UNIT,
+                    kind: IrExprKind::Lit(IrLiteral::Unit),
+                })
             }
-        }
+        };
 
-        *self.bb_mut() = after_bb;
+        //Every block created while lowering the if was allocated after the block containing its
+        //condition, so they are the only blocks that can jump to the after block
+        let missing = self
+            .ctx
+            .bbs
+            .indices()
+            .skip(old_bb.val())
+            .flat_map(|bb| self.ctx[bb].terminator.branches())
+            .any(|branch| branch.bb == after_bb && branch.args.is_empty());
+        if missing {
+            return Err(Diagnostic::error()
+                .with_message("Not every branch of an if expression produces a value")
+                .with_labels(vec![Label::primary(file, expr.cond.span)
+                    .with_message(format!(
+                        "This if expression produces a value of type {}",
+                        self.ctx.typename(ty)
+                    ))])
+                .with_notes(vec![
+                    "Every branch of the if expression must end with a phi statement, and the expression must have an else block".to_owned(),
+                ]));
+        }
 
         Ok(IrExpr {
             span: expr.cond.span,
-            ty: self.ctx[phi_var].ty,
-            kind: IrExprKind::Var(phi_var),
+            ty,
+            kind: IrExprKind::Param(after_bb, 0),
         })
     }
 
-    /// Lower a match expression, returning an IrExpr representing a load of the phi allocation
-    fn lower_match(
+    /// Lower the value of a phi statement passing it to the block after an if expression.
+    /// Number literals with no type suffix take the type of the if's value if it is already
+    /// known, or are remembered so that they can take the type of a later branch's value
+    fn lower_phi_value(
+        &mut self,
+        module: IntermediateModuleId,
+        file: FileId,
+        fun: FunId,
+        after_bb: BBId,
+        val: &Expr,
+    ) -> Result<IrExpr, Diagnostic<FileId>> {
+        let untyped = Self::is_untyped_number(val, true);
+        let ty = self.ctx[after_bb].params.first().copied();
+        let lowered = match ty {
+            Some(ty) if untyped => self.lower_expr_as(module, file, fun, val, ty)?,
+            _ => self.lower_expr(module, file, fun, val)?,
+        };
+
+        if untyped && (ty.is_none() || self.untyped_phis.contains_key(&after_bb)) {
+            let current = self.bb();
+            self.untyped_phis
+                .entry(after_bb)
+                .or_default()
+                .push((current, val.clone()));
+        }
+        self.phi_param(module, file, fun, after_bb, &lowered)?;
+        Ok(lowered)
+    }
+
+    /// Add a parameter of the type of a phi statement's value to the block after an if
+    /// expression, or check that the value agrees with the parameter added by an earlier phi.
+    /// Earlier phis of only number literals with no type suffix are given the value's type
+    fn phi_param(
+        &mut self,
+        module: IntermediateModuleId,
+        file: FileId,
+        fun: FunId,
+        after_bb: BBId,
+        val: &IrExpr,
+    ) -> Result<(), Diagnostic<FileId>> {
+        match self.ctx[after_bb].params.first().copied() {
+            None => {
+                self.ctx[after_bb].params.push(val.ty);
+                Ok(())
+            }
+            Some(ty) if ty == val.ty => Ok(()),
+            Some(ty) => {
+                let phis = self.untyped_phis.remove(&after_bb).unwrap_or_default();
+                let mut retyped = Vec::with_capacity(phis.len());
+                for (pred, phi) in phis.iter() {
+                    let phi = self.lower_expr_as(module, file, fun, phi, val.ty)?;
+                    retyped.push((*pred, phi));
+                }
+
+                if retyped.is_empty() || retyped.iter().any(|(_, phi)| phi.ty != val.ty) {
+                    return Err(Diagnostic::error()
+                        .with_message(format!(
+                            "Phi statement returns expression of type {}, but type {} was expected",
+                            self.ctx.typename(val.ty),
+                            self.ctx.typename(ty),
+                        ))
+                        .with_labels(vec![Label::primary(file, val.span).with_message(format!(
+                            "Value of type {} returned here",
+                            self.ctx.typename(val.ty),
+                        ))]));
+                }
+
+                for (pred, phi) in retyped {
+                    for branch in self.ctx[pred].terminator.branches_mut() {
+                        if branch.bb == after_bb {
+                            branch.args = vec![phi.clone()];
+                        }
+                    }
+                }
+                self.ctx[after_bb].params[0] = val.ty;
+                Ok(())
+            }
+        }
+    }
+
+    /// Resolve the type or variant name of a match arm to the discriminant it tests for,
+    /// checking that the matched value can have that discriminant
+    fn lower_match_case(
+        &mut self,
+        module: IntermediateModuleId,
+        file: FileId,
+        matched: &IrExpr,
+        case: &UnresolvedType,
+        binds: bool,
+        arm_span: Span,
+        span: Span,
+    ) -> Result<DiscriminantId, Diagnostic<FileId>> {
+        //A lone name in an arm that binds nothing may have been meant as a binding in a wildcard
+        //arm, which has no type to name
+        let wildcard_note = |err: Diagnostic<FileId>| {
+            match case {
+            UnresolvedType::UserDefined { name } if name.len() == 1 && !binds => {
+                err.with_notes(vec![format!(
+                    "Wildcard arms have no type and cannot bind the matched value, so {} is read as the type of this arm",
+                    name
+                )])
+            }
+            _ => err,
+        }
+        };
+
+        match self.ctx[self.ctx.unwrap_alias(matched.ty)].clone() {
+            IrType::Sum(variants) => {
+                let ty = self
+                    .resolve_type(case, module, file, span)
+                    .map_err(wildcard_note)?;
+                if !variants.contains(&ty) {
+                    return Err(Diagnostic::error()
+                        .with_message(format!(
+                            "Match arm with type {} but sum type {} does not contain this variant",
+                            self.ctx.typename(ty),
+                            self.ctx.typename(matched.ty),
+                        ))
+                        .with_labels(vec![
+                            Label::primary(file, arm_span).with_message("In this match arm"),
+                            Label::primary(file, matched.span).with_message(format!(
+                                "Matched value of type {} appears here",
+                                self.ctx.typename(matched.ty)
+                            )),
+                        ]));
+                }
+                Ok(DiscriminantId::Type(ty))
+            }
+            IrType::Enum(e) => {
+                //Arms name a variant either by itself or qualified with the enum's name
+                let variant = match case {
+                    UnresolvedType::UserDefined { name } if name.len() == 1 => {
+                        e.variant_idx(&name.last())
+                    }
+                    UnresolvedType::UserDefined { name } => self
+                        .resolve_variant(module, name)
+                        .filter(|(ty, _)| {
+                            self.ctx.unwrap_alias(*ty) == self.ctx.unwrap_alias(matched.ty)
+                        })
+                        .map(|(_, idx)| idx),
+                    _ => None,
+                };
+
+                variant.map(DiscriminantId::Variant).ok_or_else(|| {
+                    wildcard_note(
+                        Diagnostic::error()
+                            .with_message(format!(
+                                "Match arm does not name a variant of enum {}",
+                                self.ctx.typename(matched.ty),
+                            ))
+                            .with_labels(vec![
+                                Label::primary(file, arm_span).with_message("In this match arm"),
+                                Label::primary(file, matched.span).with_message(format!(
+                                    "Matched value of type {} appears here",
+                                    self.ctx.typename(matched.ty)
+                                )),
+                            ]),
+                    )
+                })
+            }
+            _ => Err(Diagnostic::error()
+                .with_message(format!(
+                    "Cannot match an expression of type {}",
+                    self.ctx.typename(matched.ty)
+                ))
+                .with_labels(vec![
+                    Label::primary(file, matched.span).with_message(format!(
+                        "Expression of type {} appears here",
+                        self.ctx.typename(matched.ty)
+                    )),
+                    Label::secondary(file, span).with_message("Match expression appears here"),
+                ])),
+        }
+    }
+
+    /// Lower a match expression, returning an IrExpr representing the value passed to the block
+    /// after the match by the phi statements of its arms
+    pub(super) fn lower_match(
         &mut self,
         module: IntermediateModuleId,
         file: FileId,
         fun: FunId,
         expr: &Match,
         span: Span,
+        expected: Option<TypeId>,
     ) -> Result<IrExpr, Diagnostic<FileId>> {
+        let mut matched = self.lower_expr(module, file, fun, &expr.matched)?;
         let old_bb = self.bb();
-        let matched = self.lower_expr(module, file, fun, &expr.matched)?;
-        let after_bb = self.ctx.bb();
-        let phi_var = self.ctx.vars.insert(IrVar {
-            ty: IrContext::INVALID,
-            name: Symbol::new(format!("@phi_var#{}", old_bb)),
-        });
-        self.ctx[old_bb].stmts.push(IrStmt {
-            span,
-            kind: IrStmtKind::VarLive(phi_var),
-        });
+        let after_bb = self.new_bb(fun);
+        if let Some(ty) = expected {
+            self.ctx[after_bb].params.push(ty);
+        }
 
         self.scope_stack.push(ScopePlate {
             vars: HashMap::new(),
-            return_var: Some(phi_var),
+            return_var: None,
             after_bb,
+            phi_param: true,
+            loop_bb: None,
         });
-        let cases = expr
-            .cases
-            .iter()
-            .map(|(ty, stmt)| {
-                let ty = self.resolve_type(ty, module, file, span)?;
-                if let IrType::Sum(variants) = &self.ctx[self.ctx.unwrap_alias(matched.ty)] {
-                    if !variants.contains(&ty) {
-                        return Err(Diagnostic::error()
-                            .with_message(format!(
-                                "Match arm with type {} but sum type {} does not contain this variant",
-                                self.ctx.typename(ty),
-                                self.ctx.typename(matched.ty),
-                            ))
-                            .with_labels(vec![
-                                Label::primary(file, stmt.span)
-                                    .with_message("In this match arm"),
-                                Label::primary(file, matched.span)
-                                    .with_message(format!("Matched value of type {} appears here", self.ctx.typename(matched.ty)))
-                            ])
-                        )
+
+        //Arms that bind the matched value's payload read it again, so the matched expression is
+        //stored to be evaluated only once. The variable is declared in the match's scope so that
+        //it dies when the match ends or an arm leaves it
+        let binds = expr.cases.iter().any(|arm| arm.binding.is_some());
+        if binds && !matches!(matched.kind, IrExprKind::Var(_)) {
+            let name = Symbol::new(format!("@matched#{}", old_bb));
+            let matched_var = self.ctx.vars.insert(IrVar {
+                ty: matched.ty,
+                name,
+                align: None,
+                mutable: true,
+            });
+            self.current_scope_mut()
+                .vars
+                .insert(name, (matched_var, matched.span));
+            self.ctx[old_bb].stmts.push(IrStmt {
+                span: matched.span,
+                kind: IrStmtKind::VarLive(matched_var),
+            });
+            self.ctx[old_bb].stmts.push(IrStmt {
+                span: matched.span,
+                kind: IrStmtKind::Store {
+                    var: matched_var,
+                    val: matched.clone(),
+                },
+            });
+            matched = IrExpr {
+                span: matched.span,
+                ty: matched.ty,
+                kind: IrExprKind::Var(matched_var),
+            };
+        }
+
+        let mut cases = vec![];
+        //Arms that reach the end of their body without a phi statement jump to the block after
+        //the match without a value
+        let mut valueless_arms = vec![];
+        let mut wildcard = None;
+        //Span of the arm that tests for each discriminant, as a switch can't test one twice
+        let mut arm_spans = HashMap::new();
+        for arm in expr.cases.iter() {
+            let discriminant = match &arm.case {
+                Some(case) => Some(self.lower_match_case(
+                    module,
+                    file,
+                    &matched,
+                    case,
+                    arm.binding.is_some(),
+                    arm.body.span,
+                    span,
+                )?),
+                None => None,
+            };
+            if let Some(discriminant) = discriminant {
+                if let Some(prev) = arm_spans.insert(discriminant, arm.body.span) {
+                    let name = self.discriminant_name(&matched, discriminant);
+                    return Err(Diagnostic::error()
+                        .with_message(format!("Variant {} is matched more than once", name))
+                        .with_labels(vec![
+                            Label::primary(file, arm.body.span)
+                                .with_message(format!("{} matched again here", name)),
+                            Label::secondary(file, prev)
+                                .with_message(format!("{} first matched here", name)),
+                        ]));
+                }
+            }
+            let arm_bb = self.new_bb(fun);
+            *self.bb_mut() = arm_bb;
+            //Each arm gets its own scope so that sibling arms can declare the same names
+            self.scope_stack.push(ScopePlate {
+                vars: HashMap::new(),
+                return_var: None,
+                after_bb,
+                phi_param: true,
+                loop_bb: None,
+            });
+            if let (Some(discriminant), Some(binding)) = (&discriminant, arm.binding) {
+                self.bind_match_payload(
+                    file,
+                    &matched,
+                    discriminant,
+                    binding,
+                    arm.mutable,
+                    arm.body.span,
+                )?;
+            }
+            self.lower_stmt(module, file, fun, &arm.body)?;
+
+            //The arm may have already left its scope with a break, phi, or return statement
+            let current = self.bb();
+            if matches!(self.ctx[current].terminator, IrTerminator::Invalid) && current != after_bb
+            {
+                match self.unreachable_bb(arm_bb, current) {
+                    true => self.terminate(current, IrTerminator::Unreachable, arm.body.span),
+                    false => {
+                        self.end_lifetimes(fun, 1, None);
+                        self.terminate(current, IrTerminator::Jmp(after_bb.into()), arm.body.span);
+                        valueless_arms.push(arm.body.span);
                     }
-                } else {
+                }
+            }
+            self.scope_stack.pop();
+
+            match discriminant {
+                Some(discriminant) => cases.push((discriminant, arm_bb.into())),
+                None if wildcard.is_some() => {
                     return Err(Diagnostic::error()
-                        .with_message(format!("Cannot match an expression of type {}", self.ctx.typename(matched.ty)))
+                        .with_message("Match expression has more than one wildcard arm")
                         .with_labels(vec![
-                            Label::primary(file, matched.span)
-                                .with_message(format!("Expression of type {} appears here", self.ctx.typename(matched.ty))),
-                            Label::secondary(file, span)
-                                .with_message("Match expression appears here"),
-                        ])
-                    )
+                            Label::primary(file, arm.body.span)
+                                .with_message("Second wildcard arm appears here"),
+                            Label::secondary(file, span).with_message("In this match expression"),
+                        ]))
                 }
-                let arm_bb = self.ctx.bb();
-                *self.bb_mut() = arm_bb;
-                self.lower_stmt(module, file, fun, stmt)?;
-                if matches!(self.ctx[arm_bb].terminator, IrTerminator::Invalid) {
-                    self.ctx[arm_bb].terminator = IrTerminator::Jmp(after_bb);
+                None => wildcard = Some(arm_bb),
+            }
+        }
+
+        let default_jmp = match wildcard {
+            Some(wildcard) => wildcard,
+            None => {
+                let missing = self.missing_match_variants(&matched, &cases);
+                if !missing.is_empty() {
+                    return Err(Diagnostic::error()
+                        .with_message(format!(
+                            "Match expression does not cover every variant of {}",
+                            self.ctx.typename(matched.ty)
+                        ))
+                        .with_labels(vec![Label::primary(file, span).with_message(format!(
+                            "Variants {} are not matched",
+                            missing.join(", ")
+                        ))])
+                        .with_notes(vec![
+                            "Add an arm for each missing variant or a wildcard arm with no type"
+                                .to_owned(),
+                        ]));
                 }
-                Ok((ty, arm_bb))
-            })
-            .collect::<Result<Vec<_>, _>>()?;
 
-        self.ctx[old_bb].terminator = IrTerminator::JmpMatch {
-            variant: matched,
-            discriminants: cases,
-            default_jmp: after_bb,
+                //A match that covers every variant can never take the default jump
+                let unreachable_bb = self.new_bb(fun);
+                self.terminate(unreachable_bb, IrTerminator::Unreachable, span);
+                unreachable_bb
+            }
         };
+        let default_jmp = default_jmp.into();
+
+        self.terminate(
+            old_bb,
+            IrTerminator::JmpMatch {
+                variant: matched,
+                discriminants: cases,
+                default_jmp,
+            },
+            span,
+        );
+        *self.bb_mut() = after_bb;
+        self.end_lifetimes(fun, 1, None);
         self.scope_stack.pop();
+        self.untyped_phis.remove(&after_bb);
+
+        let ty = match self.ctx[after_bb].params.first() {
+            Some(ty) => *ty,
+            None => {
+                return Ok(IrExpr {
+                    span,
+                    ty: IrContext::UNIT,
+                    kind: IrExprKind::Lit(IrLiteral::Unit),
+                })
+            }
+        };
+
+        if !valueless_arms.is_empty() {
+            let mut labels = vec![Label::primary(file, span).with_message(format!(
+                "This match expression produces a value of type {}",
+                self.ctx.typename(ty)
+            ))];
+            labels.extend(valueless_arms.into_iter().map(|arm_span| {
+                Label::secondary(file, arm_span).with_message("This arm produces no value")
+            }));
+            return Err(Diagnostic::error()
+                .with_message("Not every arm of a match expression produces a value")
+                .with_labels(labels)
+                .with_notes(vec![
+                    "Every arm of the match expression must end with a phi statement".to_owned(),
+                ]));
+        }
 
         Ok(IrExpr {
             span,
-            ty: self.ctx[phi_var].ty,
-            kind: IrExprKind::Var(phi_var),
+            ty,
+            kind: IrExprKind::Param(after_bb, 0),
         })
     }
 
+    /// Declare a variable in the current scope holding the value carried by the variant that a
+    /// match arm tests for
+    fn bind_match_payload(
+        &mut self,
+        file: FileId,
+        matched: &IrExpr,
+        discriminant: &DiscriminantId,
+        binding: Symbol,
+        mutable: bool,
+        arm_span: Span,
+    ) -> Result<(), Diagnostic<FileId>> {
+        let (ty, kind) = match (discriminant, &self.ctx[self.ctx.unwrap_alias(matched.ty)]) {
+            (DiscriminantId::Type(ty), _) => {
+                let kind = IrExprKind::Cast(Box::new(matched.clone()), *ty, CastKind::SumUnwrap);
+                (*ty, kind)
+            }
+            (DiscriminantId::Variant(idx), IrType::Enum(e)) => match e.variants[*idx].payload {
+                Some(payload) => (payload, IrExprKind::Member(Box::new(matched.clone()), *idx)),
+                None => {
+                    return Err(Diagnostic::error()
+                        .with_message(format!(
+                            "Enum variant {} carries no value to bind to {}",
+                            e.variants[*idx].name, binding,
+                        ))
+                        .with_labels(vec![
+                            Label::primary(file, arm_span).with_message("In this match arm")
+                        ]))
+                }
+            },
+            (DiscriminantId::Variant(_), _) => unreachable!(),
+        };
+        let payload = IrExpr {
+            span: matched.span,
+            ty,
+            kind,
+        };
+
+        self.ensure_sized(ty, file, arm_span)?;
+        let var = self.ctx.vars.insert(IrVar {
+            ty,
+            name: binding,
+            align: None,
+            mutable,
+        });
+        self.declare_var(file, binding, arm_span, var)?;
+
+        let store = match self.is_aggregate(ty) && Self::is_place(&payload) {
+            true => IrStmtKind::Copy {
+                dst: self.place_addr(
+                    file,
+                    IrExpr {
+                        span: payload.span,
+                        ty,
+                        kind: IrExprKind::Var(var),
+                    },
+                )?,
+                src: self.place_addr(file, payload)?,
+                ty,
+            },
+            false => IrStmtKind::Store { var, val: payload },
+        };
+        let current = self.bb();
+        self.ctx[current].stmts.push(IrStmt {
+            span: arm_span,
+            kind: IrStmtKind::VarLive(var),
+        });
+        self.ctx[current].stmts.push(IrStmt {
+            span: arm_span,
+            kind: store,
+        });
+
+        Ok(())
+    }
+
+    /// Get the names of every variant of a matched value's type that no match arm tests for
+    fn missing_match_variants(
+        &self,
+        matched: &IrExpr,
+        cases: &[(DiscriminantId, IrBranch)],
+    ) -> Vec<String> {
+        let covered =
+            |discriminant: DiscriminantId| cases.iter().any(|(case, _)| *case == discriminant);
+        match &self.ctx[self.ctx.unwrap_alias(matched.ty)] {
+            IrType::Sum(variants) => variants
+                .iter()
+                .filter(|variant| !covered(DiscriminantId::Type(**variant)))
+                .map(|variant| self.ctx.typename(*variant).to_string())
+                .collect(),
+            IrType::Enum(e) => e
+                .variants
+                .iter()
+                .enumerate()
+                .filter(|(idx, _)| !covered(DiscriminantId::Variant(*idx)))
+                .map(|(_, variant)| variant.name.to_string())
+                .collect(),
+            _ => vec![],
+        }
+    }
+
+    /// Get the name of the variant of a matched value's type that a discriminant tests for
+    fn discriminant_name(&self, matched: &IrExpr, discriminant: DiscriminantId) -> String {
+        match (discriminant, &self.ctx[self.ctx.unwrap_alias(matched.ty)]) {
+            (DiscriminantId::Variant(idx), IrType::Enum(e)) => e.variants[idx].name.to_string(),
+            (DiscriminantId::Type(ty), _) => self.ctx.typename(ty).to_string(),
+            (DiscriminantId::Variant(idx), _) => idx.to_string(),
+        }
+    }
+
     /// Lower a loop statement or expression
     fn lower_loop(
         &mut self,
@@ -998,6 +1859,8 @@ impl<'ctx> IrLowerer<'ctx> {
         let phi_var = self.ctx.vars.insert(IrVar {
             ty: IrContext::INVALID,
             name: Symbol::new(format!("@phi_var#{}", old_bb)),
+            align: None,
+            mutable: true,
         });
         let bb = self.bb();
         self.ctx[bb].stmts.push(IrStmt {
@@ -1005,27 +1868,42 @@ impl<'ctx> IrLowerer<'ctx> {
             kind: IrStmtKind::VarLive(phi_var),
         });
 
-        let loop_bb = self.ctx.bb();
-        let after_bb = self.ctx.bb();
+        let loop_bb = self.new_bb(fun);
+        let after_bb = self.new_bb(fun);
 
         self.scope_stack.push(ScopePlate {
             vars: HashMap::new(),
             return_var: Some(phi_var),
             after_bb,
+            phi_param: false,
+            loop_bb: Some(loop_bb),
         });
         *self.bb_mut() = loop_bb;
 
-        for stmt in stmts {
-            self.lower_stmt(module, file, fun, stmt)?;
-        }
+        self.lower_stmts(module, file, fun, stmts)?;
 
-        if matches!(self.ctx[self.bb()].terminator, IrTerminator::Invalid) {
-            let bb = self.bb();
-            self.ctx[bb].terminator = IrTerminator::Jmp(loop_bb);
+        //A phi or return statement may have already left the loop
+        let bb = self.bb();
+        if matches!(self.ctx[bb].terminator, IrTerminator::Invalid) && bb != after_bb {
+            match self.unreachable_bb(loop_bb, bb) {
+                true => self.terminate(bb, IrTerminator::Unreachable, span),
+                false => {
+                    //Variables declared in the loop body die at the end of every iteration
+                    self.end_lifetimes(fun, 1, None);
+                    self.terminate(bb, IrTerminator::Jmp(loop_bb.into()), span);
+                }
+            }
         }
 
         self.scope_stack.pop();
-        self.ctx[old_bb].terminator = IrTerminator::Jmp(loop_bb);
+        self.terminate(old_bb, IrTerminator::Jmp(loop_bb.into()), span);
+        *self.bb_mut() = after_bb;
+
+        //Loops with no break values produce the unit value
+        self.loop_breaks.remove(&phi_var);
+        if self.ctx[phi_var].ty == IrContext::INVALID {
+            self.ctx[phi_var].ty = IrContext::UNIT;
+        }
 
         Ok(IrExpr {
             span,
@@ -1040,28 +1918,114 @@ impl<'ctx> IrLowerer<'ctx> {
         file: FileId,
         fun: FunId,
         stmts: &[Stmt],
+        span: Span,
     ) -> Result<(), Diagnostic<FileId>> {
-        for stmt in stmts.iter() {
-            self.lower_stmt(module, file, fun, stmt)?;
+        let start = self.bb();
+        self.lower_stmts(module, file, fun, stmts)?;
+
+        //A break or phi statement may have already left the scope
+        let current = self.bb();
+        if matches!(self.ctx[current].terminator, IrTerminator::Invalid)
+            && current != self.current_scope().after_bb
+        {
+            let span = stmts.last().map_or(span, |stmt| stmt.span);
+            match self.unreachable_bb(start, current) {
+                true => self.terminate(current, IrTerminator::Unreachable, span),
+                false => {
+                    self.end_lifetimes(fun, 1, None);
+                    let after_bb = self.current_scope().after_bb;
+                    self.terminate(current, IrTerminator::Jmp(after_bb.into()), span);
+                    *self.bb_mut() = after_bb;
+                }
+            }
         }
 
-        if matches!(self.ctx[self.bb()].terminator, IrTerminator::Invalid) {
-            let current = self.bb();
-            self.ctx[current].terminator = IrTerminator::Jmp(self.current_scope().after_bb);
-            *self.bb_mut() = self.current_scope().after_bb;
+        Ok(())
+    }
+
+    /// Lower a list of statements beginning in the current block, warning about and skipping
+    /// every statement after one that never completes, like a return or a loop without a break
+    fn lower_stmts(
+        &mut self,
+        module: IntermediateModuleId,
+        file: FileId,
+        fun: FunId,
+        stmts: &[Stmt],
+    ) -> Result<(), Diagnostic<FileId>> {
+        let start = self.bb();
+        for (i, stmt) in stmts.iter().enumerate() {
+            self.lower_stmt(module, file, fun, stmt)?;
+
+            let rest = &stmts[i + 1..];
+            if let (Some(first), Some(last)) = (rest.first(), rest.last()) {
+                if self.diverged(start) {
+                    if self.lints.contains(LintFlags::UNREACHABLE) {
+                        self.warnings.push(
+                            Diagnostic::warning()
+                                .with_message("Unreachable code")
+                                .with_labels(vec![
+                                    Label::primary(file, Span::new(first.span.from, last.span.to))
+                                        .with_message("These statements are never executed"),
+                                    Label::secondary(file, stmt.span).with_message(
+                                        "Control flow never continues past this statement",
+                                    ),
+                                ]),
+                        );
+                    }
+                    break;
+                }
+            }
         }
 
         Ok(())
     }
 
-    /// Ensure that the passed arguments to the given function are of the correct type
+    /// Check if statements after the current one can never be executed, when the statements
+    /// began in the given block. This is the case when the current block has been terminated,
+    /// when a phi statement has left the current scope, or when no block jumps to it
+    fn diverged(&self, start: BBId) -> bool {
+        let current = self.bb();
+        !matches!(self.ctx[current].terminator, IrTerminator::Invalid)
+            || (current != start && current == self.current_scope().after_bb)
+            || self.unreachable_bb(start, current)
+    }
+
+    /// Check if a block other than the first block of a list of statements has no block jumping
+    /// to it.
+    /// The blocks containing the statements before a nested scope only jump into it after the
+    /// scope is lowered, so the first block of every list being lowered is always reachable
+    fn unreachable_bb(&self, start: BBId, bb: BBId) -> bool {
+        bb != start && !self.jump_targets.contains(&bb)
+    }
+
+    /// Lower the arguments passed to a function of the given type, lowering each argument as a
+    /// value of its parameter's type if the parameter exists
+    pub(super) fn lower_args(
+        &mut self,
+        module: IntermediateModuleId,
+        file: FileId,
+        fun: FunId,
+        fun_ty: &FunType,
+        args: &[Expr],
+    ) -> Result<Vec<IrExpr>, Diagnostic<FileId>> {
+        args.iter()
+            .enumerate()
+            .map(|(i, arg)| match fun_ty.params.get(i) {
+                Some((param, _)) => self.lower_expr_as(module, file, fun, arg, *param),
+                None => self.lower_expr(module, file, fun, arg),
+            })
+            .collect()
+    }
+
+    /// Ensure that the passed arguments to the given function are of the correct type, returning
+    /// the arguments after implicit conversions to the parameter types
     fn typecheck_fun(
-        &self,
+        &mut self,
         file: FileId,
         span: Span,
         fun_ty: &FunType,
-        args: &[IrExpr],
-    ) -> Result<(), Diagnostic<FileId>> {
+        args: Vec<IrExpr>,
+    ) -> Result<Vec<IrExpr>, Diagnostic<FileId>> {
         if args.len() != fun_ty.params.len() {
             return Err(Diagnostic::error()
                 .with_message(format!(
@@ -1074,6 +2038,11 @@ impl<'ctx> IrLowerer<'ctx> {
                 ]));
         }
 
+        let args = args
+            .into_iter()
+            .zip(fun_ty.params.iter())
+            .map(|(arg, param)| self.coerce(file, arg, param.0))
+            .collect::<Result<Vec<_>, _>>()?;
         for (idx, (param, arg)) in fun_ty.params.iter().zip(args.iter()).enumerate() {
             if param.0 != arg.ty {
                 return Err(Diagnostic::error()
@@ -1093,44 +2062,3158 @@ impl<'ctx> IrLowerer<'ctx> {
             }
         }
 
-        Ok(())
+        Ok(args)
     }
 
-    /// Lookup a declared variable in the current scope stack
-    fn lookup_var(&self, var: &Symbol) -> Option<VarId> {
-        for plate in self.scope_stack.iter().rev() {
-            match plate.vars.get(var) {
-                Some(var) => return Some(*var),
-                None => continue,
-            }
-        }
+    /// Implicitly convert a value to the given type. Where a slice is expected, arrays decay to a
+    /// slice of their elements with the array's length and string literals become a slice of
+    /// their bytes. Where a pointer is expected, arrays decay to a pointer to their first element
+    /// like string literals, which are pointers to their first byte. Where a sum type is expected
+    /// values become the variant of their type. Other values are returned unchanged
+    pub(super) fn coerce(
+        &mut self,
+        file: FileId,
+        expr: IrExpr,
+        to: TypeId,
+    ) -> Result<IrExpr, Diagnostic<FileId>> {
+        let element = match &self.ctx[self.ctx.unwrap_alias(to)] {
+            IrType::Slice(element) => *element,
+            IrType::Ptr(pointee) => {
+                let pointee = *pointee;
+                return match &self.ctx[self.ctx.unwrap_alias(expr.ty)] {
+                    IrType::Array(element, _) if *element == pointee => {
+                        self.decay_array(file, expr, pointee)
+                    }
+                    _ => Ok(expr),
+                };
+            }
+            IrType::Sum(variants) => {
+                let variants = variants.clone();
+                return self.upcast_sum(file, expr, to, &variants);
+            }
+            _ => return Ok(expr),
+        };
+
+        let (ptr, len) = match (&self.ctx[self.ctx.unwrap_alias(expr.ty)], &expr.kind) {
+            (IrType::Array(array_element, len), _) if *array_element == element => {
+                let len = *len;
+                (self.decay_array(file, expr, element)?, len)
+            }
+            (_, IrExprKind::Lit(IrLiteral::String(s))) if element == IrContext::U8 => {
+                let len = s.len() as u64;
+                (expr, len)
+            }
+            _ => return Ok(expr),
+        };
+
+        let span = ptr.span;
+        Ok(IrExpr {
+            span,
+            ty: to,
+            kind: IrExprKind::Slice(
+                Box::new(ptr),
+                Box::new(IrExpr {
+                    span,
+                    ty: IrContext::USIZE,
+                    kind: IrExprKind::Lit(IrLiteral::Integer(
+                        BigInt {
+                            val: len as u128,
+                            sign: false,
+                        },
+                        IrIntegerType {
+                            width: IntegerWidth::PtrSize,
+                            signed: false,
+                        },
+                    )),
+                }),
+            ),
+        })
+    }
+
+    /// Lower the fields of a structure literal. When the structure type of the literal is known,
+    /// every field of the type must be assigned exactly once with a value of the field's type and
+    /// the lowered fields are ordered like the fields of the type
+    fn lower_struct_fields(
+        &mut self,
+        module: IntermediateModuleId,
+        file: FileId,
+        fun: FunId,
+        span: Span,
+        struct_ty: Option<(TypeId, &IrStructType)>,
+        fields: &[(Symbol, Expr)],
+    ) -> Result<Vec<(Symbol, IrExpr)>, Diagnostic<FileId>> {
+        let mut assigned = HashMap::<Symbol, Span>::new();
+        for (name, field) in fields.iter() {
+            if let Some(first) = assigned.insert(*name, field.span) {
+                return Err(Diagnostic::error()
+                    .with_message(format!(
+                        "Structure literal assigns a value to field {} more than once",
+                        name
+                    ))
+                    .with_labels(vec![
+                        Label::primary(file, field.span)
+                            .with_message(format!("Field {} assigned again here", name)),
+                        Label::secondary(file, first)
+                            .with_message(format!("Field {} first assigned here", name)),
+                    ]));
+            }
+        }
+
+        let (ty, struct_ty) = match struct_ty {
+            Some(struct_ty) => struct_ty,
+            None => {
+                return fields
+                    .iter()
+                    .map(|(name, field)| Ok((*name, self.lower_expr(module, file, fun, field)?)))
+                    .collect()
+            }
+        };
+
+        let mut lowered = Vec::with_capacity(fields.len());
+        for (name, field) in fields.iter() {
+            let field_ty = match struct_ty.field_ty(name) {
+                Some(field_ty) => field_ty,
+                None => {
+                    let mut notes = vec![];
+                    let similar = struct_ty
+                        .fields
+                        .iter()
+                        .map(|declared| declared.name)
+                        .filter(|declared| !assigned.contains_key(declared))
+                        .filter(|declared| edit_distance(declared, name) <= (name.len() / 3).max(1))
+                        .min_by_key(|declared| edit_distance(declared, name));
+                    if let Some(similar) = similar {
+                        notes.push(format!("Did you mean {}?", similar));
+                    }
+
+                    return Err(Diagnostic::error()
+                        .with_message(format!(
+                            "Structure literal assigns a value for field named {}, but structure type {} contains no such field",
+                            name,
+                            self.ctx.typename(ty)
+                        ))
+                        .with_labels(vec![Label::primary(file, field.span)
+                            .with_message(format!("Field {} assigned here", name))])
+                        .with_notes(notes));
+                }
+            };
+
+            let value = self.lower_expr_as(module, file, fun, field, field_ty)?;
+            let value = self.coerce(file, value, field_ty)?;
+            if value.ty != field_ty {
+                return Err(Diagnostic::error()
+                    .with_message(format!(
+                        "Field {} of structure type {} has type {}, but a value of type {} was assigned",
+                        name,
+                        self.ctx.typename(ty),
+                        self.ctx.typename(field_ty),
+                        self.ctx.typename(value.ty),
+                    ))
+                    .with_labels(vec![
+                        Label::primary(file, value.span).with_message(format!(
+                            "Value of type {} assigned here",
+                            self.ctx.typename(value.ty)
+                        )),
+                        Label::secondary(file, span).with_message("Structure literal appears here"),
+                    ]));
+            }
+            lowered.push((*name, value));
+        }
+
+        let missing = struct_ty
+            .fields
+            .iter()
+            .filter(|field| !assigned.contains_key(&field.name))
+            .map(|field| field.name.to_string())
+            .collect::<Vec<_>>();
+        if !missing.is_empty() {
+            return Err(Diagnostic::error()
+                .with_message(format!(
+                    "Structure literal of type {} is missing fields {}",
+                    self.ctx.typename(ty),
+                    missing.join(", ")
+                ))
+                .with_labels(vec![
+                    Label::primary(file, span).with_message("Structure literal appears here")
+                ])
+                .with_notes(vec![
+                    "Every field of a structure must be assigned a value in a structure literal"
+                        .to_owned(),
+                ]));
+        }
+
+        lowered.sort_by_key(|(name, _)| struct_ty.field_idx(name));
+        Ok(lowered)
+    }
+
+    /// Get a pointer to the first element of an array, storing the array in a temporary variable
+    /// first if it is not a place
+    fn decay_array(
+        &mut self,
+        file: FileId,
+        array: IrExpr,
+        element: TypeId,
+    ) -> Result<IrExpr, Diagnostic<FileId>> {
+        let array = match Self::is_place(&array) {
+            true => array,
+            false => self.materialize(file, array)?,
+        };
+        //The elements of the array could be modified through the pointer
+        self.check_addressable(file, &array)?;
+        let element_ptr = self.ctx.ptr_to(element);
+        let addr = self.place_addr(file, array)?;
+        Ok(IrExpr {
+            span: addr.span,
+            ty: element_ptr,
+            kind: IrExprKind::Cast(Box::new(addr), element_ptr, CastKind::PtrToPtr),
+        })
+    }
+
+    /// Wrap a value in a sum type as the variant of the value's type, or as the only variant of
+    /// an integer type that the value's integer type widens to. Values that no variant can hold
+    /// are returned unchanged, and values that more than one variant can hold are an error
+    fn upcast_sum(
+        &self,
+        file: FileId,
+        expr: IrExpr,
+        sum: TypeId,
+        variants: &[TypeId],
+    ) -> Result<IrExpr, Diagnostic<FileId>> {
+        let from = self.ctx.unwrap_alias(expr.ty);
+        if from == self.ctx.unwrap_alias(sum) {
+            return Ok(expr);
+        }
+
+        //A type and its aliases are different variants of the same type
+        let mut kind = CastKind::Identity;
+        let mut candidates = variants
+            .iter()
+            .copied()
+            .filter(|variant| self.ctx.unwrap_alias(*variant) == from)
+            .collect::<Vec<_>>();
+        if let (true, IrType::Integer(from_int)) = (candidates.is_empty(), &self.ctx[from]) {
+            kind = CastKind::IntResize;
+            candidates = variants
+                .iter()
+                .copied()
+                .filter(|variant| match &self.ctx[self.ctx.unwrap_alias(*variant)] {
+                    IrType::Integer(to_int) => Self::int_widens(*from_int, *to_int),
+                    _ => false,
+                })
+                .collect();
+        }
+
+        let variant = match candidates.as_slice() {
+            [] => return Ok(expr),
+            [variant] => *variant,
+            _ => {
+                return Err(Diagnostic::error()
+                    .with_message(format!(
+                        "Value of type {} could be more than one variant of sum type {}",
+                        self.ctx.typename(expr.ty),
+                        self.ctx.typename(sum),
+                    ))
+                    .with_labels(vec![Label::primary(file, expr.span).with_message(format!(
+                        "Value of type {} appears here",
+                        self.ctx.typename(expr.ty)
+                    ))])
+                    .with_notes(vec![
+                        format!(
+                            "Variants {} can all hold the value",
+                            candidates
+                                .iter()
+                                .map(|variant| self.ctx.typename(*variant).to_string())
+                                .collect::<Vec<_>>()
+                                .join(", ")
+                        ),
+                        "Cast the value to one of the variants to choose which variant it becomes"
+                            .to_owned(),
+                    ]))
+            }
+        };
+
+        let span = expr.span;
+        let expr = match variant == expr.ty {
+            true => expr,
+            false => self.fold(
+                file,
+                IrExpr {
+                    span,
+                    ty: variant,
+                    kind: IrExprKind::Cast(Box::new(expr), variant, kind),
+                },
+            )?,
+        };
+        Ok(IrExpr {
+            span,
+            ty: sum,
+            kind: IrExprKind::Cast(Box::new(expr), sum, CastKind::SumWrap),
+        })
+    }
+
+    /// Get an expression evaluating to the address of a place expression that is assigned to
+    pub(super) fn place_addr(
+        &mut self,
+        file: FileId,
+        place: IrExpr,
+    ) -> Result<IrExpr, Diagnostic<FileId>> {
+        if !Self::is_place(&place) {
+            return Err(Diagnostic::error()
+                .with_message(
+                    "Only variables, fields, array elements, and dereferenced pointers can be assigned to",
+                )
+                .with_labels(vec![Label::primary(file, place.span)
+                    .with_message("Assigned expression appears here")]));
+        }
+
+        Ok(match place.kind {
+            IrExprKind::Unary(Op::Star, ptr) => *ptr,
+            _ => IrExpr {
+                span: place.span,
+                ty: self.ctx.ptr_to(place.ty),
+                kind: IrExprKind::Unary(Op::AND, Box::new(place)),
+            },
+        })
+    }
+
+    /// Check if an expression refers to a location in memory that has an address
+    pub(super) fn is_place(expr: &IrExpr) -> bool {
+        matches!(
+            expr.kind,
+            IrExprKind::Unary(Op::Star, _)
+                | IrExprKind::Var(_)
+                | IrExprKind::Global(_)
+                | IrExprKind::Member(..)
+                | IrExprKind::Index(..)
+        )
+    }
+
+    /// Check if values of a type are structures, tuples, arrays, sum types, or enums carrying
+    /// values that are copied in memory
+    fn is_aggregate(&self, ty: TypeId) -> bool {
+        match &self.ctx[self.ctx.unwrap_alias(ty)] {
+            IrType::Struct(_) | IrType::Array(..) | IrType::Sum(_) | IrType::Tuple(_) => true,
+            IrType::Enum(e) => e.has_payload(),
+            _ => false,
+        }
+    }
+
+    /// End the lifetimes of the variables declared in the innermost `count` scopes by appending
+    /// [VarDead](IrStmtKind::VarDead) statements to the current block, most recently declared
+    /// first. Function parameters and variables read by `keep` are left alive
+    fn end_lifetimes(&mut self, fun: FunId, count: usize, keep: Option<&IrExpr>) {
+        let mut kept = HashSet::new();
+        if let Some(keep) = keep {
+            keep.walk(&mut |expr| {
+                if let IrExprKind::Var(var) = &expr.kind {
+                    kept.insert(*var);
+                }
+            });
+        }
+        if let Some(body) = self.ctx.fun_body(fun) {
+            kept.extend(body.args.iter().flatten().copied());
+        }
+
+        let mut dead = self
+            .scope_stack
+            .iter()
+            .rev()
+            .take(count)
+            .flat_map(|plate| plate.vars.values().copied())
+            .filter(|(var, _)| !kept.contains(var))
+            .collect::<Vec<_>>();
+        dead.sort_by_key(|(var, _)| std::cmp::Reverse(var.val()));
+
+        let current = self.bb();
+        for (var, span) in dead {
+            self.ctx[current].stmts.push(IrStmt {
+                span,
+                kind: IrStmtKind::VarDead(var),
+            });
+        }
+    }
+
+    /// Lower a let statement with an assigned value. Let statements with a variable name declare
+    /// a new variable unless `declares` is false, in which case the value is assigned to a
+    /// previously declared variable or global of that name
+    fn lower_assignment(
+        &mut self,
+        module: IntermediateModuleId,
+        file: FileId,
+        fun: FunId,
+        let_stmt: &Let,
+        assigned: &Expr,
+        declares: bool,
+    ) -> Result<(), Diagnostic<FileId>> {
+        //Number literals take the type of the variable or place they are assigned to
+        let mut place = None;
+        let expected = match (&let_stmt.let_expr.node, let_stmt.ty.as_ref()) {
+            (ExprNode::Access(_), Some(ty)) => {
+                Some(self.resolve_type(ty, module, file, let_stmt.let_expr.span)?)
+            }
+            (ExprNode::Access(_), None) if declares => None,
+            (ExprNode::Access(name), None) => match self.lookup_var(&name.last()) {
+                Some(var) => Some(self.ctx[var].ty),
+                None => self
+                    .lookup_global(module, name)
+                    .map(|glob| self.ctx[glob].ty),
+            },
+            //Literals have no side effects, so the place can be lowered before them
+            _ if Self::is_untyped_number(assigned, true) => {
+                let lowered = self.lower_expr(module, file, fun, &let_stmt.let_expr)?;
+                let ty = lowered.ty;
+                place = Some(lowered);
+                Some(ty)
+            }
+            _ => None,
+        };
+        let assigned = match expected {
+            Some(ty) => self.lower_expr_as(module, file, fun, assigned, ty)?,
+            None => self.lower_expr(module, file, fun, assigned)?,
+        };
+        //Name and declaration of the variable assigned to, if it was declared earlier
+        let mut declared = None;
+        let (ty, ptr) = match &let_stmt.let_expr.node {
+            ExprNode::Access(name) => {
+                //A let statement always declares a new variable, shadowing any variable or
+                //global of the same name
+                let existing = match declares {
+                    true => None,
+                    false => self.lookup_var_decl(&name.last()),
+                };
+                let global = match (existing, declares) {
+                    (None, false) => self.lookup_global(module, name),
+                    _ => None,
+                };
+                let var_place = |var, ty| IrExpr {
+                    span: let_stmt.let_expr.span,
+                    ty,
+                    kind: IrExprKind::Var(var),
+                };
+                match (existing, global) {
+                    (None, Some(glob)) => {
+                        let place = IrExpr {
+                            span: let_stmt.let_expr.span,
+                            ty: self.ctx[glob].ty,
+                            kind: IrExprKind::Global(glob),
+                        };
+                        self.check_assignable(file, &place)?;
+                        (place.ty, place)
+                    }
+                    (Some((var, decl_span)), _) => {
+                        declared = Some((name.last(), decl_span));
+                        let ty = self.ctx[var].ty;
+                        let place = var_place(var, ty);
+                        self.check_assignable(file, &place)?;
+                        (ty, place)
+                    }
+                    (None, None) if !declares => {
+                        return Err(Diagnostic::error()
+                            .with_message(format!("Cannot assign to undeclared variable {}", name))
+                            .with_labels(vec![Label::primary(file, let_stmt.let_expr.span)
+                                .with_message("Assignment appears here")])
+                            .with_notes(vec![format!(
+                                "Declare the variable with let {} = ...",
+                                name
+                            )]))
+                    }
+                    (None, None) => {
+                        let ty = match expected {
+                            Some(ty) => ty,
+                            None => self.infer_var_type(file, name.last(), &assigned)?,
+                        };
+
+                        let var_id = self.lower_var_decl(file, let_stmt, name.last(), ty)?;
+                        (ty, var_place(var_id, ty))
+                    }
+                }
+            }
+            _ => {
+                let let_expr = match place.take() {
+                    Some(place) => place,
+                    None => self.lower_expr(module, file, fun, &let_stmt.let_expr)?,
+                };
+                self.check_assignable(file, &let_expr)?;
+                (assigned.ty, let_expr)
+            }
+        };
+
+        let assigned = self.coerce(file, assigned, ty)?;
+        let assigned_span = assigned.span;
+        if ty != assigned.ty {
+            let mut labels = vec![
+                Label::primary(file, assigned.span).with_message(format!(
+                    "Assigned value of type {} appears here",
+                    self.ctx.typename(assigned.ty)
+                )),
+                Label::secondary(file, ptr.span).with_message(format!(
+                    "Assignee of type {} appears here",
+                    self.ctx.typename(ty)
+                )),
+            ];
+            if let Some((name, decl_span)) = declared {
+                labels.push(Label::secondary(file, decl_span).with_message(format!(
+                    "Variable {} declared with type {} here",
+                    name,
+                    self.ctx.typename(ty)
+                )));
+            }
+            return Err(Diagnostic::error()
+                .with_message(format!(
+                    "Assigning a value of type {} to a value of incompatible type {}",
+                    self.ctx.typename(assigned.ty),
+                    self.ctx.typename(ty)
+                ))
+                .with_labels(labels));
+        }
+        let kind = match ptr.kind {
+            //Aggregates are copied directly between places instead of being loaded
+            _ if self.is_aggregate(ty) && Self::is_place(&assigned) => IrStmtKind::Copy {
+                dst: self.place_addr(file, ptr)?,
+                src: self.place_addr(file, assigned)?,
+                ty,
+            },
+            IrExprKind::Var(var) => IrStmtKind::Store { var, val: assigned },
+            _ => IrStmtKind::StoreMem {
+                addr: self.place_addr(file, ptr)?,
+                val: assigned,
+            },
+        };
+        let current = self.bb();
+        self.ctx[current].stmts.push(IrStmt {
+            span: (let_stmt.let_expr.span.from..assigned_span.to).into(),
+            kind,
+        });
+
+        Ok(())
+    }
+
+    /// Lower a let statement that declares a variable for every element of a tuple, like
+    /// `let (a, b) = pair`. The tuple is stored in a temporary if it isn't already a place so that
+    /// it is evaluated only once
+    fn lower_destructure(
+        &mut self,
+        module: IntermediateModuleId,
+        file: FileId,
+        fun: FunId,
+        let_stmt: &Let,
+        names: &[Expr],
+        assigned: &Expr,
+    ) -> Result<(), Diagnostic<FileId>> {
+        let names = names
+            .iter()
+            .map(|name| match &name.node {
+                ExprNode::Access(path) if path.len() == 1 => Ok((path.last(), name)),
+                _ => Err(Diagnostic::error()
+                    .with_message("Only variable names can be declared by destructuring a tuple")
+                    .with_labels(vec![Label::primary(file, name.span)
+                        .with_message("Expected a variable name here")])),
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let tuple = match let_stmt.ty.as_ref() {
+            Some(ty) => {
+                let ty = self.resolve_type(ty, module, file, let_stmt.let_expr.span)?;
+                let tuple = self.lower_expr_as(module, file, fun, assigned, ty)?;
+                self.coerce(file, tuple, ty)?
+            }
+            None => self.lower_expr(module, file, fun, assigned)?,
+        };
+        let elements = match &self.ctx[self.ctx.unwrap_alias(tuple.ty)] {
+            IrType::Tuple(elements) if elements.len() == names.len() => elements.clone(),
+            _ => {
+                return Err(Diagnostic::error()
+                    .with_message(format!(
+                        "Cannot destructure a value of type {} into {} variables",
+                        self.ctx.typename(tuple.ty),
+                        names.len()
+                    ))
+                    .with_labels(vec![
+                        Label::primary(file, tuple.span).with_message(format!(
+                            "Value of type {} appears here",
+                            self.ctx.typename(tuple.ty)
+                        )),
+                        Label::secondary(file, let_stmt.let_expr.span)
+                            .with_message(format!("{} variables declared here", names.len())),
+                    ])
+                    .with_notes(vec![
+                        "Only a tuple with one element for every variable can be destructured"
+                            .to_owned(),
+                    ]))
+            }
+        };
+
+        let tuple = match tuple.kind {
+            IrExprKind::Var(_) => tuple,
+            _ => {
+                let current = self.bb();
+                let name = Symbol::new(format!(
+                    "@tuple#{}.{}",
+                    current,
+                    self.ctx[current].stmts.len()
+                ));
+                let var = self.ctx.vars.insert(IrVar {
+                    ty: tuple.ty,
+                    name,
+                    align: None,
+                    mutable: true,
+                });
+                self.current_scope_mut()
+                    .vars
+                    .insert(name, (var, tuple.span));
+                self.ctx[current].stmts.push(IrStmt {
+                    span: tuple.span,
+                    kind: IrStmtKind::VarLive(var),
+                });
+                self.ctx[current].stmts.push(IrStmt {
+                    span: tuple.span,
+                    kind: IrStmtKind::Store {
+                        var,
+                        val: tuple.clone(),
+                    },
+                });
+                IrExpr {
+                    span: tuple.span,
+                    ty: tuple.ty,
+                    kind: IrExprKind::Var(var),
+                }
+            }
+        };
+
+        for (idx, ((name, name_expr), ty)) in names.into_iter().zip(elements).enumerate() {
+            let element_let = Let {
+                mutable: let_stmt.mutable,
+                ty: None,
+                let_expr: Box::new(name_expr.clone()),
+                assigned: None,
+                align: let_stmt.align,
+            };
+            let var = self.lower_var_decl(file, &element_let, name, ty)?;
+            let element = IrExpr {
+                span: name_expr.span,
+                ty,
+                kind: IrExprKind::Member(Box::new(tuple.clone()), idx),
+            };
+            let kind = match self.is_aggregate(ty) {
+                true => IrStmtKind::Copy {
+                    dst: self.place_addr(
+                        file,
+                        IrExpr {
+                            span: name_expr.span,
+                            ty,
+                            kind: IrExprKind::Var(var),
+                        },
+                    )?,
+                    src: self.place_addr(file, element)?,
+                    ty,
+                },
+                false => IrStmtKind::Store { var, val: element },
+            };
+            let current = self.bb();
+            self.ctx[current].stmts.push(IrStmt {
+                span: name_expr.span,
+                kind,
+            });
+        }
+
+        Ok(())
+    }
+
+    /// Declare a new variable in the current scope from a let statement, without initializing it
+    fn lower_var_decl(
+        &mut self,
+        file: FileId,
+        let_stmt: &Let,
+        name: Symbol,
+        ty: TypeId,
+    ) -> Result<VarId, Diagnostic<FileId>> {
+        let align = self.var_align(file, let_stmt.let_expr.span, let_stmt.align)?;
+        self.ensure_sized(ty, file, let_stmt.let_expr.span)?;
+        let var_id = self.ctx.vars.insert(IrVar {
+            ty,
+            name,
+            align,
+            mutable: let_stmt.mutable,
+        });
+        self.declare_var(file, name, let_stmt.let_expr.span, var_id)?;
+
+        let current = self.bb();
+        self.ctx[current].stmts.push(IrStmt {
+            span: let_stmt.let_expr.span,
+            kind: IrStmtKind::VarLive(var_id),
+        });
+
+        Ok(var_id)
+    }
+
+    /// Get the type of a variable declared without a type annotation from the type of its
+    /// initializer. Integer literals with no suffix are `i32` and float literals are `f32`, so a
+    /// variable initialized with one has that type
+    fn infer_var_type(
+        &self,
+        file: FileId,
+        name: Symbol,
+        initializer: &IrExpr,
+    ) -> Result<TypeId, Diagnostic<FileId>> {
+        match &self.ctx[self.ctx.unwrap_alias(initializer.ty)] {
+            IrType::Unit | IrType::Invalid => Err(Diagnostic::error()
+                .with_message(format!(
+                    "Cannot infer the type of variable {} from an initializer of type {}",
+                    name,
+                    self.ctx.typename(initializer.ty)
+                ))
+                .with_labels(vec![
+                    Label::primary(file, initializer.span).with_message("Initializer appears here")
+                ])
+                .with_notes(vec![format!(
+                    "Annotate the variable with a type: let [type] {} = ...",
+                    name
+                )])),
+            _ => Ok(initializer.ty),
+        }
+    }
+
+    /// Check that a user-requested variable alignment is a power of two
+    fn var_align(
+        &self,
+        file: FileId,
+        span: Span,
+        align: Option<u64>,
+    ) -> Result<Option<u32>, Diagnostic<FileId>> {
+        match align {
+            Some(align) if !align.is_power_of_two() || align > u32::MAX as u64 => {
+                Err(Diagnostic::error()
+                    .with_message(format!(
+                        "Variable alignment of {} bytes is not a power of two",
+                        align
+                    ))
+                    .with_labels(vec![
+                        Label::primary(file, span).with_message("Aligned variable declared here")
+                    ]))
+            }
+            other => Ok(other.map(|align| align as u32)),
+        }
+    }
+
+    /// Add a variable to the current scope, checking for redeclarations and warning if the
+    /// variable shadows one declared in an enclosing scope
+    fn declare_var(
+        &mut self,
+        file: FileId,
+        name: Symbol,
+        span: Span,
+        var: VarId,
+    ) -> Result<(), Diagnostic<FileId>> {
+        if let Some((_, prev)) = self.current_scope().vars.get(&name) {
+            return Err(Diagnostic::error()
+                .with_message(format!(
+                    "Variable {} is declared more than once in the same scope",
+                    name
+                ))
+                .with_labels(vec![
+                    Label::primary(file, span).with_message("Variable redeclared here"),
+                    Label::secondary(file, *prev).with_message("Previous declaration here"),
+                ]));
+        }
+
+        if self.lints.contains(LintFlags::SHADOW) {
+            if let Some((_, prev)) = self.lookup_var_decl(&name) {
+                self.warnings.push(
+                    Diagnostic::warning()
+                        .with_message(format!(
+                            "Declaration of variable {} shadows a variable in an enclosing scope",
+                            name
+                        ))
+                        .with_labels(vec![
+                            Label::primary(file, span).with_message("Shadowing declaration here"),
+                            Label::secondary(file, prev)
+                                .with_message("Shadowed variable declared here"),
+                        ]),
+                );
+            }
+        }
+
+        self.current_scope_mut().vars.insert(name, (var, span));
+        self.declared_vars.insert(var, span);
+        Ok(())
+    }
+
+    /// Lookup a declared variable in the current scope stack
+    pub(super) fn lookup_var(&self, var: &Symbol) -> Option<VarId> {
+        self.lookup_var_decl(var).map(|(var, _)| var)
+    }
+
+    /// Lookup a declared variable and the span of its declaration in the current scope stack
+    fn lookup_var_decl(&self, var: &Symbol) -> Option<(VarId, Span)> {
+        for plate in self.scope_stack.iter().rev() {
+            match plate.vars.get(var) {
+                Some(var) => return Some(*var),
+                None => continue,
+            }
+        }
+
+        None
+    }
+
+    /// Lookup a global variable by path when no local variable shadows it
+    fn lookup_global(&self, module: IntermediateModuleId, path: &SymbolPath) -> Option<GlobalId> {
+        if path.len() == 1 && self.lookup_var(&path.last()).is_some() {
+            return None;
+        }
+
+        match self.resolve_path(module, path) {
+            Some(IntermediateDefId::Global(glob, ..)) => Some(glob),
+            _ => None,
+        }
+    }
+
+    /// Get the variable or global that a place is stored in, looking through fields and array
+    /// elements of the place. Elements of a slice are stored behind its pointer, so a slice
+    /// element is its own root
+    fn place_root<'e>(&self, mut place: &'e IrExpr) -> &'e IrExpr {
+        loop {
+            place = match &place.kind {
+                IrExprKind::Member(object, _) | IrExprKind::Cast(object, ..) => object,
+                IrExprKind::Index(array, _)
+                    if matches!(self.ctx[self.ctx.unwrap_alias(array.ty)], IrType::Array(..)) =>
+                {
+                    array
+                }
+                _ => return place,
+            };
+        }
+    }
+
+    /// Check that a place may be assigned to, failing if the place is an immutable variable or
+    /// global or a field or array element stored in one
+    fn check_assignable(&self, file: FileId, place: &IrExpr) -> Result<(), Diagnostic<FileId>> {
+        let glob = match self.place_root(place).kind {
+            //Immutable variables declared without an initializer are assigned their value later,
+            //which is checked to happen only once by check_init
+            IrExprKind::Var(var)
+                if self.deferred_vars.contains_key(&var)
+                    && matches!(place.kind, IrExprKind::Var(_)) =>
+            {
+                return Ok(())
+            }
+            IrExprKind::Var(var) if !self.ctx[var].mutable => {
+                return Err(self.immutable_var(
+                    file,
+                    place.span,
+                    var,
+                    "assign to",
+                    "Assignment appears here",
+                ))
+            }
+            IrExprKind::Global(glob) if !self.ctx[glob].mutable => glob,
+            _ => return Ok(()),
+        };
+
+        let (kind, note) = match self.const_globals.contains(&glob) {
+            true => (
+                "constant",
+                "Constants are evaluated at compile time and can't be assigned to".to_owned(),
+            ),
+            false => (
+                "immutable global",
+                format!(
+                    "Declare the global with glob mut {} to allow assigning to it",
+                    self.ctx[glob].name
+                ),
+            ),
+        };
+        Err(Diagnostic::error()
+            .with_message(format!("Cannot assign to {} {}", kind, self.ctx[glob].name))
+            .with_labels(vec![
+                Label::primary(file, place.span).with_message("Assignment appears here"),
+                Label::secondary(self.ctx[glob].file, self.ctx[glob].span)
+                    .with_message(format!("{} {} defined here", kind, self.ctx[glob].name)),
+            ])
+            .with_notes(vec![note]))
+    }
+
+    /// Check that the address of a place may be taken, failing if the place is an immutable
+    /// variable or a field or array element stored in one, as it could be modified through the
+    /// pointer
+    pub(super) fn check_addressable(
+        &self,
+        file: FileId,
+        place: &IrExpr,
+    ) -> Result<(), Diagnostic<FileId>> {
+        match self.place_root(place).kind {
+            IrExprKind::Var(var) if !self.ctx[var].mutable => Err(self.immutable_var(
+                file,
+                place.span,
+                var,
+                "take a pointer to",
+                "Pointer taken here",
+            )),
+            _ => Ok(()),
+        }
+    }
+
+    /// Create an error for an action that needs a mutable variable applied to an immutable
+    /// variable, labelling the variable's declaration
+    fn immutable_var(
+        &self,
+        file: FileId,
+        span: Span,
+        var: VarId,
+        action: &str,
+        label: &str,
+    ) -> Diagnostic<FileId> {
+        let name = self.ctx[var].name;
+        let mut labels = vec![Label::primary(file, span).with_message(label)];
+        let declared = self
+            .scope_stack
+            .iter()
+            .flat_map(|plate| plate.vars.values())
+            .find(|(declared, _)| *declared == var);
+        if let Some((_, decl_span)) = declared {
+            labels.push(
+                Label::secondary(file, *decl_span)
+                    .with_message(format!("Variable {} declared here without mut", name)),
+            );
+        }
+
+        Diagnostic::error()
+            .with_message(format!("Cannot {} immutable variable {}", action, name))
+            .with_labels(labels)
+            .with_notes(vec![
+                "Consider making this binding mutable by declaring it with mut".to_owned(),
+            ])
+    }
+
+    /// Store the value of a break statement to the variable holding the value of the loop it
+    /// breaks from, giving the loop the type of the first value it breaks with
+    fn lower_break_value(
+        &mut self,
+        module: IntermediateModuleId,
+        file: FileId,
+        fun: FunId,
+        phi_var: VarId,
+        val: &Expr,
+    ) -> Result<(), Diagnostic<FileId>> {
+        let val = match self.ctx[phi_var].ty {
+            ty if self.ctx[ty] == IrType::Invalid => {
+                let val = self.lower_expr(module, file, fun, val)?;
+                self.ctx[phi_var].ty = val.ty;
+                val
+            }
+            ty => {
+                let val = self.lower_expr_as(module, file, fun, val, ty)?;
+                if val.ty != ty {
+                    return Err(Diagnostic::error()
+                        .with_message(format!(
+                            "Break statement breaks from a loop with a value of type {}, but type {} was expected",
+                            self.ctx.typename(val.ty),
+                            self.ctx.typename(ty),
+                        ))
+                        .with_labels(vec![Label::primary(file, val.span).with_message(format!(
+                            "Value of type {} appears here",
+                            self.ctx.typename(val.ty),
+                        ))])
+                        .with_notes(vec![
+                            "Every break from a loop must have a value of the same type".to_owned(),
+                        ]));
+                }
+                val
+            }
+        };
+
+        let current = self.bb();
+        self.ctx[current].stmts.push(IrStmt {
+            span: val.span,
+            kind: IrStmtKind::Store { var: phi_var, val },
+        });
+        Ok(())
+    }
+
+    /// Check that a break statement has a value if and only if the first break from the same
+    /// loop has a value, so that the loop's value is always assigned when it completes
+    fn check_break_kind(
+        &mut self,
+        file: FileId,
+        phi_var: VarId,
+        span: Span,
+        has_value: bool,
+    ) -> Result<(), Diagnostic<FileId>> {
+        let (first, first_has_value) =
+            *self.loop_breaks.entry(phi_var).or_insert((span, has_value));
+        if first_has_value == has_value {
+            return Ok(());
+        }
+
+        let describe = |has_value| match has_value {
+            true => "with a value",
+            false => "without a value",
+        };
+        Err(Diagnostic::error()
+            .with_message("Loop has break statements both with and without a value")
+            .with_labels(vec![
+                Label::primary(file, span)
+                    .with_message(format!("Break {} appears here", describe(has_value))),
+                Label::secondary(file, first).with_message(format!(
+                    "First break {} appears here",
+                    describe(first_has_value)
+                )),
+            ])
+            .with_notes(vec![
+                "Every break from a loop must have a value, or none of them may".to_owned(),
+            ]))
+    }
+
+    /// Get the number of scopes from the top of the scope stack to the innermost loop's scope,
+    /// including the loop's scope
+    fn loop_depth(
+        &self,
+        file: FileId,
+        span: Span,
+        is_break: bool,
+    ) -> Result<usize, Diagnostic<FileId>> {
+        match self
+            .scope_stack
+            .iter()
+            .rev()
+            .position(|plate| plate.loop_bb.is_some())
+        {
+            Some(pos) => Ok(pos + 1),
+            None => Err(Diagnostic::error()
+                .with_message(format!(
+                    "{} statement outside of a loop",
+                    if is_break { "Break" } else { "Continue" }
+                ))
+                .with_labels(vec![
+                    Label::primary(file, span).with_message("Statement appears here")
+                ])),
+        }
+    }
+
+    /// Get a mutable reference to the current scope plate
+    pub(super) fn current_scope_mut(&mut self) -> &mut ScopePlate {
+        self.scope_stack
+            .last_mut()
+            .expect("Internal compiler error: scope stack is empty")
+    }
+
+    /// Get an immutable reference to the current scope plate
+    fn current_scope(&self) -> &ScopePlate {
+        self.scope_stack
+            .last()
+            .expect("Internal compiler error: scope stack is empty")
+    }
+
+    fn lowest_scope(&self) -> &ScopePlate {
+        self.scope_stack
+            .first()
+            .expect("Internal compiler error: scope stack is empty")
+    }
+
+    fn lowest_scope_mut(&mut self) -> &mut ScopePlate {
+        self.scope_stack
+            .first_mut()
+            .expect("Internal compiler error: scope stack is empty")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use hashbrown::HashMap;
+
+    use crate::{
+        ir::{
+            lower::testing::{lower, lower_err, Lowered},
+            opt,
+            value::{CastKind, IrExpr, IrExprKind, IrLiteral},
+            BBId, IrContext, IrStmtKind, IrTerminator,
+        },
+        llvm::testing::run,
+        parse::token::Op,
+    };
+
+    #[test]
+    fn immutable_array_does_not_decay() {
+        let err = lower_err(
+            "fun main() -> i32 {
+    let a = [1u8, 2u8]
+    let [*u8] p = a
+    return 0
+}",
+        );
+        assert_eq!(err.message, "Cannot take a pointer to immutable variable a");
+    }
+
+    #[test]
+    fn mutable_array_decays() {
+        lower(
+            "fun main() -> i32 {
+    mut a = [1u8, 2u8]
+    let [*u8] p = a
+    return 0
+}",
+        );
+    }
+
+    #[test]
+    fn temporary_array_decays_through_a_temporary() {
+        let src = "fun sum([]i32 s) -> i32 {
+    mut total = 0
+    mut i = 0usize
+    loop {
+        if i == s.len { break }
+        total = total + s[i]
+        i = i + 1usize
+    }
+    return total
+}
+
+fun first(*i32 p) -> i32 {
+    return *p
+}
+
+fun main() -> i32 {
+    return sum([1, 2, 3]) + first([4, 5])
+}";
+        let lowered = lower(src);
+        let ctx = &lowered.ctx;
+        let body = ctx.fun_body(lowered.fun("main")).unwrap();
+        let temporaries = ctx[body.entry]
+            .stmts
+            .iter()
+            .filter(|stmt| match &stmt.kind {
+                IrStmtKind::Store { var, val } => {
+                    ctx[*var].name.as_str().starts_with("@tmp")
+                        && matches!(val.kind, IrExprKind::Lit(IrLiteral::Array(_)))
+                }
+                _ => false,
+            })
+            .count();
+        assert_eq!(temporaries, 2, "{}", ctx);
+    }
+
+    #[test]
+    fn array_passed_to_extern_function() {
+        let src = |decl: &str| {
+            format!(
+                "fun ext puts(*u8 s) -> i32
+fun main() -> i32 {{
+    {} a = [104u8, 105u8, 0u8]
+    return puts(a)
+}}",
+                decl
+            )
+        };
+        lower(&src("mut"));
+        let err = lower_err(&src("let"));
+        assert_eq!(err.message, "Cannot take a pointer to immutable variable a");
+    }
+
+    #[test]
+    fn string_where_integer_is_expected() {
+        let err = lower_err(
+            "fun main() -> i32 {
+    let [i32] x = \"hi\"
+    return x
+}",
+        );
+        assert_eq!(
+            err.message,
+            "Assigning a value of type *u8 to a value of incompatible type i32"
+        );
+    }
+
+    #[test]
+    fn string_is_pointer_to_bytes() {
+        lower(
+            "fun ext puts(*u8 s) -> i32
+fun main() -> i32 {
+    let [*u8] s = \"hi\"
+    return puts(\"hi\") + puts(s)
+}",
+        );
+    }
+
+    #[test]
+    fn let_in_inner_scope_shadows() {
+        let status = run("fun main() -> i32 {
+    let [i32] x = 1
+    if true {
+        let [*u8] x = \"s\"
+    }
+    if true {
+        let x = 2
+    }
+    return x
+}");
+        assert_eq!(status, 1);
+    }
+
+    #[test]
+    fn shadowing_warns() {
+        let lowered = lower(
+            "fun main() -> i32 {
+    let x = 1
+    if true {
+        let x = 2
+    }
+    return x
+}",
+        );
+        assert!(lowered.warning_messages().contains(
+            &"Declaration of variable x shadows a variable in an enclosing scope".to_owned()
+        ));
+    }
+
+    #[test]
+    fn variable_is_not_visible_after_its_block() {
+        for block in [
+            "if true {\n        let x = 1\n    }",
+            "loop {\n        let x = 1\n        break\n    }",
+        ] {
+            let err = lower_err(&format!(
+                "fun main() -> i32 {{\n    {}\n    return x\n}}",
+                block
+            ));
+            assert_eq!(err.message, "No variable or function found for name x");
+        }
+    }
+
+    #[test]
+    fn sibling_blocks_reuse_variable_name() {
+        let mut lowered = lower(
+            "fun main() -> i32 {
+    mut total = 0
+    if true {
+        let x = 1
+        total = total + x
+    } else {
+        let x = 2u8
+        total = total + $i32 x
+    }
+    if false {
+        let [*u8] x = \"s\"
+    }
+    return total
+}",
+        );
+        let str_ty = lowered.ctx.ptr_to(IrContext::U8);
+        let ctx = &lowered.ctx;
+        let types = ctx
+            .vars
+            .iter()
+            .filter(|var| var.name.as_str() == "x")
+            .map(|var| var.ty)
+            .collect::<Vec<_>>();
+        assert_eq!(types, [IrContext::I32, IrContext::U8, str_ty], "{}", ctx);
+    }
+
+    #[test]
+    fn assignment_updates_outer_variable() {
+        let status = run("fun main() -> i32 {
+    mut x = 1
+    if true {
+        x = x + 4
+    }
+    return x
+}");
+        assert_eq!(status, 5);
+    }
+
+    #[test]
+    fn redeclaration_in_same_scope_is_rejected() {
+        let err = lower_err(
+            "fun main() -> i32 {
+    let x = 1
+    let x = 2
+    return x
+}",
+        );
+        assert_eq!(
+            err.message,
+            "Variable x is declared more than once in the same scope"
+        );
+    }
+
+    #[test]
+    fn assignment_to_undeclared_variable_is_rejected() {
+        let err = lower_err(
+            "fun main() -> i32 {
+    x = 1
+    return 0
+}",
+        );
+        assert_eq!(err.message, "Cannot assign to undeclared variable x");
+    }
+
+    #[test]
+    fn struct_literal_has_declared_type() {
+        let lowered = lower(
+            "type S = { i64 a, u8 b, }
+fun main() -> i32 {
+    let s = #S { b = 1, a = 2 }
+    return 0
+}",
+        );
+        let ctx = &lowered.ctx;
+        let body = ctx.fun_body(lowered.fun("main")).unwrap();
+        let stored = ctx[body.entry]
+            .stmts
+            .iter()
+            .find_map(|stmt| match &stmt.kind {
+                IrStmtKind::Store { val, .. } => Some(val),
+                _ => None,
+            })
+            .expect("no store of the structure literal");
+        assert_eq!(ctx.typename(stored.ty).to_string(), "S");
+
+        let literal = match &stored.kind {
+            IrExprKind::Cast(literal, _, kind) => {
+                assert_eq!(*kind, CastKind::Identity);
+                literal
+            }
+            _ => stored,
+        };
+        assert!(matches!(
+            literal.kind,
+            IrExprKind::Lit(IrLiteral::Struct(_))
+        ));
+        assert_eq!(literal.ty, ctx.unwrap_alias(stored.ty));
+    }
+
+    #[test]
+    fn bare_break_after_value_break_is_rejected() {
+        let err = lower_err(
+            "fun main() -> i32 {
+    mut i = 0
+    let x = loop {
+        i = i + 1
+        if i == 3 { break i }
+        if i == 5 { break }
+    }
+    return x
+}",
+        );
+        assert_eq!(
+            err.message,
+            "Loop has break statements both with and without a value"
+        );
+    }
+
+    #[test]
+    fn value_break_after_bare_break_is_rejected() {
+        let err = lower_err(
+            "fun main() -> i32 {
+    mut i = 0
+    let x = loop {
+        i = i + 1
+        if i == 3 { break }
+        if i == 5 { break i }
+    }
+    return 0
+}",
+        );
+        assert_eq!(
+            err.message,
+            "Loop has break statements both with and without a value"
+        );
+    }
+
+    #[test]
+    fn loop_value_from_break() {
+        let status = run("fun main() -> i32 {
+    mut i = 0
+    let x = loop {
+        i = i + 1
+        if i == 3 { break i * 2 }
+    }
+    return x
+}");
+        assert_eq!(status, 6);
+    }
+
+    #[test]
+    fn loop_without_break_values_is_unit() {
+        let src = "fun main() -> i32 {
+    mut i = 0
+    loop {
+        i = i + 1
+        if i == 4 { break }
+    }
+    return i
+}";
+        let lowered = lower(src);
+        let ctx = &lowered.ctx;
+        for var in ctx.vars.indices() {
+            assert_ne!(ctx[var].ty, IrContext::INVALID, "{}", ctx);
+        }
+        assert_eq!(run(src), 4);
+    }
+
+    #[test]
+    fn block_terminators_record_their_spans() {
+        let src = "fun main() -> i32 {
+    let x = 4
+    if x == 4 {} else { return 1 }
+    if x == 5 {
+        let _y = x
+    }
+    return 0
+}";
+        let lowered = lower(src);
+        let ctx = &lowered.ctx;
+        let body = ctx.fun_body(lowered.fun("main")).unwrap();
+        let spans = body
+            .blocks
+            .iter()
+            .map(|bb| {
+                let span = ctx[*bb]
+                    .terminator_span
+                    .unwrap_or_else(|| panic!("{} has no terminator span\n{}", bb, ctx));
+                &src[span.from..span.to]
+            })
+            .collect::<Vec<_>>();
+        assert!(spans.contains(&"x == 4"), "{:?}", spans);
+    }
+
+    #[test]
+    fn mutable_global_counter_is_updated() {
+        let status = run("glob mut counter = 0
+
+fun bump() {
+    counter = counter + 1
+}
+
+fun main() -> i32 {
+    bump()
+    bump()
+    bump()
+    return counter
+}");
+        assert_eq!(status, 3);
+    }
+
+    #[test]
+    fn immutable_global_is_not_assignable() {
+        let err = lower_err(
+            "glob limit = 10
+
+fun main() -> i32 {
+    limit = 5
+    return limit
+}",
+        );
+        assert_eq!(err.message, "Cannot assign to immutable global limit");
+        assert_eq!(
+            err.notes,
+            vec!["Declare the global with glob mut limit to allow assigning to it".to_owned()]
+        );
+    }
+
+    #[test]
+    fn constant_is_not_assignable() {
+        let err = lower_err(
+            "const LIMIT = 10
+
+fun main() -> i32 {
+    LIMIT = 5
+    return LIMIT
+}",
+        );
+        assert_eq!(err.message, "Cannot assign to constant LIMIT");
+        assert_eq!(
+            err.notes,
+            vec!["Constants are evaluated at compile time and can't be assigned to".to_owned()]
+        );
+    }
+
+    #[test]
+    fn struct_global_is_initialized_by_setup() {
+        let lowered = lower(
+            "type Pair = { i64 a, u8 b }
+
+glob pair = #Pair { a = 1i64, b = 2u8 }
+
+fun main() -> i32 {
+    return $i32 pair.b
+}",
+        );
+        let ctx = &lowered.ctx;
+        let pair = ctx
+            .globals
+            .indices()
+            .find(|glob| ctx[*glob].name.as_str() == "pair")
+            .unwrap();
+        assert_eq!(ctx.typename(ctx[pair].ty).to_string(), "Pair");
+
+        let setup = ctx.fun_body(lowered.fun("__global_setup")).unwrap();
+        let stores = ctx[setup.entry]
+            .stmts
+            .iter()
+            .filter(|stmt| matches!(stmt.kind, IrStmtKind::StoreMem { .. }))
+            .count();
+        assert_eq!(stores, 1, "{}", ctx);
+    }
+
+    #[test]
+    fn bodies_record_their_own_blocks() {
+        let lowered = lower(
+            "glob mut total = 1
+
+fun count(i32 n) -> i32 {
+    mut i = 0
+    loop {
+        if i == n { break }
+        i = i + 1
+    }
+    return i
+}
+
+fun main() -> i32 {
+    total = total + count(3)
+    if total == 4 {} else { return 1 }
+    return 0
+}",
+        );
+        let ctx = &lowered.ctx;
+        let mut owners = HashMap::new();
+        for fun in ctx.funs.indices() {
+            let body = match ctx.fun_body(fun) {
+                Some(body) => body,
+                None => continue,
+            };
+            for bb in ctx.blocks_of(fun) {
+                assert!(body.blocks.contains(&bb), "{}", ctx);
+            }
+            for bb in body.blocks.iter() {
+                assert_eq!(owners.insert(*bb, fun), None, "{}", ctx);
+            }
+        }
+        assert!(ctx.fun_body(lowered.fun("count")).unwrap().blocks.len() > 1);
+    }
+
+    /// Get the names of the variables that die at the end of every block other than the entry
+    /// block of `main` that jumps to a block
+    fn deaths_before_jumps(lowered: &Lowered, target: BBId) -> Vec<Vec<String>> {
+        let ctx = &lowered.ctx;
+        let body = ctx.fun_body(lowered.fun("main")).unwrap();
+        let mut deaths = body
+            .blocks
+            .iter()
+            .filter(|bb| **bb != body.entry)
+            .filter(|bb| matches!(&ctx[**bb].terminator, IrTerminator::Jmp(to) if to.bb == target))
+            .map(|bb| {
+                ctx[*bb]
+                    .stmts
+                    .iter()
+                    .filter_map(|stmt| match stmt.kind {
+                        IrStmtKind::VarDead(var) => Some(ctx[var].name.to_string()),
+                        _ => None,
+                    })
+                    .collect::<Vec<_>>()
+            })
+            .collect::<Vec<_>>();
+        deaths.sort();
+        deaths
+    }
+
+    #[test]
+    fn loop_exits_end_every_scope_in_the_loop() {
+        let lowered = lower(
+            "fun main() -> i32 {
+    mut i = 0
+    loop {
+        let a = i
+        i = i + 1
+        if a == 1 {
+            let b = a
+            continue
+        }
+        if a == 3 {
+            let c = a
+            if c == 3 {
+                let d = c
+                break
+            }
+        }
+    }
+    return i
+}",
+        );
+        let ctx = &lowered.ctx;
+        let entry = ctx.fun_body(lowered.fun("main")).unwrap().entry;
+        let header = match &ctx[entry].terminator {
+            IrTerminator::Jmp(to) => to.bb,
+            _ => panic!("entry block does not jump to the loop\n{}", ctx),
+        };
+        let after = ctx
+            .fun_body(lowered.fun("main"))
+            .unwrap()
+            .blocks
+            .iter()
+            .copied()
+            .find(|bb| matches!(ctx[*bb].terminator, IrTerminator::Return(_)))
+            .unwrap();
+
+        //The continue and the end of every iteration both kill the loop body's variables
+        assert_eq!(
+            deaths_before_jumps(&lowered, header),
+            vec![vec!["a".to_owned()], vec!["b".to_owned(), "a".to_owned()]],
+            "{}",
+            ctx
+        );
+        assert_eq!(
+            deaths_before_jumps(&lowered, after),
+            vec![vec!["d".to_owned(), "c".to_owned(), "a".to_owned()]],
+            "{}",
+            ctx
+        );
+    }
+
+    const NUM: &str = "type Num = i32 | i64
+
+";
+
+    #[test]
+    fn match_value_unifies_arm_types() {
+        let src = format!(
+            "{}fun pick(Num n) -> i64 {{
+    let v = match n {{
+        i32 _a -> phi 1,
+        i64 b -> phi b
+    }}
+    return v
+}}
+
+fun main() -> i32 {{
+    return $i32 (pick($Num 5i64) + pick($Num 2i32))
+}}",
+            NUM
+        );
+        let lowered = lower(&src);
+        let ctx = &lowered.ctx;
+        let body = ctx.fun_body(lowered.fun("pick")).unwrap();
+        let params = body
+            .blocks
+            .iter()
+            .flat_map(|bb| ctx[*bb].params.iter())
+            .copied()
+            .collect::<Vec<_>>();
+        assert_eq!(params, vec![IrContext::I64], "{}", ctx);
+    }
+
+    #[test]
+    fn match_value_takes_expected_type() {
+        let lowered = lower(&format!(
+            "{}fun main() -> i32 {{
+    let n = $Num 3i32
+    let [u8] v = match n {{
+        i32 _a -> phi 200,
+        -> phi 1
+    }}
+    return $i32 v
+}}",
+            NUM
+        ));
+        let ctx = &lowered.ctx;
+        let body = ctx.fun_body(lowered.fun("main")).unwrap();
+        let params = body
+            .blocks
+            .iter()
+            .flat_map(|bb| ctx[*bb].params.iter())
+            .copied()
+            .collect::<Vec<_>>();
+        assert_eq!(params, vec![IrContext::U8], "{}", ctx);
+    }
+
+    #[test]
+    fn match_arm_type_mismatch_is_rejected() {
+        let err = lower_err(&format!(
+            "{}fun main() -> i32 {{
+    let n = $Num 3i32
+    let v = match n {{
+        i32 a -> phi a,
+        i64 b -> phi b
+    }}
+    return v
+}}",
+            NUM
+        ));
+        assert_eq!(
+            err.message,
+            "Phi statement returns expression of type i64, but type i32 was expected"
+        );
+    }
+
+    #[test]
+    fn match_arm_without_value_is_rejected() {
+        let err = lower_err(&format!(
+            "{}fun main() -> i32 {{
+    mut n = $Num 3i32
+    let v = match n {{
+        i32 a -> phi a,
+        i64 _b -> n = $Num 1i32
+    }}
+    return v
+}}",
+            NUM
+        ));
+        assert_eq!(
+            err.message,
+            "Not every arm of a match expression produces a value"
+        );
+        assert_eq!(
+            err.labels
+                .iter()
+                .map(|label| label.message.as_str())
+                .collect::<Vec<_>>(),
+            [
+                "This match expression produces a value of type i32",
+                "This arm produces no value"
+            ]
+        );
+    }
+
+    #[test]
+    fn nested_expressions_in_match_arms_produce_their_own_values() {
+        let lowered = lower(&format!(
+            "{}fun pick(Num n, i32 c) -> i32 {{
+    let v = match n {{
+        i32 a -> phi if c == 0 {{ phi a }} else {{ phi a + c }},
+        i64 _b -> phi match $Num c {{
+            i32 d -> phi d * 2,
+            i64 _e -> phi 0
+        }}
+    }}
+    return v
+}}",
+            NUM
+        ));
+        let ctx = &lowered.ctx;
+        assert!(ctx.validate().is_empty(), "{}", ctx);
+
+        //The outer match's join block receives a value from both arms
+        let joins = ctx
+            .blocks_of(lowered.fun("pick"))
+            .into_iter()
+            .flat_map(|bb| ctx[bb].terminator.branches().cloned().collect::<Vec<_>>())
+            .filter(|branch| {
+                matches!(&ctx[branch.bb].terminator, IrTerminator::Return(_))
+                    && !ctx[branch.bb].params.is_empty()
+            })
+            .map(|branch| branch.args.len())
+            .collect::<Vec<_>>();
+        assert_eq!(joins, [1, 1], "{}", ctx);
+    }
+
+    #[test]
+    fn stored_match_value_dies_when_the_match_ends() {
+        let lowered = lower(&format!(
+            "{}fun make(i32 c) -> Num {{
+    return $Num c
+}}
+
+fun main() -> i32 {{
+    let v = match make(3) {{
+        i32 a -> phi a,
+        i64 b -> return $i32 b
+    }}
+    return v
+}}",
+            NUM
+        ));
+        let ctx = &lowered.ctx;
+        let deaths = |bb: BBId| {
+            ctx[bb]
+                .stmts
+                .iter()
+                .filter_map(|stmt| match stmt.kind {
+                    IrStmtKind::VarDead(var) => Some(ctx[var].name.to_string()),
+                    _ => None,
+                })
+                .collect::<Vec<_>>()
+        };
+        let body = ctx.fun_body(lowered.fun("main")).unwrap();
+        let matched = format!("@matched#{}", body.entry);
 
-        None
+        //The block after the match begins by ending the stored value's lifetime
+        let after = body
+            .blocks
+            .iter()
+            .copied()
+            .find(|bb| ctx[*bb].params.len() == 1)
+            .unwrap();
+        assert_eq!(deaths(after), [matched.as_str()], "{}", ctx);
+
+        //Returning from an arm also leaves the match
+        let returns = body
+            .blocks
+            .iter()
+            .copied()
+            .filter(|bb| *bb != after && matches!(ctx[*bb].terminator, IrTerminator::Return(_)))
+            .collect::<Vec<_>>();
+        assert_eq!(returns.len(), 1);
+        assert!(deaths(returns[0]).contains(&matched), "{}", ctx);
     }
 
-    /// Get a mutable reference to the current scope plate
-    fn current_scope_mut(&mut self) -> &mut ScopePlate {
-        self.scope_stack
-            .last_mut()
-            .expect("Internal compiler error: scope stack is empty")
+    #[test]
+    fn match_statement_without_values_is_unit() {
+        let lowered = lower(&format!(
+            "{}fun main() -> i32 {{
+    mut total = 0
+    let n = $Num 3i32
+    match n {{
+        i32 a -> total = a,
+        -> total = 1
+    }}
+    return total
+}}",
+            NUM
+        ));
+        let ctx = &lowered.ctx;
+        let body = ctx.fun_body(lowered.fun("main")).unwrap();
+        assert!(
+            body.blocks.iter().all(|bb| ctx[*bb].params.is_empty()),
+            "{}",
+            ctx
+        );
     }
 
-    /// Get an immutable reference to the current scope plate
-    fn current_scope(&self) -> &ScopePlate {
-        self.scope_stack
-            .last()
-            .expect("Internal compiler error: scope stack is empty")
+    #[test]
+    fn non_exhaustive_match_is_rejected() {
+        let err = lower_err(&format!(
+            "{}fun main() -> i32 {{
+    let n = $Num 3i32
+    let v = match n {{
+        i32 a -> phi a
+    }}
+    return v
+}}",
+            NUM
+        ));
+        assert_eq!(
+            err.message,
+            "Match expression does not cover every variant of Num"
+        );
     }
 
-    fn lowest_scope(&self) -> &ScopePlate {
-        self.scope_stack
-            .first()
-            .expect("Internal compiler error: scope stack is empty")
+    #[test]
+    fn variant_matched_twice_is_rejected() {
+        let err = lower_err(&format!(
+            "{}fun main() -> i32 {{
+    let n = $Num 3i32
+    let v = match n {{
+        i32 a -> phi a,
+        i64 _b -> phi 1,
+        i32 c -> phi c
+    }}
+    return v
+}}",
+            NUM
+        ));
+        assert_eq!(err.message, "Variant i32 is matched more than once");
+        assert_eq!(
+            err.labels
+                .iter()
+                .map(|label| label.message.as_str())
+                .collect::<Vec<_>>(),
+            ["i32 matched again here", "i32 first matched here"]
+        );
+
+        let err = lower_err(
+            "enum Light { Red, Green }
+
+fun main() -> i32 {
+    let l = Light:Red
+    return match l {
+        Red -> phi 1,
+        Light:Red -> phi 2,
+        Green -> phi 3
+    }
+}",
+        );
+        assert_eq!(err.message, "Variant Red is matched more than once");
     }
 
-    fn lowest_scope_mut(&mut self) -> &mut ScopePlate {
-        self.scope_stack
-            .first_mut()
-            .expect("Internal compiler error: scope stack is empty")
+    #[test]
+    fn exhaustive_match_default_is_unreachable() {
+        let lowered = lower(&format!(
+            "{}fun main() -> i32 {{
+    let n = $Num 3i32
+    let v = match n {{
+        i32 a -> phi a,
+        i64 b -> phi $i32 b
+    }}
+    return v
+}}",
+            NUM
+        ));
+        let ctx = &lowered.ctx;
+        let body = ctx.fun_body(lowered.fun("main")).unwrap();
+        let default = match &ctx[body.entry].terminator {
+            IrTerminator::JmpMatch { default_jmp, .. } => default_jmp.bb,
+            other => panic!("entry ends with {:?}\n{}", other, ctx),
+        };
+        assert!(
+            matches!(ctx[default].terminator, IrTerminator::Unreachable),
+            "{}",
+            ctx
+        );
+    }
+
+    #[test]
+    fn mutable_match_binding_does_not_change_scrutinee() {
+        let lowered = lower(&format!(
+            "{}fun main() -> i32 {{
+    let n = $Num 3i32
+    match n {{
+        i32 mut a -> a = a + 1,
+        i64 _b -> return 1
+    }}
+    return 0
+}}",
+            NUM
+        ));
+        let ctx = &lowered.ctx;
+        let body = ctx.fun_body(lowered.fun("main")).unwrap();
+        let var_named = |name: &str| {
+            ctx.vars
+                .indices()
+                .find(|var| ctx[*var].name.as_str() == name)
+                .unwrap()
+        };
+        let (n, a) = (var_named("n"), var_named("a"));
+        assert!(ctx[a].mutable);
+
+        let stores = |target| {
+            body.blocks
+                .iter()
+                .flat_map(|bb| ctx[*bb].stmts.iter())
+                .filter(|stmt| matches!(stmt.kind, IrStmtKind::Store { var, .. } if var == target))
+                .count()
+        };
+        //The binding is initialized from the payload and then assigned once more in the arm,
+        //while the scrutinee is only ever stored to by its declaration
+        assert_eq!(stores(a), 2, "{}", ctx);
+        assert_eq!(stores(n), 1, "{}", ctx);
+    }
+
+    #[test]
+    fn match_binding_is_immutable_by_default() {
+        let err = lower_err(&format!(
+            "{}fun main() -> i32 {{
+    let n = $Num 3i32
+    match n {{
+        i32 a -> a = a + 1,
+        i64 _b -> return 1
+    }}
+    return 0
+}}",
+            NUM
+        ));
+        assert_eq!(err.message, "Cannot assign to immutable variable a");
+    }
+
+    #[test]
+    fn binding_variant_without_payload_is_rejected() {
+        let err = lower_err(
+            "enum Op { Stop, Go(i32) }
+
+fun main() -> i32 {
+    let op = Op:Stop
+    let v = match op {
+        Stop s -> phi 0,
+        Go n -> phi n
+    }
+    return v
+}",
+        );
+        assert_eq!(
+            err.message,
+            "Enum variant Stop carries no value to bind to s"
+        );
+    }
+
+    #[test]
+    fn wildcard_arm_cannot_bind() {
+        //Without a type a bare name is read as the type of the arm rather than a binding
+        let err = lower_err(&format!(
+            "{}fun main() -> i32 {{
+    let n = $Num 3i32
+    let v = match n {{
+        i32 a -> phi a,
+        other -> phi 0
+    }}
+    return v
+}}",
+            NUM
+        ));
+        assert_eq!(err.message, "Type other not found in module root");
+        assert_eq!(
+            err.notes,
+            ["Wildcard arms have no type and cannot bind the matched value, so other is read as the type of this arm"]
+        );
+
+        //The same arm without a name matches every other variant
+        let lowered = lower(&format!(
+            "{}fun pick(Num n) -> i32 {{
+    return match n {{
+        i32 a -> phi a,
+        -> phi 2
+    }}
+}}",
+            NUM
+        ));
+        let ctx = &lowered.ctx;
+        assert!(ctx.validate().is_empty(), "{}", ctx);
+        let entry = ctx.fun_body(lowered.fun("pick")).unwrap().entry;
+        match &ctx[entry].terminator {
+            IrTerminator::JmpMatch {
+                discriminants,
+                default_jmp,
+                ..
+            } => {
+                assert_eq!(discriminants.len(), 1);
+                assert!(matches!(
+                    &ctx[default_jmp.bb].terminator,
+                    IrTerminator::Jmp(to) if to.args.len() == 1
+                ));
+            }
+            other => panic!("Expected a match, found {:?}", other),
+        }
+    }
+
+    #[test]
+    fn enum_wildcard_arm_cannot_bind() {
+        let err = lower_err(
+            "enum Light { Red, Green }
+
+fun main() -> i32 {
+    let l = Light:Red
+    return match l {
+        Red -> phi 1,
+        other -> phi 0
+    }
+}",
+        );
+        assert_eq!(
+            err.message,
+            "Match arm does not name a variant of enum Light"
+        );
+        assert_eq!(
+            err.notes,
+            ["Wildcard arms have no type and cannot bind the matched value, so other is read as the type of this arm"]
+        );
+    }
+
+    fn unreachable_warnings(src: &str) -> usize {
+        lower(src)
+            .warning_messages()
+            .iter()
+            .filter(|msg| msg.as_str() == "Unreachable code")
+            .count()
+    }
+
+    #[test]
+    fn code_after_return_is_unreachable() {
+        let warnings = unreachable_warnings(
+            "fun main() -> i32 {
+    return 1
+    let _a = 2
+    return 3
+}",
+        );
+        assert_eq!(warnings, 1);
+    }
+
+    #[test]
+    fn code_after_break_is_unreachable() {
+        let warnings = unreachable_warnings(
+            "fun main() -> i32 {
+    mut i = 0
+    loop {
+        i = i + 1
+        break
+        i = i + 2
+    }
+    return i
+}",
+        );
+        assert_eq!(warnings, 1);
+    }
+
+    #[test]
+    fn code_after_diverging_match_is_unreachable() {
+        let warnings = unreachable_warnings(&format!(
+            "{}fun main() -> i32 {{
+    let n = $Num 3i32
+    match n {{
+        i32 a -> return a,
+        i64 _b -> return 1
+    }}
+    return 0
+}}",
+            NUM
+        ));
+        assert_eq!(warnings, 1);
+    }
+
+    #[test]
+    fn code_after_jumped_to_block_is_reachable() {
+        let warnings = unreachable_warnings(
+            "fun main() -> i32 {
+    mut i = 0
+    loop {
+        i = i + 1
+        if i == 10 {
+            break
+        }
+    }
+    if i == 10 {
+        return 0
+    }
+    return i
+}",
+        );
+        assert_eq!(warnings, 0);
+    }
+
+    #[test]
+    fn variable_types_are_inferred_from_initializers() {
+        let lowered = lower(
+            "type Pair = { i64 a, u8 b }
+
+fun len() -> u64 {
+    return 4u64
+}
+
+fun main() -> i32 {
+    let called = len()
+    let literal = 5
+    let pair = #Pair { a = 1i64, b = 2u8 }
+    return literal + $i32 called + $i32 pair.b
+}",
+        );
+        let ctx = &lowered.ctx;
+        let var_ty = |name: &str| {
+            ctx.vars
+                .indices()
+                .find(|var| ctx[*var].name.as_str() == name)
+                .map(|var| ctx.typename(ctx[var].ty).to_string())
+                .unwrap()
+        };
+        assert_eq!(var_ty("called"), "u64");
+        assert_eq!(var_ty("literal"), "i32");
+        assert_eq!(var_ty("pair"), "Pair");
+    }
+
+    #[test]
+    fn float_variable_types_are_inferred_from_initializers() {
+        let lowered = lower(
+            "fun main() -> i32 {
+    let unsuffixed = 1.5
+    let single = 2.5f32
+    let double = 3.5f64
+    return $i32 (unsuffixed + single) + $i32 double
+}",
+        );
+        let ctx = &lowered.ctx;
+        let var_ty = |name: &str| {
+            ctx.vars
+                .iter()
+                .find(|var| var.name.as_str() == name)
+                .map(|var| var.ty)
+                .unwrap()
+        };
+        assert_eq!(var_ty("unsuffixed"), IrContext::F32);
+        assert_eq!(var_ty("single"), IrContext::F32);
+        assert_eq!(var_ty("double"), IrContext::F64);
+    }
+
+    #[test]
+    fn initializer_cannot_refer_to_its_own_variable() {
+        let err = lower_err(
+            "fun main() -> i32 {
+    let x = x + 1
+    return x
+}",
+        );
+        assert_eq!(err.message, "No variable or function found for name x");
+
+        let lowered = lower(
+            "fun main() -> i32 {
+    let x = 1
+    if true {
+        let x = x + 1
+    }
+    return x
+}",
+        );
+        let ctx = &lowered.ctx;
+        let xs = ctx
+            .vars
+            .indices()
+            .filter(|var| ctx[*var].name.as_str() == "x")
+            .collect::<Vec<_>>();
+        let inner_init = ctx
+            .fun_body(lowered.fun("main"))
+            .unwrap()
+            .blocks
+            .iter()
+            .flat_map(|bb| ctx[*bb].stmts.iter())
+            .find_map(|stmt| match &stmt.kind {
+                IrStmtKind::Store { var, val } if *var == xs[1] => Some(val),
+                _ => None,
+            })
+            .unwrap();
+        assert!(
+            matches!(&inner_init.kind, IrExprKind::Binary(lhs, Op::Add, _) if matches!(lhs.kind, IrExprKind::Var(var) if var == xs[0])),
+            "{}",
+            ctx
+        );
+    }
+
+    #[test]
+    fn unit_initializer_cannot_be_inferred() {
+        let err = lower_err(
+            "fun nothing() {
+}
+
+fun main() -> i32 {
+    let v = nothing()
+    return 0
+}",
+        );
+        assert_eq!(
+            err.message,
+            "Cannot infer the type of variable v from an initializer of type ()"
+        );
+        assert_eq!(
+            err.notes,
+            vec!["Annotate the variable with a type: let [type] v = ...".to_owned()]
+        );
+    }
+
+    const PAIR: &str = "type Pair = { i64 first, u8 second }
+
+";
+
+    #[test]
+    fn unknown_struct_field_suggests_similar_field() {
+        let err = lower_err(&format!(
+            "{}fun main() -> i32 {{
+    let p = #Pair {{ first = 1i64, secnd = 2u8 }}
+    return $i32 p.second
+}}",
+            PAIR
+        ));
+        assert_eq!(
+            err.message,
+            "Structure literal assigns a value for field named secnd, but structure type Pair contains no such field"
+        );
+        assert_eq!(err.notes, vec!["Did you mean second?".to_owned()]);
+    }
+
+    #[test]
+    fn duplicate_struct_field_is_rejected() {
+        let err = lower_err(&format!(
+            "{}fun main() -> i32 {{
+    let p = #Pair {{ first = 1i64, second = 2u8, first = 3i64 }}
+    return $i32 p.second
+}}",
+            PAIR
+        ));
+        assert_eq!(
+            err.message,
+            "Structure literal assigns a value to field first more than once"
+        );
+    }
+
+    #[test]
+    fn missing_struct_fields_are_rejected() {
+        let err = lower_err(
+            "type Point = { i32 x, i32 y, i32 z }
+
+fun main() -> i32 {
+    let p = #Point { y = 1 }
+    return p.y
+}",
+        );
+        assert_eq!(
+            err.message,
+            "Structure literal of type Point is missing fields x, z"
+        );
+    }
+
+    #[test]
+    fn struct_field_type_mismatch_is_rejected() {
+        let err = lower_err(&format!(
+            "{}fun main() -> i32 {{
+    let p = #Pair {{ first = 1i64, second = 2i64 }}
+    return $i32 p.second
+}}",
+            PAIR
+        ));
+        assert_eq!(
+            err.message,
+            "Field second of structure type Pair has type u8, but a value of type i64 was assigned"
+        );
+    }
+
+    #[test]
+    fn reordered_struct_literal_uses_declared_order() {
+        let lowered = lower(&format!(
+            "{}fun main() -> i32 {{
+    let p = #Pair {{ second = 2u8, first = 1i64 }}
+    return $i32 p.second
+}}",
+            PAIR
+        ));
+        let ctx = &lowered.ctx;
+        let body = ctx.fun_body(lowered.fun("main")).unwrap();
+        let fields = body
+            .blocks
+            .iter()
+            .flat_map(|bb| ctx[*bb].stmts.iter())
+            .find_map(|stmt| match &stmt.kind {
+                //The literal is cast from its anonymous structure type to the named type
+                IrStmtKind::Store { val, .. } => match &val.kind {
+                    IrExprKind::Cast(lit, ..) => match &lit.kind {
+                        IrExprKind::Lit(IrLiteral::Struct(fields)) => Some(fields),
+                        _ => None,
+                    },
+                    _ => None,
+                },
+                _ => None,
+            })
+            .unwrap_or_else(|| panic!("no structure literal stored\n{}", ctx));
+        let fields = fields
+            .iter()
+            .map(|(name, val)| (name.as_str(), val.ty))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            fields,
+            vec![("first", IrContext::I64), ("second", IrContext::U8)]
+        );
+    }
+
+    #[test]
+    fn let_destructures_tuple() {
+        let lowered = lower(
+            "fun three() -> (i32, u8, i64) {
+    return (1, 2u8, 3i64)
+}
+
+fun main() -> i32 {
+    let (a, b, c) = three()
+    return a + $i32 b + $i32 c
+}",
+        );
+        let ctx = &lowered.ctx;
+        let body = ctx.fun_body(lowered.fun("main")).unwrap();
+        let mut calls = 0;
+        let mut elements = vec![];
+        for stmt in ctx[body.entry].stmts.iter() {
+            if let IrStmtKind::Store { var, val } = &stmt.kind {
+                match &val.kind {
+                    IrExprKind::Call(..) => calls += 1,
+                    IrExprKind::Member(tuple, idx) => {
+                        assert!(matches!(tuple.kind, IrExprKind::Var(_)), "{}", ctx);
+                        elements.push((ctx[*var].name.to_string(), *idx, val.ty));
+                    }
+                    _ => (),
+                }
+            }
+        }
+        assert_eq!(calls, 1, "{}", ctx);
+        assert_eq!(
+            elements,
+            vec![
+                ("a".to_owned(), 0, IrContext::I32),
+                ("b".to_owned(), 1, IrContext::U8),
+                ("c".to_owned(), 2, IrContext::I64),
+            ],
+            "{}",
+            ctx
+        );
+    }
+
+    #[test]
+    fn mutable_destructured_variables_can_be_assigned() {
+        lower(
+            "fun main() -> i32 {
+    mut (x, y) = (1, 2)
+    x = x + y
+    return x
+}",
+        );
+        let err = lower_err(
+            "fun main() -> i32 {
+    let (x, y) = (1, 2)
+    x = x + y
+    return x
+}",
+        );
+        assert_eq!(err.message, "Cannot assign to immutable variable x");
+    }
+
+    #[test]
+    fn destructured_variables_warn_when_unused() {
+        let lowered = lower(
+            "fun main() -> i32 {
+    let (a, b) = (1, 2)
+    return a
+}",
+        );
+        assert_eq!(
+            lowered.warning_messages(),
+            vec!["Variable b is never read".to_owned()]
+        );
+    }
+
+    #[test]
+    fn destructuring_arity_mismatch_is_rejected() {
+        let err = lower_err(
+            "fun main() -> i32 {
+    let (a, b) = (1, 2, 3)
+    return a + b
+}",
+        );
+        assert_eq!(
+            err.message,
+            "Cannot destructure a value of type (i32, i32, i32) into 2 variables"
+        );
+    }
+
+    #[test]
+    fn destructuring_non_tuple_is_rejected() {
+        let err = lower_err(
+            "fun main() -> i32 {
+    let (a, b) = 5
+    return a + b
+}",
+        );
+        assert_eq!(
+            err.message,
+            "Cannot destructure a value of type i32 into 2 variables"
+        );
+    }
+
+    #[test]
+    fn destructuring_into_non_names_is_rejected() {
+        let err = lower_err(
+            "fun main() -> i32 {
+    let (a, 1) = (1, 2)
+    return a
+}",
+        );
+        assert_eq!(
+            err.message,
+            "Only variable names can be declared by destructuring a tuple"
+        );
+    }
+
+    #[test]
+    fn break_from_nested_loop_exits_only_inner_loop() {
+        let status = run("fun main() -> i32 {
+    mut outer = 0
+    mut total = 0
+    loop {
+        outer = outer + 1
+        if outer == 4 { break }
+        mut inner = 0
+        loop {
+            inner = inner + 1
+            if inner == 3 { break }
+            total = total + 1
+        }
+    }
+    return total * 10 + outer
+}");
+        assert_eq!(status, 64);
+    }
+
+    #[test]
+    fn continue_skips_rest_of_body() {
+        let status = run("fun main() -> i32 {
+    mut i = 0
+    mut odd = 0
+    loop {
+        i = i + 1
+        if i == 10 { break }
+        if i % 2 == 0 { continue }
+        odd = odd + i
+    }
+    return odd
+}");
+        assert_eq!(status, 25);
+    }
+
+    #[test]
+    fn break_and_continue_outside_loop_are_rejected() {
+        for (stmt, message) in [
+            ("break", "Break statement outside of a loop"),
+            ("continue", "Continue statement outside of a loop"),
+        ] {
+            let src = format!(
+                "fun main() -> i32 {{
+    if 1 == 1 {{ {} }}
+    return 0
+}}",
+                stmt
+            );
+            let err = lower_err(&src);
+            assert_eq!(err.message, message);
+            //The diagnostic points at the statement itself
+            assert_eq!(err.labels[0].range.start, src.find(stmt).unwrap());
+        }
+    }
+
+    #[test]
+    fn break_and_continue_jump_to_the_innermost_loop() {
+        let lowered = lower(
+            "fun main() -> i32 {
+    mut i = 0
+    loop {
+        let a = i
+        i = i + 1
+        loop {
+            let b = a
+            if b == 2 { continue }
+            break
+        }
+        if i == 5 { break }
+    }
+    return i
+}",
+        );
+        let ctx = &lowered.ctx;
+        let body = ctx.fun_body(lowered.fun("main")).unwrap();
+        let jmp_target = |bb: BBId| match &ctx[bb].terminator {
+            IrTerminator::Jmp(to) => to.bb,
+            other => panic!("Expected {} to jump, found {:?}\n{}", bb, other, ctx),
+        };
+        let outer_header = jmp_target(body.entry);
+        let inner_header = jmp_target(outer_header);
+
+        //The outer loop's body enters the inner loop, and only the continue jumps back to it as
+        //the end of the inner body is unreachable
+        assert_eq!(
+            deaths_before_jumps(&lowered, inner_header),
+            vec![vec![], vec!["b".to_owned()]],
+            "{}",
+            ctx
+        );
+
+        //The inner break exits to the rest of the outer loop's body instead of leaving both loops
+        let inner_exits = body
+            .blocks
+            .iter()
+            .filter(|bb| matches!(&ctx[**bb].terminator, IrTerminator::Jmp(to) if to.bb != inner_header))
+            .filter(|bb| {
+                ctx[**bb].stmts.iter().any(|stmt| match stmt.kind {
+                    IrStmtKind::VarDead(var) => ctx[var].name.as_str() == "b",
+                    _ => false,
+                })
+            })
+            .map(|bb| jmp_target(*bb))
+            .collect::<Vec<_>>();
+        assert_eq!(inner_exits.len(), 1, "{}", ctx);
+        assert!(
+            matches!(ctx[inner_exits[0]].terminator, IrTerminator::JmpIf { .. }),
+            "{}",
+            ctx
+        );
+
+        //The outer break and the end of the outer body both end a's lifetime
+        let exit = body
+            .blocks
+            .iter()
+            .copied()
+            .find(|bb| matches!(ctx[*bb].terminator, IrTerminator::Return(_)))
+            .unwrap();
+        assert_eq!(
+            deaths_before_jumps(&lowered, outer_header),
+            vec![vec!["a".to_owned()]]
+        );
+        assert_eq!(
+            deaths_before_jumps(&lowered, exit),
+            vec![vec!["a".to_owned()]]
+        );
+    }
+
+    /// Lower a function that must fail to lower because it may not return, returning the
+    /// messages of the secondary labels on the error
+    fn missing_return_labels(src: &str) -> Vec<String> {
+        let err = lower_err(src);
+        assert_eq!(
+            err.message,
+            "Function f must return a value of type i32 but may reach the end of its body without returning"
+        );
+        assert_eq!(
+            err.labels[0].message,
+            "Function returns a value of type i32"
+        );
+        err.labels[1..]
+            .iter()
+            .map(|label| label.message.clone())
+            .collect()
+    }
+
+    #[test]
+    fn if_without_else_may_not_return() {
+        let labels = missing_return_labels(
+            "fun f(i32 a) -> i32 {
+    if a == 1 {
+        return 1
+    }
+}",
+        );
+        assert_eq!(
+            labels,
+            ["This if has no else branch and the function does not return after it"]
+        );
+    }
+
+    #[test]
+    fn loop_with_break_may_not_return() {
+        let labels = missing_return_labels(
+            "fun f(i32 a) -> i32 {
+    mut i = 0
+    loop {
+        if i == a { break }
+        i = i + 1
+    }
+}",
+        );
+        assert_eq!(
+            labels,
+            ["Control flow reaches the end of the function from here"]
+        );
+    }
+
+    #[test]
+    fn match_arm_without_return_may_not_return() {
+        let labels = missing_return_labels(
+            "type Num = i32 | i64
+fun f(Num n) -> i32 {
+    match n {
+        i32 a -> return a,
+        i64 b -> let _c = b,
+    }
+}",
+        );
+        assert!(!labels.is_empty());
+        assert!(labels
+            .iter()
+            .all(|label| label == "Control flow reaches the end of the function from here"));
+    }
+
+    #[test]
+    fn straight_line_body_may_not_return() {
+        let labels = missing_return_labels(
+            "fun f(i32 a) -> i32 {
+    let _b = a
+}",
+        );
+        assert_eq!(
+            labels,
+            ["Function body ends here without returning a value"]
+        );
+    }
+
+    #[test]
+    fn returning_on_every_path_is_accepted() {
+        lower(
+            "type Num = i32 | i64
+fun f(i32 a) -> i32 {
+    if a == 1 {
+        return 1
+    } else {
+        return 2
+    }
+}
+fun g(Num n) -> i32 {
+    match n {
+        i32 a -> return a,
+        i64 b -> return 0,
+    }
+}
+fun h() -> i32 {
+    loop { }
+}
+fun unit(i32 a) {
+    if a == 1 { let _b = a }
+}",
+        );
+    }
+
+    #[test]
+    fn if_expression_arms_produce_value() {
+        let status = run("fun pick(i32 a) -> i32 {
+    let x = if a == 1 { phi 10 } else if a == 2 { phi 20 } else { phi 30 }
+    return x
+}
+
+fun main() -> i32 {
+    return pick(1) + pick(2) + pick(3)
+}");
+        assert_eq!(status, 60);
+    }
+
+    #[test]
+    fn if_expression_value_is_a_block_parameter() {
+        let mut lowered = lower(
+            "fun pick(i32 c) -> i32 {
+    let x = if c == 0 { phi 1 } else { phi 2 }
+    return x
+}",
+        );
+        let ctx = &lowered.ctx;
+        let blocks = ctx.blocks_of(lowered.fun("pick"));
+        let joins = blocks
+            .iter()
+            .filter(|bb| !ctx[**bb].params.is_empty())
+            .collect::<Vec<_>>();
+        assert_eq!(joins.len(), 1);
+        let join = *joins[0];
+        assert_eq!(ctx[join].params.len(), 1);
+        assert_eq!(ctx.typename(ctx[join].params[0]).to_string(), "i32");
+
+        //Both arms pass their value to the join block
+        let arms = blocks
+            .iter()
+            .filter_map(|bb| match &ctx[*bb].terminator {
+                IrTerminator::Jmp(to) if to.bb == join => Some(to.args.len()),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(arms, [1, 1]);
+
+        //The value is not stored to a temporary variable before it is assigned to x
+        let vars = blocks
+            .iter()
+            .flat_map(|bb| ctx[*bb].stmts.iter())
+            .filter_map(|stmt| match stmt.kind {
+                IrStmtKind::VarLive(var) => Some(ctx[var].name.to_string()),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(vars, ["@return_var#pick", "x"]);
+
+        //Once x is promoted, nothing in the body needs a stack slot
+        let pick = lowered.fun("pick");
+        let ctx = &mut lowered.ctx;
+        opt::promote_vars(ctx, pick);
+        let stack_slots = ctx
+            .blocks_of(pick)
+            .into_iter()
+            .flat_map(|bb| ctx[bb].stmts.iter())
+            .filter(|stmt| matches!(stmt.kind, IrStmtKind::VarLive(_)))
+            .count();
+        assert_eq!(stack_slots, 0);
+    }
+
+    #[test]
+    fn if_expression_literal_arm_takes_other_arm_type() {
+        let lowered = lower(
+            "fun f(u8 b) -> u8 {
+    let x = if b == 1u8 { phi 200 } else { phi b }
+    return x
+}",
+        );
+        let ctx = &lowered.ctx;
+        let x = ctx
+            .vars
+            .indices()
+            .find(|var| ctx[*var].name.as_str() == "x")
+            .unwrap();
+        assert_eq!(ctx.typename(ctx[x].ty).to_string(), "u8");
+    }
+
+    #[test]
+    fn comparisons_have_type_bool() {
+        for ty in ["i32", "u8", "i64", "f64"] {
+            for op in ["==", "<", "<=", ">", ">="] {
+                let lowered = lower(&format!(
+                    "fun f({ty} a, {ty} b) -> i32 {{
+    let c = a {op} b
+    let folded = 1 {op} 2
+    if c {{
+        return 1
+    }}
+    return 0
+}}",
+                    ty = ty,
+                    op = op
+                ));
+                let ctx = &lowered.ctx;
+                for name in ["c", "folded"] {
+                    let var = ctx
+                        .vars
+                        .indices()
+                        .find(|var| ctx[*var].name.as_str() == name)
+                        .unwrap();
+                    assert_eq!(
+                        ctx.typename(ctx[var].ty).to_string(),
+                        "bool",
+                        "{} {} {}",
+                        ty,
+                        op,
+                        name
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn non_bool_if_condition_is_rejected() {
+        let err = lower_err(
+            "fun f() -> i32 {
+    if 1 {}
+    return 0
+}",
+        );
+        assert_eq!(err.message, "Expected bool, found i32");
+        assert_eq!(err.labels[0].message, "Condition of type i32 appears here");
+        assert_eq!(
+            err.notes,
+            ["The condition of an if expression must be of type bool"]
+        );
+
+        let err = lower_err(
+            "fun f(u8 a) -> i32 {
+    if a + 1u8 { return 1 }
+    return 0
+}",
+        );
+        assert_eq!(err.message, "Expected bool, found u8");
+    }
+
+    #[test]
+    fn if_expression_arms_of_different_types_are_rejected() {
+        let err = lower_err(
+            "fun f(i32 a, i64 b) -> i32 {
+    let x = if a == 1 { phi a } else { phi b }
+    return 0
+}",
+        );
+        assert_eq!(
+            err.message,
+            "Phi statement returns expression of type i64, but type i32 was expected"
+        );
+    }
+
+    #[test]
+    fn if_expression_without_else_is_rejected() {
+        let err = lower_err(
+            "fun f(i32 a) -> i32 {
+    let x = if a == 1 { phi a }
+    return x
+}",
+        );
+        assert_eq!(
+            err.message,
+            "Not every branch of an if expression produces a value"
+        );
+    }
+
+    #[test]
+    fn if_expression_with_diverging_arm_takes_other_arm_type() {
+        let status = run("fun f(i32 a) -> i32 {
+    let x = if a == 1 { phi 7 } else { return 40 }
+    return x
+}
+
+fun main() -> i32 {
+    return f(1) + f(2)
+}");
+        assert_eq!(status, 47);
+    }
+
+    /// Get the names of the types of every value wrapped in a sum type in a function's stores,
+    /// returns, and call arguments, in the order they appear
+    fn sum_wraps(lowered: &Lowered, fun: &str) -> Vec<String> {
+        fn visit(ctx: &IrContext, expr: &IrExpr, wrapped: &mut Vec<String>) {
+            match &expr.kind {
+                IrExprKind::Cast(inner, _, CastKind::SumWrap) => {
+                    wrapped.push(ctx.typename(inner.ty).to_string())
+                }
+                IrExprKind::Call(_, args) => args.iter().for_each(|arg| visit(ctx, arg, wrapped)),
+                _ => (),
+            }
+        }
+
+        let ctx = &lowered.ctx;
+        let mut wrapped = vec![];
+        for bb in ctx.blocks_of(lowered.fun(fun)) {
+            for stmt in ctx[bb].stmts.iter() {
+                if let IrStmtKind::Store { val, .. } = &stmt.kind {
+                    visit(ctx, val, &mut wrapped);
+                }
+            }
+            if let IrTerminator::Return(expr) = &ctx[bb].terminator {
+                visit(ctx, expr, &mut wrapped);
+            }
+        }
+        wrapped
+    }
+
+    #[test]
+    fn values_upcast_to_sum_from_either_variant() {
+        let lowered = lower(
+            "type Either = i32 | *u8
+fun take(Either e) -> i32 { return 0 }
+fun from_int(i32 a) -> Either { return a }
+fun from_ptr(*u8 p) -> Either { return p }
+fun main() -> i32 {
+    let [Either] n = 5i32
+    let [Either] s = \"hi\"
+    return take(7i32)
+}",
+        );
+        assert_eq!(sum_wraps(&lowered, "from_int"), ["i32"]);
+        assert_eq!(sum_wraps(&lowered, "from_ptr"), ["*u8"]);
+        assert_eq!(sum_wraps(&lowered, "main"), ["i32", "*u8", "i32"]);
+    }
+
+    #[test]
+    fn value_widening_to_one_variant_is_upcast() {
+        let lowered = lower(
+            "type Either = i32 | *u8
+fun from_byte(i8 b) -> Either { return b }",
+        );
+        assert_eq!(sum_wraps(&lowered, "from_byte"), ["i32"]);
+    }
+
+    #[test]
+    fn value_of_more_than_one_variant_is_ambiguous() {
+        let err = lower_err(
+            "type Num = i32 | i64
+fun from_byte(i8 b) -> Num { return b }",
+        );
+        assert_eq!(
+            err.message,
+            "Value of type i8 could be more than one variant of sum type Num"
+        );
+        assert_eq!(
+            err.notes,
+            [
+                "Variants i32, i64 can all hold the value".to_owned(),
+                "Cast the value to one of the variants to choose which variant it becomes"
+                    .to_owned(),
+            ]
+        );
+    }
+
+    #[test]
+    fn sum_is_not_implicitly_downcast() {
+        let err = lower_err(
+            "type Either = i32 | *u8
+fun to_int(Either e) -> i32 { return e }",
+        );
+        assert_eq!(
+            err.message,
+            "Returning a value of type Either from function to_int that returns type i32"
+        );
+    }
+
+    #[test]
+    fn sum_is_not_explicitly_downcast() {
+        let err = lower_err(
+            "type Either = i32 | *u8
+fun to_int(Either e) -> i32 { return $i32 e }",
+        );
+        assert_eq!(
+            err.message,
+            "Cannot cast an expression of type Either to i32"
+        );
+        assert_eq!(
+            err.notes,
+            ["Use a match expression to get the i32 value of a Either after checking its variant"]
+        );
+    }
+
+    /// Get the field index and number of dereferences of the member access returned by a function
+    fn returned_member(lowered: &Lowered, fun: &str) -> (usize, usize) {
+        let ctx = &lowered.ctx;
+        let returned = ctx
+            .blocks_of(lowered.fun(fun))
+            .into_iter()
+            .find_map(|bb| match &ctx[bb].terminator {
+                IrTerminator::Return(expr) => Some(expr.clone()),
+                _ => None,
+            })
+            .unwrap();
+        let (mut object, idx) = match returned.kind {
+            IrExprKind::Member(object, idx) => (object, idx),
+            other => panic!("returned {:?} instead of a member", other),
+        };
+        let mut derefs = 0;
+        while let IrExprKind::Unary(Op::Star, pointer) = object.kind {
+            object = pointer;
+            derefs += 1;
+        }
+        (idx, derefs)
+    }
+
+    const POINT: &str = "type Point = { i32 x, i32 y }\n";
+
+    #[test]
+    fn member_access_dereferences_pointers() {
+        let lowered = lower(&format!(
+            "{}fun one(*Point p) -> i32 {{ return p.y }}
+fun two(**Point p) -> i32 {{ return p.y }}
+fun none(Point p) -> i32 {{ return p.x }}",
+            POINT
+        ));
+        assert_eq!(returned_member(&lowered, "one"), (1, 1));
+        assert_eq!(returned_member(&lowered, "two"), (1, 2));
+        assert_eq!(returned_member(&lowered, "none"), (0, 0));
+    }
+
+    #[test]
+    fn member_assignment_through_pointer_stores_in_pointee() {
+        let lowered = lower(&format!(
+            "{}fun set(*Point p) {{
+    let p.x = 40
+}}",
+            POINT
+        ));
+        let ctx = &lowered.ctx;
+        let stores = ctx
+            .blocks_of(lowered.fun("set"))
+            .into_iter()
+            .flat_map(|bb| ctx[bb].stmts.iter())
+            .filter(|stmt| matches!(stmt.kind, IrStmtKind::StoreMem { .. }))
+            .count();
+        assert_eq!(stores, 1);
+    }
+
+    /// Get every statement of a function's body that writes to a variable or through an address
+    fn writes(lowered: &Lowered, fun: &str) -> Vec<IrStmtKind> {
+        let ctx = &lowered.ctx;
+        ctx.blocks_of(lowered.fun(fun))
+            .into_iter()
+            .flat_map(|bb| ctx[bb].stmts.iter())
+            .filter(|stmt| {
+                matches!(
+                    stmt.kind,
+                    IrStmtKind::Store { .. }
+                        | IrStmtKind::StoreMem { .. }
+                        | IrStmtKind::Copy { .. }
+                )
+            })
+            .map(|stmt| stmt.kind.clone())
+            .collect()
+    }
+
+    #[test]
+    fn stores_through_pointers_write_to_memory() {
+        let lowered = lower(&format!(
+            "{}fun set(*i32 p, *Point q, i32 n) -> i32 {{
+    let *p = n
+    let q.y = 2
+    mut local = 0
+    local = n
+    return local
+}}",
+            POINT
+        ));
+        let ctx = &lowered.ctx;
+        let var_name = |expr: &IrExpr| match expr.kind {
+            IrExprKind::Var(var) => ctx[var].name.to_string(),
+            _ => panic!("Expected a variable, found {:?}", expr),
+        };
+
+        let writes = writes(&lowered, "set");
+        assert_eq!(writes.len(), 4);
+        match &writes[0] {
+            IrStmtKind::StoreMem { addr, val } => {
+                assert_eq!(var_name(addr), "p");
+                assert_eq!(var_name(val), "n");
+            }
+            other => panic!("Expected a store through p, found {:?}", other),
+        }
+        match &writes[1] {
+            IrStmtKind::StoreMem { addr, val } => {
+                assert_eq!(ctx.typename(addr.ty).to_string(), "*i32");
+                assert!(matches!(
+                    &addr.kind,
+                    IrExprKind::Unary(Op::AND, field) if matches!(field.kind, IrExprKind::Member(_, 1))
+                ));
+                assert!(matches!(val.kind, IrExprKind::Lit(IrLiteral::Integer(..))));
+            }
+            other => panic!("Expected a store to the field of q, found {:?}", other),
+        }
+        //Locals are still stored to directly
+        for write in &writes[2..] {
+            match write {
+                IrStmtKind::Store { var, .. } => assert_eq!(ctx[*var].name.as_str(), "local"),
+                other => panic!("Expected a store to local, found {:?}", other),
+            }
+        }
+    }
+
+    #[test]
+    fn aggregate_assignment_copies_between_places() {
+        let lowered = lower(&format!(
+            "{}fun set(Point src, *Point dst) -> i32 {{
+    mut local = src
+    local = src
+    let *dst = local
+    local = #Point {{ x = 1, y = 2 }}
+    return local.x
+}}",
+            POINT
+        ));
+        let ctx = &lowered.ctx;
+        //Addresses of variables are taken to copy between them, parameters holding pointers are
+        //used as addresses directly
+        let addr_of = |expr: &IrExpr| match &expr.kind {
+            IrExprKind::Unary(Op::AND, var) => match var.kind {
+                IrExprKind::Var(var) => ctx[var].name.to_string(),
+                _ => panic!("Expected the address of a variable, found {:?}", var),
+            },
+            IrExprKind::Var(var) => ctx[*var].name.to_string(),
+            _ => panic!("Expected an address, found {:?}", expr),
+        };
+
+        let copies = writes(&lowered, "set")
+            .into_iter()
+            .map(|write| match write {
+                IrStmtKind::Copy { dst, src, ty } => {
+                    assert_eq!(ctx.typename(ty).to_string(), "Point");
+                    Some((addr_of(&dst), addr_of(&src)))
+                }
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        let copy = |dst: &str, src: &str| Some((dst.to_owned(), src.to_owned()));
+        //Assigning a struct literal stores the value instead, as it has no address to copy from
+        assert_eq!(
+            copies,
+            [
+                copy("local", "src"),
+                copy("local", "src"),
+                copy("dst", "local"),
+                None
+            ]
+        );
+    }
+
+    #[test]
+    fn member_access_through_pointer_names_pointee() {
+        let err = lower_err("fun f(*i32 p) -> i32 { return p.x }");
+        assert_eq!(
+            err.message,
+            "Attempting to access field x of expression of non-structure type i32"
+        );
+
+        let err = lower_err(&format!(
+            "{}fun f(**Point p) -> i32 {{ return p.z }}",
+            POINT
+        ));
+        assert_eq!(err.message, "Field z not found for structure type Point");
     }
 }
@@ -0,0 +1,204 @@
+//! Lowering of builtin functions that are called like normal functions but have no definition,
+//! and can be overriden by a user-defined function of the same name
+
+use codespan_reporting::diagnostic::{Diagnostic, Label};
+
+use crate::{
+    ast::{Expr, ExprNode, Literal, SymbolPath},
+    ir::{
+        types::IrType,
+        value::{IrExpr, IrExprKind},
+        FunId, IrContext, IrStmt, IrStmtKind,
+    },
+    util::{files::FileId, loc::Span},
+};
+
+use super::{IntermediateModuleId, IrLowerer};
+
+impl<'ctx> IrLowerer<'ctx> {
+    /// Check if the given path refers to the builtin function with name `builtin`, instead of a
+    /// user-defined variable or function
+    pub(super) fn is_builtin(
+        &self,
+        module: IntermediateModuleId,
+        path: &SymbolPath,
+        builtin: &str,
+    ) -> bool {
+        path.iter().len() == 1
+            && path.last().as_str() == builtin
+            && self.lookup_var(&path.last()).is_none()
+            && self.resolve_path(module, path).is_none()
+    }
+
+    /// Lower a call to `volatile_read(ptr)`, a load through a pointer that will never be elided
+    /// or reordered by optimizations
+    pub(super) fn lower_volatile_read(
+        &mut self,
+        module: IntermediateModuleId,
+        file: FileId,
+        fun: FunId,
+        span: Span,
+        args: &[Expr],
+    ) -> Result<IrExpr, Diagnostic<FileId>> {
+        let ptr = match args {
+            [ptr] => self.lower_volatile_ptr(module, file, fun, ptr)?,
+            _ => {
+                return Err(Self::builtin_args_err(
+                    file,
+                    span,
+                    "volatile_read",
+                    1,
+                    args.len(),
+                ))
+            }
+        };
+
+        let ty = match &self.ctx[self.ctx.unwrap_alias(ptr.ty)] {
+            IrType::Ptr(to) => *to,
+            _ => unreachable!(),
+        };
+
+        Ok(IrExpr {
+            span,
+            ty,
+            kind: IrExprKind::VolatileRead(Box::new(ptr)),
+        })
+    }
+
+    /// Lower a call to `volatile_write(ptr, val)`, a store through a pointer that will never be
+    /// elided or reordered by optimizations
+    pub(super) fn lower_volatile_write(
+        &mut self,
+        module: IntermediateModuleId,
+        file: FileId,
+        fun: FunId,
+        span: Span,
+        args: &[Expr],
+    ) -> Result<(), Diagnostic<FileId>> {
+        let (ptr, val) = match args {
+            [ptr, val] => (
+                self.lower_volatile_ptr(module, file, fun, ptr)?,
+                self.lower_expr(module, file, fun, val)?,
+            ),
+            _ => {
+                return Err(Self::builtin_args_err(
+                    file,
+                    span,
+                    "volatile_write",
+                    2,
+                    args.len(),
+                ))
+            }
+        };
+
+        let pointee = match &self.ctx[self.ctx.unwrap_alias(ptr.ty)] {
+            IrType::Ptr(to) => *to,
+            _ => unreachable!(),
+        };
+        if pointee != val.ty {
+            return Err(Diagnostic::error()
+                .with_message(format!(
+                    "Cannot volatile write a value of type {} through a pointer of type {}",
+                    self.ctx.typename(val.ty),
+                    self.ctx.typename(ptr.ty),
+                ))
+                .with_labels(vec![
+                    Label::primary(file, val.span).with_message("Written value appears here"),
+                    Label::secondary(file, ptr.span).with_message("Pointer appears here"),
+                ]));
+        }
+
+        let current = self.bb();
+        self.ctx[current].stmts.push(IrStmt {
+            span,
+            kind: IrStmtKind::VolatileWrite { ptr, val },
+        });
+
+        Ok(())
+    }
+
+    /// Lower a call to `assert(cond)` or `assert(cond, "message")`, aborting the program if the
+    /// condition is false
+    pub(super) fn lower_assert(
+        &mut self,
+        module: IntermediateModuleId,
+        file: FileId,
+        fun: FunId,
+        span: Span,
+        args: &[Expr],
+    ) -> Result<(), Diagnostic<FileId>> {
+        let (cond, msg) = match args {
+            [cond] => (cond, None),
+            [cond, msg] => match &msg.node {
+                ExprNode::Literal(Literal::String(msg)) => (cond, Some(msg.clone())),
+                _ => {
+                    return Err(Diagnostic::error()
+                        .with_message("Assertion message must be a string literal")
+                        .with_labels(vec![Label::primary(file, msg.span)
+                            .with_message("Assertion message appears here")]))
+                }
+            },
+            _ => return Err(Self::builtin_args_err(file, span, "assert", 2, args.len())),
+        };
+
+        let cond = self.lower_expr(module, file, fun, cond)?;
+        if self.ctx.unwrap_alias(cond.ty) != IrContext::BOOL {
+            return Err(Diagnostic::error()
+                .with_message(format!(
+                    "Assertion condition must be of type bool, found {}",
+                    self.ctx.typename(cond.ty)
+                ))
+                .with_labels(vec![
+                    Label::primary(file, cond.span).with_message("Condition appears here")
+                ]));
+        }
+
+        let current = self.bb();
+        self.ctx[current].stmts.push(IrStmt {
+            span,
+            kind: IrStmtKind::Assert { cond, msg },
+        });
+
+        Ok(())
+    }
+
+    /// Lower the pointer operand of a volatile builtin, ensuring that it is of pointer type
+    fn lower_volatile_ptr(
+        &mut self,
+        module: IntermediateModuleId,
+        file: FileId,
+        fun: FunId,
+        ptr: &Expr,
+    ) -> Result<IrExpr, Diagnostic<FileId>> {
+        let ptr = self.lower_expr(module, file, fun, ptr)?;
+        match &self.ctx[self.ctx.unwrap_alias(ptr.ty)] {
+            IrType::Ptr(_) => Ok(ptr),
+            _ => Err(Diagnostic::error()
+                .with_message(format!(
+                    "Volatile access through an expression of non-pointer type {}",
+                    self.ctx.typename(ptr.ty)
+                ))
+                .with_labels(vec![
+                    Label::primary(file, ptr.span).with_message("Expression must be a pointer")
+                ])),
+        }
+    }
+
+    /// Create an error for a builtin function called with the wrong number of arguments
+    fn builtin_args_err(
+        file: FileId,
+        span: Span,
+        builtin: &str,
+        expected: usize,
+        found: usize,
+    ) -> Diagnostic<FileId> {
+        Diagnostic::error()
+            .with_message(format!(
+                "Builtin function {} expects {} arguments, found {}",
+                builtin, expected, found
+            ))
+            .with_labels(vec![
+                Label::primary(file, span).with_message("Call expression occurs here")
+            ])
+    }
+}
@@ -0,0 +1,598 @@
+//! Definite initialization analysis, ensuring that variables declared without an initializer are
+//! never read before they are assigned a value on every path through a function
+
+use codespan_reporting::diagnostic::{Diagnostic, Label};
+use hashbrown::{HashMap, HashSet};
+
+use crate::{
+    ir::{
+        types::IrType,
+        value::{IrExpr, IrExprKind, IrLiteral},
+        BBId, FunId, IrStmtKind, IrTerminator, VarId,
+    },
+    parse::token::Op,
+    util::{files::FileId, loc::Span},
+};
+
+use super::IrLowerer;
+
+impl<'ctx> IrLowerer<'ctx> {
+    /// Check that no variable declared without an initializer in the given function's body may be
+    /// read before it is assigned
+    pub(super) fn check_init(
+        &mut self,
+        file: FileId,
+        fun: FunId,
+    ) -> Result<(), Diagnostic<FileId>> {
+        self.check_assigned_once(file, fun)?;
+        let uninit = std::mem::take(&mut self.uninit_vars);
+        if uninit.is_empty() {
+            return Ok(());
+        }
+
+        let entry = match self.ctx.fun_body(fun) {
+            Some(body) => body.entry,
+            None => return Ok(()),
+        };
+
+        //Set of tracked variables that are definitely initialized at the start of each block,
+        //blocks not yet visited have no entry
+        let mut init_at = HashMap::<BBId, HashSet<VarId>>::new();
+        init_at.insert(entry, HashSet::new());
+        let mut worklist = vec![entry];
+
+        while let Some(bb) = worklist.pop() {
+            let mut init = init_at[&bb].clone();
+            self.init_transfer(bb, &uninit, &mut init, &mut |_, _| ());
+
+            for succ in self.ctx[bb].terminator.successors() {
+                let changed = match init_at.get_mut(&succ) {
+                    Some(succ_init) => {
+                        let before = succ_init.len();
+                        succ_init.retain(|var| init.contains(var));
+                        before != succ_init.len()
+                    }
+                    None => {
+                        init_at.insert(succ, init.clone());
+                        true
+                    }
+                };
+
+                if changed {
+                    worklist.push(succ);
+                }
+            }
+        }
+
+        let mut visited = init_at.keys().copied().collect::<Vec<_>>();
+        visited.sort_by_key(|bb| bb.val());
+
+        let mut uninit_read = None;
+        for bb in visited {
+            let mut init = init_at[&bb].clone();
+            self.init_transfer(bb, &uninit, &mut init, &mut |var, span| {
+                if uninit_read.is_none() {
+                    uninit_read = Some((var, span));
+                }
+            });
+
+            if uninit_read.is_some() {
+                break;
+            }
+        }
+
+        match uninit_read {
+            Some((var, span)) => Err(Diagnostic::error()
+                .with_message(format!(
+                    "Variable {} may be used before it is initialized",
+                    self.ctx[var].name
+                ))
+                .with_labels(vec![
+                    Label::primary(file, span).with_message("Variable read here"),
+                    Label::secondary(file, uninit[&var])
+                        .with_message("Variable declared here without an initializer"),
+                ])),
+            None => Ok(()),
+        }
+    }
+
+    /// Check that no immutable variable declared without an initializer in the given function's
+    /// body may be assigned more than once
+    fn check_assigned_once(&mut self, file: FileId, fun: FunId) -> Result<(), Diagnostic<FileId>> {
+        let deferred = std::mem::take(&mut self.deferred_vars);
+        if deferred.is_empty() {
+            return Ok(());
+        }
+
+        let entry = match self.ctx.fun_body(fun) {
+            Some(body) => body.entry,
+            None => return Ok(()),
+        };
+
+        //Set of tracked variables that may have been assigned at the start of each block
+        let mut assigned_at = HashMap::<BBId, HashSet<VarId>>::new();
+        assigned_at.insert(entry, HashSet::new());
+        let mut worklist = vec![entry];
+
+        while let Some(bb) = worklist.pop() {
+            let mut assigned = assigned_at[&bb].clone();
+            self.assign_transfer(bb, &deferred, &mut assigned, &mut |_, _| ());
+
+            for succ in self.ctx[bb].terminator.successors() {
+                let changed = match assigned_at.get_mut(&succ) {
+                    Some(succ_assigned) => {
+                        let before = succ_assigned.len();
+                        succ_assigned.extend(assigned.iter().copied());
+                        before != succ_assigned.len()
+                    }
+                    None => {
+                        assigned_at.insert(succ, assigned.clone());
+                        true
+                    }
+                };
+
+                if changed {
+                    worklist.push(succ);
+                }
+            }
+        }
+
+        let mut visited = assigned_at.keys().copied().collect::<Vec<_>>();
+        visited.sort_by_key(|bb| bb.val());
+
+        let mut reassigned = None;
+        for bb in visited {
+            let mut assigned = assigned_at[&bb].clone();
+            self.assign_transfer(bb, &deferred, &mut assigned, &mut |var, span| {
+                if reassigned.is_none() {
+                    reassigned = Some((var, span));
+                }
+            });
+
+            if reassigned.is_some() {
+                break;
+            }
+        }
+
+        match reassigned {
+            Some((var, span)) => Err(Diagnostic::error()
+                .with_message(format!(
+                    "Cannot assign twice to immutable variable {}",
+                    self.ctx[var].name
+                ))
+                .with_labels(vec![
+                    Label::primary(file, span).with_message("Variable may be assigned again here"),
+                    Label::secondary(file, deferred[&var]).with_message(format!(
+                        "Variable {} declared here without mut",
+                        self.ctx[var].name
+                    )),
+                ])
+                .with_notes(vec![
+                    "Consider making this binding mutable by declaring it with mut".to_owned(),
+                ])),
+            None => Ok(()),
+        }
+    }
+
+    /// Apply the effects of all statements in a basic block to the set of tracked variables that
+    /// may have been assigned, calling `on_reassign` for every assignment to a tracked variable
+    /// that may already be assigned
+    fn assign_transfer(
+        &self,
+        bb: BBId,
+        tracked: &HashMap<VarId, Span>,
+        assigned: &mut HashSet<VarId>,
+        on_reassign: &mut dyn FnMut(VarId, Span),
+    ) {
+        for stmt in self.ctx[bb].stmts.iter() {
+            let var = match &stmt.kind {
+                IrStmtKind::VarLive(var) | IrStmtKind::VarDead(var) => {
+                    assigned.remove(var);
+                    continue;
+                }
+                IrStmtKind::Store { var, .. } => *var,
+                //Aggregates are assigned through the address of the variable
+                IrStmtKind::StoreMem { addr: dst, .. } | IrStmtKind::Copy { dst, .. } => {
+                    match &dst.kind {
+                        IrExprKind::Unary(Op::AND, place) => match place.kind {
+                            IrExprKind::Var(var) => var,
+                            _ => continue,
+                        },
+                        _ => continue,
+                    }
+                }
+                _ => continue,
+            };
+
+            if tracked.contains_key(&var) && !assigned.insert(var) {
+                on_reassign(var, stmt.span);
+            }
+        }
+    }
+
+    /// Apply the effects of all statements in a basic block to the set of initialized variables,
+    /// calling `on_uninit` for every read of a tracked variable that is not initialized
+    fn init_transfer(
+        &self,
+        bb: BBId,
+        tracked: &HashMap<VarId, Span>,
+        init: &mut HashSet<VarId>,
+        on_uninit: &mut dyn FnMut(VarId, Span),
+    ) {
+        let mut read = |expr: &IrExpr, init: &mut HashSet<VarId>| {
+            self.init_reads(expr, &mut |var, span, written| {
+                if !tracked.contains_key(&var) {
+                    return;
+                }
+                if written {
+                    init.insert(var);
+                } else if !init.contains(&var) {
+                    on_uninit(var, span)
+                }
+            })
+        };
+
+        for stmt in self.ctx[bb].stmts.iter() {
+            match &stmt.kind {
+                IrStmtKind::VarLive(var) | IrStmtKind::VarDead(var) => {
+                    init.remove(var);
+                }
+                IrStmtKind::Zero(var) => {
+                    init.insert(*var);
+                }
+                IrStmtKind::Store { var, val } => {
+                    read(val, init);
+                    init.insert(*var);
+                }
+                IrStmtKind::StoreMem { addr, val } => {
+                    read(val, init);
+                    self.init_write(addr, init, &mut read);
+                }
+                IrStmtKind::Copy { dst, src, .. } => {
+                    //The copied place is read instead of having its address taken
+                    match &src.kind {
+                        IrExprKind::Unary(Op::AND, place) => read(place.as_ref(), init),
+                        _ => read(src, init),
+                    }
+                    self.init_write(dst, init, &mut read);
+                }
+                IrStmtKind::VolatileWrite { ptr, val } => {
+                    read(val, init);
+                    read(ptr, init);
+                }
+                IrStmtKind::Assert { cond, .. } => read(cond, init),
+                IrStmtKind::Call { args, .. } => {
+                    for arg in args {
+                        read(arg, init);
+                    }
+                }
+                IrStmtKind::Exec(expr) => read(expr, init),
+            }
+        }
+
+        match &self.ctx[bb].terminator {
+            IrTerminator::Return(expr) => read(expr, init),
+            IrTerminator::JmpIf { condition, .. } => read(condition, init),
+            IrTerminator::JmpMatch { variant, .. } => read(variant, init),
+            IrTerminator::Jmp(_) | IrTerminator::Unreachable | IrTerminator::Invalid => (),
+        }
+        for branch in self.ctx[bb].terminator.branches() {
+            for arg in branch.args.iter() {
+                read(arg, init);
+            }
+        }
+    }
+
+    /// Apply the effects of writing through an address to the set of initialized variables,
+    /// initializing the variable that the address points to if the whole variable is written.
+    /// Writing to a field or element of a variable leaves the rest of it uninitialized
+    fn init_write(
+        &self,
+        addr: &IrExpr,
+        init: &mut HashSet<VarId>,
+        read: &mut dyn FnMut(&IrExpr, &mut HashSet<VarId>),
+    ) {
+        match &addr.kind {
+            IrExprKind::Unary(Op::AND, place) => match (&place.kind, self.lval_root(place)) {
+                (IrExprKind::Var(var), _) => {
+                    init.insert(*var);
+                }
+                (_, Some(_)) => Self::lval_reads(place, &mut |expr| read(expr, init)),
+                (_, None) => read(addr, init),
+            },
+            _ => read(addr, init),
+        }
+    }
+
+    /// Get the variable that a written-to lvalue expression stores into, if any. Elements of a
+    /// slice are stored behind its pointer and not in the variable holding the slice
+    pub(super) fn lval_root(&self, expr: &IrExpr) -> Option<VarId> {
+        match &expr.kind {
+            IrExprKind::Var(var) => Some(*var),
+            IrExprKind::Index(obj, _)
+                if matches!(&self.ctx[self.ctx.unwrap_alias(obj.ty)], IrType::Slice(_)) =>
+            {
+                None
+            }
+            IrExprKind::Member(obj, _) | IrExprKind::Cast(obj, ..) | IrExprKind::Index(obj, _) => {
+                self.lval_root(obj)
+            }
+            _ => None,
+        }
+    }
+
+    /// Visit all expressions that are read when writing to the given lvalue expression
+    pub(super) fn lval_reads(expr: &IrExpr, read: &mut dyn FnMut(&IrExpr)) {
+        match &expr.kind {
+            IrExprKind::Member(obj, _) | IrExprKind::Cast(obj, ..) => Self::lval_reads(obj, read),
+            IrExprKind::Index(arr, idx) => {
+                Self::lval_reads(arr, read);
+                read(idx);
+            }
+            _ => (),
+        }
+    }
+
+    /// Visit all variable accesses in an expression, with a flag indicating if the variable's
+    /// address is taken and so it must be assumed to be initialized
+    pub(super) fn init_reads(&self, expr: &IrExpr, visit: &mut dyn FnMut(VarId, Span, bool)) {
+        match &expr.kind {
+            IrExprKind::Var(var) => visit(*var, expr.span, false),
+            IrExprKind::Unary(Op::AND, operand) => match self.lval_root(operand) {
+                Some(var) => visit(var, operand.span, true),
+                None => self.init_reads(operand, visit),
+            },
+            IrExprKind::Unary(_, operand)
+            | IrExprKind::VolatileRead(operand)
+            | IrExprKind::Member(operand, _)
+            | IrExprKind::Cast(operand, ..) => self.init_reads(operand, visit),
+            IrExprKind::Binary(lhs, _, rhs)
+            | IrExprKind::Index(lhs, rhs)
+            | IrExprKind::Slice(lhs, rhs) => {
+                self.init_reads(lhs, visit);
+                self.init_reads(rhs, visit);
+            }
+            IrExprKind::Call(called, args) => {
+                self.init_reads(called, visit);
+                for arg in args {
+                    self.init_reads(arg, visit);
+                }
+            }
+            IrExprKind::Lit(IrLiteral::Array(elems) | IrLiteral::Tuple(elems)) => {
+                for elem in elems {
+                    self.init_reads(elem, visit);
+                }
+            }
+            IrExprKind::Lit(IrLiteral::Struct(fields)) => {
+                for (_, field) in fields {
+                    self.init_reads(field, visit);
+                }
+            }
+            IrExprKind::Variant(_, payload) => {
+                if let Some(payload) = payload {
+                    self.init_reads(payload, visit);
+                }
+            }
+            IrExprKind::Lit(_)
+            | IrExprKind::Param(..)
+            | IrExprKind::Global(_)
+            | IrExprKind::Fun(_)
+            | IrExprKind::SizeOf(_)
+            | IrExprKind::AlignOf(_) => (),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::ir::lower::testing::{lower, lower_err};
+
+    const MUT_NOTE: &str = "Consider making this binding mutable by declaring it with mut";
+
+    #[test]
+    fn assignment_to_immutable_variable_is_rejected() {
+        let err = lower_err(
+            "fun main() -> i32 {
+    let x = 1
+    x = 2
+    return x
+}",
+        );
+        assert_eq!(err.message, "Cannot assign to immutable variable x");
+        assert_eq!(err.notes, vec![MUT_NOTE.to_owned()]);
+        assert!(err
+            .labels
+            .iter()
+            .any(|label| label.message == "Variable x declared here without mut"));
+    }
+
+    #[test]
+    fn member_assignment_to_immutable_variable_is_rejected() {
+        let err = lower_err(
+            "type S = { i32 a, }
+fun main() -> i32 {
+    let s = #S { a = 1 }
+    let s.a = 2
+    return s.a
+}",
+        );
+        assert_eq!(err.message, "Cannot assign to immutable variable s");
+        assert_eq!(err.notes, vec![MUT_NOTE.to_owned()]);
+    }
+
+    #[test]
+    fn assignment_to_immutable_parameter_is_rejected() {
+        let err = lower_err(
+            "fun f(i32 a) -> i32 {
+    a = 2
+    return a
+}
+fun main() -> i32 { return f(1) }",
+        );
+        assert_eq!(err.message, "Cannot assign to immutable variable a");
+        lower(
+            "fun f(mut i32 a) -> i32 {
+    a = 2
+    return a
+}
+fun main() -> i32 { return f(1) }",
+        );
+    }
+
+    #[test]
+    fn deferred_initialization_is_assigned_once() {
+        lower(
+            "fun main() -> i32 {
+    let [i32] x
+    if true {
+        x = 1
+    } else {
+        x = 2
+    }
+    return x
+}",
+        );
+    }
+
+    #[test]
+    fn deferred_initialization_assigned_twice_is_rejected() {
+        let err = lower_err(
+            "fun main() -> i32 {
+    let [i32] x
+    x = 1
+    x = 2
+    return x
+}",
+        );
+        assert_eq!(err.message, "Cannot assign twice to immutable variable x");
+        assert_eq!(err.notes, vec![MUT_NOTE.to_owned()]);
+    }
+
+    #[test]
+    fn deferred_initialization_in_loop_is_rejected() {
+        let err = lower_err(
+            "fun main() -> i32 {
+    let [i32] x
+    loop {
+        x = 1
+    }
+    return 0
+}",
+        );
+        assert_eq!(err.message, "Cannot assign twice to immutable variable x");
+    }
+
+    #[test]
+    fn deferred_member_assignment_is_rejected() {
+        let err = lower_err(
+            "type S = { i32 a, }
+fun main() -> i32 {
+    let [S] s
+    let s.a = 2
+    return s.a
+}",
+        );
+        assert_eq!(err.message, "Cannot assign to immutable variable s");
+    }
+
+    #[test]
+    fn field_write_does_not_initialize_variable() {
+        let err = lower_err(
+            "type S = { i32 a, i32 b, }
+fun main() -> i32 {
+    mut [S] s
+    let s.a = 1
+    return s.b
+}",
+        );
+        assert_eq!(
+            err.message,
+            "Variable s may be used before it is initialized"
+        );
+    }
+
+    #[test]
+    fn whole_write_initializes_variable() {
+        lower(
+            "type S = { i32 a, i32 b, }
+fun main() -> i32 {
+    mut [S] s
+    s = #S { a = 1, b = 2 }
+    let s.a = 3
+    return s.b
+}",
+        );
+    }
+
+    #[test]
+    fn assignment_in_one_branch_does_not_initialize() {
+        let err = lower_err(
+            "fun main() -> i32 {
+    mut [i32] x
+    if true {
+        x = 1
+    }
+    return x
+}",
+        );
+        assert_eq!(
+            err.message,
+            "Variable x may be used before it is initialized"
+        );
+        let labels = err
+            .labels
+            .iter()
+            .map(|label| label.message.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            labels,
+            [
+                "Variable read here",
+                "Variable declared here without an initializer"
+            ]
+        );
+    }
+
+    #[test]
+    fn assignment_in_loop_body_does_not_initialize_after_loop() {
+        let err = lower_err(
+            "fun main() -> i32 {
+    mut [i32] x
+    mut i = 0
+    loop {
+        if i == 3 { break }
+        x = i
+        i = i + 1
+    }
+    return x
+}",
+        );
+        assert_eq!(
+            err.message,
+            "Variable x may be used before it is initialized"
+        );
+    }
+
+    #[test]
+    fn assignment_through_pointer_initializes_variable() {
+        lower(
+            "fun set(*i32 p) {
+    let *p = 5
+}
+
+fun main() -> i32 {
+    mut [i32] x
+    set(&x)
+    return x
+}",
+        );
+        lower(
+            "fun main() -> i32 {
+    mut [i32] x
+    let p = &x
+    let *p = 5
+    return x
+}",
+        );
+    }
+}
@@ -0,0 +1,253 @@
+//! Symbol name mangling for functions, giving every function other than external or exported
+//! functions a unique name in the output object file that encodes its module path and
+//! signature
+//!
+//! A mangled name has the form `_S` followed by each module path component and the function's
+//! name as `<length><name>`, then `E` and the encoded parameter types
+
+use std::fmt::Write;
+
+use crate::{
+    ast::IntegerWidth,
+    ir::{
+        types::{FunType, IrEnumType, IrIntegerType, IrType},
+        TypeId,
+    },
+    Symbol,
+};
+
+use super::{IntermediateDefId, IntermediateModuleId, IrLowerer};
+
+impl<'ctx> IrLowerer<'ctx> {
+    /// Create the mangled symbol name of a function defined in the given module
+    pub(super) fn mangle_fun(
+        &self,
+        module: IntermediateModuleId,
+        name: &Symbol,
+        ty: &FunType,
+    ) -> Symbol {
+        let mut path = vec![];
+        let mut current = module;
+        while let Some(IntermediateDefId::Module(parent)) =
+            self.modules[current].defs.get(&Symbol::from("up"))
+        {
+            path.push(self.modules[current].name);
+            current = *parent;
+        }
+
+        let mut mangled = String::from("_S");
+        for part in path.iter().rev().chain(std::iter::once(name)) {
+            write!(mangled, "{}{}", part.len(), part).unwrap();
+        }
+        mangled.push('E');
+
+        for (param, _) in ty.params.iter() {
+            self.mangle_type(*param, &mut mangled);
+        }
+
+        Symbol::new(mangled)
+    }
+
+    /// Append the mangled encoding of a type to the given string
+    fn mangle_type(&self, ty: TypeId, buf: &mut String) {
+        match &self.ctx[ty] {
+            IrType::Integer(IrIntegerType { signed, width }) => buf.push(match (signed, width) {
+                (true, IntegerWidth::Eight) => 'a',
+                (true, IntegerWidth::Sixteen) => 's',
+                (true, IntegerWidth::ThirtyTwo) => 'i',
+                (true, IntegerWidth::SixtyFour) => 'l',
+                (true, IntegerWidth::OneTwentyEight) => 'n',
+                (true, IntegerWidth::PtrSize) => 'x',
+                (false, IntegerWidth::Eight) => 'h',
+                (false, IntegerWidth::Sixteen) => 't',
+                (false, IntegerWidth::ThirtyTwo) => 'j',
+                (false, IntegerWidth::SixtyFour) => 'm',
+                (false, IntegerWidth::OneTwentyEight) => 'o',
+                (false, IntegerWidth::PtrSize) => 'y',
+            }),
+            IrType::Float(fty) => buf.push(if fty.doublewide { 'd' } else { 'f' }),
+            IrType::Char => buf.push('c'),
+            IrType::Bool => buf.push('b'),
+            IrType::Unit => buf.push('v'),
+            IrType::Alias { name, .. } | IrType::Enum(IrEnumType { name, .. }) => {
+                write!(buf, "N{}{}", name.len(), name).unwrap()
+            }
+            IrType::Ptr(to) => {
+                buf.push('P');
+                self.mangle_type(*to, buf);
+            }
+            IrType::Array(elem, len) => {
+                write!(buf, "A{}_", len).unwrap();
+                self.mangle_type(*elem, buf);
+            }
+            IrType::Slice(elem) => {
+                buf.push('Q');
+                self.mangle_type(*elem, buf);
+            }
+            IrType::Struct(s) => {
+                buf.push('S');
+                if s.packed {
+                    buf.push('K');
+                }
+                for field in s.fields.iter() {
+                    if let Some(align) = field.align {
+                        write!(buf, "L{}_", align).unwrap();
+                    }
+                    write!(buf, "{}{}", field.name.len(), field.name).unwrap();
+                    self.mangle_type(field.ty, buf);
+                }
+                buf.push('E');
+            }
+            IrType::Tuple(elements) => {
+                buf.push('T');
+                for element in elements.iter() {
+                    self.mangle_type(*element, buf);
+                }
+                buf.push('E');
+            }
+            IrType::Sum(variants) => {
+                buf.push('U');
+                for variant in variants.iter() {
+                    self.mangle_type(*variant, buf);
+                }
+                buf.push('E');
+            }
+            IrType::Fun(f) => {
+                buf.push('F');
+                self.mangle_type(f.return_ty, buf);
+                for (param, _) in f.params.iter() {
+                    self.mangle_type(*param, buf);
+                }
+                buf.push('E');
+            }
+            IrType::Opaque(name) => write!(buf, "O{}{}", name.len(), name).unwrap(),
+            IrType::Invalid => buf.push('z'),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::ir::{
+        lower::testing::{lower, lower_modules},
+        value::IrExprKind,
+        IrContext, IrStmtKind, IrTerminator,
+    };
+
+    #[test]
+    fn external_functions_are_not_mangled() {
+        let lowered = lower(
+            "fun ext puts(*u8 s) -> i32
+fun export callback(i32 a) -> i32 { return a }
+fun helper(i32 a) -> i32 { return a }
+
+fun main() -> i32 {
+    return helper(callback(1))
+}",
+        );
+        let symbol = |name| lowered.ctx[lowered.fun(name)].symbol.to_string();
+        assert_eq!(symbol("main"), "_S4mainE");
+        assert_eq!(symbol("__c_main"), "main");
+        assert_eq!(symbol("puts"), "puts");
+        assert_eq!(symbol("callback"), "callback");
+        assert_eq!(symbol("helper"), "_S6helperEi");
+    }
+
+    #[test]
+    fn c_main_returns_exit_code_of_spark_main() {
+        let lowered = lower(
+            "fun main() -> i32 {
+    return 3
+}",
+        );
+        let ctx = &lowered.ctx;
+        let main = lowered.fun("main");
+        let c_main = lowered.fun("__c_main");
+        assert_eq!(ctx.entry, Some(main));
+        assert_eq!(ctx[c_main].ty.return_ty, IrContext::I32);
+        let params = ctx[c_main]
+            .ty
+            .params
+            .iter()
+            .map(|(ty, _)| ctx.typename(*ty).to_string())
+            .collect::<Vec<_>>();
+        assert_eq!(params, ["i32", "**u8"]);
+
+        let entry = &ctx[ctx.fun_body(c_main).unwrap().entry];
+        assert!(matches!(
+            entry.stmts.as_slice(),
+            [stmt] if matches!(&stmt.kind, IrStmtKind::Call { fun, .. } if ctx[*fun].name.as_str() == "__global_setup")
+        ));
+        assert!(matches!(
+            &entry.terminator,
+            IrTerminator::Return(expr) if matches!(
+                &expr.kind,
+                IrExprKind::Call(called, _) if matches!(called.kind, IrExprKind::Fun(fun) if fun == main)
+            )
+        ));
+    }
+
+    #[test]
+    fn unit_main_keeps_its_type_and_c_main_returns_zero() {
+        let lowered = lower(
+            "glob mut counter = 1
+
+fun bump() {
+    counter = counter + 1
+}
+
+fun main() {
+    return bump()
+}",
+        );
+        let ctx = &lowered.ctx;
+        let main = lowered.fun("main");
+        assert_eq!(ctx[main].ty.return_ty, IrContext::UNIT);
+
+        let is_setup = |kind: &IrStmtKind| matches!(kind, IrStmtKind::Call { fun, .. } if ctx[*fun].name.as_str() == "__global_setup");
+        let entry = &ctx[ctx.fun_body(main).unwrap().entry];
+        assert!(!entry.stmts.iter().any(|stmt| is_setup(&stmt.kind)));
+
+        let c_main = &ctx[ctx.fun_body(lowered.fun("__c_main")).unwrap().entry];
+        assert!(matches!(
+            c_main.stmts.as_slice(),
+            [setup, stmt] if is_setup(&setup.kind)
+                && matches!(&stmt.kind, IrStmtKind::Exec(call) if matches!(call.kind, IrExprKind::Call(..)))
+        ));
+        assert!(matches!(
+            &c_main.terminator,
+            IrTerminator::Return(expr) if expr.ty == IrContext::I32
+        ));
+    }
+
+    #[test]
+    fn same_named_functions_in_different_modules_have_different_symbols() {
+        let lowered = lower_modules(
+            "fun main() -> i32 {
+    return a:value() + b:value()
+}",
+            &[
+                (
+                    "a",
+                    "fun init() -> i32 { return 1 }\npub fun value() -> i32 { return init() }",
+                ),
+                (
+                    "b",
+                    "fun init() -> i32 { return 20 }\npub fun value() -> i32 { return init() * 2 }",
+                ),
+            ],
+        );
+        let mut symbols = lowered
+            .ctx
+            .funs
+            .iter()
+            .filter(|fun| fun.name.as_str() == "init" || fun.name.as_str() == "value")
+            .map(|fun| fun.symbol.to_string())
+            .collect::<Vec<_>>();
+        symbols.sort();
+        assert_eq!(
+            symbols,
+            ["_S1a4initE", "_S1a5valueE", "_S1b4initE", "_S1b5valueE"]
+        );
+    }
+}
@@ -0,0 +1,418 @@
+//! Resolution of method calls like `value.len()` to functions that take the type of the value
+//! they are called on as their first parameter
+
+use codespan_reporting::diagnostic::{Diagnostic, Label};
+
+use crate::{
+    ast::Expr,
+    ir::{
+        types::{FunType, IrType},
+        value::{IrExpr, IrExprKind},
+        FunId, TypeId,
+    },
+    parse::token::Op,
+    util::{files::FileId, loc::Span},
+    Symbol,
+};
+
+use super::{IntermediateDefId, IntermediateModuleId, IrLowerer};
+
+impl<'ctx> IrLowerer<'ctx> {
+    /// Resolve a call of the form `object.name(args)` where `object` has no field called `name`,
+    /// returning the called function and the receiver to pass as its first argument.
+    /// The called function is either the function called `name` in the current module or a
+    /// function called `name` defined alongside the receiver's type, and must take the receiver's
+    /// type or a pointer to it as its first parameter.
+    /// The receiver is dereferenced or has its address taken to match the type of that parameter
+    pub(super) fn resolve_method(
+        &mut self,
+        module: IntermediateModuleId,
+        file: FileId,
+        span: Span,
+        object: IrExpr,
+        name: &Symbol,
+    ) -> Result<(IrExpr, IrExpr), Diagnostic<FileId>> {
+        let receiver = self.receiver_type(object.ty);
+        let candidates = self
+            .method_candidates(module, receiver, name)
+            .into_iter()
+            .filter(|method| self.receiver_param(*method, receiver).is_some())
+            .collect::<Vec<_>>();
+
+        let method = match candidates.as_slice() {
+            [method] => *method,
+            [] => return Err(self.no_method(module, file, span, receiver, name)),
+            _ => {
+                return Err(Diagnostic::error()
+                    .with_message(format!(
+                        "Method call {} on type {} is ambiguous",
+                        name,
+                        self.ctx.typename(receiver)
+                    ))
+                    .with_labels(
+                        std::iter::once(
+                            Label::primary(file, span).with_message("Method is called here"),
+                        )
+                        .chain(candidates.iter().map(|candidate| {
+                            Label::secondary(self.ctx[*candidate].file, self.ctx[*candidate].span)
+                                .with_message("Candidate function defined here")
+                        }))
+                        .collect(),
+                    )
+                    .with_notes(vec![format!(
+                        "Call the function by its path with {} as the first argument instead",
+                        self.ctx.typename(object.ty)
+                    )]))
+            }
+        };
+
+        let by_ptr = self.receiver_param(method, receiver) == Some(true);
+        let receiver_arg = self.receiver_arg(file, object, receiver, by_ptr)?;
+        Ok((
+            IrExpr {
+                kind: IrExprKind::Fun(method),
+                ty: self.ctx[method].ty_id,
+                span,
+            },
+            receiver_arg,
+        ))
+    }
+
+    /// Lower the arguments of a method call after the receiver, which is passed as the first
+    /// argument
+    pub(super) fn lower_method_args(
+        &mut self,
+        module: IntermediateModuleId,
+        file: FileId,
+        fun: FunId,
+        fun_ty: &FunType,
+        receiver: IrExpr,
+        args: &[Expr],
+    ) -> Result<Vec<IrExpr>, Diagnostic<FileId>> {
+        let rest_ty = FunType {
+            return_ty: fun_ty.return_ty,
+            params: fun_ty.params[1..].to_vec(),
+        };
+        let mut lowered = vec![receiver];
+        lowered.extend(self.lower_args(module, file, fun, &rest_ty, args)?);
+        Ok(lowered)
+    }
+
+    /// Get the type that methods are looked up for when called on a value of the given type,
+    /// removing every level of pointer indirection
+    fn receiver_type(&self, mut ty: TypeId) -> TypeId {
+        while let IrType::Ptr(pointee) = &self.ctx[self.ctx.unwrap_alias(ty)] {
+            ty = *pointee;
+        }
+        ty
+    }
+
+    /// Get every function called `name` that may be called as a method of the receiver type: the
+    /// function of that name in the current module, and functions of that name in the modules
+    /// containing the receiver's type that are defined in the same file as the type
+    fn method_candidates(
+        &self,
+        module: IntermediateModuleId,
+        receiver: TypeId,
+        name: &Symbol,
+    ) -> Vec<FunId> {
+        let mut candidates = vec![];
+        if let Some(IntermediateDefId::Fun(method, ..)) = self.modules[module].defs.get(name) {
+            candidates.push(*method);
+        }
+
+        for (ty_module, ty_file) in self.receiver_modules(receiver) {
+            if let Some(IntermediateDefId::Fun(method, ..)) = self.modules[ty_module].defs.get(name)
+            {
+                if self.ctx[*method].file == ty_file
+                    && self.is_visible(module, ty_module, *name)
+                    && !candidates.contains(method)
+                {
+                    candidates.push(*method);
+                }
+            }
+        }
+
+        candidates
+    }
+
+    /// Get every module that contains the receiver type, along with the file that the type is
+    /// defined in
+    fn receiver_modules(
+        &self,
+        receiver: TypeId,
+    ) -> impl Iterator<Item = (IntermediateModuleId, FileId)> + '_ {
+        self.modules.indices().filter_map(move |module| {
+            self.modules[module]
+                .defs
+                .values()
+                .find_map(|def| match def {
+                    IntermediateDefId::Type(ty, ty_file, _) if *ty == receiver => {
+                        Some((module, *ty_file))
+                    }
+                    _ => None,
+                })
+        })
+    }
+
+    /// Check if a function can be called as a method of the receiver type, returning `Some(true)`
+    /// if the function takes a pointer to the receiver and `Some(false)` if it takes the receiver
+    /// by value
+    fn receiver_param(&self, method: FunId, receiver: TypeId) -> Option<bool> {
+        let (param, _) = self.ctx[method].ty.params.first()?;
+        if *param == receiver {
+            return Some(false);
+        }
+
+        match &self.ctx[self.ctx.unwrap_alias(*param)] {
+            IrType::Ptr(pointee) if *pointee == receiver => Some(true),
+            _ => None,
+        }
+    }
+
+    /// Convert the object that a method is called on to the type of the method's first parameter,
+    /// dereferencing pointers to the receiver type and taking the address of the object when the
+    /// method takes a pointer
+    fn receiver_arg(
+        &mut self,
+        file: FileId,
+        mut object: IrExpr,
+        receiver: TypeId,
+        by_ptr: bool,
+    ) -> Result<IrExpr, Diagnostic<FileId>> {
+        while object.ty != receiver {
+            let pointee = match &self.ctx[self.ctx.unwrap_alias(object.ty)] {
+                IrType::Ptr(pointee) => *pointee,
+                _ => break,
+            };
+            if by_ptr && pointee == receiver {
+                return Ok(object);
+            }
+
+            object = IrExpr {
+                span: object.span,
+                ty: pointee,
+                kind: IrExprKind::Unary(Op::Star, Box::new(object)),
+            };
+        }
+
+        if !by_ptr {
+            return Ok(object);
+        }
+
+        let place = match Self::is_place(&object) {
+            true => {
+                self.check_addressable(file, &object)?;
+                object
+            }
+            false => self.materialize(file, object)?,
+        };
+        self.place_addr(file, place)
+    }
+
+    /// Create an error for a method call with no function to call, suggesting functions with
+    /// similar names that can be called on the receiver type
+    fn no_method(
+        &self,
+        module: IntermediateModuleId,
+        file: FileId,
+        span: Span,
+        receiver: TypeId,
+        name: &Symbol,
+    ) -> Diagnostic<FileId> {
+        let mut notes = vec![];
+
+        let similar = self.modules[module]
+            .defs
+            .iter()
+            .map(|(def_name, def)| (def_name, def, None))
+            .chain(
+                self.receiver_modules(receiver)
+                    .flat_map(|(ty_module, ty_file)| {
+                        self.modules[ty_module]
+                            .defs
+                            .iter()
+                            .filter(move |(def_name, _)| {
+                                self.is_visible(module, ty_module, **def_name)
+                            })
+                            .map(move |(def_name, def)| (def_name, def, Some(ty_file)))
+                    }),
+            )
+            .filter_map(|(def_name, def, ty_file)| match def {
+                IntermediateDefId::Fun(method, ..)
+                    if (ty_file.is_none() || ty_file == Some(self.ctx[*method].file))
+                        && self.receiver_param(*method, receiver).is_some()
+                        && edit_distance(def_name, name) <= (name.len() / 3).max(1) =>
+                {
+                    Some(def_name)
+                }
+                _ => None,
+            })
+            .min_by_key(|def_name| edit_distance(def_name, name));
+        if let Some(similar) = similar {
+            notes.push(format!("Did you mean {}?", similar));
+        }
+
+        if let Some(IntermediateDefId::Fun(free, ..)) = self.modules[module].defs.get(name) {
+            notes.push(match self.ctx[*free].ty.params.first() {
+                Some((param, _)) => format!(
+                    "Function {} takes a first parameter of type {}, not {} or a pointer to it",
+                    name,
+                    self.ctx.typename(*param),
+                    self.ctx.typename(receiver),
+                ),
+                None => format!("Function {} takes no parameters", name),
+            });
+        }
+
+        Diagnostic::error()
+            .with_message(format!(
+                "No method named {} on type {}",
+                name,
+                self.ctx.typename(receiver)
+            ))
+            .with_labels(vec![
+                Label::primary(file, span).with_message("Method is called here")
+            ])
+            .with_notes(notes)
+    }
+}
+
+/// Get the number of single character insertions, deletions, and substitutions needed to turn
+/// one string into another
+pub(super) fn edit_distance(from: &str, to: &str) -> usize {
+    let to = to.chars().collect::<Vec<_>>();
+    let mut prev = (0..=to.len()).collect::<Vec<_>>();
+    for (i, from_ch) in from.chars().enumerate() {
+        let mut row = vec![i + 1];
+        for (j, to_ch) in to.iter().enumerate() {
+            let substitute = prev[j] + usize::from(from_ch != *to_ch);
+            row.push(substitute.min(prev[j + 1] + 1).min(row[j] + 1));
+        }
+        prev = row;
+    }
+    prev[to.len()]
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        ir::{
+            lower::testing::{lower_modules, lower_modules_err, Lowered},
+            value::{IrExpr, IrExprKind},
+            IrTerminator,
+        },
+        parse::token::Op,
+    };
+
+    const CHILDREN: &[(&str, &str)] = &[
+        (
+            "vec",
+            "pub type Vec = { i32 n }
+pub fun len(*Vec v) -> i32 { return v.n }",
+        ),
+        (
+            "list",
+            "pub type List = { i32 n, i32 extra }
+pub fun len(List l) -> i32 { return l.n + l.extra }",
+        ),
+    ];
+
+    /// Get the type of the first parameter of every function called in the value returned by a
+    /// function, and whether the address of the receiver was taken to call it
+    fn returned_calls(lowered: &Lowered, fun: &str) -> Vec<(String, bool)> {
+        fn visit(lowered: &Lowered, expr: &IrExpr, calls: &mut Vec<(String, bool)>) {
+            let ctx = &lowered.ctx;
+            match &expr.kind {
+                IrExprKind::Binary(lhs, _, rhs) => {
+                    visit(lowered, lhs, calls);
+                    visit(lowered, rhs, calls);
+                }
+                IrExprKind::Call(called, args) => {
+                    let called = match called.kind {
+                        IrExprKind::Fun(called) => called,
+                        _ => panic!("called a function pointer"),
+                    };
+                    let param = ctx[called].ty.params[0].0;
+                    let by_addr = matches!(args[0].kind, IrExprKind::Unary(Op::AND, _));
+                    calls.push((ctx.typename(param).to_string(), by_addr));
+                }
+                _ => (),
+            }
+        }
+
+        let ctx = &lowered.ctx;
+        let mut calls = vec![];
+        for bb in ctx.blocks_of(lowered.fun(fun)) {
+            if let IrTerminator::Return(expr) = &ctx[bb].terminator {
+                visit(lowered, expr, &mut calls);
+            }
+        }
+        calls
+    }
+
+    #[test]
+    fn method_is_resolved_by_receiver_type() {
+        let lowered = lower_modules(
+            "imp vec:Vec
+imp list:List
+fun count(mut Vec v, List l) -> i32 {
+    return v.len() + l.len()
+}",
+            CHILDREN,
+        );
+        assert_eq!(
+            returned_calls(&lowered, "count"),
+            [("*Vec".to_owned(), true), ("List".to_owned(), false)]
+        );
+    }
+
+    #[test]
+    fn method_on_pointer_receiver_is_not_dereferenced() {
+        let lowered = lower_modules(
+            "imp vec:Vec
+imp list:List
+fun count(*Vec v, **List l) -> i32 {
+    return v.len() + l.len()
+}",
+            CHILDREN,
+        );
+        assert_eq!(
+            returned_calls(&lowered, "count"),
+            [("*Vec".to_owned(), false), ("List".to_owned(), false)]
+        );
+    }
+
+    #[test]
+    fn missing_method_suggests_similar_name() {
+        let err = lower_modules_err(
+            "imp vec:Vec
+fun count(mut Vec v) -> i32 {
+    return v.lens()
+}",
+            CHILDREN,
+        );
+        assert_eq!(err.message, "No method named lens on type Vec");
+        assert_eq!(err.notes, vec!["Did you mean len?".to_owned()]);
+    }
+
+    #[test]
+    fn free_function_and_method_are_ambiguous() {
+        let err = lower_modules_err(
+            "imp vec:Vec
+fun len(Vec v) -> i32 { return 0 }
+fun count(mut Vec v) -> i32 {
+    return v.len()
+}",
+            CHILDREN,
+        );
+        assert_eq!(err.message, "Method call len on type Vec is ambiguous");
+        assert_eq!(
+            err.labels
+                .iter()
+                .filter(|label| label.message == "Candidate function defined here")
+                .count(),
+            2
+        );
+    }
+}
@@ -1,17 +1,19 @@
 use codespan_reporting::diagnostic::{Diagnostic, Label};
 
 use crate::{
-    ast::Expr,
+    ast::{BigInt, Expr, ExprNode, IntegerWidth, Literal, NumberLiteral},
     ir::{
-        types::IrType,
-        value::{IrExpr, IrExprKind},
-        FunId, IrContext, TypeId,
+        consteval::{ConstEvalError, ConstValue},
+        types::{IrIntegerType, IrType},
+        value::{CastKind, IrExpr, IrExprKind, IrLiteral},
+        FunId, IrBranch, IrContext, IrStmt, IrStmtKind, IrTerminator, IrVar, TypeId,
     },
     parse::token::Op,
     util::{files::FileId, loc::Span},
+    Symbol,
 };
 
-use super::{IntermediateModuleId, IrLowerer};
+use super::{IntermediateModuleId, IrLowerer, LintFlags};
 
 impl<'ctx> IrLowerer<'ctx> {
     /// Lower a binary expression to IR
@@ -24,45 +26,88 @@ impl<'ctx> IrLowerer<'ctx> {
         op: Op,
         rhs: &Expr,
     ) -> Result<IrExpr, Diagnostic<FileId>> {
+        //Global initializers are all lowered into the entry block of the global setup function,
+        //so both operands are evaluated there instead of jumping between blocks
+        if let (Op::LogicalAnd | Op::LogicalOr, false) = (op, fun == self.global_setup_fun) {
+            return self.lower_short_circuit(module, file, fun, lhs, op, rhs);
+        }
+
+        let untyped = (Self::is_untyped_int(lhs), Self::is_untyped_int(rhs));
         let lhs = self.lower_expr(module, file, fun, lhs)?;
         let rhs = self.lower_expr(module, file, fun, rhs)?;
+        let (lhs, rhs) = self.promote_ints(file, lhs, op, rhs, untyped)?;
 
         let ty = match (&self.ctx[lhs.ty], op, &self.ctx[rhs.ty]) {
             (
                 IrType::Bool,
-                Op::LogicalAnd | Op::LogicalOr | Op::LogicalNot | Op::Eq,
+                Op::LogicalAnd
+                | Op::LogicalOr
+                | Op::LogicalNot
+                | Op::Eq
+                | Op::AND
+                | Op::OR
+                | Op::XOR,
                 IrType::Bool,
             ) => IrContext::BOOL,
             (
                 IrType::Integer(_),
-                Op::Eq
-                | Op::Greater
-                | Op::GreaterEq
-                | Op::Less
-                | Op::LessEq
-                | Op::Star
+                Op::Eq | Op::Greater | Op::GreaterEq | Op::Less | Op::LessEq,
+                IrType::Integer(_),
+            )
+            | (
+                IrType::Float(_),
+                Op::Eq | Op::Greater | Op::GreaterEq | Op::Less | Op::LessEq,
+                IrType::Float(_),
+            ) => IrContext::BOOL,
+            (
+                IrType::Ptr(_),
+                Op::Eq | Op::Greater | Op::GreaterEq | Op::Less | Op::LessEq,
+                IrType::Ptr(_),
+            ) if lhs.ty == rhs.ty => IrContext::BOOL,
+            (
+                IrType::Integer(_),
+                Op::Star
                 | Op::Div
+                | Op::Mod
                 | Op::Add
                 | Op::Sub
                 | Op::ShLeft
-                | Op::ShRight,
+                | Op::ShRight
+                | Op::AND
+                | Op::OR
+                | Op::XOR,
                 IrType::Integer(_),
             ) => lhs.ty,
             (
                 IrType::Float(_),
-                Op::Eq
-                | Op::Greater
-                | Op::GreaterEq
-                | Op::Less
-                | Op::LessEq
-                | Op::Star
-                | Op::Div
-                | Op::Add
-                | Op::Sub,
+                Op::Star | Op::Div | Op::Mod | Op::Add | Op::Sub,
                 IrType::Float(_),
             ) => lhs.ty,
             (IrType::Ptr(_), Op::ShRight | Op::ShLeft, IrType::Integer(_)) => lhs.ty,
-            (IrType::Ptr(_), Op::Add | Op::Sub, IrType::Ptr(_) | IrType::Integer(_)) => lhs.ty,
+            //The difference of two pointers is the number of elements between them
+            (IrType::Ptr(l), Op::Sub, IrType::Ptr(r)) => {
+                if self.ctx.unwrap_alias(*l) != self.ctx.unwrap_alias(*r) {
+                    return Err(Diagnostic::error()
+                        .with_message(format!(
+                            "Cannot subtract pointers to different types {} and {}",
+                            self.ctx.typename(*l),
+                            self.ctx.typename(*r),
+                        ))
+                        .with_labels(vec![
+                            Label::primary(file, Span::from(lhs.span.from..rhs.span.to)),
+                            Label::secondary(file, lhs.span).with_message(format!(
+                                "LHS of type {} appears here",
+                                self.ctx.typename(lhs.ty)
+                            )),
+                            Label::secondary(file, rhs.span).with_message(format!(
+                                "RHS of type {} appears here",
+                                self.ctx.typename(rhs.ty)
+                            )),
+                        ]));
+                }
+                IrContext::ISIZE
+            }
+            (IrType::Ptr(_), Op::Add | Op::Sub, IrType::Integer(_)) => lhs.ty,
             _ => {
                 return Err(Diagnostic::error()
                     .with_message(format!(
@@ -85,10 +130,366 @@ impl<'ctx> IrLowerer<'ctx> {
             }
         };
 
+        self.fold(
+            file,
+            IrExpr {
+                span: Span::from(lhs.span.from..rhs.span.to),
+                ty,
+                kind: IrExprKind::Binary(Box::new(lhs), op, Box::new(rhs)),
+            },
+        )
+    }
+
+    /// Lower a logical and or logical or expression to a jump that only evaluates the right hand
+    /// side if the left hand side does not decide the result, passing the result to a parameter
+    /// of the block after the expression
+    fn lower_short_circuit(
+        &mut self,
+        module: IntermediateModuleId,
+        file: FileId,
+        fun: FunId,
+        lhs: &Expr,
+        op: Op,
+        rhs: &Expr,
+    ) -> Result<IrExpr, Diagnostic<FileId>> {
+        let lhs = self.lower_expr(module, file, fun, lhs)?;
+        self.expect_logical_operand(file, &lhs, op)?;
+        let decided = op == Op::LogicalOr;
+
+        //A constant left hand side either decides the result or makes it the right hand side, so
+        //constant expressions can use logical operators
+        let constant = match self.const_eval(&lhs) {
+            Ok(ConstValue::Bool(val)) => Some(val),
+            _ => None,
+        };
+        if constant == Some(!decided) {
+            let rhs = self.lower_expr(module, file, fun, rhs)?;
+            self.expect_logical_operand(file, &rhs, op)?;
+            return Ok(rhs);
+        }
+
+        let old_bb = self.bb();
+        let rhs_bb = self.new_bb(fun);
+        *self.bb_mut() = rhs_bb;
+        let rhs = self.lower_expr(module, file, fun, rhs)?;
+        self.expect_logical_operand(file, &rhs, op)?;
+        let span = Span::from(lhs.span.from..rhs.span.to);
+
+        //The right hand side is still type checked, but its block is never jumped to
+        if constant.is_some() {
+            let current = self.bb();
+            self.terminate(current, IrTerminator::Unreachable, span);
+            *self.bb_mut() = old_bb;
+            return Ok(lhs);
+        }
+
+        let after_bb = self.new_bb(fun);
+        self.ctx[after_bb].params.push(IrContext::BOOL);
+        let current = self.bb();
+        self.terminate(
+            current,
+            IrTerminator::Jmp(IrBranch {
+                bb: after_bb,
+                args: vec![rhs],
+            }),
+            span,
+        );
+
+        let skip_rhs = IrBranch {
+            bb: after_bb,
+            args: vec![IrExpr {
+                span: lhs.span,
+                ty: IrContext::BOOL,
+                kind: IrExprKind::Lit(IrLiteral::Bool(decided)),
+            }],
+        };
+        let (if_true, if_false) = match decided {
+            true => (skip_rhs, rhs_bb.into()),
+            false => (rhs_bb.into(), skip_rhs),
+        };
+        self.terminate(
+            old_bb,
+            IrTerminator::JmpIf {
+                condition: lhs,
+                if_true,
+                if_false,
+            },
+            span,
+        );
+        *self.bb_mut() = after_bb;
+
+        Ok(IrExpr {
+            span,
+            ty: IrContext::BOOL,
+            kind: IrExprKind::Param(after_bb, 0),
+        })
+    }
+
+    /// Check that an operand of a logical and or logical or operator is a boolean
+    fn expect_logical_operand(
+        &self,
+        file: FileId,
+        operand: &IrExpr,
+        op: Op,
+    ) -> Result<(), Diagnostic<FileId>> {
+        match self.ctx[self.ctx.unwrap_alias(operand.ty)] {
+            IrType::Bool => Ok(()),
+            _ => Err(Diagnostic::error()
+                .with_message(format!(
+                    "Cannot apply logical operator {} to an operand of type {}",
+                    op,
+                    self.ctx.typename(operand.ty),
+                ))
+                .with_labels(vec![Label::primary(file, operand.span)
+                    .with_message("Expected a value of type bool here")])),
+        }
+    }
+
+    /// Convert the integer operands of a binary operator to a common type. Operands with the same
+    /// signedness widen to the larger width, an unsigned operand widens to a wider signed type,
+    /// and an integer literal with no type annotation takes the type of the other operand. Shift
+    /// amounts are never converted
+    fn promote_ints(
+        &self,
+        file: FileId,
+        lhs: IrExpr,
+        op: Op,
+        rhs: IrExpr,
+        (lhs_untyped, rhs_untyped): (bool, bool),
+    ) -> Result<(IrExpr, IrExpr), Diagnostic<FileId>> {
+        let (l, r) = match (&self.ctx[lhs.ty], &self.ctx[rhs.ty]) {
+            (IrType::Integer(l), IrType::Integer(r)) => (*l, *r),
+            _ => return Ok((lhs, rhs)),
+        };
+        if l == r || matches!(op, Op::ShLeft | Op::ShRight) {
+            return Ok((lhs, rhs));
+        }
+
+        //Casts of constants are folded so that literals are retyped in place
+        let implicit_cast = |expr: IrExpr, ty: TypeId| {
+            self.fold(
+                file,
+                IrExpr {
+                    span: expr.span,
+                    ty,
+                    kind: IrExprKind::Cast(Box::new(expr), ty, CastKind::IntResize),
+                },
+            )
+        };
+        if rhs_untyped || Self::int_widens(r, l) {
+            let ty = lhs.ty;
+            Ok((lhs, implicit_cast(rhs, ty)?))
+        } else if lhs_untyped || Self::int_widens(l, r) {
+            let ty = rhs.ty;
+            Ok((implicit_cast(lhs, ty)?, rhs))
+        } else {
+            let (message, reason) = if l.signed != r.signed {
+                let (signed, unsigned) = match l.signed {
+                    true => (lhs.ty, rhs.ty),
+                    false => (rhs.ty, lhs.ty),
+                };
+                let what = match op {
+                    Op::Eq | Op::Greater | Op::GreaterEq | Op::Less | Op::LessEq => "Comparison",
+                    _ => "Arithmetic",
+                };
+                (
+                    format!(
+                        "{} between signed integer type {} and unsigned integer type {}",
+                        what,
+                        self.ctx.typename(signed),
+                        self.ctx.typename(unsigned),
+                    ),
+                    format!(
+                        "Converting a negative {} to {} wraps around to a large positive value, \
+                        and large {} values become negative as {}, so the result may not be what \
+                        is expected",
+                        self.ctx.typename(signed),
+                        self.ctx.typename(unsigned),
+                        self.ctx.typename(unsigned),
+                        self.ctx.typename(signed),
+                    ),
+                )
+            } else {
+                (
+                    format!(
+                        "Cannot implicitly convert between {} and {} in binary operator {}",
+                        self.ctx.typename(lhs.ty),
+                        self.ctx.typename(rhs.ty),
+                        op,
+                    ),
+                    "Integers are not converted implicitly because the width of pointer-sized \
+                    integers depends on the target"
+                        .to_owned(),
+                )
+            };
+            Err(Diagnostic::error()
+                .with_message(message)
+                .with_labels(vec![
+                    Label::primary(file, Span::from(lhs.span.from..rhs.span.to)),
+                    Label::secondary(file, lhs.span).with_message(format!(
+                        "LHS of type {} appears here",
+                        self.ctx.typename(lhs.ty)
+                    )),
+                    Label::secondary(file, rhs.span).with_message(format!(
+                        "RHS of type {} appears here",
+                        self.ctx.typename(rhs.ty)
+                    )),
+                ])
+                .with_notes(vec![
+                    reason,
+                    format!(
+                        "Cast an operand explicitly with ${} or ${}",
+                        self.ctx.typename(lhs.ty),
+                        self.ctx.typename(rhs.ty),
+                    ),
+                ]))
+        }
+    }
+
+    /// Check if an expression is an integer literal with no type annotation
+    fn is_untyped_int(expr: &Expr) -> bool {
+        matches!(
+            &expr.node,
+            ExprNode::Literal(Literal::Number(num @ NumberLiteral::Integer(..)))
+                if num.annotation().is_none()
+        )
+    }
+
+    /// Lower an expression in a context that expects a value of the given type. Number literals
+    /// with no type suffix, and arithmetic on only those literals, take the expected type if it
+    /// is an integer or float type instead of defaulting to `i32` or `f32`
+    pub(super) fn lower_expr_as(
+        &mut self,
+        module: IntermediateModuleId,
+        file: FileId,
+        fun: FunId,
+        expr: &Expr,
+        ty: TypeId,
+    ) -> Result<IrExpr, Diagnostic<FileId>> {
+        let float = match &self.ctx[self.ctx.unwrap_alias(ty)] {
+            IrType::Integer(_) => false,
+            IrType::Float(_) => true,
+            _ => return self.lower_expr(module, file, fun, expr),
+        };
+
+        //Phi statements of number literals in the branches of an if or match take the expected type
+        match &expr.node {
+            ExprNode::If(if_expr) => return self.lower_if(module, file, fun, if_expr, Some(ty)),
+            ExprNode::Match(match_expr) => {
+                return self.lower_match(module, file, fun, match_expr, expr.span, Some(ty))
+            }
+            _ => (),
+        }
+
+        match Self::is_untyped_number(expr, float) {
+            true => self.lower_untyped_number(file, expr, ty, false),
+            false => self.lower_expr(module, file, fun, expr),
+        }
+    }
+
+    /// Check if an expression is made only of number literals with no type suffix, negations of
+    /// them, and arithmetic on them. Float literals are only included if `float` is set
+    pub(super) fn is_untyped_number(expr: &Expr, float: bool) -> bool {
+        match &expr.node {
+            ExprNode::Literal(Literal::Number(num)) => {
+                num.annotation().is_none() && (float || matches!(num, NumberLiteral::Integer(..)))
+            }
+            ExprNode::Unary(Op::Sub, operand) => {
+                matches!(&operand.node, ExprNode::Literal(_) | ExprNode::Unary(..))
+                    && Self::is_untyped_number(operand, float)
+            }
+            ExprNode::Bin(lhs, op, rhs) => {
+                let arithmetic = match op {
+                    Op::Add | Op::Sub | Op::Star | Op::Div | Op::Mod => true,
+                    Op::AND | Op::OR | Op::XOR | Op::ShLeft | Op::ShRight => !float,
+                    _ => false,
+                };
+                arithmetic
+                    && Self::is_untyped_number(lhs, float)
+                    && Self::is_untyped_number(rhs, float)
+            }
+            _ => false,
+        }
+    }
+
+    /// Lower an expression accepted by [is_untyped_number](Self::is_untyped_number) to a value
+    /// of the given integer or float type, failing if a literal or the result of arithmetic does
+    /// not fit in the type
+    fn lower_untyped_number(
+        &self,
+        file: FileId,
+        expr: &Expr,
+        ty: TypeId,
+        negated: bool,
+    ) -> Result<IrExpr, Diagnostic<FileId>> {
+        let num = match &expr.node {
+            ExprNode::Literal(Literal::Number(num)) => num,
+            ExprNode::Unary(_, operand) => {
+                let mut operand = self.lower_untyped_number(file, operand, ty, !negated)?;
+                operand.span = expr.span;
+                return Ok(operand);
+            }
+            ExprNode::Bin(lhs, op, rhs) => {
+                let lhs = self.lower_untyped_number(file, lhs, ty, false)?;
+                let rhs = self.lower_untyped_number(file, rhs, ty, false)?;
+                let bin = IrExpr {
+                    span: expr.span,
+                    ty,
+                    kind: IrExprKind::Binary(Box::new(lhs), *op, Box::new(rhs)),
+                };
+                //Pointer-sized arithmetic is left to the code generator
+                return match self.ctx.const_eval_checked(&bin) {
+                    Ok(val) => Ok(val.to_expr(bin.span, ty)),
+                    Err(e) if e.is_not_const() => Ok(bin),
+                    Err(e) => Err(e.to_diagnostic(file)),
+                };
+            }
+            _ => unreachable!(),
+        };
+
+        let lit = match (num, &self.ctx[self.ctx.unwrap_alias(ty)]) {
+            (NumberLiteral::Integer(val, _), IrType::Integer(ity)) => {
+                let bits = self.target.int_bits(ity.width);
+                let max = match (ity.signed, negated) {
+                    (false, false) => u128::MAX >> (128 - bits),
+                    (false, true) => 0,
+                    (true, false) => (1 << (bits - 1)) - 1,
+                    (true, true) => 1 << (bits - 1),
+                };
+                if val.val > max {
+                    return Err(Diagnostic::error()
+                        .with_message(format!(
+                            "Integer literal {}{} does not fit in type {}",
+                            if negated { "-" } else { "" },
+                            val.val,
+                            self.ctx.typename(ty)
+                        ))
+                        .with_labels(vec![Label::primary(file, expr.span).with_message(
+                            format!("Expected a value of type {} here", self.ctx.typename(ty)),
+                        )]));
+                }
+
+                //Literals are stored as two's complement values
+                let val = match negated {
+                    true => (val.val as i128).wrapping_neg() as u128,
+                    false => val.val,
+                };
+                IrLiteral::Integer(BigInt { val, sign: false }, *ity)
+            }
+            (NumberLiteral::Integer(val, _), IrType::Float(fty)) => {
+                let val = val.val as f64;
+                IrLiteral::Float(if negated { -val } else { val }, *fty)
+            }
+            (NumberLiteral::Float(val, _), IrType::Float(fty)) => {
+                IrLiteral::Float(if negated { -val } else { *val }, *fty)
+            }
+            _ => unreachable!(),
+        };
+
         Ok(IrExpr {
-            span: (lhs.span.from..rhs.span.to).into(),
+            span: expr.span,
             ty,
-            kind: IrExprKind::Binary(Box::new(lhs), op, Box::new(rhs)),
+            kind: IrExprKind::Lit(lit),
         })
     }
 
@@ -101,11 +502,25 @@ impl<'ctx> IrLowerer<'ctx> {
         op: Op,
         expr: &Expr,
     ) -> Result<IrExpr, Diagnostic<FileId>> {
-        let expr = self.lower_expr(module, file, fun, expr)?;
+        let mut expr = self.lower_expr(module, file, fun, expr)?;
 
         let ty = match (op, self.ctx[expr.ty].clone()) {
-            (Op::Star, IrType::Ptr(to)) => to,
-            (Op::AND, _) => self.ctx.types.insert(IrType::Ptr(expr.ty)),
+            (Op::Star, IrType::Ptr(to)) => {
+                self.ensure_sized(to, file, expr.span)?;
+                to
+            }
+            (Op::AND, _) if !Self::is_place(&expr) => {
+                expr = self.materialize(file, expr)?;
+                self.ctx.ptr_to(expr.ty)
+            }
+            (Op::AND, _) => {
+                self.check_addressable(file, &expr)?;
+                self.ctx.ptr_to(expr.ty)
+            }
+            (Op::Sub, IrType::Integer(ity)) if !ity.signed => {
+                expr = self.negate_unsigned(file, expr, ity)?;
+                expr.ty
+            }
             (Op::Sub, IrType::Integer(_) | IrType::Float(_)) => expr.ty,
             (Op::NOT, IrType::Integer(_) | IrType::Ptr(_)) => expr.ty,
             _ => {
@@ -119,13 +534,129 @@ impl<'ctx> IrLowerer<'ctx> {
             }
         };
 
+        self.fold(
+            file,
+            IrExpr {
+                ty,
+                span: expr.span,
+                kind: IrExprKind::Unary(op, Box::new(expr)),
+            },
+        )
+    }
+
+    /// Store a value that isn't a place in memory in a temporary variable so that its address can
+    /// be taken, returning the temporary. The temporary lives until the end of the current scope
+    pub(super) fn materialize(
+        &mut self,
+        file: FileId,
+        expr: IrExpr,
+    ) -> Result<IrExpr, Diagnostic<FileId>> {
+        if self.strict_temporaries {
+            return Err(Diagnostic::error()
+                .with_message("Cannot take the address of a temporary value")
+                .with_labels(vec![Label::primary(file, expr.span).with_message(format!(
+                    "Value of type {} is not stored in memory",
+                    self.ctx.typename(expr.ty)
+                ))])
+                .with_notes(vec![
+                    "Only variables, fields, array elements, and dereferenced pointers have an address".to_owned(),
+                    "Assign the value to a variable and take the address of the variable instead".to_owned(),
+                ]));
+        }
+
+        //Temporaries of global initializers and constants would be locals of the global setup
+        //function, and their addresses would dangle after it returns
+        if self.scope_stack.is_empty() {
+            return Err(Diagnostic::error()
+                .with_message("Cannot take the address of a temporary value outside of a function")
+                .with_labels(vec![Label::primary(file, expr.span).with_message(format!(
+                    "Value of type {} is not stored in memory",
+                    self.ctx.typename(expr.ty)
+                ))])
+                .with_notes(vec![
+                    "Declare a global with the value and take the address of the global instead"
+                        .to_owned(),
+                ]));
+        }
+
+        self.ensure_sized(expr.ty, file, expr.span)?;
+        let current = self.bb();
+        let name = Symbol::new(format!(
+            "@tmp#{}.{}",
+            current,
+            self.ctx[current].stmts.len()
+        ));
+        let var = self.ctx.vars.insert(IrVar {
+            ty: expr.ty,
+            name,
+            align: None,
+            mutable: true,
+        });
+        self.current_scope_mut().vars.insert(name, (var, expr.span));
+
+        let span = expr.span;
+        let ty = expr.ty;
+        self.ctx[current].stmts.push(IrStmt {
+            span,
+            kind: IrStmtKind::VarLive(var),
+        });
+        self.ctx[current].stmts.push(IrStmt {
+            span,
+            kind: IrStmtKind::Store { var, val: expr },
+        });
+
         Ok(IrExpr {
+            span,
             ty,
-            span: expr.span,
-            kind: IrExprKind::Unary(op, Box::new(expr)),
+            kind: IrExprKind::Var(var),
         })
     }
 
+    /// Re-type an unsigned integer literal that is about to be negated as the signed integer type
+    /// of the same width, or produce an error for negating any other unsigned value
+    fn negate_unsigned(
+        &self,
+        file: FileId,
+        expr: IrExpr,
+        ity: IrIntegerType,
+    ) -> Result<IrExpr, Diagnostic<FileId>> {
+        let signed = IrContext::itype(true, ity.width);
+        let bits = self.target.int_bits(ity.width);
+        //Integer literals are lowered as a cast of a 64 or 128 bit literal to the literal's type
+        let fits = match &expr.kind {
+            IrExprKind::Cast(lit, ..) => matches!(
+                &lit.kind,
+                IrExprKind::Lit(IrLiteral::Integer(val, _)) if val.val <= 1 << (bits - 1)
+            ),
+            _ => false,
+        };
+        match expr.kind {
+            IrExprKind::Cast(lit, _, kind) if fits => Ok(IrExpr {
+                span: expr.span,
+                ty: signed,
+                kind: IrExprKind::Cast(lit, signed, kind),
+            }),
+            _ => Err(Diagnostic::error()
+                .with_message(format!(
+                    "Cannot negate a value of unsigned integer type {}",
+                    self.ctx.typename(expr.ty)
+                ))
+                .with_labels(vec![Label::primary(file, expr.span).with_message(format!(
+                    "Operand of type {} appears here",
+                    self.ctx.typename(expr.ty)
+                ))])
+                .with_notes(vec![
+                    "Negating an unsigned integer wraps around to a large positive value"
+                        .to_owned(),
+                    format!(
+                        "Cast the operand to a signed integer with ${} before negating it, or \
+                        subtract it from zero explicitly if wrapping is intended",
+                        self.ctx.typename(signed)
+                    ),
+                ])),
+        }
+    }
+
     /// Lower and typecheck a cast expression
     pub fn lower_cast(
         &mut self,
@@ -134,35 +665,611 @@ impl<'ctx> IrLowerer<'ctx> {
         fun: FunId,
         expr: &Expr,
         ty: TypeId,
+        span: Span,
     ) -> Result<IrExpr, Diagnostic<FileId>> {
         let expr = self.lower_expr(module, file, fun, expr)?;
 
         let uty = self.ctx.unwrap_alias(ty);
         let uexprty = self.ctx.unwrap_alias(expr.ty);
-        match (&self.ctx[uexprty], &self.ctx[uty]) {
-            (IrType::Float(_) | IrType::Integer(_), IrType::Integer(_) | IrType::Float(_)) => (),
-            (IrType::Ptr(_) | IrType::Integer(_), IrType::Ptr(_) | IrType::Integer(_)) => (),
-            (IrType::Ptr(_) | IrType::Fun(_), IrType::Ptr(_) | IrType::Fun(_)) => (),
-            (IrType::Integer(_) | IrType::Char, IrType::Integer(_) | IrType::Char) => (),
-            (IrType::Sum(s), _) if s.contains(&uty) => (),
-            (_, IrType::Sum(s)) if s.contains(&expr.ty) => (),
-            (from, to) if from == to => (),
-            _ => {
-                return Err(Diagnostic::error()
+        let kind = match self.ctx.cast_kind(expr.ty, ty) {
+            Some(kind) => kind,
+            None => {
+                let mut err = Diagnostic::error()
                     .with_message(format!(
                         "Cannot cast an expression of type {} to {}",
                         self.ctx.typename(expr.ty),
                         self.ctx.typename(uty),
                     ))
-                    .with_labels(vec![Label::primary(file, expr.span)
-                        .with_message("Cast expression appears here")]))
+                    .with_labels(vec![
+                        Label::primary(file, span).with_message("Cast expression appears here")
+                    ]);
+                if let IrType::Sum(variants) = &self.ctx[uexprty] {
+                    if variants.contains(&ty) || variants.contains(&uty) {
+                        err = err.with_notes(vec![format!(
+                            "Use a match expression to get the {} value of a {} after checking its variant",
+                            self.ctx.typename(ty),
+                            self.ctx.typename(expr.ty),
+                        )]);
+                    }
+                }
+                return Err(err);
+            }
+        };
+
+        if self.lints.contains(LintFlags::PTR_CAST) {
+            let int = match (&self.ctx[uexprty], &self.ctx[uty]) {
+                (IrType::Ptr(_), IrType::Integer(ity)) | (IrType::Integer(ity), IrType::Ptr(_)) => {
+                    Some(*ity)
+                }
+                _ => None,
+            };
+            if let Some(ity) = int.filter(|ity| ity.width != IntegerWidth::PtrSize) {
+                self.warnings.push(
+                    Diagnostic::warning()
+                        .with_message(format!(
+                            "Cast between {} and {} may truncate on targets with a different pointer width",
+                            self.ctx.typename(expr.ty),
+                            self.ctx.typename(ty),
+                        ))
+                        .with_labels(vec![Label::primary(file, span)
+                            .with_message("Cast expression appears here")])
+                        .with_notes(vec![format!(
+                            "Use the pointer-sized {} type instead",
+                            if ity.signed { "isz" } else { "usz" }
+                        )]),
+                );
             }
         }
 
         Ok(IrExpr {
-            span: expr.span,
+            span,
             ty,
-            kind: IrExprKind::Cast(Box::new(expr), ty),
+            kind: IrExprKind::Cast(Box::new(expr), ty, kind),
         })
     }
+
+    /// Check if every value of an integer type can be represented by another integer type, not
+    /// counting pointer-sized integers that have a different width on each target
+    pub(super) fn int_widens(from: IrIntegerType, to: IrIntegerType) -> bool {
+        from.width != IntegerWidth::PtrSize
+            && to.width != IntegerWidth::PtrSize
+            && (from.signed == to.signed || !from.signed)
+            && from.width < to.width
+    }
+
+    /// Evaluate an operator expression at compile time if its operands are constant, returning
+    /// the expression unchanged if it depends on runtime values
+    pub(super) fn fold(&self, file: FileId, expr: IrExpr) -> Result<IrExpr, Diagnostic<FileId>> {
+        match self.const_eval(&expr) {
+            Ok(val) => Ok(val.to_expr(expr.span, expr.ty)),
+            Err(e) if e.is_not_const() => Ok(expr),
+            Err(e) => Err(e.to_diagnostic(file)),
+        }
+    }
+
+    /// Evaluate an expression at compile time, checking integer arithmetic for overflow if
+    /// overflow checks are enabled or the expression must be evaluated at compile time.
+    /// Otherwise arithmetic wraps the same as it would at runtime
+    pub(super) fn const_eval(&self, expr: &IrExpr) -> Result<ConstValue, ConstEvalError> {
+        match self.overflow_checks || self.in_const {
+            true => self.ctx.const_eval_checked(expr),
+            false => self.ctx.const_eval(expr),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        ir::{
+            layout::TargetSpec,
+            lower::testing::{lower, lower_err, lower_with},
+            value::{CastKind, IrExprKind},
+            IrContext, IrTerminator,
+        },
+        llvm::testing::run,
+    };
+
+    /// Lower a single module for the target with the given triple, returning the error message
+    /// if lowering fails
+    fn lower_for(triple: &str, src: &str) -> Result<(), String> {
+        lower_with(src, &[], |lowerer| {
+            lowerer.set_target(TargetSpec::from_triple(triple))
+        })
+        .map(|_| ())
+        .map_err(|e| e.message)
+    }
+
+    /// Lower a binary operator applied to parameters of the given types, returning the type of
+    /// the result or the error message if the operator can't be applied to the types
+    fn binary_ty(lhs: &str, op: &str, rhs: &str) -> Result<String, String> {
+        let src = format!(
+            "fun f({} a, {} b) {{
+    let _r = a {} b
+}}
+
+fun main() -> i32 {{ return 0 }}",
+            lhs, rhs, op
+        );
+        let lowered = lower_with(&src, &[], |_| ()).map_err(|e| e.message)?;
+        let ctx = &lowered.ctx;
+        let result = ctx
+            .vars
+            .indices()
+            .find(|var| ctx[*var].name.as_str() == "_r")
+            .unwrap();
+        Ok(ctx.typename(ctx[result].ty).to_string())
+    }
+
+    #[test]
+    fn untyped_literals_adopt_expected_type() {
+        let status = run("fun take(u8 x) -> u8 {
+    return x
+}
+
+fun main() -> i32 {
+    let [u8] a = 255
+    mut [u16] b = 0
+    b = 1000
+    let [i8] c = -128
+    return $i32 take(40) + $i32 (a - 250) + $i32 (b / 100) + $i32 (c + 128)
+}");
+        assert_eq!(status, 55);
+    }
+
+    #[test]
+    fn untyped_literal_adopts_struct_field_type() {
+        let lowered = lower(
+            "type S = { u8 small, i64 big }
+
+fun main() -> i32 {
+    let s = #S { small = 255, big = 9000000000 }
+    return 0
+}",
+        );
+        let ctx = &lowered.ctx;
+        let s = ctx
+            .vars
+            .indices()
+            .find(|var| ctx[*var].name.as_str() == "s")
+            .unwrap();
+        assert_eq!(ctx.typename(ctx[s].ty).to_string(), "S");
+    }
+
+    #[test]
+    fn untyped_literal_out_of_range_is_rejected() {
+        let cases = [
+            (
+                "let [u8] x = 300",
+                "Integer literal 300 does not fit in type u8",
+            ),
+            (
+                "let [i8] x = -129",
+                "Integer literal -129 does not fit in type i8",
+            ),
+            (
+                "let [u32] x = -1",
+                "Integer literal -1 does not fit in type u32",
+            ),
+            (
+                "mut [u16] x = 0\n    x = 65536",
+                "Integer literal 65536 does not fit in type u16",
+            ),
+            (
+                "let x = take(256)",
+                "Integer literal 256 does not fit in type u8",
+            ),
+        ];
+        for (body, message) in cases.iter() {
+            let src = format!(
+                "fun take(u8 x) -> u8 {{ return x }}\n\nfun main() -> i32 {{\n    {}\n    return 0\n}}",
+                body
+            );
+            assert_eq!(lower_err(&src).message, *message, "{}", body);
+        }
+
+        let err = lower_err(
+            "fun small() -> u8 {
+    return 300
+}",
+        );
+        assert_eq!(err.message, "Integer literal 300 does not fit in type u8");
+    }
+
+    #[test]
+    fn untyped_literal_arithmetic_is_checked_in_expected_type() {
+        assert_eq!(lower_checked(true, "    let [u8] x = 200 + 55"), Ok(()));
+        let err = lower_checked(true, "    let [u8] x = 200 + 56").unwrap_err();
+        assert!(
+            err.starts_with("Overflow in constant expression"),
+            "{}",
+            err
+        );
+    }
+
+    #[test]
+    fn untyped_literals_in_float_context_are_floats() {
+        let lowered = lower(
+            "fun main() -> i32 {
+    let [f32] x = 1
+    let [f64] y = 2 + 1.5
+    return 0
+}",
+        );
+        let ctx = &lowered.ctx;
+        let names = ["x", "y"]
+            .iter()
+            .map(|name| {
+                let var = ctx
+                    .vars
+                    .indices()
+                    .find(|var| ctx[*var].name.as_str() == *name)
+                    .unwrap();
+                ctx.typename(ctx[var].ty).to_string()
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(names, ["f32", "f64"]);
+
+        let status = run("fun main() -> i32 {
+    let [f32] x = 1
+    let [f64] y = 2 + 1.5
+    return $i32 (x + $f32 y)
+}");
+        assert_eq!(status, 4);
+    }
+
+    #[test]
+    fn modulo_keeps_operand_type() {
+        assert_eq!(binary_ty("i32", "%", "i32"), Ok("i32".to_owned()));
+        assert_eq!(binary_ty("u64", "%", "u64"), Ok("u64".to_owned()));
+        assert_eq!(binary_ty("f64", "%", "f64"), Ok("f64".to_owned()));
+        assert_eq!(
+            binary_ty("*u8", "%", "i32"),
+            Err("Cannot apply binary operator % to operand types *u8 and i32".to_owned())
+        );
+    }
+
+    #[test]
+    fn modulo_uses_operand_signedness() {
+        let status = run("fun main() -> i32 {
+    mut signed = -7
+    mut unsigned = $u32 signed
+    signed = signed % 5
+    unsigned = unsigned % 5u32
+    return (signed + 10) * 10 + $i32 unsigned
+}");
+        assert_eq!(status, 84);
+    }
+
+    #[test]
+    fn modulo_by_constant_zero_is_rejected() {
+        let err = lower_err(
+            "fun main() -> i32 {
+    return 7 % (3 - 3)
+}",
+        );
+        assert_eq!(err.message, "Division by zero in constant expression");
+    }
+
+    #[test]
+    fn integer_promotion_table() {
+        //Name, signedness, and width of each integer type, with no width for pointer-sized types
+        let types = [
+            ("i8", true, Some(8)),
+            ("i16", true, Some(16)),
+            ("i32", true, Some(32)),
+            ("i64", true, Some(64)),
+            ("i128", true, Some(128)),
+            ("isz", true, None),
+            ("u8", false, Some(8)),
+            ("u16", false, Some(16)),
+            ("u32", false, Some(32)),
+            ("u64", false, Some(64)),
+            ("u128", false, Some(128)),
+            ("usz", false, None),
+        ];
+        //Same signedness widens to the wider type, and unsigned types widen to any wider signed
+        //type because every value still fits
+        type Int = (&'static str, bool, Option<u32>);
+        let widens = |(_, from_signed, from): Int, (_, to_signed, to): Int| {
+            matches!((from, to), (Some(from), Some(to)) if from < to)
+                && (from_signed == to_signed || !from_signed)
+        };
+
+        for l in types {
+            for r in types {
+                let result = binary_ty(l.0, "+", r.0);
+                let expected = if l == r || widens(r, l) {
+                    Some(l.0)
+                } else if widens(l, r) {
+                    Some(r.0)
+                } else {
+                    None
+                };
+
+                match (expected, result) {
+                    (Some(expected), Ok(ty)) => assert_eq!(ty, expected, "{} + {}", l.0, r.0),
+                    (None, Err(msg)) if l.1 != r.1 => assert!(
+                        msg.starts_with("Arithmetic between signed integer type"),
+                        "{} + {}: {}",
+                        l.0,
+                        r.0,
+                        msg
+                    ),
+                    (None, Err(msg)) => assert_eq!(
+                        msg,
+                        format!(
+                            "Cannot implicitly convert between {} and {} in binary operator +",
+                            l.0, r.0
+                        )
+                    ),
+                    (expected, result) => panic!(
+                        "{} + {} expected {:?} but got {:?}",
+                        l.0, r.0, expected, result
+                    ),
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn implicit_widening_casts_keep_operand_spans() {
+        let lowered = lower(
+            "fun f(i32 a, i64 b) -> i64 {
+    return a + b
+}
+
+fun main() -> i32 { return 0 }",
+        );
+        let ctx = &lowered.ctx;
+        let body = ctx.fun_body(lowered.fun("f")).unwrap();
+        let returned = body
+            .blocks
+            .iter()
+            .find_map(|bb| match &ctx[*bb].terminator {
+                IrTerminator::Return(val) => Some(val),
+                _ => None,
+            })
+            .unwrap();
+        let lhs = match &returned.kind {
+            IrExprKind::Binary(lhs, _, _) => lhs,
+            other => panic!("returned {:?}", other),
+        };
+        match &lhs.kind {
+            IrExprKind::Cast(operand, ty, CastKind::IntResize) => {
+                assert_eq!(*ty, IrContext::I64);
+                assert_eq!(lhs.span, operand.span);
+            }
+            other => panic!("left operand is {:?}", other),
+        }
+    }
+
+    #[test]
+    fn mixed_signedness_comparison_is_rejected() {
+        let err = lower_err(
+            "fun f(i32 a, u32 b) -> i32 {
+    if a < b { return 1 }
+    return 0
+}
+
+fun main() -> i32 { return 0 }",
+        );
+        assert_eq!(
+            err.message,
+            "Comparison between signed integer type i32 and unsigned integer type u32"
+        );
+        assert_eq!(
+            err.labels
+                .iter()
+                .skip(1)
+                .map(|label| label.message.as_str())
+                .collect::<Vec<_>>(),
+            vec![
+                "LHS of type i32 appears here",
+                "RHS of type u32 appears here"
+            ]
+        );
+        assert_eq!(err.notes[1], "Cast an operand explicitly with $i32 or $u32");
+        assert_eq!(
+            binary_ty("u64", "-", "i64").map_err(|msg| msg.starts_with("Arithmetic between")),
+            Err(true)
+        );
+    }
+
+    #[test]
+    fn literal_compared_with_unsigned_takes_its_type() {
+        let lowered = lower(
+            "fun f(u32 b) -> i32 {
+    if 5 < b { return 1 }
+    return 0
+}
+
+fun main() -> i32 { return 0 }",
+        );
+        let ctx = &lowered.ctx;
+        let body = ctx.fun_body(lowered.fun("f")).unwrap();
+        let condition = match &ctx[body.entry].terminator {
+            IrTerminator::JmpIf { condition, .. } => condition,
+            other => panic!("entry ends with {:?}", other),
+        };
+        match &condition.kind {
+            IrExprKind::Binary(lhs, _, rhs) => {
+                assert_eq!(lhs.ty, IrContext::U32);
+                assert_eq!(rhs.ty, IrContext::U32);
+            }
+            other => panic!("condition is {:?}", other),
+        }
+    }
+
+    #[test]
+    fn bitwise_operators_mask_flags() {
+        let status = run("fun main() -> i32 {
+    mut flags = 0u8
+    flags = flags | 4u8
+    flags = flags | 1u8
+    flags = flags | 64u8
+    flags = flags & (255u8 ^ 64u8)
+    if (flags & 4u8) == 4u8 {
+        return $i32 (flags ^ 16u8)
+    }
+    return 0
+}");
+        assert_eq!(status, 21);
+    }
+
+    #[test]
+    fn bitwise_operators_on_bools_are_bools() {
+        let lowered = lower(
+            "fun f(i32 a, i32 b) {
+    let _r = (a == 1) & (b == 2) | (a == b) ^ (b == 0)
+}
+
+fun main() -> i32 { return 0 }",
+        );
+        let ctx = &lowered.ctx;
+        let result = ctx
+            .vars
+            .indices()
+            .find(|var| ctx[*var].name.as_str() == "_r")
+            .unwrap();
+        assert_eq!(ctx[result].ty, IrContext::BOOL);
+        assert_eq!(binary_ty("i16", "|", "i64"), Ok("i64".to_owned()));
+    }
+
+    #[test]
+    fn bitwise_operators_on_floats_are_rejected() {
+        for op in ["&", "|", "^"] {
+            assert_eq!(
+                binary_ty("f32", op, "f32"),
+                Err(format!(
+                    "Cannot apply binary operator {} to operand types f32 and f32",
+                    op
+                ))
+            );
+        }
+    }
+
+    #[test]
+    fn temporary_address_in_global_is_rejected() {
+        let err = lower_err(
+            "glob p = &5
+fun main() -> i32 { return 0 }",
+        );
+        assert_eq!(
+            err.message,
+            "Cannot take the address of a temporary value outside of a function"
+        );
+    }
+
+    #[test]
+    fn temporary_address_in_const_is_rejected() {
+        let err = lower_err(
+            "const P = &5
+fun main() -> i32 { return 0 }",
+        );
+        assert_eq!(
+            err.message,
+            "Cannot take the address of a temporary value outside of a function"
+        );
+    }
+
+    #[test]
+    fn short_circuit_in_function_branches() {
+        let lowered = lower(
+            "fun f() -> i32 { return 1 }
+fun main() -> i32 {
+    let x = f() == 1 && f() == 2
+    return 0
+}",
+        );
+        let ctx = &lowered.ctx;
+        let body = ctx.fun_body(lowered.fun("main")).unwrap();
+        assert!(matches!(
+            ctx[body.entry].terminator,
+            IrTerminator::JmpIf { .. }
+        ));
+    }
+
+    #[test]
+    fn short_circuit_in_global_initializer() {
+        let lowered = lower(
+            "fun f() -> i32 { return 1 }
+glob x = false
+glob g = f() == 1 && x
+glob h = x || f() == 2
+fun main() -> i32 { return 0 }",
+        );
+        let ctx = &lowered.ctx;
+        let body = ctx.fun_body(lowered.fun("__global_setup")).unwrap();
+        assert_eq!(body.blocks, vec![body.entry]);
+        assert!(matches!(
+            ctx[body.entry].terminator,
+            IrTerminator::Return(_)
+        ));
+        assert_eq!(ctx[body.entry].stmts.len(), 3);
+    }
+
+    #[test]
+    fn negated_pointer_sized_literal_uses_target_width() {
+        let src = "fun main() -> i32 {
+    let x = -40000usize
+    return 0
+}";
+        assert_eq!(lower_for("x86_64-unknown-linux-gnu", src), Ok(()));
+        assert_eq!(
+            lower_for("avr-unknown-unknown", src),
+            Err("Cannot negate a value of unsigned integer type usz".to_owned())
+        );
+    }
+
+    #[test]
+    fn pointer_sized_literal_range_uses_target_width() {
+        let src = "fun main() -> i32 {
+    let [usize] x = 70000
+    return 0
+}";
+        assert_eq!(lower_for("x86_64-unknown-linux-gnu", src), Ok(()));
+        assert_eq!(lower_for("i686-unknown-linux-gnu", src), Ok(()));
+        assert_eq!(
+            lower_for("avr-unknown-unknown", src),
+            Err("Integer literal 70000 does not fit in type usz".to_owned())
+        );
+    }
+
+    /// Lower the body of a main function with overflow checks on or off, returning the error
+    /// message if lowering fails
+    fn lower_checked(overflow_checks: bool, body: &str) -> Result<(), String> {
+        let src = format!("fun main() -> i32 {{\n{}\n    return 0\n}}", body);
+        lower_with(&src, &[], |lowerer| {
+            lowerer.set_overflow_checks(overflow_checks)
+        })
+        .map(|_| ())
+        .map_err(|e| e.message)
+    }
+
+    #[test]
+    fn negating_minimum_constant_overflows() {
+        assert_eq!(
+            lower_checked(true, "    let x = -(-128i8)"),
+            Err(
+                "Overflow in constant expression: -(-128) does not fit in a 8 bit integer"
+                    .to_owned()
+            )
+        );
+        assert_eq!(
+            lower_checked(
+                true,
+                "    let x = -(-170141183460469231731687303715884105728i128)"
+            ),
+            Err("Overflow in constant expression: -(-170141183460469231731687303715884105728) does not fit in a 128 bit integer".to_owned())
+        );
+        assert_eq!(lower_checked(false, "    let x = -(-128i8)"), Ok(()));
+    }
+
+    #[test]
+    fn negating_constants_in_range_does_not_overflow() {
+        assert_eq!(lower_checked(true, "    let x = -(-127i8)"), Ok(()));
+        assert_eq!(lower_checked(true, "    let x = -(127i8)"), Ok(()));
+        assert_eq!(lower_checked(true, "    let x = -(0i64)"), Ok(()));
+    }
 }
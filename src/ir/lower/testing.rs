@@ -0,0 +1,155 @@
+//! Helpers for tests that parse and lower spark source code to IR
+
+use codespan_reporting::diagnostic::Diagnostic;
+
+use crate::{
+    ast::ParsedModule,
+    ir::{FunId, IrContext},
+    parse::Parser,
+    util::files::{CompiledFile, FileId, Files},
+    Symbol,
+};
+
+use super::IrLowerer;
+
+/// IR lowered from source code, along with the warnings emitted while lowering it and the
+/// source files it was lowered from
+pub struct Lowered {
+    pub ctx: IrContext,
+    pub warnings: Vec<Diagnostic<FileId>>,
+    pub files: Files,
+}
+
+impl Lowered {
+    /// Get the function with the given name, panicking if there is none
+    pub fn fun(&self, name: &str) -> FunId {
+        self.ctx
+            .funs
+            .indices()
+            .find(|fun| self.ctx[*fun].name.as_str() == name)
+            .unwrap_or_else(|| panic!("no function named {}", name))
+    }
+
+    /// Get the messages of every warning emitted while lowering
+    pub fn warning_messages(&self) -> Vec<String> {
+        self.warnings
+            .iter()
+            .map(|warning| warning.message.clone())
+            .collect()
+    }
+}
+
+/// Parse the root module from source code, with child modules given by name and source code
+pub fn parse(files: &mut Files, root: &str, children: &[(&str, &str)]) -> ParsedModule {
+    let mut parse_module = |name: &str, src: &str| {
+        let file = files.add(CompiledFile::in_memory(src.to_owned()));
+        Parser::new(src)
+            .parse(Symbol::from(name), file)
+            .unwrap_or_else(|e| panic!("failed to parse module {}: {:?}", name, e))
+    };
+
+    let mut module = parse_module("root", root);
+    for (name, src) in children {
+        let child = parse_module(name, src);
+        module.children.push(child);
+    }
+    module
+}
+
+/// Parse every source file into the root module, like the driver does when given multiple input
+/// files
+fn parse_files(files: &mut Files, srcs: &[&str]) -> ParsedModule {
+    let mut module = ParsedModule::new(Symbol::from("root"));
+    for (idx, src) in srcs.iter().enumerate() {
+        let file = files.add(CompiledFile::in_memory((*src).to_owned()));
+        Parser::new(src)
+            .parse_to(&mut module, file)
+            .unwrap_or_else(|e| panic!("failed to parse file {}: {:?}", idx, e));
+    }
+    module
+}
+
+/// Lower a root module and child modules after applying options to the lowerer
+pub fn lower_with(
+    root: &str,
+    children: &[(&str, &str)],
+    configure: impl FnOnce(&mut IrLowerer),
+) -> Result<Lowered, Diagnostic<FileId>> {
+    let mut files = Files::new();
+    let module = parse(&mut files, root, children);
+    lower_parsed(files, module, configure)
+}
+
+/// Lower a parsed root module read from the given files after applying options to the lowerer
+fn lower_parsed(
+    files: Files,
+    module: ParsedModule,
+    configure: impl FnOnce(&mut IrLowerer),
+) -> Result<Lowered, Diagnostic<FileId>> {
+    let mut ctx = IrContext::new();
+    let mut lowerer = IrLowerer::new(&mut ctx, module.name);
+    configure(&mut lowerer);
+    lowerer.lower(&module)?;
+    let warnings = lowerer.take_warnings();
+    drop(lowerer);
+    Ok(Lowered {
+        ctx,
+        warnings,
+        files,
+    })
+}
+
+/// Lower a root module and child modules, panicking if lowering fails or produces invalid IR
+pub fn lower_modules(root: &str, children: &[(&str, &str)]) -> Lowered {
+    expect_lowered(lower_with(root, children, |_| ()))
+}
+
+/// Lower several source files that make up the root module, panicking if lowering fails or
+/// produces invalid IR
+pub fn lower_files(srcs: &[&str]) -> Lowered {
+    let mut files = Files::new();
+    let module = parse_files(&mut files, srcs);
+    expect_lowered(lower_parsed(files, module, |_| ()))
+}
+
+/// Unwrap the result of lowering, panicking if lowering failed or produced invalid IR
+pub fn expect_lowered(lowered: Result<Lowered, Diagnostic<FileId>>) -> Lowered {
+    let lowered = lowered.unwrap_or_else(|e| {
+        panic!(
+            "failed to lower: {} {:?} {:?}",
+            e.message,
+            e.notes,
+            e.labels
+                .iter()
+                .map(|label| &label.message)
+                .collect::<Vec<_>>()
+        )
+    });
+
+    let errors = lowered.ctx.validate();
+    assert!(
+        errors.is_empty(),
+        "lowered invalid IR: {:?}\n{}",
+        errors.iter().map(|e| &e.msg).collect::<Vec<_>>(),
+        lowered.ctx
+    );
+    lowered
+}
+
+/// Lower a single module, panicking if lowering fails or produces invalid IR
+pub fn lower(src: &str) -> Lowered {
+    lower_modules(src, &[])
+}
+
+/// Lower a root module and child modules that must fail to lower, returning the error
+pub fn lower_modules_err(root: &str, children: &[(&str, &str)]) -> Diagnostic<FileId> {
+    match lower_with(root, children, |_| ()) {
+        Ok(lowered) => panic!("lowering succeeded:\n{}", lowered.ctx),
+        Err(e) => e,
+    }
+}
+
+/// Lower a single module that must fail to lower, returning the error
+pub fn lower_err(src: &str) -> Diagnostic<FileId> {
+    lower_modules_err(src, &[])
+}
@@ -0,0 +1,288 @@
+//! Warnings for variables and parameters that are declared in a function but never read
+
+use codespan_reporting::diagnostic::{Diagnostic, Label};
+use hashbrown::HashSet;
+
+use crate::{
+    ast::FunFlags,
+    ir::{
+        value::{IrExpr, IrExprKind},
+        FunId, IrStmtKind, IrTerminator, VarId,
+    },
+    parse::token::Op,
+    util::{files::FileId, loc::Span},
+};
+
+use super::{IrLowerer, LintFlags};
+
+impl<'ctx> IrLowerer<'ctx> {
+    /// Warn about every variable declared in the given function's body and every named
+    /// parameter of the function that is never read.
+    /// Assigning to a variable is not a read, but taking its address is assumed to be, and
+    /// variables with names starting with an underscore or in functions declared with
+    /// `allow(unused)` are never reported
+    pub(super) fn check_unused_vars(&mut self, file: FileId, fun: FunId, params: &[(VarId, Span)]) {
+        let declared = std::mem::take(&mut self.declared_vars);
+        if !self.lints.contains(LintFlags::UNUSED_VARIABLES)
+            || self.ctx[fun].flags.contains(FunFlags::ALLOW_UNUSED)
+            || (declared.is_empty() && params.is_empty())
+        {
+            return;
+        }
+
+        let mut used = HashSet::new();
+        let mut read = |expr: &IrExpr| {
+            self.init_reads(expr, &mut |var, _, _| {
+                used.insert(var);
+            })
+        };
+        for bb in self.ctx.blocks_of(fun) {
+            for stmt in self.ctx[bb].stmts.iter() {
+                match &stmt.kind {
+                    IrStmtKind::Store { val, .. } | IrStmtKind::Exec(val) => read(val),
+                    IrStmtKind::StoreMem { addr, val } => {
+                        read(val);
+                        self.unused_write(addr, &mut read);
+                    }
+                    IrStmtKind::Copy { dst, src, .. } => {
+                        //The copied place is read instead of having its address taken
+                        match &src.kind {
+                            IrExprKind::Unary(Op::AND, place) => read(place),
+                            _ => read(src),
+                        }
+                        self.unused_write(dst, &mut read);
+                    }
+                    IrStmtKind::VolatileWrite { ptr, val } => {
+                        read(ptr);
+                        read(val);
+                    }
+                    IrStmtKind::Assert { cond, .. } => read(cond),
+                    IrStmtKind::Call { args, .. } => args.iter().for_each(&mut read),
+                    IrStmtKind::VarLive(_) | IrStmtKind::VarDead(_) | IrStmtKind::Zero(_) => (),
+                }
+            }
+
+            match &self.ctx[bb].terminator {
+                IrTerminator::Return(expr) => read(expr),
+                IrTerminator::JmpIf { condition, .. } => read(condition),
+                IrTerminator::JmpMatch { variant, .. } => read(variant),
+                IrTerminator::Jmp(_) | IrTerminator::Unreachable | IrTerminator::Invalid => (),
+            }
+            for branch in self.ctx[bb].terminator.branches() {
+                branch.args.iter().for_each(&mut read);
+            }
+        }
+
+        let ctx = &*self.ctx;
+        let is_unused = |var: &VarId| !used.contains(var) && !ctx[*var].name.starts_with('_');
+        let unused_params = params
+            .iter()
+            .filter(|(param, _)| is_unused(param))
+            .collect::<Vec<_>>();
+        let mut unused = declared
+            .into_iter()
+            .filter(|(var, _)| is_unused(var))
+            .collect::<Vec<_>>();
+        unused.sort_by_key(|(var, _)| var.val());
+
+        for (param, span) in unused_params {
+            let name = self.ctx[*param].name;
+            self.warnings.push(
+                Diagnostic::warning()
+                    .with_message(format!(
+                        "Parameter {} of function {} is never read",
+                        name, self.ctx[fun].name
+                    ))
+                    .with_labels(vec![
+                        Label::primary(file, *span).with_message("Parameter declared here")
+                    ])
+                    .with_notes(vec![format!(
+                        "Name the parameter _{} to allow it to be unused",
+                        name
+                    )]),
+            );
+        }
+
+        for (var, span) in unused {
+            let name = self.ctx[var].name;
+            self.warnings.push(
+                Diagnostic::warning()
+                    .with_message(format!("Variable {} is never read", name))
+                    .with_labels(vec![
+                        Label::primary(file, span).with_message("Variable declared here")
+                    ])
+                    .with_notes(vec![format!(
+                        "Name the variable _{} to allow it to be unused",
+                        name
+                    )]),
+            );
+        }
+    }
+
+    /// Visit the expressions read when writing through an address, which don't include the
+    /// variable written to if the address is of a place in a variable
+    fn unused_write(&self, addr: &IrExpr, read: &mut dyn FnMut(&IrExpr)) {
+        match &addr.kind {
+            IrExprKind::Unary(Op::AND, place) if self.lval_root(place).is_some() => {
+                Self::lval_reads(place, read)
+            }
+            _ => read(addr),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::ir::lower::{
+        testing::{lower, lower_with},
+        LintFlags,
+    };
+
+    #[test]
+    fn unused_parameter_warns() {
+        let lowered = lower(
+            "fun add(i32 a, i32 b) -> i32 {
+    return a
+}
+
+fun main() -> i32 {
+    return add(1, 2)
+}",
+        );
+        assert_eq!(
+            lowered.warning_messages(),
+            vec!["Parameter b of function add is never read".to_owned()]
+        );
+    }
+
+    #[test]
+    fn underscore_parameter_does_not_warn() {
+        let lowered = lower(
+            "fun add(i32 a, i32 _b) -> i32 {
+    return a
+}
+
+fun main() -> i32 {
+    return add(1, 2)
+}",
+        );
+        assert_eq!(lowered.warning_messages(), Vec::<String>::new());
+    }
+
+    #[test]
+    fn extern_parameters_do_not_warn() {
+        let lowered = lower(
+            "fun ext puts(*u8 s) -> i32
+
+fun main() -> i32 {
+    return 0
+}",
+        );
+        assert_eq!(lowered.warning_messages(), Vec::<String>::new());
+    }
+
+    #[test]
+    fn allow_unused_suppresses_warnings() {
+        let lowered = lower(
+            "fun allow(unused) stub(i32 a, i32 b) -> i32 {
+    let c = 3
+    return 0
+}
+
+fun main() -> i32 {
+    return stub(1, 2)
+}",
+        );
+        assert_eq!(lowered.warning_messages(), Vec::<String>::new());
+    }
+
+    #[test]
+    fn function_named_allow_is_not_an_attribute() {
+        let lowered = lower(
+            "fun allow(i32 unused) -> i32 {
+    return unused
+}
+
+fun main() -> i32 {
+    return allow(1)
+}",
+        );
+        assert_eq!(lowered.warning_messages(), Vec::<String>::new());
+    }
+
+    #[test]
+    fn unused_variable_warns_at_declaration() {
+        let lowered = lower(
+            "fun main() -> i32 {
+    let unused = 1
+    return 0
+}",
+        );
+        assert_eq!(
+            lowered.warning_messages(),
+            vec!["Variable unused is never read".to_owned()]
+        );
+        let warning = &lowered.warnings[0];
+        assert_eq!(warning.labels[0].message, "Variable declared here");
+        assert_eq!(
+            warning.notes,
+            vec!["Name the variable _unused to allow it to be unused".to_owned()]
+        );
+    }
+
+    #[test]
+    fn underscore_variable_does_not_warn() {
+        let lowered = lower(
+            "fun main() -> i32 {
+    let _unused = 1
+    return 0
+}",
+        );
+        assert_eq!(lowered.warning_messages(), Vec::<String>::new());
+    }
+
+    #[test]
+    fn write_only_variable_warns() {
+        let lowered = lower(
+            "fun main() -> i32 {
+    mut written = 1
+    written = 2
+    return 0
+}",
+        );
+        assert_eq!(
+            lowered.warning_messages(),
+            vec!["Variable written is never read".to_owned()]
+        );
+    }
+
+    #[test]
+    fn address_taken_variable_does_not_warn() {
+        let lowered = lower(
+            "fun set(*i32 p) {
+    let *p = 5
+}
+
+fun main() -> i32 {
+    mut x = 0
+    set(&x)
+    return 0
+}",
+        );
+        assert_eq!(lowered.warning_messages(), Vec::<String>::new());
+    }
+
+    #[test]
+    fn disabled_lint_does_not_warn() {
+        let lowered = lower_with(
+            "fun main() -> i32 {
+    let unused = 1
+    return 0
+}",
+            &[],
+            |lowerer| lowerer.set_lints(LintFlags::all() - LintFlags::UNUSED_VARIABLES),
+        )
+        .unwrap();
+        assert_eq!(lowered.warning_messages(), Vec::<String>::new());
+    }
+}
@@ -1,13 +1,24 @@
 //! Module containing definitions for structures representing type-lowered Intermediate
 //! Representation created from an Abstract Syntax Tree
 
+pub mod callgraph;
+pub mod cfg;
+pub mod consteval;
+pub mod dom;
+pub mod layout;
 pub mod lower;
+pub mod opt;
+pub mod print;
+#[cfg(test)]
+pub(crate) mod testing;
 pub mod types;
+pub mod validate;
 pub mod value;
 
-use std::ops::IndexMut;
+use std::{cell::RefCell, ops::IndexMut};
 
-use hashbrown::HashSet;
+use hashbrown::HashMap;
+use serde::Serialize;
 
 use crate::{
     arena::{Arena, Index, Interner},
@@ -17,12 +28,15 @@ use crate::{
 };
 
 use self::{
-    types::{FunType, IrFloatType, IrIntegerType, IrType},
-    value::IrExpr,
+    consteval::ConstValue,
+    layout::{Layout, TargetSpec},
+    types::{FunType, IrEnumType, IrFloatType, IrIntegerType, IrType},
+    value::{CastKind, IrExpr},
 };
 
 /// An IR context containing arenas with all type definitons, function declarations / definitions,
 /// and modules
+#[derive(Serialize)]
 pub struct IrContext {
     /// A container with all defined types
     pub types: Interner<IrType>,
@@ -34,6 +48,12 @@ pub struct IrContext {
     pub vars: Arena<IrVar>,
     /// All global values in the program
     pub globals: Arena<IrGlobal>,
+    /// The spark `main` function of the root module, which is called by the generated C `main`
+    /// function of the program
+    pub entry: Option<FunId>,
+    /// Layouts of types on every target they have been requested for
+    #[serde(skip)]
+    layouts: RefCell<HashMap<(TypeId, TargetSpec), Layout>>,
 }
 
 /// ID referencing an [IrType] in an [IrContext]
@@ -48,43 +68,71 @@ pub type VarId = Index<IrVar>;
 /// ID referencing an [IrFun] in an [IrContext]
 pub type FunId = Index<IrFun>;
 
-/// ID referencing an [IrType::Type] that is an enum discriminant in an [Ir::Sum]
-pub type DiscriminantId = TypeId;
+/// Identifies the variant of a matched value that an arm of a
+/// [JmpMatch](IrTerminator::JmpMatch) jumps for
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize)]
+pub enum DiscriminantId {
+    /// Variant type of a [Sum](types::IrType::Sum) type
+    Type(TypeId),
+    /// Index of a variant of an [Enum](types::IrType::Enum) type
+    Variant(usize),
+}
 
 /// ID referencing an [IrGlobal]
 pub type GlobalId = Index<IrGlobal>;
 
 /// A single basic block in the IR containing a list of statements
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, Serialize)]
 pub struct IrBB {
+    /// Types of the values passed to this block by every jump to it, read in the block and the
+    /// blocks it dominates with [Param](value::IrExprKind::Param) expressions
+    pub params: Vec<TypeId>,
     /// A list of statements in the order they should execute
     pub stmts: Vec<IrStmt>,
     /// The terminator statement of this basic block
     pub terminator: IrTerminator,
+    /// Location in the source file of the statement or expression that produced the
+    /// terminator, if any
+    pub terminator_span: Option<Span>,
 }
 
 /// A declared variable with type and name
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, Serialize)]
 pub struct IrVar {
     /// Type of the variable
     pub ty: TypeId,
     /// User-asigned name of the variable
     pub name: Symbol,
+    /// Alignment in bytes of the variable's allocation, or the type's natural alignment if `None`
+    pub align: Option<u32>,
+    /// If the variable may be assigned to or have its address taken after it is initialized
+    pub mutable: bool,
 }
 
 /// A global variable
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, Serialize)]
 pub struct IrGlobal {
     /// Type of the global value
     pub ty: TypeId,
     /// Name of this global
     pub name: Symbol,
+    /// Value of a constant global, evaluated at compile time
+    pub value: Option<ConstValue>,
+    /// If the global may be assigned to after it is initialized
+    pub mutable: bool,
+    /// File that the global is defined in
+    pub file: FileId,
+    /// Span of the global's definition
+    pub span: Span,
 }
 
 /// Function with source location information and optional body
+#[derive(Serialize)]
 pub struct IrFun {
     /// Name of the function, may be generated by the compiler
     pub name: Symbol,
+    /// Name of the function in the output object file, mangled unless the function is external
+    pub symbol: Symbol,
     /// Function's signature
     pub ty: FunType,
     /// ID of the function type
@@ -100,7 +148,7 @@ pub struct IrFun {
 }
 
 /// The body of a function, composed of multiple statements and basic blocks
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, Serialize)]
 pub struct IrBody {
     /// Entry block of the body
     pub entry: BBId,
@@ -108,49 +156,75 @@ pub struct IrBody {
     pub parent: FunId,
     /// A list of argument allocations for each parameter
     pub args: Vec<Option<VarId>>,
+    /// All basic blocks created for the body, which may include blocks that are unreachable
+    /// from the entry block until [remove_dead_blocks](opt::remove_dead_blocks) is run
+    pub blocks: Vec<BBId>,
+}
+
+/// The target of a jump and the values passed to the target block's parameters
+#[derive(Clone, Debug, Serialize)]
+pub struct IrBranch {
+    /// Basic block that is jumped to
+    pub bb: BBId,
+    /// Values of each of the target block's parameters
+    pub args: Vec<IrExpr>,
+}
+
+impl From<BBId> for IrBranch {
+    fn from(bb: BBId) -> Self {
+        Self { bb, args: vec![] }
+    }
 }
 
 /// A statement that may terminate a basic block
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, Serialize)]
 pub enum IrTerminator {
     /// Exits the currently executing function
     Return(IrExpr),
     /// Jumps unconditionally to another basic block
-    Jmp(BBId),
+    Jmp(IrBranch),
     /// Jumps conditionally
     JmpIf {
         /// Boolean-valued condtion being checked
         condition: IrExpr,
         /// Basic block to jump to if the condition evaluates to true
-        if_true: BBId,
+        if_true: IrBranch,
         /// Basic block to jump to otherwise
-        if_false: BBId,
+        if_false: IrBranch,
     },
     /// Matches against an enum's discriminant
     JmpMatch {
         /// Variant being tested
         variant: IrExpr,
         /// List of checked discriminants by their indices
-        discriminants: Vec<(DiscriminantId, BBId)>,
+        discriminants: Vec<(DiscriminantId, IrBranch)>,
         /// Default jump
-        default_jmp: BBId,
+        default_jmp: IrBranch,
     },
+    /// Terminates a block that can never be reached, like the default jump of a match that
+    /// covers every variant of a sum type
+    Unreachable,
     /// Internal compiler usage
     Invalid,
 }
 
 /// A single statement in the IR, an instruction that produces no value
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, Serialize)]
 pub struct IrStmt {
     pub span: Span,
     pub kind: IrStmtKind,
 }
 
 /// A single statement in the Intermediate Representation
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, Serialize)]
 pub enum IrStmtKind {
     /// Allocate space for the given variable
     VarLive(VarId),
+    /// End the lifetime of a variable when it goes out of scope, after which it is not accessed
+    /// until the next [VarLive](IrStmtKind::VarLive) of the variable
+    VarDead(VarId),
+    /// Fill a variable's allocation with zeroes
+    Zero(VarId),
     /// Store a value in a variable
     Store {
         /// The variable to store into
@@ -158,12 +232,37 @@ pub enum IrStmtKind {
         /// Value to store in variable
         val: IrExpr,
     },
-    Write {
-        //// Expression that must be of pointer type
+    /// Store a value in the memory pointed to by an address, used for assignments to fields,
+    /// array elements, and dereferenced pointers
+    StoreMem {
+        /// Pointer-valued expression of type `*T`, where `T` is the type of the stored value
+        addr: IrExpr,
+        /// Value to write to the address
+        val: IrExpr,
+    },
+    /// Copy an aggregate value from one place in memory to another
+    Copy {
+        /// Pointer-valued expression of type `*T` that the value is copied to
+        dst: IrExpr,
+        /// Pointer-valued expression of type `*T` that the value is copied from
+        src: IrExpr,
+        /// Type `T` of the copied structure, array, or sum type value
+        ty: TypeId,
+    },
+    /// Volatile store through a pointer
+    VolatileWrite {
+        /// Expression that must be of pointer type
         ptr: IrExpr,
         /// Value to write to the pointer
         val: IrExpr,
     },
+    /// Abort the program with a message if a boolean condition is false
+    Assert {
+        /// Boolean-valued condition that must be true
+        cond: IrExpr,
+        /// Message printed when the assertion fails
+        msg: Option<String>,
+    },
     /// Call a function directly
     Call { fun: FunId, args: Vec<IrExpr> },
     /// Execute the given expression for side effects
@@ -193,6 +292,9 @@ impl IrContext {
 
     pub const CHAR: TypeId = unsafe { TypeId::from_raw(15) };
 
+    pub const I128: TypeId = unsafe { TypeId::from_raw(16) };
+    pub const U128: TypeId = unsafe { TypeId::from_raw(17) };
+
     /// Create a new `IRContext` with primitive types defined
     pub fn new() -> Self {
         let mut types = Interner::<IrType>::new();
@@ -274,19 +376,41 @@ impl IrContext {
 
         types.insert(IrType::Char);
 
+        types.insert(IrType::Integer(IrIntegerType {
+            width: IntegerWidth::OneTwentyEight,
+            signed: true,
+        }));
+        types.insert(IrType::Integer(IrIntegerType {
+            width: IntegerWidth::OneTwentyEight,
+            signed: false,
+        }));
+
         Self {
             types,
             funs: Arena::new(),
             bbs: Arena::new(),
             vars: Arena::new(),
             globals: Arena::new(),
+            entry: None,
+            layouts: RefCell::new(HashMap::new()),
         }
     }
 
-    /// Get a human-readable type name for the given type
+    /// Get a human-readable type name for the given type. Named types are printed by name, and
+    /// self-referential types are cut short with `...` where they contain themselves
     #[inline]
-    pub fn typename(&self, ty: TypeId) -> TypenameFormatter<'_> {
-        TypenameFormatter { ctx: self, ty }
+    pub fn typename(&self, ty: TypeId) -> TypenameFormatter<'_, '_> {
+        TypenameFormatter {
+            ctx: self,
+            ty,
+            outer: None,
+        }
+    }
+
+    /// Serialize every type, function, block, variable and global in this context to a JSON
+    /// string, with IDs written as indices into the arrays of their items
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).expect("Failed to serialize IR context")
     }
 
     /// Get the [TypeId] of an integer type with the given width and signededness
@@ -296,16 +420,45 @@ impl IrContext {
             (true, IntegerWidth::Sixteen) => Self::I16,
             (true, IntegerWidth::ThirtyTwo) => Self::I32,
             (true, IntegerWidth::SixtyFour) => Self::I64,
+            (true, IntegerWidth::OneTwentyEight) => Self::I128,
             (true, IntegerWidth::PtrSize) => Self::ISIZE,
 
             (false, IntegerWidth::Eight) => Self::U8,
             (false, IntegerWidth::Sixteen) => Self::U16,
             (false, IntegerWidth::ThirtyTwo) => Self::U32,
             (false, IntegerWidth::SixtyFour) => Self::U64,
+            (false, IntegerWidth::OneTwentyEight) => Self::U128,
             (false, IntegerWidth::PtrSize) => Self::USIZE,
         }
     }
 
+    /// Get the largest explicit alignment of any structure field contained in the given type by
+    /// value, which the type's storage must also be aligned to
+    pub fn explicit_align(&self, ty: TypeId) -> Option<u32> {
+        match &self[ty] {
+            IrType::Alias { ty, .. } | IrType::Array(ty, _) => self.explicit_align(*ty),
+            IrType::Struct(s_ty) => s_ty
+                .fields
+                .iter()
+                .map(|field| field.align.max(self.explicit_align(field.ty)))
+                .max()
+                .flatten(),
+            IrType::Sum(variants) | IrType::Tuple(variants) => variants
+                .iter()
+                .map(|variant| self.explicit_align(*variant))
+                .max()
+                .flatten(),
+            IrType::Enum(e) => e
+                .variants
+                .iter()
+                .filter_map(|variant| variant.payload)
+                .map(|payload| self.explicit_align(payload))
+                .max()
+                .flatten(),
+            _ => None,
+        }
+    }
+
     /// Unwrap any type aliases to get a type that is guranteed to not be an alias
     pub fn unwrap_alias(&self, ty: TypeId) -> TypeId {
         match &self[ty] {
@@ -314,32 +467,169 @@ impl IrContext {
         }
     }
 
+    /// Get the conversion performed by casting a value of one type to another type, or `None` if
+    /// values of the first type can't be cast to the second type
+    pub fn cast_kind(&self, from: TypeId, to: TypeId) -> Option<CastKind> {
+        let (ufrom, uto) = (self.unwrap_alias(from), self.unwrap_alias(to));
+        if ufrom == uto {
+            return Some(CastKind::Identity);
+        }
+
+        Some(match (&self[ufrom], &self[uto]) {
+            (IrType::Integer(_) | IrType::Char, IrType::Integer(_) | IrType::Char) => {
+                CastKind::IntResize
+            }
+            (IrType::Integer(_), IrType::Float(_)) => CastKind::IntToFloat,
+            (IrType::Float(_), IrType::Integer(_)) => CastKind::FloatToInt,
+            (IrType::Float(_), IrType::Float(_)) => CastKind::FloatResize,
+            (IrType::Integer(_), IrType::Ptr(_)) => CastKind::IntToPtr,
+            (IrType::Ptr(_), IrType::Integer(_)) => CastKind::PtrToInt,
+            (IrType::Ptr(_) | IrType::Fun(_), IrType::Ptr(_) | IrType::Fun(_)) => {
+                CastKind::PtrToPtr
+            }
+            (IrType::Bool, IrType::Integer(_)) => CastKind::BoolToInt,
+            (IrType::Integer(_), IrType::Bool) => CastKind::IntToBool,
+            //Casting an enum to an integer gives the discriminant of its variant
+            (IrType::Enum(_), IrType::Integer(_)) => CastKind::EnumToInt,
+            //Downcasting a sum would read the payload whatever variant is stored, so it is only
+            //done by match arms that have tested the variant
+            (_, IrType::Sum(s)) if s.contains(&from) || s.contains(&ufrom) => CastKind::SumWrap,
+            _ => return None,
+        })
+    }
+
+    /// Get the body of a function, or `None` if the function is only declared
+    pub fn fun_body(&self, fun: FunId) -> Option<&IrBody> {
+        self[fun].body.as_ref()
+    }
+
+    /// Get a mutable reference to the body of a function, or `None` if the function is only
+    /// declared
+    pub fn fun_body_mut(&mut self, fun: FunId) -> Option<&mut IrBody> {
+        self[fun].body.as_mut()
+    }
+
+    /// Get the type of pointers to values of the given type
+    pub fn ptr_to(&mut self, ty: TypeId) -> TypeId {
+        self.types.insert(IrType::Ptr(ty))
+    }
+
+    /// Get the type of arrays with the given element type and length
+    pub fn array_of(&mut self, element: TypeId, len: u64) -> TypeId {
+        self.types.insert(IrType::Array(element, len))
+    }
+
+    /// Get the sum type of the given variants. Variants are sorted and deduplicated so that sum
+    /// types with the same variants written in a different order are the same type
+    pub fn sum_of(&mut self, mut variants: Vec<TypeId>) -> TypeId {
+        variants.sort_by_key(|variant| variant.val());
+        variants.dedup();
+        self.types.insert(IrType::Sum(variants))
+    }
+
+    /// Get the slice type with the given element type
+    pub fn slice_of(&mut self, element: TypeId) -> TypeId {
+        self.types.insert(IrType::Slice(element))
+    }
+
+    /// Get the tuple type with the given element types, which must not be empty as the unit type
+    /// takes the place of the empty tuple
+    pub fn tuple_of(&mut self, elements: Vec<TypeId>) -> TypeId {
+        debug_assert!(!elements.is_empty(), "Empty tuples are the unit type");
+        self.types.insert(IrType::Tuple(elements))
+    }
+
+    /// Get a basic block, the same as indexing the context with its ID
+    ///
+    /// ```
+    /// # use spark::ir::{IrContext, IrTerminator};
+    /// let mut ctx = IrContext::new();
+    /// let bb = ctx.new_bb();
+    /// assert!(ctx.bb(bb).stmts.is_empty());
+    /// assert!(matches!(ctx.bb(bb).terminator, IrTerminator::Invalid));
+    /// ```
+    pub fn bb(&self, bb: BBId) -> &IrBB {
+        &self.bbs[bb]
+    }
+
+    /// Get a mutable reference to a basic block, the same as indexing the context with its ID
+    ///
+    /// ```
+    /// # use spark::ir::{IrContext, IrTerminator};
+    /// let mut ctx = IrContext::new();
+    /// let bb = ctx.new_bb();
+    /// ctx.bb_mut(bb).terminator = IrTerminator::Unreachable;
+    /// assert!(matches!(ctx[bb].terminator, IrTerminator::Unreachable));
+    /// ```
+    pub fn bb_mut(&mut self, bb: BBId) -> &mut IrBB {
+        &mut self.bbs[bb]
+    }
+
     /// Create a new basic block with invalid terminator and return the ID
-    pub fn bb(&mut self) -> BBId {
+    pub fn new_bb(&mut self) -> BBId {
         self.bbs.insert(IrBB {
+            params: vec![],
             stmts: vec![],
             terminator: IrTerminator::Invalid,
+            terminator_span: None,
         })
     }
 }
 
 /// Structure for more efficiently formatting typename strings via a std::fmt::Display
 /// implementation avoiding multiple string allocations
-pub struct TypenameFormatter<'ctx> {
+pub struct TypenameFormatter<'ctx, 'outer> {
     ctx: &'ctx IrContext,
     ty: TypeId,
+    /// Formatter of the type that contains this one, used to detect cycles
+    outer: Option<&'outer TypenameFormatter<'ctx, 'outer>>,
 }
 
-impl<'ctx> TypenameFormatter<'ctx> {
-    /// Create a new formatter for the given type ID using the same shared context
-    const fn create(&self, ty: TypeId) -> Self {
-        Self { ctx: self.ctx, ty }
+impl<'ctx, 'outer> TypenameFormatter<'ctx, 'outer> {
+    /// Create a new formatter for a type contained in this formatter's type using the same
+    /// shared context
+    fn create<'a>(&'a self, ty: TypeId) -> TypenameFormatter<'ctx, 'a> {
+        TypenameFormatter {
+            ctx: self.ctx,
+            ty,
+            outer: Some(self),
+        }
+    }
+
+    /// Check if this formatter's type is already being written by a formatter that contains
+    /// this one
+    fn is_cycle(&self) -> bool {
+        let mut outer = self.outer;
+        while let Some(formatter) = outer {
+            if formatter.ty == self.ty {
+                return true;
+            }
+            outer = formatter.outer;
+        }
+        false
     }
 }
 
-impl<'ctx> std::fmt::Display for TypenameFormatter<'ctx> {
+impl<'ctx, 'outer> std::fmt::Display for TypenameFormatter<'ctx, 'outer> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        match &self.ctx[self.ty] {
+        let ty = &self.ctx[self.ty];
+        //Types that contain other types are cut short when they contain themselves, named types
+        //are always written by name
+        let expands = matches!(
+            ty,
+            IrType::Sum(_)
+                | IrType::Array(..)
+                | IrType::Slice(_)
+                | IrType::Struct(_)
+                | IrType::Tuple(_)
+                | IrType::Ptr(_)
+                | IrType::Fun(_)
+        );
+        if expands && self.is_cycle() {
+            return write!(f, "...");
+        }
+
+        match ty {
             IrType::Integer(IrIntegerType { signed, width }) => write!(
                 f,
                 "{}",
@@ -348,12 +638,14 @@ impl<'ctx> std::fmt::Display for TypenameFormatter<'ctx> {
                     (true, IntegerWidth::Sixteen) => "i16",
                     (true, IntegerWidth::ThirtyTwo) => "i32",
                     (true, IntegerWidth::SixtyFour) => "i64",
+                    (true, IntegerWidth::OneTwentyEight) => "i128",
                     (true, IntegerWidth::PtrSize) => "isz",
 
                     (false, IntegerWidth::Eight) => "u8",
                     (false, IntegerWidth::Sixteen) => "u16",
                     (false, IntegerWidth::ThirtyTwo) => "u32",
                     (false, IntegerWidth::SixtyFour) => "u64",
+                    (false, IntegerWidth::OneTwentyEight) => "u128",
                     (false, IntegerWidth::PtrSize) => "usz",
                 }
             ),
@@ -374,34 +666,63 @@ impl<'ctx> std::fmt::Display for TypenameFormatter<'ctx> {
                     false => "f32",
                 }
             ),
-            IrType::Alias { name, .. } => write!(f, "{}", name),
+            IrType::Alias { name, .. } | IrType::Enum(IrEnumType { name, .. }) => {
+                write!(f, "{}", name)
+            }
             IrType::Array(element, len) => write!(f, "[{}]{}", len, self.create(*element)),
+            IrType::Slice(element) => write!(f, "[]{}", self.create(*element)),
             IrType::Struct(structure) => {
+                if structure.packed {
+                    write!(f, "packed ")?;
+                }
                 write!(f, "{{")?;
                 for field in structure.fields.iter() {
+                    if let Some(align) = field.align {
+                        write!(f, "align({}) ", align)?;
+                    }
                     write!(f, "{} {},", self.create(field.ty), field.name)?;
                 }
                 write!(f, "}}")
             }
+            IrType::Tuple(elements) => {
+                write!(f, "(")?;
+                for (i, element) in elements.iter().enumerate() {
+                    if i != 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}", self.create(*element))?;
+                }
+                //A one-element tuple is written with a trailing comma to tell it apart from a
+                //type in parentheses
+                if elements.len() == 1 {
+                    write!(f, ",")?;
+                }
+                write!(f, ")")
+            }
             IrType::Ptr(ty) => write!(f, "*{}", self.create(*ty)),
             IrType::Fun(fun) => {
-                write!(f, "fun (")?;
-                for (arg_ty, arg_name) in fun.params.iter() {
-                    write!(
-                        f,
-                        "{} {}, ",
-                        self.create(*arg_ty),
-                        arg_name.unwrap_or(Symbol::from(""))
-                    )?;
+                write!(f, "fun(")?;
+                for (i, (arg_ty, arg_name)) in fun.params.iter().enumerate() {
+                    if i != 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}", self.create(*arg_ty))?;
+                    if let Some(arg_name) = arg_name {
+                        write!(f, " {}", arg_name)?;
+                    }
                 }
 
                 write!(f, ") -> {}", self.create(fun.return_ty))
             }
+            IrType::Opaque(name) => write!(f, "{}", name),
             IrType::Invalid => write!(f, "INVALID"),
         }
     }
 }
 
+//Types are interned, so there is no IndexMut<TypeId> implementation: changing an interned type
+//would change every type that is structurally equal to it. Use Interner::get_mut on types that
+//were inserted without interning instead
 impl std::ops::Index<TypeId> for IrContext {
     type Output = IrType;
     fn index(&self, index: TypeId) -> &Self::Output {
@@ -457,127 +778,162 @@ impl IndexMut<GlobalId> for IrContext {
     }
 }
 
-impl std::fmt::Display for IrContext {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        fn fmt_bb(
-            ctx: &IrContext,
-            f: &mut std::fmt::Formatter<'_>,
-            bb: BBId,
-            indent: usize,
-            written: &mut HashSet<BBId>,
-        ) -> std::fmt::Result {
-            if written.contains(&bb) {
-                return Ok(());
-            } else {
-                written.insert(bb);
-            }
-
-            writeln!(
-                f,
-                "{} BB {}",
-                std::iter::repeat(' ').take(indent * 2).collect::<String>(),
-                bb
-            )?;
-            let indented = std::iter::repeat(' ')
-                .take(indent + 1 * 2)
-                .collect::<String>();
-            for inst in ctx[bb].stmts.iter() {
-                writeln!(
-                    f,
-                    "{}{}",
-                    indented,
-                    match &inst.kind {
-                        IrStmtKind::Exec(inst) => format!("EXEC {:?}", inst.kind),
-                        IrStmtKind::VarLive(v) =>
-                            format!("VARLIVE {} ({})", ctx[*v].name, ctx.typename(ctx[*v].ty)),
-                        IrStmtKind::Store { var, val } => format!(
-                            "STORE {:?} -> {} ({})",
-                            val.kind, ctx[*var].name, ctx[*var].ty
-                        ),
-                        IrStmtKind::Write { ptr, val } =>
-                            format!("WRITE {:?} -> {:?}", ptr.kind, val.kind),
-                        IrStmtKind::Call { fun, args } => format!(
-                            "CALL {} ({:?})",
-                            ctx[*fun].name,
-                            args.iter()
-                                .map(|arg| format!("{:?}", arg.kind))
-                                .collect::<Vec<_>>()
-                        ),
-                    }
-                )?;
-            }
-
-            writeln!(
-                f,
-                "{}{}",
-                indented,
-                match &ctx[bb].terminator {
-                    IrTerminator::Return(v) => format!("RETURN {:?}", v.kind),
-                    IrTerminator::Jmp(bb) => format!("JMP {}", bb),
-                    IrTerminator::JmpIf {
-                        condition,
-                        if_true,
-                        if_false,
-                    } => format!(
-                        "JMPIF {:?} -> {} else {}",
-                        condition.kind, if_true, if_false
-                    ),
-                    IrTerminator::JmpMatch {
-                        variant,
-                        discriminants,
-                        default_jmp,
-                    } => format!(
-                        "JMPMATCH {:?} -> {}else {}",
-                        variant.kind,
-                        discriminants
-                            .iter()
-                            .map(|(v, bb)| format!("{}{} -> {}\n", indented, ctx.typename(*v), bb))
-                            .collect::<String>(),
-                        default_jmp,
-                    ),
-                    IrTerminator::Invalid => "INVALID".to_owned(),
-                }
-            )?;
-
-            match &ctx[bb].terminator {
-                IrTerminator::Jmp(bb) => fmt_bb(ctx, f, *bb, indent + 1, written),
-                IrTerminator::JmpIf {
-                    condition: _,
-                    if_true,
-                    if_false,
-                } => {
-                    fmt_bb(ctx, f, *if_true, indent + 1, written)?;
-                    fmt_bb(ctx, f, *if_false, indent + 1, written)
-                }
-                IrTerminator::JmpMatch {
-                    variant: _,
-                    discriminants,
-                    default_jmp,
-                } => {
-                    for (_, bb) in discriminants {
-                        fmt_bb(ctx, f, *bb, indent + 1, written)?;
-                    }
-                    fmt_bb(ctx, f, *default_jmp, indent, written)
-                }
-                _ => Ok(()),
-            }
+#[cfg(test)]
+mod tests {
+    use super::{
+        types::IrType,
+        value::{CastKind, IrExpr, IrExprKind, IrLiteral},
+        IrContext, IrStmt, IrStmtKind, IrTerminator,
+    };
+    use crate::{ir::lower::testing::lower, util::loc::Span, Symbol};
+
+    #[test]
+    fn cast_kinds() {
+        let mut ctx = IrContext::new();
+        let ptr = ctx.ptr_to(IrContext::U8);
+        let ptr_i32 = ctx.ptr_to(IrContext::I32);
+        let alias = ctx.types.insert_nointern(IrType::Alias {
+            name: Symbol::from("int"),
+            ty: IrContext::I32,
+        });
+        let sum = ctx.sum_of(vec![IrContext::I32, IrContext::F64]);
+
+        let cases = [
+            (IrContext::I32, IrContext::I32, Some(CastKind::Identity)),
+            (alias, IrContext::I32, Some(CastKind::Identity)),
+            (IrContext::I8, IrContext::U32, Some(CastKind::IntResize)),
+            (IrContext::U64, IrContext::I16, Some(CastKind::IntResize)),
+            (IrContext::CHAR, IrContext::U32, Some(CastKind::IntResize)),
+            (IrContext::I32, IrContext::F64, Some(CastKind::IntToFloat)),
+            (IrContext::F32, IrContext::U8, Some(CastKind::FloatToInt)),
+            (IrContext::F32, IrContext::F64, Some(CastKind::FloatResize)),
+            (IrContext::USIZE, ptr, Some(CastKind::IntToPtr)),
+            (ptr, IrContext::USIZE, Some(CastKind::PtrToInt)),
+            (ptr, ptr_i32, Some(CastKind::PtrToPtr)),
+            (IrContext::BOOL, IrContext::I32, Some(CastKind::BoolToInt)),
+            (IrContext::I32, IrContext::BOOL, Some(CastKind::IntToBool)),
+            (sum, IrContext::F64, None),
+            (IrContext::I32, sum, Some(CastKind::SumWrap)),
+            (IrContext::F32, ptr, None),
+            (IrContext::BOOL, IrContext::F32, None),
+            (IrContext::UNIT, IrContext::I32, None),
+        ];
+
+        for &(from, to, kind) in cases.iter() {
+            assert_eq!(
+                ctx.cast_kind(from, to),
+                kind,
+                "casting {} to {}",
+                ctx.typename(from),
+                ctx.typename(to)
+            );
         }
+    }
 
-        let mut written = HashSet::new();
-        for fun in self.funs.indices() {
-            writeln!(
-                f,
-                "{} {} [{:?}] in file {}",
-                self.typename(self[fun].ty_id),
-                self[fun].name,
-                self[fun].flags,
-                self[fun].file
-            )?;
-            if let Some(body) = self[fun].body.as_ref() {
-                fmt_bb(self, f, body.entry, 0, &mut written)?;
-            }
+    #[test]
+    fn typename_writes_long_pointer_chains() {
+        let mut ctx = IrContext::new();
+        let mut ty = IrContext::U8;
+        for _ in 0..8 {
+            ty = ctx.ptr_to(ty);
         }
+        let array = ctx.array_of(ty, 2);
+        assert_eq!(ctx.typename(array).to_string(), "[2]********u8");
+    }
+
+    #[test]
+    fn typename_cuts_off_cycles() {
+        let mut ctx = IrContext::new();
+        let ptr = ctx.types.insert_nointern(IrType::Invalid);
+        let tuple = ctx.tuple_of(vec![IrContext::I32, ptr]);
+        *ctx.types.get_mut(ptr) = IrType::Ptr(tuple);
+        assert_eq!(ctx.typename(tuple).to_string(), "(i32, *...)");
+    }
+
+    #[test]
+    fn block_helpers_match_indexing() {
+        let mut ctx = IrContext::new();
+        let bb = ctx.new_bb();
+        let other = ctx.new_bb();
+        assert_ne!(bb, other);
+
+        ctx[bb].terminator = IrTerminator::Unreachable;
+        ctx.bb_mut(other).stmts.push(IrStmt {
+            span: Span::from(0..0),
+            kind: IrStmtKind::Exec(IrExpr {
+                span: Span::from(0..0),
+                ty: IrContext::UNIT,
+                kind: IrExprKind::Lit(IrLiteral::Unit),
+            }),
+        });
+
+        assert!(matches!(ctx.bb(bb).terminator, IrTerminator::Unreachable));
+        assert!(ctx.bb(bb).stmts.is_empty());
+        assert_eq!(ctx[other].stmts.len(), 1);
+        assert!(matches!(ctx.bb(other).terminator, IrTerminator::Invalid));
+    }
 
-        Ok(())
+    #[test]
+    fn structurally_equal_types_are_interned_once() {
+        let mut ctx = IrContext::new();
+        assert_eq!(ctx.ptr_to(IrContext::I32), ctx.ptr_to(IrContext::I32));
+        assert_ne!(ctx.ptr_to(IrContext::I32), ctx.ptr_to(IrContext::U32));
+
+        let ptr = ctx.ptr_to(IrContext::U8);
+        let chain = ctx.ptr_to(ptr);
+        let other_ptr = ctx.ptr_to(IrContext::U8);
+        assert_eq!(ctx.ptr_to(other_ptr), chain);
+
+        assert_eq!(
+            ctx.array_of(IrContext::I64, 4),
+            ctx.array_of(IrContext::I64, 4)
+        );
+        assert_ne!(
+            ctx.array_of(IrContext::I64, 4),
+            ctx.array_of(IrContext::I64, 5)
+        );
+        assert_eq!(
+            ctx.sum_of(vec![IrContext::I32, IrContext::F64]),
+            ctx.sum_of(vec![IrContext::F64, IrContext::I32, IrContext::I32])
+        );
+        assert_eq!(
+            ctx.tuple_of(vec![IrContext::I32, ptr]),
+            ctx.tuple_of(vec![IrContext::I32, other_ptr])
+        );
+    }
+
+    #[test]
+    fn json_output_is_stable_and_uses_raw_indices() {
+        let src = "fun add(i32 a, i32 b) -> i32 {
+    return a + b
+}
+
+fun main() -> i32 {
+    return add(1, 2)
+}";
+        let json = lower(src).ctx.to_json();
+        assert_eq!(json, lower(src).ctx.to_json());
+
+        let value: serde_json::Value = serde_json::from_str(&json).unwrap();
+        let funs = value["funs"].as_array().unwrap();
+        let add = funs
+            .iter()
+            .find(|fun| fun["name"] == "add")
+            .unwrap_or_else(|| panic!("no function named add in {}", json));
+        assert_eq!(add["ty"]["return_ty"], IrContext::I32.val() as u64);
+        assert_eq!(add["ty"]["params"][0][1], "a");
+
+        //Blocks of the body are referenced by their index in the block array
+        let blocks = value["bbs"].as_array().unwrap();
+        let entry = add["body"]["entry"].as_u64().unwrap() as usize;
+        assert!(
+            blocks[entry]["terminator"].get("Return").is_some(),
+            "{}",
+            json
+        );
+        assert_eq!(
+            value["types"][IrContext::I32.val()],
+            serde_json::json!({ "Integer": { "signed": true, "width": "ThirtyTwo" } })
+        );
     }
 }
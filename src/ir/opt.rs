@@ -0,0 +1,768 @@
+//! Passes that simplify lowered IR before it is passed to a code generator
+
+use hashbrown::{HashMap, HashSet};
+
+use crate::{
+    ast::{BigInt, IntegerWidth},
+    parse::token::Op,
+    util::loc::Span,
+};
+
+use super::{
+    dom::DomTree,
+    types::{IrIntegerType, IrType},
+    value::{CastKind, IrExpr, IrExprKind, IrLiteral},
+    BBId, FunId, IrBB, IrBranch, IrContext, IrStmt, IrStmtKind, IrTerminator, TypeId, VarId,
+};
+
+/// Replace conditional jumps on constant conditions in a function's body with unconditional
+/// jumps to the taken branch, leaving the other branch to be removed by
+/// [remove_dead_blocks] if nothing else jumps to it
+pub fn fold_branches(ctx: &mut IrContext, fun: FunId) {
+    for bb in ctx.blocks_of(fun) {
+        let to = match &ctx[bb].terminator {
+            IrTerminator::JmpIf {
+                condition,
+                if_true,
+                if_false,
+            } => match condition.kind {
+                IrExprKind::Lit(IrLiteral::Bool(true)) => if_true.clone(),
+                IrExprKind::Lit(IrLiteral::Bool(false)) => if_false.clone(),
+                _ => continue,
+            },
+            _ => continue,
+        };
+
+        ctx[bb].terminator = IrTerminator::Jmp(to);
+    }
+}
+
+/// Drop all blocks of a function's body that can't be reached from its entry block, clearing
+/// their statements. Blocks can't be removed from the arena, so dead blocks are left empty with
+/// an [Unreachable](IrTerminator::Unreachable) terminator. No live block can jump to a dead
+/// block, so no other blocks need to be changed
+pub fn remove_dead_blocks(ctx: &mut IrContext, fun: FunId) {
+    let reachable = ctx.blocks_of(fun).into_iter().collect::<HashSet<_>>();
+    let blocks = match ctx.fun_body_mut(fun) {
+        Some(body) => std::mem::take(&mut body.blocks),
+        None => return,
+    };
+
+    let (live, dead): (Vec<_>, Vec<_>) = blocks.into_iter().partition(|bb| reachable.contains(bb));
+    for bb in dead {
+        ctx[bb].stmts = vec![];
+        ctx[bb].terminator = IrTerminator::Unreachable;
+        ctx[bb].terminator_span = None;
+    }
+
+    if let Some(body) = ctx.fun_body_mut(fun) {
+        body.blocks = live;
+    }
+}
+
+/// Remove stores to variables of a function's body that are overwritten or go out of scope
+/// before being read, then remove the declarations of variables that are no longer referenced.
+/// Stores of values with side effects are replaced with [Exec](IrStmtKind::Exec) statements,
+/// and variables that have their address taken are left alone as they may be read through a
+/// pointer
+pub fn remove_dead_stores(ctx: &mut IrContext, fun: FunId) {
+    //Blocks are visited in postorder so that most successors are visited before their
+    //predecessors
+    let mut order = ctx.reverse_postorder(fun);
+    order.reverse();
+
+    let address_taken = address_taken(ctx, &order);
+
+    //Removing a store may leave the stores of variables it read dead in other blocks
+    loop {
+        let live_in = live_vars(ctx, &order);
+        let mut removed = false;
+        for bb in order.iter() {
+            let mut live = live_out(ctx, *bb, &live_in);
+            terminator_reads(&ctx[*bb].terminator, &mut |expr| add_reads(expr, &mut live));
+
+            let stmts = std::mem::take(&mut ctx[*bb].stmts);
+            let mut kept = Vec::with_capacity(stmts.len());
+            for stmt in stmts.into_iter().rev() {
+                let stmt = match stmt.kind {
+                    IrStmtKind::Store { var, val }
+                        if !live.contains(&var) && !address_taken.contains(&var) =>
+                    {
+                        removed = true;
+                        if !has_side_effects(&val) {
+                            continue;
+                        }
+                        IrStmt {
+                            span: stmt.span,
+                            kind: IrStmtKind::Exec(val),
+                        }
+                    }
+                    IrStmtKind::Zero(var)
+                        if !live.contains(&var) && !address_taken.contains(&var) =>
+                    {
+                        removed = true;
+                        continue;
+                    }
+                    kind => IrStmt {
+                        span: stmt.span,
+                        kind,
+                    },
+                };
+
+                live_transfer(&stmt.kind, &mut live);
+                kept.push(stmt);
+            }
+
+            kept.reverse();
+            ctx[*bb].stmts = kept;
+        }
+
+        if !removed {
+            break;
+        }
+    }
+
+    let mut referenced = HashSet::new();
+    for bb in order.iter() {
+        for stmt in ctx[*bb].stmts.iter() {
+            match &stmt.kind {
+                IrStmtKind::Store { var, .. } | IrStmtKind::Zero(var) => {
+                    referenced.insert(*var);
+                }
+                _ => (),
+            }
+        }
+        block_reads(&ctx[*bb], &mut |expr| add_reads(expr, &mut referenced));
+    }
+
+    for bb in order {
+        ctx[bb].stmts.retain(|stmt| match &stmt.kind {
+            IrStmtKind::VarLive(var) | IrStmtKind::VarDead(var) => referenced.contains(var),
+            _ => true,
+        });
+    }
+}
+
+/// Promote variables of a function's body that never have their address taken from memory to
+/// SSA values, removing their declarations and stores. Reads of a promoted variable are
+/// replaced with the value most recently stored to it, and blocks where different stored values
+/// meet are given a parameter for the variable by every jump to them, placed at the dominance
+/// frontiers of the blocks that store to the variable where the variable is still live.
+///
+/// Values that are cheap to compute and read no memory, like literals, are copied to every read
+/// of the variable. Other stored values are passed to a new block that the rest of the storing
+/// block is moved to, so that they are evaluated once at the point of the store. Variables
+/// holding function arguments or values that are not numbers, booleans, characters, or pointers
+/// are left in memory
+pub fn promote_vars(ctx: &mut IrContext, fun: FunId) {
+    let (entry, args) = match ctx.fun_body(fun) {
+        Some(body) => (
+            body.entry,
+            body.args.iter().flatten().copied().collect::<HashSet<_>>(),
+        ),
+        None => return,
+    };
+    //The entry block can't have parameters, so values can't meet in it
+    if ctx
+        .predecessors(fun)
+        .get(&entry)
+        .is_some_and(|preds| !preds.is_empty())
+    {
+        return;
+    }
+
+    let dom = DomTree::new(ctx, fun);
+    let address_taken = address_taken(ctx, dom.rpo());
+
+    //The initial value of every promoted variable is zero, which is read only if the variable
+    //is read before it is assigned
+    let span = ctx[fun].span;
+    let mut zeros = HashMap::new();
+    let mut stores = HashMap::<VarId, Vec<BBId>>::new();
+    for bb in dom.rpo() {
+        let mut vars = HashSet::new();
+        block_reads(&ctx[*bb], &mut |expr| add_reads(expr, &mut vars));
+        for stmt in ctx[*bb].stmts.iter() {
+            match &stmt.kind {
+                IrStmtKind::Store { var, .. } | IrStmtKind::Zero(var) => {
+                    let blocks = stores.entry(*var).or_default();
+                    if blocks.last() != Some(bb) {
+                        blocks.push(*bb);
+                    }
+                    vars.insert(*var);
+                }
+                IrStmtKind::VarLive(var) | IrStmtKind::VarDead(var) => {
+                    vars.insert(*var);
+                }
+                _ => (),
+            }
+        }
+
+        for var in vars {
+            if args.contains(&var) || address_taken.contains(&var) || zeros.contains_key(&var) {
+                continue;
+            }
+            if let Some(zero) = zero_value(ctx, ctx[var].ty, span) {
+                zeros.insert(var, zero);
+            }
+        }
+    }
+
+    if zeros.is_empty() {
+        return;
+    }
+
+    //Parameters added to each block by the variable they hold the value of, variables are
+    //visited in order so that parameters are added in the same order every compilation
+    let mut order = dom.rpo().to_vec();
+    order.reverse();
+    let live_in = live_vars(ctx, &order);
+    let mut promoted = zeros.keys().copied().collect::<Vec<_>>();
+    promoted.sort_by_key(|var| var.val());
+    let mut params = HashMap::<BBId, Vec<(VarId, usize)>>::new();
+    for var in promoted {
+        let mut worklist = stores.get(&var).cloned().unwrap_or_default();
+        let mut added = HashSet::new();
+        while let Some(bb) = worklist.pop() {
+            for frontier in dom.frontier(bb).iter().copied() {
+                let live = live_in
+                    .get(&frontier)
+                    .is_some_and(|live| live.contains(&var));
+                if live && added.insert(frontier) {
+                    let ty = ctx[var].ty;
+                    let params_of = &mut ctx[frontier].params;
+                    params_of.push(ty);
+                    params
+                        .entry(frontier)
+                        .or_default()
+                        .push((var, params_of.len() - 1));
+                    worklist.push(frontier);
+                }
+            }
+        }
+    }
+
+    //Every block is renamed with the values of variables at the end of its immediate dominator
+    let mut stack = vec![(entry, zeros)];
+    while let Some((bb, mut values)) = stack.pop() {
+        rename_block(ctx, fun, bb, &mut values, &params);
+        for child in dom.children(bb) {
+            stack.push((child, values.clone()));
+        }
+    }
+}
+
+/// Replace the reads, stores, and declarations of promoted variables in a block with their
+/// values, given the values of all promoted variables at the start of the block, and pass the
+/// values of variables to the parameters added to the block's successors
+fn rename_block(
+    ctx: &mut IrContext,
+    fun: FunId,
+    bb: BBId,
+    values: &mut HashMap<VarId, IrExpr>,
+    params: &HashMap<BBId, Vec<(VarId, usize)>>,
+) {
+    for (var, idx) in params.get(&bb).into_iter().flatten() {
+        values.insert(
+            *var,
+            IrExpr {
+                span: values[var].span,
+                ty: ctx[bb].params[*idx],
+                kind: IrExprKind::Param(bb, *idx),
+            },
+        );
+    }
+
+    let mut bb = bb;
+    let stmts = std::mem::take(&mut ctx[bb].stmts);
+    let mut terminator = std::mem::replace(&mut ctx[bb].terminator, IrTerminator::Invalid);
+    let terminator_span = ctx[bb].terminator_span;
+    let mut kept = Vec::with_capacity(stmts.len());
+    for mut stmt in stmts {
+        stmt_exprs_mut(&mut stmt.kind, &mut |expr| replace_reads(expr, values));
+        match stmt.kind {
+            IrStmtKind::Store { var, val } if values.contains_key(&var) => {
+                if is_rematerializable(&val) {
+                    values.insert(var, val);
+                    continue;
+                }
+
+                //Move the rest of the block to a new block that receives the stored value
+                let ty = ctx[var].ty;
+                let next = ctx.new_bb();
+                ctx[next].params.push(ty);
+                if let Some(body) = ctx.fun_body_mut(fun) {
+                    body.blocks.push(next);
+                }
+                ctx[bb].stmts = std::mem::take(&mut kept);
+                ctx[bb].terminator = IrTerminator::Jmp(IrBranch {
+                    bb: next,
+                    args: vec![val],
+                });
+                ctx[bb].terminator_span = Some(stmt.span);
+                values.insert(
+                    var,
+                    IrExpr {
+                        span: stmt.span,
+                        ty,
+                        kind: IrExprKind::Param(next, 0),
+                    },
+                );
+                bb = next;
+            }
+            IrStmtKind::Zero(var) if values.contains_key(&var) => {
+                let span = values[&var].span;
+                if let Some(zero) = zero_value(ctx, ctx[var].ty, span) {
+                    values.insert(var, zero);
+                }
+            }
+            IrStmtKind::VarLive(var) | IrStmtKind::VarDead(var) if values.contains_key(&var) => {}
+            kind => kept.push(IrStmt {
+                span: stmt.span,
+                kind,
+            }),
+        }
+    }
+
+    terminator_exprs_mut(&mut terminator, &mut |expr| replace_reads(expr, values));
+    for branch in terminator.branches_mut() {
+        for (var, _) in params.get(&branch.bb).into_iter().flatten() {
+            branch.args.push(values[var].clone());
+        }
+    }
+
+    ctx[bb].stmts = kept;
+    ctx[bb].terminator = terminator;
+    ctx[bb].terminator_span = terminator_span;
+}
+
+/// Replace a read of a promoted variable with the variable's current value
+fn replace_reads(expr: &mut IrExpr, values: &HashMap<VarId, IrExpr>) {
+    expr.walk_mut(&mut |expr| {
+        if let IrExprKind::Var(var) = &expr.kind {
+            if let Some(val) = values.get(var) {
+                *expr = IrExpr {
+                    span: expr.span,
+                    ..val.clone()
+                };
+            }
+        }
+    })
+}
+
+/// Check if an expression can be evaluated at every read of a variable it is stored in instead
+/// of once when it is stored, because it reads no memory and is no more expensive than a
+/// constant
+fn is_rematerializable(expr: &IrExpr) -> bool {
+    match &expr.kind {
+        IrExprKind::Lit(
+            IrLiteral::Integer(..)
+            | IrLiteral::Float(..)
+            | IrLiteral::Char(_)
+            | IrLiteral::Bool(_)
+            | IrLiteral::Unit,
+        )
+        | IrExprKind::Param(..)
+        | IrExprKind::Fun(_)
+        | IrExprKind::SizeOf(_)
+        | IrExprKind::AlignOf(_) => true,
+        IrExprKind::Cast(operand, ..) => is_rematerializable(operand),
+        _ => false,
+    }
+}
+
+/// Create a zero constant of the given type, or `None` if variables of the type can't be
+/// promoted to SSA values
+fn zero_value(ctx: &IrContext, ty: TypeId, span: Span) -> Option<IrExpr> {
+    let lit = |lit| IrExprKind::Lit(lit);
+    let kind = match &ctx[ctx.unwrap_alias(ty)] {
+        IrType::Integer(ity) => lit(IrLiteral::Integer(
+            BigInt {
+                val: 0,
+                sign: false,
+            },
+            *ity,
+        )),
+        IrType::Float(fty) => lit(IrLiteral::Float(0.0, *fty)),
+        IrType::Bool => lit(IrLiteral::Bool(false)),
+        IrType::Char => lit(IrLiteral::Char('\0')),
+        IrType::Ptr(_) => IrExprKind::Cast(
+            Box::new(IrExpr {
+                span,
+                ty: IrContext::USIZE,
+                kind: lit(IrLiteral::Integer(
+                    BigInt {
+                        val: 0,
+                        sign: false,
+                    },
+                    IrIntegerType {
+                        signed: false,
+                        width: IntegerWidth::PtrSize,
+                    },
+                )),
+            }),
+            ty,
+            CastKind::IntToPtr,
+        ),
+        _ => return None,
+    };
+
+    Some(IrExpr { span, ty, kind })
+}
+
+/// Get every variable that has its address taken in the given blocks, which may be read or
+/// written through a pointer
+fn address_taken(ctx: &IrContext, blocks: &[BBId]) -> HashSet<VarId> {
+    let mut address_taken = HashSet::new();
+    for bb in blocks.iter() {
+        block_reads(&ctx[*bb], &mut |expr| {
+            expr.walk(&mut |expr| {
+                if let IrExprKind::Unary(Op::AND, operand) = &expr.kind {
+                    operand.walk(&mut |expr| {
+                        if let IrExprKind::Var(var) = &expr.kind {
+                            address_taken.insert(*var);
+                        }
+                    })
+                }
+            })
+        });
+    }
+    address_taken
+}
+
+/// Compute the set of variables that may be read before being overwritten at the start of
+/// each block, given blocks in postorder
+fn live_vars(ctx: &IrContext, order: &[BBId]) -> HashMap<BBId, HashSet<VarId>> {
+    let mut live_in = HashMap::<BBId, HashSet<VarId>>::new();
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for bb in order.iter() {
+            let mut live = live_out(ctx, *bb, &live_in);
+            terminator_reads(&ctx[*bb].terminator, &mut |expr| add_reads(expr, &mut live));
+            for stmt in ctx[*bb].stmts.iter().rev() {
+                live_transfer(&stmt.kind, &mut live);
+            }
+
+            if live_in.get(bb) != Some(&live) {
+                live_in.insert(*bb, live);
+                changed = true;
+            }
+        }
+    }
+
+    live_in
+}
+
+/// Get the set of variables that may be read after a block by any of its successors
+fn live_out(ctx: &IrContext, bb: BBId, live_in: &HashMap<BBId, HashSet<VarId>>) -> HashSet<VarId> {
+    ctx[bb]
+        .terminator
+        .successors()
+        .filter_map(|succ| live_in.get(&succ))
+        .flatten()
+        .copied()
+        .collect()
+}
+
+/// Apply the effects of a statement to the set of variables live after it, giving the set of
+/// variables live before it
+fn live_transfer(stmt: &IrStmtKind, live: &mut HashSet<VarId>) {
+    match stmt {
+        IrStmtKind::Store { var, .. } | IrStmtKind::Zero(var) | IrStmtKind::VarDead(var) => {
+            live.remove(var);
+        }
+        _ => (),
+    }
+    stmt_reads(stmt, &mut |expr| add_reads(expr, live));
+}
+
+/// Add every variable referenced in an expression to a set of variables
+fn add_reads(expr: &IrExpr, vars: &mut HashSet<VarId>) {
+    expr.walk(&mut |expr| {
+        if let IrExprKind::Var(var) = &expr.kind {
+            vars.insert(*var);
+        }
+    })
+}
+
+/// Check if evaluating an expression may have effects other than producing its value
+fn has_side_effects(expr: &IrExpr) -> bool {
+    let mut effects = false;
+    expr.walk(&mut |expr| {
+        if let IrExprKind::Call(..) | IrExprKind::VolatileRead(_) = &expr.kind {
+            effects = true;
+        }
+    });
+    effects
+}
+
+/// Visit every expression evaluated by the statements and terminator of a block
+pub(super) fn block_reads(bb: &IrBB, visit: &mut dyn FnMut(&IrExpr)) {
+    for stmt in bb.stmts.iter() {
+        stmt_reads(&stmt.kind, visit);
+    }
+    terminator_reads(&bb.terminator, visit);
+}
+
+/// Visit every expression evaluated by a statement, including the lvalues that are written to
+fn stmt_reads(stmt: &IrStmtKind, visit: &mut dyn FnMut(&IrExpr)) {
+    match stmt {
+        IrStmtKind::Store { val, .. } | IrStmtKind::Exec(val) => visit(val),
+        IrStmtKind::StoreMem { addr: ptr, val } | IrStmtKind::VolatileWrite { ptr, val } => {
+            visit(ptr);
+            visit(val);
+        }
+        IrStmtKind::Copy { dst, src, .. } => {
+            visit(dst);
+            visit(src);
+        }
+        IrStmtKind::Assert { cond, .. } => visit(cond),
+        IrStmtKind::Call { args, .. } => {
+            for arg in args {
+                visit(arg);
+            }
+        }
+        IrStmtKind::VarLive(_) | IrStmtKind::VarDead(_) | IrStmtKind::Zero(_) => (),
+    }
+}
+
+/// Visit every expression evaluated by a statement mutably, in the same order as [stmt_reads]
+fn stmt_exprs_mut(stmt: &mut IrStmtKind, visit: &mut dyn FnMut(&mut IrExpr)) {
+    match stmt {
+        IrStmtKind::Store { val, .. } | IrStmtKind::Exec(val) => visit(val),
+        IrStmtKind::StoreMem { addr: ptr, val } | IrStmtKind::VolatileWrite { ptr, val } => {
+            visit(ptr);
+            visit(val);
+        }
+        IrStmtKind::Copy { dst, src, .. } => {
+            visit(dst);
+            visit(src);
+        }
+        IrStmtKind::Assert { cond, .. } => visit(cond),
+        IrStmtKind::Call { args, .. } => {
+            for arg in args {
+                visit(arg);
+            }
+        }
+        IrStmtKind::VarLive(_) | IrStmtKind::VarDead(_) | IrStmtKind::Zero(_) => (),
+    }
+}
+
+/// Visit the expressions evaluated by a terminator mutably, in the same order as
+/// [terminator_reads]
+fn terminator_exprs_mut(terminator: &mut IrTerminator, visit: &mut dyn FnMut(&mut IrExpr)) {
+    match terminator {
+        IrTerminator::Return(expr) => visit(expr),
+        IrTerminator::JmpIf { condition, .. } => visit(condition),
+        IrTerminator::JmpMatch { variant, .. } => visit(variant),
+        IrTerminator::Jmp(_) | IrTerminator::Unreachable | IrTerminator::Invalid => (),
+    }
+    for branch in terminator.branches_mut() {
+        for arg in branch.args.iter_mut() {
+            visit(arg);
+        }
+    }
+}
+
+/// Visit the expressions evaluated by a terminator, including the arguments passed to the blocks
+/// it jumps to
+fn terminator_reads(terminator: &IrTerminator, visit: &mut dyn FnMut(&IrExpr)) {
+    match terminator {
+        IrTerminator::Return(expr) => visit(expr),
+        IrTerminator::JmpIf { condition, .. } => visit(condition),
+        IrTerminator::JmpMatch { variant, .. } => visit(variant),
+        IrTerminator::Jmp(_) | IrTerminator::Unreachable | IrTerminator::Invalid => (),
+    }
+    for branch in terminator.branches() {
+        for arg in branch.args.iter() {
+            visit(arg);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{fold_branches, promote_vars, remove_dead_blocks, remove_dead_stores};
+    use crate::ir::{
+        consteval::ConstValue,
+        lower::testing::lower,
+        value::{IrExprKind, IrLiteral},
+        BBId, FunId, IrContext, IrStmtKind, IrTerminator,
+    };
+
+    /// Count the statements of a function's body that declare or store to a variable
+    fn var_stmts(ctx: &IrContext, fun: FunId) -> usize {
+        ctx.fun_body(fun)
+            .unwrap()
+            .blocks
+            .iter()
+            .flat_map(|bb| ctx[*bb].stmts.iter())
+            .filter(|stmt| matches!(stmt.kind, IrStmtKind::VarLive(_) | IrStmtKind::Store { .. }))
+            .count()
+    }
+
+    /// Get the constant integer returned from a block, panicking if the block doesn't return one
+    fn returned(ctx: &IrContext, bb: BBId) -> i128 {
+        match &ctx[bb].terminator {
+            IrTerminator::Return(returned) => match ctx.const_eval(returned) {
+                Ok(ConstValue::Integer(val, _)) => val,
+                other => panic!("Returned value is not a constant integer: {:?}", other),
+            },
+            other => panic!("Block ends with {:?} instead of returning", other),
+        }
+    }
+
+    #[test]
+    fn promote_vars_removes_stores() {
+        let mut lowered = lower(
+            "fun main() -> i32 {
+    let x = 1
+    let y = x + 2
+    return y
+}",
+        );
+        let main = lowered.fun("main");
+        let ctx = &mut lowered.ctx;
+        assert_eq!(var_stmts(ctx, main), 5);
+
+        promote_vars(ctx, main);
+        assert_eq!(var_stmts(ctx, main), 0);
+        assert!(ctx.validate().is_empty());
+
+        let body = ctx.fun_body(main).unwrap();
+        let returned = body
+            .blocks
+            .iter()
+            .find_map(|bb| match &ctx[*bb].terminator {
+                IrTerminator::Return(returned) => Some(returned),
+                _ => None,
+            })
+            .unwrap();
+        //The sum is evaluated once where it was stored and passed to the returning block
+        assert!(matches!(returned.kind, IrExprKind::Param(..)));
+    }
+
+    #[test]
+    fn promote_vars_keeps_address_taken_vars() {
+        let mut lowered = lower(
+            "fun main() -> i32 {
+    mut x = 1
+    let p = &x
+    return *p
+}",
+        );
+        let main = lowered.fun("main");
+        let ctx = &mut lowered.ctx;
+        promote_vars(ctx, main);
+        assert_eq!(var_stmts(ctx, main), 2);
+        assert!(ctx.validate().is_empty());
+    }
+
+    #[test]
+    fn constant_branches_are_folded_and_removed() {
+        let mut lowered = lower(
+            "fun main() -> i32 {
+    if true {
+        return 1
+    } else {
+        return 2
+    }
+}",
+        );
+        let main = lowered.fun("main");
+        let ctx = &mut lowered.ctx;
+        let before = ctx.fun_body(main).unwrap().blocks.len();
+
+        fold_branches(ctx, main);
+        let entry = ctx.fun_body(main).unwrap().entry;
+        assert!(matches!(ctx[entry].terminator, IrTerminator::Jmp(_)));
+
+        remove_dead_blocks(ctx, main);
+        assert!(ctx.fun_body(main).unwrap().blocks.len() < before);
+        assert!(ctx.validate().is_empty());
+    }
+
+    #[test]
+    fn overwritten_stores_are_removed() {
+        let mut lowered = lower(
+            "fun main() -> i32 {
+    let x = 1
+    let y = 2
+    return y
+}",
+        );
+        let main = lowered.fun("main");
+        let ctx = &mut lowered.ctx;
+        assert_eq!(var_stmts(ctx, main), 5);
+
+        remove_dead_stores(ctx, main);
+        assert_eq!(var_stmts(ctx, main), 2);
+        assert!(ctx.validate().is_empty());
+    }
+
+    #[test]
+    fn constant_comparisons_fold_to_jumps() {
+        let mut lowered = lower(
+            "fun main() -> i32 {
+    if 1 < 2 {
+        return 1
+    } else {
+        return 2
+    }
+}",
+        );
+        let main = lowered.fun("main");
+        let ctx = &mut lowered.ctx;
+        let entry = ctx.fun_body(main).unwrap().entry;
+        match &ctx[entry].terminator {
+            IrTerminator::JmpIf { condition, .. } => assert!(matches!(
+                condition.kind,
+                IrExprKind::Lit(IrLiteral::Bool(true))
+            )),
+            other => panic!("Expected a conditional jump, found {:?}", other),
+        }
+
+        fold_branches(ctx, main);
+        let taken = match &ctx[entry].terminator {
+            IrTerminator::Jmp(to) => to.bb,
+            other => panic!("Expected the branch to be folded, found {:?}", other),
+        };
+        assert_eq!(returned(ctx, taken), 1);
+
+        remove_dead_blocks(ctx, main);
+        assert_eq!(ctx.fun_body(main).unwrap().blocks.len(), 2);
+        assert!(ctx.validate().is_empty());
+    }
+
+    #[test]
+    fn i8_arithmetic_wraps_past_127() {
+        let mut lowered = lower(
+            "fun wrapped() -> i8 {
+    return 127i8 + 1i8
+}
+fun main() -> i32 {
+    if 127i8 + 1i8 < 0i8 {
+        return 1
+    } else {
+        return 2
+    }
+}",
+        );
+        let wrapped = lowered.fun("wrapped");
+        let main = lowered.fun("main");
+        let ctx = &mut lowered.ctx;
+
+        let entry = ctx.fun_body(wrapped).unwrap().entry;
+        assert_eq!(returned(ctx, entry), -128);
+
+        //The wrapped sum is negative, so the branch folds to the true block
+        fold_branches(ctx, main);
+        let entry = ctx.fun_body(main).unwrap().entry;
+        let taken = match &ctx[entry].terminator {
+            IrTerminator::Jmp(to) => to.bb,
+            other => panic!("Expected the branch to be folded, found {:?}", other),
+        };
+        assert_eq!(returned(ctx, taken), 1);
+    }
+}
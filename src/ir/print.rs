@@ -0,0 +1,686 @@
+//! Textual rendering of lowered IR for debugging the lowerer
+//!
+//! Basic blocks are labelled `bbN` in the order they are reached from a function's entry block,
+//! block parameters are numbered `vN` in the same order, and variables are rendered as
+//! `%name.N` with the variable's index, so output is the same across runs
+
+use std::fmt::{self, Write};
+
+use codespan_reporting::files::Files as _;
+use hashbrown::HashMap;
+
+use crate::{
+    ast::FunFlags,
+    util::{
+        files::{FileId, Files},
+        loc::Span,
+    },
+};
+
+use super::{
+    types::IrType,
+    value::{IrExpr, IrExprKind, IrLiteral},
+    BBId, DiscriminantId, FunId, IrBranch, IrContext, IrStmt, IrStmtKind, IrTerminator, TypeId,
+    VarId,
+};
+
+/// Labels of the blocks and block parameters of a single function
+struct Labels {
+    /// Label of every reachable block
+    blocks: HashMap<BBId, usize>,
+    /// Label of the first parameter of every reachable block
+    params: HashMap<BBId, usize>,
+}
+
+impl IrContext {
+    /// Render the signature and body of a single function
+    pub fn print_fun(&self, fun: FunId) -> String {
+        let mut buf = String::new();
+        self.write_fun(&mut buf, fun, None).unwrap();
+        buf
+    }
+
+    /// Render all globals and functions in the order they were declared
+    pub fn print(&self) -> String {
+        self.to_string()
+    }
+
+    /// Render all globals and functions like [print](Self::print), annotating every statement and
+    /// terminator with the line and column of the source code that produced it
+    pub fn print_with_locations(&self, files: &Files) -> String {
+        let mut buf = String::new();
+        self.write_all(&mut buf, Some(files)).unwrap();
+        buf
+    }
+
+    /// Write all globals and functions, annotating statements with their locations if source
+    /// files are given
+    fn write_all(&self, f: &mut dyn Write, files: Option<&Files>) -> fmt::Result {
+        let mut separate = false;
+        for glob in self.globals.iter() {
+            let mutability = if glob.mutable { "mut " } else { "" };
+            writeln!(
+                f,
+                "global {}@{}: {}",
+                mutability,
+                glob.name,
+                self.typename(glob.ty)
+            )?;
+            separate = true;
+        }
+
+        for fun in self.funs.indices() {
+            if separate {
+                writeln!(f)?;
+            }
+            self.write_fun(f, fun, files)?;
+            separate = true;
+        }
+
+        Ok(())
+    }
+
+    /// Write the signature and body of a function, labelling blocks in the order they are reached
+    fn write_fun(&self, f: &mut dyn Write, fun: FunId, files: Option<&Files>) -> fmt::Result {
+        let ir_fun = &self[fun];
+        let body = self.fun_body(fun);
+
+        if ir_fun.flags.contains(FunFlags::EXTERN) {
+            write!(f, "ext ")?;
+        }
+        if ir_fun.flags.contains(FunFlags::EXPORT) {
+            write!(f, "export ")?;
+        }
+        if ir_fun.flags.contains(FunFlags::USED) {
+            write!(f, "used ")?;
+        }
+        write!(f, "fun {}(", ir_fun.name)?;
+        for (idx, (ty, name)) in ir_fun.ty.params.iter().enumerate() {
+            if idx > 0 {
+                write!(f, ", ")?;
+            }
+            match (body.and_then(|body| body.args[idx]), name) {
+                (Some(var), _) => self.write_var(f, var)?,
+                (None, Some(name)) => write!(f, "{}", name)?,
+                (None, None) => write!(f, "_")?,
+            }
+            write!(f, ": {}", self.typename(*ty))?;
+        }
+        write!(
+            f,
+            ") -> {} @{}",
+            self.typename(ir_fun.ty.return_ty),
+            ir_fun.symbol
+        )?;
+
+        if body.is_none() {
+            return writeln!(f);
+        }
+        writeln!(f, " {{")?;
+
+        let order = self.blocks_of(fun);
+        let labels = self.labels(&order);
+        for bb in order.iter() {
+            self.write_block_header(f, *bb, &labels)?;
+            writeln!(f, ":")?;
+            for stmt in self[*bb].stmts.iter() {
+                write!(f, "    ")?;
+                self.write_stmt(f, stmt, &labels)?;
+                self.write_location(f, files, ir_fun.file, Some(stmt.span))?;
+                writeln!(f)?;
+            }
+            write!(f, "    ")?;
+            self.write_terminator(f, &self[*bb].terminator, &labels)?;
+            self.write_location(f, files, ir_fun.file, self[*bb].terminator_span)?;
+            writeln!(f)?;
+        }
+
+        writeln!(f, "}}")
+    }
+
+    /// Write the line and column of a span as a comment, if source files are given
+    fn write_location(
+        &self,
+        f: &mut dyn Write,
+        files: Option<&Files>,
+        file: FileId,
+        span: Option<Span>,
+    ) -> fmt::Result {
+        let location = match (files, span) {
+            (Some(files), Some(span)) => match files.location(file, span.from) {
+                Ok(location) => location,
+                Err(_) => return Ok(()),
+            },
+            _ => return Ok(()),
+        };
+        write!(f, "  ; {}:{}", location.line_number, location.column_number)
+    }
+
+    /// Render the control flow graph of a function's body as a Graphviz DOT digraph, with a node
+    /// for every reachable block listing its statements and an edge for every jump between
+    /// blocks
+    pub fn dot_of(&self, fun: FunId) -> String {
+        let mut buf = String::new();
+        self.write_dot(&mut buf, fun).unwrap();
+        buf
+    }
+
+    /// Write the control flow graph of a function as a DOT digraph named after the function
+    fn write_dot(&self, f: &mut dyn Write, fun: FunId) -> fmt::Result {
+        writeln!(f, "digraph \"{}\" {{", DotEscape(&self[fun].name))?;
+        writeln!(f, "    node [shape=box, fontname=monospace];")?;
+
+        let order = self.blocks_of(fun);
+        let labels = self.labels(&order);
+        for bb in order.iter() {
+            //Every line of a node's label is ended with \l to left-justify it
+            let mut label = String::new();
+            self.write_block_header(&mut label, *bb, &labels)?;
+            writeln!(label, ":")?;
+            for stmt in self[*bb].stmts.iter() {
+                write!(label, "    ")?;
+                self.write_stmt(&mut label, stmt, &labels)?;
+                writeln!(label)?;
+            }
+            write!(label, "    ")?;
+            self.write_terminator(&mut label, &self[*bb].terminator, &labels)?;
+            writeln!(label)?;
+
+            writeln!(
+                f,
+                "    bb{} [label=\"{}\"];",
+                labels.blocks[bb],
+                DotEscape(&label)
+            )?;
+        }
+
+        for bb in order.iter() {
+            let from = labels.blocks[bb];
+            match &self[*bb].terminator {
+                IrTerminator::Jmp(to) => {
+                    writeln!(f, "    bb{} -> bb{};", from, labels.blocks[&to.bb])?;
+                }
+                IrTerminator::JmpIf {
+                    if_true, if_false, ..
+                } => {
+                    for (branch, taken) in [(if_true, "true"), (if_false, "false")] {
+                        writeln!(
+                            f,
+                            "    bb{} -> bb{} [label=\"{}\"];",
+                            from, labels.blocks[&branch.bb], taken
+                        )?;
+                    }
+                }
+                IrTerminator::JmpMatch {
+                    variant,
+                    discriminants,
+                    default_jmp,
+                } => {
+                    for (discriminant, branch) in discriminants.iter() {
+                        let mut label = String::new();
+                        match discriminant {
+                            DiscriminantId::Type(ty) => write!(label, "{}", self.typename(*ty))?,
+                            DiscriminantId::Variant(idx) => {
+                                self.write_variant(&mut label, variant.ty, *idx)?
+                            }
+                        }
+                        writeln!(
+                            f,
+                            "    bb{} -> bb{} [label=\"{}\"];",
+                            from,
+                            labels.blocks[&branch.bb],
+                            DotEscape(&label)
+                        )?;
+                    }
+                    writeln!(
+                        f,
+                        "    bb{} -> bb{} [label=\"default\"];",
+                        from, labels.blocks[&default_jmp.bb]
+                    )?;
+                }
+                IrTerminator::Return(_) | IrTerminator::Unreachable | IrTerminator::Invalid => (),
+            }
+        }
+
+        writeln!(f, "}}")
+    }
+
+    /// Label the given blocks and their parameters in order
+    fn labels(&self, order: &[BBId]) -> Labels {
+        let mut labels = Labels {
+            blocks: HashMap::new(),
+            params: HashMap::new(),
+        };
+        let mut param_count = 0;
+        for (idx, bb) in order.iter().enumerate() {
+            labels.blocks.insert(*bb, idx);
+            labels.params.insert(*bb, param_count);
+            param_count += self[*bb].params.len();
+        }
+        labels
+    }
+
+    /// Write the label of a block followed by its parameters, if any
+    fn write_block_header(&self, f: &mut dyn Write, bb: BBId, labels: &Labels) -> fmt::Result {
+        write!(f, "bb{}", labels.blocks[&bb])?;
+        if self[bb].params.is_empty() {
+            return Ok(());
+        }
+        write!(f, "(")?;
+        for (idx, ty) in self[bb].params.iter().enumerate() {
+            if idx > 0 {
+                write!(f, ", ")?;
+            }
+            write!(f, "v{}: {}", labels.params[&bb] + idx, self.typename(*ty))?;
+        }
+        write!(f, ")")
+    }
+
+    /// Write a single statement without indentation or a trailing newline
+    fn write_stmt(&self, f: &mut dyn Write, stmt: &IrStmt, labels: &Labels) -> fmt::Result {
+        match &stmt.kind {
+            IrStmtKind::VarLive(var) => {
+                write!(f, "live ")?;
+                self.write_var(f, *var)?;
+                write!(f, ": {}", self.typename(self[*var].ty))
+            }
+            IrStmtKind::VarDead(var) => {
+                write!(f, "dead ")?;
+                self.write_var(f, *var)
+            }
+            IrStmtKind::Zero(var) => {
+                write!(f, "zero ")?;
+                self.write_var(f, *var)
+            }
+            IrStmtKind::Store { var, val } => {
+                self.write_var(f, *var)?;
+                write!(f, " = ")?;
+                self.write_expr(f, val, labels)
+            }
+            IrStmtKind::StoreMem { addr, val } => {
+                write!(f, "store ")?;
+                self.write_expr(f, addr, labels)?;
+                write!(f, ", ")?;
+                self.write_expr(f, val, labels)
+            }
+            IrStmtKind::Copy { dst, src, ty } => {
+                write!(f, "copy ")?;
+                self.write_expr(f, dst, labels)?;
+                write!(f, ", ")?;
+                self.write_expr(f, src, labels)?;
+                write!(f, ": {}", self.typename(*ty))
+            }
+            IrStmtKind::VolatileWrite { ptr, val } => {
+                write!(f, "volatile_write(")?;
+                self.write_expr(f, ptr, labels)?;
+                write!(f, ", ")?;
+                self.write_expr(f, val, labels)?;
+                write!(f, ")")
+            }
+            IrStmtKind::Assert { cond, msg } => {
+                write!(f, "assert(")?;
+                self.write_expr(f, cond, labels)?;
+                if let Some(msg) = msg {
+                    write!(f, ", {:?}", msg)?;
+                }
+                write!(f, ")")
+            }
+            IrStmtKind::Call { fun, args } => {
+                write!(f, "call {}(", self[*fun].name)?;
+                self.write_list(f, args, labels)?;
+                write!(f, ")")
+            }
+            IrStmtKind::Exec(expr) => self.write_expr(f, expr, labels),
+        }
+    }
+
+    /// Write a block terminator, referring to other blocks by their labels
+    fn write_terminator(
+        &self,
+        f: &mut dyn Write,
+        terminator: &IrTerminator,
+        labels: &Labels,
+    ) -> fmt::Result {
+        match terminator {
+            IrTerminator::Return(val) => {
+                write!(f, "return ")?;
+                self.write_expr(f, val, labels)
+            }
+            IrTerminator::Jmp(to) => {
+                write!(f, "jmp ")?;
+                self.write_branch(f, to, labels)
+            }
+            IrTerminator::JmpIf {
+                condition,
+                if_true,
+                if_false,
+            } => {
+                write!(f, "jmpif ")?;
+                self.write_expr(f, condition, labels)?;
+                write!(f, ", ")?;
+                self.write_branch(f, if_true, labels)?;
+                write!(f, ", ")?;
+                self.write_branch(f, if_false, labels)
+            }
+            IrTerminator::JmpMatch {
+                variant,
+                discriminants,
+                default_jmp,
+            } => {
+                write!(f, "jmpmatch ")?;
+                self.write_expr(f, variant, labels)?;
+                write!(f, " [")?;
+                for (idx, (discriminant, branch)) in discriminants.iter().enumerate() {
+                    if idx > 0 {
+                        write!(f, ", ")?;
+                    }
+                    match discriminant {
+                        DiscriminantId::Type(ty) => write!(f, "{} -> ", self.typename(*ty))?,
+                        DiscriminantId::Variant(idx) => {
+                            self.write_variant(f, variant.ty, *idx)?;
+                            write!(f, " -> ")?;
+                        }
+                    }
+                    self.write_branch(f, branch, labels)?;
+                }
+                write!(f, "], default ")?;
+                self.write_branch(f, default_jmp, labels)
+            }
+            IrTerminator::Unreachable => write!(f, "unreachable"),
+            IrTerminator::Invalid => write!(f, "invalid"),
+        }
+    }
+
+    /// Write the name of an enum's variant qualified by the enum's name
+    fn write_variant(&self, f: &mut dyn Write, ty: TypeId, idx: usize) -> fmt::Result {
+        match &self[self.unwrap_alias(ty)] {
+            IrType::Enum(e) if idx < e.variants.len() => {
+                write!(f, "{}:{}", e.name, e.variants[idx].name)
+            }
+            _ => write!(f, "{}:{}", self.typename(ty), idx),
+        }
+    }
+
+    /// Write the label of a jump's target block followed by the arguments passed to it, if any
+    fn write_branch(&self, f: &mut dyn Write, branch: &IrBranch, labels: &Labels) -> fmt::Result {
+        write!(f, "bb{}", labels.blocks[&branch.bb])?;
+        if branch.args.is_empty() {
+            return Ok(());
+        }
+        write!(f, "(")?;
+        self.write_list(f, &branch.args, labels)?;
+        write!(f, ")")
+    }
+
+    /// Write an expression, parenthesizing nested binary expressions and casts
+    fn write_expr(&self, f: &mut dyn Write, expr: &IrExpr, labels: &Labels) -> fmt::Result {
+        match &expr.kind {
+            IrExprKind::Var(var) => self.write_var(f, *var),
+            IrExprKind::Param(bb, idx) => match labels.params.get(bb) {
+                Some(first) => write!(f, "v{}", first + idx),
+                None => write!(f, "{}.v{}", bb, idx),
+            },
+            IrExprKind::Variant(idx, payload) => {
+                self.write_variant(f, expr.ty, *idx)?;
+                if let Some(payload) = payload {
+                    write!(f, "(")?;
+                    self.write_expr(f, payload, labels)?;
+                    write!(f, ")")?;
+                }
+                Ok(())
+            }
+            IrExprKind::Global(glob) => write!(f, "@{}", self[*glob].name),
+            IrExprKind::Lit(lit) => match lit {
+                IrLiteral::Integer(val, ity) if ity.signed => write!(f, "{}", val.val as i128),
+                IrLiteral::Integer(val, _) => write!(f, "{}", val.val),
+                IrLiteral::Float(val, _) => write!(f, "{:?}", val),
+                IrLiteral::Char(c) => write!(f, "{:?}", c),
+                IrLiteral::String(s) => write!(f, "{:?}", s),
+                IrLiteral::Bool(b) => write!(f, "{}", b),
+                IrLiteral::Array(elems) => {
+                    write!(f, "[")?;
+                    self.write_list(f, elems, labels)?;
+                    write!(f, "]")
+                }
+                IrLiteral::Struct(fields) => {
+                    write!(f, "{{")?;
+                    for (idx, (name, field)) in fields.iter().enumerate() {
+                        if idx > 0 {
+                            write!(f, ",")?;
+                        }
+                        write!(f, " {} = ", name)?;
+                        self.write_expr(f, field, labels)?;
+                    }
+                    write!(f, " }}")
+                }
+                IrLiteral::Tuple(elems) => {
+                    write!(f, "(")?;
+                    self.write_list(f, elems, labels)?;
+                    if elems.len() == 1 {
+                        write!(f, ",")?;
+                    }
+                    write!(f, ")")
+                }
+                IrLiteral::Unit => write!(f, "()"),
+            },
+            IrExprKind::Binary(lhs, op, rhs) => {
+                write!(f, "(")?;
+                self.write_expr(f, lhs, labels)?;
+                write!(f, " {} ", op)?;
+                self.write_expr(f, rhs, labels)?;
+                write!(f, ")")
+            }
+            IrExprKind::Unary(op, operand) => {
+                write!(f, "{}", op)?;
+                self.write_expr(f, operand, labels)
+            }
+            IrExprKind::Call(called, args) => {
+                self.write_expr(f, called, labels)?;
+                write!(f, "(")?;
+                self.write_list(f, args, labels)?;
+                write!(f, ")")
+            }
+            IrExprKind::Fun(fun) => write!(f, "{}", self[*fun].name),
+            IrExprKind::Member(obj, idx) => {
+                self.write_expr(f, obj, labels)?;
+                match &self[self.unwrap_alias(obj.ty)] {
+                    IrType::Struct(s_ty) => write!(f, ".{}", s_ty.fields[*idx].name),
+                    IrType::Enum(e) => write!(f, ".{}", e.variants[*idx].name),
+                    IrType::Slice(_) if *idx == 0 => write!(f, ".ptr"),
+                    IrType::Slice(_) => write!(f, ".len"),
+                    _ => write!(f, ".{}", idx),
+                }
+            }
+            IrExprKind::Cast(casted, ty, _) => {
+                write!(f, "(")?;
+                self.write_expr(f, casted, labels)?;
+                write!(f, " as {})", self.typename(*ty))
+            }
+            IrExprKind::Index(arr, idx) => {
+                self.write_expr(f, arr, labels)?;
+                write!(f, "[")?;
+                self.write_expr(f, idx, labels)?;
+                write!(f, "]")
+            }
+            IrExprKind::Slice(ptr, len) => {
+                write!(f, "slice(")?;
+                self.write_expr(f, ptr, labels)?;
+                write!(f, ", ")?;
+                self.write_expr(f, len, labels)?;
+                write!(f, ")")
+            }
+            IrExprKind::SizeOf(ty) => write!(f, "sizeof({})", self.typename(*ty)),
+            IrExprKind::AlignOf(ty) => write!(f, "alignof({})", self.typename(*ty)),
+            IrExprKind::VolatileRead(ptr) => {
+                write!(f, "volatile_read(")?;
+                self.write_expr(f, ptr, labels)?;
+                write!(f, ")")
+            }
+        }
+    }
+
+    /// Write a comma-separated list of expressions
+    fn write_list(&self, f: &mut dyn Write, exprs: &[IrExpr], labels: &Labels) -> fmt::Result {
+        for (idx, expr) in exprs.iter().enumerate() {
+            if idx > 0 {
+                write!(f, ", ")?;
+            }
+            self.write_expr(f, expr, labels)?;
+        }
+        Ok(())
+    }
+
+    /// Write a variable as its name followed by its index, distinguishing shadowed variables
+    fn write_var(&self, f: &mut dyn Write, var: VarId) -> fmt::Result {
+        write!(f, "%{}.{}", self[var].name, var.val())
+    }
+}
+
+/// Formats a string for use in a quoted DOT identifier, escaping quotes and backslashes and
+/// ending every line with a left-justified line break
+struct DotEscape<'a>(&'a str);
+
+impl fmt::Display for DotEscape<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for c in self.0.chars() {
+            match c {
+                '"' => write!(f, "\\\"")?,
+                '\\' => write!(f, "\\\\")?,
+                '\n' => write!(f, "\\l")?,
+                c => write!(f, "{}", c)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for IrContext {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.write_all(f, None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::ir::lower::testing::lower;
+
+    #[test]
+    fn dot_of_if_inside_loop() {
+        let lowered = lower(
+            "fun count(i32 n) -> i32 {
+    mut i = 0
+    loop {
+        if i == n { break }
+        i = i + 1
+    }
+    return i
+}
+
+fun main() -> i32 {
+    return count(3)
+}",
+        );
+        let dot = lowered.ctx.dot_of(lowered.fun("count"));
+        assert_eq!(
+            dot,
+            r#"digraph "count" {
+    node [shape=box, fontname=monospace];
+    bb0 [label="bb0:\l    live %@return_var#count.0: i32\l    live %i.2: i32\l    %i.2 = (0 as i32)\l    live %@phi_var#2.3: ()\l    jmp bb1\l"];
+    bb1 [label="bb1:\l    jmpif (%i.2 == %n.1), bb2, bb4\l"];
+    bb2 [label="bb2:\l    jmp bb3\l"];
+    bb3 [label="bb3:\l    return %i.2\l"];
+    bb4 [label="bb4:\l    %i.2 = (%i.2 + (1 as i32))\l    jmp bb1\l"];
+    bb0 -> bb1;
+    bb1 -> bb2 [label="true"];
+    bb1 -> bb4 [label="false"];
+    bb2 -> bb3;
+    bb4 -> bb1;
+}
+"#
+        );
+    }
+
+    #[test]
+    fn block_parameters_are_printed_with_their_types() {
+        let lowered = lower(
+            "fun pick(i32 c) -> i32 {
+    let x = if c == 0 { phi 1 } else { phi 2 }
+    return x
+}",
+        );
+        assert_eq!(
+            lowered.ctx.print_fun(lowered.fun("pick")),
+            "fun pick(%c.1: i32) -> i32 @_S4pickEi {
+bb0:
+    live %@return_var#pick.0: i32
+    jmpif (%c.1 == (0 as i32)), bb1, bb3
+bb1:
+    jmp bb2((1 as i32))
+bb2(v0: i32):
+    live %x.2: i32
+    %x.2 = v0
+    return %x.2
+bb3:
+    jmp bb2(2)
+}
+"
+        );
+    }
+
+    #[test]
+    fn stores_through_pointers_and_copies_are_printed() {
+        let lowered = lower(
+            "type Pair = { i32 a, i32 b }
+
+fun set(*i32 p, *Pair dst, Pair src) {
+    let *p = 5
+    let *dst = src
+}",
+        );
+        assert_eq!(
+            lowered.ctx.print_fun(lowered.fun("set")),
+            "fun set(%p.0: *i32, %dst.1: *Pair, %src.2: Pair) -> () @_S3setEPiPN4PairN4Pair {
+bb0:
+    store %p.0, 5
+    copy %dst.1, &%src.2: Pair
+    return ()
+}
+"
+        );
+    }
+
+    #[test]
+    fn unreachable_match_default_is_printed() {
+        let lowered = lower(
+            "type Num = i32 | i64
+
+fun widen(Num n) -> i64 {
+    return match n {
+        i32 a -> phi $i64 a,
+        i64 b -> phi b
+    }
+}",
+        );
+        assert_eq!(
+            lowered.ctx.print_fun(lowered.fun("widen")),
+            "fun widen(%n.1: Num) -> i64 @_S5widenEN3Num {
+bb0:
+    live %@return_var#widen.0: i64
+    jmpmatch %n.1 [i32 -> bb1, i64 -> bb3], default bb4
+bb1:
+    live %a.2: i32
+    %a.2 = (%n.1 as i32)
+    jmp bb2((%a.2 as i64))
+bb2(v0: i64):
+    return v0
+bb3:
+    live %b.3: i64
+    %b.3 = (%n.1 as i64)
+    jmp bb2(%b.3)
+bb4:
+    unreachable
+}
+"
+        );
+    }
+}
@@ -0,0 +1,79 @@
+//! Helpers for tests that build the control flow graph of a function by hand
+
+use crate::{
+    ast::FunFlags,
+    util::{
+        files::{CompiledFile, Files},
+        loc::Span,
+    },
+    Symbol,
+};
+
+use super::{
+    types::{FunType, IrType},
+    value::{IrExpr, IrExprKind, IrLiteral},
+    BBId, DiscriminantId, FunId, IrBody, IrBranch, IrContext, IrFun, IrTerminator,
+};
+
+/// Create a function whose body has one block for every list of successor indices, the first
+/// being the entry block. Blocks without successors return, blocks with one jump to it, blocks
+/// with two jump conditionally, and blocks with more match on a placeholder value with the last
+/// successor as the default jump
+///
+/// Only the shape of the graph is meaningful, the IR is not expected to pass validation
+pub fn build_cfg(succs: &[&[usize]]) -> (IrContext, FunId, Vec<BBId>) {
+    let mut ctx = IrContext::new();
+    let blocks = succs.iter().map(|_| ctx.new_bb()).collect::<Vec<_>>();
+    let expr = |ty, lit| IrExpr {
+        span: Span::from(0..0),
+        ty,
+        kind: IrExprKind::Lit(lit),
+    };
+
+    for (bb, succs) in blocks.iter().zip(succs) {
+        let mut targets = succs.iter().map(|idx| IrBranch::from(blocks[*idx]));
+        ctx[*bb].terminator = match succs.len() {
+            0 => IrTerminator::Return(expr(IrContext::UNIT, IrLiteral::Unit)),
+            1 => IrTerminator::Jmp(targets.next().unwrap()),
+            2 => IrTerminator::JmpIf {
+                condition: expr(IrContext::BOOL, IrLiteral::Bool(true)),
+                if_true: targets.next().unwrap(),
+                if_false: targets.next().unwrap(),
+            },
+            _ => {
+                let default_jmp = targets.next_back().unwrap();
+                IrTerminator::JmpMatch {
+                    variant: expr(IrContext::UNIT, IrLiteral::Unit),
+                    discriminants: targets
+                        .enumerate()
+                        .map(|(idx, branch)| (DiscriminantId::Variant(idx), branch))
+                        .collect(),
+                    default_jmp,
+                }
+            }
+        };
+    }
+
+    let ty = FunType {
+        return_ty: IrContext::UNIT,
+        params: vec![],
+    };
+    let fun = ctx.funs.insert(IrFun {
+        name: Symbol::from("cfg"),
+        symbol: Symbol::from("cfg"),
+        ty_id: ctx.types.insert(IrType::Fun(ty.clone())),
+        ty,
+        file: Files::new().add(CompiledFile::in_memory(String::new())),
+        span: Span::from(0..0),
+        body: None,
+        flags: FunFlags::empty(),
+    });
+    ctx[fun].body = Some(IrBody {
+        entry: blocks[0],
+        parent: fun,
+        args: vec![],
+        blocks: blocks.clone(),
+    });
+
+    (ctx, fun, blocks)
+}
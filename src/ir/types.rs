@@ -1,9 +1,13 @@
+use std::convert::TryFrom;
+
+use serde::Serialize;
+
 use crate::{ast::IntegerWidth, Symbol};
 
 use super::TypeId;
 
 /// The signature of a function with argument and return types
-#[derive(Clone, Hash, PartialEq, Eq, Debug)]
+#[derive(Clone, Hash, PartialEq, Eq, Debug, Serialize)]
 pub struct FunType {
     /// Type that the IR function must return
     pub return_ty: TypeId,
@@ -12,33 +16,56 @@ pub struct FunType {
 }
 
 /// Structure representing signed and unsigned integer types of varying bit width
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize)]
 pub struct IrIntegerType {
     pub width: IntegerWidth,
     pub signed: bool,
 }
 
 /// Structure representing either a 32 bit or 64 bit float type
-#[derive(Clone, Copy, PartialEq, Eq, Debug, Hash)]
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Hash, Serialize)]
 pub struct IrFloatType {
     pub doublewide: bool,
 }
 
 /// Structure contained in an [IrStructType] representing a single field of s structure type
-#[derive(Clone, Copy, PartialEq, Eq, Debug, Hash)]
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Hash, Serialize)]
 pub struct IrStructField {
     pub ty: TypeId,
     pub name: Symbol,
+    /// Explicit alignment of the field in bytes, padding is inserted before the field to reach it
+    pub align: Option<u32>,
 }
 
 /// Structure representing an anonymous structure type with fields
-#[derive(Clone, PartialEq, Eq, Debug, Hash)]
+#[derive(Clone, PartialEq, Eq, Debug, Hash, Serialize)]
 pub struct IrStructType {
     pub fields: Vec<IrStructField>,
+    /// If the structure is laid out with no padding between fields
+    pub packed: bool,
+}
+
+/// A single named variant of an [IrEnumType]
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Hash, Serialize)]
+pub struct IrEnumVariant {
+    pub name: Symbol,
+    /// Type of the value carried by the variant, if any
+    pub payload: Option<TypeId>,
+    /// Value of the enum's tag when it holds this variant
+    pub discriminant: i64,
+}
+
+/// Structure representing a declared enum type with named variants
+#[derive(Clone, PartialEq, Eq, Debug, Hash, Serialize)]
+pub struct IrEnumType {
+    /// Name the enum was declared with
+    pub name: Symbol,
+    /// Variants in the order they were declared
+    pub variants: Vec<IrEnumVariant>,
 }
 
 /// Data for an [IRType] that contains the actual type data
-#[derive(Clone, Hash, PartialEq, Eq, Debug)]
+#[derive(Clone, Hash, PartialEq, Eq, Debug, Serialize)]
 pub enum IrType {
     /// An integer type with width and signedness
     Integer(IrIntegerType),
@@ -48,8 +75,13 @@ pub enum IrType {
     Char,
     /// Unnamed structure type with fields
     Struct(IrStructType),
+    /// Declared enum type with named variants and explicit discriminants
+    Enum(IrEnumType),
     /// Sum type that can be many different types
     Sum(Vec<TypeId>),
+    /// Anonymous product of element types, laid out like a structure with unnamed fields. Tuples
+    /// always have at least one element, the empty tuple `()` is the [Unit](IrType::Unit) type
+    Tuple(Vec<TypeId>),
     /// Boolean true or false type
     Bool,
     /// Unit type with a single value
@@ -61,16 +93,33 @@ pub enum IrType {
         /// Aliased type
         ty: TypeId,
     },
-    /// Array with compile-time known length and element type
+    /// Array with compile-time known length and element type, zero-length arrays are allowed and
+    /// take up no space, making them usable as trailing flexible members of a structure
     Array(TypeId, u64),
     /// Pointer to a type
     Ptr(TypeId),
+    /// Pointer to the first of a run of elements of a type, paired with the number of elements
+    /// as a `usz`. Laid out as a structure of the pointer followed by the length
+    Slice(TypeId),
     /// Function type
     Fun(FunType),
+    /// Declared type with an unknown layout that can only be used behind a pointer
+    Opaque(Symbol),
     /// Never used except by the IR lowerer
     Invalid,
 }
 
+impl FunType {
+    /// Get the signature without parameter names, which are not part of the type of a function
+    /// value, so that functions can be stored in function pointers with the same signature
+    pub fn unnamed(&self) -> Self {
+        Self {
+            return_ty: self.return_ty,
+            params: self.params.iter().map(|(ty, _)| (*ty, None)).collect(),
+        }
+    }
+}
+
 impl IrStructType {
     /// Get the field of this structure type by the given name
     pub fn field_ty(&self, name: &Symbol) -> Option<TypeId> {
@@ -83,6 +132,11 @@ impl IrStructType {
         })
     }
 
+    /// Get the field of this structure type with the given name
+    pub fn field(&self, name: &Symbol) -> Option<&IrStructField> {
+        self.fields.iter().find(|field| field.name == *name)
+    }
+
     /// Get the index of the given field name
     pub fn field_idx(&self, name: &Symbol) -> Option<usize> {
         self.fields
@@ -91,3 +145,37 @@ impl IrStructType {
             .find_map(|(idx, field)| if field.name == *name { Some(idx) } else { None })
     }
 }
+
+impl IrEnumType {
+    /// Get the index of the variant with the given name
+    pub fn variant_idx(&self, name: &Symbol) -> Option<usize> {
+        self.variants
+            .iter()
+            .position(|variant| variant.name == *name)
+    }
+
+    /// Check if any variant of this enum carries a value, in which case the enum is laid out as
+    /// its tag followed by a payload instead of as a bare integer
+    pub fn has_payload(&self) -> bool {
+        self.variants
+            .iter()
+            .any(|variant| variant.payload.is_some())
+    }
+
+    /// Get the integer type of this enum's tag: an `i32` like a C enum if every discriminant
+    /// fits, and an `i64` otherwise
+    pub fn tag_type(&self) -> IrIntegerType {
+        let fits = self
+            .variants
+            .iter()
+            .all(|variant| i32::try_from(variant.discriminant).is_ok());
+        IrIntegerType {
+            width: if fits {
+                IntegerWidth::ThirtyTwo
+            } else {
+                IntegerWidth::SixtyFour
+            },
+            signed: true,
+        }
+    }
+}
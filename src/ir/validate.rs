@@ -0,0 +1,569 @@
+//! Checks of structural invariants that all lowered IR must uphold, catching bugs in the lowerer
+//! before they surface as panics in a code generator
+
+use codespan_reporting::diagnostic::{Diagnostic, Label};
+use hashbrown::{HashMap, HashSet};
+
+use crate::util::{files::FileId, loc::Span};
+
+use super::{
+    dom::DomTree,
+    opt::block_reads,
+    types::IrType,
+    value::{IrExpr, IrExprKind},
+    BBId, DiscriminantId, FunId, IrBody, IrContext, IrStmtKind, IrTerminator, TypeId, VarId,
+};
+
+/// A broken invariant found in the body of a function
+#[derive(Clone, Debug)]
+pub struct IrError {
+    /// Function containing the invalid IR
+    pub fun: FunId,
+    /// Location of the offending statement or expression, if known
+    pub span: Option<Span>,
+    /// Description of the broken invariant
+    pub msg: String,
+}
+
+impl IrError {
+    /// Create an internal compiler error diagnostic pointing at the invalid IR
+    pub fn to_diagnostic(&self, ctx: &IrContext) -> Diagnostic<FileId> {
+        let fun = &ctx[self.fun];
+        Diagnostic::bug()
+            .with_message(format!("Invalid IR in function {}: {}", fun.name, self.msg))
+            .with_labels(vec![Label::primary(
+                fun.file,
+                self.span.unwrap_or(fun.span),
+            )])
+    }
+}
+
+impl IrContext {
+    /// Check every function body for broken structural invariants, returning all errors found
+    pub fn validate(&self) -> Vec<IrError> {
+        let mut errors = vec![];
+        for fun in self.funs.indices() {
+            if let Some(body) = self.fun_body(fun) {
+                self.validate_fun(fun, body, &mut errors);
+            }
+        }
+        errors
+    }
+
+    /// Validate all blocks reachable from the entry block of a function's body
+    fn validate_fun(&self, fun: FunId, body: &IrBody, errors: &mut Vec<IrError>) {
+        let bb_count = self.bbs.iter().len();
+        let err = |span: Option<Span>, msg: String| IrError { fun, span, msg };
+        if body.entry.val() >= bb_count {
+            errors.push(err(
+                None,
+                format!("Entry block {} does not exist", body.entry),
+            ));
+            return;
+        }
+        if !self[body.entry].params.is_empty() {
+            errors.push(err(
+                None,
+                format!("Entry block {} has parameters", body.entry),
+            ));
+        }
+
+        //Set of variables that are definitely live at the start of each block, parameters are
+        //live from the start of the function. A variable is live after its VarLive statement
+        //until its VarDead statement, and must be live on every path to a statement using it
+        let mut live_at = HashMap::<BBId, HashSet<VarId>>::new();
+        live_at.insert(body.entry, body.args.iter().flatten().copied().collect());
+        let mut worklist = vec![body.entry];
+
+        while let Some(bb) = worklist.pop() {
+            let mut live = live_at[&bb].clone();
+            self.live_transfer(bb, &mut live, &mut |_, _| ());
+
+            for succ in self[bb].terminator.successors() {
+                if succ.val() >= bb_count {
+                    continue;
+                }
+                let changed = match live_at.get_mut(&succ) {
+                    Some(succ_live) => {
+                        let before = succ_live.len();
+                        succ_live.retain(|var| live.contains(var));
+                        before != succ_live.len()
+                    }
+                    None => {
+                        live_at.insert(succ, live.clone());
+                        true
+                    }
+                };
+
+                if changed {
+                    worklist.push(succ);
+                }
+            }
+        }
+
+        let mut reached = live_at.keys().copied().collect::<Vec<_>>();
+        reached.sort_by_key(|bb| bb.val());
+
+        //Dominators can only be computed once every jump is known to have a valid target
+        let dom = reached
+            .iter()
+            .all(|bb| {
+                self[*bb]
+                    .terminator
+                    .successors()
+                    .all(|succ| succ.val() < bb_count)
+            })
+            .then(|| DomTree::new(self, fun));
+
+        let return_ty = self.unwrap_alias(self[fun].ty.return_ty);
+        for bb in reached {
+            let mut live = live_at[&bb].clone();
+            self.live_transfer(bb, &mut live, &mut |var, span| {
+                errors.push(err(
+                    Some(span),
+                    format!(
+                        "Variable {} is used outside of its lifetime in block {}",
+                        self[var].name, bb
+                    ),
+                ))
+            });
+
+            for stmt in self[bb].stmts.iter() {
+                match &stmt.kind {
+                    IrStmtKind::Store { var, val } if !self.types_agree(self[*var].ty, val.ty) => {
+                        errors.push(err(
+                            Some(stmt.span),
+                            format!(
+                                "Value of type {} stored in variable {} of type {}",
+                                self.typename(val.ty),
+                                self[*var].name,
+                                self.typename(self[*var].ty)
+                            ),
+                        ))
+                    }
+                    IrStmtKind::StoreMem { addr, val } => {
+                        if !self.points_to(addr.ty, val.ty) {
+                            errors.push(err(
+                                Some(stmt.span),
+                                format!(
+                                    "Value of type {} stored through address of type {}",
+                                    self.typename(val.ty),
+                                    self.typename(addr.ty)
+                                ),
+                            ))
+                        }
+                    }
+                    IrStmtKind::Copy { dst, src, ty } => {
+                        for addr in [dst, src] {
+                            if !self.points_to(addr.ty, *ty) {
+                                errors.push(err(
+                                    Some(addr.span),
+                                    format!(
+                                        "Value of type {} copied through address of type {}",
+                                        self.typename(*ty),
+                                        self.typename(addr.ty)
+                                    ),
+                                ))
+                            }
+                        }
+                    }
+                    _ => (),
+                }
+            }
+
+            match &self[bb].terminator {
+                IrTerminator::Return(val) => {
+                    if !self.types_agree(return_ty, val.ty) {
+                        errors.push(err(
+                            Some(val.span),
+                            format!(
+                                "Returned value of type {} from a function returning {}",
+                                self.typename(val.ty),
+                                self.typename(return_ty)
+                            ),
+                        ));
+                    }
+                }
+                IrTerminator::JmpIf { condition, .. } => {
+                    if self.unwrap_alias(condition.ty) != IrContext::BOOL {
+                        errors.push(err(
+                            Some(condition.span),
+                            format!(
+                                "Condition of type {} is not a boolean",
+                                self.typename(condition.ty)
+                            ),
+                        ));
+                    }
+                }
+                IrTerminator::JmpMatch {
+                    variant,
+                    discriminants,
+                    ..
+                } => {
+                    let matched = &self[self.unwrap_alias(variant.ty)];
+                    if !matches!(matched, IrType::Sum(_) | IrType::Enum(_)) {
+                        errors.push(err(
+                            Some(variant.span),
+                            format!(
+                                "Matched value of type {} is not a sum or enum type",
+                                self.typename(variant.ty)
+                            ),
+                        ));
+                    }
+
+                    for (discriminant, _) in discriminants {
+                        let msg = match (matched, discriminant) {
+                            (IrType::Sum(variants), DiscriminantId::Type(ty))
+                                if !variants.contains(ty) =>
+                            {
+                                format!(
+                                    "Matched type {} is not a variant of {}",
+                                    self.typename(*ty),
+                                    self.typename(variant.ty)
+                                )
+                            }
+                            (IrType::Enum(e), DiscriminantId::Variant(idx))
+                                if *idx >= e.variants.len() =>
+                            {
+                                format!(
+                                    "Matched variant {} does not exist in enum {}",
+                                    idx,
+                                    self.typename(variant.ty)
+                                )
+                            }
+                            (IrType::Sum(_), DiscriminantId::Variant(_))
+                            | (IrType::Enum(_), DiscriminantId::Type(_)) => format!(
+                                "Discriminant {:?} can't be matched against a value of type {}",
+                                discriminant,
+                                self.typename(variant.ty)
+                            ),
+                            _ => continue,
+                        };
+                        errors.push(err(Some(variant.span), msg));
+                    }
+                }
+                IrTerminator::Invalid => errors.push(err(
+                    self[bb].terminator_span,
+                    format!("Block {} is not terminated", bb),
+                )),
+                IrTerminator::Jmp(_) | IrTerminator::Unreachable => (),
+            }
+
+            for branch in self[bb].terminator.branches() {
+                if branch.bb.val() >= bb_count {
+                    errors.push(err(
+                        self[bb].terminator_span,
+                        format!("Block {} jumps to nonexistent block {}", bb, branch.bb),
+                    ));
+                    continue;
+                }
+
+                let params = &self[branch.bb].params;
+                if branch.args.len() != params.len() {
+                    errors.push(err(
+                        self[bb].terminator_span,
+                        format!(
+                            "Block {} passes {} arguments to block {} with {} parameters",
+                            bb,
+                            branch.args.len(),
+                            branch.bb,
+                            params.len()
+                        ),
+                    ));
+                    continue;
+                }
+                for (arg, param) in branch.args.iter().zip(params.iter()) {
+                    if !self.types_agree(*param, arg.ty) {
+                        errors.push(err(
+                            Some(arg.span),
+                            format!(
+                                "Value of type {} passed to parameter of type {} of block {}",
+                                self.typename(arg.ty),
+                                self.typename(*param),
+                                branch.bb
+                            ),
+                        ));
+                    }
+                }
+            }
+
+            if let Some(dom) = dom.as_ref() {
+                block_reads(&self[bb], &mut |expr| {
+                    expr.walk(&mut |expr| {
+                        if let IrExprKind::Param(param_bb, idx) = &expr.kind {
+                            self.check_param(
+                                bb,
+                                *param_bb,
+                                *idx,
+                                expr.span,
+                                dom,
+                                &mut |span, msg| errors.push(err(Some(span), msg)),
+                            )
+                        }
+                    })
+                });
+            }
+        }
+    }
+
+    /// Check that a block parameter used in `bb` exists and that its block dominates `bb`, so
+    /// that the parameter has been assigned on every path to the use
+    fn check_param(
+        &self,
+        bb: BBId,
+        param_bb: BBId,
+        idx: usize,
+        span: Span,
+        dom: &DomTree,
+        on_err: &mut dyn FnMut(Span, String),
+    ) {
+        if param_bb.val() >= self.bbs.iter().len() || idx >= self[param_bb].params.len() {
+            on_err(
+                span,
+                format!(
+                    "Block {} uses nonexistent parameter {} of block {}",
+                    bb, idx, param_bb
+                ),
+            );
+        } else if !dom.dominates(param_bb, bb) {
+            on_err(
+                span,
+                format!(
+                    "Block {} uses parameter {} of block {}, which does not dominate it",
+                    bb, idx, param_bb
+                ),
+            );
+        }
+    }
+
+    /// Check if a value of type `from` may be stored where a value of type `to` is expected.
+    /// Placeholder invalid types never agree with any type, as they must not survive lowering
+    fn types_agree(&self, to: TypeId, from: TypeId) -> bool {
+        let (to, from) = (self.unwrap_alias(to), self.unwrap_alias(from));
+        to == from && to != IrContext::INVALID
+    }
+
+    /// Check if an address of type `addr` may point to a value of type `to`
+    fn points_to(&self, addr: TypeId, to: TypeId) -> bool {
+        match &self[self.unwrap_alias(addr)] {
+            IrType::Ptr(pointee) => self.types_agree(*pointee, to),
+            _ => false,
+        }
+    }
+
+    /// Apply the effects of all statements in a basic block to the set of live variables,
+    /// calling `on_dead` for every use of a variable that is not live
+    fn live_transfer(
+        &self,
+        bb: BBId,
+        live: &mut HashSet<VarId>,
+        on_dead: &mut dyn FnMut(VarId, Span),
+    ) {
+        for stmt in self[bb].stmts.iter() {
+            match &stmt.kind {
+                IrStmtKind::VarLive(var) => {
+                    live.insert(*var);
+                }
+                IrStmtKind::VarDead(var) => {
+                    if !live.remove(var) {
+                        on_dead(*var, stmt.span);
+                    }
+                }
+                IrStmtKind::Zero(var) => {
+                    if !live.contains(var) {
+                        on_dead(*var, stmt.span);
+                    }
+                }
+                IrStmtKind::Store { var, val } => {
+                    Self::dead_uses(val, live, on_dead);
+                    if !live.contains(var) {
+                        on_dead(*var, stmt.span);
+                    }
+                }
+                IrStmtKind::StoreMem { addr: ptr, val }
+                | IrStmtKind::VolatileWrite { ptr, val } => {
+                    Self::dead_uses(ptr, live, on_dead);
+                    Self::dead_uses(val, live, on_dead);
+                }
+                IrStmtKind::Copy { dst, src, .. } => {
+                    Self::dead_uses(dst, live, on_dead);
+                    Self::dead_uses(src, live, on_dead);
+                }
+                IrStmtKind::Assert { cond, .. } => Self::dead_uses(cond, live, on_dead),
+                IrStmtKind::Call { args, .. } => {
+                    for arg in args {
+                        Self::dead_uses(arg, live, on_dead);
+                    }
+                }
+                IrStmtKind::Exec(expr) => Self::dead_uses(expr, live, on_dead),
+            }
+        }
+
+        match &self[bb].terminator {
+            IrTerminator::Return(expr) => Self::dead_uses(expr, live, on_dead),
+            IrTerminator::JmpIf { condition, .. } => Self::dead_uses(condition, live, on_dead),
+            IrTerminator::JmpMatch { variant, .. } => Self::dead_uses(variant, live, on_dead),
+            IrTerminator::Jmp(_) | IrTerminator::Unreachable | IrTerminator::Invalid => (),
+        }
+        for branch in self[bb].terminator.branches() {
+            for arg in branch.args.iter() {
+                Self::dead_uses(arg, live, on_dead);
+            }
+        }
+    }
+
+    /// Call `on_dead` for every variable referenced in an expression that is not live
+    fn dead_uses(expr: &IrExpr, live: &HashSet<VarId>, on_dead: &mut dyn FnMut(VarId, Span)) {
+        Self::var_uses(expr, &mut |var, span| {
+            if !live.contains(&var) {
+                on_dead(var, span)
+            }
+        })
+    }
+
+    /// Visit every variable referenced in an expression
+    fn var_uses(expr: &IrExpr, visit: &mut dyn FnMut(VarId, Span)) {
+        expr.walk(&mut |expr| {
+            if let IrExprKind::Var(var) = &expr.kind {
+                visit(*var, expr.span)
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::ir::{
+        lower::testing::{lower, Lowered},
+        value::{IrExpr, IrExprKind},
+        BBId, IrContext, IrTerminator,
+    };
+
+    /// Lower a function choosing a value with an if expression, returning the lowered module and
+    /// the entry block, the two branches, and the join block taking the chosen value
+    fn pick() -> (Lowered, [BBId; 4]) {
+        let lowered = lower(
+            "fun pick(i32 c) -> i32 {
+    let x = if c == 0 { phi 1 } else { phi 2 }
+    return x
+}",
+        );
+        let ctx = &lowered.ctx;
+        let entry = ctx.fun_body(lowered.fun("pick")).unwrap().entry;
+        let (if_true, if_false) = match &ctx[entry].terminator {
+            IrTerminator::JmpIf {
+                if_true, if_false, ..
+            } => (if_true.bb, if_false.bb),
+            other => panic!("entry block ends with {:?}", other),
+        };
+        let join = match &ctx[if_true].terminator {
+            IrTerminator::Jmp(to) => to.bb,
+            other => panic!("branch ends with {:?}", other),
+        };
+        (lowered, [entry, if_true, if_false, join])
+    }
+
+    /// Get the messages of every error found by validating the IR
+    fn errors(ctx: &IrContext) -> Vec<String> {
+        ctx.validate().into_iter().map(|e| e.msg).collect()
+    }
+
+    /// Get the expression passed as the only argument when a block jumps to the join block
+    fn join_arg(ctx: &mut IrContext, bb: BBId) -> &mut IrExpr {
+        match &mut ctx[bb].terminator {
+            IrTerminator::Jmp(to) => &mut to.args[0],
+            other => panic!("block ends with {:?}", other),
+        }
+    }
+
+    #[test]
+    fn lowered_ir_is_valid() {
+        let (lowered, _) = pick();
+        assert_eq!(errors(&lowered.ctx), Vec::<String>::new());
+    }
+
+    #[test]
+    fn use_of_undefined_block_parameter_is_reported() {
+        let (mut lowered, [_, if_true, if_false, join]) = pick();
+        let ctx = &mut lowered.ctx;
+        join_arg(ctx, if_true).kind = IrExprKind::Param(if_true, 0);
+        join_arg(ctx, if_false).kind = IrExprKind::Param(join, 0);
+        assert_eq!(
+            errors(ctx),
+            [
+                format!(
+                    "Block {} uses nonexistent parameter 0 of block {}",
+                    if_true, if_true
+                ),
+                format!(
+                    "Block {} uses parameter 0 of block {}, which does not dominate it",
+                    if_false, join
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn wrong_branch_argument_count_is_reported() {
+        let (mut lowered, [_, if_true, _, join]) = pick();
+        let ctx = &mut lowered.ctx;
+        match &mut ctx[if_true].terminator {
+            IrTerminator::Jmp(to) => to.args.clear(),
+            other => panic!("block ends with {:?}", other),
+        }
+        assert_eq!(
+            errors(ctx),
+            [format!(
+                "Block {} passes 0 arguments to block {} with 1 parameters",
+                if_true, join
+            )]
+        );
+    }
+
+    #[test]
+    fn missing_terminator_is_reported() {
+        let (mut lowered, [_, _, if_false, _]) = pick();
+        let ctx = &mut lowered.ctx;
+        ctx[if_false].terminator = IrTerminator::Invalid;
+        assert_eq!(
+            errors(ctx),
+            [format!("Block {} is not terminated", if_false)]
+        );
+    }
+
+    #[test]
+    fn type_mismatches_are_reported() {
+        let (mut lowered, [_, if_true, _, join]) = pick();
+        let ctx = &mut lowered.ctx;
+        join_arg(ctx, if_true).ty = IrContext::I64;
+        assert_eq!(
+            errors(ctx),
+            [format!(
+                "Value of type i64 passed to parameter of type i32 of block {}",
+                join
+            )]
+        );
+    }
+
+    #[test]
+    fn invalid_operand_types_are_reported() {
+        let (mut lowered, [_, if_true, if_false, join]) = pick();
+        let ctx = &mut lowered.ctx;
+        join_arg(ctx, if_true).ty = IrContext::INVALID;
+        ctx[join].params[0] = IrContext::INVALID;
+        join_arg(ctx, if_false).ty = IrContext::INVALID;
+        assert_eq!(
+            errors(ctx),
+            [
+                format!(
+                    "Value of type INVALID passed to parameter of type INVALID of block {}",
+                    join
+                ),
+                format!(
+                    "Value of type INVALID passed to parameter of type INVALID of block {}",
+                    join
+                ),
+            ]
+        );
+    }
+}
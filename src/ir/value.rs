@@ -1,12 +1,14 @@
+use serde::Serialize;
+
 use crate::{ast::BigInt, parse::token::Op, util::loc::Span, Symbol};
 
 use super::{
     types::{IrFloatType, IrIntegerType},
-    FunId, GlobalId, TypeId, VarId,
+    BBId, FunId, GlobalId, TypeId, VarId,
 };
 
 /// Structure containing an [IrExprKind] plus location data for error messages
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, Serialize)]
 pub struct IrExpr {
     /// Location in the source file of this expression
     pub span: Span,
@@ -17,7 +19,7 @@ pub struct IrExpr {
 }
 
 /// Literal in the IR containing any user-created literal value
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, Serialize)]
 pub enum IrLiteral {
     Integer(BigInt, IrIntegerType),
     Float(f64, IrFloatType),
@@ -26,11 +28,45 @@ pub enum IrLiteral {
     Bool(bool),
     Array(Vec<IrExpr>),
     Struct(Vec<(Symbol, IrExpr)>),
+    /// Tuple literal with a value for every element in order
+    Tuple(Vec<IrExpr>),
     Unit,
 }
 
+/// Conversion performed by a cast expression, determined from the casted value's type and the
+/// casted-to type when the cast is lowered
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+pub enum CastKind {
+    /// Both types are the same after unwrapping aliases, the value is unchanged
+    Identity,
+    /// Widening or truncating an integer, or converting between integers and characters
+    IntResize,
+    /// Converting an integer to the nearest floating point value
+    IntToFloat,
+    /// Truncating a floating point value towards zero to an integer
+    FloatToInt,
+    /// Converting between single and double precision floating point values
+    FloatResize,
+    /// Converting an integer to a pointer with the integer as its address
+    IntToPtr,
+    /// Converting a pointer to its address
+    PtrToInt,
+    /// Reinterpreting a pointer or function pointer as another pointer or function pointer
+    PtrToPtr,
+    /// Converting a boolean to an integer that is 1 if true and 0 if false
+    BoolToInt,
+    /// Converting an integer to a boolean that is true if the integer is not 0
+    IntToBool,
+    /// Getting the discriminant of an enum's variant
+    EnumToInt,
+    /// Getting the value of one variant of a sum type
+    SumUnwrap,
+    /// Creating a sum type holding one of its variants
+    SumWrap,
+}
+
 /// Enumeration containing all expressions that produce a value in the intermediate representation
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, Serialize)]
 pub enum IrExprKind {
     /// Variable access with the ID of the variable
     Var(VarId),
@@ -46,10 +82,112 @@ pub enum IrExprKind {
     Call(Box<IrExpr>, Vec<IrExpr>),
     /// Taking the address of a function
     Fun(FunId),
-    /// Accessing a field of an expression
+    /// Accessing a field of an expression or an element of a tuple, or the payload of a variant
+    /// by index for values of enum type
     Member(Box<IrExpr>, usize),
-    /// Casting an expression to another type
-    Cast(Box<IrExpr>, TypeId),
-    /// Indexing an array type with integer-valued index
+    /// Casting an expression to another type with the given conversion
+    Cast(Box<IrExpr>, TypeId, CastKind),
+    /// Creating a slice from a pointer to its first element and its length
+    Slice(Box<IrExpr>, Box<IrExpr>),
+    /// Indexing an array or slice type with integer-valued index
     Index(Box<IrExpr>, Box<IrExpr>),
+    /// Size in bytes of a type on the compilation target
+    SizeOf(TypeId),
+    /// Alignment in bytes of a type on the compilation target
+    AlignOf(TypeId),
+    /// Volatile load through a pointer-valued expression
+    VolatileRead(Box<IrExpr>),
+    /// Value of a block's parameter by index
+    Param(BBId, usize),
+    /// Value of the expression's enum type holding the variant with the given index and its
+    /// payload
+    Variant(usize, Option<Box<IrExpr>>),
+}
+
+impl IrExpr {
+    /// Visit this expression and every expression nested in it, visiting expressions before
+    /// their operands
+    pub fn walk(&self, visit: &mut dyn FnMut(&IrExpr)) {
+        visit(self);
+        match &self.kind {
+            IrExprKind::Unary(_, operand)
+            | IrExprKind::VolatileRead(operand)
+            | IrExprKind::Member(operand, _)
+            | IrExprKind::Variant(_, Some(operand))
+            | IrExprKind::Cast(operand, ..) => operand.walk(visit),
+            IrExprKind::Binary(lhs, _, rhs)
+            | IrExprKind::Index(lhs, rhs)
+            | IrExprKind::Slice(lhs, rhs) => {
+                lhs.walk(visit);
+                rhs.walk(visit);
+            }
+            IrExprKind::Call(called, args) => {
+                called.walk(visit);
+                for arg in args {
+                    arg.walk(visit);
+                }
+            }
+            IrExprKind::Lit(IrLiteral::Array(elems) | IrLiteral::Tuple(elems)) => {
+                for elem in elems {
+                    elem.walk(visit);
+                }
+            }
+            IrExprKind::Lit(IrLiteral::Struct(fields)) => {
+                for (_, field) in fields {
+                    field.walk(visit);
+                }
+            }
+            IrExprKind::Var(_)
+            | IrExprKind::Param(..)
+            | IrExprKind::Variant(_, None)
+            | IrExprKind::Lit(_)
+            | IrExprKind::Global(_)
+            | IrExprKind::Fun(_)
+            | IrExprKind::SizeOf(_)
+            | IrExprKind::AlignOf(_) => (),
+        }
+    }
+
+    /// Visit this expression and every expression nested in it mutably, visiting expressions
+    /// before their operands so that the operands of a replaced expression are visited
+    pub fn walk_mut(&mut self, visit: &mut dyn FnMut(&mut IrExpr)) {
+        visit(self);
+        match &mut self.kind {
+            IrExprKind::Unary(_, operand)
+            | IrExprKind::VolatileRead(operand)
+            | IrExprKind::Member(operand, _)
+            | IrExprKind::Variant(_, Some(operand))
+            | IrExprKind::Cast(operand, ..) => operand.walk_mut(visit),
+            IrExprKind::Binary(lhs, _, rhs)
+            | IrExprKind::Index(lhs, rhs)
+            | IrExprKind::Slice(lhs, rhs) => {
+                lhs.walk_mut(visit);
+                rhs.walk_mut(visit);
+            }
+            IrExprKind::Call(called, args) => {
+                called.walk_mut(visit);
+                for arg in args {
+                    arg.walk_mut(visit);
+                }
+            }
+            IrExprKind::Lit(IrLiteral::Array(elems) | IrLiteral::Tuple(elems)) => {
+                for elem in elems {
+                    elem.walk_mut(visit);
+                }
+            }
+            IrExprKind::Lit(IrLiteral::Struct(fields)) => {
+                for (_, field) in fields {
+                    field.walk_mut(visit);
+                }
+            }
+            IrExprKind::Var(_)
+            | IrExprKind::Param(..)
+            | IrExprKind::Variant(_, None)
+            | IrExprKind::Lit(_)
+            | IrExprKind::Global(_)
+            | IrExprKind::Fun(_)
+            | IrExprKind::SizeOf(_)
+            | IrExprKind::AlignOf(_) => (),
+        }
+    }
 }
@@ -4,6 +4,8 @@ use internment::LocalIntern;
 
 pub mod arena;
 pub mod ast;
+#[cfg(feature = "backend-cranelift")]
+pub mod clif;
 pub mod error;
 pub mod ir;
 pub mod llvm;
@@ -18,15 +20,63 @@ pub enum OutputFileType {
     Assembly,
     Object,
     LLVMIR,
+    /// LLVM bitcode for use with other LLVM tools
+    Bitcode,
     IR,
+    /// Spark IR serialized as JSON for use with external tools
+    IRJson,
+    /// Graphviz DOT control flow graphs of function bodies
+    CfgDot,
+}
+
+/// Syntax used for x86 assembly output
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AsmSyntax {
+    ATT,
+    Intel,
+}
+
+/// Relocation model used for generated code
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RelocModel {
+    Default,
+    Static,
+    PIC,
+    DynamicNoPIC,
+}
+
+/// Code model limiting the size and placement of generated code and data
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CodeModel {
+    Default,
+    Small,
+    Kernel,
+    Medium,
+    Large,
+}
+
+/// Description of the machine that generated code will run on
+#[derive(Clone, Debug)]
+pub struct TargetConfig {
+    /// Target triple like `aarch64-unknown-linux-gnu`, or the host's triple if not given
+    pub triple: Option<String>,
+    /// Name of the target CPU, or the host CPU if not given and compiling for the host
+    pub cpu: Option<String>,
+    /// Comma-separated list of CPU features to enable or disable like `+avx2,-sse4.1`
+    pub features: Option<String>,
+    /// Relocation model of the generated code
+    pub reloc: RelocModel,
+    /// Code model of the generated code
+    pub code_model: CodeModel,
 }
 
 /// Enumeration representing all supported optimization profiles for the
 /// generated output
 #[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
 pub enum OutputOptimizationLevel {
-    Release = 3,
-    Medium = 2,
+    Release = 4,
+    Medium = 3,
+    Basic = 2,
     Size = 1,
     Debug = 0,
 }
@@ -40,8 +90,47 @@ pub struct CompileOpts {
     pub out_file: PathBuf,
     /// How much the output should be optimized
     pub opt_lvl: OutputOptimizationLevel,
-    /// Generate position independent code
-    pub pic: bool,
+    /// Machine to generate code for
+    pub target: TargetConfig,
     /// If symbols should be stripped from the output
     pub stripped: bool,
+    /// Generate DWARF debug information
+    pub debug: bool,
+    /// Check array indices against the array's length at runtime
+    pub bounds_check: bool,
+    /// Abort at runtime when integer addition, subtraction, or multiplication overflows
+    pub overflow_check: bool,
+    /// Generate code for assert statements
+    pub asserts: bool,
+    /// Syntax of x86 assembly output
+    pub asm_syntax: AsmSyntax,
+    /// Write the generated LLVM IR to a file when it fails verification
+    pub dump_invalid_ir: bool,
+}
+
+#[cfg(test)]
+impl CompileOpts {
+    /// Options for tests that generate unoptimized code for the host with runtime overflow and
+    /// bounds checks disabled
+    pub(crate) fn testing(out_type: OutputFileType, out_file: impl Into<PathBuf>) -> Self {
+        Self {
+            out_type,
+            out_file: out_file.into(),
+            opt_lvl: OutputOptimizationLevel::Debug,
+            target: TargetConfig {
+                triple: None,
+                cpu: None,
+                features: None,
+                reloc: RelocModel::Default,
+                code_model: CodeModel::Default,
+            },
+            stripped: false,
+            debug: false,
+            bounds_check: false,
+            overflow_check: false,
+            asserts: true,
+            asm_syntax: AsmSyntax::ATT,
+            dump_invalid_ir: false,
+        }
+    }
 }
@@ -0,0 +1,376 @@
+//! Generation of DWARF debug information describing functions, variables, and source locations
+
+use hashbrown::HashMap;
+use inkwell::{
+    debug_info::{
+        AsDIScope, DICompileUnit, DIFile, DIFlags, DIFlagsConstants, DISubprogram, DIType,
+        DWARFEmissionKind, DWARFSourceLanguage, DebugInfoBuilder,
+    },
+    module::{FlagBehavior, Module},
+    values::{FunctionValue, PointerValue},
+    AddressSpace,
+};
+
+use crate::{
+    ir::{types::IrType, IrContext, IrFun, TypeId, VarId},
+    util::{
+        files::{FileId, Files},
+        loc::Span,
+    },
+    OutputOptimizationLevel,
+};
+
+use super::LLVMCodeGeneratorState;
+
+/// DWARF base type encodings used for primitive types
+const DW_ATE_BOOLEAN: u32 = 0x02;
+const DW_ATE_FLOAT: u32 = 0x04;
+const DW_ATE_SIGNED: u32 = 0x05;
+const DW_ATE_UNSIGNED: u32 = 0x08;
+const DW_ATE_UTF: u32 = 0x10;
+
+/// All state needed to attach debug information to the generated LLVM module
+pub struct LLVMDebugInfo<'llvm> {
+    /// Builder used to create all debug metadata
+    builder: DebugInfoBuilder<'llvm>,
+    /// Compile unit that contains all other debug information
+    unit: DICompileUnit<'llvm>,
+    /// Debug file metadata of every source file
+    files: HashMap<FileId, DIFile<'llvm>>,
+    /// Debug scope of the function currently being generated
+    current: Option<DISubprogram<'llvm>>,
+}
+
+impl<'llvm> LLVMDebugInfo<'llvm> {
+    /// Create a debug info builder and compile unit for the given module
+    pub fn new(module: &Module<'llvm>, files: &Files, opt_lvl: OutputOptimizationLevel) -> Self {
+        module.add_basic_value_flag(
+            "Debug Info Version",
+            FlagBehavior::Warning,
+            module
+                .get_context()
+                .i32_type()
+                .const_int(inkwell::debug_info::debug_metadata_version() as u64, false),
+        );
+
+        let (builder, unit) = module.create_debug_info_builder(
+            true,
+            DWARFSourceLanguage::C,
+            module
+                .get_name()
+                .to_str()
+                .expect("module names are created from valid UTF-8 symbols"),
+            ".",
+            "sparkc",
+            opt_lvl > OutputOptimizationLevel::Debug,
+            "",
+            0,
+            "",
+            DWARFEmissionKind::Full,
+            0,
+            false,
+            false,
+            "",
+            "",
+        );
+
+        let files = files
+            .iter()
+            .map(|(id, file)| {
+                let name = file
+                    .path
+                    .file_name()
+                    .map(|name| name.to_string_lossy().into_owned())
+                    .unwrap_or_else(|| "<memory>".to_owned());
+                let dir = file
+                    .path
+                    .parent()
+                    .map(|dir| dir.to_string_lossy().into_owned())
+                    .unwrap_or_else(|| ".".to_owned());
+                (id, builder.create_file(&name, &dir))
+            })
+            .collect();
+
+        Self {
+            builder,
+            unit,
+            files,
+            current: None,
+        }
+    }
+
+    /// Resolve all debug metadata, must be called before the module is verified
+    pub fn finalize(&self) {
+        self.builder.finalize()
+    }
+}
+
+impl<'llvm> LLVMCodeGeneratorState<'llvm> {
+    /// Create the debug scope for a function that is about to have its body generated
+    pub fn debug_fun(&mut self, irctx: &IrContext, fun: &IrFun, llvm_fun: FunctionValue<'llvm>) {
+        if self.debug.is_none() {
+            return;
+        }
+
+        let param_tys = fun
+            .ty
+            .params
+            .iter()
+            .map(|(ty, _)| self.gen_debug_type(irctx, *ty))
+            .collect::<Vec<_>>();
+        let return_ty = match irctx[fun.ty.return_ty] {
+            IrType::Unit => None,
+            _ => Some(self.gen_debug_type(irctx, fun.ty.return_ty)),
+        };
+        let (line, _) = self.line_col(fun.span.from);
+        let debug = self.debug.as_mut().unwrap();
+
+        let file = debug.files[&fun.file];
+        let subroutine_ty =
+            debug
+                .builder
+                .create_subroutine_type(file, return_ty, &param_tys, DIFlags::PUBLIC);
+        let subprogram = debug.builder.create_function(
+            debug.unit.as_debug_info_scope(),
+            fun.name.as_str(),
+            Some(fun.symbol.as_str()),
+            file,
+            line,
+            subroutine_ty,
+            false,
+            true,
+            line,
+            DIFlags::PUBLIC,
+            self.opts.opt_lvl > OutputOptimizationLevel::Debug,
+        );
+
+        llvm_fun.set_subprogram(subprogram);
+        debug.current = Some(subprogram);
+        self.debug_loc(fun.span);
+    }
+
+    /// Set the source location attached to all instructions built after this call
+    pub fn debug_loc(&mut self, span: Span) {
+        if let Some(debug) = &self.debug {
+            if let Some(scope) = debug.current {
+                let (line, col) = self.line_col(span.from);
+                let loc = debug.builder.create_debug_location(
+                    self.ctx,
+                    line,
+                    col,
+                    scope.as_debug_info_scope(),
+                    None,
+                );
+                self.build.set_current_debug_location(self.ctx, loc);
+            }
+        }
+    }
+
+    /// Describe a local variable or parameter's stack allocation in the current function's scope
+    pub fn debug_var(
+        &mut self,
+        irctx: &IrContext,
+        var: VarId,
+        ptr: PointerValue<'llvm>,
+        span: Span,
+        arg_no: Option<u32>,
+    ) {
+        //Compiler-generated variables are not visible to the user
+        if irctx[var].name.starts_with('@') {
+            return;
+        }
+
+        let debug = match &self.debug {
+            Some(debug) => debug,
+            None => return,
+        };
+        let ty = self.gen_debug_type(irctx, irctx[var].ty);
+        let (scope, file) = match (debug.current, self.current_file) {
+            (Some(scope), Some(file)) => (scope, debug.files[&file]),
+            _ => return,
+        };
+
+        let (line, col) = self.line_col(span.from);
+        let scope = scope.as_debug_info_scope();
+        let var_info = match arg_no {
+            Some(arg_no) => debug.builder.create_parameter_variable(
+                scope,
+                irctx[var].name.as_str(),
+                arg_no,
+                file,
+                line,
+                ty,
+                true,
+                DIFlags::ZERO,
+            ),
+            None => debug.builder.create_auto_variable(
+                scope,
+                irctx[var].name.as_str(),
+                file,
+                line,
+                ty,
+                true,
+                DIFlags::ZERO,
+                irctx[var].align.unwrap_or(0) * 8,
+            ),
+        };
+
+        let loc = debug
+            .builder
+            .create_debug_location(self.ctx, line, col, scope, None);
+        debug.builder.insert_declare_at_end(
+            ptr,
+            Some(var_info),
+            None,
+            loc,
+            self.build.get_insert_block().unwrap(),
+        );
+    }
+
+    /// Get the debug type of an IR type. Types without a DWARF base or pointer representation are
+    /// described as structures with the size of the type and no members
+    fn gen_debug_type(&self, irctx: &IrContext, ty: TypeId) -> DIType<'llvm> {
+        let debug = self
+            .debug
+            .as_ref()
+            .expect("debug types are only generated when debug info is enabled");
+        let name = irctx.typename(ty).to_string();
+        let opaque = |bits| {
+            debug
+                .builder
+                .create_struct_type(
+                    debug.unit.as_debug_info_scope(),
+                    &name,
+                    debug.unit.get_file(),
+                    0,
+                    bits,
+                    0,
+                    DIFlags::PUBLIC,
+                    None,
+                    &[],
+                    0,
+                    None,
+                    &name,
+                )
+                .as_type()
+        };
+
+        let encoding = match &irctx[ty] {
+            IrType::Integer(ity) if ity.signed => DW_ATE_SIGNED,
+            IrType::Integer(_) => DW_ATE_UNSIGNED,
+            IrType::Float(_) => DW_ATE_FLOAT,
+            IrType::Bool => DW_ATE_BOOLEAN,
+            IrType::Char => DW_ATE_UTF,
+            IrType::Alias { ty, .. } => return self.gen_debug_type(irctx, *ty),
+            //Opaque types have no size to describe
+            IrType::Opaque(_) => return opaque(0),
+            IrType::Ptr(to) => {
+                let pointee = self.gen_debug_type(irctx, *to);
+                return debug
+                    .builder
+                    .create_pointer_type(
+                        &name,
+                        pointee,
+                        self.debug_bits(ty),
+                        0,
+                        AddressSpace::Generic,
+                    )
+                    .as_type();
+            }
+            _ => return opaque(self.debug_bits(ty)),
+        };
+
+        debug
+            .builder
+            .create_basic_type(&name, self.debug_bits(ty), encoding, DIFlags::PUBLIC)
+            .expect("type names are never empty")
+            .as_type()
+    }
+
+    /// Get the size in bits of an IR type with a known layout
+    fn debug_bits(&self, ty: TypeId) -> u64 {
+        self.target_data
+            .get_bit_size(self.llvm_types.get_secondary(ty))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::llvm::testing::gen_ir_with;
+
+    /// Generate LLVM IR with debug info for a module, returning the textual IR
+    fn debug_ir(src: &str) -> String {
+        gen_ir_with(src, |opts| opts.debug = true)
+    }
+
+    /// Get the metadata node with the given id, like `!12`
+    fn metadata<'a>(ir: &'a str, id: &str) -> &'a str {
+        let prefix = format!("{} = ", id);
+        ir.lines()
+            .find_map(|line| line.strip_prefix(prefix.as_str()))
+            .unwrap_or_else(|| panic!("no metadata node {}:\n{}", id, ir))
+    }
+
+    /// Get the id of the metadata node that a field of a metadata node refers to
+    fn field<'a>(node: &'a str, name: &str) -> &'a str {
+        let start = node
+            .find(&format!("{}: ", name))
+            .unwrap_or_else(|| panic!("no field {} in {}", name, node))
+            + name.len()
+            + 2;
+        let len = node[start..]
+            .find(|c| c == ',' || c == ')')
+            .unwrap_or(node.len() - start);
+        &node[start..start + len]
+    }
+
+    /// Get the number of types in the debug signature of a function, including its return type
+    fn signature_len(ir: &str, fun: &str) -> usize {
+        let name = format!("name: \"{}\"", fun);
+        let subprogram = ir
+            .lines()
+            .find(|line| line.contains("DISubprogram(") && line.contains(&name))
+            .unwrap_or_else(|| panic!("no debug info for {}:\n{}", fun, ir));
+        let subroutine = metadata(ir, field(subprogram, "type"));
+        let types = metadata(ir, field(subroutine, "types"));
+        types.split(',').count()
+    }
+
+    #[test]
+    fn parameters_without_base_types_keep_their_position() {
+        let ir = debug_ir(
+            "type Pair = { i32 a, i32 b }
+
+fun takes(Pair pair, i32 x) -> i32 {
+    return pair.a + x
+}
+
+fun main() -> i32 {
+    let p = #Pair { a = 1, b = 2 }
+    return takes(p, 3)
+}",
+        );
+        assert_eq!(signature_len(&ir, "takes"), 3);
+        assert!(
+            ir.contains("DICompositeType(tag: DW_TAG_structure_type"),
+            "{}",
+            ir
+        );
+    }
+
+    #[test]
+    fn unit_functions_return_void() {
+        let ir = debug_ir(
+            "fun nothing(i64 x) {
+    let _y = x
+}
+
+fun main() -> i32 {
+    nothing(1)
+    return 0
+}",
+        );
+        assert_eq!(signature_len(&ir, "nothing"), 2);
+        assert!(ir.contains("!{null, "), "{}", ir);
+    }
+}
@@ -1,19 +1,19 @@
 use std::convert::{TryFrom, TryInto};
 
-use hashbrown::HashMap;
 use inkwell::{
     types::{BasicType, BasicTypeEnum},
-    values::{BasicValueEnum, CallableValue, PointerValue},
+    values::{BasicValueEnum, CallableValue, IntValue, PointerValue},
     AddressSpace, FloatPredicate, IntPredicate,
 };
 
 use crate::{
     ir::{
         types::{IrIntegerType, IrType},
-        value::{IrExpr, IrExprKind, IrLiteral},
+        value::{CastKind, IrExpr, IrExprKind, IrLiteral},
         IrContext, TypeId,
     },
     parse::token::Op,
+    util::loc::Span,
 };
 
 use super::{LLVMCodeGenerator, LLVMCodeGeneratorState};
@@ -39,16 +39,15 @@ impl<'llvm> LLVMCodeGeneratorState<'llvm> {
                 let alloca = self.gen_lval(irctx, expr);
                 self.build.build_load(alloca, "var_load")
             }
+            IrExprKind::Param(bb, idx) => self.llvm_params[bb][*idx].as_basic_value(),
             IrExprKind::Lit(lit) => match lit {
-                IrLiteral::Integer(v, ty) => self
-                    .ctx
-                    .i64_type()
-                    .const_int(v.val, v.sign)
-                    .const_cast(
-                        LLVMCodeGenerator::gen_inttype(&self.ctx, &self.target_data, ty),
-                        ty.signed,
-                    )
-                    .into(),
+                IrLiteral::Integer(v, ty) => {
+                    let lty = LLVMCodeGenerator::gen_inttype(&self.ctx, &self.target_data, ty);
+                    //Values are stored as two's complement, so truncating gives the value in
+                    //narrower types
+                    lty.const_int_arbitrary_precision(&[v.val as u64, (v.val >> 64) as u64])
+                        .into()
+                }
                 IrLiteral::Float(f, ty) => self
                     .ctx
                     .f64_type()
@@ -98,11 +97,20 @@ impl<'llvm> LLVMCodeGeneratorState<'llvm> {
                     let s = self.gen_lval(irctx, expr);
                     self.build.build_load(s, "struct_lit_load")
                 }
-                IrLiteral::String(s) => self
-                    .build
-                    .build_global_string_ptr(s.as_str(), "strlit")
-                    .as_pointer_value()
-                    .into(),
+                IrLiteral::Tuple(elems) => {
+                    let tuple =
+                        self.entry_alloca(*self.llvm_types.get_secondary(expr.ty), "tuple_lit");
+                    for (idx, elem) in elems.iter().enumerate() {
+                        let val = self.gen_expr(irctx, elem);
+                        let gep = self
+                            .build
+                            .build_struct_gep(tuple, idx as u32, "tuple_lit_elem")
+                            .unwrap();
+                        self.build.build_store(gep, val);
+                    }
+                    self.build.build_load(tuple, "tuple_lit_load")
+                }
+                IrLiteral::String(s) => self.gen_string_lit(s.as_str(), "strlit").into(),
             },
             IrExprKind::Call(fun_expr, args) => {
                 let fun = self.gen_expr(irctx, fun_expr).into_pointer_value();
@@ -121,11 +129,53 @@ impl<'llvm> LLVMCodeGeneratorState<'llvm> {
                     .unwrap_or(self.ctx.i8_type().const_int(0, false).into())
             }
             IrExprKind::Fun(..) => self.gen_lval(irctx, expr).into(),
+            IrExprKind::Variant(idx, payload) => {
+                self.gen_enum_construct(irctx, expr.ty, *idx, payload.as_deref())
+            }
             IrExprKind::Member(..) | IrExprKind::Index(..) => {
                 let ptr = self.gen_lval(irctx, expr);
                 self.build.build_load(ptr, "load")
             }
-            IrExprKind::Cast(expr, ty) => self.gen_cast(irctx, expr, *ty),
+            IrExprKind::Cast(expr, ty, kind) => self.gen_cast(irctx, expr, *ty, *kind),
+            IrExprKind::Slice(ptr, len) => {
+                let ty = self.llvm_types.get_secondary(expr.ty).into_struct_type();
+                let ptr = self.gen_expr(irctx, ptr);
+                let len = self.gen_expr(irctx, len);
+                let slice = self
+                    .build
+                    .build_insert_value(ty.get_undef(), ptr, 0, "slice_ptr")
+                    .unwrap();
+                self.build
+                    .build_insert_value(slice, len, 1, "slice")
+                    .unwrap()
+                    .into_struct_value()
+                    .into()
+            }
+            IrExprKind::SizeOf(ty) => {
+                let size = self.type_size(*ty);
+                self.llvm_types
+                    .get_secondary(IrContext::USIZE)
+                    .into_int_type()
+                    .const_int(size, false)
+                    .into()
+            }
+            IrExprKind::AlignOf(ty) => {
+                let align = self.type_align(irctx, *ty);
+                self.llvm_types
+                    .get_secondary(IrContext::USIZE)
+                    .into_int_type()
+                    .const_int(align as u64, false)
+                    .into()
+            }
+            IrExprKind::VolatileRead(ptr) => {
+                let ptr = self.gen_expr(irctx, ptr).into_pointer_value();
+                let load = self.build.build_load(ptr, "volatile_load");
+                load.as_instruction_value()
+                    .unwrap()
+                    .set_volatile(true)
+                    .unwrap();
+                load
+            }
             IrExprKind::Unary(op, expr) => match op {
                 Op::AND => self.gen_lval(irctx, expr).into(),
                 Op::Star => {
@@ -134,7 +184,7 @@ impl<'llvm> LLVMCodeGeneratorState<'llvm> {
                 }
                 _ => todo!(),
             },
-            IrExprKind::Binary(lhs, op, rhs) => self.gen_bin(irctx, lhs, *op, rhs),
+            IrExprKind::Binary(lhs, op, rhs) => self.gen_bin(irctx, lhs, *op, rhs, expr.span),
         }
     }
 
@@ -151,68 +201,94 @@ impl<'llvm> LLVMCodeGeneratorState<'llvm> {
                 .get_secondary(*f)
                 .as_global_value()
                 .as_pointer_value(),
+            IrExprKind::Member(obj, _)
+                if matches!(&irctx[irctx.unwrap_alias(obj.ty)], IrType::Enum(_)) =>
+            {
+                let enum_ptr = self.gen_lval(irctx, obj);
+                self.gen_sum_payload_ptr(enum_ptr, expr.ty)
+            }
             IrExprKind::Member(obj, field) => {
+                let idx = Self::field_idx(irctx, obj.ty, *field);
                 let obj = self.gen_lval(irctx, obj);
 
-                self.build
-                    .build_struct_gep(obj, *field as u32, "struct_gep")
-                    .unwrap()
+                self.build.build_struct_gep(obj, idx, "struct_gep").unwrap()
+            }
+            IrExprKind::Index(slice, elem)
+                if matches!(&irctx[irctx.unwrap_alias(slice.ty)], IrType::Slice(_)) =>
+            {
+                let slice_ptr = self.gen_lval(irctx, slice);
+                let elem_val = self.gen_expr(irctx, elem).into_int_value();
+                if self.opts.bounds_check {
+                    let len_ptr = self
+                        .build
+                        .build_struct_gep(slice_ptr, 1, "slice_len_ptr")
+                        .unwrap();
+                    let len = self.build.build_load(len_ptr, "slice_len").into_int_value();
+                    let signed = matches!(
+                        &irctx[irctx.unwrap_alias(elem.ty)],
+                        IrType::Integer(IrIntegerType { signed: true, .. })
+                    );
+                    self.gen_bounds_check(elem_val, signed, len, expr.span);
+                }
+
+                let ptr_ptr = self
+                    .build
+                    .build_struct_gep(slice_ptr, 0, "slice_ptr_ptr")
+                    .unwrap();
+                let ptr = self
+                    .build
+                    .build_load(ptr_ptr, "slice_ptr")
+                    .into_pointer_value();
+                unsafe {
+                    self.build
+                        .build_in_bounds_gep(ptr, &[elem_val], "slice_index")
+                }
             }
             IrExprKind::Index(arr, elem) => {
-                let arr = self.gen_lval(irctx, arr);
-                let elem = self.gen_expr(irctx, elem);
+                let arr_ptr = self.gen_lval(irctx, arr);
+                let elem_val = self.gen_expr(irctx, elem).into_int_value();
+                if self.opts.bounds_check {
+                    if let IrType::Array(_, len) = &irctx[irctx.unwrap_alias(arr.ty)] {
+                        let signed = matches!(
+                            &irctx[irctx.unwrap_alias(elem.ty)],
+                            IrType::Integer(IrIntegerType { signed: true, .. })
+                        );
+                        let len = self.ctx.i64_type().const_int(*len, false);
+                        self.gen_bounds_check(elem_val, signed, len, expr.span);
+                    }
+                }
                 unsafe {
                     self.build
                         .build_in_bounds_gep(
-                            arr,
-                            &[self.ctx.i32_type().const_zero(), elem.into_int_value()],
+                            arr_ptr,
+                            &[self.ctx.i32_type().const_zero(), elem_val],
                             "arr_index",
                         )
                         .into()
                 }
             }
-            IrExprKind::Cast(expr, _)
-                if matches!(&irctx[irctx.unwrap_alias(expr.ty)], IrType::Sum(_)) =>
-            {
-                let lval = self.gen_lval(irctx, expr);
-                let ptr = self
-                    .build
-                    .build_struct_gep(lval, 1, "sum_unwrap_ptr")
-                    .unwrap();
-
-                self.build.build_pointer_cast(
-                    ptr,
-                    self.llvm_types
-                        .get_secondary(expr.ty)
-                        .ptr_type(AddressSpace::Generic),
-                    "sum_unwrap_ptr",
-                )
+            IrExprKind::Cast(sum, variant, CastKind::SumUnwrap) => {
+                let sum_ptr = self.gen_lval(irctx, sum);
+                self.gen_sum_payload_ptr(sum_ptr, *variant)
             }
             IrExprKind::Lit(IrLiteral::Struct(s)) => {
-                let irty = if let IrType::Struct(s_ty) = &irctx[expr.ty] {
-                    s_ty
-                } else {
-                    unreachable!()
-                };
-
                 let ty = self.llvm_types.get_secondary(expr.ty).into_struct_type();
-                let mut fields = HashMap::new();
-                for (name, field) in s.iter() {
-                    let idx = irty.field_idx(name).unwrap();
-                    fields.insert(idx, self.gen_expr(irctx, field));
-                }
-
-                let mut field_vec = vec![];
-                for i in 0..s.len() {
-                    field_vec.push(*fields.get(&i).unwrap());
-                }
+                //Fields of structure literals are lowered in the order they are declared in
+                let fields = s
+                    .iter()
+                    .map(|(_, field)| self.gen_expr(irctx, field))
+                    .collect::<Vec<_>>();
 
                 let alloca = self.build.build_alloca(ty, "struct_lit_alloca");
 
-                for (idx, field) in field_vec.into_iter().enumerate() {
+                for (idx, field) in fields.into_iter().enumerate() {
                     let gep = self
                         .build
-                        .build_struct_gep(alloca, idx as u32, "struct_lit")
+                        .build_struct_gep(
+                            alloca,
+                            Self::field_idx(irctx, expr.ty, idx),
+                            "struct_lit",
+                        )
                         .unwrap();
                     self.build.build_store(gep, field);
                 }
@@ -220,9 +296,8 @@ impl<'llvm> LLVMCodeGeneratorState<'llvm> {
                 alloca
             }
             _ => {
-                let alloca = self
-                    .build
-                    .build_alloca(*self.llvm_types.get_secondary(expr.ty), "lval_alloca");
+                let alloca =
+                    self.entry_alloca(*self.llvm_types.get_secondary(expr.ty), "lval_alloca");
                 let val = self.gen_expr(irctx, expr);
                 self.build.build_store(alloca, val);
                 alloca.into()
@@ -230,18 +305,193 @@ impl<'llvm> LLVMCodeGeneratorState<'llvm> {
         }
     }
 
+    /// Get the index of a structure's field in its LLVM type, skipping the padding members that
+    /// precede explicitly aligned fields
+    pub(super) fn field_idx(irctx: &IrContext, ty: TypeId, field: usize) -> u32 {
+        match &irctx[irctx.unwrap_alias(ty)] {
+            IrType::Struct(s_ty) => {
+                let padding = s_ty.fields[..=field]
+                    .iter()
+                    .filter(|field| field.align.is_some())
+                    .count();
+                (field + padding) as u32
+            }
+            _ => field as u32,
+        }
+    }
+
+    /// Generate a check that an index is less than an array or slice's length, aborting at
+    /// runtime with the source location of the index expression otherwise
+    fn gen_bounds_check(
+        &mut self,
+        idx: IntValue<'llvm>,
+        signed: bool,
+        len: IntValue<'llvm>,
+        span: Span,
+    ) {
+        //Constant indices that are in range of a constant length need no check
+        let const_len = len.get_zero_extended_constant();
+        let in_range = match (signed, const_len) {
+            (_, None) => false,
+            (true, Some(len)) => idx
+                .get_sign_extended_constant()
+                .map_or(false, |i| i >= 0 && (i as u64) < len),
+            (false, Some(len)) => idx.get_zero_extended_constant().map_or(false, |i| i < len),
+        };
+        if in_range {
+            return;
+        }
+
+        //Indices are compared at 64 bits or wider so that 128 bit indices aren't truncated into
+        //range before they are checked
+        let width = idx
+            .get_type()
+            .get_bit_width()
+            .max(len.get_type().get_bit_width())
+            .max(64);
+        let cmp_ty = self.ctx.custom_width_int_type(width);
+        let idx = self
+            .build
+            .build_int_cast_sign_flag(idx, cmp_ty, signed, "bounds_idx");
+        let len_val = self
+            .build
+            .build_int_cast_sign_flag(len, cmp_ty, false, "bounds_len");
+        let in_bounds =
+            self.build
+                .build_int_compare(IntPredicate::ULT, idx, len_val, "bounds_check");
+
+        let fun = self
+            .build
+            .get_insert_block()
+            .and_then(|bb| bb.get_parent())
+            .unwrap();
+        let ok_bb = self.ctx.append_basic_block(fun, "in_bounds");
+        let fail_bb = self.ctx.append_basic_block(fun, "out_of_bounds");
+        self.build
+            .build_conditional_branch(in_bounds, ok_bb, fail_bb);
+
+        self.build.position_at_end(fail_bb);
+        let msg = match const_len {
+            Some(len) => format!(
+                "{}: array index out of bounds for array of length {}",
+                self.source_loc(span),
+                len
+            ),
+            None => format!("{}: slice index out of bounds", self.source_loc(span)),
+        };
+        self.gen_abort(&msg);
+
+        self.build.position_at_end(ok_bb);
+    }
+
     pub fn gen_bin(
         &mut self,
         irctx: &IrContext,
         lhs: &IrExpr,
         op: Op,
         rhs: &IrExpr,
+        span: Span,
     ) -> BasicValueEnum<'llvm> {
         let llvm_lhs = self.gen_expr(irctx, lhs);
         let llvm_rhs = self.gen_expr(irctx, rhs);
+        if let (true, Op::Add | Op::Sub | Op::Star, IrType::Integer(ity), IrType::Integer(_)) = (
+            self.opts.overflow_check,
+            op,
+            &irctx[irctx.unwrap_alias(lhs.ty)],
+            &irctx[irctx.unwrap_alias(rhs.ty)],
+        ) {
+            let llvm_lhs = llvm_lhs.into_int_value();
+            let llvm_rhs = self.build.build_int_cast_sign_flag(
+                llvm_rhs.into_int_value(),
+                llvm_lhs.get_type(),
+                ity.signed,
+                "icast",
+            );
+            return self
+                .gen_checked_arith(op, ity.signed, llvm_lhs, llvm_rhs, span)
+                .into();
+        }
+
         self.gen_bin_impl(irctx, lhs.ty, op, rhs.ty, llvm_lhs, llvm_rhs)
     }
 
+    /// Generate an addition, subtraction, or multiplication of two integers of the same type
+    /// that aborts at runtime with the source location of the expression if the result
+    /// overflows
+    fn gen_checked_arith(
+        &mut self,
+        op: Op,
+        signed: bool,
+        lhs: IntValue<'llvm>,
+        rhs: IntValue<'llvm>,
+        span: Span,
+    ) -> IntValue<'llvm> {
+        let int_ty = lhs.get_type();
+        let intrinsic = format!(
+            "llvm.{}{}.with.overflow.i{}",
+            if signed { 's' } else { 'u' },
+            match op {
+                Op::Add => "add",
+                Op::Sub => "sub",
+                _ => "mul",
+            },
+            int_ty.get_bit_width()
+        );
+        let checked = self.root.get_function(&intrinsic).unwrap_or_else(|| {
+            let result_ty = self
+                .ctx
+                .struct_type(&[int_ty.into(), self.ctx.bool_type().into()], false);
+            self.root.add_function(
+                &intrinsic,
+                result_ty.fn_type(&[int_ty.into(), int_ty.into()], false),
+                None,
+            )
+        });
+
+        let result = self
+            .build
+            .build_call(checked, &[lhs.into(), rhs.into()], "checked")
+            .try_as_basic_value()
+            .left()
+            .unwrap()
+            .into_struct_value();
+        let val = self
+            .build
+            .build_extract_value(result, 0, "checked_val")
+            .unwrap()
+            .into_int_value();
+        let overflowed = self
+            .build
+            .build_extract_value(result, 1, "overflowed")
+            .unwrap()
+            .into_int_value();
+
+        let fun = self
+            .build
+            .get_insert_block()
+            .and_then(|bb| bb.get_parent())
+            .unwrap();
+        let ok_bb = self.ctx.append_basic_block(fun, "no_overflow");
+        let fail_bb = self.ctx.append_basic_block(fun, "overflow");
+        self.build
+            .build_conditional_branch(overflowed, fail_bb, ok_bb);
+
+        self.build.position_at_end(fail_bb);
+        let msg = format!(
+            "{}: integer overflow in {}",
+            self.source_loc(span),
+            match op {
+                Op::Add => "addition",
+                Op::Sub => "subtraction",
+                _ => "multiplication",
+            }
+        );
+        self.gen_abort(&msg);
+
+        self.build.position_at_end(ok_bb);
+        val
+    }
+
     /// Generate LLVM bytecode for a binary expression
     pub fn gen_bin_impl(
         &mut self,
@@ -274,8 +524,19 @@ impl<'llvm> LLVMCodeGeneratorState<'llvm> {
                         .build
                         .build_int_unsigned_div(llvm_lhs, llvm_rhs, "udiv")
                         .into(),
+                    (Op::Mod, true) => self
+                        .build
+                        .build_int_signed_rem(llvm_lhs, llvm_rhs, "irem")
+                        .into(),
+                    (Op::Mod, false) => self
+                        .build
+                        .build_int_unsigned_rem(llvm_lhs, llvm_rhs, "urem")
+                        .into(),
                     (Op::Add, _) => self.build.build_int_add(llvm_lhs, llvm_rhs, "iadd").into(),
                     (Op::Sub, _) => self.build.build_int_sub(llvm_lhs, llvm_rhs, "isub").into(),
+                    (Op::AND, _) => self.build.build_and(llvm_lhs, llvm_rhs, "iand").into(),
+                    (Op::OR, _) => self.build.build_or(llvm_lhs, llvm_rhs, "ior").into(),
+                    (Op::XOR, _) => self.build.build_xor(llvm_lhs, llvm_rhs, "ixor").into(),
                     (Op::ShRight, _) => self
                         .build
                         .build_right_shift(llvm_lhs, llvm_rhs, *signed, "ishift")
@@ -372,6 +633,53 @@ impl<'llvm> LLVMCodeGeneratorState<'llvm> {
                     )
                     .into()
             }
+            //The difference of two pointers is the number of elements between them
+            (IrType::Ptr(_), Op::Sub, IrType::Ptr(_)) => {
+                let diff = self.build.build_ptr_diff(
+                    llvm_lhs.into_pointer_value(),
+                    llvm_rhs.into_pointer_value(),
+                    "pdiff",
+                );
+                self.build
+                    .build_int_cast(
+                        diff,
+                        self.llvm_types
+                            .get_secondary(IrContext::ISIZE)
+                            .into_int_type(),
+                        "pdiff",
+                    )
+                    .into()
+            }
+            //Both operands of boolean operators are always evaluated
+            (IrType::Bool, op, IrType::Bool) => {
+                let llvm_lhs = llvm_lhs.into_int_value();
+                let llvm_rhs = llvm_rhs.into_int_value();
+                match op {
+                    Op::AND | Op::LogicalAnd => {
+                        self.build.build_and(llvm_lhs, llvm_rhs, "band").into()
+                    }
+                    Op::OR | Op::LogicalOr => self.build.build_or(llvm_lhs, llvm_rhs, "bor").into(),
+                    Op::XOR => self.build.build_xor(llvm_lhs, llvm_rhs, "bxor").into(),
+                    Op::Eq => self
+                        .build
+                        .build_int_compare(IntPredicate::EQ, llvm_lhs, llvm_rhs, "beq")
+                        .into(),
+                    _ => unreachable!(),
+                }
+            }
+            //Pointers are compared by their addresses
+            (IrType::Ptr(_), op, IrType::Ptr(_)) => self.gen_bin_impl(
+                irctx,
+                IrContext::U64,
+                op,
+                IrContext::U64,
+                self.build
+                    .build_ptr_to_int(llvm_lhs.into_pointer_value(), self.ctx.i64_type(), "picast")
+                    .into(),
+                self.build
+                    .build_ptr_to_int(llvm_rhs.into_pointer_value(), self.ctx.i64_type(), "picast")
+                    .into(),
+            ),
             (IrType::Integer(_), op, IrType::Ptr(_)) => self.gen_bin_impl(
                 irctx,
                 lhs_ty,
@@ -397,29 +705,45 @@ impl<'llvm> LLVMCodeGeneratorState<'llvm> {
         irctx: &IrContext,
         expr: &IrExpr,
         ty: TypeId,
+        kind: CastKind,
     ) -> BasicValueEnum<'llvm> {
         let lty = *self.llvm_types.get_secondary(ty);
+        let signed = |ty: TypeId| {
+            matches!(
+                &irctx[irctx.unwrap_alias(ty)],
+                IrType::Integer(IrIntegerType { signed: true, .. })
+            )
+        };
 
-        if irctx.unwrap_alias(expr.ty) == irctx.unwrap_alias(ty) {
-            return self.gen_expr(irctx, expr);
-        }
-
-        match (
-            &irctx[irctx.unwrap_alias(expr.ty)],
-            &irctx[irctx.unwrap_alias(ty)],
-        ) {
-            (IrType::Integer(_), IrType::Integer(IrIntegerType { signed, .. })) => {
+        match kind {
+            CastKind::Identity => {
+                let val = self.gen_expr(irctx, expr);
+                //Aliases of structures are named LLVM types distinct from the aliased structure type
+                if val.get_type() != lty {
+                    let alloca = self.entry_alloca(val.get_type(), "alias_cast");
+                    self.build.build_store(alloca, val);
+                    let ptr = self.build.build_pointer_cast(
+                        alloca,
+                        lty.ptr_type(AddressSpace::Generic),
+                        "alias_cast",
+                    );
+                    return self.build.build_load(ptr, "alias_cast");
+                }
+                val
+            }
+            //Widening extends by the signedness of the value being widened
+            CastKind::IntResize => {
                 let val = self.gen_expr(irctx, expr);
                 self.build
                     .build_int_cast_sign_flag(
                         val.into_int_value(),
                         lty.into_int_type(),
-                        *signed,
+                        signed(expr.ty),
                         "icast",
                     )
                     .into()
             }
-            (IrType::Integer(IrIntegerType { signed: true, .. }), IrType::Float(_)) => {
+            CastKind::IntToFloat if signed(expr.ty) => {
                 let val = self.gen_expr(irctx, expr);
                 self.build
                     .build_signed_int_to_float(
@@ -429,7 +753,7 @@ impl<'llvm> LLVMCodeGeneratorState<'llvm> {
                     )
                     .into()
             }
-            (IrType::Integer(IrIntegerType { signed: false, .. }), IrType::Float(_)) => {
+            CastKind::IntToFloat => {
                 let val = self.gen_expr(irctx, expr);
                 self.build
                     .build_unsigned_int_to_float(
@@ -439,97 +763,237 @@ impl<'llvm> LLVMCodeGeneratorState<'llvm> {
                     )
                     .into()
             }
-            (IrType::Integer(_), IrType::Ptr(_)) => {
+            CastKind::FloatToInt if signed(ty) => {
+                let val = self.gen_expr(irctx, expr);
+                self.build
+                    .build_float_to_signed_int(
+                        val.into_float_value(),
+                        lty.into_int_type(),
+                        "ficast",
+                    )
+                    .into()
+            }
+            CastKind::FloatToInt => {
+                let val = self.gen_expr(irctx, expr);
+                self.build
+                    .build_float_to_unsigned_int(
+                        val.into_float_value(),
+                        lty.into_int_type(),
+                        "fucast",
+                    )
+                    .into()
+            }
+            CastKind::FloatResize => {
+                let val = self.gen_expr(irctx, expr);
+                self.build
+                    .build_float_cast(val.into_float_value(), lty.into_float_type(), "ffcast")
+                    .into()
+            }
+            CastKind::IntToPtr => {
                 let val = self.gen_expr(irctx, expr);
                 self.build
                     .build_int_to_ptr(val.into_int_value(), lty.into_pointer_type(), "ipcast")
                     .into()
             }
-            (IrType::Ptr(_), IrType::Integer(_)) => {
+            CastKind::PtrToInt => {
                 let val = self.gen_expr(irctx, expr);
                 self.build
                     .build_ptr_to_int(val.into_pointer_value(), lty.into_int_type(), "picast")
                     .into()
             }
-            (IrType::Ptr(_) | IrType::Fun(_), IrType::Ptr(_) | IrType::Fun(_)) => {
+            CastKind::PtrToPtr => {
                 let val = self.gen_expr(irctx, expr);
                 self.build.build_bitcast(val, lty, "ppcast")
             }
-            (IrType::Sum(_), _) => {
-                let lval = self.gen_lval(irctx, expr);
-                let ptr = self
-                    .build
-                    .build_struct_gep(lval, 1, "sum_unwrap_ptr")
-                    .unwrap();
-                let ptr_to_t = self.build.build_pointer_cast(
-                    ptr,
-                    self.llvm_types
-                        .get_secondary(ty)
-                        .ptr_type(AddressSpace::Generic),
-                    "sum_unwrap_ptr",
-                );
-
-                self.build.build_load(ptr_to_t, "sum_unwrap")
+            CastKind::BoolToInt => {
+                let val = self.gen_expr(irctx, expr);
+                self.build
+                    .build_int_z_extend(val.into_int_value(), lty.into_int_type(), "bicast")
+                    .into()
             }
-            (_, IrType::Sum(s)) if s.contains(&expr.ty) => {
-                let idx = s
-                    .iter()
-                    .enumerate()
-                    .find_map(|(idx, variant)| if *variant == expr.ty { Some(idx) } else { None })
-                    .unwrap();
-                let lty = lty.into_struct_type();
+            CastKind::IntToBool => {
+                let val = self.gen_expr(irctx, expr).into_int_value();
+                self.build
+                    .build_int_compare(IntPredicate::NE, val, val.get_type().const_zero(), "ibcast")
+                    .into()
+            }
+            CastKind::EnumToInt => {
+                let tag = self.gen_match_tag(irctx, expr);
+                self.build
+                    .build_int_cast_sign_flag(tag, lty.into_int_type(), true, "etoi")
+                    .into()
+            }
+            CastKind::SumUnwrap => {
+                let sum_ptr = self.gen_lval(irctx, expr);
+                let payload_ptr = self.gen_sum_payload_ptr(sum_ptr, ty);
+                self.build.build_load(payload_ptr, "sum_unwrap")
+            }
+            CastKind::SumWrap => self.gen_sum_construct(irctx, ty, expr),
+        }
+    }
+}
 
-                let structure = self.build.build_alloca(lty, "sumlit");
+#[cfg(test)]
+mod tests {
+    use crate::{
+        llvm::testing::{fun_ir, gen_ir, gen_ir_with, gen_target_ir, run},
+        OutputOptimizationLevel,
+    };
 
-                let ptr_to_discrim = self
-                    .build
-                    .build_struct_gep(structure, 0, "sumlit_discrim")
-                    .unwrap();
+    #[test]
+    fn wide_integers_hold_products_of_64_bit_values() {
+        let src = "fun wide_mul(u64 a, u64 b) -> u128 {
+    return $u128 a * $u128 b
+}
 
-                self.build.build_store(
-                    ptr_to_discrim,
-                    self.ctx.i8_type().const_int(idx as u64, false),
-                );
+fun main() -> i32 {
+    let product = wide_mul(18446744073709551615u64, 4u64)
+    mut result = $i32 (product >> 64u128) * 10000 + $i32 (product & 1023u128)
+    if product > 18446744073709551616u128 {
+        result = result + 1
+    }
+    return result
+}";
+        let ir = gen_ir(src);
+        let wide_mul = fun_ir(&ir, "_S8wide_mulEmm");
+        assert!(wide_mul.contains("zext i64"), "{}", wide_mul);
+        assert!(wide_mul.contains("mul i128"), "{}", wide_mul);
+        assert!(
+            fun_ir(&ir, "_S4mainE").contains("i128 18446744073709551616"),
+            "{}",
+            ir
+        );
 
-                let ptr_to_val = self
-                    .build
-                    .build_struct_gep(structure, 1, "sumlit_val")
-                    .unwrap();
+        //(2^64 - 1) * 4 is 3 * 2^64 + 2^64 - 4, whose low ten bits are 1020
+        assert_eq!(run(src), 31021);
+    }
 
-                let ptr_to_val = self.build.build_pointer_cast(
-                    ptr_to_val,
-                    self.llvm_types
-                        .get_secondary(expr.ty)
-                        .ptr_type(AddressSpace::Generic),
-                    "sumlit_val",
-                );
+    #[test]
+    fn pointer_sized_integers_follow_the_target() {
+        let src = "fun address(*u8 p) -> usize {
+    return $usize p
+}
 
-                let val = self.gen_expr(irctx, expr);
-                self.build.build_store(ptr_to_val, val);
+fun pointer_size() -> usize {
+    return sizeof(*u8)
+}
 
-                self.build.build_load(structure, "sumlit")
-            },
-            (IrType::Integer(_), IrType::Char) => {
-                let to_char = LLVMCodeGenerator::gen_type(self.ctx, &self.target_data, irctx, &IrType::Char)
-                        .into_int_type();
-                
-                let expr = self.gen_expr(irctx, expr).into_int_value();
-                self
-                    .build
-                    .build_int_cast_sign_flag(expr, to_char, false, "itoc")
-                    .into()
-            },
-            (IrType::Char, IrType::Integer(to_ity)) => {
-                let signed = to_ity.signed;
-                let to_ity = LLVMCodeGenerator::gen_inttype(self.ctx, &self.target_data, to_ity);
+fun offset(isize a, isize b) -> isize {
+    return a + b
+}";
+        for (triple, int, size) in [
+            ("i686-unknown-linux-gnu", "i32", 4),
+            ("x86_64-unknown-linux-gnu", "i64", 8),
+        ] {
+            let ir = gen_target_ir(src, triple);
+            let address = fun_ir(&ir, "_S7addressEPh");
+            assert!(
+                address.starts_with(&format!("define {} @_S7addressEPh(i8* %0)", int)),
+                "{}",
+                address
+            );
+            assert!(
+                address.contains("ptrtoint i8*") && address.contains(&format!(" to {}", int)),
+                "{}",
+                address
+            );
+            assert!(
+                fun_ir(&ir, "_S12pointer_sizeE").contains(&format!("{} {}", int, size)),
+                "{}",
+                ir
+            );
+            assert!(
+                fun_ir(&ir, "_S6offsetExx").contains(&format!("add {}", int)),
+                "{}",
+                ir
+            );
+        }
+    }
 
-                let expr = self.gen_expr(irctx, expr).into_int_value();
-                self
-                    .build
-                    .build_int_cast_sign_flag(expr, to_ity, signed, "ctoi")
-                    .into()
-            }
-            _ => unreachable!("{} != {}", irctx.typename(expr.ty), irctx.typename(ty)),
+    const INDEX: &str = "fun get(i32 i) -> i32 {
+    let arr = [10, 20, 30, 40]
+    return arr[i]
+}
+
+fun get_wide(u128 i) -> i32 {
+    let arr = [10, 20, 30, 40]
+    return arr[i]
+}
+
+fun main() -> i32 {
+    return get(2) + get_wide(1u128)
+}";
+
+    #[test]
+    fn out_of_bounds_indices_abort_when_checked() {
+        let ir = gen_ir_with(INDEX, |opts| opts.bounds_check = true);
+        let get = fun_ir(&ir, "_S3getEi");
+        //Negative indices are sign extended so that they compare as out of bounds
+        assert!(get.contains("sext i32 %"), "{}", get);
+        assert!(get.contains("%bounds_check = icmp ult i64"), "{}", get);
+        assert!(get.contains("call void @abort()"), "{}", get);
+        assert!(
+            ir.contains(":3:12: array index out of bounds for array of length 4\\0A"),
+            "{}",
+            ir
+        );
+
+        //A 128 bit index is checked without being truncated to 64 bits
+        let get_wide = fun_ir(&ir, "_S8get_wideEo");
+        assert!(
+            get_wide.contains("%bounds_check = icmp ult i128"),
+            "{}",
+            get_wide
+        );
+
+        assert_eq!(run(INDEX), 50);
+    }
+
+    #[test]
+    fn unchecked_indices_have_no_compare() {
+        let ir = gen_ir_with(INDEX, |opts| {
+            opts.bounds_check = false;
+            opts.opt_lvl = OutputOptimizationLevel::Release;
+        });
+        for symbol in ["_S3getEi", "_S8get_wideEo"] {
+            let fun = fun_ir(&ir, symbol);
+            assert!(!fun.contains("icmp"), "{}", fun);
+            assert!(!fun.contains("@abort"), "{}", fun);
         }
+        assert!(!ir.contains("out of bounds"), "{}", ir);
+    }
+
+    const MUL: &str = "fun mul(i32 a, i32 b) -> i32 {
+    return a * b
+}
+
+fun main() -> i32 {
+    return mul(65536, 65536) + 7
+}";
+
+    #[test]
+    fn runtime_overflow_aborts_only_when_checked() {
+        let ir = gen_ir_with(MUL, |opts| opts.overflow_check = true);
+        let mul = fun_ir(&ir, "_S3mulEii");
+        assert!(
+            mul.contains("call { i32, i1 } @llvm.smul.with.overflow.i32"),
+            "{}",
+            mul
+        );
+        assert!(mul.contains("call void @abort()"), "{}", mul);
+        assert!(
+            ir.contains(":2:12: integer overflow in multiplication\\0A"),
+            "{}",
+            ir
+        );
+
+        let ir = gen_ir(MUL);
+        let mul = fun_ir(&ir, "_S3mulEii");
+        assert!(!mul.contains("with.overflow"), "{}", mul);
+        assert!(!mul.contains("@abort"), "{}", mul);
+        assert!(!ir.contains("integer overflow"), "{}", ir);
+
+        //Without the check the product wraps to zero, 2^32 doesn't fit in 32 bits
+        assert_eq!(run(MUL), 7);
     }
 }
@@ -0,0 +1,290 @@
+//! Just-in-time compilation of the generated module, running spark code in the compiler's own
+//! process instead of writing an output file
+
+use codespan_reporting::diagnostic::{Diagnostic, Label};
+use inkwell::{
+    execution_engine::ExecutionEngine,
+    values::{BasicMetadataValueEnum, BasicValueEnum},
+};
+
+use crate::{
+    ir::{types::IrType, IrContext, TypeId},
+    util::files::FileId,
+};
+
+use super::LLVMCodeGenerator;
+
+/// Name of the generated function that calls the JIT entry point with its arguments
+const JIT_ENTRY: &str = "__spark_jit_entry";
+
+/// Name of the generated function that initializes global variables
+const GLOBAL_SETUP: &str = "__global_setup";
+
+impl<'ctx, 'llvm> LLVMCodeGenerator<'ctx, 'llvm> {
+    /// Compile the generated module to machine code in memory and call the function named
+    /// `entry` with the given arguments, returning its result sign or zero extended to 64 bits
+    ///
+    /// The entry function must take one integer parameter for every argument and return an
+    /// integer, bool, or unit value. Globals are initialized before the entry function is
+    /// called, and calls to external functions like `printf` and `malloc` are resolved from the
+    /// symbols of the host process
+    pub fn jit_run(&self, entry: &str, args: &[i64]) -> Result<i64, Diagnostic<FileId>> {
+        //Prefer spark names so that `main` runs the spark main function instead of the C one
+        let defined = || {
+            self.irctx
+                .funs
+                .indices()
+                .filter(|fun| self.irctx[*fun].body.is_some())
+        };
+        let fun_id = defined()
+            .find(|fun| self.irctx[*fun].name.as_str() == entry)
+            .or_else(|| defined().find(|fun| self.irctx[*fun].symbol.as_str() == entry))
+            .ok_or_else(|| {
+                Diagnostic::error().with_message(format!(
+                    "Cannot run function {}: no function with that name is defined",
+                    entry
+                ))
+            })?;
+        let fun = &self.irctx[fun_id];
+
+        let fun_err = |msg: String| {
+            Diagnostic::error()
+                .with_message(msg)
+                .with_labels(vec![
+                    Label::primary(fun.file, fun.span).with_message("Function defined here")
+                ])
+        };
+
+        if fun.ty.params.len() != args.len() {
+            return Err(fun_err(format!(
+                "Cannot run function {} with {} arguments, it expects {}",
+                fun.name,
+                args.len(),
+                fun.ty.params.len()
+            )));
+        }
+        let is_int =
+            |ty: TypeId| matches!(&self.irctx[self.irctx.unwrap_alias(ty)], IrType::Integer(_));
+        if let Some((ty, _)) = fun.ty.params.iter().find(|(ty, _)| !is_int(*ty)) {
+            return Err(fun_err(format!(
+                "Cannot run function {} with a parameter of non-integer type {}",
+                fun.name,
+                self.irctx.typename(*ty)
+            )));
+        }
+
+        let return_ty = self.irctx.unwrap_alias(fun.ty.return_ty);
+        let signed = match &self.irctx[return_ty] {
+            IrType::Integer(ity) => ity.signed,
+            IrType::Bool | IrType::Unit => false,
+            _ => {
+                return Err(fun_err(format!(
+                    "Cannot run function {} returning non-integer type {}",
+                    fun.name,
+                    self.irctx.typename(fun.ty.return_ty)
+                )))
+            }
+        };
+
+        let i64_ty = self.state.ctx.i64_type();
+        let wrapper = self
+            .state
+            .root
+            .add_function(JIT_ENTRY, i64_ty.fn_type(&[], false), None);
+        let bb = self.state.ctx.append_basic_block(wrapper, "entry");
+        self.state.build.position_at_end(bb);
+        self.state.build.unset_current_debug_location();
+
+        //Initialize globals first, as the C main function would before calling the spark main
+        if let Some(setup) = self
+            .irctx
+            .funs
+            .indices()
+            .find(|fun| self.irctx[*fun].name.as_str() == GLOBAL_SETUP)
+        {
+            self.state
+                .build
+                .build_call(*self.state.llvm_funs.get_secondary(setup), &[], "");
+        }
+
+        let call_args = fun
+            .ty
+            .params
+            .iter()
+            .zip(args)
+            .map(|((ty, _), arg)| {
+                BasicMetadataValueEnum::from(
+                    self.state
+                        .llvm_types
+                        .get_secondary(*ty)
+                        .into_int_type()
+                        .const_int(*arg as u64, true),
+                )
+            })
+            .collect::<Vec<_>>();
+        let result = self.state.build.build_call(
+            *self.state.llvm_funs.get_secondary(fun_id),
+            &call_args,
+            "jit_result",
+        );
+
+        let result = match result.try_as_basic_value().left() {
+            Some(BasicValueEnum::IntValue(val)) if return_ty != IrContext::UNIT => {
+                let width = val.get_type().get_bit_width();
+                if width == 64 {
+                    val
+                } else if width > 64 {
+                    self.state
+                        .build
+                        .build_int_truncate(val, i64_ty, "jit_trunc")
+                } else if signed {
+                    self.state.build.build_int_s_extend(val, i64_ty, "jit_ext")
+                } else {
+                    self.state.build.build_int_z_extend(val, i64_ty, "jit_ext")
+                }
+            }
+            _ => i64_ty.const_zero(),
+        };
+        self.state.build.build_return(Some(&result));
+
+        let output = self.jit_call();
+        unsafe {
+            wrapper.delete();
+        }
+        output
+    }
+
+    /// Create an execution engine for the module and call the generated JIT entry point
+    fn jit_call(&self) -> Result<i64, Diagnostic<FileId>> {
+        ExecutionEngine::link_in_mc_jit();
+
+        //Make the symbols of the compiler's process visible to the JIT'd code
+        unsafe {
+            llvm_sys::support::LLVMLoadLibraryPermanently(std::ptr::null());
+        }
+
+        let engine = self
+            .state
+            .root
+            .create_jit_execution_engine(Self::codegen_opt_lvl(self.state.opts.opt_lvl))
+            .map_err(|e| {
+                Diagnostic::error()
+                    .with_message(format!("Failed to create JIT execution engine: {}", e))
+            })?;
+
+        let result = unsafe { engine.get_function::<unsafe extern "C" fn() -> i64>(JIT_ENTRY) }
+            .map(|entry| unsafe { entry.call() })
+            .map_err(|e| {
+                Diagnostic::error().with_message(format!("Failed to find JIT entry point: {}", e))
+            });
+
+        //Give ownership of the module back to the code generator so it can still be used
+        engine.remove_module(&self.state.root).ok();
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use inkwell::context::Context;
+
+    use crate::{
+        ir::lower::testing::lower,
+        llvm::{testing::run, LLVMCodeGenerator},
+        CompileOpts, OutputFileType,
+    };
+
+    /// Generate code for a module and run the function named `entry` with the given arguments
+    fn jit(src: &str, entry: &str, args: &[i64]) -> Result<i64, String> {
+        let mut lowered = lower(src);
+        let opts = CompileOpts::testing(OutputFileType::Object, "test.o");
+        let ctx = Context::create();
+        let mut gen = LLVMCodeGenerator::new(&mut lowered.ctx, &ctx, &lowered.files, opts)
+            .unwrap_or_else(|e| panic!("failed to create code generator: {}", e.message));
+        gen.gen_module()
+            .unwrap_or_else(|e| panic!("generated invalid LLVM IR: {} {:?}", e.message, e.notes));
+        gen.jit_run(entry, args).map_err(|e| e.message)
+    }
+
+    const ARITH: &str = "fun add(i32 a, i32 b) -> i32 {
+    return a + b
+}
+
+fun negate(i8 a) -> i8 {
+    return 0i8 - a
+}
+
+fun small(u8 a) -> u8 {
+    return a
+}
+
+fun main() -> i32 {
+    return add(1, 2)
+}";
+
+    #[test]
+    fn main_returns_exit_code() {
+        assert_eq!(run(ARITH), 3);
+    }
+
+    #[test]
+    fn entry_is_called_with_arguments() {
+        assert_eq!(jit(ARITH, "add", &[40, 2]), Ok(42));
+    }
+
+    #[test]
+    fn results_are_extended_by_signedness() {
+        assert_eq!(jit(ARITH, "negate", &[5]), Ok(-5));
+        assert_eq!(jit(ARITH, "small", &[-1]), Ok(255));
+    }
+
+    #[test]
+    fn unit_main_returns_zero_after_running_body() {
+        assert_eq!(
+            run("glob mut counter = 0
+
+fun main() {
+    counter = counter + 1
+}"),
+            0
+        );
+    }
+
+    #[test]
+    fn globals_are_initialized_before_main() {
+        assert_eq!(
+            run("glob base = 40
+
+fun main() -> i32 {
+    return base + 2
+}"),
+            42
+        );
+    }
+
+    #[test]
+    fn recursive_main_does_not_reinitialize_globals() {
+        assert_eq!(
+            run("glob mut depth = 0
+
+fun main() -> i32 {
+    depth = depth + 1
+    if depth < 3 { return main() }
+    return depth
+}"),
+            3
+        );
+    }
+
+    #[test]
+    fn calling_with_wrong_arguments_is_an_error() {
+        assert_eq!(
+            jit(ARITH, "add", &[1]),
+            Err("Cannot run function add with 1 arguments, it expects 2".to_owned())
+        );
+        assert_eq!(
+            jit(ARITH, "missing", &[]),
+            Err("Cannot run function missing: no function with that name is defined".to_owned())
+        );
+    }
+}
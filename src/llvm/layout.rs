@@ -0,0 +1,374 @@
+//! Queries of the size, alignment, and field offsets of types on the configured target machine
+
+use codespan_reporting::diagnostic::Diagnostic;
+
+use crate::{
+    ir::{types::IrType, IrContext, TypeId},
+    util::files::FileId,
+    Symbol,
+};
+
+use super::{LLVMCodeGenerator, LLVMCodeGeneratorState};
+
+impl<'llvm> LLVMCodeGeneratorState<'llvm> {
+    /// Get the size in bytes of a type, including any padding needed to place it in an array
+    pub fn type_size(&self, ty: TypeId) -> u64 {
+        self.target_data
+            .get_abi_size(self.llvm_types.get_secondary(ty))
+    }
+
+    /// Get the alignment in bytes of a type, accounting for explicitly aligned structure fields
+    pub fn type_align(&self, irctx: &IrContext, ty: TypeId) -> u32 {
+        let abi = self
+            .target_data
+            .get_abi_alignment(self.llvm_types.get_secondary(ty));
+        irctx.explicit_align(ty).map_or(abi, |align| align.max(abi))
+    }
+}
+
+impl<'ctx, 'llvm> LLVMCodeGenerator<'ctx, 'llvm> {
+    /// Get the size in bytes of a type on the target machine
+    pub fn size_of(&self, ty: TypeId) -> Result<u64, Diagnostic<FileId>> {
+        self.ensure_layout(ty)?;
+        Ok(self.state.type_size(ty))
+    }
+
+    /// Get the alignment in bytes of a type on the target machine
+    pub fn align_of(&self, ty: TypeId) -> Result<u64, Diagnostic<FileId>> {
+        self.ensure_layout(ty)?;
+        Ok(self.state.type_align(self.irctx, ty) as u64)
+    }
+
+    /// Get the offset in bytes of a structure's field from the start of the structure
+    pub fn field_offset(&self, ty: TypeId, field: &Symbol) -> Result<u64, Diagnostic<FileId>> {
+        self.ensure_layout(ty)?;
+        let idx = match &self.irctx[self.irctx.unwrap_alias(ty)] {
+            IrType::Struct(s_ty) => s_ty.field_idx(field).ok_or_else(|| {
+                Diagnostic::error().with_message(format!(
+                    "Structure type {} has no field named {}",
+                    self.irctx.typename(ty),
+                    field
+                ))
+            })?,
+            _ => {
+                return Err(Diagnostic::error().with_message(format!(
+                    "Cannot get the offset of field {} in non-structure type {}",
+                    field,
+                    self.irctx.typename(ty)
+                )))
+            }
+        };
+
+        let s_ty = self.state.llvm_types.get_secondary(ty).into_struct_type();
+        let idx = LLVMCodeGeneratorState::field_idx(self.irctx, ty, idx);
+        Ok(self
+            .state
+            .target_data
+            .offset_of_element(&s_ty, idx)
+            .unwrap())
+    }
+
+    /// Ensure that a type has a layout known to the compiler
+    fn ensure_layout(&self, ty: TypeId) -> Result<(), Diagnostic<FileId>> {
+        match &self.irctx[self.irctx.unwrap_alias(ty)] {
+            IrType::Opaque(_) => Err(Diagnostic::error().with_message(format!(
+                "Cannot get the layout of opaque type {}",
+                self.irctx.typename(ty)
+            ))),
+            IrType::Invalid => {
+                Err(Diagnostic::error().with_message("Cannot get the layout of an invalid type"))
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use inkwell::{
+        context::Context,
+        targets::{CodeModel, InitializationConfig, RelocMode, Target, TargetMachine},
+        OptimizationLevel,
+    };
+
+    use crate::{
+        ir::{layout::TargetSpec, lower::testing::lower, types::IrType, TypeId},
+        llvm::testing::{fun_ir, gen_ir_with, run},
+        CompileOpts, OutputFileType, Symbol,
+    };
+
+    use super::LLVMCodeGenerator;
+
+    /// Generate the LLVM IR of a module for 64 bit x86 Linux
+    fn x86_64_ir(src: &str) -> String {
+        gen_ir_with(src, |opts| {
+            opts.target.triple = Some("x86_64-unknown-linux-gnu".to_owned())
+        })
+    }
+
+    /// Generate code for a module on 64 bit x86 Linux and call a function with the code generator
+    /// and the types of the parameters of the function named `take`
+    fn with_x86_64_params<T>(src: &str, f: impl FnOnce(&LLVMCodeGenerator, &[TypeId]) -> T) -> T {
+        with_params(src, "x86_64-unknown-linux-gnu", f)
+    }
+
+    /// Generate code for a module on the given target and call a function with the code generator
+    /// and the types of the parameters of the function named `take`
+    fn with_params<T>(
+        src: &str,
+        triple: &str,
+        f: impl FnOnce(&LLVMCodeGenerator, &[TypeId]) -> T,
+    ) -> T {
+        let mut lowered = lower(src);
+        let params = lowered.ctx[lowered.fun("take")]
+            .ty
+            .params
+            .iter()
+            .map(|(ty, _)| *ty)
+            .collect::<Vec<_>>();
+        let mut opts = CompileOpts::testing(OutputFileType::Object, "test.o");
+        opts.target.triple = Some(triple.to_owned());
+
+        let ctx = Context::create();
+        let gen = LLVMCodeGenerator::new(&mut lowered.ctx, &ctx, &lowered.files, opts)
+            .unwrap_or_else(|e| panic!("failed to create code generator: {}", e.message));
+        f(&gen, &params)
+    }
+
+    const ARRAYS: &str = "type Words = { u8 tag, [8]u32 words }
+type Padded = { u8 a, u64 b, u8 c }
+type Inner = { u16 x, [3]u8 y }
+type Outer = { u8 a, Inner b }
+type Flexible = { u32 len, [0]u8 data }
+
+fun take(Words a, Padded b, Outer c, Flexible d, [3]Inner e) {}
+
+fun main() -> i32 {
+    mut [Words] w = #Words { tag = 1u8, words = [1u32, 2u32, 3u32, 4u32, 5u32, 6u32, 7u32, 8u32] }
+    return $i32 w.words[7] + $i32 w.tag
+}";
+
+    #[test]
+    fn layouts_match_c_layouts_on_x86_64() {
+        //Sizes, alignments, and offsets given by clang for the equivalent C structures
+        with_x86_64_params(ARRAYS, |gen, params| {
+            let size = |idx: usize| gen.size_of(params[idx]).unwrap();
+            let align = |idx: usize| gen.align_of(params[idx]).unwrap();
+            let offset =
+                |idx: usize, field| gen.field_offset(params[idx], &Symbol::from(field)).unwrap();
+
+            assert_eq!((size(0), align(0), offset(0, "words")), (36, 4, 4));
+            assert_eq!((size(1), align(1)), (24, 8));
+            assert_eq!((offset(1, "b"), offset(1, "c")), (8, 16));
+            assert_eq!((size(2), align(2), offset(2, "b")), (8, 2, 2));
+            assert_eq!((size(3), offset(3, "data")), (4, 4));
+            assert_eq!((size(4), align(4)), (18, 2));
+        });
+    }
+
+    #[test]
+    fn array_fields_are_llvm_arrays() {
+        let ir = x86_64_ir(ARRAYS);
+        assert!(ir.contains("%Words = type { i8, [8 x i32] }"), "{}", ir);
+        assert!(ir.contains("%Inner = type { i16, [3 x i8] }"), "{}", ir);
+        assert!(ir.contains("%Flexible = type { i32, [0 x i8] }"), "{}", ir);
+        assert_eq!(run(ARRAYS), 9);
+    }
+
+    const PACKED: &str = "type Packet = packed { u8 kind, u32 len, u16 crc }
+type Framed = { u8 start, Packet packet, u32 end }
+
+fun take(Packet a, Framed b) {}
+
+fun main() -> i32 {
+    mut [Framed] f = #Framed { start = 1u8, packet = #Packet { kind = 2u8, len = 30u32, crc = 4u16 }, end = 5u32 }
+    let f.packet.len = f.packet.len + 10u32
+    return $i32 f.start + $i32 f.packet.kind + $i32 f.packet.len + $i32 f.packet.crc + $i32 f.end
+}";
+
+    #[test]
+    fn packed_structs_have_no_padding() {
+        with_x86_64_params(PACKED, |gen, params| {
+            let size = |idx: usize| gen.size_of(params[idx]).unwrap();
+            let align = |idx: usize| gen.align_of(params[idx]).unwrap();
+            let offset =
+                |idx: usize, field| gen.field_offset(params[idx], &Symbol::from(field)).unwrap();
+
+            assert_eq!((size(0), align(0)), (7, 1));
+            assert_eq!(
+                (offset(0, "kind"), offset(0, "len"), offset(0, "crc")),
+                (0, 1, 5)
+            );
+            assert_eq!((size(1), align(1)), (12, 4));
+            assert_eq!((offset(1, "packet"), offset(1, "end")), (1, 8));
+        });
+    }
+
+    #[test]
+    fn packed_structs_are_packed_llvm_structs() {
+        let ir = x86_64_ir(PACKED);
+        assert!(ir.contains("%Packet = type <{ i8, i32, i16 }>"), "{}", ir);
+        assert!(ir.contains("%Framed = type { i8, %Packet, i32 }"), "{}", ir);
+        assert_eq!(run(PACKED), 52);
+    }
+
+    const SUMS: &str = "type Wide = i32 | f64
+type Byte = u8 | f64
+type Pair = { u8 tag, Byte b }
+
+fun take(Wide a, Byte b, Pair c) {}
+
+fun wrap(f64 v) -> Byte {
+    return v
+}
+
+fun unwrap(Byte b) -> i32 {
+    return match b {
+        f64 v -> phi $i32 (v * 2.0),
+        u8 c -> phi $i32 c
+    }
+}
+
+fun main() -> i32 {
+    mut [Pair] p = #Pair { tag = 1u8, b = wrap(20.25) }
+    return unwrap(p.b) + unwrap($Byte 2u8)
+}";
+
+    #[test]
+    fn sum_payloads_are_aligned_to_the_most_aligned_variant() {
+        with_x86_64_params(SUMS, |gen, params| {
+            let size = |idx: usize| gen.size_of(params[idx]).unwrap();
+            let align = |idx: usize| gen.align_of(params[idx]).unwrap();
+
+            assert_eq!((size(0), align(0)), (16, 8));
+            assert_eq!((size(1), align(1)), (16, 8));
+            assert_eq!((size(2), align(2)), (24, 8));
+            assert_eq!(gen.field_offset(params[2], &Symbol::from("b")).unwrap(), 8);
+        });
+    }
+
+    #[test]
+    fn sum_variants_round_trip_through_the_payload() {
+        let ir = x86_64_ir(SUMS);
+        assert!(
+            ir.contains("%Pair = type { i8, { i8, { double, [0 x i8] } } }"),
+            "{}",
+            ir
+        );
+        assert_eq!(run(SUMS), 42);
+    }
+
+    #[test]
+    fn pointer_sizes_follow_the_target_triple() {
+        let src = "type Node = { *Node next, u8 tag }
+
+fun take(*u8 p, Node n) {}";
+        for (triple, ptr, node) in [
+            ("i686-unknown-linux-gnu", 4, 8),
+            ("x86_64-unknown-linux-gnu", 8, 16),
+        ] {
+            with_params(src, triple, |gen, params| {
+                let size = |idx: usize| gen.size_of(params[idx]).unwrap();
+                assert_eq!((size(0), size(1)), (ptr, node), "{}", triple);
+            });
+        }
+
+        let ir = gen_ir_with(src, |opts| {
+            opts.target.triple = Some("i686-unknown-linux-gnu".to_owned())
+        });
+        assert!(
+            ir.contains("target triple = \"i686-unknown-linux-gnu\""),
+            "{}",
+            ir
+        );
+        assert!(ir.contains("p:32:32"), "{}", ir);
+    }
+
+    #[test]
+    fn layout_queries_report_types_without_a_layout() {
+        let src = "type Ctx
+type Point = { i32 x, i32 y }
+
+fun take(*Ctx c, Point p, i32 n) {}";
+        with_x86_64_params(src, |gen, params| {
+            let opaque = match &gen.irctx[params[0]] {
+                IrType::Ptr(pointee) => *pointee,
+                other => panic!("expected a pointer, found {:?}", other),
+            };
+            let message = "Cannot get the layout of opaque type Ctx";
+            assert_eq!(gen.size_of(opaque).unwrap_err().message, message);
+            assert_eq!(gen.align_of(opaque).unwrap_err().message, message);
+            assert_eq!(gen.size_of(params[0]).unwrap(), 8);
+
+            let offset = |idx: usize, field| gen.field_offset(params[idx], &Symbol::from(field));
+            assert_eq!(offset(1, "y").unwrap(), 4);
+            assert_eq!(
+                offset(1, "z").unwrap_err().message,
+                "Structure type Point has no field named z"
+            );
+            assert_eq!(
+                offset(2, "x").unwrap_err().message,
+                "Cannot get the offset of field x in non-structure type i32"
+            );
+        });
+    }
+
+    #[test]
+    fn alignof_uses_target_alignment() {
+        let ir = x86_64_ir(
+            "fun align_u8() -> usize { return alignof(u8) }
+fun align_u64() -> usize { return alignof(u64) }
+fun main() -> i32 { return $i32 (align_u8() + align_u64()) }",
+        );
+        assert!(fun_ir(&ir, "_S8align_u8E").contains("ret i64 1"), "{}", ir);
+        assert!(fun_ir(&ir, "_S9align_u64E").contains("ret i64 8"), "{}", ir);
+    }
+
+    #[test]
+    fn struct_layout_matches_ir_layout() {
+        let lowered = lower(
+            "type Aligned = { u8 a, align(16) u8 b, }
+type Overaligned = { align(8) u8 a, }
+type Padded = { i64 a, u8 b, }
+type Nested = { Aligned a, u8 b, }
+type Packed = packed { u8 a, i32 b, }
+fun main() -> i32 { return 0 }",
+        );
+        let irctx = &lowered.ctx;
+
+        Target::initialize_native(&InitializationConfig::default()).unwrap();
+        let triple = TargetMachine::get_default_triple();
+        let machine = Target::from_triple(&triple)
+            .unwrap()
+            .create_target_machine(
+                &triple,
+                "generic",
+                "",
+                OptimizationLevel::None,
+                RelocMode::Default,
+                CodeModel::Default,
+            )
+            .unwrap();
+        let target_data = machine.get_target_data();
+        let spec = TargetSpec::from_triple(&triple.as_str().to_string_lossy());
+
+        let ctx = Context::create();
+        let structs = LLVMCodeGenerator::gen_named_structs(&ctx, &target_data, irctx);
+        assert!(!structs.is_empty());
+        for (id, llvm_ty) in structs.iter() {
+            let layout = irctx.layout_of(*id, &spec).unwrap();
+            let abi_align = target_data.get_abi_alignment(llvm_ty);
+            let align = irctx
+                .explicit_align(*id)
+                .map_or(abi_align, |align| align.max(abi_align));
+            let name = irctx.typename(*id).to_string();
+            assert_eq!(
+                target_data.get_abi_size(llvm_ty),
+                layout.size,
+                "size of {}",
+                name
+            );
+            assert_eq!(align as u64, layout.align, "alignment of {}", name);
+        }
+    }
+}
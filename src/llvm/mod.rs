@@ -1,15 +1,21 @@
-use hashbrown::HashMap;
+use std::{ffi::CString, path::Path, sync::Once};
+
+use codespan_reporting::diagnostic::{Diagnostic, Label};
+use hashbrown::{HashMap, HashSet};
 use inkwell::{
+    attributes::AttributeLoc,
     basic_block::BasicBlock,
     builder::Builder,
     context::Context,
     module::{Linkage, Module},
-    passes::PassManager,
+    passes::{PassManager, PassManagerBuilder},
+    support::LLVMString,
     targets::{
         CodeModel, FileType, InitializationConfig, RelocMode, Target, TargetData, TargetMachine,
+        TargetTriple,
     },
-    types::{BasicType, BasicTypeEnum, FunctionType, IntType},
-    values::{FunctionValue, GlobalValue, PointerValue},
+    types::{BasicType, BasicTypeEnum, FunctionType, IntType, StructType},
+    values::{FunctionValue, GlobalValue, PhiValue, PointerValue},
     AddressSpace, OptimizationLevel,
 };
 
@@ -17,14 +23,27 @@ use crate::{
     arena::Arena,
     ast::{FunFlags, IntegerWidth},
     ir::{
-        types::{FunType, IrFloatType, IrIntegerType, IrType},
-        BBId, IrContext,
+        types::{FunType, IrFloatType, IrIntegerType, IrStructType, IrType},
+        BBId, IrContext, TypeId,
+    },
+    util::{
+        files::{FileId, Files},
+        loc::Span,
     },
-    CompileOpts, OutputFileType, OutputOptimizationLevel,
+    AsmSyntax, CompileOpts, OutputFileType, OutputOptimizationLevel, RelocModel,
 };
 
+use self::debug::LLVMDebugInfo;
+
+pub mod debug;
 pub mod expr;
+pub mod jit;
+pub mod layout;
 pub mod stmt;
+pub mod sum;
+
+#[cfg(test)]
+pub(crate) mod testing;
 
 /// Structure containing all state needed to generate LLVM IR from spark IR
 pub struct LLVMCodeGenerator<'ctx, 'llvm> {
@@ -43,62 +62,132 @@ pub struct LLVMCodeGeneratorState<'llvm> {
     llvm_types: Arena<BasicTypeEnum<'llvm>>,
     llvm_vars: Arena<Option<PointerValue<'llvm>>>,
     llvm_bbs: HashMap<BBId, BasicBlock<'llvm>>,
+    /// Phi nodes of the parameters of every basic block generated so far
+    llvm_params: HashMap<BBId, Vec<PhiValue<'llvm>>>,
     llvm_globs: Arena<GlobalValue<'llvm>>,
+    debug: Option<LLVMDebugInfo<'llvm>>,
+    /// Paths and line start offsets of all source files, used to report source locations
+    sources: HashMap<FileId, (String, Vec<usize>)>,
+    /// Source file of the function currently being generated
+    current_file: Option<FileId>,
+    /// If the target is WebAssembly, which has no mergeable sections or separate address spaces
+    wasm: bool,
 }
 
 impl<'ctx, 'llvm> LLVMCodeGenerator<'ctx, 'llvm> {
     /// Create a new [LLVMCodeGenerator] from shared reference to a [Files] structure and unique
     /// reference to the IR context
-    pub fn new(irctx: &'ctx mut IrContext, ctx: &'llvm Context, opts: CompileOpts) -> Self {
-        let root = ctx.create_module("spark_module");
-        let mut id = 0;
+    pub fn new(
+        irctx: &'ctx mut IrContext,
+        ctx: &'llvm Context,
+        files: &Files,
+        opts: CompileOpts,
+    ) -> Result<Self, Diagnostic<FileId>> {
+        let root = ctx.create_module(
+            opts.out_file
+                .file_stem()
+                .and_then(|stem| stem.to_str())
+                .unwrap_or("spark_module"),
+        );
 
-        Target::initialize_native(&InitializationConfig::default()).unwrap();
+        match opts.target.triple {
+            Some(_) => Target::initialize_all(&InitializationConfig::default()),
+            None => Target::initialize_native(&InitializationConfig::default()).map_err(|e| {
+                Diagnostic::error()
+                    .with_message(format!("Failed to initialize native target: {}", e))
+            })?,
+        }
 
-        let opt = match opts.opt_lvl {
-            OutputOptimizationLevel::Release => OptimizationLevel::Aggressive,
-            OutputOptimizationLevel::Medium => OptimizationLevel::Default,
-            OutputOptimizationLevel::Size => OptimizationLevel::Less,
-            OutputOptimizationLevel::Debug => OptimizationLevel::None,
+        let opt = Self::codegen_opt_lvl(opts.opt_lvl);
+        let reloc = match opts.target.reloc {
+            RelocModel::Default => RelocMode::Default,
+            RelocModel::Static => RelocMode::Static,
+            RelocModel::PIC => RelocMode::PIC,
+            RelocModel::DynamicNoPIC => RelocMode::DynamicNoPic,
         };
-        let reloc = match opts.pic {
-            true => RelocMode::PIC,
-            false => RelocMode::Default,
+        let model = match opts.target.code_model {
+            crate::CodeModel::Default => CodeModel::Default,
+            crate::CodeModel::Small => CodeModel::Small,
+            crate::CodeModel::Kernel => CodeModel::Kernel,
+            crate::CodeModel::Medium => CodeModel::Medium,
+            crate::CodeModel::Large => CodeModel::Large,
         };
-        let model = CodeModel::Default;
-        let target = Target::from_triple(&TargetMachine::get_default_triple()).unwrap();
+
+        //CPU name and features default to the host's only when compiling for the host
+        let (triple, cpu, features) = match &opts.target.triple {
+            Some(triple) => (
+                TargetTriple::create(triple),
+                opts.target
+                    .cpu
+                    .clone()
+                    .unwrap_or_else(|| "generic".to_owned()),
+                opts.target.features.clone().unwrap_or_default(),
+            ),
+            None => (
+                TargetMachine::get_default_triple(),
+                opts.target
+                    .cpu
+                    .clone()
+                    .unwrap_or_else(|| TargetMachine::get_host_cpu_name().to_string()),
+                opts.target
+                    .features
+                    .clone()
+                    .unwrap_or_else(|| TargetMachine::get_host_cpu_features().to_string()),
+            ),
+        };
+        let arch = triple.as_str().to_string_lossy();
+        let wasm = arch.starts_with("wasm");
+        let x86 = ["x86", "i386", "i486", "i586", "i686"]
+            .iter()
+            .any(|prefix| arch.starts_with(prefix));
+        if opts.out_type == OutputFileType::Assembly && opts.asm_syntax == AsmSyntax::Intel && x86 {
+            //LLVM exits the process when an option is given twice, so the syntax stays selected for
+            //every later compilation in the same process
+            static INTEL_SYNTAX: Once = Once::new();
+            INTEL_SYNTAX.call_once(|| Self::set_llvm_option("-x86-asm-syntax=intel"));
+        }
+
+        let target = Target::from_triple(&triple).map_err(|e| {
+            Diagnostic::error().with_message(format!(
+                "Failed to find target for triple {}: {}",
+                triple.as_str().to_string_lossy(),
+                e
+            ))
+        })?;
         let target_machine = target
-            .create_target_machine(
-                &TargetMachine::get_default_triple(),
-                TargetMachine::get_host_cpu_name().to_str().unwrap(),
-                TargetMachine::get_host_cpu_features().to_str().unwrap(),
-                opt,
-                reloc,
-                model,
-            )
-            .unwrap();
+            .create_target_machine(&triple, &cpu, &features, opt, reloc, model)
+            .ok_or_else(|| {
+                Diagnostic::error().with_message(format!(
+                    "Failed to create target machine for triple {}",
+                    triple.as_str().to_string_lossy()
+                ))
+            })?;
         let target_data = target_machine.get_target_data();
-        
-        let llvm_types = irctx.types.secondary(|(_, ty)| {
+        root.set_triple(&triple);
+        root.set_data_layout(&target_data.get_data_layout());
+
+        let structs = Self::gen_named_structs(ctx, &target_data, irctx);
+        let llvm_types = irctx.types.secondary(|(id, ty)| {
             if matches!(ty, IrType::Invalid) {
                 ctx.i8_type().into()
             } else {
-                Self::gen_type(ctx, &target_data, irctx, ty)
+                Self::gen_type_id(ctx, &target_data, irctx, &structs, id)
             }
         });
 
         let llvm_funs = irctx.funs.secondary(|(_, fun)| {
-            root.add_function(
-                if fun.flags.contains(FunFlags::EXTERN) {
-                    fun.name.to_string()
-                } else {
-                    id += 1;
-                    format!("{}#{}", fun.name, id)
-                }
-                .as_str(),
-                Self::gen_funtype(ctx, &target_data, irctx, &fun.ty),
+            let llvm_fun = root.add_function(
+                fun.symbol.as_str(),
+                Self::gen_funtype(ctx, &target_data, irctx, &structs, &fun.ty),
                 Some(Linkage::External),
-            )
+            );
+            if wasm && fun.flags.contains(FunFlags::EXPORT) {
+                llvm_fun.add_attribute(
+                    AttributeLoc::Function,
+                    ctx.create_string_attribute("wasm-export-name", fun.symbol.as_str()),
+                );
+            }
+            llvm_fun
         });
 
         let llvm_globs = irctx.globals.secondary(|(_, glob)| {
@@ -106,18 +195,38 @@ impl<'ctx, 'llvm> LLVMCodeGenerator<'ctx, 'llvm> {
                 return root.get_first_function().unwrap().as_global_value();
             }
             let ty = *llvm_types.get_secondary(glob.ty);
-            let glob = root.add_global(ty, Some(AddressSpace::Global), &glob.name);
+            let glob = root.add_global(ty, Some(AddressSpace::Generic), &glob.name);
             glob.set_initializer(&ty.const_zero());
             glob
         });
 
-        Self {
+        let debug = match opts.debug {
+            true => Some(LLVMDebugInfo::new(&root, files, opts.opt_lvl)),
+            false => None,
+        };
+
+        let sources = files
+            .iter()
+            .map(|(id, file)| {
+                (
+                    id,
+                    (file.path.to_string_lossy().into_owned(), file.lines.clone()),
+                )
+            })
+            .collect();
+
+        Ok(Self {
             state: LLVMCodeGeneratorState {
+                debug,
+                sources,
+                current_file: None,
+                wasm,
                 llvm_funs,
                 llvm_types,
                 llvm_globs,
                 llvm_vars: irctx.vars.secondary(|_| None),
                 llvm_bbs: HashMap::new(),
+                llvm_params: HashMap::new(),
                 ctx,
                 target_data,
                 target_machine,
@@ -126,11 +235,48 @@ impl<'ctx, 'llvm> LLVMCodeGenerator<'ctx, 'llvm> {
                 build: ctx.create_builder(),
             },
             irctx,
+        })
+    }
+
+    /// Get the optimization level of machine code generation for an output optimization level
+    fn codegen_opt_lvl(opt_lvl: OutputOptimizationLevel) -> OptimizationLevel {
+        match opt_lvl {
+            OutputOptimizationLevel::Release => OptimizationLevel::Aggressive,
+            OutputOptimizationLevel::Medium => OptimizationLevel::Default,
+            OutputOptimizationLevel::Basic | OutputOptimizationLevel::Size => {
+                OptimizationLevel::Less
+            }
+            OutputOptimizationLevel::Debug => OptimizationLevel::None,
         }
     }
 
-    /// Generate all LLVM bytecode for the given IR context and return the completed LLVM module
-    pub fn gen(mut self) -> Module<'llvm> {
+    /// Set a global LLVM option as if it were passed to an LLVM tool on the command line
+    fn set_llvm_option(option: &str) {
+        let args = [
+            CString::new("sparkc").unwrap(),
+            CString::new(option).unwrap(),
+        ];
+        let argv = args.iter().map(|arg| arg.as_ptr()).collect::<Vec<_>>();
+        unsafe {
+            llvm_sys::support::LLVMParseCommandLineOptions(
+                argv.len() as i32,
+                argv.as_ptr(),
+                std::ptr::null(),
+            );
+        }
+    }
+
+    /// Generate all LLVM bytecode for the given IR context, write it to the output file, and
+    /// return the completed LLVM module
+    pub fn gen(mut self) -> Result<Module<'llvm>, Diagnostic<FileId>> {
+        self.gen_module()?;
+        self.write_output()?;
+        Ok(self.state.root)
+    }
+
+    /// Generate all LLVM bytecode for the given IR context, verifying and optimizing the module
+    /// without writing any output
+    pub fn gen_module(&mut self) -> Result<(), Diagnostic<FileId>> {
         for fun_id in self.state.llvm_funs.indices() {
             let fun = self.irctx.funs.get_secondary(fun_id);
             let llvm_fun = self.state.llvm_funs[fun_id];
@@ -138,12 +284,20 @@ impl<'ctx, 'llvm> LLVMCodeGenerator<'ctx, 'llvm> {
                 let bb = self.state.ctx.append_basic_block(llvm_fun, "entry");
                 self.state.llvm_bbs.insert(body.entry, bb);
                 self.state.build.position_at_end(bb);
+                self.state.current_file = Some(fun.file);
+                self.state.debug_fun(self.irctx, fun, llvm_fun);
                 for (idx, (ty, param)) in fun.ty.params.iter().enumerate() {
                     if let Some(name) = param {
                         let alloca = self
                             .state
-                            .build
-                            .build_alloca(*self.state.llvm_types.get_secondary(*ty), name.as_str());
+                            .entry_alloca(*self.state.llvm_types.get_secondary(*ty), name.as_str());
+                        if let Some(align) = self.irctx.explicit_align(*ty) {
+                            alloca
+                                .as_instruction()
+                                .unwrap()
+                                .set_alignment(align)
+                                .unwrap();
+                        }
                         self.state
                             .build
                             .build_store(alloca, llvm_fun.get_nth_param(idx as u32).unwrap());
@@ -151,6 +305,13 @@ impl<'ctx, 'llvm> LLVMCodeGenerator<'ctx, 'llvm> {
                             .state
                             .llvm_vars
                             .get_secondary_mut(body.args[idx].unwrap()) = Some(alloca);
+                        self.state.debug_var(
+                            self.irctx,
+                            body.args[idx].unwrap(),
+                            alloca,
+                            fun.span,
+                            Some(idx as u32 + 1),
+                        );
                     }
                 }
                 self.state
@@ -158,28 +319,103 @@ impl<'ctx, 'llvm> LLVMCodeGenerator<'ctx, 'llvm> {
             }
         }
 
-        self.state.root.verify().unwrap_or_else(|e| {
-            eprintln!("ICE: LLVM module verification failed: {}", e.to_string())
-        });
+        if let Some(debug) = &self.state.debug {
+            debug.finalize();
+        }
+
+        self.verify()?;
+        self.optimize();
+        Ok(())
+    }
+
+    /// Run the LLVM verifier over the generated module, reporting a failure at the definition of
+    /// the first function that contains invalid IR
+    pub fn verify(&self) -> Result<(), Diagnostic<FileId>> {
+        let msg = match self.state.root.verify() {
+            Ok(()) => return Ok(()),
+            Err(e) => e.to_string(),
+        };
 
-        let fpm = PassManager::create(&self.state.root);
-        if self.state.opts.opt_lvl > OutputOptimizationLevel::Debug {
-            fpm.add_instruction_combining_pass();
-            fpm.add_reassociate_pass();
-            fpm.add_gvn_pass();
-            fpm.add_cfg_simplification_pass();
-            fpm.add_basic_alias_analysis_pass();
-            fpm.add_promote_memory_to_register_pass();
-            fpm.add_instruction_combining_pass();
-            fpm.add_reassociate_pass();
+        let invalid = self
+            .state
+            .llvm_funs
+            .indices()
+            .find(|fun| !self.state.llvm_funs[*fun].verify(false))
+            .or_else(|| {
+                self.state.llvm_funs.indices().find(|fun| {
+                    let name = self.irctx.funs.get_secondary(*fun).symbol;
+                    msg.contains(&format!("@{}", name))
+                })
+            });
+
+        let mut diag = Diagnostic::error();
+        if let Some(fun) = invalid {
+            let fun = self.irctx.funs.get_secondary(fun);
+            diag = diag
+                .with_message(format!(
+                    "ICE: Generated invalid LLVM IR for function {}",
+                    fun.name
+                ))
+                .with_labels(vec![
+                    Label::primary(fun.file, fun.span).with_message("Function defined here")
+                ]);
+        } else {
+            diag = diag.with_message("ICE: Generated invalid LLVM IR");
+        }
+
+        let mut notes = vec![format!("LLVM verifier output:\n{}", msg.trim_end())];
+        if self.state.opts.dump_invalid_ir {
+            let path = self.state.opts.out_file.with_extension("invalid.ll");
+            notes.push(match self.state.root.print_to_file(&path) {
+                Ok(()) => format!("Invalid LLVM IR written to {}", path.display()),
+                Err(e) => format!(
+                    "Failed to write invalid LLVM IR to {}: {}",
+                    path.display(),
+                    e
+                ),
+            });
+        } else {
+            notes.push("Pass --dump-invalid-ir to write the invalid LLVM IR to a file".to_owned());
+        }
+
+        Err(diag.with_notes(notes))
+    }
+
+    /// Run the optimization pipeline selected by the configured optimization level over the
+    /// generated module
+    pub fn optimize(&self) {
+        let pm = PassManager::create(());
+        let (opt, size) = match self.state.opts.opt_lvl {
+            OutputOptimizationLevel::Release => (OptimizationLevel::Aggressive, 0),
+            OutputOptimizationLevel::Medium => (OptimizationLevel::Default, 0),
+            OutputOptimizationLevel::Basic => (OptimizationLevel::Less, 0),
+            OutputOptimizationLevel::Size => (OptimizationLevel::Default, 1),
+            OutputOptimizationLevel::Debug => (OptimizationLevel::None, 0),
+        };
+
+        if opt != OptimizationLevel::None {
+            pm.add_promote_memory_to_register_pass();
+            pm.add_instruction_combining_pass();
+            pm.add_reassociate_pass();
+            pm.add_gvn_pass();
+            pm.add_cfg_simplification_pass();
+
+            let builder = PassManagerBuilder::create();
+            builder.set_optimization_level(opt);
+            builder.set_size_level(size);
+            builder.populate_module_pass_manager(&pm);
         }
 
         if self.state.opts.stripped {
-            fpm.add_strip_symbol_pass();
+            pm.add_strip_symbol_pass();
         }
 
-        fpm.finalize();
+        pm.run_on(&self.state.root);
+    }
 
+    /// Write the generated module to the output file in the configured output format
+    pub fn write_output(&self) -> Result<(), Diagnostic<FileId>> {
+        let path = &self.state.opts.out_file;
         match self.state.opts.out_type {
             OutputFileType::Object => self.state.target_machine.write_to_file(
                 &self.state.root,
@@ -191,12 +427,41 @@ impl<'ctx, 'llvm> LLVMCodeGenerator<'ctx, 'llvm> {
                 FileType::Assembly,
                 &self.state.opts.out_file,
             ),
-            OutputFileType::LLVMIR => self.state.root.print_to_file(&self.state.opts.out_file),
-            OutputFileType::IR => unreachable!(),
+            OutputFileType::LLVMIR => self.state.root.print_to_file(path),
+            OutputFileType::Bitcode => return self.emit_bitcode(path),
+            OutputFileType::IR | OutputFileType::IRJson | OutputFileType::CfgDot => {
+                unreachable!()
+            }
+        }
+        .map_err(|e| {
+            Diagnostic::error().with_message(format!(
+                "Failed to write output file {}: {}",
+                path.display(),
+                e
+            ))
+        })
+    }
+
+    /// Write the generated module to a file as LLVM bitcode, failing without writing anything
+    /// if the module does not pass verification
+    pub fn emit_bitcode(&self, path: impl AsRef<Path>) -> Result<(), Diagnostic<FileId>> {
+        let path = path.as_ref();
+        self.verify()?;
+        match self.state.root.write_bitcode_to_path(path) {
+            true => Ok(()),
+            false => Err(Diagnostic::error()
+                .with_message(format!("Failed to write bitcode to {}", path.display()))),
         }
-        .unwrap();
+    }
+
+    /// Get the textual LLVM IR of the generated module
+    pub fn emit_ir(&self) -> String {
+        self.state.root.print_to_string().to_string()
+    }
 
-        self.state.root
+    /// Write the textual LLVM IR of the generated module to a file
+    pub fn emit_ir_to_file(&self, path: impl AsRef<Path>) -> Result<(), LLVMString> {
+        self.state.root.print_to_file(path)
     }
 
     /// Translate integer types to LLVM
@@ -210,15 +475,178 @@ impl<'ctx, 'llvm> LLVMCodeGenerator<'ctx, 'llvm> {
             IntegerWidth::Sixteen => ctx.i16_type(),
             IntegerWidth::ThirtyTwo => ctx.i32_type(),
             IntegerWidth::SixtyFour => ctx.i64_type(),
+            IntegerWidth::OneTwentyEight => ctx.i128_type(),
             IntegerWidth::PtrSize => ctx.ptr_sized_int_type(tdata, None),
         }
     }
 
+    /// Create named LLVM structure types for every alias of a structure type, first as opaque
+    /// types so that structures may refer to themselves through pointers, then setting their
+    /// bodies once all of them exist
+    fn gen_named_structs<'c>(
+        ctx: &'llvm Context,
+        target_data: &TargetData,
+        irctx: &'c IrContext,
+    ) -> HashMap<TypeId, StructType<'llvm>> {
+        let mut structs = HashMap::new();
+        for (id, ty) in irctx.types.indices().zip(irctx.types.iter()) {
+            match ty {
+                IrType::Alias { name, ty } if matches!(&irctx[*ty], IrType::Struct(_)) => {
+                    structs.insert(id, ctx.opaque_struct_type(name.as_str()));
+                }
+                //Opaque types are never given a body
+                IrType::Opaque(name) => {
+                    structs.insert(id, ctx.opaque_struct_type(name.as_str()));
+                }
+                _ => (),
+            }
+        }
+
+        let mut done = HashSet::new();
+        for id in structs.keys() {
+            if let IrType::Alias { .. } = &irctx[*id] {
+                Self::gen_struct_body(ctx, target_data, irctx, &structs, *id, &mut done);
+            }
+        }
+
+        structs
+    }
+
+    /// Set the body of a named structure type, first setting the bodies of all named structures
+    /// it contains by value so that their layouts are known
+    fn gen_struct_body<'c>(
+        ctx: &'llvm Context,
+        target_data: &TargetData,
+        irctx: &'c IrContext,
+        structs: &HashMap<TypeId, StructType<'llvm>>,
+        id: TypeId,
+        done: &mut HashSet<TypeId>,
+    ) {
+        if !done.insert(id) {
+            return;
+        }
+
+        let s_ty = match &irctx[irctx.unwrap_alias(id)] {
+            IrType::Struct(s_ty) => s_ty,
+            _ => unreachable!(),
+        };
+
+        let mut contained = vec![];
+        for field in s_ty.fields.iter() {
+            Self::contained_structs(irctx, structs, field.ty, &mut contained);
+        }
+        for contained in contained {
+            Self::gen_struct_body(ctx, target_data, irctx, structs, contained, done);
+        }
+
+        let fields = Self::gen_struct_fields(ctx, target_data, irctx, structs, s_ty);
+        structs[&id].set_body(&fields, s_ty.packed);
+    }
+
+    /// Collect all named structure types that are contained by value in the given type
+    fn contained_structs(
+        irctx: &IrContext,
+        structs: &HashMap<TypeId, StructType<'llvm>>,
+        ty: TypeId,
+        contained: &mut Vec<TypeId>,
+    ) {
+        if structs.contains_key(&ty) {
+            contained.push(ty);
+            return;
+        }
+
+        match &irctx[ty] {
+            IrType::Alias { ty, .. } | IrType::Array(ty, _) => {
+                Self::contained_structs(irctx, structs, *ty, contained)
+            }
+            IrType::Struct(s_ty) => {
+                for field in s_ty.fields.iter() {
+                    Self::contained_structs(irctx, structs, field.ty, contained);
+                }
+            }
+            IrType::Sum(variants) | IrType::Tuple(variants) => {
+                for variant in variants.iter() {
+                    Self::contained_structs(irctx, structs, *variant, contained);
+                }
+            }
+            IrType::Enum(e) => {
+                for payload in e.variants.iter().filter_map(|variant| variant.payload) {
+                    Self::contained_structs(irctx, structs, payload, contained);
+                }
+            }
+            _ => (),
+        }
+    }
+
+    /// Generate the LLVM field types of a structure, inserting a byte array before every field
+    /// with an explicit alignment that pads the field to its requested offset, and a byte array
+    /// after the last field that pads the structure's size to its largest field alignment
+    fn gen_struct_fields<'c>(
+        ctx: &'llvm Context,
+        target_data: &TargetData,
+        irctx: &'c IrContext,
+        structs: &HashMap<TypeId, StructType<'llvm>>,
+        s_ty: &IrStructType,
+    ) -> Vec<BasicTypeEnum<'llvm>> {
+        let align_to = |offset: u64, align: u64| (offset + align - 1) / align * align;
+
+        let mut fields = Vec::with_capacity(s_ty.fields.len());
+        let mut offset = 0;
+        //Alignment LLVM gives the structure, and the alignment including explicit alignments
+        let mut natural_align = 1;
+        let mut align = 1;
+        for field in s_ty.fields.iter() {
+            let ty = Self::gen_type_id(ctx, target_data, irctx, structs, field.ty);
+            let natural = match s_ty.packed {
+                true => 1,
+                false => target_data.get_abi_alignment(&ty) as u64,
+            };
+            natural_align = natural_align.max(natural);
+
+            if let Some(field_align) = field.align {
+                let field_align = natural.max(field_align as u64);
+                let aligned = align_to(offset, field_align);
+                fields.push(ctx.i8_type().array_type((aligned - offset) as u32).into());
+                offset = aligned;
+                align = align.max(field_align);
+            } else {
+                offset = align_to(offset, natural);
+                align = align.max(natural);
+            }
+
+            offset += target_data.get_abi_size(&ty);
+            fields.push(ty);
+        }
+
+        let size = align_to(offset, align);
+        if size != align_to(offset, natural_align) {
+            fields.push(ctx.i8_type().array_type((size - offset) as u32).into());
+        }
+
+        fields
+    }
+
+    /// Generate LLVM IR for the type with the given ID, using the named structure type if the
+    /// type is an alias of a structure
+    fn gen_type_id<'c>(
+        ctx: &'llvm Context,
+        target_data: &TargetData,
+        irctx: &'c IrContext,
+        structs: &HashMap<TypeId, StructType<'llvm>>,
+        ty: TypeId,
+    ) -> BasicTypeEnum<'llvm> {
+        match structs.get(&ty) {
+            Some(named) => (*named).into(),
+            None => Self::gen_type(ctx, target_data, irctx, structs, &irctx[ty]),
+        }
+    }
+
     /// Generate LLVM IR for a single IR type
     pub fn gen_type<'c>(
         ctx: &'llvm Context,
         target_data: &TargetData,
         irctx: &'c IrContext,
+        structs: &HashMap<TypeId, StructType<'llvm>>,
         ty: &IrType,
     ) -> BasicTypeEnum<'llvm> {
         match ty {
@@ -230,19 +658,15 @@ impl<'ctx, 'llvm> LLVMCodeGenerator<'ctx, 'llvm> {
             IrType::Bool => ctx.bool_type().into(),
             IrType::Char => ctx.i32_type().into(),
             IrType::Unit => ctx.i8_type().into(),
-            IrType::Ptr(ty) => Self::gen_type(ctx, target_data, irctx, &irctx[*ty])
+            IrType::Ptr(ty) => Self::gen_type_id(ctx, target_data, irctx, structs, *ty)
                 .ptr_type(AddressSpace::Generic)
                 .into(),
-            IrType::Fun(f) => Self::gen_funtype(ctx, target_data, irctx, f)
+            IrType::Fun(f) => Self::gen_funtype(ctx, target_data, irctx, structs, f)
                 .ptr_type(AddressSpace::Generic)
                 .into(),
             IrType::Struct(s_ty) => {
-                let fields = s_ty
-                    .fields
-                    .iter()
-                    .map(|field| Self::gen_type(ctx, target_data, irctx, &irctx[field.ty]))
-                    .collect::<Vec<_>>();
-                ctx.struct_type(&fields, false).into()
+                let fields = Self::gen_struct_fields(ctx, target_data, irctx, structs, s_ty);
+                ctx.struct_type(&fields, s_ty.packed).into()
             }
             IrType::Sum(variants) => {
                 if variants.is_empty() {
@@ -250,46 +674,571 @@ impl<'ctx, 'llvm> LLVMCodeGenerator<'ctx, 'llvm> {
                 }
                 let variants = variants
                     .iter()
-                    .map(|variant| Self::gen_type(ctx, target_data, irctx, &irctx[*variant]))
+                    .map(|variant| Self::gen_type_id(ctx, target_data, irctx, structs, *variant))
                     .collect::<Vec<_>>();
 
-                let largest_size = variants
+                let payload = Self::gen_payload_type(ctx, target_data, &variants);
+                let discrim = Self::gen_discrim_type(ctx, variants.len());
+                ctx.struct_type(&[discrim.into(), payload.into()], false)
+                    .into()
+            }
+            IrType::Tuple(elements) => {
+                let elements = elements
                     .iter()
-                    .map(|ty| target_data.get_store_size(ty))
-                    .max()
-                    .unwrap();
-
-                ctx.struct_type(
-                    &[
-                        ctx.i8_type().into(),
-                        ctx.i8_type().array_type(largest_size as u32).into(),
-                    ],
-                    false,
-                )
-                .into()
+                    .map(|element| Self::gen_type_id(ctx, target_data, irctx, structs, *element))
+                    .collect::<Vec<_>>();
+                ctx.struct_type(&elements, false).into()
             }
-            IrType::Array(ty, sz) => Self::gen_type(ctx, target_data, irctx, &irctx[*ty])
+            IrType::Enum(e) => {
+                let tag = Self::gen_inttype(ctx, target_data, &e.tag_type());
+                if !e.has_payload() {
+                    return tag.into();
+                }
+
+                let payloads = e
+                    .variants
+                    .iter()
+                    .filter_map(|variant| variant.payload)
+                    .map(|payload| Self::gen_type_id(ctx, target_data, irctx, structs, payload))
+                    .collect::<Vec<_>>();
+                let payload = Self::gen_payload_type(ctx, target_data, &payloads);
+                ctx.struct_type(&[tag.into(), payload.into()], false).into()
+            }
+            IrType::Array(ty, sz) => Self::gen_type_id(ctx, target_data, irctx, structs, *ty)
                 .array_type(*sz as u32)
                 .into(),
-            IrType::Alias { ty, .. } => Self::gen_type(ctx, target_data, irctx, &irctx[*ty]),
+            IrType::Slice(ty) => {
+                let ptr = Self::gen_type_id(ctx, target_data, irctx, structs, *ty)
+                    .ptr_type(AddressSpace::Generic);
+                let len = Self::gen_inttype(
+                    ctx,
+                    target_data,
+                    &IrIntegerType {
+                        signed: false,
+                        width: IntegerWidth::PtrSize,
+                    },
+                );
+                ctx.struct_type(&[ptr.into(), len.into()], false).into()
+            }
+            IrType::Alias { ty, .. } => Self::gen_type_id(ctx, target_data, irctx, structs, *ty),
+            IrType::Opaque(name) => ctx.opaque_struct_type(name.as_str()).into(),
             IrType::Invalid => ctx.i8_type().into(),
         }
     }
 
-    /// Generate the LLVM IR signature for the given IR function signature
+    /// Generate the payload of a tagged union that can hold a value of any of the given types
+    fn gen_payload_type(
+        ctx: &'llvm Context,
+        target_data: &TargetData,
+        variants: &[BasicTypeEnum<'llvm>],
+    ) -> StructType<'llvm> {
+        let largest_size = variants
+            .iter()
+            .map(|ty| target_data.get_abi_size(ty))
+            .max()
+            .unwrap();
+
+        //The payload begins with the most strictly aligned variant so that every variant is
+        //properly aligned when the payload is cast to a pointer to it, followed by enough bytes
+        //to hold the largest variant
+        let most_aligned = *variants
+            .iter()
+            .max_by_key(|ty| target_data.get_abi_alignment(*ty))
+            .unwrap();
+        let padding = largest_size - target_data.get_abi_size(&most_aligned);
+        ctx.struct_type(
+            &[
+                most_aligned,
+                ctx.i8_type().array_type(padding as u32).into(),
+            ],
+            false,
+        )
+    }
+
+    /// Generate the LLVM IR signature for the given IR function signature, functions returning
+    /// the unit type are given a `void` return type
     fn gen_funtype<'c>(
         ctx: &'llvm Context,
         target_data: &TargetData,
         irctx: &'c IrContext,
+        structs: &HashMap<TypeId, StructType<'llvm>>,
         ty: &FunType,
     ) -> FunctionType<'llvm> {
-        let return_ty = Self::gen_type(ctx, target_data, irctx, &irctx[ty.return_ty]);
         let params = ty
             .params
             .iter()
-            .map(|(ty, _)| Self::gen_type(ctx, target_data, irctx, &irctx[*ty]).into())
+            .map(|(ty, _)| Self::gen_type_id(ctx, target_data, irctx, structs, *ty).into())
             .collect::<Vec<_>>();
 
+        if irctx.unwrap_alias(ty.return_ty) == IrContext::UNIT {
+            return ctx.void_type().fn_type(&params, false);
+        }
+
+        let return_ty = Self::gen_type_id(ctx, target_data, irctx, structs, ty.return_ty);
         return_ty.fn_type(&params, false)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::{ir::lower::testing::lower_files, CompileOpts, OutputFileType};
+
+    use super::testing::{
+        fun_ir, gen_ir, gen_lowered_ir, gen_modules_ir, gen_output, run, run_lowered, run_modules,
+    };
+
+    const MAIN: &str = "fun main() -> i32 {
+    return a:value() + b:value()
+}";
+
+    const SAME_NAMED: [(&str, &str); 2] = [
+        (
+            "a",
+            "fun init() -> i32 { return 1 }\npub fun value() -> i32 { return init() }",
+        ),
+        (
+            "b",
+            "fun init() -> i32 { return 20 }\npub fun value() -> i32 { return init() * 2 }",
+        ),
+    ];
+
+    #[test]
+    fn same_named_private_functions_get_separate_definitions() {
+        let ir = gen_modules_ir(MAIN, &SAME_NAMED);
+        assert!(fun_ir(&ir, "_S1a4initE").contains("ret i32 1\n"), "{}", ir);
+        assert!(fun_ir(&ir, "_S1b4initE").contains("ret i32 20\n"), "{}", ir);
+        assert!(fun_ir(&ir, "_S1a5valueE").contains("call i32 @_S1a4initE()"));
+        assert!(fun_ir(&ir, "_S1b5valueE").contains("call i32 @_S1b4initE()"));
+        assert!(!ir.contains("@init("), "{}", ir);
+
+        assert_eq!(run_modules(MAIN, &SAME_NAMED), 41);
+    }
+
+    #[test]
+    fn calls_to_functions_in_other_files_resolve_in_one_module() {
+        let files = [
+            "fun main() -> i32 { return helper(20) + 1 }",
+            "fun helper(i32 a) -> i32 { return a * 2 }",
+        ];
+        let ir = gen_lowered_ir(
+            lower_files(&files),
+            CompileOpts::testing(OutputFileType::LLVMIR, "test.ll"),
+        );
+        assert!(
+            fun_ir(&ir, "_S4mainE").contains("call i32 @_S6helperEi(i32 20)"),
+            "{}",
+            ir
+        );
+        assert!(fun_ir(&ir, "_S6helperEi").contains("mul"), "{}", ir);
+        assert!(!ir.contains("declare i32 @_S6helperEi"), "{}", ir);
+
+        assert_eq!(run_lowered(lower_files(&files)), 41);
+    }
+
+    #[test]
+    fn parameters_are_stored_in_addressable_allocas() {
+        let src = "fun set(*i32 p) {
+    let *p = *p + 1
+}
+
+fun bump(mut i32 a) -> i32 {
+    a = a * 2
+    set(&a)
+    return a
+}
+
+fun main() -> i32 {
+    return bump(20)
+}";
+        let ir = gen_ir(src);
+        let bump = fun_ir(&ir, "_S4bumpEi");
+        assert!(bump.contains("%a = alloca i32"), "{}", bump);
+        assert!(bump.contains("store i32 %0, i32* %a"), "{}", bump);
+        assert!(bump.contains("call void @_S3setEPi(i32* %a)"), "{}", bump);
+
+        assert_eq!(run(src), 41);
+    }
+
+    #[test]
+    fn functions_are_called_through_function_pointer_fields() {
+        let src = "type Handler = { fun(i32, *u8) -> i32 callback, fun(i32) -> () done, i32 base }
+
+fun on_event(i32 code, *u8 data) -> i32 {
+    return code + 1
+}
+
+fun finish(i32 code) {}
+
+fun dispatch(Handler h) -> i32 {
+    let callback = h.callback
+    return callback(h.base, \"event\")
+}
+
+fun main() -> i32 {
+    let [Handler] h = #Handler { callback = on_event, done = finish, base = 41 }
+    return dispatch(h)
+}";
+        let ir = gen_ir(src);
+        assert!(
+            ir.contains("%Handler = type { i32 (i32, i8*)*, void (i32)*, i32 }"),
+            "{}",
+            ir
+        );
+        assert!(
+            fun_ir(&ir, "_S4mainE").contains("@_S8on_eventEiPh"),
+            "{}",
+            ir
+        );
+        assert_eq!(run(src), 42);
+    }
+
+    #[test]
+    fn linked_lists_are_built_and_traversed() {
+        let src = "type Node = { *Node next, i32 value }
+
+fun sum(*Node head, i32 len) -> i32 {
+    mut total = 0
+    mut node = head
+    mut i = 0
+    loop {
+        if i == len { break }
+        total = total + node.value
+        node = node.next
+        i = i + 1
+    }
+    return total
+}
+
+fun main() -> i32 {
+    mut [Node] c = #Node { next = $*Node 0usize, value = 30 }
+    mut [Node] b = #Node { next = &c, value = 20 }
+    mut [Node] a = #Node { next = &b, value = 10 }
+    return sum(&a, 3)
+}";
+        let ir = gen_ir(src);
+        assert!(ir.contains("%Node = type { %Node*, i32 }"), "{}", ir);
+        assert!(
+            ir.contains("define i32 @_S3sumEPN4Nodei(%Node* %0, i32 %1)"),
+            "{}",
+            ir
+        );
+        assert_eq!(run(src), 60);
+    }
+
+    #[test]
+    fn opaque_types_defined_in_another_file_get_a_body() {
+        let files = [
+            "type Ctx
+
+fun bump(*Ctx c) -> i32 {
+    return ctx_value(c) + 1
+}",
+            "type Ctx = { i32 value }
+
+fun ctx_value(*Ctx c) -> i32 {
+    return c.value
+}
+
+fun main() -> i32 {
+    mut [Ctx] c = #Ctx { value = 41 }
+    return bump(&c)
+}",
+        ];
+        let ir = gen_lowered_ir(
+            lower_files(&files),
+            CompileOpts::testing(OutputFileType::LLVMIR, "test.ll"),
+        );
+        assert!(ir.contains("%Ctx = type { i32 }"), "{}", ir);
+        assert!(!ir.contains("opaque"), "{}", ir);
+        assert!(
+            ir.contains("define i32 @_S4bumpEPN3Ctx(%Ctx* %0)"),
+            "{}",
+            ir
+        );
+        assert_eq!(run_lowered(lower_files(&files)), 42);
+    }
+
+    const GREET: &str = "fun ext puts(*u8 s) -> i32
+
+fun add(i32 a, i32 b) -> i32 {
+    return a + b
+}
+
+fun main() -> i32 {
+    puts(\"hello\")
+    return add(1, 2)
+}";
+
+    #[test]
+    fn emitted_ir_contains_function_definitions() {
+        let ir = gen_ir(GREET);
+        assert!(ir.contains("declare i32 @puts(i8*)"), "{}", ir);
+        assert!(
+            ir.contains("define i32 @_S3addEii(i32 %0, i32 %1)"),
+            "{}",
+            ir
+        );
+        assert!(ir.contains("define i32 @_S4mainE()"), "{}", ir);
+        assert!(ir.contains("define i32 @main(i32 %0, i8** %1)"), "{}", ir);
+        assert!(ir.starts_with("; ModuleID = 'test'"), "{}", ir);
+
+        //The module is named after the output file
+        let written = gen_output(GREET, OutputFileType::LLVMIR, |_| ())
+            .unwrap_or_else(|e| panic!("{}", e.message));
+        let written = String::from_utf8(written).unwrap();
+        assert!(
+            written.starts_with("; ModuleID = 'spark-llvm-test-"),
+            "{}",
+            written
+        );
+        assert!(
+            written.contains("define i32 @_S3addEii(i32 %0, i32 %1)"),
+            "{}",
+            written
+        );
+    }
+
+    #[test]
+    fn bitcode_round_trips_with_the_same_functions() {
+        let mut lowered = lower(GREET);
+        let path = temp_path("bc");
+        let opts = CompileOpts::testing(OutputFileType::Bitcode, path.clone());
+
+        let ctx = Context::create();
+        let module = LLVMCodeGenerator::new(&mut lowered.ctx, &ctx, &lowered.files, opts)
+            .and_then(|gen| gen.gen())
+            .unwrap_or_else(|e| panic!("failed to write bitcode: {}", e.message));
+        let parsed = Module::parse_bitcode_from_path(&path, &ctx);
+        std::fs::remove_file(&path).ok();
+        let parsed = parsed.unwrap_or_else(|e| panic!("failed to parse bitcode: {}", e));
+
+        let names = |module: &Module| {
+            module
+                .get_functions()
+                .map(|fun| fun.get_name().to_string_lossy().into_owned())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(names(&parsed), names(&module));
+        assert!(names(&parsed).contains(&"_S3addEii".to_owned()));
+        assert!(parsed
+            .get_function("puts")
+            .unwrap()
+            .get_first_basic_block()
+            .is_none());
+    }
+
+    #[test]
+    fn object_files_are_written_for_the_target() {
+        let object = gen_output(GREET, OutputFileType::Object, |opts| {
+            opts.target.triple = Some("x86_64-unknown-linux-gnu".to_owned())
+        })
+        .unwrap_or_else(|e| panic!("failed to write object: {}", e.message));
+        assert_eq!(&object[..4], b"\x7fELF");
+    }
+
+    #[test]
+    fn output_failures_are_diagnostics() {
+        let err = gen_output(GREET, OutputFileType::Object, |opts| {
+            opts.out_file = temp_path("missing").join("test.o")
+        })
+        .unwrap_err();
+        assert!(
+            err.message.starts_with("Failed to write output file "),
+            "{}",
+            err.message
+        );
+
+        let err = gen_output(GREET, OutputFileType::Object, |opts| {
+            opts.target.triple = Some("nonsense-unknown-none".to_owned())
+        })
+        .unwrap_err();
+        assert!(
+            err.message
+                .starts_with("Failed to find target for triple nonsense-unknown-none: "),
+            "{}",
+            err.message
+        );
+    }
+
+    #[test]
+    fn assembly_is_written_in_the_selected_syntax() {
+        let asm = |syntax| {
+            let asm = gen_output(GREET, OutputFileType::Assembly, |opts| {
+                opts.target.triple = Some("x86_64-unknown-linux-gnu".to_owned());
+                opts.asm_syntax = syntax;
+            })
+            .unwrap_or_else(|e| panic!("failed to write assembly: {}", e.message));
+            String::from_utf8(asm).unwrap()
+        };
+
+        //AT&T syntax is checked first as Intel syntax stays selected once it has been used
+        let att = asm(AsmSyntax::ATT);
+        assert!(att.contains("_S3addEii:"), "{}", att);
+        assert!(att.contains("%edi"), "{}", att);
+        assert!(!att.contains(".intel_syntax"), "{}", att);
+
+        let intel = asm(AsmSyntax::Intel);
+        assert!(intel.contains("_S3addEii:"), "{}", intel);
+        assert!(intel.contains(".intel_syntax noprefix"), "{}", intel);
+        assert!(intel.contains("dword ptr"), "{}", intel);
+        assert!(!intel.contains("%edi"), "{}", intel);
+    }
+
+    #[test]
+    fn optimization_promotes_locals_to_registers() {
+        let src = "fun accumulate(i32 n) -> i32 {
+    mut total = 0
+    mut i = 0
+    loop {
+        if i == n { break }
+        total = total + i
+        i = i + 1
+    }
+    return total
+}
+
+fun main() -> i32 {
+    return accumulate(10)
+}";
+        let ir = gen_ir(src);
+        assert!(fun_ir(&ir, "_S10accumulateEi").contains("alloca"), "{}", ir);
+
+        for opt_lvl in [
+            OutputOptimizationLevel::Basic,
+            OutputOptimizationLevel::Medium,
+            OutputOptimizationLevel::Release,
+            OutputOptimizationLevel::Size,
+        ] {
+            let ir = gen_ir_with(src, |opts| opts.opt_lvl = opt_lvl);
+            let accumulate = fun_ir(&ir, "_S10accumulateEi");
+            assert!(
+                !accumulate.contains("alloca"),
+                "{:?}\n{}",
+                opt_lvl,
+                accumulate
+            );
+        }
+        assert_eq!(run(src), 45);
+    }
+
+    #[test]
+    fn verifier_failures_point_at_the_invalid_function() {
+        let verify_broken = |dump_invalid_ir| {
+            let mut lowered = lower(GREET);
+            let mut opts = CompileOpts::testing(OutputFileType::Object, temp_path("o"));
+            opts.dump_invalid_ir = dump_invalid_ir;
+            let dump_path = opts.out_file.with_extension("invalid.ll");
+
+            let ctx = Context::create();
+            let mut gen = LLVMCodeGenerator::new(&mut lowered.ctx, &ctx, &lowered.files, opts)
+                .unwrap_or_else(|e| panic!("failed to create code generator: {}", e.message));
+            gen.gen_module()
+                .unwrap_or_else(|e| panic!("generated invalid LLVM IR: {}", e.message));
+
+            //A block without a terminator is invalid
+            let add = gen.state.root.get_function("_S3addEii").unwrap();
+            ctx.append_basic_block(add, "broken");
+            let err = gen.verify().unwrap_err();
+            drop(gen);
+
+            let fun = &lowered.ctx[lowered.fun("add")];
+            let label = Label::primary(fun.file, fun.span);
+            assert_eq!(err.labels.len(), 1);
+            assert_eq!(
+                (err.labels[0].file_id, &err.labels[0].range),
+                (label.file_id, &label.range)
+            );
+            (err, dump_path)
+        };
+
+        let (err, dump_path) = verify_broken(false);
+        assert_eq!(
+            err.message,
+            "ICE: Generated invalid LLVM IR for function add"
+        );
+        assert!(
+            err.notes[0].starts_with("LLVM verifier output:\n"),
+            "{:?}",
+            err.notes
+        );
+        assert!(
+            err.notes[0].contains("does not have terminator"),
+            "{:?}",
+            err.notes
+        );
+        assert_eq!(
+            err.notes[1],
+            "Pass --dump-invalid-ir to write the invalid LLVM IR to a file"
+        );
+        assert!(!dump_path.exists());
+
+        let (err, dump_path) = verify_broken(true);
+        assert_eq!(
+            err.notes[1],
+            format!("Invalid LLVM IR written to {}", dump_path.display())
+        );
+        let dumped = std::fs::read_to_string(&dump_path).unwrap();
+        std::fs::remove_file(&dump_path).ok();
+        assert!(dumped.contains("broken:"), "{}", dumped);
+    }
+
+    const WASM_ADD: &str = "fun ext puts(*u8 s) -> i32
+
+fun export add(i32 a, i32 b) -> i32 {
+    puts(\"adding\")
+    return a + b
+}";
+
+    #[test]
+    fn wasm_exports_are_marked_for_the_linker() {
+        let wasm32 =
+            |opts: &mut CompileOpts| opts.target.triple = Some("wasm32-unknown-unknown".to_owned());
+
+        let ir = gen_ir_with(WASM_ADD, wasm32);
+        assert!(
+            ir.contains("target triple = \"wasm32-unknown-unknown\""),
+            "{}",
+            ir
+        );
+        assert!(
+            fun_ir(&ir, "add").starts_with("define i32 @add(i32 %0, i32 %1) #"),
+            "{}",
+            ir
+        );
+        assert!(ir.contains("\"wasm-export-name\"=\"add\""), "{}", ir);
+        assert!(!ir.contains("unnamed_addr"), "{}", ir);
+
+        let object = gen_output(WASM_ADD, OutputFileType::Object, wasm32)
+            .unwrap_or_else(|e| panic!("failed to write object: {}", e.message));
+        assert_eq!(&object[..8], b"\0asm\x01\0\0\0");
+    }
+
+    #[test]
+    fn chained_aliases_use_the_aliased_struct_type() {
+        let src = "type Point = { i32 x, i32 y }
+type Coord = Point
+type Position = Coord
+
+fun x_of(*Position p) -> i32 {
+    return p.x
+}
+
+fun main() -> i32 {
+    mut [Position] p = #Position { x = 40, y = 2 }
+    return x_of(&p) + p.y
+}";
+        let ir = gen_ir(src);
+        assert!(ir.contains("%Point = type { i32, i32 }"), "{}", ir);
+        assert!(
+            !ir.contains("%Coord") && !ir.contains("%Position"),
+            "{}",
+            ir
+        );
+        assert!(
+            ir.contains("define i32 @_S4x_ofEPN8Position(%Point* %0)"),
+            "{}",
+            ir
+        );
+        assert_eq!(run(src), 42);
+    }
+}
@@ -1,9 +1,24 @@
-use inkwell::values::FunctionValue;
+use std::convert::TryFrom;
 
-use crate::ir::{types::IrType, BBId, IrContext, IrStmt, IrStmtKind, IrTerminator};
+use inkwell::{
+    basic_block::BasicBlock,
+    module::Linkage,
+    types::{BasicType, BasicTypeEnum, FunctionType},
+    values::{BasicValue, BasicValueEnum, CallableValue, FunctionValue, PointerValue},
+    AddressSpace,
+};
+
+use crate::{
+    ir::{BBId, IrBranch, IrContext, IrStmt, IrStmtKind, IrTerminator, VarId},
+    util::loc::Span,
+};
 
 use super::LLVMCodeGeneratorState;
 
+/// Size in bytes above which zero-initialized variables are filled with a memset call instead of
+/// a single store
+const MEMSET_THRESHOLD: u64 = 64;
+
 impl<'llvm> LLVMCodeGeneratorState<'llvm> {
     /// Translate IR to LLVM bytecode for a single basic block
     pub fn gen_bb(&mut self, irctx: &IrContext, bb: BBId, fun: FunctionValue<'llvm>) {
@@ -16,90 +31,95 @@ impl<'llvm> LLVMCodeGeneratorState<'llvm> {
 
         self.build.position_at_end(llvm_bb);
         for stmt in irctx[bb].stmts.iter() {
+            self.debug_loc(stmt.span);
             self.gen_stmt(irctx, stmt);
         }
 
+        if let Some(span) = irctx[bb].terminator_span {
+            self.debug_loc(span);
+        }
         match &irctx[bb].terminator {
             IrTerminator::Return(v) => {
+                self.debug_loc(v.span);
                 let return_val = self.gen_expr(irctx, &v);
-                self.build.build_return(Some(&return_val));
-            }
-            IrTerminator::Jmp(bb) => match self.llvm_bbs.get(bb) {
-                Some(new_bb) => {
-                    self.build.build_unconditional_branch(*new_bb);
+                if irctx.unwrap_alias(v.ty) == IrContext::UNIT {
+                    self.build.build_return(None);
+                } else {
+                    self.build.build_return(Some(&return_val));
                 }
-                None => {
-                    let new_bb = self.ctx.append_basic_block(fun, "bb");
-                    self.llvm_bbs.insert(*bb, new_bb);
-                    self.build.build_unconditional_branch(new_bb);
-                    self.gen_bb(irctx, *bb, fun);
+            }
+            IrTerminator::Jmp(to) => {
+                let args = self.gen_branch_args(irctx, to);
+                let (llvm_bb, new) = self.jmp_target(irctx, to, args, fun, "bb");
+                self.build.build_unconditional_branch(llvm_bb);
+                if new {
+                    self.gen_bb(irctx, to.bb, fun);
                 }
-            },
+            }
             IrTerminator::JmpIf {
                 condition,
                 if_true,
                 if_false,
             } => {
-                let if_true_llvm = self.ctx.append_basic_block(fun, "if_t");
-                self.llvm_bbs.insert(*if_true, if_true_llvm);
-                let if_false_llvm = self.ctx.append_basic_block(fun, "if_f");
-                self.llvm_bbs.insert(*if_false, if_false_llvm);
                 let condition = self.gen_expr(irctx, condition).into_int_value();
+                let true_args = self.gen_branch_args(irctx, if_true);
+                let false_args = self.gen_branch_args(irctx, if_false);
+                let (if_true_llvm, new_true) =
+                    self.jmp_target(irctx, if_true, true_args, fun, "if_t");
+                let (if_false_llvm, new_false) =
+                    self.jmp_target(irctx, if_false, false_args, fun, "if_f");
                 self.build
                     .build_conditional_branch(condition, if_true_llvm, if_false_llvm);
-                self.gen_bb(irctx, *if_true, fun);
-                self.gen_bb(irctx, *if_false, fun);
+                if new_true {
+                    self.gen_bb(irctx, if_true.bb, fun);
+                }
+                if new_false {
+                    self.gen_bb(irctx, if_false.bb, fun);
+                }
             }
             IrTerminator::JmpMatch {
                 variant,
                 discriminants,
                 default_jmp,
             } => {
-                let variant_ty = variant.ty;
-                let sum_ty = if let IrType::Sum(sum) = &irctx[irctx.unwrap_alias(variant.ty)] {
-                    sum
-                } else {
-                    unreachable!("{}", irctx.typename(variant.ty))
-                };
-                let variant = self.gen_lval(irctx, variant);
-                let discrim_ptr = self
-                    .build
-                    .build_struct_gep(variant, 0, "sum_discrim_ptr")
-                    .unwrap();
-                let discrim = self
-                    .build
-                    .build_load(discrim_ptr, "sum_discrim")
-                    .into_int_value();
+                let matched_ty = variant.ty;
+                let discrim = self.gen_match_tag(irctx, variant);
 
-                let after_bb = self.ctx.append_basic_block(fun, "match_default");
-                self.llvm_bbs.insert(*default_jmp, after_bb);
-                self.gen_bb(irctx, *default_jmp, fun);
+                let default_args = self.gen_branch_args(irctx, default_jmp);
+                let arm_args = discriminants
+                    .iter()
+                    .map(|(_, branch)| self.gen_branch_args(irctx, branch))
+                    .collect::<Vec<_>>();
 
-                let discriminants = discriminants
+                let (after_bb, new_default) =
+                    self.jmp_target(irctx, default_jmp, default_args, fun, "match_default");
+                let arms = discriminants
                     .iter()
-                    .map(|(ty, bb)| {
-                        let idx = sum_ty
-                            .iter()
-                            .enumerate()
-                            .find_map(|(idx, variant)| if ty == variant { Some(idx) } else { None })
-                            .unwrap_or_else(|| {
-                                panic!(
-                                    "type {} is not in {}",
-                                    irctx.typename(*ty),
-                                    irctx.typename(variant_ty)
-                                )
-                            });
-                        let arm = self.ctx.append_basic_block(fun, "matcharm");
-                        self.llvm_bbs.insert(*bb, arm);
-                        self.gen_bb(irctx, *bb, fun);
-
-                        (self.ctx.i8_type().const_int(idx as u64, false), arm)
+                    .zip(arm_args)
+                    .map(|((discriminant, branch), args)| {
+                        let discrim = self.gen_discrim(irctx, matched_ty, *discriminant);
+                        let (arm, new) = self.jmp_target(irctx, branch, args, fun, "matcharm");
+                        (discrim, arm, new)
                     })
                     .collect::<Vec<_>>();
 
-                self.build.position_at_end(llvm_bb);
-                self.build.build_switch(discrim, after_bb, &discriminants);
-                self.build.position_at_end(after_bb);
+                let cases = arms
+                    .iter()
+                    .map(|(discrim, arm, _)| (*discrim, *arm))
+                    .collect::<Vec<_>>();
+                self.build.build_switch(discrim, after_bb, &cases);
+
+                if new_default {
+                    self.gen_bb(irctx, default_jmp.bb, fun);
+                }
+                for ((_, branch), (_, _, new)) in discriminants.iter().zip(arms) {
+                    if new {
+                        self.gen_bb(irctx, branch.bb, fun);
+                    }
+                }
+            }
+            IrTerminator::Unreachable => {
+                self.build.build_unreachable();
             }
             IrTerminator::Invalid => {
                 for inst in irctx[bb].stmts.iter() {
@@ -110,25 +130,295 @@ impl<'llvm> LLVMCodeGeneratorState<'llvm> {
         }
     }
 
+    /// Generate the values that a jump passes to its target's parameters
+    fn gen_branch_args(
+        &mut self,
+        irctx: &IrContext,
+        branch: &IrBranch,
+    ) -> Vec<BasicValueEnum<'llvm>> {
+        branch
+            .args
+            .iter()
+            .map(|arg| self.gen_expr(irctx, arg))
+            .collect()
+    }
+
+    /// Get the LLVM block of a jump's target, creating it and the phi nodes of its parameters if
+    /// the target has not been reached yet, and add the generated arguments of the jump as
+    /// incoming values from the current block.
+    /// Returns `true` if the block was created and its body must still be generated, so that
+    /// blocks reached from multiple jumps are only generated once
+    fn jmp_target(
+        &mut self,
+        irctx: &IrContext,
+        branch: &IrBranch,
+        args: Vec<BasicValueEnum<'llvm>>,
+        fun: FunctionValue<'llvm>,
+        name: &str,
+    ) -> (BasicBlock<'llvm>, bool) {
+        let pred = self.build.get_insert_block().unwrap();
+        let (llvm_bb, new) = match self.llvm_bbs.get(&branch.bb) {
+            Some(llvm_bb) => (*llvm_bb, false),
+            None => {
+                let llvm_bb = self.ctx.append_basic_block(fun, name);
+                self.llvm_bbs.insert(branch.bb, llvm_bb);
+
+                self.build.position_at_end(llvm_bb);
+                let phis = irctx[branch.bb]
+                    .params
+                    .iter()
+                    .map(|ty| {
+                        self.build
+                            .build_phi(*self.llvm_types.get_secondary(*ty), "param")
+                    })
+                    .collect();
+                self.llvm_params.insert(branch.bb, phis);
+                self.build.position_at_end(pred);
+
+                (llvm_bb, true)
+            }
+        };
+
+        for (phi, arg) in self.llvm_params[&branch.bb].iter().zip(args.iter()) {
+            phi.add_incoming(&[(arg as &dyn BasicValue<'llvm>, pred)]);
+        }
+
+        (llvm_bb, new)
+    }
+
+    /// Get the 1-based line and column numbers of an offset in the current source file
+    pub fn line_col(&self, offset: usize) -> (u32, u32) {
+        let lines = match self.current_file {
+            Some(file) => &self.sources[&file].1,
+            None => return (0, 0),
+        };
+        let line = match lines.binary_search(&offset) {
+            Ok(line) => line,
+            Err(line) => line.saturating_sub(1),
+        };
+        let col = offset - lines.get(line).copied().unwrap_or(0);
+        (line as u32 + 1, col as u32 + 1)
+    }
+
+    /// Format the location of a span in the current source file for runtime error messages
+    pub fn source_loc(&self, span: Span) -> String {
+        let (line, col) = self.line_col(span.from);
+        match self.current_file {
+            Some(file) => format!("{}:{}:{}", self.sources[&file].0, line, col),
+            None => format!("{}:{}", line, col),
+        }
+    }
+
+    /// Print a message to standard error and abort the program, leaving the builder positioned
+    /// in an unreachable state
+    pub fn gen_abort(&mut self, msg: &str) {
+        let size_ty = self
+            .llvm_types
+            .get_secondary(IrContext::USIZE)
+            .into_int_type();
+        let write = self.libc_fun(
+            "write",
+            size_ty.fn_type(
+                &[
+                    self.ctx.i32_type().into(),
+                    self.ctx.i8_type().ptr_type(AddressSpace::Generic).into(),
+                    size_ty.into(),
+                ],
+                false,
+            ),
+        );
+        let abort = self.libc_fun("abort", self.ctx.void_type().fn_type(&[], false));
+
+        let msg = format!("{}\n", msg);
+        let msg_ptr = self.gen_string_lit(&msg, "abort_msg");
+        self.build.build_call(
+            write,
+            &[
+                self.ctx.i32_type().const_int(2, false).into(),
+                msg_ptr.into(),
+                size_ty.const_int(msg.len() as u64, false).into(),
+            ],
+            "abort_write",
+        );
+        self.build.build_call(abort, &[], "abort");
+        self.build.build_unreachable();
+    }
+
+    /// Get a C library function of the given type, declaring it if the module has no function of
+    /// the same name. A function of the same name declared with another signature, like an
+    /// external function declared in spark code, is called through a pointer cast to the type
+    fn libc_fun(&self, name: &str, ty: FunctionType<'llvm>) -> CallableValue<'llvm> {
+        let fun = self
+            .root
+            .get_function(name)
+            .unwrap_or_else(|| self.root.add_function(name, ty, Some(Linkage::External)));
+        if fun.get_type() == ty {
+            return fun.into();
+        }
+
+        let ptr = self
+            .build
+            .build_bitcast(
+                fun.as_global_value().as_pointer_value(),
+                ty.ptr_type(AddressSpace::Generic),
+                name,
+            )
+            .into_pointer_value();
+        CallableValue::try_from(ptr).unwrap()
+    }
+
+    /// Create a global constant containing a null-terminated string and get a pointer to its
+    /// first character
+    pub fn gen_string_lit(&self, s: &str, name: &str) -> PointerValue<'llvm> {
+        let glob = self.build.build_global_string_ptr(s, name);
+        //Strings with unnamed addresses are placed in mergeable sections that wasm-ld cannot link
+        if self.wasm {
+            glob.set_unnamed_addr(false);
+        }
+        glob.as_pointer_value()
+    }
+
+    /// Create a stack allocation at the start of the current function's entry block, so that
+    /// variables declared in loops do not grow the stack on every iteration
+    pub fn entry_alloca(&self, ty: BasicTypeEnum<'llvm>, name: &str) -> PointerValue<'llvm> {
+        let entry = self
+            .build
+            .get_insert_block()
+            .and_then(|bb| bb.get_parent())
+            .and_then(|fun| fun.get_first_basic_block())
+            .expect("ICE: Creating a stack allocation outside of a function");
+
+        let build = self.ctx.create_builder();
+        match entry.get_first_instruction() {
+            Some(first) => build.position_before(&first),
+            None => build.position_at_end(entry),
+        }
+        build.build_alloca(ty, name)
+    }
+
+    /// Call a lifetime intrinsic on the allocation of a variable, allowing LLVM to reuse the stack
+    /// space of variables that are never live at the same time
+    fn gen_lifetime(&mut self, irctx: &IrContext, var: VarId, intrinsic: &str) {
+        let ptr = match self.llvm_vars.get_secondary(var) {
+            Some(ptr) => *ptr,
+            None => return,
+        };
+
+        let i64_ty = self.ctx.i64_type();
+        let i8_ptr_ty = self.ctx.i8_type().ptr_type(AddressSpace::Generic);
+        let lifetime = self.root.get_function(intrinsic).unwrap_or_else(|| {
+            self.root.add_function(
+                intrinsic,
+                self.ctx
+                    .void_type()
+                    .fn_type(&[i64_ty.into(), i8_ptr_ty.into()], false),
+                None,
+            )
+        });
+
+        let size = self
+            .target_data
+            .get_store_size(self.llvm_types.get_secondary(irctx[var].ty));
+        let ptr = self.build.build_pointer_cast(ptr, i8_ptr_ty, "");
+        self.build.build_call(
+            lifetime,
+            &[i64_ty.const_int(size, false).into(), ptr.into()],
+            "",
+        );
+    }
+
     /// Translate one IR statement to LLVM bytecode instructions
     pub fn gen_stmt(&mut self, irctx: &IrContext, stmt: &IrStmt) {
         match &stmt.kind {
             IrStmtKind::VarLive(v) => {
                 let var = &irctx[*v];
-                let pv = self
-                    .build
-                    .build_alloca(*self.llvm_types.get_secondary(var.ty), var.name.as_str());
+                let pv =
+                    self.entry_alloca(*self.llvm_types.get_secondary(var.ty), var.name.as_str());
+                if let Some(align) = var.align.max(irctx.explicit_align(var.ty)) {
+                    pv.as_instruction().unwrap().set_alignment(align).unwrap();
+                }
                 *self.llvm_vars.get_secondary_mut(*v) = Some(pv);
+                self.debug_var(irctx, *v, pv, stmt.span, None);
+                self.gen_lifetime(irctx, *v, "llvm.lifetime.start.p0i8");
+            }
+            IrStmtKind::VarDead(v) => self.gen_lifetime(irctx, *v, "llvm.lifetime.end.p0i8"),
+            IrStmtKind::Zero(v) => {
+                let ptr = self.llvm_vars.get_secondary(*v).unwrap();
+                let ty = *self.llvm_types.get_secondary(irctx[*v].ty);
+                let size = self.target_data.get_store_size(&ty);
+                if size > MEMSET_THRESHOLD {
+                    let align = irctx[*v]
+                        .align
+                        .max(irctx.explicit_align(irctx[*v].ty))
+                        .unwrap_or_else(|| self.type_align(irctx, irctx[*v].ty));
+                    let size = self
+                        .llvm_types
+                        .get_secondary(IrContext::USIZE)
+                        .into_int_type()
+                        .const_int(size, false);
+                    self.build
+                        .build_memset(ptr, align, self.ctx.i8_type().const_zero(), size)
+                        .unwrap();
+                } else {
+                    self.build.build_store(ptr, ty.const_zero());
+                }
             }
             IrStmtKind::Store { var, val } => {
                 let alloca = self.llvm_vars.get_secondary(*var).unwrap();
                 let val = self.gen_expr(irctx, val);
                 self.build.build_store(alloca, val);
             }
-            IrStmtKind::Write { ptr, val } => {
-                let ptr = self.gen_lval(irctx, ptr);
+            IrStmtKind::StoreMem { addr, val } => {
+                let addr = self.gen_expr(irctx, addr).into_pointer_value();
                 let val = self.gen_expr(irctx, val);
-                self.build.build_store(ptr, val);
+                self.build.build_store(addr, val);
+            }
+            IrStmtKind::Copy { dst, src, ty } => {
+                let dst = self.gen_expr(irctx, dst).into_pointer_value();
+                let src = self.gen_expr(irctx, src).into_pointer_value();
+                let align = self.type_align(irctx, *ty);
+                let size = self
+                    .target_data
+                    .get_store_size(self.llvm_types.get_secondary(*ty));
+                let size = self
+                    .llvm_types
+                    .get_secondary(IrContext::USIZE)
+                    .into_int_type()
+                    .const_int(size, false);
+                self.build
+                    .build_memcpy(dst, align, src, align, size)
+                    .unwrap();
+            }
+            IrStmtKind::VolatileWrite { ptr, val } => {
+                let ptr = self.gen_expr(irctx, ptr).into_pointer_value();
+                let val = self.gen_expr(irctx, val);
+                self.build.build_store(ptr, val).set_volatile(true).unwrap();
+            }
+            IrStmtKind::Assert { cond, msg } => {
+                if !self.opts.asserts {
+                    return;
+                }
+
+                let cond = self.gen_expr(irctx, cond).into_int_value();
+                let fun = self
+                    .build
+                    .get_insert_block()
+                    .and_then(|bb| bb.get_parent())
+                    .unwrap();
+                let ok_bb = self.ctx.append_basic_block(fun, "assert_ok");
+                let fail_bb = self.ctx.append_basic_block(fun, "assert_fail");
+                self.build.build_conditional_branch(cond, ok_bb, fail_bb);
+
+                self.build.position_at_end(fail_bb);
+                let msg = match msg {
+                    Some(msg) => {
+                        format!("{}: assertion failed: {}", self.source_loc(stmt.span), msg)
+                    }
+                    None => format!("{}: assertion failed", self.source_loc(stmt.span)),
+                };
+                self.gen_abort(&msg);
+
+                self.build.position_at_end(ok_bb);
             }
             IrStmtKind::Call { fun, args } => {
                 let fun = *self.llvm_funs.get_secondary(*fun);
@@ -144,3 +434,120 @@ impl<'llvm> LLVMCodeGeneratorState<'llvm> {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        llvm::testing::{fun_ir, gen_ir, gen_ir_lowered_with, gen_ir_with, run},
+        OutputOptimizationLevel,
+    };
+
+    #[test]
+    fn abort_with_conflicting_write_declaration_verifies() {
+        gen_ir_with(
+            "fun ext write(i32 fd, *u8 buf, u32 len) -> i32
+fun ext abort(i32 code)
+
+fun add(i32 a, i32 b) -> i32 {
+    return a + b
+}
+
+fun main() -> i32 {
+    return add(1, 2)
+}",
+            |opts| opts.overflow_check = true,
+        );
+    }
+
+    #[test]
+    fn overaligned_variables_have_aligned_allocas() {
+        let ir = gen_ir(
+            "fun aligned() -> i32 {
+    let align(64) x = 1
+    return x
+}
+
+fun main() -> i32 {
+    return aligned()
+}",
+        );
+        assert!(
+            fun_ir(&ir, "_S7alignedE").contains("%x = alloca i32, align 64"),
+            "{}",
+            ir
+        );
+    }
+
+    #[test]
+    fn large_zeroed_variables_use_memset() {
+        let ir = gen_ir_lowered_with(
+            "fun main() -> i32 {
+    mut [[4096]u8] buf
+    mut [i32] small
+    return small + $i32 buf[4095]
+}",
+            |lowerer| lowerer.set_zero_init(true),
+        );
+        let main = fun_ir(&ir, "_S4mainE");
+        assert!(main.contains("call void @llvm.memset"), "{}", main);
+        assert!(main.contains("i64 4096"), "{}", main);
+        assert!(!main.contains("zeroinitializer"), "{}", main);
+        assert!(main.contains("store i32 0, i32* %small"), "{}", main);
+    }
+
+    #[test]
+    fn volatile_accesses_survive_optimization() {
+        let src = "fun poke(*u32 reg) -> u32 {
+    volatile_write(reg, 1u32)
+    volatile_write(reg, 2u32)
+    return volatile_read(reg)
+}
+
+fun main() -> i32 {
+    mut [u32] reg = 0u32
+    return $i32 poke(&reg)
+}";
+        let ir = gen_ir(src);
+        let poke = fun_ir(&ir, "_S4pokeEPj");
+        assert!(poke.contains("store volatile i32 1"), "{}", poke);
+        assert!(poke.contains("store volatile i32 2"), "{}", poke);
+        assert!(poke.contains("load volatile i32"), "{}", poke);
+
+        let ir = gen_ir_with(src, |opts| opts.opt_lvl = OutputOptimizationLevel::Release);
+        let poke = fun_ir(&ir, "_S4pokeEPj");
+        assert_eq!(poke.matches("store volatile").count(), 2, "{}", poke);
+        assert_eq!(poke.matches("load volatile").count(), 1, "{}", poke);
+
+        assert_eq!(run(src), 2);
+    }
+
+    const ASSERTS: &str = "fun check(i32 x) -> i32 {
+    assert(x < 10, \"too big\")
+    assert(x > 0)
+    return x
+}
+
+fun main() -> i32 {
+    return check(3)
+}";
+
+    #[test]
+    fn failed_asserts_abort_with_location_and_message() {
+        let ir = gen_ir(ASSERTS);
+        let check = fun_ir(&ir, "_S5checkEi");
+        assert_eq!(check.matches("call void @abort()").count(), 2, "{}", check);
+        assert!(ir.contains(":2:"), "{}", ir);
+        assert!(ir.contains(":3:"), "{}", ir);
+        assert!(ir.contains("assertion failed: too big\\0A"), "{}", ir);
+        assert!(ir.contains("assertion failed\\0A"), "{}", ir);
+
+        assert_eq!(run(ASSERTS), 3);
+    }
+
+    #[test]
+    fn asserts_can_be_compiled_out() {
+        let ir = gen_ir_with(ASSERTS, |opts| opts.asserts = false);
+        assert!(!ir.contains("assertion failed"), "{}", ir);
+        assert!(!fun_ir(&ir, "_S5checkEi").contains("abort"), "{}", ir);
+    }
+}
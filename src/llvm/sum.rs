@@ -0,0 +1,230 @@
+//! Lowering of sum types to tagged unions, laid out as an integer discriminant holding the index
+//! of the contained variant followed by a payload large enough to hold any variant
+//!
+//! Enums whose variants carry values share the same layout with their declared discriminants as
+//! the tag, enums without values are lowered to the tag alone
+
+use inkwell::{
+    context::Context,
+    types::{BasicType, IntType},
+    values::{BasicValueEnum, IntValue, PointerValue},
+    AddressSpace,
+};
+
+use crate::ir::{
+    types::{IrEnumType, IrType},
+    value::IrExpr,
+    DiscriminantId, IrContext, TypeId,
+};
+
+use super::{LLVMCodeGenerator, LLVMCodeGeneratorState};
+
+impl<'ctx, 'llvm> LLVMCodeGenerator<'ctx, 'llvm> {
+    /// Get the smallest integer type that can hold the discriminant of a sum type with the given
+    /// number of variants
+    pub fn gen_discrim_type(ctx: &'llvm Context, variants: usize) -> IntType<'llvm> {
+        match variants {
+            0..=0x100 => ctx.i8_type(),
+            0x101..=0x10000 => ctx.i16_type(),
+            _ => ctx.i32_type(),
+        }
+    }
+}
+
+impl<'llvm> LLVMCodeGeneratorState<'llvm> {
+    /// Get the variants of a sum type
+    fn sum_variants(irctx: &IrContext, sum: TypeId) -> &[TypeId] {
+        match &irctx[irctx.unwrap_alias(sum)] {
+            IrType::Sum(variants) => variants,
+            _ => unreachable!("{} is not a sum type", irctx.typename(sum)),
+        }
+    }
+
+    /// Get the declaration of an enum type
+    fn enum_type(irctx: &IrContext, ty: TypeId) -> &IrEnumType {
+        match &irctx[irctx.unwrap_alias(ty)] {
+            IrType::Enum(e) => e,
+            _ => unreachable!("{} is not an enum type", irctx.typename(ty)),
+        }
+    }
+
+    /// Get the value of the tag that marks a sum or enum type as holding the given discriminant
+    pub fn gen_discrim(
+        &self,
+        irctx: &IrContext,
+        matched: TypeId,
+        discriminant: DiscriminantId,
+    ) -> IntValue<'llvm> {
+        match discriminant {
+            DiscriminantId::Type(variant) => self.gen_sum_discrim_const(irctx, matched, variant),
+            DiscriminantId::Variant(idx) => {
+                let e = Self::enum_type(irctx, matched);
+                LLVMCodeGenerator::gen_inttype(self.ctx, &self.target_data, &e.tag_type())
+                    .const_int(e.variants[idx].discriminant as u64, true)
+            }
+        }
+    }
+
+    /// Get the discriminant value that marks a sum type as containing the given variant
+    fn gen_sum_discrim_const(
+        &self,
+        irctx: &IrContext,
+        sum: TypeId,
+        variant: TypeId,
+    ) -> IntValue<'llvm> {
+        let variants = Self::sum_variants(irctx, sum);
+        let idx = variants
+            .iter()
+            .position(|ty| *ty == variant)
+            .unwrap_or_else(|| {
+                panic!(
+                    "type {} is not in {}",
+                    irctx.typename(variant),
+                    irctx.typename(sum)
+                )
+            });
+
+        LLVMCodeGenerator::gen_discrim_type(self.ctx, variants.len()).const_int(idx as u64, false)
+    }
+
+    /// Create a value of a sum type containing the value of one of its variants
+    pub fn gen_sum_construct(
+        &mut self,
+        irctx: &IrContext,
+        sum: TypeId,
+        variant: &IrExpr,
+    ) -> BasicValueEnum<'llvm> {
+        let sum_ptr = self.entry_alloca(*self.llvm_types.get_secondary(sum), "sumlit");
+
+        let discrim_ptr = self
+            .build
+            .build_struct_gep(sum_ptr, 0, "sumlit_discrim")
+            .unwrap();
+        self.build.build_store(
+            discrim_ptr,
+            self.gen_sum_discrim_const(irctx, sum, variant.ty),
+        );
+
+        let payload_ptr = self.gen_sum_payload_ptr(sum_ptr, variant.ty);
+        let val = self.gen_expr(irctx, variant);
+        self.build.build_store(payload_ptr, val);
+
+        self.build.build_load(sum_ptr, "sumlit")
+    }
+
+    /// Create a value of an enum type holding the given variant and the value it carries
+    pub fn gen_enum_construct(
+        &mut self,
+        irctx: &IrContext,
+        ty: TypeId,
+        idx: usize,
+        payload: Option<&IrExpr>,
+    ) -> BasicValueEnum<'llvm> {
+        let tag = self.gen_discrim(irctx, ty, DiscriminantId::Variant(idx));
+        if !Self::enum_type(irctx, ty).has_payload() {
+            return tag.into();
+        }
+
+        let enum_ptr = self.entry_alloca(*self.llvm_types.get_secondary(ty), "enumlit");
+        let tag_ptr = self
+            .build
+            .build_struct_gep(enum_ptr, 0, "enumlit_tag")
+            .unwrap();
+        self.build.build_store(tag_ptr, tag);
+
+        if let Some(payload) = payload {
+            let payload_ptr = self.gen_sum_payload_ptr(enum_ptr, payload.ty);
+            let val = self.gen_expr(irctx, payload);
+            self.build.build_store(payload_ptr, val);
+        }
+
+        self.build.build_load(enum_ptr, "enumlit")
+    }
+
+    /// Read the tag of a sum or enum typed value, compared against the values of
+    /// [gen_discrim](Self::gen_discrim) when matching on it
+    pub fn gen_match_tag(&mut self, irctx: &IrContext, matched: &IrExpr) -> IntValue<'llvm> {
+        match &irctx[irctx.unwrap_alias(matched.ty)] {
+            IrType::Enum(e) if !e.has_payload() => self.gen_expr(irctx, matched).into_int_value(),
+            _ => {
+                let ptr = self.gen_lval(irctx, matched);
+                self.gen_sum_discrim(ptr)
+            }
+        }
+    }
+
+    /// Read the discriminant of the sum type value behind a pointer
+    pub fn gen_sum_discrim(&self, sum_ptr: PointerValue<'llvm>) -> IntValue<'llvm> {
+        let discrim_ptr = self
+            .build
+            .build_struct_gep(sum_ptr, 0, "sum_discrim_ptr")
+            .unwrap();
+        self.build
+            .build_load(discrim_ptr, "sum_discrim")
+            .into_int_value()
+    }
+
+    /// Get a pointer to the payload of the sum type value behind a pointer, reinterpreted as the
+    /// given variant type
+    pub fn gen_sum_payload_ptr(
+        &self,
+        sum_ptr: PointerValue<'llvm>,
+        variant: TypeId,
+    ) -> PointerValue<'llvm> {
+        let payload_ptr = self
+            .build
+            .build_struct_gep(sum_ptr, 1, "sum_payload_ptr")
+            .unwrap();
+        self.build.build_pointer_cast(
+            payload_ptr,
+            self.llvm_types
+                .get_secondary(variant)
+                .ptr_type(AddressSpace::Generic),
+            "sum_unwrap_ptr",
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::llvm::testing::{fun_ir, gen_ir, run};
+
+    const NUM: &str = "type Num = i32 | f64
+
+fun double(Num n) -> Num {
+    return match n {
+        i32 i -> phi $Num (i * 2),
+        f64 f -> phi $Num (f + f)
+    }
+}
+
+fun to_int(Num n) -> i32 {
+    return match n {
+        i32 i -> phi i,
+        f64 f -> phi $i32 f
+    }
+}
+
+fun main() -> i32 {
+    return to_int(double($Num 20i32)) + to_int(double($Num 1.5f64))
+}";
+
+    #[test]
+    fn sums_are_tagged_unions_with_the_smallest_discriminant() {
+        let ir = gen_ir(NUM);
+        let double = fun_ir(&ir, "_S6doubleEN3Num");
+        assert!(
+            double.starts_with(
+                "define { i8, { double, [0 x i8] } } @_S6doubleEN3Num({ i8, { double, [0 x i8] } } %0)"
+            ),
+            "{}",
+            double
+        );
+        assert!(double.contains("switch i8"), "{}", double);
+    }
+
+    #[test]
+    fn both_variants_round_trip_through_functions() {
+        assert_eq!(run(NUM), 43);
+    }
+}
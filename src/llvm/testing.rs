@@ -0,0 +1,153 @@
+//! Helpers for tests that generate LLVM IR from spark source code and run it in memory
+
+use std::{
+    path::PathBuf,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use codespan_reporting::diagnostic::Diagnostic;
+use inkwell::context::Context;
+
+use crate::{
+    ir::{
+        layout::TargetSpec,
+        lower::{
+            testing::{expect_lowered, lower, lower_modules, lower_with, Lowered},
+            IrLowerer,
+        },
+    },
+    util::files::FileId,
+    CompileOpts, OutputFileType,
+};
+
+use super::LLVMCodeGenerator;
+
+/// Get a path in the temporary directory that no other test in this process uses
+pub fn temp_path(extension: &str) -> PathBuf {
+    static COUNTER: AtomicUsize = AtomicUsize::new(0);
+    std::env::temp_dir().join(format!(
+        "spark-llvm-test-{}-{}.{}",
+        std::process::id(),
+        COUNTER.fetch_add(1, Ordering::Relaxed),
+        extension
+    ))
+}
+
+/// Generate code for a module and write it to the output file after applying options to the
+/// default test options, returning the contents of the written file
+pub fn gen_output(
+    src: &str,
+    out_type: OutputFileType,
+    configure: impl FnOnce(&mut CompileOpts),
+) -> Result<Vec<u8>, Diagnostic<FileId>> {
+    let mut lowered = lower(src);
+    let mut opts = CompileOpts::testing(out_type, temp_path("out"));
+    configure(&mut opts);
+    let path = opts.out_file.clone();
+
+    let ctx = Context::create();
+    LLVMCodeGenerator::new(&mut lowered.ctx, &ctx, &lowered.files, opts)?.gen()?;
+    let output = std::fs::read(&path)
+        .unwrap_or_else(|e| panic!("failed to read output {}: {}", path.display(), e));
+    std::fs::remove_file(&path).ok();
+    Ok(output)
+}
+
+/// Generate and verify LLVM IR for lowered modules with the given options, returning the textual
+/// IR
+pub fn gen_lowered_ir(mut lowered: Lowered, opts: CompileOpts) -> String {
+    let ctx = Context::create();
+    let mut gen = LLVMCodeGenerator::new(&mut lowered.ctx, &ctx, &lowered.files, opts)
+        .unwrap_or_else(|e| panic!("failed to create code generator: {}", e.message));
+    gen.gen_module()
+        .unwrap_or_else(|e| panic!("generated invalid LLVM IR: {} {:?}", e.message, e.notes));
+    gen.emit_ir()
+}
+
+/// Generate and verify LLVM IR for a module after applying options to the default test options,
+/// returning the textual IR
+pub fn gen_ir_with(src: &str, configure: impl FnOnce(&mut CompileOpts)) -> String {
+    let mut opts = CompileOpts::testing(OutputFileType::LLVMIR, "test.ll");
+    configure(&mut opts);
+    gen_lowered_ir(lower(src), opts)
+}
+
+/// Generate and verify unoptimized LLVM IR for a module after applying options to the lowerer,
+/// returning the textual IR
+pub fn gen_ir_lowered_with(src: &str, configure: impl FnOnce(&mut IrLowerer)) -> String {
+    gen_lowered_ir(
+        expect_lowered(lower_with(src, &[], configure)),
+        CompileOpts::testing(OutputFileType::LLVMIR, "test.ll"),
+    )
+}
+
+/// Generate and verify unoptimized LLVM IR for a module lowered and compiled for the given target
+/// triple, returning the textual IR
+pub fn gen_target_ir(src: &str, triple: &str) -> String {
+    let lowered = expect_lowered(lower_with(src, &[], |lowerer| {
+        lowerer.set_target(TargetSpec::from_triple(triple))
+    }));
+    let mut opts = CompileOpts::testing(OutputFileType::LLVMIR, "test.ll");
+    opts.target.triple = Some(triple.to_owned());
+    gen_lowered_ir(lowered, opts)
+}
+
+/// Generate and verify unoptimized LLVM IR for a root module and child modules given by name and
+/// source code, returning the textual IR of the single LLVM module they are compiled into
+pub fn gen_modules_ir(root: &str, children: &[(&str, &str)]) -> String {
+    gen_lowered_ir(
+        lower_modules(root, children),
+        CompileOpts::testing(OutputFileType::LLVMIR, "test.ll"),
+    )
+}
+
+/// Generate and verify unoptimized LLVM IR for a module, returning the textual IR
+pub fn gen_ir(src: &str) -> String {
+    gen_ir_with(src, |_| ())
+}
+
+/// Get the textual IR of the definition of the function with the given symbol name from the IR
+/// of a module
+pub fn fun_ir<'a>(ir: &'a str, symbol: &str) -> &'a str {
+    let signature = format!("@{}(", symbol);
+    let start = ir
+        .match_indices("define ")
+        .map(|(start, _)| start)
+        .find(|start| {
+            ir[*start..]
+                .lines()
+                .next()
+                .map_or(false, |line| line.contains(&signature))
+        })
+        .unwrap_or_else(|| panic!("no definition of function {}:\n{}", symbol, ir));
+    let end = ir[start..]
+        .find("\n}\n")
+        .map_or(ir.len(), |end| start + end + 3);
+    &ir[start..end]
+}
+
+/// Generate code for lowered modules and run the `main` function with the JIT, returning its
+/// result
+pub fn run_lowered(mut lowered: Lowered) -> i64 {
+    let opts = CompileOpts::testing(OutputFileType::Object, "test.o");
+
+    let ctx = Context::create();
+    LLVMCodeGenerator::new(&mut lowered.ctx, &ctx, &lowered.files, opts)
+        .and_then(|mut gen| {
+            gen.gen_module()?;
+            gen.jit_run("main", &[])
+        })
+        .unwrap_or_else(|e| panic!("failed to run: {} {:?}", e.message, e.notes))
+}
+
+/// Generate code for a root module and child modules and run the `main` function with the JIT,
+/// returning its result
+pub fn run_modules(root: &str, children: &[(&str, &str)]) -> i64 {
+    run_lowered(lower_modules(root, children))
+}
+
+/// Generate code for a single module and run the `main` function with the JIT, returning its
+/// result as the exit code of the program
+pub fn run(src: &str) -> i32 {
+    run_modules(src, &[]) as i32
+}
@@ -169,7 +169,10 @@ impl<'src> Lexer<'src> {
                 }
 
                 if let (end, '\'') = self.next_char()? {
-                    Token::new(startpos..end, TokenData::Char(&self.src[firstpos - 1..end - 1]))
+                    Token::new(
+                        startpos..end,
+                        TokenData::Char(&self.src[firstpos - 1..end - 1]),
+                    )
                 } else {
                     return None;
                 }
@@ -283,6 +286,11 @@ impl<'src> Lexer<'src> {
     pub fn peek2(&self) -> Option<&Token<'src>> {
         self.peek2.as_ref()
     }
+
+    /// Check if the source string contains a line break between two locations
+    pub fn line_break_between(&self, from: usize, to: usize) -> bool {
+        matches!(self.src.get(from..to), Some(between) if between.contains('\n'))
+    }
 }
 
 impl<'src> Iterator for Lexer<'src> {
@@ -1,16 +1,16 @@
 use std::{borrow::Cow, fmt};
 
 use crate::{
-    ast::{BigInt, FunDef, Let, Literal, Match},
+    ast::{BigInt, FunDef, Let, Literal, Match, MatchArm},
     Symbol,
 };
 use smallvec::SmallVec;
 
 use crate::{
     ast::{
-        Def, DefData, ElseExpr, Expr, ExprNode, FunFlags, FunProto, If, IntegerWidth,
-        NumberLiteral, NumberLiteralAnnotation, ParsedModule, Stmt, StmtNode, SymbolPath,
-        UnresolvedFunType, UnresolvedType,
+        Def, DefData, ElseExpr, EnumVariantDef, Expr, ExprNode, FunFlags, FunProto, If,
+        IntegerWidth, NumberLiteral, NumberLiteralAnnotation, ParsedModule, Stmt, StmtNode,
+        SymbolPath, UnresolvedFunType, UnresolvedType,
     },
     parse::token::Op,
     util::{files::FileId, loc::Span},
@@ -213,14 +213,32 @@ impl<'src> Parser<'src> {
     /// Parse a top-level declaration from the token stream
     fn parse_decl(&mut self, file: FileId) -> ParseResult<'src, Def> {
         const EXPECTING_NEXT: &[TokenData<'static>] = &[
+            TokenData::Ident("pub"),
             TokenData::Ident("fun"),
             TokenData::Ident("type"),
+            TokenData::Ident("enum"),
             TokenData::Ident("const"),
+            TokenData::Ident("glob"),
             TokenData::Ident("imp"),
         ];
 
         let next = self.next_tok(EXPECTING_NEXT)?;
         match next.data {
+            TokenData::Ident("pub") => {
+                let mut def = self.parse_decl(file)?;
+                if let DefData::ImportDef { .. } = def.data {
+                    return Err(ParseError {
+                        highlighted_span: Some(next.span),
+                        backtrace: self.trace.clone(),
+                        error: ParseErrorKind::UnexpectedToken {
+                            found: next,
+                            expecting: ExpectingOneOf(&EXPECTING_NEXT[1..6]),
+                        },
+                    });
+                }
+                def.public = true;
+                Ok(def)
+            }
             TokenData::Ident("imp") => {
                 self.trace.push("import statement".into());
                 let imported = self.expect_next_path(&[TokenData::Ident("imported module")])?;
@@ -230,17 +248,37 @@ impl<'src> Parser<'src> {
                     file,
                     span: next.span,
                     data: DefData::ImportDef { name: imported },
+                    public: false,
                 })
             }
             TokenData::Ident("fun") => {
-                let (name, flags) =
+                let mut flags = FunFlags::empty();
+                let name = loop {
                     match self.expect_next_ident(&[TokenData::Ident("function name")])? {
-                        "ext" => (
-                            self.expect_next_ident(&[TokenData::Ident("function name")])?,
-                            FunFlags::EXTERN,
-                        ),
-                        other => (other, FunFlags::empty()),
-                    };
+                        "ext" => flags |= FunFlags::EXTERN,
+                        "export" => flags |= FunFlags::EXPORT,
+                        "used" => flags |= FunFlags::USED,
+                        //A function named allow has its parameter list here instead
+                        "allow"
+                            if matches!(
+                                (
+                                    self.toks.peek().map(|tok| &tok.data),
+                                    self.toks.peek2().map(|tok| &tok.data)
+                                ),
+                                (
+                                    Some(TokenData::OpenBracket(BracketType::Smooth)),
+                                    Some(TokenData::Ident("unused"))
+                                )
+                            ) =>
+                        {
+                            self.toks.next();
+                            self.toks.next();
+                            self.expect_next(&[TokenData::CloseBracket(BracketType::Smooth)])?;
+                            flags |= FunFlags::ALLOW_UNUSED;
+                        }
+                        other => break other,
+                    }
+                };
 
                 self.trace
                     .push(format!("function declaration '{}'", name).into());
@@ -254,6 +292,8 @@ impl<'src> Parser<'src> {
                 self.expect_next(&[TokenData::OpenBracket(BracketType::Smooth)])?;
 
                 let mut args = Vec::new();
+                let mut arg_spans = Vec::new();
+                let mut arg_mutable = Vec::new();
 
                 loop {
                     let peeked = self.peek_tok(ARGS_EXPECTING)?;
@@ -263,12 +303,22 @@ impl<'src> Parser<'src> {
                             break;
                         }
                         _ => {
+                            let mut arg_span = peeked.span;
+                            let mutable = peeked.data == TokenData::Ident("mut");
+                            if mutable {
+                                self.toks.next();
+                            }
+
                             self.trace.push("function argument typename".into());
                             let arg_type = self.parse_typename()?;
                             self.trace.pop();
 
-                            let arg_name = match self.toks.peek().map(|t| &t.data) {
-                                Some(TokenData::Ident(_)) => {
+                            let arg_name = match self.toks.peek() {
+                                Some(Token {
+                                    data: TokenData::Ident(_),
+                                    span,
+                                }) => {
+                                    arg_span.to = span.to;
                                     self.trace.push("function argument name".into());
                                     let arg_name = self.expect_next_ident(&[TokenData::Ident(
                                         "function argument name",
@@ -280,6 +330,8 @@ impl<'src> Parser<'src> {
                             };
 
                             args.push((arg_type, arg_name));
+                            arg_spans.push(arg_span);
+                            arg_mutable.push(mutable);
 
                             const EXPECTING_AFTER_ARG: &[TokenData<'static>] = &[
                                 TokenData::OpenBracket(BracketType::Curly),
@@ -322,6 +374,8 @@ impl<'src> Parser<'src> {
                     name: self.symbol(name),
                     ty,
                     flags,
+                    arg_spans,
+                    arg_mutable,
                 };
 
                 self.trace.pop();
@@ -340,20 +394,123 @@ impl<'src> Parser<'src> {
                             proto,
                             body: body.0,
                         }),
+                        public: false,
                     })
                 } else {
                     Ok(Def {
                         file,
                         span: next.span,
                         data: DefData::FunDec(proto),
+                        public: false,
                     })
                 }
             }
+            TokenData::Ident("enum") => {
+                let name = self.expect_next_ident(&[TokenData::Ident("enum name")])?;
+                self.trace
+                    .push(format!("enum definition '{}'", name).into());
+                self.expect_next(&[TokenData::OpenBracket(BracketType::Curly)])?;
+
+                let mut variants = vec![];
+                loop {
+                    const EXPECTING_VARIANT: &[TokenData<'static>] = &[
+                        TokenData::Ident("enum variant name"),
+                        TokenData::CloseBracket(BracketType::Curly),
+                    ];
+                    const EXPECTING_AFTER_VARIANT: &[TokenData<'static>] = &[
+                        TokenData::Comma,
+                        TokenData::CloseBracket(BracketType::Curly),
+                    ];
+
+                    let variant = self.next_tok(EXPECTING_VARIANT)?;
+                    let variant_name = match variant.data {
+                        TokenData::CloseBracket(BracketType::Curly) => break,
+                        TokenData::Ident(variant_name) => variant_name,
+                        _ => {
+                            return Err(ParseError {
+                                highlighted_span: Some(variant.span),
+                                backtrace: self.trace.clone(),
+                                error: ParseErrorKind::UnexpectedToken {
+                                    found: variant,
+                                    expecting: ExpectingOneOf(EXPECTING_VARIANT),
+                                },
+                            })
+                        }
+                    };
+                    self.trace
+                        .push(format!("enum variant '{}'", variant_name).into());
+
+                    let payload = match self.toks.peek().map(|tok| &tok.data) {
+                        Some(TokenData::OpenBracket(BracketType::Smooth)) => {
+                            self.toks.next();
+                            let payload = self.parse_typename()?;
+                            self.expect_next(&[TokenData::CloseBracket(BracketType::Smooth)])?;
+                            Some(payload)
+                        }
+                        _ => None,
+                    };
+
+                    let discriminant = match self.toks.peek().map(|tok| &tok.data) {
+                        Some(TokenData::Assign) => {
+                            self.toks.next();
+                            Some(self.parse_expr()?)
+                        }
+                        _ => None,
+                    };
+
+                    self.trace.pop();
+                    variants.push(EnumVariantDef {
+                        name: self.symbol(variant_name),
+                        payload,
+                        discriminant,
+                        span: variant.span,
+                    });
+
+                    let next = self.next_tok(EXPECTING_AFTER_VARIANT)?;
+                    match next.data {
+                        TokenData::Comma => (),
+                        TokenData::CloseBracket(BracketType::Curly) => break,
+                        _ => {
+                            return Err(ParseError {
+                                highlighted_span: Some(next.span),
+                                backtrace: self.trace.clone(),
+                                error: ParseErrorKind::UnexpectedToken {
+                                    found: next,
+                                    expecting: ExpectingOneOf(EXPECTING_AFTER_VARIANT),
+                                },
+                            })
+                        }
+                    }
+                }
+
+                self.trace.pop();
+                Ok(Def {
+                    span: next.span,
+                    data: DefData::EnumDef {
+                        name: self.symbol(name),
+                        variants,
+                    },
+                    file,
+                    public: false,
+                })
+            }
             TokenData::Ident("type") => {
                 let name = self.expect_next_ident(&[TokenData::Ident("type name")])?;
                 self.trace
                     .push(format!("type definition '{}'", name).into());
 
+                if self.toks.peek().map(|tok| &tok.data) != Some(&TokenData::Assign) {
+                    self.trace.pop();
+                    return Ok(Def {
+                        span: next.span,
+                        data: DefData::OpaqueDef {
+                            name: self.symbol(name),
+                        },
+                        file,
+                        public: false,
+                    });
+                }
+
                 self.expect_next(&[TokenData::Assign])?;
                 let aliased = self.parse_typename()?;
 
@@ -365,14 +522,30 @@ impl<'src> Parser<'src> {
                         aliased,
                     },
                     file,
+                    public: false,
                 })
             }
-            TokenData::Ident("glob") => {
+            TokenData::Ident("glob") | TokenData::Ident("const") => {
+                //Constants are globals that are always evaluated at compile time
+                let is_const = next.data == TokenData::Ident("const");
                 const EXPECTING_AFTER_GLOB: &[TokenData<'static>] = &[
                     TokenData::Ident("global name"),
+                    TokenData::Ident("mut"),
                     TokenData::OpenBracket(BracketType::Square),
                 ];
 
+                let mutable = if self
+                    .toks
+                    .peek()
+                    .map(|t| matches!(t.data, TokenData::Ident("mut")))
+                    .unwrap_or(false)
+                {
+                    self.toks.next();
+                    true
+                } else {
+                    false
+                };
+
                 let next = self.peek_tok(EXPECTING_AFTER_GLOB)?.clone();
 
                 let ty = match next.data {
@@ -394,7 +567,7 @@ impl<'src> Parser<'src> {
                     self.toks.next();
                     true
                 } else {
-                    false
+                    is_const
                 };
 
                 let name = self.expect_next_path(&[TokenData::Ident("Global value name")])?;
@@ -420,10 +593,12 @@ impl<'src> Parser<'src> {
                     data: DefData::Global {
                         name,
                         comptime,
+                        mutable,
                         val,
                         ty,
                     },
                     file,
+                    public: false,
                 })
             }
             _ => Err(ParseError {
@@ -501,9 +676,29 @@ impl<'src> Parser<'src> {
             }
             TokenData::Ident("break") => {
                 self.toks.next();
+                //An expression on the same line as the break is the value of the loop
+                let value = match self.toks.peek() {
+                    Some(Token {
+                        data: TokenData::CloseBracket(_) | TokenData::Comma,
+                        ..
+                    })
+                    | None => None,
+                    Some(tok) if self.toks.line_break_between(peeked.span.to, tok.span.from) => {
+                        None
+                    }
+                    Some(_) => {
+                        self.trace.push("break value".into());
+                        let value = self.parse_expr()?;
+                        self.trace.pop();
+                        Some(Box::new(value))
+                    }
+                };
                 Ok(Stmt {
-                    span: peeked.span,
-                    node: StmtNode::Break,
+                    span: match &value {
+                        Some(value) => (peeked.span.from, value.span.to).into(),
+                        None => peeked.span,
+                    },
+                    node: StmtNode::Break(value),
                 })
             }
             TokenData::Ident("continue") => {
@@ -537,6 +732,25 @@ impl<'src> Parser<'src> {
                 let mutable = peeked.data == TokenData::Ident("mut");
                 self.trace.push("let statement".into());
 
+                let align = match (
+                    self.toks.peek().map(|tok| &tok.data),
+                    self.toks.peek2().map(|tok| &tok.data),
+                ) {
+                    (
+                        Some(TokenData::Ident("align")),
+                        Some(TokenData::OpenBracket(BracketType::Smooth)),
+                    ) => {
+                        self.toks.next();
+                        self.toks.next();
+                        self.trace.push("variable alignment".into());
+                        let align = self.parse_alignment()?;
+                        self.expect_next(&[TokenData::CloseBracket(BracketType::Smooth)])?;
+                        self.trace.pop();
+                        Some(align)
+                    }
+                    _ => None,
+                };
+
                 let next = self.peek_tok(EXPECTING_AFTER_LET)?.clone();
 
                 let mut var_type = None;
@@ -571,6 +785,7 @@ impl<'src> Parser<'src> {
                         let_expr: Box::new(expr),
                         assigned,
                         mutable,
+                        align,
                     }),
                 })
             }
@@ -599,8 +814,30 @@ impl<'src> Parser<'src> {
             TokenData::Ident(_) => {
                 const EXPECTING_FOR_CALL: &[TokenData<'static>] =
                     &[TokenData::Ident("Function name")];
+                const EXPECTING_AFTER_NAME: &[TokenData<'static>] = &[
+                    TokenData::OpenBracket(BracketType::Smooth),
+                    TokenData::Assign,
+                ];
 
                 let name = self.expect_next_path(EXPECTING_FOR_CALL)?;
+                if self.peek_tok(EXPECTING_AFTER_NAME)?.data == TokenData::Assign {
+                    self.toks.next();
+                    self.trace.push("assignment".into());
+                    let assigned = self.parse_expr()?;
+                    self.trace.pop();
+
+                    return Ok(Stmt {
+                        span: (peeked.span.from..assigned.span.to).into(),
+                        node: StmtNode::Assign(
+                            Box::new(Expr {
+                                span: peeked.span,
+                                node: ExprNode::Access(name),
+                            }),
+                            Box::new(assigned),
+                        ),
+                    });
+                }
+
                 let args = self.parse_fun_args()?;
 
                 Ok(Stmt {
@@ -657,6 +894,30 @@ impl<'src> Parser<'src> {
                     node: ExprNode::Literal(Literal::Bool(false)),
                 }
             }
+            TokenData::Ident(query @ ("sizeof" | "alignof")) => {
+                let query = *query;
+                self.toks.next();
+                self.trace.push(format!("{} expression", query).into());
+                self.expect_next(&[TokenData::OpenBracket(BracketType::Smooth)])?;
+                let ty = self.parse_typename()?;
+                let close = self.next_tok(&[TokenData::CloseBracket(BracketType::Smooth)])?;
+                if close.data != TokenData::CloseBracket(BracketType::Smooth) {
+                    return Err(self.unexpected(
+                        close.span,
+                        close,
+                        &[TokenData::CloseBracket(BracketType::Smooth)],
+                    ));
+                }
+                self.trace.pop();
+
+                Expr {
+                    span: (peeked.span.from, close.span.to).into(),
+                    node: match query {
+                        "sizeof" => ExprNode::SizeOf(ty),
+                        _ => ExprNode::AlignOf(ty),
+                    },
+                }
+            }
             TokenData::Dollar => {
                 self.toks.next();
                 self.trace.push("cast expression typename".into());
@@ -899,10 +1160,8 @@ impl<'src> Parser<'src> {
     /// Parse a character literal from the token stream, respecting escaped characters with
     /// backslash
     fn parse_char_literal(&mut self) -> ParseResult<'src, char> {
-        const EXPECTING_CHAR: &[TokenData<'static>] = &[
-            TokenData::Char("character literal")
-        ];
-        
+        const EXPECTING_CHAR: &[TokenData<'static>] = &[TokenData::Char("character literal")];
+
         let next = self.next_tok(EXPECTING_CHAR)?;
         match next.data {
             TokenData::Char(chars) => {
@@ -968,6 +1227,7 @@ impl<'src> Parser<'src> {
             let next = self.peek_tok(&[
                 TokenData::CloseBracket(BracketType::Curly),
                 TokenData::Ident("type name"),
+                TokenData::Arrow(1),
                 TokenData::Comma,
             ])?;
             match next.data {
@@ -978,11 +1238,47 @@ impl<'src> Parser<'src> {
                 TokenData::Comma => {
                     self.toks.next();
                 }
+                //An arm with no type matches anything that the other arms don't
+                TokenData::Arrow(1) => {
+                    self.toks.next();
+                    let body = self.parse_stmt()?;
+                    cases.push(MatchArm {
+                        case: None,
+                        binding: None,
+                        mutable: false,
+                        body,
+                    });
+                }
                 _ => {
                     let ty = self.parse_typename()?;
+                    let mutable = matches!(
+                        self.toks.peek().map(|tok| &tok.data),
+                        Some(TokenData::Ident("mut"))
+                    );
+                    if mutable {
+                        self.toks.next();
+                    }
+                    let binding = match self.toks.peek().map(|tok| &tok.data) {
+                        _ if mutable => {
+                            let name =
+                                self.expect_next_ident(&[TokenData::Ident("match binding name")])?;
+                            Some(self.symbol(name))
+                        }
+                        Some(TokenData::Ident(name)) => {
+                            let name = *name;
+                            self.toks.next();
+                            Some(self.symbol(name))
+                        }
+                        _ => None,
+                    };
                     self.expect_next(&[TokenData::Arrow(1)])?;
-                    let stmt = self.parse_stmt()?;
-                    cases.push((ty, stmt));
+                    let body = self.parse_stmt()?;
+                    cases.push(MatchArm {
+                        case: Some(ty),
+                        binding,
+                        mutable,
+                        body,
+                    });
                 }
             }
         };
@@ -1109,10 +1405,44 @@ impl<'src> Parser<'src> {
                     });
                 }
 
-                let expr = self.parse_expr()?;
-                self.expect_next(&[TokenData::CloseBracket(BracketType::Smooth)])?;
+                let mut elements = vec![self.parse_expr()?];
+                //A comma after the first expression makes a tuple literal, like (1, 2) or (1,)
+                //for a tuple of one element
+                let mut tuple = false;
+                while let Some(TokenData::Comma) = self.toks.peek().map(|tok| &tok.data) {
+                    self.toks.next();
+                    tuple = true;
+                    if let Some(TokenData::CloseBracket(BracketType::Smooth)) =
+                        self.toks.peek().map(|tok| &tok.data)
+                    {
+                        break;
+                    }
+                    self.trace.push("tuple element".into());
+                    elements.push(self.parse_expr()?);
+                    self.trace.pop();
+                }
+                let close = self.next_tok(&[TokenData::CloseBracket(BracketType::Smooth)])?;
+                if close.data != TokenData::CloseBracket(BracketType::Smooth) {
+                    return Err(ParseError {
+                        highlighted_span: Some(close.span),
+                        backtrace: self.trace.clone(),
+                        error: ParseErrorKind::UnexpectedToken {
+                            found: close,
+                            expecting: ExpectingOneOf(&[
+                                TokenData::Comma,
+                                TokenData::CloseBracket(BracketType::Smooth),
+                            ]),
+                        },
+                    });
+                }
 
-                expr
+                match tuple {
+                    true => Expr {
+                        span: (next.span.from, close.span.to).into(),
+                        node: ExprNode::Literal(Literal::Tuple(elements)),
+                    },
+                    false => elements.pop().unwrap(),
+                }
             }
             _ => {
                 return Err(ParseError {
@@ -1192,6 +1522,7 @@ impl<'src> Parser<'src> {
             TokenData::Period => {
                 const EXPECTING_AFTER_PERIOD: &[TokenData<'static>] = &[
                     TokenData::Ident("structure field name"),
+                    TokenData::Number("tuple element index"),
                     TokenData::OpenBracket(BracketType::Smooth),
                 ];
 
@@ -1208,6 +1539,24 @@ impl<'src> Parser<'src> {
                             node: ExprNode::Member(Box::new(accessing), symbol),
                         })
                     }
+                    //Nested tuple accesses like t.0.1 are lexed as one number
+                    TokenData::Number(indices)
+                        if indices.split('.').all(|idx| {
+                            !idx.is_empty() && idx.bytes().all(|c| c.is_ascii_digit())
+                        }) =>
+                    {
+                        self.trace.pop();
+
+                        let mut accessing = accessing;
+                        for idx in indices.split('.') {
+                            let symbol = self.symbol(idx);
+                            accessing = Expr {
+                                span: (accessing.span.from, next.span.to).into(),
+                                node: ExprNode::Member(Box::new(accessing), symbol),
+                            };
+                        }
+                        self.parse_access(accessing)
+                    }
                     _ => {
                         return Err(ParseError {
                             highlighted_span: Some(next.span),
@@ -1274,17 +1623,20 @@ impl<'src> Parser<'src> {
             TokenData::Ident("i16"),
             TokenData::Ident("i32"),
             TokenData::Ident("i64"),
+            TokenData::Ident("i128"),
             TokenData::Ident("u8"),
             TokenData::Ident("u16"),
             TokenData::Ident("u32"),
             TokenData::Ident("u64"),
+            TokenData::Ident("u128"),
         ];
 
         let next = self.next_tok(EXPECTING_NEXT)?;
 
         match next.data {
             TokenData::Ident(name) => match name {
-                "i8" | "i16" | "i32" | "i64" | "u8" | "u16" | "u32" | "u64" | "isz" | "usz" => {
+                "i8" | "i16" | "i32" | "i64" | "i128" | "u8" | "u16" | "u32" | "u64" | "u128"
+                | "isz" | "usz" | "isize" | "usize" => {
                     let signed = &name[0..1] == "i";
 
                     match &name[1..] {
@@ -1304,7 +1656,11 @@ impl<'src> Parser<'src> {
                             signed,
                             width: IntegerWidth::SixtyFour,
                         }),
-                        "sz" => Ok(UnresolvedType::Integer {
+                        "128" => Ok(UnresolvedType::Integer {
+                            signed,
+                            width: IntegerWidth::OneTwentyEight,
+                        }),
+                        "sz" | "size" => Ok(UnresolvedType::Integer {
                             signed,
                             width: IntegerWidth::PtrSize,
                         }),
@@ -1382,6 +1738,33 @@ impl<'src> Parser<'src> {
                     }),
                 },
                 "b" if name == "bool" => Ok(UnresolvedType::Bool),
+                "packed" => {
+                    const EXPECTING_AFTER_PACKED: &[TokenData<'static>] =
+                        &[TokenData::OpenBracket(BracketType::Curly)];
+
+                    self.trace.push("packed structure typename".into());
+                    let peeked = self.peek_tok(EXPECTING_AFTER_PACKED)?.clone();
+                    if peeked.data != TokenData::OpenBracket(BracketType::Curly) {
+                        return Err(ParseError {
+                            highlighted_span: Some(peeked.span),
+                            backtrace: self.trace.clone(),
+                            error: ParseErrorKind::UnexpectedToken {
+                                found: peeked,
+                                expecting: ExpectingOneOf(EXPECTING_AFTER_PACKED),
+                            },
+                        });
+                    }
+
+                    let ty = match self.parse_first_typename()? {
+                        UnresolvedType::Struct { fields, .. } => UnresolvedType::Struct {
+                            fields,
+                            packed: true,
+                        },
+                        _ => unreachable!(),
+                    };
+                    self.trace.pop();
+                    Ok(ty)
+                }
                 _ => {
                     self.trace.push("user-defined typename".into());
                     let name = self.symbol(name);
@@ -1393,12 +1776,22 @@ impl<'src> Parser<'src> {
                     Ok(ty)
                 }
             },
+            TokenData::OpenBracket(BracketType::Square)
+                if matches!(
+                    self.toks.peek().map(|tok| &tok.data),
+                    Some(TokenData::CloseBracket(BracketType::Square))
+                ) =>
+            {
+                self.toks.next();
+                self.trace.push("slice item typename".into());
+                let item_type = self.parse_typename()?;
+                self.trace.pop();
+
+                Ok(UnresolvedType::Slice(Box::new(item_type)))
+            }
             TokenData::OpenBracket(BracketType::Square) => {
                 self.trace.push("array type length".into());
-                let len = match self.parse_numliteral()? {
-                    NumberLiteral::Integer(bigint, _) => bigint.val,
-                    NumberLiteral::Float(floating, _) => floating as u64,
-                };
+                let len = Box::new(self.parse_expr()?);
 
                 self.trace.pop();
 
@@ -1452,12 +1845,28 @@ impl<'src> Parser<'src> {
                     }
 
                     self.trace.push("struct type field".into());
+                    let align = match (
+                        self.toks.peek().map(|tok| &tok.data),
+                        self.toks.peek2().map(|tok| &tok.data),
+                    ) {
+                        (
+                            Some(TokenData::Ident("align")),
+                            Some(TokenData::OpenBracket(BracketType::Smooth)),
+                        ) => {
+                            self.toks.next();
+                            self.toks.next();
+                            let align = self.parse_alignment()?;
+                            self.expect_next(&[TokenData::CloseBracket(BracketType::Smooth)])?;
+                            Some(align)
+                        }
+                        _ => None,
+                    };
                     let field_typename = self.parse_typename()?;
 
                     let field_name =
                         self.expect_next_ident(&[TokenData::Ident("struct field name")])?;
                     self.trace.pop();
-                    fields.push((field_typename, self.symbol(field_name)));
+                    fields.push((field_typename, self.symbol(field_name), align));
 
                     let next = self.next_tok(EXPECTING_AFTER_FIELD)?;
 
@@ -1479,7 +1888,10 @@ impl<'src> Parser<'src> {
 
                 self.trace.pop();
 
-                Ok(UnresolvedType::Struct { fields })
+                Ok(UnresolvedType::Struct {
+                    fields,
+                    packed: false,
+                })
             }
             TokenData::OpenBracket(BracketType::Smooth) => {
                 self.trace.push("Type in parentheses".into());
@@ -1496,9 +1908,28 @@ impl<'src> Parser<'src> {
                         UnresolvedType::Unit
                     }
                     _ => {
-                        let ty = self.parse_typename()?;
+                        let mut elements = vec![self.parse_typename()?];
+                        //A comma after the first type makes a tuple type, like (i32, *u8) or
+                        //(i32,) for a tuple of one element
+                        let mut tuple = false;
+                        while let Some(TokenData::Comma) = self.toks.peek().map(|tok| &tok.data) {
+                            self.toks.next();
+                            tuple = true;
+                            if let Some(TokenData::CloseBracket(BracketType::Smooth)) =
+                                self.toks.peek().map(|tok| &tok.data)
+                            {
+                                break;
+                            }
+                            self.trace.push("tuple element typename".into());
+                            elements.push(self.parse_typename()?);
+                            self.trace.pop();
+                        }
                         self.expect_next(&[TokenData::CloseBracket(BracketType::Smooth)])?;
-                        ty
+                        self.trace.pop();
+                        match tuple {
+                            true => UnresolvedType::Tuple(elements),
+                            false => elements.pop().unwrap(),
+                        }
                     }
                 };
 
@@ -1522,6 +1953,25 @@ impl<'src> Parser<'src> {
         }
     }
 
+    /// Parse the integer alignment in bytes inside an `align(...)` attribute. Alignments too
+    /// large for a `u64` saturate, and are rejected later as they are not a power of two
+    fn parse_alignment(&mut self) -> ParseResult<'src, u64> {
+        const EXPECTING_ALIGNMENT: &[TokenData<'static>] =
+            &[TokenData::Number("integer alignment")];
+        let number = self.peek_tok(EXPECTING_ALIGNMENT)?.clone();
+        match self.parse_numliteral()? {
+            NumberLiteral::Integer(bigint, _) => Ok(bigint.val.min(u64::MAX as u128) as u64),
+            NumberLiteral::Float(..) => Err(ParseError {
+                highlighted_span: Some(number.span),
+                backtrace: self.trace.clone(),
+                error: ParseErrorKind::UnexpectedToken {
+                    found: number,
+                    expecting: ExpectingOneOf(EXPECTING_ALIGNMENT),
+                },
+            }),
+        }
+    }
+
     /// Parse a number literal from the token stream
     fn parse_numliteral(&mut self) -> ParseResult<'src, NumberLiteral> {
         const EXPECTED_FOR_NUMLITERAL: &[TokenData<'static>] =
@@ -1559,6 +2009,10 @@ impl<'src> Parser<'src> {
                             self.toks.next();
                             Some(NumberLiteralAnnotation::U64)
                         }
+                        "u128" => {
+                            self.toks.next();
+                            Some(NumberLiteralAnnotation::U128)
+                        }
 
                         "i8" => {
                             self.toks.next();
@@ -1576,6 +2030,10 @@ impl<'src> Parser<'src> {
                             self.toks.next();
                             Some(NumberLiteralAnnotation::I64)
                         }
+                        "i128" => {
+                            self.toks.next();
+                            Some(NumberLiteralAnnotation::I128)
+                        }
 
                         "f32" => {
                             self.toks.next();
@@ -1586,11 +2044,11 @@ impl<'src> Parser<'src> {
                             Some(NumberLiteralAnnotation::F64)
                         }
 
-                        "usz" => {
+                        "usz" | "usize" => {
                             self.toks.next();
                             Some(NumberLiteralAnnotation::Usz)
                         }
-                        "isz" => {
+                        "isz" | "isize" => {
                             self.toks.next();
                             Some(NumberLiteralAnnotation::Isz)
                         }
@@ -1601,7 +2059,7 @@ impl<'src> Parser<'src> {
                     None
                 };
 
-            Ok(match u64::from_str_radix(number, base) {
+            Ok(match u128::from_str_radix(number, base) {
                 Ok(val) => NumberLiteral::Integer(BigInt { val, sign: false }, annotation),
                 Err(_) => match number.parse::<f64>() {
                     Ok(val) => NumberLiteral::Float(val, annotation),
@@ -1707,3 +2165,72 @@ impl fmt::Display for ExpectingOneOf {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        util::files::{CompiledFile, Files},
+        Symbol,
+    };
+
+    use super::{ParseErrorKind, Parser};
+
+    const EXPECTING_ALIGNMENT: &str = "expecting number: integer alignment";
+
+    /// Parse a module, returning the message of the parse error if there is one
+    fn parse(src: &str) -> Result<(), String> {
+        let file = Files::new().add(CompiledFile::in_memory(src.to_owned()));
+        Parser::new(src)
+            .parse(Symbol::from("test"), file)
+            .map(|_| ())
+            .map_err(|e| {
+                assert!(matches!(e.error, ParseErrorKind::UnexpectedToken { .. }));
+                e.error.to_string()
+            })
+    }
+
+    #[test]
+    fn integer_alignments_are_parsed() {
+        assert_eq!(
+            parse(
+                "type S = { align(8) i32 a }
+fun main() -> i32 {
+    let align(16) x = 1
+    return x
+}"
+            ),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn float_variable_alignment_is_rejected() {
+        let err = parse(
+            "fun main() -> i32 {
+    let align(4.5) x = 1
+    return x
+}",
+        )
+        .unwrap_err();
+        assert!(err.contains(EXPECTING_ALIGNMENT), "{}", err);
+    }
+
+    #[test]
+    fn float_field_alignment_is_rejected() {
+        let err = parse("type S = { align(2.0) i32 a }").unwrap_err();
+        assert!(err.contains(EXPECTING_ALIGNMENT), "{}", err);
+    }
+
+    #[test]
+    fn declaration_errors_list_constants_and_globals() {
+        let err = parse("let x = 1").unwrap_err();
+        assert!(err.contains("const") && err.contains("glob"), "{}", err);
+    }
+
+    #[test]
+    fn public_import_errors_list_public_declarations() {
+        let err = parse("pub imp std").unwrap_err();
+        assert!(err.contains("const") && err.contains("glob"), "{}", err);
+        assert!(!err.contains("imp"), "{}", err);
+    }
+}
@@ -1,5 +1,7 @@
 use std::fmt;
 
+use serde::Serialize;
+
 use crate::util::loc::Span;
 
 /// The main type used for a token lexed from a source file containing location information
@@ -97,7 +99,7 @@ impl fmt::Display for TokenData<'_> {
 }
 
 /// A binary or unary operator
-#[derive(Clone, Debug, Copy, PartialEq, Eq)]
+#[derive(Clone, Debug, Copy, PartialEq, Eq, Serialize)]
 pub enum Op {
     Star,
     Div,
@@ -79,6 +79,11 @@ impl Files {
     pub fn get(&self, id: FileId) -> &CompiledFile {
         self.files.get(id)
     }
+
+    /// Get an iterator over all files and their IDs
+    pub fn iter(&self) -> impl Iterator<Item = (FileId, &CompiledFile)> {
+        self.files.indices().zip(self.files.iter())
+    }
 }
 
 impl<'a> codespan_reporting::files::Files<'a> for Files {